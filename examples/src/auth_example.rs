@@ -51,10 +51,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 async fn run_server(private_key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
     // Define allowed admin peers (in production, load from config)
-    let admin_peers: Vec<String> = vec![
+    let admin_peers: std::sync::Arc<Vec<String>> = std::sync::Arc::new(vec![
         // Add specific peer IDs that should have admin access
         // "adminpeer123...".to_string(),
-    ];
+    ]);
     
     println!("🎧 Server listening on: {}", private_key.id52());
     println!("🔒 Authorization enabled:");
@@ -62,35 +62,37 @@ async fn run_server(private_key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std
     println!("   - Admin protocol: Restricted to {} peers", admin_peers.len());
 
     fastn_p2p::listen(private_key)
-        .with_connection_auth(move |peer| {
-            // Could implement IP-based blocking, rate limiting, etc.
-            println!("🔗 Connection from peer: {}", peer.id52());
+        .with_connection_auth(move |peer, hello| async move {
+            // Could consult a database, check hello.auth_token, rate limit, etc.
+            println!("🔗 Connection from peer: {} ({} {})", peer.id52(), hello.client_name, hello.client_version);
             true // Allow all connections for this example
         })
-        .with_stream_auth(move |peer, protocol, data| {
-            // Convert protocol to our enum for checking
-            let protocol_str = protocol.as_str().unwrap_or("");
-            
-            println!("🔑 Stream auth check: peer={} protocol={} data={}", 
-                    peer.id52(), protocol_str, data);
-            
-            match protocol_str {
-                "\"Echo\"" => {
-                    println!("✅ Allowing Echo protocol for peer {}", peer.id52());
-                    true // Echo is open to everyone
-                }
-                "\"Admin\"" => {
-                    let allowed = admin_peers.contains(&peer.id52());
-                    if allowed {
-                        println!("✅ Allowing Admin protocol for authorized peer {}", peer.id52());
-                    } else {
-                        println!("❌ Denying Admin protocol for unauthorized peer {}", peer.id52());
+        .with_stream_auth(move |request| {
+            let admin_peers = admin_peers.clone();
+            async move {
+                let peer = request.peer;
+                println!("🔑 Stream auth check: peer={} protocol={} data={}",
+                        peer.id52(), request.protocol, request.data);
+
+                match request.protocol.as_str().unwrap_or("") {
+                    "Echo" => {
+                        println!("✅ Allowing Echo protocol for peer {}", peer.id52());
+                        fastn_p2p::server::AuthDecision::Allow // Echo is open to everyone
+                    }
+                    "Admin" => {
+                        if admin_peers.contains(&peer.id52()) {
+                            println!("✅ Allowing Admin protocol for authorized peer {}", peer.id52());
+                            fastn_p2p::server::AuthDecision::Allow
+                        } else {
+                            println!("❌ Denying Admin protocol for unauthorized peer {}", peer.id52());
+                            // The client sees the code and message
+                            fastn_p2p::server::AuthDecision::deny("not-admin", "Ask the server owner to add your ID")
+                        }
+                    }
+                    other => {
+                        println!("❓ Unknown protocol: {}", other);
+                        fastn_p2p::server::AuthDecision::deny("unknown-protocol", format!("No such protocol: {}", other))
                     }
-                    allowed
-                }
-                _ => {
-                    println!("❓ Unknown protocol: {}", protocol_str);
-                    false // Deny unknown protocols
                 }
             }
         })
@@ -117,12 +119,14 @@ async fn run_client(
     println!("🔑 Our ID: {}", private_key.id52());
 
     let request = Request { message };
-    let result: Result<Response, AppError> =
-        fastn_p2p::client::call(private_key, target, protocol, request).await?;
+    let result: Result<Result<Response, AppError>, _> =
+        fastn_p2p::client::call(private_key, target, protocol, request).await;
 
     match result {
-        Ok(response) => println!("✅ Success: {}", response.result),
-        Err(error) => println!("❌ Error: {:?}", error),
+        Ok(Ok(response)) => println!("✅ Success: {}", response.result),
+        Ok(Err(error)) => println!("❌ Error: {:?}", error),
+        Err(fastn_p2p::client::CallError::Denied { code, message }) => println!("🚫 Denied ({}): {}", code, message),
+        Err(error) => return Err(error.into()),
     }
     Ok(())
 }
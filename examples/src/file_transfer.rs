@@ -1,7 +1,8 @@
 //! File Transfer Example
 //!
 //! Stream files directly over P2P without loading into memory.
-//! Uses async I/O copy for efficient streaming.
+//! Uses async I/O copy for efficient streaming. Transfers end with a BLAKE3
+//! checksum, so a corrupted or truncated download is detected.
 //!
 //! Usage:
 //!   file_transfer server [key]              # Start file server
@@ -70,14 +71,21 @@ async fn run_client(
     )
     .await?;
 
-    // Stream file content directly to local file using convenient copy method
+    // Stream into a partial file; it only gets its real name once the checksum matched
     let local_filename = format!("downloaded_{}", filename);
-    let mut output_file = tokio::fs::File::create(&local_filename).await?;
-
-    // Clean copy method - no manual tokio::io::copy needed!
-    let bytes_copied = session.copy_to(&mut output_file).await?;
+    let partial_filename = format!("{local_filename}.partial");
+    let mut output_file = tokio::fs::File::create(&partial_filename).await?;
+
+    let bytes_copied = match session.copy_to_verified(&mut output_file).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tokio::fs::remove_file(&partial_filename).await.ok();
+            return Err(e.into());
+        }
+    };
+    tokio::fs::rename(&partial_filename, &local_filename).await?;
 
-    println!("✅ Downloaded {} ({} bytes)", filename, bytes_copied);
+    println!("✅ Downloaded {} ({} bytes, checksum verified)", filename, bytes_copied);
     println!("💾 Saved as: {local_filename}");
 
     Ok(())
@@ -110,8 +118,8 @@ async fn file_stream_handler(
 
     println!("📤 Streaming file: {filename}");
 
-    // Clean copy method - returns io::Result, convert if needed
-    let bytes_sent = session.copy_from(&mut file).await.map_err(FileError::Io)?;
+    // Streams the file followed by its checksum for the client to verify
+    let bytes_sent = session.copy_from_verified(&mut file).await.map_err(FileError::Io)?;
     println!("✅ Sent {filename} ({bytes_sent} bytes)");
 
     Ok(())
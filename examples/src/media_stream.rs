@@ -390,8 +390,18 @@ async fn audio_publisher_handler(
     let streaming_start = Instant::now();
     println!("🚀 Starting audio stream (+{:.3}s)", streaming_start.duration_since(handler_start).as_secs_f64());
     
+    // Stop encoding as soon as the subscriber leaves, not at the next failed write
+    let closed = session.closed();
+    tokio::pin!(closed);
+    
     for chunk_data in audio_data.chunks(chunk_size) {
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            reason = &mut closed => {
+                println!("👋 Subscriber left: {}", reason);
+                break;
+            }
+        }
         
         let chunk = AudioChunk {
             sequence,
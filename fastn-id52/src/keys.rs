@@ -30,9 +30,25 @@ type InnerSignature = ed25519_dalek::Signature;
 /// // Convert back to ID52
 /// assert_eq!(public_key.to_string(), id52);
 /// ```
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PublicKey(InnerPublicKey);
 
+/// Characters of the ID52 that `Debug` shows for keys
+///
+/// Enough to tell keys apart in logs, not enough to copy one from a log.
+const DEBUG_ID52_PREFIX: usize = 8;
+
+fn redacted_id52(id52: &str) -> String {
+    format!("{}…", &id52[..DEBUG_ID52_PREFIX])
+}
+
+// Debug shows an ID52 prefix only; use Display or .id52() for the full key
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PublicKey").field(&redacted_id52(&self.id52())).finish()
+    }
+}
+
 /// Ed25519 secret key for signing operations.
 ///
 /// A `SecretKey` represents the private half of an Ed25519 key pair. It can be used
@@ -68,13 +84,12 @@ impl Clone for SecretKey {
 }
 
 // Manual Debug implementation to avoid exposing the secret key material.
-// Only shows the public ID52, omitting the actual 32-byte secret key value
-// that would be exposed by a derived Debug implementation.
-// Safe Debug implementation - shows public ID only, never secret material
+// Only shows a prefix of the public ID52, omitting the actual 32-byte secret
+// key value that would be exposed by a derived Debug implementation.
 impl fmt::Debug for SecretKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SecretKey")
-            .field("public_id52", &self.id52())
+            .field("public_id52", &redacted_id52(&self.id52()))
             .finish_non_exhaustive() // Shows ".. " to indicate hidden fields
     }
 }
@@ -255,6 +270,53 @@ impl SecretKey {
         SecretKey(ed25519_dalek::SigningKey::generate(&mut rng))
     }
 
+    /// Derives a secret key from a phrase, for tests, docs and local development.
+    ///
+    /// The same phrase always gives the same key, so fixtures can use stable,
+    /// reproducible peer IDs:
+    ///
+    /// ```
+    /// use fastn_id52::SecretKey;
+    ///
+    /// let alice = SecretKey::from_seed_phrase("alice");
+    /// assert_eq!(alice.id52(), SecretKey::from_seed_phrase("alice").id52());
+    /// assert_ne!(alice.id52(), SecretKey::from_seed_phrase("bob").id52());
+    /// ```
+    ///
+    /// # Security
+    ///
+    /// ⚠️  DEV/TEST ONLY: this is not a mnemonic scheme like BIP39. Anyone who
+    /// knows or guesses the phrase has the key. Never use it for real identities;
+    /// use [`SecretKey::generate`] instead.
+    pub fn from_seed_phrase(phrase: &str) -> Self {
+        Self::from_bytes(&blake3::derive_key("fastn-id52 seed phrase v1", phrase.as_bytes()))
+    }
+
+    /// Derives the `n`th child key of this key.
+    ///
+    /// Children are stable for a given parent and `n`, and unrelated to each
+    /// other and to the parent's public key, so one seed can describe a whole
+    /// multi-identity setup:
+    ///
+    /// ```
+    /// use fastn_id52::SecretKey;
+    ///
+    /// let root = SecretKey::from_seed_phrase("mail-test");
+    /// let [server, alice, bob] = [0, 1, 2].map(|n| root.derive(n));
+    /// assert_eq!(alice.id52(), root.derive(1).id52());
+    /// assert_ne!(alice.id52(), bob.id52());
+    /// # let _ = server;
+    /// ```
+    ///
+    /// Children are only as secret as the parent: derived from a
+    /// [`SecretKey::from_seed_phrase`] key they are test keys too.
+    pub fn derive(&self, n: u32) -> Self {
+        let mut hasher = blake3::Hasher::new_derive_key("fastn-id52 child key v1");
+        hasher.update(&self.to_secret_bytes());
+        hasher.update(&n.to_le_bytes());
+        Self::from_bytes(hasher.finalize().as_bytes())
+    }
+
     /// Creates a secret key from its raw 32-byte representation.
     ///
     /// # Security
@@ -488,8 +550,8 @@ impl SecretKey {
 
         // Strict mode: cannot have both
         if has_file && has_direct {
-            eprintln!(
-                "ERROR: Both FASTN_SECRET_KEYS_FILE and FASTN_SECRET_KEYS are set. \
+            tracing::warn!(
+                "Both FASTN_SECRET_KEYS_FILE and FASTN_SECRET_KEYS are set. \
                       This is not allowed in strict mode. Please use only one."
             );
             return None;
@@ -687,6 +749,21 @@ mod tests {
         assert_eq!(parsed, public_key);
     }
 
+    #[test]
+    fn test_debug_redacts_keys() {
+        let secret_key = SecretKey::generate();
+        let id52 = secret_key.id52();
+        let prefix = &id52[..DEBUG_ID52_PREFIX];
+
+        let public = format!("{:?}", secret_key.public_key());
+        assert_eq!(public, format!("PublicKey(\"{}…\")", prefix));
+
+        let secret = format!("{:?}", secret_key);
+        assert!(secret.contains(prefix));
+        assert!(!secret.contains(&id52));
+        assert!(!secret.contains(&secret_key.to_secret_hex()));
+    }
+
     #[test]
     fn test_secret_key_hex_roundtrip() {
         let secret_key = SecretKey::generate();
@@ -696,6 +773,18 @@ mod tests {
         assert_eq!(parsed.to_secret_bytes(), secret_key.to_secret_bytes());
     }
 
+    #[test]
+    fn test_deterministic_keys() {
+        let root = SecretKey::from_seed_phrase("fixture");
+        assert_eq!(root.to_secret_bytes(), SecretKey::from_seed_phrase("fixture").to_secret_bytes());
+        assert_ne!(root.id52(), SecretKey::from_seed_phrase("fixture ").id52());
+
+        assert_eq!(root.derive(3).id52(), root.derive(3).id52());
+        assert_ne!(root.derive(0).id52(), root.derive(1).id52());
+        assert_ne!(root.derive(0).id52(), root.id52());
+        assert_ne!(root.derive(0).id52(), SecretKey::generate().derive(0).id52());
+    }
+
     #[test]
     fn test_signature_verification() {
         let secret_key = SecretKey::generate();
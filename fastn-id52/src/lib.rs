@@ -81,6 +81,13 @@
 //! - [`PublicKey`]: Entity's public key with ID52 encoding
 //! - [`Signature`]: Ed25519 signature for entity authentication
 //!
+//! ### Test Keys
+//!
+//! Tests and docs can use stable peer IDs instead of random ones:
+//! [`SecretKey::from_seed_phrase`] turns a phrase into a key and
+//! [`SecretKey::derive`] makes numbered child keys from it. These are for
+//! development only; anyone who knows the phrase has the key.
+//!
 //! ## Key Loading
 //!
 //! The crate provides comprehensive key loading with automatic fallback:
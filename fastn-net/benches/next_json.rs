@@ -0,0 +1,77 @@
+//! Benchmarks for reading newline-delimited JSON frames
+//!
+//! Compares the byte-at-a-time loop used by `next_json` against the buffered
+//! `FrameReader`. Run with `cargo bench -p fastn-net`.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+
+/// Same loop as `fastn_net::next_json`, over any `AsyncRead`
+async fn next_json_unbuffered<R, T>(recv: &mut R) -> eyre::Result<T>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    T: serde::de::DeserializeOwned,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut buffer = Vec::with_capacity(1024);
+    loop {
+        let mut byte = [0u8];
+        if recv.read(&mut byte).await? == 0 {
+            return Err(eyre::anyhow!("connection closed while reading response header"));
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        buffer.push(byte[0]);
+    }
+    Ok(serde_json::from_slice(&buffer)?)
+}
+
+/// `count` wrapper requests with a `size`-byte message each
+fn payload(count: usize, size: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    for i in 0..count {
+        let line = serde_json::json!({
+            "protocol": "Echo",
+            "data": { "id": i, "message": "x".repeat(size) },
+        });
+        serde_json::to_writer(&mut out, &line).unwrap();
+        out.push(b'\n');
+    }
+    out
+}
+
+fn bench_next_json(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let count = 100;
+
+    let mut group = c.benchmark_group("next_json");
+    for size in [64, 1024, 16 * 1024] {
+        let input = payload(count, size);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("byte_at_a_time", size), &input, |b, input| {
+            b.to_async(&rt).iter(|| async {
+                let mut recv: &[u8] = input;
+                for _ in 0..count {
+                    let _: serde_json::Value = next_json_unbuffered(&mut recv).await.unwrap();
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("frame_reader", size), &input, |b, input| {
+            b.to_async(&rt).iter(|| async {
+                let mut reader = fastn_net::FrameReader::new(&input[..]);
+                for _ in 0..count {
+                    let _: serde_json::Value = reader.next_json().await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_next_json);
+criterion_main!(benches);
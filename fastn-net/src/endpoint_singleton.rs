@@ -27,7 +27,7 @@ pub async fn get_or_create_endpoint(
                 Ok(ep) => Arc::new(ep),
                 Err(e) => {
                     // Log error and panic since we can't recover from this
-                    eprintln!("Failed to create iroh endpoint: {}", e);
+                    tracing::error!("Failed to create iroh endpoint: {}", e);
                     panic!("Failed to create iroh endpoint: {}", e);
                 }
             }
@@ -42,7 +42,7 @@ async fn create_endpoint(secret_key: fastn_id52::SecretKey) -> eyre::Result<iroh
     // Convert fastn_id52::SecretKey to iroh::SecretKey
     let iroh_secret_key = iroh::SecretKey::from_bytes(&secret_key.to_secret_bytes());
 
-    println!("🔧 Creating singleton iroh endpoint...");
+    tracing::debug!("Creating singleton iroh endpoint");
     
     match iroh::Endpoint::builder()
         .discovery_n0()
@@ -53,7 +53,7 @@ async fn create_endpoint(secret_key: fastn_id52::SecretKey) -> eyre::Result<iroh
         .await
     {
         Ok(ep) => {
-            println!("✅ Singleton endpoint created: {}", ep.node_id());
+            tracing::info!("Singleton endpoint created: {}", ep.node_id());
             Ok(ep)
         }
         Err(e) => {
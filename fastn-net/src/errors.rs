@@ -118,3 +118,10 @@ pub enum StreamError {
     #[error("Stream unexpectedly closed")]
     StreamClosed,
 }
+
+/// A frame read by [`crate::FrameReader`] grew past [`crate::MAX_FRAME_LEN`]
+#[derive(Debug, Error)]
+#[error("frame exceeds maximum length of {limit} bytes")]
+pub struct FrameTooLargeError {
+    pub limit: usize,
+}
@@ -0,0 +1,204 @@
+//! Buffered reader for newline-delimited JSON frames.
+//!
+//! [`next_json`](crate::next_json) and [`next_string`](crate::next_string) read
+//! one byte at a time so they never consume bytes past the newline - required
+//! when the stream is handed to raw streaming code afterwards. That costs one
+//! `read` call per byte, which adds up on the request path.
+//!
+//! [`FrameReader`] instead reads in large chunks with `read_buf` and splits
+//! lines out of its buffer, parsing JSON directly from the buffered slice.
+//! Bytes read past the last frame are not lost: `FrameReader` implements
+//! [`tokio::io::AsyncRead`] and drains its buffer before reading from the inner
+//! stream again, and [`FrameReader::into_parts`] hands them back explicitly.
+
+/// Default number of bytes requested from the inner stream per read
+const DEFAULT_READ_SIZE: usize = 8 * 1024;
+
+/// Frames larger than this are rejected instead of growing the buffer forever
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Buffered reader yielding newline-terminated frames from an async stream.
+pub struct FrameReader<R> {
+    inner: R,
+    buffer: bytes::BytesMut,
+    read_size: usize,
+    /// Length (including newline) of the frame returned last; dropped lazily so
+    /// the returned slice can borrow the buffer
+    pending_consume: usize,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_read_size(inner, DEFAULT_READ_SIZE)
+    }
+
+    pub fn with_read_size(inner: R, read_size: usize) -> Self {
+        Self {
+            inner,
+            buffer: bytes::BytesMut::with_capacity(read_size),
+            read_size,
+            pending_consume: 0,
+        }
+    }
+
+    /// Reads the next frame, without the trailing newline.
+    ///
+    /// The returned slice borrows the internal buffer and is valid until the
+    /// next call on this reader.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream ends before a newline or the frame
+    /// exceeds [`MAX_FRAME_LEN`].
+    pub async fn next_frame(&mut self) -> eyre::Result<&[u8]> {
+        use tokio::io::AsyncReadExt;
+
+        self.consume_pending();
+
+        let mut scanned = 0;
+        loop {
+            if let Some(pos) = self.buffer[scanned..].iter().position(|b| *b == b'\n') {
+                let end = scanned + pos;
+                self.pending_consume = end + 1;
+                return Ok(&self.buffer[..end]);
+            }
+            scanned = self.buffer.len();
+
+            if scanned > MAX_FRAME_LEN {
+                return Err(crate::errors::FrameTooLargeError {
+                    limit: MAX_FRAME_LEN,
+                }
+                .into());
+            }
+
+            if self.buffer.capacity() == self.buffer.len() {
+                self.buffer.reserve(self.read_size);
+            }
+            if self.inner.read_buf(&mut self.buffer).await? == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed while reading response header",
+                )
+                .into());
+            }
+        }
+    }
+
+    /// Reads the next frame and deserializes it as JSON.
+    pub async fn next_json<T: serde::de::DeserializeOwned>(&mut self) -> eyre::Result<T> {
+        let frame = self.next_frame().await?;
+        Ok(serde_json::from_slice(frame)?)
+    }
+
+    /// Reads the next frame and deserializes it as JSON borrowing from the buffer.
+    ///
+    /// Use this with types holding `&str` / `&RawValue` fields to avoid copying
+    /// string data out of the frame.
+    pub async fn next_json_borrowed<'a, T: serde::Deserialize<'a>>(
+        &'a mut self,
+    ) -> eyre::Result<T> {
+        let frame = self.next_frame().await?;
+        Ok(serde_json::from_slice(frame)?)
+    }
+
+    /// Reads the next frame as a UTF-8 string.
+    pub async fn next_string(&mut self) -> eyre::Result<String> {
+        let frame = self.next_frame().await?;
+        std::str::from_utf8(frame)
+            .map(str::to_string)
+            .map_err(|e| eyre::anyhow!("failed to convert bytes to string: {e}"))
+    }
+
+    /// Bytes read from the inner stream that have not been returned yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer[self.pending_consume..]
+    }
+
+    /// Returns the inner stream and any bytes already read past the last frame.
+    pub fn into_parts(mut self) -> (R, bytes::Bytes) {
+        self.consume_pending();
+        (self.inner, self.buffer.freeze())
+    }
+
+    fn consume_pending(&mut self) {
+        bytes::Buf::advance(&mut self.buffer, self.pending_consume);
+        self.pending_consume = 0;
+    }
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for FrameReader<R> {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        this.consume_pending();
+
+        // Serve already-buffered bytes before touching the inner stream
+        if !this.buffer.is_empty() {
+            let n = std::cmp::min(buf.remaining(), this.buffer.len());
+            buf.put_slice(&this.buffer[..n]);
+            bytes::Buf::advance(&mut this.buffer, n);
+            return std::task::Poll::Ready(Ok(()));
+        }
+
+        std::pin::Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frames_and_leftover() {
+        let input: &[u8] = b"{\"a\":1}\n\"ack\"\nraw bytes";
+        // Tiny read size forces frames to span several reads
+        let mut reader = FrameReader::with_read_size(input, 3);
+
+        let value: serde_json::Value = reader.next_json().await.unwrap();
+        assert_eq!(value, serde_json::json!({"a": 1}));
+
+        let ack: &str = reader.next_json_borrowed().await.unwrap();
+        assert_eq!(ack, "ack");
+
+        // Whatever follows the last frame is still readable as a raw stream
+        let mut rest = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut rest)
+            .await
+            .unwrap();
+        assert_eq!(rest, b"raw bytes");
+    }
+
+    #[tokio::test]
+    async fn test_closed_before_newline() {
+        let input: &[u8] = b"{\"a\":1}";
+        let mut reader = FrameReader::new(input);
+        assert!(reader.next_string().await.is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_frames_match_lines_of_input(
+            input in proptest::collection::vec(proptest::num::u8::ANY, 0..1024),
+            read_size in 1..64usize,
+        ) {
+            let frames = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let mut reader = FrameReader::with_read_size(input.as_slice(), read_size);
+                    let mut frames = Vec::new();
+                    while let Ok(frame) = reader.next_frame().await {
+                        frames.push(frame.to_vec());
+                    }
+                    frames
+                });
+            // Every newline ends a frame; whatever follows the last one is incomplete
+            let mut lines: Vec<Vec<u8>> = input.split(|b| *b == b'\n').map(<[u8]>::to_vec).collect();
+            lines.pop();
+            proptest::prop_assert_eq!(frames, lines);
+        }
+    }
+}
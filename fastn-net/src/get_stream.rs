@@ -50,7 +50,7 @@ type StreamRequestReceiver = tokio::sync::mpsc::Receiver<StreamRequest>;
 /// # Errors
 ///
 /// Returns an error if connection fails or protocol negotiation times out.
-#[tracing::instrument(skip_all)]
+#[tracing::instrument(skip_all, fields(trace_id = header.trace.as_ref().map(|t| t.trace_id.as_str())))]
 pub async fn get_stream(
     self_endpoint: iroh::Endpoint,
     header: crate::ProtocolHeader,
@@ -61,8 +61,8 @@ pub async fn get_stream(
     use eyre::WrapErr;
 
     tracing::trace!("get_stream: {header:?}");
-    println!(
-        "🔍 DEBUG get_stream: Starting stream request for {}",
+    tracing::trace!(
+        "get_stream: Starting stream request for {}",
         remote_public_key.id52()
     );
     let stream_request_sender = get_stream_request_sender(
@@ -72,23 +72,23 @@ pub async fn get_stream(
         graceful,
     )
     .await?;
-    println!("✅ DEBUG get_stream: Got stream_request_sender");
+    tracing::trace!("get_stream: Got stream_request_sender");
     tracing::trace!("got stream_request_sender");
     let (reply_channel, receiver) = tokio::sync::oneshot::channel();
-    println!("🔗 DEBUG get_stream: Created oneshot channel");
+    tracing::trace!("get_stream: Created oneshot channel");
 
-    println!("📤 DEBUG get_stream: About to send stream request");
+    tracing::trace!("get_stream: About to send stream request");
     stream_request_sender
         .send((header, reply_channel))
         .await
         .wrap_err_with(|| "failed to send on stream_request_sender")?;
-    println!("✅ DEBUG get_stream: Stream request sent");
+    tracing::trace!("get_stream: Stream request sent");
 
     tracing::trace!("sent stream request");
 
-    println!("⏳ DEBUG get_stream: Waiting for stream reply");
+    tracing::trace!("get_stream: Waiting for stream reply");
     let r = receiver.await?;
-    println!("✅ DEBUG get_stream: Received stream reply");
+    tracing::trace!("get_stream: Received stream reply");
 
     tracing::trace!("got stream request reply");
     r
@@ -101,19 +101,19 @@ async fn get_stream_request_sender(
     peer_stream_senders: PeerStreamSenders,
     graceful: crate::Graceful,
 ) -> eyre::Result<StreamRequestSender> {
-    println!(
-        "🔍 DEBUG get_stream_request_sender: Starting for {}",
+    tracing::trace!(
+        "get_stream_request_sender: Starting for {}",
         remote_public_key.id52()
     );
     // Convert iroh node_id to fastn_id52::PublicKey
     let self_public_key = fastn_id52::PublicKey::from_bytes(self_endpoint.node_id().as_bytes())
         .map_err(|e| eyre::anyhow!("Invalid self endpoint node_id: {}", e))?;
-    println!(
-        "🔍 DEBUG get_stream_request_sender: Self PublicKey: {}",
+    tracing::trace!(
+        "get_stream_request_sender: Self PublicKey: {}",
         self_public_key.id52()
     );
     let mut senders = peer_stream_senders.lock().await;
-    println!("🔍 DEBUG get_stream_request_sender: Got peer_stream_senders lock");
+    tracing::trace!("get_stream_request_sender: Got peer_stream_senders lock");
 
     if let Some(sender) = senders.get(&(self_public_key, *remote_public_key)) {
         return Ok(sender.clone());
@@ -126,13 +126,13 @@ async fn get_stream_request_sender(
 
     let graceful_for_connection_manager = graceful.clone();
     let remote_public_key_for_task = *remote_public_key;
-    println!(
-        "🚀 DEBUG get_stream_request_sender: Spawning connection_manager task for {}",
+    tracing::trace!(
+        "get_stream_request_sender: Spawning connection_manager task for {}",
         remote_public_key.id52()
     );
     graceful.spawn(async move {
-        println!(
-            "📋 DEBUG connection_manager: Task started for {}",
+        tracing::trace!(
+            "connection_manager: Task started for {}",
             remote_public_key_for_task.id52()
         );
         let result = connection_manager(
@@ -142,8 +142,8 @@ async fn get_stream_request_sender(
             graceful_for_connection_manager,
         )
         .await;
-        println!(
-            "📋 DEBUG connection_manager: Task ended for {} with result: {:?}",
+        tracing::trace!(
+            "connection_manager: Task ended for {} with result: {:?}",
             remote_public_key_for_task.id52(),
             result
         );
@@ -162,8 +162,8 @@ async fn connection_manager(
     remote_public_key: fastn_id52::PublicKey,
     graceful: crate::Graceful,
 ) {
-    println!(
-        "🔧 DEBUG connection_manager: Function started for {}",
+    tracing::trace!(
+        "connection_manager: Function started for {}",
         remote_public_key.id52()
     );
     let e = match connection_manager_(&mut receiver, self_endpoint, remote_public_key, graceful)
@@ -214,8 +214,8 @@ async fn connection_manager_(
     remote_public_key: fastn_id52::PublicKey,
     graceful: crate::Graceful,
 ) -> eyre::Result<()> {
-    println!(
-        "🔧 DEBUG connection_manager_: Starting main loop for {}",
+    tracing::trace!(
+        "connection_manager_: Starting main loop for {}",
         remote_public_key.id52()
     );
     let conn = match self_endpoint
@@ -235,15 +235,15 @@ async fn connection_manager_(
         }
     };
 
-    let timeout = std::time::Duration::from_secs(12);
-    let mut idle_counter = 0;
+    // the pool can't tell when handed out streams are done, so a connection counts as idle from
+    // the last stream request on; pings keep it healthy but do not reset the idle clock.
+    let mut idle = crate::IdleTimer::new(crate::IdlePolicy::default());
 
     loop {
         tracing::trace!("connection manager loop");
 
-        if idle_counter > 4 {
-            tracing::info!("connection idle timeout, returning");
-            // this ensures we keep a connection open only for 12 * 5 seconds = 1 min
+        if let Some(expiry) = idle.expired(false) {
+            tracing::info!("closing pooled connection, {expiry}");
             break;
         }
 
@@ -252,18 +252,20 @@ async fn connection_manager_(
                 tracing::info!("graceful shutdown");
                 break;
             },
-            _ = tokio::time::sleep(timeout) => {
+            _ = tokio::time::sleep(idle.policy().keepalive_interval.min(idle.next_check())) => {
                 tracing::info!("woken up");
+                if idle.expired(false).is_some() {
+                    continue;
+                }
                 if let Err(e) = crate::ping(&conn).await {
                     tracing::error!("pinging failed: {e:?}");
                     break;
                 }
-                idle_counter += 1;
             },
             Some((header, reply_channel)) = receiver.recv() => {
-                println!("📨 DEBUG connection_manager: Received stream request for {header:?}, idle counter: {idle_counter}");
-                tracing::info!("connection: {header:?}, idle counter: {idle_counter}");
-                idle_counter = 0;
+                tracing::trace!("connection_manager: Received stream request for {header:?}");
+                tracing::info!("connection: {header:?}");
+                idle.touch(&header.idle_policy.unwrap_or_default());
                 // is this a good idea to serialize this part? if 10 concurrent requests come in, we will
                 // handle each one sequentially. the other alternative is to spawn a task for each request.
                 // so which is better?
@@ -324,9 +326,9 @@ async fn handle_request(
     use eyre::WrapErr;
 
     tracing::trace!("handling request: {header:?}");
-    println!("🔧 DEBUG handle_request: Handling stream request for protocol {header:?}");
+    tracing::trace!("handle_request: Handling stream request for protocol {header:?}");
 
-    println!("🔗 DEBUG handle_request: About to open bi-directional stream");
+    tracing::trace!("handle_request: About to open bi-directional stream");
     let (mut send, mut recv) = match tokio::time::timeout(
         std::time::Duration::from_secs(10), // 10 second timeout for stream opening
         conn.open_bi(),
@@ -334,37 +336,37 @@ async fn handle_request(
     .await
     {
         Ok(Ok(v)) => {
-            println!("✅ DEBUG handle_request: Successfully opened bi-directional stream");
+            tracing::trace!("handle_request: Successfully opened bi-directional stream");
             tracing::trace!("opened bi-stream");
             v
         }
         Ok(Err(e)) => {
-            println!("❌ DEBUG handle_request: Failed to open bi-directional stream: {e:?}");
+            tracing::trace!("handle_request: Failed to open bi-directional stream: {e:?}");
             tracing::error!("failed to open_bi: {e:?}");
             return Err(eyre::anyhow!("failed to open_bi: {e:?}"));
         }
         Err(_timeout) => {
-            println!(
-                "⏰ DEBUG handle_request: Timed out opening bi-directional stream after 10 seconds"
+            tracing::trace!(
+                "handle_request: Timed out opening bi-directional stream after 10 seconds"
             );
             return Err(eyre::anyhow!("timed out opening bi-directional stream"));
         }
     };
 
-    println!("📤 DEBUG handle_request: About to write protocol to stream");
+    tracing::trace!("handle_request: About to write protocol to stream");
     send.write_all(
         &serde_json::to_vec(&header.protocol)
             .wrap_err_with(|| format!("failed to serialize protocol: {:?}", header.protocol))?,
     )
     .await?;
-    println!("✅ DEBUG handle_request: Successfully wrote protocol to stream");
+    tracing::trace!("handle_request: Successfully wrote protocol to stream");
     tracing::trace!("wrote protocol");
 
-    println!("📤 DEBUG handle_request: About to write newline");
+    tracing::trace!("handle_request: About to write newline");
     send.write(b"\n")
         .await
         .wrap_err_with(|| "failed to write newline")?;
-    println!("✅ DEBUG handle_request: Successfully wrote newline");
+    tracing::trace!("handle_request: Successfully wrote newline");
 
     tracing::trace!("wrote newline");
 
@@ -386,12 +388,12 @@ async fn handle_request(
 
     tracing::trace!("received ack");
 
-    println!("📤 DEBUG handle_request: About to send stream reply");
+    tracing::trace!("handle_request: About to send stream reply");
     reply_channel.send(Ok((send, recv))).unwrap_or_else(|e| {
-        println!("❌ DEBUG handle_request: Failed to send stream reply: {e:?}");
+        tracing::trace!("handle_request: Failed to send stream reply: {e:?}");
         tracing::error!("failed to send reply: {e:?}");
     });
-    println!("✅ DEBUG handle_request: Stream reply sent successfully");
+    tracing::trace!("handle_request: Stream reply sent successfully");
 
     tracing::trace!("handle_request done");
 
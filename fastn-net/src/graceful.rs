@@ -126,14 +126,56 @@
 //!    to properly propagate errors during shutdown.
 //!
 //! 5. **Shutdown order**: Call `shutdown()` from your main function or signal
-//!    handler, which will:
-//!    - Cancel all tasks via the cancellation token
-//!    - Wait for all tracked tasks to complete
-//!    - Return any errors from failed tasks
+//!    handler, or [`Graceful::shutdown_with_timeout`] to skip the ctrl-c
+//!    confirmation. Shutdown runs in [`ShutdownPhase`]s:
+//!    - Stop accepting: [`Graceful::draining`] fires, listeners stop taking
+//!      new connections and streams
+//!    - Drain: wait (bounded) for in-flight tasks to finish on their own
+//!    - Cancel: [`Graceful::cancelled`] fires for the stragglers
+//!    - Stopped: every task has exited or been given up on
+//!
+//!    Hooks registered with [`Graceful::on_shutdown`] run at the start of
+//!    their phase, e.g. to flush a store once handlers are done.
 
 use eyre::Context;
 use tokio::task::JoinHandle;
 
+/// How long [`Graceful::shutdown`] waits for in-flight tasks before cancelling them
+pub const DEFAULT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How long cancelled tasks get to exit before shutdown gives up on them
+const CANCEL_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often pending tasks are reported while waiting
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Phases of a graceful shutdown, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShutdownPhase {
+    /// Listeners stop accepting new connections and streams
+    StopAccepting,
+    /// In-flight tasks are given time to finish
+    Draining,
+    /// Tasks still running after the drain timeout are cancelled
+    Cancelling,
+    /// All tasks have exited, or shutdown gave up waiting for them
+    Stopped,
+}
+
+/// Outcome of a phased shutdown
+#[derive(Debug, Clone)]
+pub struct ShutdownReport {
+    /// Whether every task finished within the drain timeout
+    pub drained: bool,
+    /// Tasks still running when shutdown gave up on them
+    pub abandoned: usize,
+    pub elapsed: std::time::Duration,
+}
+
+type ShutdownHook = std::sync::Arc<
+    dyn Fn() -> std::pin::Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+>;
+
 /// Manages graceful shutdown of async tasks.
 ///
 /// Combines cancellation signaling with task tracking to ensure
@@ -142,6 +184,9 @@ use tokio::task::JoinHandle;
 #[derive(Clone)]
 pub struct Graceful {
     cancel: tokio_util::sync::CancellationToken,
+    /// Child of `cancel`: cancelled first, or along with it
+    drain: tokio_util::sync::CancellationToken,
+    hooks: std::sync::Arc<std::sync::Mutex<Vec<(ShutdownPhase, ShutdownHook)>>>,
     tracker: tokio_util::task::TaskTracker,
     show_info_tx: tokio::sync::watch::Sender<bool>,
     show_info_rx: tokio::sync::watch::Receiver<bool>,
@@ -157,8 +202,11 @@ impl Graceful {
     pub fn new() -> Self {
         let (show_info_tx, show_info_rx) = tokio::sync::watch::channel(false);
 
+        let cancel = tokio_util::sync::CancellationToken::new();
         Self {
-            cancel: tokio_util::sync::CancellationToken::new(),
+            drain: cancel.child_token(),
+            cancel,
+            hooks: std::sync::Arc::new(std::sync::Mutex::new(Vec::new())),
             tracker: tokio_util::task::TaskTracker::new(),
             show_info_tx,
             show_info_rx,
@@ -182,6 +230,32 @@ impl Graceful {
         self.tracker.spawn(task)
     }
 
+    /// Run blocking or CPU-heavy `task` on tokio's blocking pool, tracked like [`Graceful::spawn`]
+    ///
+    /// A running closure can't be interrupted; long ones should check
+    /// [`Graceful::is_cancelled`] now and then so shutdown doesn't wait on them.
+    #[inline]
+    #[track_caller]
+    pub fn spawn_blocking<F, T>(&self, task: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.tracker.spawn_blocking(task)
+    }
+
+    /// A group of tasks that ends with one piece of work, e.g. a stream handler
+    ///
+    /// Its tasks are also tracked here, so shutdown waits for them, and they
+    /// are cancelled along with every other task.
+    pub fn scope(&self) -> Scope {
+        Scope {
+            cancel: self.cancel.child_token(),
+            tasks: tokio_util::task::TaskTracker::new(),
+            tracker: self.tracker.clone(),
+        }
+    }
+
     pub async fn shutdown(&self) -> eyre::Result<()> {
         loop {
             tokio::signal::ctrl_c()
@@ -200,31 +274,14 @@ impl Graceful {
                     tracing::info!("Received second ctrl-c signal, shutting down.");
                     tracing::debug!("Pending tasks: {}", self.tracker.len());
 
-                    self.cancel.cancel();
-                    self.tracker.close();
-
-                    let mut count = 0;
-                    loop {
-                        tokio::select! {
-                            _ = self.tracker.wait() => {
-                                tracing::info!("All tasks have exited.");
-                                break;
-                            }
-                            _ = tokio::time::sleep(std::time::Duration::from_secs(3)) => {
-                                count += 1;
-                                if count > 10 {
-                                    eprintln!("Timeout expired, {} pending tasks. Exiting...", self.tracker.len());
-                                    break;
-                                }
-                                tracing::debug!("Pending tasks: {}", self.tracker.len());
-                            }
-                        }
+                    let report = self.shutdown_with_timeout(DEFAULT_DRAIN_TIMEOUT).await;
+                    if report.abandoned > 0 {
+                        tracing::warn!("Timeout expired, {} pending tasks. Exiting...", report.abandoned);
                     }
                     break;
                 }
                 _ = tokio::time::sleep(std::time::Duration::from_secs(3)) => {
-                    tracing::info!("Timeout expired. Continuing...");
-                    println!("Did not receive ctrl+c within 3 secs. Press ctrl+c in quick succession to exit.");
+                    tracing::info!("Did not receive ctrl+c within 3 secs. Press ctrl+c in quick succession to exit.");
                 }
             }
         }
@@ -232,7 +289,267 @@ impl Graceful {
         Ok(())
     }
 
+    /// Shut down in phases without waiting for ctrl-c
+    ///
+    /// New connections and streams are refused first, in-flight tasks get up
+    /// to `timeout` to finish, and only then is [`Graceful::cancelled`]
+    /// signalled. Progress is logged while waiting.
+    pub async fn shutdown_with_timeout(&self, timeout: std::time::Duration) -> ShutdownReport {
+        let started = std::time::Instant::now();
+
+        self.stop_accepting().await;
+
+        self.enter(ShutdownPhase::Draining).await;
+        let drained = self.wait_for_tasks(timeout).await;
+
+        if !drained {
+            self.enter(ShutdownPhase::Cancelling).await;
+        }
+        // Long-lived loops waiting on `cancelled()` exit here even after a clean drain
+        self.cancel.cancel();
+        let stopped = drained || self.wait_for_tasks(CANCEL_GRACE).await;
+
+        self.enter(ShutdownPhase::Stopped).await;
+        let report = ShutdownReport {
+            drained,
+            abandoned: if stopped { 0 } else { self.tracker.len() },
+            elapsed: started.elapsed(),
+        };
+        tracing::info!(
+            "Shutdown finished in {:?} (drained: {}, abandoned: {})",
+            report.elapsed, report.drained, report.abandoned
+        );
+        report
+    }
+
+    /// Only the first phase of shutdown: refuse new connections and streams
+    ///
+    /// In-flight tasks keep running and nothing is cancelled, so a host can
+    /// be drained before it restarts. A later shutdown goes on from here;
+    /// calling this again does nothing.
+    pub async fn stop_accepting(&self) {
+        if self.drain.is_cancelled() {
+            return;
+        }
+        self.enter(ShutdownPhase::StopAccepting).await;
+        self.drain.cancel();
+        self.tracker.close();
+    }
+
+    /// Whether new connections and streams are refused, see [`Graceful::stop_accepting`]
+    pub fn is_draining(&self) -> bool {
+        self.drain.is_cancelled()
+    }
+
+    /// Run `hook` when shutdown reaches `phase`
+    ///
+    /// Hooks of a phase run one after another, in registration order, before
+    /// the phase starts; shutdown waits for them.
+    pub fn on_shutdown<F, Fut>(&self, phase: ShutdownPhase, hook: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks
+            .lock()
+            .expect("Failed to acquire lock on shutdown hooks")
+            .push((phase, std::sync::Arc::new(move || Box::pin(hook()))));
+    }
+
+    /// Resolves once shutdown has started: stop accepting new work
+    ///
+    /// Also fires when tasks are cancelled, so accept loops only need this.
+    pub fn draining(&self) -> tokio_util::sync::WaitForCancellationFuture<'_> {
+        self.drain.cancelled()
+    }
+
+    /// Resolves when running tasks must stop
     pub fn cancelled(&self) -> tokio_util::sync::WaitForCancellationFuture<'_> {
         self.cancel.cancelled()
     }
+
+    /// Whether running tasks must stop, for code that can't await [`Graceful::cancelled`]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    async fn enter(&self, phase: ShutdownPhase) {
+        tracing::info!("Shutdown: {:?} ({} pending tasks)", phase, self.tracker.len());
+        let hooks: Vec<ShutdownHook> = self
+            .hooks
+            .lock()
+            .expect("Failed to acquire lock on shutdown hooks")
+            .iter()
+            .filter(|(hook_phase, _)| *hook_phase == phase)
+            .map(|(_, hook)| hook.clone())
+            .collect();
+        for hook in hooks {
+            hook().await;
+        }
+    }
+
+    /// Wait up to `timeout` for all tracked tasks; `false` if some are still running
+    async fn wait_for_tasks(&self, timeout: std::time::Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            tokio::select! {
+                _ = self.tracker.wait() => {
+                    tracing::info!("All tasks have exited.");
+                    return true;
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    tracing::warn!("{} tasks still running after {:?}", self.tracker.len(), timeout);
+                    return false;
+                }
+                _ = tokio::time::sleep(PROGRESS_INTERVAL) => {
+                    tracing::info!("Waiting for {} tasks", self.tracker.len());
+                }
+            }
+        }
+    }
+}
+
+/// Tasks scoped to one piece of work, made by [`Graceful::scope`]
+///
+/// [`Scope::close`] cancels whatever is still running and waits until every
+/// task is gone, so helpers can't outlive the work that spawned them. Clones
+/// share the same scope.
+#[derive(Clone)]
+pub struct Scope {
+    cancel: tokio_util::sync::CancellationToken,
+    tasks: tokio_util::task::TaskTracker,
+    /// The owning [`Graceful`]'s tracker, so shutdown waits for scoped tasks too
+    tracker: tokio_util::task::TaskTracker,
+}
+
+impl Scope {
+    /// Run `task` until it finishes or the scope is cancelled
+    ///
+    /// A cancelled task is dropped at its next await point and its handle
+    /// yields `None`.
+    #[track_caller]
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<Option<F::Output>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let cancel = self.cancel.clone();
+        let task = self.tasks.track_future(async move {
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => None,
+                output = task => Some(output),
+            }
+        });
+        self.tracker.spawn(task)
+    }
+
+    /// Cancel every task of the scope without waiting for them
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Resolves once the scope, or the whole [`Graceful`], is cancelled
+    pub fn cancelled(&self) -> tokio_util::sync::WaitForCancellationFuture<'_> {
+        self.cancel.cancelled()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Tasks still running
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Cancel the remaining tasks and wait until all of them have exited
+    pub async fn close(&self) {
+        self.cancel.cancel();
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scope_close_cancels_and_waits() {
+        let graceful = Graceful::new();
+        let scope = graceful.scope();
+
+        let finished = scope.spawn(async { 7 });
+        assert_eq!(finished.await.unwrap(), Some(7));
+
+        let (dropped_tx, dropped_rx) = tokio::sync::oneshot::channel::<()>();
+        let forever = scope.spawn(async move {
+            let _dropped = dropped_tx;
+            std::future::pending::<()>().await
+        });
+        scope.close().await;
+        assert!(scope.is_empty());
+        assert!(dropped_rx.await.is_err());
+        assert_eq!(forever.await.unwrap(), None);
+
+        // Shutting down the whole tree cancels scoped tasks too
+        let scope = graceful.scope();
+        let waiting = scope.spawn(std::future::pending::<()>());
+        let report = graceful.shutdown_with_timeout(std::time::Duration::from_millis(10)).await;
+        assert_eq!(report.abandoned, 0);
+        assert_eq!(waiting.await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_before_cancelling() {
+        let graceful = Graceful::new();
+        let phases = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        for phase in [ShutdownPhase::StopAccepting, ShutdownPhase::Draining, ShutdownPhase::Cancelling, ShutdownPhase::Stopped] {
+            let phases = phases.clone();
+            graceful.on_shutdown(phase, move || {
+                let phases = phases.clone();
+                async move { phases.lock().unwrap().push(phase) }
+            });
+        }
+
+        // Finishes its work once draining starts, without being cancelled
+        let worker = graceful.clone();
+        let finished_before_cancel = graceful.spawn(async move {
+            worker.draining().await;
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            !worker.cancel.is_cancelled()
+        });
+
+        let report = graceful.shutdown_with_timeout(std::time::Duration::from_secs(5)).await;
+        assert!(report.drained);
+        assert_eq!(report.abandoned, 0);
+        assert!(finished_before_cancel.await.unwrap());
+        assert_eq!(
+            *phases.lock().unwrap(),
+            vec![ShutdownPhase::StopAccepting, ShutdownPhase::Draining, ShutdownPhase::Stopped]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blocking_tasks_are_joined_and_see_cancellation() {
+        let graceful = Graceful::new();
+        let worker = graceful.clone();
+        let stopped_early = graceful.spawn_blocking(move || {
+            // A CPU-heavy loop that stops once cancelled
+            while !worker.is_cancelled() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            true
+        });
+
+        let report = graceful.shutdown_with_timeout(std::time::Duration::from_millis(10)).await;
+        assert!(!report.drained);
+        assert_eq!(report.abandoned, 0);
+        assert!(stopped_early.await.unwrap());
+    }
 }
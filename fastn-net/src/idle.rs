@@ -0,0 +1,169 @@
+/// How long a connection may sit idle, and live at all, while serving a protocol
+///
+/// Keepalive pings never count as activity, so a peer can't hold a
+/// connection open with pings alone; only streams do. Long-lived protocols
+/// like chat raise `max_idle`, while `max_lifetime` caps how long any
+/// connection lives, busy or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdlePolicy {
+    /// Close the connection once no stream has been open for this long
+    pub max_idle: std::time::Duration,
+    /// How often pooled connections ping the peer while idle
+    pub keepalive_interval: std::time::Duration,
+    /// Close the connection this long after it was opened; `None` is no limit
+    pub max_lifetime: Option<std::time::Duration>,
+}
+
+/// Idle timeout of connections serving protocols without a policy of their own
+pub const DEFAULT_MAX_IDLE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Keepalive interval of protocols without a policy of their own
+pub const DEFAULT_KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(12);
+
+/// Busy connections past their idle deadline are checked again this often
+const RECHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        Self {
+            max_idle: DEFAULT_MAX_IDLE,
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            max_lifetime: None,
+        }
+    }
+}
+
+impl IdlePolicy {
+    pub fn with_max_idle(mut self, max_idle: std::time::Duration) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    pub fn with_keepalive_interval(mut self, interval: std::time::Duration) -> Self {
+        self.keepalive_interval = interval;
+        self
+    }
+
+    pub fn with_max_lifetime(mut self, max_lifetime: std::time::Duration) -> Self {
+        self.max_lifetime = Some(max_lifetime);
+        self
+    }
+}
+
+/// Why [`IdleTimer`] says a connection should close
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum IdleExpiry {
+    #[error("idle for {max_idle:?}")]
+    Idle { max_idle: std::time::Duration },
+
+    #[error("reached its maximum lifetime of {max_lifetime:?}")]
+    Lifetime { max_lifetime: std::time::Duration },
+}
+
+/// Idle and lifetime deadlines of one connection
+///
+/// Starts out with the policy the connection was opened under. Once it has
+/// served a stream, the most lenient policy among the protocols it served
+/// applies: the longest idle timeout and lifetime, the shortest keepalive.
+#[derive(Debug, Clone)]
+pub struct IdleTimer {
+    opened: std::time::Instant,
+    last_active: std::time::Instant,
+    policy: IdlePolicy,
+    served: bool,
+}
+
+impl IdleTimer {
+    pub fn new(policy: IdlePolicy) -> Self {
+        let now = std::time::Instant::now();
+        Self { opened: now, last_active: now, policy, served: false }
+    }
+
+    /// The connection is used for a stream under `policy`, now
+    pub fn touch(&mut self, policy: &IdlePolicy) {
+        self.touch_at(std::time::Instant::now(), policy);
+    }
+
+    fn touch_at(&mut self, now: std::time::Instant, policy: &IdlePolicy) {
+        self.last_active = now;
+        if !self.served {
+            self.served = true;
+            self.policy = *policy;
+            return;
+        }
+        self.policy.max_idle = self.policy.max_idle.max(policy.max_idle);
+        self.policy.keepalive_interval = self.policy.keepalive_interval.min(policy.keepalive_interval);
+        self.policy.max_lifetime = match (self.policy.max_lifetime, policy.max_lifetime) {
+            (Some(current), Some(other)) => Some(current.max(other)),
+            _ => None,
+        };
+    }
+
+    /// The policy in effect
+    pub fn policy(&self) -> &IdlePolicy {
+        &self.policy
+    }
+
+    /// Whether the connection should close; `busy` ones only by lifetime
+    pub fn expired(&self, busy: bool) -> Option<IdleExpiry> {
+        self.expired_at(std::time::Instant::now(), busy)
+    }
+
+    fn expired_at(&self, now: std::time::Instant, busy: bool) -> Option<IdleExpiry> {
+        if let Some(max_lifetime) = self.policy.max_lifetime
+            && now >= self.opened + max_lifetime
+        {
+            return Some(IdleExpiry::Lifetime { max_lifetime });
+        }
+        let max_idle = self.policy.max_idle;
+        match !busy && now >= self.last_active + max_idle {
+            true => Some(IdleExpiry::Idle { max_idle }),
+            false => None,
+        }
+    }
+
+    /// How long to wait before calling [`Self::expired`] again
+    ///
+    /// Until the nearest deadline, but at least a second, so a busy
+    /// connection past its idle deadline is not polled in a tight loop.
+    pub fn next_check(&self) -> std::time::Duration {
+        let now = std::time::Instant::now();
+        let idle_deadline = self.last_active + self.policy.max_idle;
+        let deadline = match self.policy.max_lifetime {
+            Some(max_lifetime) => idle_deadline.min(self.opened + max_lifetime),
+            None => idle_deadline,
+        };
+        deadline.saturating_duration_since(now).max(RECHECK_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_lenient_served_policy_applies() {
+        let second = std::time::Duration::from_secs(1);
+        let mut timer = IdleTimer::new(IdlePolicy::default());
+        let start = timer.opened;
+
+        // The first stream replaces the policy the connection opened under
+        let short = IdlePolicy::default().with_max_idle(10 * second).with_max_lifetime(100 * second);
+        timer.touch_at(start, &short);
+        assert_eq!(timer.expired_at(start + 11 * second, false), Some(IdleExpiry::Idle { max_idle: 10 * second }));
+        assert_eq!(timer.expired_at(start + 11 * second, true), None);
+
+        let chat = IdlePolicy::default().with_max_idle(30 * 60 * second).with_max_lifetime(3600 * second);
+        timer.touch_at(start + 5 * second, &chat);
+        assert_eq!(timer.expired_at(start + 600 * second, false), None);
+        assert_eq!(
+            timer.expired_at(start + 3600 * second, true),
+            Some(IdleExpiry::Lifetime { max_lifetime: 3600 * second })
+        );
+
+        // A protocol without a lifetime lifts the cap
+        timer.touch_at(start + 10 * second, &IdlePolicy::default());
+        assert_eq!(timer.expired_at(start + 3600 * second, true), None);
+        assert_eq!(timer.policy().keepalive_interval, DEFAULT_KEEPALIVE_INTERVAL);
+    }
+}
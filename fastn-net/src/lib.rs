@@ -36,9 +36,11 @@ extern crate self as fastn_net;
 
 pub mod dot_fastn;
 pub mod errors;
+mod frame_reader;
 pub mod get_endpoint;
 mod get_stream;
 mod graceful;
+pub mod idle;
 pub mod http;
 mod http_connection_manager;
 mod http_to_peer;
@@ -47,13 +49,16 @@ mod ping;
 pub mod protocol;
 mod secret;
 mod tcp;
+pub mod trace;
 mod utils;
 mod utils_iroh;
 
+pub use frame_reader::{FrameReader, MAX_FRAME_LEN};
 pub use get_endpoint::get_endpoint;
 pub use get_stream::{PeerStreamSenders, get_stream};
-pub use graceful::Graceful;
+pub use graceful::{DEFAULT_DRAIN_TIMEOUT, Graceful, Scope, ShutdownPhase, ShutdownReport};
 pub use http::ProxyResult;
+pub use idle::{IdleExpiry, IdlePolicy, IdleTimer};
 pub use http_connection_manager::{HttpConnectionManager, HttpConnectionPool, HttpConnectionPools};
 pub use http_to_peer::{http_to_peer, http_to_peer_non_streaming};
 pub use peer_to_http::peer_to_http;
@@ -61,9 +66,10 @@ pub use ping::{PONG, ping};
 pub use protocol::{APNS_IDENTITY, Protocol, ProtocolHeader};
 pub use secret::read_or_create_key;
 pub use tcp::{peer_to_tcp, pipe_tcp_stream_over_iroh, tcp_to_peer};
+pub use trace::TraceContext;
 pub use utils::mkdir;
 pub use utils_iroh::{
-    accept_bi, accept_bi_with, get_remote_id52, global_iroh_endpoint, next_json, next_string,
+    accept_any_bi, accept_bi, accept_bi_with, get_remote_id52, global_iroh_endpoint, next_json, next_string,
 };
 
 // Deprecated helper functions - use fastn_id52 directly
@@ -109,18 +109,13 @@ pub async fn peer_to_http(
         }
     }
 
-    tracing::info!("handled http request in {:?}", start.elapsed());
-
-    {
-        use colored::Colorize;
-        println!(
-            "{} {} {} in {}",
-            req.method.to_uppercase().green(),
-            req.uri,
-            resp.status.as_str().on_blue().black(),
-            format!("{}ms", start.elapsed().as_millis()).yellow()
-        );
-    }
+    tracing::info!(
+        "{} {} {} in {}ms",
+        req.method.to_uppercase(),
+        req.uri,
+        resp.status.as_str(),
+        start.elapsed().as_millis()
+    );
 
     Ok(())
 }
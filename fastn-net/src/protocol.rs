@@ -110,6 +110,25 @@ pub enum Protocol {
     /// Control messages for Rig management (bring online/offline, set current, etc.)
     RigControl,
 
+    /// Forward this stream to `target` (an ID52) through the receiving peer.
+    ///
+    /// Clients send it with `from: None`. A relay passes the stream on with
+    /// `from` set to the ID52 of the peer it is relaying for, which the target
+    /// only honours if it trusts the relay.
+    Relay {
+        target: String,
+        #[serde(default)]
+        from: Option<String>,
+    },
+
+    /// Pair the sending device with the identity it connects to, using a
+    /// one-time code shown on the primary.
+    DevicePair,
+
+    /// Forward this stream to `target` (an ID52) as the identity the receiving
+    /// peer hosts. Only accepted from devices paired with that identity.
+    DeviceProxy { target: String },
+
     /// Generic protocol for user-defined types
     /// This allows users to define their own protocol types while maintaining
     /// compatibility with the existing fastn-net infrastructure.
@@ -129,6 +148,9 @@ impl std::fmt::Display for Protocol {
             Protocol::AccountToAccount => write!(f, "AccountToAccount"),
             Protocol::AccountToDevice => write!(f, "AccountToDevice"),
             Protocol::RigControl => write!(f, "RigControl"),
+            Protocol::Relay { target, .. } => write!(f, "Relay({target})"),
+            Protocol::DevicePair => write!(f, "DevicePair"),
+            Protocol::DeviceProxy { target } => write!(f, "DeviceProxy({target})"),
             Protocol::Generic(value) => write!(f, "Generic({value})"),
         }
     }
@@ -152,6 +174,18 @@ mod tests {
             _ => panic!("Expected Generic variant"),
         }
     }
+
+    #[test]
+    fn test_protocol_relay_from_defaults_to_none() {
+        let protocol: Protocol = serde_json::from_str(r#"{"Relay":{"target":"abc"}}"#).unwrap();
+        assert_eq!(
+            protocol,
+            Protocol::Relay {
+                target: "abc".to_string(),
+                from: None,
+            }
+        );
+    }
 }
 
 /// Single ALPN protocol identifier for all fastn entity connections.
@@ -169,10 +203,19 @@ pub const APNS_IDENTITY: &[u8] = b"/fastn/entity/0.1";
 ///
 /// Sent at the beginning of each bidirectional stream to identify
 /// the protocol and provide any protocol-specific metadata.
+///
+/// `trace` is not written to the stream; it ties the local spans for opening
+/// the stream to the request being made (see [`crate::trace`]). Protocols that
+/// propagate traces to the peer carry them in their own payload.
+///
+/// `idle_policy` isn't written either; it tells the connection pool how long
+/// to keep the connection around for this protocol, the default if `None`.
 #[derive(Debug)]
 pub struct ProtocolHeader {
     pub protocol: Protocol,
     pub extra: Option<String>,
+    pub trace: Option<crate::TraceContext>,
+    pub idle_policy: Option<crate::IdlePolicy>,
 }
 
 impl From<Protocol> for ProtocolHeader {
@@ -180,6 +223,8 @@ impl From<Protocol> for ProtocolHeader {
         Self {
             protocol,
             extra: None,
+            trace: None,
+            idle_policy: None,
         }
     }
 }
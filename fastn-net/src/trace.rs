@@ -0,0 +1,127 @@
+//! Trace context propagated across peers
+//!
+//! A [`TraceContext`] follows one logical request from the CLI through the
+//! local daemon and any remote daemons to the handler that serves it. It is
+//! carried as W3C `traceparent` ids: a 128-bit trace id shared by every hop and
+//! a 64-bit span id per hop.
+//!
+//! Within a process the context of the request being served is task-local
+//! ([`TraceContext::scope`]), so outgoing calls made while handling it become
+//! children of it ([`TraceContext::for_outgoing`]).
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TraceContext {
+    /// 32 lowercase hex characters, shared by every span of the trace
+    pub trace_id: String,
+    /// 16 lowercase hex characters, unique to this hop
+    pub span_id: String,
+    /// Span id of the hop that created this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_span_id: Option<String>,
+}
+
+impl TraceContext {
+    /// Start a new trace
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: random_hex(16),
+            span_id: random_hex(8),
+            parent_span_id: None,
+        }
+    }
+
+    /// A new span in the same trace, with this one as its parent
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            span_id: random_hex(8),
+            parent_span_id: Some(self.span_id.clone()),
+        }
+    }
+
+    /// The context of the request this task is serving, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Context for an outgoing request: a child of [`TraceContext::current`], or a new trace
+    pub fn for_outgoing() -> Self {
+        Self::current()
+            .map(|current| current.child())
+            .unwrap_or_else(Self::new_root)
+    }
+
+    /// Run `future` with this as the current context
+    pub async fn scope<F: std::future::Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    /// W3C `traceparent` header value, always sampled
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+
+    /// Parse a W3C `traceparent` header value
+    pub fn from_traceparent(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let (Some("00"), Some(trace_id), Some(span_id), Some(_flags), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+
+        let is_hex_id = |id: &str, len: usize| {
+            id.len() == len
+                && id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+                && id.bytes().any(|b| b != b'0')
+        };
+        if !is_hex_id(trace_id, 32) || !is_hex_id(span_id, 16) {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            span_id: span_id.to_string(),
+            parent_span_id: None,
+        })
+    }
+}
+
+fn random_hex(bytes: usize) -> String {
+    let random: Vec<u8> = (0..bytes).map(|_| rand::random::<u8>()).collect();
+    data_encoding::HEXLOWER.encode(&random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent_round_trip() {
+        let root = TraceContext::new_root();
+        let parsed = TraceContext::from_traceparent(&root.traceparent()).unwrap();
+        assert_eq!(parsed.trace_id, root.trace_id);
+        assert_eq!(parsed.span_id, root.span_id);
+
+        let child = root.child();
+        assert_eq!(child.trace_id, root.trace_id);
+        assert_eq!(child.parent_span_id.as_deref(), Some(root.span_id.as_str()));
+
+        assert!(TraceContext::from_traceparent("00-abc-def-01").is_none());
+        assert!(TraceContext::from_traceparent(&format!("00-{}-{}-01", "0".repeat(32), root.span_id)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_context_is_child_of_current() {
+        assert!(TraceContext::current().is_none());
+
+        let root = TraceContext::new_root();
+        let outgoing = root.clone().scope(async { TraceContext::for_outgoing() }).await;
+        assert_eq!(outgoing.trace_id, root.trace_id);
+        assert_eq!(outgoing.parent_span_id, Some(root.span_id));
+    }
+}
@@ -52,6 +52,7 @@ async fn ack(send: &mut iroh::endpoint::SendStream) -> eyre::Result<()> {
 /// # Returns
 ///
 /// Returns the actual protocol received along with the send and receive streams.
+/// The receive stream is buffered, see [`crate::FrameReader`].
 ///
 /// # Errors
 ///
@@ -62,7 +63,30 @@ pub async fn accept_bi(
 ) -> eyre::Result<(
     crate::Protocol,
     iroh::endpoint::SendStream,
-    iroh::endpoint::RecvStream,
+    crate::FrameReader<iroh::endpoint::RecvStream>,
+)> {
+    let (found, s, r) = accept_any_bi(conn).await?;
+    if expected.contains(&found) {
+        return Ok((found, s, r));
+    }
+    Err(eyre::anyhow!("expected one of: {expected:?}, got {found:?}"))
+}
+
+/// Accepts the next non-ping bidirectional stream, whatever its protocol.
+///
+/// Use this instead of [`accept_bi`] when the protocol carries data that is
+/// not known up front, like the target of [`crate::Protocol::Relay`]. Pings are
+/// answered automatically.
+///
+/// # Errors
+///
+/// Returns an error if the stream cannot be accepted or its header is invalid.
+pub async fn accept_any_bi(
+    conn: &iroh::endpoint::Connection,
+) -> eyre::Result<(
+    crate::Protocol,
+    iroh::endpoint::SendStream,
+    crate::FrameReader<iroh::endpoint::RecvStream>,
 )> {
     loop {
         tracing::trace!("accepting bidirectional stream");
@@ -77,12 +101,7 @@ pub async fn accept_bi(
             }
             (s, r, found) => {
                 tracing::trace!("got bidirectional stream: {found:?}");
-                if expected.contains(&found) {
-                    return Ok((found, s, r));
-                }
-                return Err(eyre::anyhow!(
-                    "expected one of: {expected:?}, got {found:?}"
-                ));
+                return Ok((found, s, r));
             }
         }
     }
@@ -107,10 +126,11 @@ pub async fn accept_bi_with<T: serde::de::DeserializeOwned>(
     crate::Protocol,
     T,
     iroh::endpoint::SendStream,
-    iroh::endpoint::RecvStream,
+    crate::FrameReader<iroh::endpoint::RecvStream>,
 )> {
     let (protocol, send, mut recv) = accept_bi(conn, expected).await?;
-    let next = next_json(&mut recv)
+    let next = recv
+        .next_json()
         .await
         .inspect_err(|e| tracing::error!("failed to read next message: {e}"))?;
 
@@ -121,14 +141,17 @@ async fn accept_bi_(
     conn: &iroh::endpoint::Connection,
 ) -> eyre::Result<(
     iroh::endpoint::SendStream,
-    iroh::endpoint::RecvStream,
+    crate::FrameReader<iroh::endpoint::RecvStream>,
     crate::Protocol,
 )> {
     tracing::trace!("accept_bi_ called");
-    let (mut send, mut recv) = conn.accept_bi().await?;
+    let (mut send, recv) = conn.accept_bi().await?;
     tracing::trace!("accept_bi_ got send and recv");
 
-    let msg: crate::Protocol = next_json(&mut recv)
+    // Whatever the peer sent after the header stays buffered in the reader
+    let mut recv = crate::FrameReader::new(recv);
+    let msg: crate::Protocol = recv
+        .next_json()
         .await
         .inspect_err(|e| tracing::error!("failed to read next message: {e}"))?;
 
@@ -149,30 +172,12 @@ async fn accept_bi_(
 ///
 /// Returns an error if:
 /// - Connection is closed while reading
+/// - The message exceeds [`crate::MAX_FRAME_LEN`]
 /// - JSON deserialization fails
 pub async fn next_json<T: serde::de::DeserializeOwned>(
-    recv: &mut iroh::endpoint::RecvStream,
+    recv: &mut (impl tokio::io::AsyncRead + Unpin),
 ) -> eyre::Result<T> {
-    // NOTE: the capacity is just a guess to avoid reallocations
-    let mut buffer = Vec::with_capacity(1024);
-
-    loop {
-        let mut byte = [0u8];
-        let n = recv.read(&mut byte).await?;
-
-        if n == Some(0) || n.is_none() {
-            return Err(eyre::anyhow!(
-                "connection closed while reading response header"
-            ));
-        }
-
-        if byte[0] == b'\n' {
-            break;
-        } else {
-            buffer.push(byte[0]);
-        }
-    }
-
+    let buffer = next_frame(recv, crate::MAX_FRAME_LEN).await?;
     Ok(serde_json::from_slice(&buffer)?)
 }
 
@@ -185,29 +190,42 @@ pub async fn next_json<T: serde::de::DeserializeOwned>(
 ///
 /// Returns an error if:
 /// - Connection is closed while reading
+/// - The string exceeds [`crate::MAX_FRAME_LEN`]
 /// - Bytes are not valid UTF-8
-pub async fn next_string(recv: &mut iroh::endpoint::RecvStream) -> eyre::Result<String> {
+pub async fn next_string(recv: &mut (impl tokio::io::AsyncRead + Unpin)) -> eyre::Result<String> {
+    let buffer = next_frame(recv, crate::MAX_FRAME_LEN).await?;
+    String::from_utf8(buffer).map_err(|e| eyre::anyhow!("failed to convert bytes to string: {e}"))
+}
+
+/// Reads up to the next newline, one byte at a time so nothing past it is consumed
+async fn next_frame(
+    recv: &mut (impl tokio::io::AsyncRead + Unpin),
+    limit: usize,
+) -> eyre::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
     // NOTE: the capacity is just a guess to avoid reallocations
     let mut buffer = Vec::with_capacity(1024);
 
     loop {
         let mut byte = [0u8];
-        let n = recv.read(&mut byte).await?;
-
-        if n == Some(0) || n.is_none() {
-            return Err(eyre::anyhow!(
-                "connection closed while reading response header"
-            ));
+        if recv.read(&mut byte).await? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed while reading response header",
+            )
+            .into());
         }
 
         if byte[0] == b'\n' {
-            break;
-        } else {
-            buffer.push(byte[0]);
+            return Ok(buffer);
+        }
+        // Without a limit a peer that never sends a newline grows the buffer forever
+        if buffer.len() == limit {
+            return Err(crate::errors::FrameTooLargeError { limit }.into());
         }
+        buffer.push(byte[0]);
     }
-
-    String::from_utf8(buffer).map_err(|e| eyre::anyhow!("failed to convert bytes to string: {e}"))
 }
 
 /// Returns a global singleton Iroh endpoint.
@@ -237,3 +255,47 @@ pub async fn global_iroh_endpoint() -> iroh::Endpoint {
         tokio::sync::OnceCell::const_new();
     IROH_ENDPOINT.get_or_init(new_iroh_endpoint).await.clone()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_next_json_never_panics(input in proptest::collection::vec(proptest::num::u8::ANY, 0..512)) {
+            let _ = block_on(next_json::<serde_json::Value>(&mut input.as_slice()));
+            let _ = block_on(next_json::<crate::Protocol>(&mut input.as_slice()));
+            let _ = block_on(next_string(&mut input.as_slice()));
+        }
+
+        #[test]
+        fn test_next_string_stops_at_the_newline(
+            line in "[^\n]*",
+            rest in proptest::collection::vec(proptest::num::u8::ANY, 0..64),
+        ) {
+            let input = [format!("{line}\n").into_bytes(), rest.clone()].concat();
+            let mut recv = input.as_slice();
+            proptest::prop_assert_eq!(block_on(next_string(&mut recv)).unwrap(), line);
+            proptest::prop_assert_eq!(recv, rest.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_endless_frames_are_rejected() {
+        // A peer streaming bytes without ever sending a newline
+        let error = block_on(next_frame(&mut tokio::io::repeat(b'a'), 64)).unwrap_err();
+        assert_eq!(
+            error
+                .downcast_ref::<crate::errors::FrameTooLargeError>()
+                .map(|e| e.limit),
+            Some(64)
+        );
+    }
+}
@@ -0,0 +1,519 @@
+//! Daemon management over the control socket
+//!
+//! Typed versions of what `fastn-p2p create-identity`, `add-protocol`,
+//! `identity-online` and `status` do, for tools that manage the daemon
+//! without shelling out to the CLI. Each function sends one request to the
+//! daemon of `fastn_home` and returns the daemon's answer as a struct.
+//!
+//! ```rust,no_run
+//! # async fn example(fastn_home: &std::path::Path) -> Result<(), fastn_p2p_client::ClientError> {
+//! use fastn_p2p_client::admin;
+//!
+//! let created = admin::create_identity(fastn_home, "alice").await?;
+//! admin::add_protocol(fastn_home, "alice", "mail.fastn.com", "default", serde_json::json!({})).await?;
+//! admin::set_identity_online(fastn_home, "alice", true).await?;
+//!
+//! for identity in admin::identities(fastn_home).await? {
+//!     println!("{} {} online={}", identity.alias, identity.peer, identity.online);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A daemon that refuses a change answers with a `kind`, mapped by
+//! [`ClientError::from_daemon_error`] to e.g. [`ClientError::Identity`],
+//! [`ClientError::Protocol`] or [`ClientError::Rejected`].
+
+use std::path::Path;
+
+use crate::client::DaemonRequest;
+use crate::error::ClientError;
+
+/// Result of [`reload_identities`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdentitiesReloaded {
+    pub total: usize,
+    pub online: usize,
+}
+
+/// Result of [`create_identity`] and [`import_identity`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdentityCreated {
+    pub identity: String,
+    /// ID52 of the new identity; its secret key stays with the daemon
+    pub peer: String,
+}
+
+/// Result of [`set_identity_online`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdentityStateChanged {
+    pub identity: String,
+    pub online: bool,
+}
+
+/// State of a binding's listener in the daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerState {
+    Running,
+    Stopped,
+}
+
+/// Result of [`add_protocol`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProtocolAdded {
+    pub identity: String,
+    pub protocol: String,
+    pub bind_alias: String,
+    /// `None` until something serves the binding
+    pub state: Option<ListenerState>,
+}
+
+/// Result of [`remove_protocol`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProtocolRemoved {
+    pub identity: String,
+    pub protocol: String,
+    pub bind_alias: String,
+}
+
+/// One identity as [`identities`] reports it
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdentityStatus {
+    pub alias: String,
+    /// ID52 of the identity
+    pub peer: String,
+    pub online: bool,
+    pub bindings: Vec<BindingStatus>,
+}
+
+/// One protocol binding of an [`IdentityStatus`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BindingStatus {
+    pub protocol: String,
+    pub bind_alias: String,
+    /// `None` if the daemon has no listener for it
+    pub state: Option<ListenerState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// One peer's circuit breaker in the daemon, see [`peer_status`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PeerCircuit {
+    pub peer: String,
+    pub state: CircuitState,
+    /// Calls and failures in the current window
+    pub calls: u32,
+    pub failures: u32,
+    /// Seconds until the next probe is let through, unless closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>,
+}
+
+/// Result of [`ping`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PingReply {
+    pub peer: String,
+    /// Identity the daemon pinged as
+    pub from_identity: String,
+    pub rtt_ms: f64,
+    /// `direct`, `relay`, `mixed` or `unknown`
+    pub path: String,
+}
+
+/// Latency and errors of one protocol's (or serve_all command's) request handler, see [`protocol_metrics`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HandlerStats {
+    pub protocol: String,
+    /// serve_all command; `None` for protocols served with `listen()`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    pub calls: u64,
+    /// Calls that ended in an error, a timeout or the peer going away
+    pub errors: u64,
+    pub mean_ms: f64,
+    /// Estimated from histogram buckets, so accurate to a bucket's width
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+/// How the daemon maps online identities to endpoints, see [`endpoint_status`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EndpointsStatus {
+    pub max_endpoints: usize,
+    /// Endpoints bound right now
+    pub bound: usize,
+    /// Endpoints start on first use
+    #[serde(default)]
+    pub lazy: bool,
+    /// Endpoints unused this long are stopped; 0 never
+    #[serde(default)]
+    pub idle_stop_secs: u64,
+    pub identities: Vec<IdentityEndpoint>,
+}
+
+/// The endpoint serving one online identity
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct IdentityEndpoint {
+    pub identity: String,
+    pub peer: String,
+    /// ID the endpoint answers for
+    pub endpoint: String,
+    /// Local UDP ports the endpoint is bound to
+    pub ports: Vec<u16>,
+    /// Home relay the endpoint registered with, if any yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay: Option<String>,
+    /// Not bound until the identity is next used
+    #[serde(default)]
+    pub stopped: bool,
+    /// Seconds since the identity was last used, or went online
+    #[serde(default)]
+    pub idle_secs: u64,
+}
+
+/// Result of [`clear_cache`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheCleared {
+    /// Cached answers dropped
+    pub cleared: usize,
+}
+
+/// A stream waiting for the user to accept or deny it, see [`approvals`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PendingApproval {
+    pub id: String,
+    /// ID52 of the identity the stream was opened to
+    pub identity: String,
+    pub peer: String,
+    pub protocol: String,
+    /// What the handler asks about, e.g. "photos.zip (14 MB)"
+    pub summary: String,
+    /// Milliseconds since the Unix epoch
+    pub requested_ms: u64,
+    /// Denied automatically from then on
+    pub expires_ms: u64,
+}
+
+/// A peer's strikes against one identity, see [`reputation`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PeerReputation {
+    /// ID52 of the identity the peer offended
+    pub identity: String,
+    pub peer: String,
+    pub strikes: u32,
+    /// Strikes that ban the peer
+    pub threshold: u32,
+    /// e.g. `auth-failure`, `malformed-frame` or `limit-violation`
+    pub last_offense: String,
+    /// Milliseconds since the Unix epoch; `None` when not banned
+    pub banned_until_ms: Option<u64>,
+}
+
+/// Result of [`clear_reputation`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ReputationCleared {
+    /// Records dropped, one per identity the peer had strikes with
+    pub cleared: usize,
+}
+
+/// Result of [`drain`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DrainReport {
+    /// Whether every stream finished within the timeout
+    pub drained: bool,
+    /// Streams being served when draining started
+    pub in_flight_at_start: usize,
+    /// Streams still running when the daemon answered
+    pub remaining: usize,
+    pub elapsed_ms: u64,
+}
+
+/// Result of [`daemon_info`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DaemonInfo {
+    /// Release of the fastn-p2p daemon
+    pub version: String,
+    /// Control protocol it speaks, see [`crate::version`]
+    pub wire_version: u32,
+    /// Oldest control protocol it still serves
+    pub min_wire_version: u32,
+    /// Platform it was built for, e.g. `x86_64-linux`
+    pub target: String,
+    /// Optional features it was built with
+    pub features: Vec<String>,
+    pub started_ms: u64,
+    /// A newer release on the configured update channel, if the daemon found one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update: Option<UpdateInfo>,
+}
+
+/// A release announced on the daemon's update channel
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UpdateInfo {
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// Result of [`accept_approval`] and [`deny_approval`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ApprovalResolved {
+    pub id: String,
+    pub approved: bool,
+}
+
+/// Re-read every identity from FASTN_HOME
+pub async fn reload_identities(fastn_home: &Path) -> Result<IdentitiesReloaded, ClientError> {
+    request(fastn_home, &DaemonRequest::ReloadIdentities).await
+}
+
+/// Create identity `alias` with a fresh key, offline and without bindings
+pub async fn create_identity(fastn_home: &Path, alias: &str) -> Result<IdentityCreated, ClientError> {
+    request(fastn_home, &DaemonRequest::CreateIdentity { identity: alias.to_string(), secret_key: None }).await
+}
+
+/// Create identity `alias` with an existing key, e.g. one an example server used
+///
+/// Fails if another identity already has this key.
+pub async fn import_identity(
+    fastn_home: &Path,
+    alias: &str,
+    secret_key: &fastn_id52::SecretKey,
+) -> Result<IdentityCreated, ClientError> {
+    let request_data = DaemonRequest::CreateIdentity { identity: alias.to_string(), secret_key: Some(secret_key.clone()) };
+    request(fastn_home, &request_data).await
+}
+
+/// Every identity the daemon knows, with its bindings
+pub async fn identities(fastn_home: &Path) -> Result<Vec<IdentityStatus>, ClientError> {
+    #[derive(serde::Deserialize)]
+    struct Identities {
+        identities: Vec<IdentityStatus>,
+    }
+    let response: Identities = request(fastn_home, &DaemonRequest::ListIdentities).await?;
+    Ok(response.identities)
+}
+
+/// Bring `identity` online or take it offline; its bindings start or stop
+pub async fn set_identity_online(
+    fastn_home: &Path,
+    identity: &str,
+    online: bool,
+) -> Result<IdentityStateChanged, ClientError> {
+    request(fastn_home, &DaemonRequest::SetIdentityState { identity: identity.to_string(), online }).await
+}
+
+/// Bind `protocol` as `bind_alias` for `identity`, with `config` as its `config.json`
+pub async fn add_protocol(
+    fastn_home: &Path,
+    identity: &str,
+    protocol: &str,
+    bind_alias: &str,
+    config: serde_json::Value,
+) -> Result<ProtocolAdded, ClientError> {
+    let add = DaemonRequest::AddProtocol {
+        identity: identity.to_string(),
+        protocol: protocol.to_string(),
+        bind_alias: bind_alias.to_string(),
+        config: crate::Sensitive(config),
+    };
+    request(fastn_home, &add).await
+}
+
+/// Remove a binding; its data next to `config.json` is kept
+pub async fn remove_protocol(
+    fastn_home: &Path,
+    identity: &str,
+    protocol: &str,
+    bind_alias: &str,
+) -> Result<ProtocolRemoved, ClientError> {
+    let remove = DaemonRequest::RemoveProtocol {
+        identity: identity.to_string(),
+        protocol: protocol.to_string(),
+        bind_alias: bind_alias.to_string(),
+    };
+    request(fastn_home, &remove).await
+}
+
+/// Circuit breaker of every peer the daemon called, ordered by peer
+pub async fn peer_status(fastn_home: &Path) -> Result<Vec<PeerCircuit>, ClientError> {
+    #[derive(serde::Deserialize)]
+    struct Peers {
+        peers: Vec<PeerCircuit>,
+    }
+    let response: Peers = request(fastn_home, &DaemonRequest::PeerStatus).await?;
+    Ok(response.peers)
+}
+
+/// Handler latencies of every protocol served by the daemon, ordered by protocol and command
+pub async fn protocol_metrics(fastn_home: &Path) -> Result<Vec<HandlerStats>, ClientError> {
+    #[derive(serde::Deserialize)]
+    struct Handlers {
+        handlers: Vec<HandlerStats>,
+    }
+    let response: Handlers = request(fastn_home, &DaemonRequest::ProtocolMetrics).await?;
+    Ok(response.handlers)
+}
+
+/// Endpoint limits of the daemon and the endpoint of every online identity, ordered by identity
+pub async fn endpoint_status(fastn_home: &Path) -> Result<EndpointsStatus, ClientError> {
+    request(fastn_home, &DaemonRequest::EndpointStatus).await
+}
+
+/// Have the daemon ping `peer` once as `from_identity`, or the default identity
+///
+/// The daemon keeps its connection to `peer`, so only the first ping of a
+/// series includes connection setup.
+pub async fn ping(
+    fastn_home: &Path,
+    from_identity: Option<&str>,
+    peer: fastn_id52::PublicKey,
+) -> Result<PingReply, ClientError> {
+    request(fastn_home, &DaemonRequest::Ping { from_identity: from_identity.map(str::to_string), to_peer: peer }).await
+}
+
+/// Drop the call answers the daemon cached, all or only those of `peer` and/or `protocol`
+pub async fn clear_cache(
+    fastn_home: &Path,
+    peer: Option<fastn_id52::PublicKey>,
+    protocol: Option<&str>,
+) -> Result<CacheCleared, ClientError> {
+    request(fastn_home, &DaemonRequest::ClearCache { peer, protocol: protocol.map(str::to_string) }).await
+}
+
+/// Streams waiting for approval in the daemon, oldest first
+pub async fn approvals(fastn_home: &Path) -> Result<Vec<PendingApproval>, ClientError> {
+    #[derive(serde::Deserialize)]
+    struct Approvals {
+        approvals: Vec<PendingApproval>,
+    }
+    let response: Approvals = request(fastn_home, &DaemonRequest::ListApprovals).await?;
+    Ok(response.approvals)
+}
+
+/// Let the stream waiting under approval `id` go on
+pub async fn accept_approval(fastn_home: &Path, id: &str) -> Result<ApprovalResolved, ClientError> {
+    request(fastn_home, &DaemonRequest::ResolveApproval { id: id.to_string(), approve: true }).await
+}
+
+/// Reject the stream waiting under approval `id`
+pub async fn deny_approval(fastn_home: &Path, id: &str) -> Result<ApprovalResolved, ClientError> {
+    request(fastn_home, &DaemonRequest::ResolveApproval { id: id.to_string(), approve: false }).await
+}
+
+/// Peers the daemon's identities hold strikes against, banned ones first
+pub async fn reputation(fastn_home: &Path) -> Result<Vec<PeerReputation>, ClientError> {
+    #[derive(serde::Deserialize)]
+    struct Reputation {
+        peers: Vec<PeerReputation>,
+    }
+    let response: Reputation = request(fastn_home, &DaemonRequest::ListReputation).await?;
+    Ok(response.peers)
+}
+
+/// Forgive `peer` and lift its ban, with `identity` only or with every identity
+pub async fn clear_reputation(
+    fastn_home: &Path,
+    peer: fastn_id52::PublicKey,
+    identity: Option<fastn_id52::PublicKey>,
+) -> Result<ReputationCleared, ClientError> {
+    request(fastn_home, &DaemonRequest::ClearReputation { peer, identity }).await
+}
+
+/// Make the daemon stop accepting connections and streams, then wait up to
+/// `timeout` for the ones in flight to finish
+///
+/// Answers once they have or the timeout passed; the daemon keeps running
+/// either way, ready to be restarted.
+pub async fn drain(fastn_home: &Path, timeout: std::time::Duration) -> Result<DrainReport, ClientError> {
+    request(fastn_home, &DaemonRequest::Drain { timeout_ms: timeout.as_millis() as u64 }).await
+}
+
+/// Build and protocol versions of the daemon
+///
+/// Fails with [`ClientError::VersionMismatch`] when this client can't talk
+/// to it, with a hint on which side to upgrade.
+pub async fn daemon_info(fastn_home: &Path) -> Result<DaemonInfo, ClientError> {
+    let info: DaemonInfo = request(fastn_home, &DaemonRequest::Version).await?;
+    if !crate::version::is_supported(info.wire_version) {
+        return Err(ClientError::VersionMismatch {
+            daemon_version: info.version,
+            daemon_wire: info.wire_version,
+            client_wire: crate::version::WIRE_VERSION,
+        });
+    }
+    Ok(info)
+}
+
+/// Send one control request and decode the `data` of the response line
+pub(crate) async fn request<T>(fastn_home: &Path, request: &DaemonRequest<serde_json::Value>) -> Result<T, ClientError>
+where
+    T: for<'de> serde::Deserialize<'de>,
+{
+    let (_, _, data) = open(fastn_home, request).await?;
+    Ok(serde_json::from_value(data)?)
+}
+
+/// Send one control request and read the response line
+///
+/// Returns the connection too, for requests the daemon keeps answering
+/// after the response line, like `subscribe-events`.
+pub(crate) async fn open(
+    fastn_home: &Path,
+    request: &DaemonRequest<serde_json::Value>,
+) -> Result<
+    (tokio::io::BufReader<tokio::net::unix::OwnedReadHalf>, tokio::net::unix::OwnedWriteHalf, serde_json::Value),
+    ClientError,
+> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let socket_path = fastn_home.join("control.sock");
+    // A socket left behind by a daemon that died refuses connections
+    let stream = match tokio::net::UnixStream::connect(&socket_path).await {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused) => {
+            return Err(ClientError::DaemonNotRunning { path: socket_path });
+        }
+        Err(e) => return Err(ClientError::DaemonConnection(format!("Failed to connect to daemon: {}", e))),
+    };
+
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(crate::version::encode_request(request)?.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut response_line = String::new();
+    if reader.read_line(&mut response_line).await? == 0 {
+        // Daemons from before version negotiation drop requests they don't know
+        if matches!(request, DaemonRequest::Version) {
+            return Err(ClientError::VersionMismatch {
+                daemon_version: "unknown".to_string(),
+                daemon_wire: 0,
+                client_wire: crate::version::WIRE_VERSION,
+            });
+        }
+        return Err(ClientError::DaemonConnection("Daemon closed connection without response".to_string()));
+    }
+    let response: Response = serde_json::from_str(response_line.trim())?;
+    if !response.success {
+        return Err(ClientError::from_daemon_error(&response.data));
+    }
+    Ok((reader, writer, response.data))
+}
+
+#[derive(serde::Deserialize)]
+struct Response {
+    success: bool,
+    data: serde_json::Value,
+}
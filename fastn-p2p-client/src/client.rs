@@ -3,41 +3,232 @@
 //! This module provides the same API as the original fastn_p2p::client but
 //! routes all communication through the fastn-p2p daemon via Unix socket.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use serde::Serialize;
 
 use crate::error::{ClientError, ConnectionError};
 
+/// Which network paths a call may take to the peer
+///
+/// Relayed traffic is end-to-end encrypted either way; `DirectOnly` is for
+/// deployments that must not send requests through a relay at all, and both
+/// restrictions let tests and diagnostics pin the path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PathPreference {
+    /// Whatever path the endpoint picks
+    #[default]
+    Any,
+    /// Only straight to the peer's address, never through a relay
+    DirectOnly,
+    /// Only through a relay server
+    RelayOnly,
+}
+
+impl PathPreference {
+    pub fn is_any(&self) -> bool {
+        *self == PathPreference::Any
+    }
+}
+
+impl std::str::FromStr for PathPreference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(PathPreference::Any),
+            "direct-only" => Ok(PathPreference::DirectOnly),
+            "relay-only" => Ok(PathPreference::RelayOnly),
+            other => Err(format!("Unknown path preference '{}', expected any, direct-only or relay-only", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for PathPreference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathPreference::Any => write!(f, "any"),
+            PathPreference::DirectOnly => write!(f, "direct-only"),
+            PathPreference::RelayOnly => write!(f, "relay-only"),
+        }
+    }
+}
+
 /// Client request to daemon - shared protocol structure
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub enum DaemonRequest<T> {
     #[serde(rename = "call")]
     Call {
-        from_identity: String,
+        /// Identity to send as; `None` lets the daemon use the default identity
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from_identity: Option<String>,
         to_peer: fastn_id52::PublicKey,
         protocol: String,
         bind_alias: String,
-        request: T,
+        /// serve_all command to address; the daemon then sends a `CommandProtocol`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        /// Extra arguments for `command`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        request: crate::Sensitive<T>,
+        /// Headers added by interceptors, see [`crate::interceptor`]
+        #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+        metadata: std::collections::BTreeMap<String, String>,
+        /// W3C trace context the daemon continues when calling the peer
+        #[serde(skip_serializing_if = "Option::is_none")]
+        traceparent: Option<String>,
+        /// Network paths the daemon may use to reach the peer
+        #[serde(default, skip_serializing_if = "PathPreference::is_any")]
+        path: PathPreference,
+    },
+    /// The same call to every member of a peer group in the sending identity's address book
+    ///
+    /// Answered with one result per member, in the group's order; members
+    /// that could not be reached have `success: false` and the error.
+    #[serde(rename = "group-call")]
+    GroupCall {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from_identity: Option<String>,
+        group: String,
+        protocol: String,
+        bind_alias: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        request: crate::Sensitive<T>,
+        #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+        metadata: std::collections::BTreeMap<String, String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        traceparent: Option<String>,
     },
+    /// Answered with a response line, then relayed as described in [`crate::stream`]
     #[serde(rename = "stream")]
     Stream {
-        from_identity: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from_identity: Option<String>,
+        to_peer: fastn_id52::PublicKey,
+        protocol: String,
+        bind_alias: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        initial_data: crate::Sensitive<T>,
+        #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+        metadata: std::collections::BTreeMap<String, String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        traceparent: Option<String>,
+    },
+    /// Answered with the daemon's circuit breaker state of every peer it called
+    #[serde(rename = "peer-status")]
+    PeerStatus,
+    /// Answered with the latency histograms of the daemon's request handlers
+    #[serde(rename = "protocol-metrics")]
+    ProtocolMetrics,
+    /// Answered with the endpoint serving each online identity, see [`crate::admin::endpoint_status`]
+    #[serde(rename = "endpoint-status")]
+    EndpointStatus,
+    /// Answered with a response line, then one event per line, see [`crate::events`]
+    #[serde(rename = "subscribe-events")]
+    SubscribeEvents,
+    /// Answered with the streams waiting for approval, see [`crate::admin::approvals`]
+    #[serde(rename = "list-approvals")]
+    ListApprovals,
+    /// Let a stream waiting for approval go on, or reject it
+    #[serde(rename = "resolve-approval")]
+    ResolveApproval { id: String, approve: bool },
+    /// Stop accepting and wait for in-flight streams, see [`crate::admin::drain`]
+    #[serde(rename = "drain")]
+    Drain { timeout_ms: u64 },
+    /// Answered with the daemon's build and protocol versions, see [`crate::admin::daemon_info`]
+    #[serde(rename = "version")]
+    Version,
+    /// Answered with the daemon's strikes and bans, see [`crate::admin::reputation`]
+    #[serde(rename = "list-reputation")]
+    ListReputation,
+    /// Forgive a peer and lift its ban, see [`crate::admin::clear_reputation`]
+    #[serde(rename = "clear-reputation")]
+    ClearReputation {
+        peer: fastn_id52::PublicKey,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        identity: Option<fastn_id52::PublicKey>,
+    },
+    /// Drop answers the daemon cached, see [`crate::admin::clear_cache`]
+    #[serde(rename = "clear-cache")]
+    ClearCache {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        peer: Option<fastn_id52::PublicKey>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        protocol: Option<String>,
+    },
+    /// One ping round trip to `to_peer`, answered with its `rtt_ms` and `path`
+    #[serde(rename = "ping")]
+    Ping {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from_identity: Option<String>,
         to_peer: fastn_id52::PublicKey,
+    },
+    /// Queue `message` for delivery until the peer confirms it, see [`crate::outbox`]
+    #[serde(rename = "send")]
+    Send {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from_identity: Option<String>,
+        to_peer: fastn_id52::PublicKey,
+        protocol: String,
+        bind_alias: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        message: crate::Sensitive<T>,
+    },
+    /// Answered with the delivery status of a message queued with `send`
+    #[serde(rename = "delivery-status")]
+    DeliveryStatus {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from_identity: Option<String>,
+        message_id: String,
+    },
+    /// Control requests, see [`crate::admin`]
+    #[serde(rename = "reload-identities")]
+    ReloadIdentities,
+    #[serde(rename = "create-identity")]
+    CreateIdentity {
+        identity: String,
+        /// Key to import; the daemon generates one when absent
+        #[serde(skip_serializing_if = "Option::is_none")]
+        secret_key: Option<fastn_id52::SecretKey>,
+    },
+    #[serde(rename = "list-identities")]
+    ListIdentities,
+    #[serde(rename = "set-identity-state")]
+    SetIdentityState { identity: String, online: bool },
+    #[serde(rename = "add-protocol")]
+    AddProtocol {
+        identity: String,
+        protocol: String,
+        bind_alias: String,
+        config: crate::Sensitive<serde_json::Value>,
+    },
+    #[serde(rename = "remove-protocol")]
+    RemoveProtocol {
+        identity: String,
         protocol: String,
         bind_alias: String,
-        initial_data: T,
     },
 }
 
 /// Make a type-safe request/response call to a remote peer via daemon
 ///
 /// This function connects to the local fastn-p2p daemon via Unix socket,
-/// uses a configured identity to send a request, and waits for a response.
+/// sends the request as the default identity (see [`crate::identity`]) and
+/// waits for a response. Use [`call_as`] to pick the identity per call.
 ///
 /// # Parameters
 ///
-/// * `from_identity` - Identity name to send from (daemon looks up keys)
 /// * `to_peer` - Target peer ID52 string
 /// * `protocol` - Protocol name string
 /// * `bind_alias` - Protocol bind alias (e.g., "default", "backup")
@@ -61,11 +252,11 @@ pub enum DaemonRequest<T> {
 /// #[derive(Serialize, Deserialize)]
 /// struct MailResponse { message_id: String }
 ///
-/// #[derive(Serialize, Deserialize, thiserror::Error)]
+/// #[derive(Debug, Serialize, Deserialize, thiserror::Error)]
 /// #[error("Mail error: {reason}")]
 /// struct MailError { reason: String }
 ///
-/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// # async fn example(peer: fastn_p2p::PublicKey) -> Result<(), Box<dyn std::error::Error>> {
 /// let request = MailRequest { 
 ///     to: "bob@example.com".to_string(),
 ///     subject: "Hello".to_string(), 
@@ -73,8 +264,7 @@ pub enum DaemonRequest<T> {
 /// };
 ///
 /// let result: Result<MailResponse, MailError> = fastn_p2p::call(
-///     "alice",                    // Send from alice identity
-///     "abc123...",               // To this peer  
+///     peer,                      // To this peer
 ///     "Mail",                    // Using Mail protocol
 ///     "default",                 // Default Mail server instance
 ///     request                    // Request data
@@ -83,7 +273,6 @@ pub enum DaemonRequest<T> {
 /// # }
 /// ```
 pub async fn call<REQUEST, RESPONSE, ERROR>(
-    from_identity: &str,
     to_peer: fastn_id52::PublicKey,
     protocol: &str,
     bind_alias: &str,
@@ -94,54 +283,106 @@ where
     RESPONSE: serde::Serialize + for<'de> serde::Deserialize<'de>,
     ERROR: serde::Serialize + for<'de> serde::Deserialize<'de>,
 {
-    let fastn_home = get_fastn_home()?;
-    let socket_path = fastn_home.join("control.sock");
-    
-    if !socket_path.exists() {
-        return Err(ClientError::DaemonConnection(
-            format!("Daemon not running. Socket not found: {}. Start with: fastn-p2p daemon", socket_path.display())
-        ));
+    call_from(None, to_peer, protocol, bind_alias, request).await
+}
+
+/// Make a request/response call as a specific identity
+///
+/// Same as [`call`], but `identity` (an alias configured in the daemon) is
+/// used instead of the default. The daemon rejects unknown or offline
+/// identities with [`ClientError::Identity`].
+///
+/// ```rust,no_run
+/// # async fn example(peer: fastn_p2p_client::PublicKey) -> Result<(), Box<dyn std::error::Error>> {
+/// let result: Result<serde_json::Value, serde_json::Value> = fastn_p2p_client::call_as(
+///     "alice", peer, "Mail", "default", serde_json::json!({"subject": "Hello"})
+/// ).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn call_as<REQUEST, RESPONSE, ERROR>(
+    identity: &str,
+    to_peer: fastn_id52::PublicKey,
+    protocol: &str,
+    bind_alias: &str,
+    request: REQUEST,
+) -> Result<Result<RESPONSE, ERROR>, ClientError>
+where
+    REQUEST: serde::Serialize + for<'de> serde::Deserialize<'de>,
+    RESPONSE: serde::Serialize + for<'de> serde::Deserialize<'de>,
+    ERROR: serde::Serialize + for<'de> serde::Deserialize<'de>,
+{
+    call_from(Some(identity), to_peer, protocol, bind_alias, request).await
+}
+
+async fn call_from<REQUEST, RESPONSE, ERROR>(
+    from_identity: Option<&str>,
+    to_peer: fastn_id52::PublicKey,
+    protocol: &str,
+    bind_alias: &str,
+    request: REQUEST,
+) -> Result<Result<RESPONSE, ERROR>, ClientError>
+where
+    REQUEST: serde::Serialize + for<'de> serde::Deserialize<'de>,
+    RESPONSE: serde::Serialize + for<'de> serde::Deserialize<'de>,
+    ERROR: serde::Serialize + for<'de> serde::Deserialize<'de>,
+{
+    let call = crate::interceptor::OutgoingCall::new(
+        from_identity, to_peer, protocol, bind_alias, serde_json::to_value(request)?, false,
+    );
+    let transport: crate::interceptor::Transport = std::sync::Arc::new(|call| {
+        Box::pin(async move {
+            send_call(&get_fastn_home()?, call).await.map(crate::interceptor::Reply::Response)
+        })
+    });
+
+    match crate::interceptor::run(call, transport).await? {
+        crate::interceptor::Reply::Response(Ok(response)) => Ok(Ok(serde_json::from_value(response)?)),
+        crate::interceptor::Reply::Response(Err(error)) => Ok(Err(serde_json::from_value(error)?)),
+        crate::interceptor::Reply::Session(_) => {
+            Err(ClientError::Protocol("Interceptor answered a call with a session".to_string()))
+        }
     }
-    
-    println!("🔌 Connecting to daemon as identity '{}'", from_identity);
-    println!("📤 Sending {} {} request to {}", protocol, bind_alias, to_peer.id52());
-    
-    // Connect to Unix socket  
-    let mut stream = tokio::net::UnixStream::connect(&socket_path).await
-        .map_err(|e| ClientError::DaemonConnection(format!("Failed to connect to daemon: {}", e)))?;
-
-    // Create typed request using shared protocol structure
+}
+
+/// Send one call to the daemon of `fastn_home`, after the interceptors have run
+///
+/// The peer's answer comes back in the `p2p_response` of a `success: true`
+/// line; its handler's error as a failure of kind `application`, which is the
+/// inner `Err` here.
+async fn send_call(
+    fastn_home: &Path,
+    call: crate::interceptor::OutgoingCall,
+) -> Result<Result<serde_json::Value, serde_json::Value>, ClientError> {
+    tracing::debug!(
+        "Sending {} {} request to {} as identity '{}'",
+        call.protocol(),
+        call.bind_alias(),
+        call.to_peer().id52(),
+        call.from_identity().unwrap_or("(default)"),
+    );
+
     let daemon_request = DaemonRequest::Call {
-        from_identity: from_identity.to_string(),
-        to_peer,
-        protocol: protocol.to_string(),
-        bind_alias: bind_alias.to_string(),
-        request,
+        from_identity: call.from_identity().map(str::to_string),
+        to_peer: *call.to_peer(),
+        protocol: call.protocol().to_string(),
+        bind_alias: call.bind_alias().to_string(),
+        command: None,
+        args: Vec::new(),
+        request: crate::Sensitive(call.data.clone()),
+        metadata: call.metadata.clone(),
+        traceparent: Some(call.traceparent().to_string()),
+        path: call.path_preference,
     };
-    
-    // Send request to daemon
-    use tokio::io::{AsyncWriteExt, AsyncReadExt};
-    let request_json = serde_json::to_string(&daemon_request)?;
-    stream.write_all(request_json.as_bytes()).await
-        .map_err(|e| ClientError::Io { source: e })?;
-    stream.write_all(b"\n").await
-        .map_err(|e| ClientError::Io { source: e })?;
-    
-    println!("📡 Request sent to daemon, waiting for response...");
-    
-    // Read response from daemon
-    let mut response_buffer = Vec::new();
-    stream.read_to_end(&mut response_buffer).await
-        .map_err(|e| ClientError::Io { source: e })?;
-    
-    let response_str = String::from_utf8(response_buffer)
-        .map_err(|e| ClientError::DaemonConnection(format!("Invalid response from daemon: {}", e)))?;
-    
-    println!("📥 Received response from daemon: {}", response_str);
-    
-    // For now, return hardcoded success to test coordination
-    // TODO: Parse actual daemon response and deserialize RESPONSE/ERROR
-    return Err(ClientError::DaemonConnection("New coordination API working!".to_string()));
+
+    match crate::admin::open(fastn_home, &daemon_request).await {
+        Ok((_, _, mut data)) => match data.get_mut("p2p_response") {
+            Some(response) => Ok(Ok(response.take())),
+            None => Err(ClientError::Protocol("Daemon answered a call without a p2p_response".to_string())),
+        },
+        Err(ClientError::Application { error }) => Ok(Err(error)),
+        Err(e) => Err(e),
+    }
 }
 
 /// Establish a streaming P2P session via daemon
@@ -150,11 +391,14 @@ where
 /// streaming session to a remote peer. The API matches the original but
 /// uses daemon coordination.
 ///
+/// The session is opened as the default identity; use [`connect_as`] to pick
+/// another one. Keys never leave the daemon.
+///
 /// # Parameters
 ///
-/// * `our_key` - Your private key (only used for daemon authentication)  
 /// * `target` - The public key of the peer to connect to
-/// * `protocol` - The protocol enum variant for this stream type
+/// * `protocol` - Protocol name string
+/// * `bind_alias` - Protocol bind alias (e.g., "default", "backup")
 /// * `data` - Initial data sent with the connection
 ///
 /// # Returns
@@ -166,12 +410,9 @@ where
 /// ```rust,no_run
 /// use fastn_p2p_client as fastn_p2p;
 ///
-/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
-/// let our_key = fastn_p2p::SecretKey::generate();
-/// let target = fastn_p2p::SecretKey::generate().public_key();
-///
+/// # async fn example(target: fastn_p2p::PublicKey) -> Result<(), Box<dyn std::error::Error>> {
 /// let mut session = fastn_p2p::client::connect(
-///     our_key, target, "FileTransfer", "filename.txt"
+///     target, "FileTransfer", "default", "filename.txt"
 /// ).await?;
 ///
 /// // Stream data (same API as original)
@@ -180,62 +421,170 @@ where
 /// # Ok(())
 /// # }
 /// ```
-pub async fn connect<PROTOCOL, DATA>(
-    _our_key: fastn_id52::SecretKey,
-    _target: fastn_id52::PublicKey,
-    _protocol: PROTOCOL,
-    _data: DATA,
+pub async fn connect<DATA>(
+    target: fastn_id52::PublicKey,
+    protocol: &str,
+    bind_alias: &str,
+    data: DATA,
 ) -> Result<Session, ConnectionError>
 where
-    PROTOCOL: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug,
     DATA: serde::Serialize,
 {
-    todo!("Connect to fastn-p2p daemon via Unix socket, send JSON stream request, return Session proxy");
+    connect_from(None, target, protocol, bind_alias, data).await
+}
+
+/// Establish a streaming P2P session as a specific identity
+pub async fn connect_as<DATA>(
+    identity: &str,
+    target: fastn_id52::PublicKey,
+    protocol: &str,
+    bind_alias: &str,
+    data: DATA,
+) -> Result<Session, ConnectionError>
+where
+    DATA: serde::Serialize,
+{
+    connect_from(Some(identity), target, protocol, bind_alias, data).await
+}
+
+async fn connect_from<DATA>(
+    from_identity: Option<&str>,
+    target: fastn_id52::PublicKey,
+    protocol: &str,
+    bind_alias: &str,
+    data: DATA,
+) -> Result<Session, ConnectionError>
+where
+    DATA: serde::Serialize,
+{
+    let call = crate::interceptor::OutgoingCall::new(
+        from_identity, target, protocol, bind_alias, serde_json::to_value(data)?, true,
+    );
+    let transport: crate::interceptor::Transport = std::sync::Arc::new(|call| {
+        Box::pin(async move {
+            send_connect(&get_fastn_home()?, call).await.map(crate::interceptor::Reply::Session)
+        })
+    });
+
+    match crate::interceptor::run(call, transport).await {
+        Ok(crate::interceptor::Reply::Session(session)) => Ok(session),
+        Ok(crate::interceptor::Reply::Response(_)) => Err(ConnectionError::StreamSetup(
+            "Interceptor answered a connect with a response".to_string(),
+        )),
+        Err(e) => Err(ConnectionError::StreamSetup(e.to_string())),
+    }
+}
+
+/// Open one stream through the daemon of `fastn_home`, after the interceptors have run
+async fn send_connect(fastn_home: &Path, call: crate::interceptor::OutgoingCall) -> Result<Session, ClientError> {
+    tracing::debug!(
+        "Opening {} {} stream to {} as identity '{}'",
+        call.protocol(),
+        call.bind_alias(),
+        call.to_peer().id52(),
+        call.from_identity().unwrap_or("(default)"),
+    );
+
+    let daemon_request = DaemonRequest::Stream {
+        from_identity: call.from_identity().map(str::to_string),
+        to_peer: *call.to_peer(),
+        protocol: call.protocol().to_string(),
+        bind_alias: call.bind_alias().to_string(),
+        command: None,
+        args: Vec::new(),
+        initial_data: crate::Sensitive(call.data.clone()),
+        metadata: call.metadata.clone(),
+        traceparent: Some(call.traceparent().to_string()),
+    };
+    let (reader, writer, _) = crate::admin::open(fastn_home, &daemon_request).await?;
+    Ok(Session { reader, writer: Some(writer), end: None })
 }
 
 /// Client-side streaming session that proxies through daemon
 ///
 /// This provides the same API as the original Session but routes all
-/// communication through the fastn-p2p daemon via Unix socket.
+/// communication through the fastn-p2p daemon via Unix socket, framed as
+/// described in [`crate::stream`].
 pub struct Session {
-    // TODO: Unix socket connection to daemon for streaming
-    _daemon_connection: (),
+    reader: tokio::io::BufReader<tokio::net::unix::OwnedReadHalf>,
+    /// `None` once our side is finished
+    writer: Option<tokio::net::unix::OwnedWriteHalf>,
+    end: Option<crate::stream::StreamEnd>,
 }
 
 impl Session {
     /// Copy data from the peer to a local writer (download pattern)
     ///
-    /// Same API as original but streams through daemon Unix socket.
-    pub async fn copy_to<W>(&mut self, _writer: W) -> std::io::Result<u64>
+    /// Returns once the peer's side ended; see [`Session::end`] for how.
+    pub async fn copy_to<W>(&mut self, mut writer: W) -> std::io::Result<u64>
     where
         W: tokio::io::AsyncWrite + Unpin,
     {
-        todo!("Stream data from daemon Unix socket to writer");
+        use tokio::io::AsyncWriteExt;
+
+        let mut copied = 0;
+        while self.end.is_none() {
+            match crate::stream::read_frame(&mut self.reader).await? {
+                crate::stream::Frame::Data(bytes) => {
+                    writer.write_all(&bytes).await?;
+                    copied += bytes.len() as u64;
+                }
+                crate::stream::Frame::End(end) => self.end = Some(end),
+            }
+        }
+        writer.flush().await?;
+        match self.end.as_ref().and_then(|end| end.error.as_deref()) {
+            Some(error) => Err(std::io::Error::other(error.to_string())),
+            None => Ok(copied),
+        }
     }
 
     /// Copy data from a local reader to the peer (upload pattern)
     ///
-    /// Same API as original but streams through daemon Unix socket.
-    pub async fn copy_from<R>(&mut self, _reader: R) -> std::io::Result<u64>
+    /// Finishes our side of the stream afterwards, so the peer sees the end
+    /// of its input.
+    pub async fn copy_from<R>(&mut self, mut reader: R) -> std::io::Result<u64>
     where
         R: tokio::io::AsyncRead + Unpin,
     {
-        todo!("Stream data from reader to daemon Unix socket");
+        use tokio::io::AsyncWriteExt;
+
+        let Some(mut writer) = self.writer.take() else {
+            return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Session already finished sending"));
+        };
+        let copied = tokio::io::copy(&mut reader, &mut writer).await?;
+        writer.shutdown().await?;
+        Ok(copied)
     }
 
     /// Simultaneously copy data in both directions (bidirectional pattern)
     ///
-    /// Same API as original but coordinates through daemon Unix socket.
+    /// Returns the bytes sent and the bytes received.
     pub async fn copy_both<R, W>(
         &mut self,
-        _reader: R,
-        _writer: W,
+        mut reader: R,
+        writer: W,
     ) -> std::io::Result<(u64, u64)>
     where
         R: tokio::io::AsyncRead + Unpin,
         W: tokio::io::AsyncWrite + Unpin,
     {
-        todo!("Coordinate bidirectional streaming through daemon Unix socket");
+        use tokio::io::AsyncWriteExt;
+
+        let Some(mut to_daemon) = self.writer.take() else {
+            return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Session already finished sending"));
+        };
+        let send = async {
+            let copied = tokio::io::copy(&mut reader, &mut to_daemon).await?;
+            to_daemon.shutdown().await?;
+            Ok::<_, std::io::Error>(copied)
+        };
+        tokio::try_join!(send, self.copy_to(writer))
+    }
+
+    /// How the peer's side ended, once [`Session::copy_to`] read it
+    pub fn end(&self) -> Option<&crate::stream::StreamEnd> {
+        self.end.as_ref()
     }
 }
 
@@ -251,4 +600,75 @@ fn get_fastn_home() -> Result<PathBuf, ClientError> {
         .to_path_buf();
 
     Ok(home_dir.join(".fastn"))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A control socket in `dir` that answers the first request with `response`
+    ///
+    /// Resolves to the request line the client sent.
+    fn fake_daemon(dir: &Path, response: serde_json::Value) -> tokio::task::JoinHandle<serde_json::Value> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::UnixListener::bind(dir.join("control.sock")).unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut line = String::new();
+            tokio::io::BufReader::new(reader).read_line(&mut line).await.unwrap();
+            writer.write_all(format!("{response}\n").as_bytes()).await.unwrap();
+            serde_json::from_str(&line).unwrap()
+        })
+    }
+
+    fn outgoing_call(peer: fastn_id52::PublicKey) -> crate::interceptor::OutgoingCall {
+        crate::interceptor::OutgoingCall::new(
+            Some("alice"), peer, "Mail", "inbox", serde_json::json!({ "subject": "Hello" }), false,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_call_returns_the_peer_answer_and_its_handler_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+
+        let daemon = fake_daemon(dir.path(), serde_json::json!({
+            "success": true,
+            "data": { "p2p_response": { "message_id": "m-1" }, "protocol": "Mail", "bind_alias": "inbox" },
+        }));
+        let answer = send_call(dir.path(), outgoing_call(peer)).await.unwrap();
+        assert_eq!(answer, Ok(serde_json::json!({ "message_id": "m-1" })));
+        let request = daemon.await.unwrap();
+        assert_eq!(request["type"], "call");
+        assert_eq!(request["from_identity"], "alice");
+        assert_eq!(request["bind_alias"], "inbox");
+        assert_eq!(request["request"], serde_json::json!({ "subject": "Hello" }));
+
+        std::fs::remove_file(dir.path().join("control.sock")).unwrap();
+        let daemon = fake_daemon(dir.path(), serde_json::json!({
+            "success": false,
+            "data": { "kind": "application", "error": { "reason": "mailbox full" } },
+        }));
+        let answer = send_call(dir.path(), outgoing_call(peer)).await.unwrap();
+        assert_eq!(answer, Err(serde_json::json!({ "reason": "mailbox full" })));
+        daemon.await.unwrap();
+
+        std::fs::remove_file(dir.path().join("control.sock")).unwrap();
+        let daemon = fake_daemon(dir.path(), serde_json::json!({
+            "success": false,
+            "data": { "kind": "peer-unreachable", "error": "no route" },
+        }));
+        let error = send_call(dir.path(), outgoing_call(peer)).await.unwrap_err();
+        assert!(matches!(error, ClientError::PeerUnreachable(_)), "{error}");
+        daemon.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_call_without_a_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let error = send_call(dir.path(), outgoing_call(peer)).await.unwrap_err();
+        assert!(matches!(error, ClientError::DaemonNotRunning { .. }), "{error}");
+    }
+}
@@ -6,9 +6,58 @@ pub enum ClientError {
     #[error("Daemon connection failed: {0}")]
     DaemonConnection(String),
 
+    #[error("Daemon not running (no control socket at {}), start it with: fastn-p2p daemon", path.display())]
+    DaemonNotRunning { path: std::path::PathBuf },
+
+    /// The daemon refused a control request, see [`crate::admin`]
+    #[error("Daemon rejected the request ({kind}): {error}")]
+    Rejected { kind: String, error: String },
+
     #[error("Protocol error: {0}")]
     Protocol(String),
 
+    #[error("Identity error: {0}")]
+    Identity(String),
+
+    #[error("Peer unreachable: {0}")]
+    PeerUnreachable(String),
+
+    #[error("Peer rejected the handshake: {code}")]
+    HandshakeRejected { code: String },
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    /// The peer's auth hook said no; `code` says why, see fastn_p2p's `AuthDecision`
+    #[error("Denied by the peer ({code}): {error}")]
+    Denied { code: String, error: String },
+
+    /// The peer's auth hook wants us to back off for `retry_after`
+    #[error("Throttled by the peer, retry after {retry_after:?}: {error}")]
+    Throttled { retry_after: std::time::Duration, error: String },
+
+    /// The peer's handler answered with an error; [`crate::call`] returns it as the inner `Err`
+    #[error("The peer's handler returned an error: {error}")]
+    Application { error: serde_json::Value },
+
+    /// The daemon is backing off from a peer that kept failing; retry later
+    #[error("Circuit open: {0}")]
+    CircuitOpen(String),
+
+    /// The daemon has too many calls from this process (or overall) waiting; retry later
+    #[error("Daemon busy: {0}")]
+    Busy(String),
+
+    /// The daemon and this client don't share a control protocol version, see [`crate::version`]
+    #[error(
+        "Daemon {daemon_version} speaks control protocol {daemon_wire}, this client {client_wire}: {}",
+        crate::version::upgrade_hint(*daemon_wire, *client_wire)
+    )]
+    VersionMismatch { daemon_version: String, daemon_wire: u32, client_wire: u32 },
+
+    #[error("Message too large: {0}")]
+    TooLarge(String),
+
     #[error("Serialization error: {source}")]
     Serialization { 
         #[from]
@@ -25,6 +74,53 @@ pub enum ClientError {
     Configuration(String),
 }
 
+impl ClientError {
+    /// The error in the `data` of a daemon's `success: false` response, by its `kind`
+    ///
+    /// Kinds of failed calls are fastn_p2p's `CallError::kind()`; control
+    /// requests add `identity`, `protocol`, `io`, `request` and `version-mismatch`,
+    /// calls refused by a draining daemon `draining`, and calls answered with
+    /// the handler's error `application`.
+    pub fn from_daemon_error(data: &serde_json::Value) -> Self {
+        let error = data
+            .get("error")
+            .and_then(|e| e.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| data.to_string());
+        match data.get("kind").and_then(|k| k.as_str()) {
+            Some("identity") => ClientError::Identity(error),
+            Some("peer-unreachable") => ClientError::PeerUnreachable(error),
+            Some("handshake-rejected") => ClientError::HandshakeRejected {
+                code: data.get("code").and_then(|c| c.as_str()).unwrap_or("unknown").to_string(),
+            },
+            Some("timeout") => ClientError::Timeout(error),
+            Some("denied") => ClientError::Denied {
+                code: data.get("code").and_then(|c| c.as_str()).unwrap_or("unknown").to_string(),
+                error,
+            },
+            Some("throttled") => ClientError::Throttled {
+                retry_after: std::time::Duration::from_millis(
+                    data.get("retry_after_ms").and_then(|ms| ms.as_u64()).unwrap_or_default(),
+                ),
+                error,
+            },
+            Some("application") => ClientError::Application {
+                error: data.get("error").cloned().unwrap_or_default(),
+            },
+            Some("circuit-open") => ClientError::CircuitOpen(error),
+            Some("busy") => ClientError::Busy(error),
+            Some("too-large") => ClientError::TooLarge(error),
+            Some("protocol-not-accepted") | Some("protocol") => ClientError::Protocol(error),
+            Some("version-mismatch") => ClientError::VersionMismatch {
+                daemon_version: data.get("daemon_version").and_then(|v| v.as_str()).unwrap_or("unknown").to_string(),
+                daemon_wire: data.get("wire_version").and_then(|v| v.as_u64()).unwrap_or_default() as u32,
+                client_wire: crate::version::WIRE_VERSION,
+            },
+            kind => ClientError::Rejected { kind: kind.unwrap_or("unknown").to_string(), error },
+        }
+    }
+}
+
 /// Connection errors for streaming operations
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectionError {
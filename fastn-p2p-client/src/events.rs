@@ -0,0 +1,170 @@
+//! Live daemon events over the control socket
+//!
+//! [`subscribe`] sends `subscribe-events` and the daemon answers with one
+//! JSON line per event until the subscriber disconnects: peers connecting to
+//! and leaving its identities, requests they make, identities and bindings
+//! changing, bindings failing, and notifications protocols raise.
+//! Dashboards follow the daemon this way instead of polling `status`.
+//!
+//! ```rust,no_run
+//! # async fn example(fastn_home: &std::path::Path) -> Result<(), fastn_p2p_client::ClientError> {
+//! let mut events = fastn_p2p_client::events::subscribe(fastn_home).await?;
+//! while let Some(event) = events.next().await? {
+//!     println!("{:?}", event.kind);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Events are not stored: a subscriber sees what happens after it
+//! subscribed, and one too slow to keep up gets [`EventKind::Lagged`]
+//! instead of the events it missed.
+
+use std::path::Path;
+
+use crate::client::DaemonRequest;
+use crate::error::ClientError;
+
+/// Something that happened in the daemon
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DaemonEvent {
+    /// When it happened, in milliseconds since the Unix epoch
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl DaemonEvent {
+    /// `kind`, happening now
+    pub fn now(kind: EventKind) -> Self {
+        let at_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        Self { at_ms, kind }
+    }
+}
+
+/// What happened; `identity` is the alias or ID52 of the daemon's identity involved
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum EventKind {
+    /// A peer completed the handshake with one of the identities
+    PeerConnected {
+        identity: String,
+        peer: String,
+        protocols: Vec<serde_json::Value>,
+        client_name: String,
+    },
+    /// A connected peer is gone
+    PeerDisconnected {
+        identity: String,
+        peer: String,
+        connected_ms: u64,
+        streams: u64,
+        close_reason: Option<String>,
+    },
+    /// A peer's request was handled
+    IncomingRequest {
+        identity: String,
+        peer: String,
+        protocol: String,
+        command: Option<String>,
+        duration_ms: u64,
+        ok: bool,
+    },
+    IdentityCreated {
+        identity: String,
+        peer: String,
+    },
+    IdentityStateChanged {
+        identity: String,
+        online: bool,
+    },
+    ProtocolAdded {
+        identity: String,
+        protocol: String,
+        bind_alias: String,
+    },
+    ProtocolRemoved {
+        identity: String,
+        protocol: String,
+        bind_alias: String,
+    },
+    /// A binding failed to start, reload or stop
+    ProtocolError {
+        identity: Option<String>,
+        protocol: Option<String>,
+        bind_alias: Option<String>,
+        error: String,
+    },
+    /// A protocol asks for the user's attention, e.g. new mail arrived
+    ///
+    /// The daemon acts on it as the binding's `notify.json` says.
+    Notification {
+        identity: String,
+        protocol: String,
+        bind_alias: String,
+        /// What happened, e.g. `new-mail`, `file-offer` or `pairing-request`
+        name: String,
+        peer: Option<String>,
+        title: String,
+        body: String,
+    },
+    /// A stream handler waits for the user, see [`crate::admin::approvals`]
+    ApprovalRequested {
+        id: String,
+        identity: String,
+        peer: String,
+        protocol: String,
+        summary: String,
+        expires_ms: u64,
+    },
+    /// A waiting stream was accepted, denied, expired or the peer left
+    ApprovalResolved {
+        id: String,
+        approved: bool,
+    },
+    /// A peer reached the strike threshold and is refused for a while
+    PeerBanned {
+        identity: String,
+        peer: String,
+        /// The offense that banned it, e.g. `malformed-frame`
+        offense: String,
+        ban_secs: u64,
+    },
+    /// This subscriber fell behind and `missed` events were dropped
+    Lagged { missed: u64 },
+}
+
+/// Events from the daemon, see [`subscribe`]
+///
+/// Dropping it unsubscribes.
+pub struct EventStream {
+    reader: tokio::io::BufReader<tokio::net::unix::OwnedReadHalf>,
+    // The daemon stops sending once this half closes
+    _writer: tokio::net::unix::OwnedWriteHalf,
+}
+
+impl EventStream {
+    /// The next event; `None` once the daemon stops
+    pub async fn next(&mut self) -> Result<Option<DaemonEvent>, ClientError> {
+        use tokio::io::AsyncBufReadExt;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line).await? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                return Ok(Some(serde_json::from_str(line.trim())?));
+            }
+        }
+    }
+}
+
+/// Follow the events of the daemon of `fastn_home`
+pub async fn subscribe(fastn_home: &Path) -> Result<EventStream, ClientError> {
+    let (reader, writer, _) = crate::admin::open(fastn_home, &DaemonRequest::SubscribeEvents).await?;
+    Ok(EventStream { reader, _writer: writer })
+}
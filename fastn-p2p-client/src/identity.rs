@@ -0,0 +1,41 @@
+//! Default identity setting stored in FASTN_HOME
+//!
+//! Calls that do not name an identity are sent as the default identity. The
+//! setting is a plain file holding the identity alias, shared by the CLI,
+//! the daemon and this library.
+
+use std::path::Path;
+
+use crate::error::ClientError;
+
+/// File in FASTN_HOME holding the default identity alias
+pub const DEFAULT_IDENTITY_FILE: &str = "default-identity";
+
+/// The configured default identity, if any
+pub async fn default_identity(fastn_home: &Path) -> Result<Option<String>, ClientError> {
+    match tokio::fs::read_to_string(fastn_home.join(DEFAULT_IDENTITY_FILE)).await {
+        Ok(alias) => {
+            let alias = alias.trim();
+            Ok((!alias.is_empty()).then(|| alias.to_string()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(ClientError::Io { source }),
+    }
+}
+
+/// Make `alias` the default identity
+pub async fn set_default_identity(fastn_home: &Path, alias: &str) -> Result<(), ClientError> {
+    if alias.trim().is_empty() {
+        return Err(ClientError::Configuration("Identity alias cannot be empty".to_string()));
+    }
+    tokio::fs::write(fastn_home.join(DEFAULT_IDENTITY_FILE), format!("{}\n", alias.trim())).await?;
+    Ok(())
+}
+
+/// Remove the default identity setting
+pub async fn clear_default_identity(fastn_home: &Path) -> Result<(), ClientError> {
+    match tokio::fs::remove_file(fastn_home.join(DEFAULT_IDENTITY_FILE)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(ClientError::Io { source: e }),
+        _ => Ok(()),
+    }
+}
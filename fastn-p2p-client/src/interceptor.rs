@@ -0,0 +1,174 @@
+//! Interceptors around every daemon `call()` and `connect()`
+//!
+//! Same shape as the interceptors on `fastn_p2p::client::Client`, but
+//! registered once per process since calls here are free functions. An
+//! interceptor can add metadata headers (forwarded by the daemon), time the
+//! call, retry it or short-circuit it:
+//!
+//! ```rust,no_run
+//! fastn_p2p_client::interceptor::add_interceptor(|mut call, next| async move {
+//!     call.metadata.insert("app".to_string(), "mail-cli".to_string());
+//!     let protocol = call.protocol().to_string();
+//!     let started = std::time::Instant::now();
+//!     let reply = next.run(call).await;
+//!     eprintln!("{} took {:?}", protocol, started.elapsed());
+//!     reply
+//! });
+//! ```
+//!
+//! Interceptors run in the order they were added, the first one outermost.
+//! [`CallNext`] can be run more than once, which is how retries are written.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::error::ClientError;
+
+/// Interceptors registered with [`add_interceptor`]
+static INTERCEPTORS: std::sync::LazyLock<std::sync::Mutex<std::sync::Arc<Vec<Interceptor>>>> =
+    std::sync::LazyLock::new(|| std::sync::Mutex::new(std::sync::Arc::new(Vec::new())));
+
+/// What the rest of the chain produced
+pub enum Reply {
+    /// Application result of a `call()`: serialized RESPONSE or ERROR
+    Response(Result<serde_json::Value, serde_json::Value>),
+    /// Session opened by `connect()`
+    Session(crate::client::Session),
+}
+
+pub type InterceptorResult = Result<Reply, ClientError>;
+
+type BoxFuture = Pin<Box<dyn Future<Output = InterceptorResult> + Send>>;
+
+type Interceptor = std::sync::Arc<dyn Fn(OutgoingCall, CallNext) -> BoxFuture + Send + Sync>;
+
+/// Talks to the daemon once all interceptors have run
+pub(crate) type Transport = std::sync::Arc<dyn Fn(OutgoingCall) -> BoxFuture + Send + Sync>;
+
+/// Run `interceptor` around every `call()` and `connect()` made by this process
+pub fn add_interceptor<F, Fut>(interceptor: F)
+where
+    F: Fn(OutgoingCall, CallNext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = InterceptorResult> + Send + 'static,
+{
+    let mut interceptors = INTERCEPTORS
+        .lock()
+        .expect("Failed to acquire lock on INTERCEPTORS");
+    let mut updated = (**interceptors).clone();
+    updated.push(std::sync::Arc::new(move |call, next| Box::pin(interceptor(call, next))));
+    *interceptors = std::sync::Arc::new(updated);
+}
+
+/// A `call()` or `connect()` as seen by interceptors
+#[derive(Debug, Clone)]
+pub struct OutgoingCall {
+    from_identity: Option<String>,
+    to_peer: fastn_id52::PublicKey,
+    protocol: String,
+    bind_alias: String,
+    /// Request (or initial stream data), may be rewritten
+    pub data: serde_json::Value,
+    /// Headers the daemon delivers to the server alongside the request
+    pub metadata: std::collections::BTreeMap<String, String>,
+    is_stream: bool,
+    traceparent: String,
+    /// Network paths the daemon may use for a `call()`, see
+    /// [`crate::client::PathPreference`]; the daemon's answer says which one it took
+    pub path_preference: crate::client::PathPreference,
+}
+
+impl OutgoingCall {
+    pub(crate) fn new(
+        from_identity: Option<&str>,
+        to_peer: fastn_id52::PublicKey,
+        protocol: &str,
+        bind_alias: &str,
+        data: serde_json::Value,
+        is_stream: bool,
+    ) -> Self {
+        Self {
+            from_identity: from_identity.map(str::to_string),
+            to_peer,
+            protocol: protocol.to_string(),
+            bind_alias: bind_alias.to_string(),
+            data,
+            metadata: std::collections::BTreeMap::new(),
+            is_stream,
+            traceparent: new_traceparent(),
+            path_preference: crate::client::PathPreference::Any,
+        }
+    }
+
+    /// Identity alias the call is sent as; `None` is the daemon's default
+    pub fn from_identity(&self) -> Option<&str> {
+        self.from_identity.as_deref()
+    }
+
+    pub fn to_peer(&self) -> &fastn_id52::PublicKey {
+        &self.to_peer
+    }
+
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    pub fn bind_alias(&self) -> &str {
+        &self.bind_alias
+    }
+
+    /// Whether this is a `connect()` rather than a `call()`
+    pub fn is_stream(&self) -> bool {
+        self.is_stream
+    }
+
+    /// W3C `traceparent` of this call; the daemon and the peer continue its trace
+    pub fn traceparent(&self) -> &str {
+        &self.traceparent
+    }
+}
+
+/// A new root `traceparent`: random 128-bit trace id and 64-bit span id
+fn new_traceparent() -> String {
+    let trace_id = uuid::Uuid::new_v4().simple().to_string();
+    let span_id = uuid::Uuid::new_v4().simple().to_string();
+    format!("00-{}-{}-01", trace_id, &span_id[..16])
+}
+
+/// The rest of the chain: the remaining interceptors, then the daemon
+#[derive(Clone)]
+pub struct CallNext {
+    interceptors: std::sync::Arc<Vec<Interceptor>>,
+    index: usize,
+    transport: Transport,
+}
+
+impl CallNext {
+    pub async fn run(&self, call: OutgoingCall) -> InterceptorResult {
+        match self.interceptors.get(self.index) {
+            Some(interceptor) => {
+                let next = CallNext {
+                    interceptors: self.interceptors.clone(),
+                    index: self.index + 1,
+                    transport: self.transport.clone(),
+                };
+                interceptor(call, next).await
+            }
+            None => (self.transport)(call).await,
+        }
+    }
+}
+
+/// Run `call` through the registered interceptors and then `transport`
+pub(crate) async fn run(call: OutgoingCall, transport: Transport) -> InterceptorResult {
+    let interceptors = INTERCEPTORS
+        .lock()
+        .expect("Failed to acquire lock on INTERCEPTORS")
+        .clone();
+    CallNext {
+        interceptors,
+        index: 0,
+        transport,
+    }
+    .run(call)
+    .await
+}
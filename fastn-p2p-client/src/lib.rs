@@ -6,7 +6,7 @@
 //!
 //! ## Architecture
 //!
-//! ```
+//! ```text
 //! ┌─────────────────┐    Unix Socket     ┌──────────────────┐
 //! │ fastn-p2p-client│◄──────────────────►│ fastn-p2p daemon │
 //! │  (lightweight)  │                    │    (full stack)  │
@@ -21,19 +21,35 @@
 //! // Same API as examples, but daemon-powered
 //! use fastn_p2p_client as fastn_p2p;
 //!
-//! let result = fastn_p2p::client::call(
-//!     private_key, target_peer, protocol, request
-//! ).await?;
+//! # async fn example(target_peer: fastn_p2p::PublicKey, request: serde_json::Value) -> Result<(), fastn_p2p::ClientError> {
+//! // Sent as the default identity (`fastn-p2p default-identity <alias>`)
+//! let result: Result<serde_json::Value, serde_json::Value> =
+//!     fastn_p2p::client::call(target_peer, "Mail", "default", request.clone()).await?;
+//!
+//! // Or pick the identity per call
+//! let result: Result<serde_json::Value, serde_json::Value> =
+//!     fastn_p2p::client::call_as("alice", target_peer, "Mail", "default", request).await?;
+//! # Ok(())
+//! # }
 //! ```
 
+pub mod admin;
 pub mod client;
 pub mod error;
+pub mod events;
+pub mod identity;
+pub mod interceptor;
+pub mod outbox;
+pub mod sensitive;
+pub mod stream;
+pub mod version;
 
 // Re-export only PublicKey for peer identification (no SecretKey - daemon manages all keys)
 pub use fastn_id52::PublicKey;
 
 // Re-export client functions and protocol types for convenience  
-pub use client::{call, connect, Session, DaemonRequest};
+pub use client::{call, call_as, connect, connect_as, Session, DaemonRequest};
+pub use sensitive::Sensitive;
 
 /// Error type for client operations
 pub use error::{ClientError, ConnectionError};
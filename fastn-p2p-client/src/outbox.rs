@@ -0,0 +1,91 @@
+//! Store-and-forward messages through the daemon
+//!
+//! [`send`] queues a message in the sending identity's outbox and returns
+//! right away with its message ID. The daemon keeps delivering it until the
+//! peer answers, even across restarts, and the peer applies it once however
+//! often it arrives. [`delivery_status`] tells whether it got there:
+//!
+//! ```rust,no_run
+//! # async fn example(fastn_home: &std::path::Path, peer: fastn_id52::PublicKey) -> Result<(), fastn_p2p_client::ClientError> {
+//! use fastn_p2p_client::outbox;
+//!
+//! let queued = outbox::send(fastn_home, None, peer, "mail.fastn.com", "default", serde_json::json!({"to": "bob"})).await?;
+//! match outbox::delivery_status(fastn_home, None, &queued.message_id).await?.status {
+//!     outbox::DeliveryStatus::Delivered { response, .. } => println!("Delivered: {}", response),
+//!     outbox::DeliveryStatus::Pending { attempts, .. } => println!("Pending after {} attempts", attempts),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::Path;
+
+use crate::client::DaemonRequest;
+use crate::error::ClientError;
+
+/// Result of [`send`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Queued {
+    pub message_id: String,
+    /// Identity the message is sent as
+    pub from_identity: String,
+    pub peer: String,
+}
+
+/// Where a queued message is
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum DeliveryStatus {
+    /// Not delivered yet; the daemon keeps retrying
+    Pending { attempts: u32, last_error: Option<String> },
+    /// The peer applied it and answered `response`; RFC 3339 time of the receipt
+    Delivered { delivered_at: String, response: serde_json::Value },
+}
+
+/// Result of [`delivery_status`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Delivery {
+    pub message_id: String,
+    pub peer: String,
+    /// Protocol as sent to the peer
+    pub protocol: String,
+    pub created_at: String,
+    #[serde(flatten)]
+    pub status: DeliveryStatus,
+}
+
+/// Queue `message` for `peer`'s `protocol` binding `bind_alias`
+///
+/// Sent as `from_identity`, or the default identity when `None`.
+pub async fn send(
+    fastn_home: &Path,
+    from_identity: Option<&str>,
+    peer: fastn_id52::PublicKey,
+    protocol: &str,
+    bind_alias: &str,
+    message: serde_json::Value,
+) -> Result<Queued, ClientError> {
+    let request = DaemonRequest::Send {
+        from_identity: from_identity.map(str::to_string),
+        to_peer: peer,
+        protocol: protocol.to_string(),
+        bind_alias: bind_alias.to_string(),
+        command: None,
+        args: Vec::new(),
+        message: crate::Sensitive(message),
+    };
+    crate::admin::request(fastn_home, &request).await
+}
+
+/// Delivery status of message `message_id` sent by `from_identity` (or the default identity)
+pub async fn delivery_status(
+    fastn_home: &Path,
+    from_identity: Option<&str>,
+    message_id: &str,
+) -> Result<Delivery, ClientError> {
+    let request = DaemonRequest::DeliveryStatus {
+        from_identity: from_identity.map(str::to_string),
+        message_id: message_id.to_string(),
+    };
+    crate::admin::request(fastn_home, &request).await
+}
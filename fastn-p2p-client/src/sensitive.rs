@@ -0,0 +1,43 @@
+//! Values that must not end up in logs
+//!
+//! Request bodies, stream data and binding configs travel in
+//! [`crate::DaemonRequest`] and in the daemon's own request types, which
+//! derive `Debug`. Wrapping those fields in [`Sensitive`] keeps them out of
+//! any `{:?}` of the request while serializing exactly as before.
+
+/// A value that serializes as itself but shows as `[redacted]` in `Debug`
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Sensitive<T>(pub T);
+
+impl<T> Sensitive<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl<T> std::ops::Deref for Sensitive<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for Sensitive<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> From<T> for Sensitive<T> {
+    fn from(value: T) -> Self {
+        Sensitive(value)
+    }
+}
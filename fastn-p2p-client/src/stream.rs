@@ -0,0 +1,113 @@
+//! Framing of streams the daemon relays to a peer
+//!
+//! After a [`crate::DaemonRequest::Stream`] the daemon answers with one JSON
+//! response line, then the socket carries the stream itself:
+//!
+//! - client to daemon: raw bytes, forwarded to the peer as they are. Shutting
+//!   down the write half finishes the peer's receive stream (half-close).
+//! - daemon to client: [`Frame`]s, so the peer's output can be followed by how
+//!   the stream ended. Each frame is a tag byte, a big-endian `u32` length and
+//!   the payload; the [`StreamEnd`] frame is always last.
+//!
+//! A peer reports a non-zero exit by resetting its send stream with that code
+//! instead of finishing it; a clean finish is exit code 0.
+
+/// Tag of a frame carrying peer output
+const DATA: u8 = 0;
+/// Tag of the final frame, carrying a JSON [`StreamEnd`]
+const END: u8 = 1;
+
+/// Bytes the daemon puts in one data frame at most
+pub const MAX_CHUNK: usize = 64 * 1024;
+
+/// How the peer's side of a relayed stream ended
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StreamEnd {
+    /// 0 when the peer finished its stream, the reset code otherwise
+    pub exit_code: i32,
+    /// Set when the stream broke instead of ending, e.g. the connection was lost
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl StreamEnd {
+    pub fn finished() -> Self {
+        Self { exit_code: 0, error: None }
+    }
+
+    /// The peer reset its stream with `code`; codes outside `1..=255` become 1
+    pub fn reset(code: u64) -> Self {
+        let exit_code = if (1..=255).contains(&code) { code as i32 } else { 1 };
+        Self { exit_code, error: None }
+    }
+
+    pub fn failed(error: impl std::fmt::Display) -> Self {
+        Self { exit_code: 1, error: Some(error.to_string()) }
+    }
+}
+
+/// One frame sent from the daemon to the client
+#[derive(Debug, PartialEq, Eq)]
+pub enum Frame {
+    Data(Vec<u8>),
+    End(StreamEnd),
+}
+
+/// Write peer output as data frames
+pub async fn write_data<W>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    for chunk in bytes.chunks(MAX_CHUNK) {
+        write_frame(writer, DATA, chunk).await?;
+    }
+    Ok(())
+}
+
+/// Write the final frame and flush
+pub async fn write_end<W>(writer: &mut W, end: &StreamEnd) -> std::io::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    write_frame(writer, END, &serde_json::to_vec(end)?).await?;
+    tokio::io::AsyncWriteExt::flush(writer).await
+}
+
+async fn write_frame<W>(writer: &mut W, tag: u8, payload: &[u8]) -> std::io::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncWriteExt;
+
+    writer.write_u8(tag).await?;
+    writer.write_u32(payload.len() as u32).await?;
+    writer.write_all(payload).await
+}
+
+/// Read the next frame; the daemon going away before [`Frame::End`] is an `UnexpectedEof` error
+pub async fn read_frame<R>(reader: &mut R) -> std::io::Result<Frame>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let tag = reader.read_u8().await?;
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_CHUNK {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Stream frame of {len} bytes exceeds {MAX_CHUNK}"),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+
+    match tag {
+        DATA => Ok(Frame::Data(payload)),
+        END => Ok(Frame::End(serde_json::from_slice(&payload)?)),
+        other => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Unknown stream frame tag {other}"),
+        )),
+    }
+}
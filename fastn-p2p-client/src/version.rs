@@ -0,0 +1,42 @@
+//! Versions of the control socket protocol
+//!
+//! Every request line carries the client's [`WIRE_VERSION`] as
+//! `wire_version`. A daemon that can't serve it answers with a
+//! `version-mismatch` error, returned as
+//! [`crate::ClientError::VersionMismatch`] saying which side to upgrade.
+//! Lines without the field come from clients before negotiation, which spoke
+//! version 1.
+//!
+//! [`WIRE_VERSION`] goes up when a request or response changes in a way the
+//! other side can't read; [`MIN_WIRE_VERSION`] when daemons stop serving
+//! clients that old.
+
+/// Control protocol this build speaks
+pub const WIRE_VERSION: u32 = 1;
+
+/// Oldest control protocol this build still serves or understands
+pub const MIN_WIRE_VERSION: u32 = 1;
+
+/// Whether this build can talk to a peer speaking `wire_version`
+pub fn is_supported(wire_version: u32) -> bool {
+    (MIN_WIRE_VERSION..=WIRE_VERSION).contains(&wire_version)
+}
+
+/// `request` as one control socket line, without the newline, tagged with [`WIRE_VERSION`]
+pub fn encode_request<T: serde::Serialize>(request: &T) -> Result<String, serde_json::Error> {
+    let mut line = serde_json::to_value(request)?;
+    if let serde_json::Value::Object(fields) = &mut line {
+        fields.insert("wire_version".to_string(), WIRE_VERSION.into());
+    }
+    Ok(line.to_string())
+}
+
+/// What to upgrade so a daemon speaking `daemon_wire` and a client speaking `client_wire` get along
+pub fn upgrade_hint(daemon_wire: u32, client_wire: u32) -> &'static str {
+    if daemon_wire < client_wire {
+        "upgrade fastn-p2p and restart the daemon"
+    } else {
+        "upgrade this program's fastn-p2p-client to the daemon's release"
+    }
+}
+
@@ -0,0 +1,364 @@
+//! Large binary attachments alongside a request/response call
+//!
+//! Blobs put into the request JSON would be base64'd and held in memory on
+//! both sides. Attachments go next to the request instead:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::{attachment::Attachment, RequestContext};
+//! # #[derive(serde::Serialize)]
+//! # enum Mail { Send }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # struct Sent;
+//! # #[derive(Debug, serde::Serialize, serde::Deserialize, thiserror::Error)]
+//! # #[error("mail failed")]
+//! # struct MailError;
+//! # impl From<std::io::Error> for MailError { fn from(_: std::io::Error) -> Self { MailError } }
+//! # impl From<fastn_p2p::attachment::AttachmentError> for MailError { fn from(_: fastn_p2p::attachment::AttachmentError) -> Self { MailError } }
+//! # fn spool() -> std::path::PathBuf { std::path::PathBuf::from("spool") }
+//! # async fn run(client: fastn_p2p::client::Client, target: fastn_p2p::PublicKey, mail: String) -> Result<(), Box<dyn std::error::Error>> {
+//! let pdf = Attachment::from_file("report.pdf").await?;
+//! let sent: Result<Sent, MailError> = client.call_with_attachments(target, Mail::Send, mail, vec![pdf]).await?;
+//! # Ok(())
+//! # }
+//!
+//! async fn send(mail: String, ctx: RequestContext) -> Result<Sent, MailError> {
+//! #   let spool = spool();
+//!     for attachment in ctx.attachments() {
+//!         let mut reader = attachment.open().await?;
+//!         let mut file = tokio::fs::File::create(spool.join(attachment.name())).await?;
+//!         tokio::io::copy(&mut reader, &mut file).await?;
+//!     }
+//!     Ok(Sent)
+//! }
+//! ```
+//!
+//! The request lists each attachment's id, name and size
+//! (`WrapperRequest::attachments`). Its bytes go on a unidirectional stream
+//! of its own, starting with an [`AttachmentHeader`] line, while the client
+//! waits for the response; whatever the handler hasn't read when it answers
+//! is dropped. Attachments are not covered by request signatures, and only
+//! reach servers the client is connected to directly, not relayed ones.
+
+/// How long [`IncomingAttachment::open`] waits for the attachment's stream
+pub const OPEN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Streams a connection may have waiting for a handler to open them
+const MAX_UNCLAIMED: usize = 64;
+
+/// An attachment as listed in the request
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AttachmentInfo {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+}
+
+/// First line of an attachment's stream, naming the attachment
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct AttachmentHeader {
+    pub attachment: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttachmentError {
+    #[error("Attachment '{name}' did not arrive within {timeout:?}")]
+    Missing { name: String, timeout: std::time::Duration },
+
+    #[error("Attachment '{name}' was opened already")]
+    AlreadyOpened { name: String },
+
+    #[error("Attachment '{name}' is {actual} bytes, not the {expected} announced")]
+    SizeChanged { name: String, expected: u64, actual: u64 },
+
+    #[error("Connection closed before attachment '{name}' arrived")]
+    Closed { name: String },
+
+    #[error("Failed to send attachment: {source}")]
+    Io { source: std::io::Error },
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    Bytes(std::sync::Arc<Vec<u8>>),
+    File(std::path::PathBuf),
+}
+
+/// A blob to send with [`crate::client::Client::call_with_attachments`]
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    info: AttachmentInfo,
+    source: Source,
+}
+
+impl Attachment {
+    pub fn from_bytes(name: impl Into<String>, bytes: impl Into<Vec<u8>>) -> Self {
+        let bytes = bytes.into();
+        Self {
+            info: AttachmentInfo { id: new_id(), name: name.into(), size: bytes.len() as u64 },
+            source: Source::Bytes(std::sync::Arc::new(bytes)),
+        }
+    }
+
+    /// The file at `path`, read when the call is made; named after the file
+    pub async fn from_file(path: impl Into<std::path::PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let size = tokio::fs::metadata(&path).await?.len();
+        let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        Ok(Self {
+            info: AttachmentInfo { id: new_id(), name, size },
+            source: Source::File(path),
+        })
+    }
+
+    /// Send it under another name
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.info.name = name.into();
+        self
+    }
+
+    pub fn info(&self) -> &AttachmentInfo {
+        &self.info
+    }
+
+    /// Send the attachment on a new stream of `conn`
+    async fn send(&self, conn: &iroh::endpoint::Connection) -> Result<(), AttachmentError> {
+        use tokio::io::AsyncReadExt;
+
+        fn io_error(e: impl std::error::Error + Send + Sync + 'static) -> AttachmentError {
+            AttachmentError::Io { source: std::io::Error::other(e) }
+        }
+
+        let mut send = conn.open_uni().await.map_err(io_error)?;
+        let mut header = serde_json::to_string(&AttachmentHeader { attachment: self.info.id.clone() })
+            .map_err(|e| AttachmentError::Io { source: e.into() })?;
+        header.push('\n');
+        send.write_all(header.as_bytes()).await.map_err(io_error)?;
+        match &self.source {
+            Source::Bytes(bytes) => send.write_all(bytes).await.map_err(io_error)?,
+            Source::File(path) => {
+                let file = tokio::fs::File::open(path).await.map_err(|source| AttachmentError::Io { source })?;
+                let sent = tokio::io::copy(&mut file.take(self.info.size), &mut send)
+                    .await
+                    .map_err(|source| AttachmentError::Io { source })?;
+                if sent != self.info.size {
+                    return Err(AttachmentError::SizeChanged {
+                        name: self.info.name.clone(),
+                        expected: self.info.size,
+                        actual: sent,
+                    });
+                }
+            }
+        }
+        send.finish().map_err(io_error)
+    }
+}
+
+fn new_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Run `work`, sending `attachments` on `conn` until it is done
+///
+/// Failed uploads are only logged: the handler sees the attachment missing
+/// or cut short and answers accordingly.
+pub(crate) async fn upload_while<T>(
+    conn: &iroh::endpoint::Connection,
+    attachments: &[Attachment],
+    work: impl std::future::Future<Output = T>,
+) -> T {
+    let uploads = async {
+        let sent = futures_util::future::join_all(attachments.iter().map(|attachment| attachment.send(conn))).await;
+        for (attachment, result) in attachments.iter().zip(sent) {
+            if let Err(e) = result {
+                tracing::debug!("Attachment '{}' not sent: {}", attachment.info.name, e);
+            }
+        }
+        std::future::pending::<T>().await
+    };
+    tokio::select! {
+        result = work => result,
+        never = uploads => never,
+    }
+}
+
+/// An attachment of the request being handled, see [`crate::server::RequestContext::attachments`]
+#[derive(Debug, Clone)]
+pub struct IncomingAttachment {
+    info: AttachmentInfo,
+    inbox: std::sync::Arc<Inbox>,
+    opened: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl IncomingAttachment {
+    /// The attachments `infos` lists, arriving on `conn`
+    pub(crate) fn for_request(conn: &iroh::endpoint::Connection, infos: Vec<AttachmentInfo>) -> Vec<Self> {
+        let inbox = Inbox::for_connection(conn);
+        infos
+            .into_iter()
+            .map(|info| Self { info, inbox: inbox.clone(), opened: Default::default() })
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    /// Bytes the client announced
+    pub fn size(&self) -> u64 {
+        self.info.size
+    }
+
+    /// Wait for the attachment's bytes; they can be read once
+    ///
+    /// The reader ends after the announced size, or earlier if the client
+    /// sent less.
+    pub async fn open(&self) -> Result<tokio::io::Take<iroh::endpoint::RecvStream>, AttachmentError> {
+        use tokio::io::AsyncReadExt;
+
+        if self.opened.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            return Err(AttachmentError::AlreadyOpened { name: self.info.name.clone() });
+        }
+        let stream = self.inbox.take(&self.info).await?;
+        Ok(stream.take(self.info.size))
+    }
+}
+
+/// Attachment streams of one connection, by attachment id
+#[derive(Debug)]
+pub(crate) struct Inbox {
+    slots: std::sync::Mutex<std::collections::HashMap<String, Slot>>,
+    /// Stops accepting streams once the connection's last user is gone
+    stop: tokio_util::sync::CancellationToken,
+}
+
+#[derive(Debug)]
+enum Slot {
+    /// The stream came before the handler asked for it
+    Arrived(iroh::endpoint::RecvStream),
+    /// The handler asked before the stream came
+    Waiting(tokio::sync::oneshot::Sender<iroh::endpoint::RecvStream>),
+}
+
+/// Inboxes by [`iroh::endpoint::Connection::stable_id`], like the datagram routers
+static INBOXES: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<usize, std::sync::Weak<Inbox>>>> =
+    std::sync::LazyLock::new(Default::default);
+
+impl Inbox {
+    /// The inbox of `conn`, accepting its unidirectional streams from first use
+    pub(crate) fn for_connection(conn: &iroh::endpoint::Connection) -> std::sync::Arc<Self> {
+        let mut inboxes = INBOXES.lock().expect("attachment inboxes lock poisoned");
+        if let Some(inbox) = inboxes.get(&conn.stable_id()).and_then(std::sync::Weak::upgrade) {
+            return inbox;
+        }
+        inboxes.retain(|_, inbox| inbox.strong_count() > 0);
+
+        let inbox = std::sync::Arc::new(Self {
+            slots: Default::default(),
+            stop: tokio_util::sync::CancellationToken::new(),
+        });
+        inboxes.insert(conn.stable_id(), std::sync::Arc::downgrade(&inbox));
+        crate::spawn(accept(std::sync::Arc::downgrade(&inbox), conn.clone(), inbox.stop.clone()));
+        inbox
+    }
+
+    fn deliver(&self, id: String, stream: iroh::endpoint::RecvStream) {
+        let mut slots = self.slots.lock().expect("attachment slots lock poisoned");
+        let unclaimed = slots.values().filter(|slot| matches!(slot, Slot::Arrived(_))).count();
+        match slots.remove(&id) {
+            Some(Slot::Waiting(waiter)) => {
+                // The handler may have given up in between
+                let _ = waiter.send(stream);
+            }
+            Some(arrived) => {
+                slots.insert(id, arrived);
+                tracing::debug!("Dropped a second stream for one attachment");
+            }
+            None if unclaimed >= MAX_UNCLAIMED => tracing::debug!("Dropped attachment {}: too many unclaimed", id),
+            None => {
+                slots.insert(id, Slot::Arrived(stream));
+            }
+        }
+    }
+
+    async fn take(&self, info: &AttachmentInfo) -> Result<iroh::endpoint::RecvStream, AttachmentError> {
+        let waiting = {
+            let mut slots = self.slots.lock().expect("attachment slots lock poisoned");
+            if let Some(Slot::Arrived(stream)) = slots.remove(&info.id) {
+                return Ok(stream);
+            }
+            let (waiter, waiting) = tokio::sync::oneshot::channel();
+            slots.insert(info.id.clone(), Slot::Waiting(waiter));
+            waiting
+        };
+        match tokio::time::timeout(OPEN_TIMEOUT, waiting).await {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(_)) => Err(AttachmentError::Closed { name: info.name.clone() }),
+            Err(_) => {
+                self.slots.lock().expect("attachment slots lock poisoned").remove(&info.id);
+                Err(AttachmentError::Missing { name: info.name.clone(), timeout: OPEN_TIMEOUT })
+            }
+        }
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// Hand `conn`'s unidirectional streams to the inbox until it closes or `stop`
+async fn accept(inbox: std::sync::Weak<Inbox>, conn: iroh::endpoint::Connection, stop: tokio_util::sync::CancellationToken) {
+    loop {
+        let stream = tokio::select! {
+            stream = conn.accept_uni() => stream,
+            _ = stop.cancelled() => return,
+            _ = crate::cancelled() => return,
+        };
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::debug!("Stopped accepting attachments: {}", e);
+                // Handlers waiting in open() see the connection is gone
+                if let Some(inbox) = inbox.upgrade() {
+                    inbox.slots.lock().expect("attachment slots lock poisoned").clear();
+                }
+                return;
+            }
+        };
+        // Each header is read on its own, so a slow sender doesn't hold up the others
+        let inbox = inbox.clone();
+        crate::spawn(async move {
+            let header = tokio::time::timeout(OPEN_TIMEOUT, fastn_net::next_json::<AttachmentHeader>(&mut stream)).await;
+            match (header, inbox.upgrade()) {
+                (Ok(Ok(header)), Some(inbox)) => inbox.deliver(header.attachment, stream),
+                (Ok(Err(e)), _) => tracing::debug!("Dropped a stream without an attachment header: {}", e),
+                _ => {}
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_attachment_sources() {
+        let bytes = Attachment::from_bytes("notes.txt", b"hello".to_vec());
+        assert_eq!(bytes.info().size, 5);
+        assert_eq!(bytes.info().id.len(), 16);
+
+        let temp = tempfile::tempdir().unwrap();
+
+        let path = temp.path().join("attachment.bin");
+        tokio::fs::write(&path, vec![7u8; 1000]).await.unwrap();
+        let file = Attachment::from_file(&path).await.unwrap().with_name("blob.bin");
+        assert_eq!(file.info().size, 1000);
+        assert_eq!(file.info().name, "blob.bin");
+        assert_ne!(file.info().id, bytes.info().id);
+
+        let header = serde_json::to_string(&AttachmentHeader { attachment: file.info().id.clone() }).unwrap();
+        assert_eq!(header, format!(r#"{{"attachment":"{}"}}"#, file.info().id));
+    }
+}
@@ -0,0 +1,49 @@
+//! Batched calls: several requests in one stream round trip
+//!
+//! A client that needs many small answers from the same peer (a mail client
+//! fetching headers, then bodies) pays a bi-stream open and a round trip per
+//! [`crate::client::Client::call`]. [`crate::client::Client::call_batch`]
+//! sends them all as one wrapper request for [`BATCH_PROTOCOL`] instead:
+//!
+//! ```text
+//! → {"protocol":"fastn-p2p-batch","data":{"calls":[{"protocol":"Headers","data":{...}},...]}}
+//! ← {"status":"ok","data":{"results":[{"status":"ok","data":...},{"status":"err","data":...}]}}
+//! ```
+//!
+//! The server runs the calls concurrently, each through its middleware,
+//! stream auth and timeout as if it came alone, and answers once all are
+//! done, results in the order of the calls. Metadata, trace and signature of
+//! the batch apply to every call in it. Only request/response handlers can
+//! be batched; a call to anything else gets an error result.
+//!
+//! Every server built with [`crate::server::ServerBuilder`] accepts
+//! [`BATCH_PROTOCOL`]. Servers that predate it refuse it in the handshake,
+//! and `call_batch` then makes the calls one by one.
+
+/// Protocol of a batch's wrapper request, accepted by every server
+pub const BATCH_PROTOCOL: &str = "fastn-p2p-batch";
+
+/// Calls a server takes in one batch; larger batches are refused as a whole
+pub const MAX_BATCH_CALLS: usize = 256;
+
+pub(crate) fn protocol_json() -> serde_json::Value {
+    serde_json::Value::String(BATCH_PROTOCOL.to_string())
+}
+
+/// Data of a batch's wrapper request
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct BatchRequest {
+    pub calls: Vec<BatchCall>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct BatchCall {
+    pub protocol: serde_json::Value,
+    pub data: serde_json::Value,
+}
+
+/// Answer to a [`BatchRequest`]; always tagged, whatever the handshake negotiated
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct BatchResponse {
+    pub results: Vec<crate::wire::ResponseEnvelope<serde_json::Value, serde_json::Value>>,
+}
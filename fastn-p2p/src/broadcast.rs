@@ -0,0 +1,218 @@
+//! One producer feeding many streaming sessions
+//!
+//! A live source (a camera, a log tail, a game state) is read once by a
+//! producer task that sends each item into a [`Channel`]; every session
+//! subscribes and forwards items to its peer. Items are kept once in a shared
+//! ring buffer of `capacity` items, so `T` should be cheap to clone (`Arc`,
+//! `bytes::Bytes`). A subscriber that falls more than `capacity` items behind
+//! is handled by the channel's [`LagPolicy`] without slowing the producer or
+//! the other subscribers.
+//!
+//! ```rust,no_run
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum Media { Live }
+//! # struct Camera;
+//! # impl Camera { async fn next_frame(&mut self) -> Option<Vec<u8>> { None } }
+//! # async fn run(key: fastn_p2p::SecretKey, mut camera: Camera) -> Result<(), Box<dyn std::error::Error>> {
+//! let channel = fastn_p2p::broadcast::Channel::new(64, fastn_p2p::broadcast::LagPolicy::DropOldest);
+//! let producer = channel.clone();
+//! fastn_p2p::spawn(async move {
+//!     while let Some(frame) = camera.next_frame().await {
+//!         producer.send(std::sync::Arc::<[u8]>::from(frame));
+//!     }
+//! });
+//!
+//! fastn_p2p::listen(key)
+//!     .handle_streams(Media::Live, channel, |mut session: fastn_p2p::Session<Media>, _: (), channel| async move {
+//!         channel.subscribe().forward(&mut session.send).await.map(drop)
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Dropping every clone of the channel ends all subscriptions.
+
+/// What happens to a subscriber that falls more than the capacity behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LagPolicy {
+    /// Skip the items it missed and carry on from the oldest one still kept
+    #[default]
+    DropOldest,
+    /// End the subscription with [`BroadcastError::Lagged`]
+    Disconnect,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BroadcastError {
+    #[error("Broadcast channel closed")]
+    Closed,
+
+    #[error("Subscriber fell {skipped} items behind and was disconnected")]
+    Lagged { skipped: u64 },
+
+    #[error("Failed to write to subscriber: {source}")]
+    Io {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Sending side; clones send into the same channel
+#[derive(Debug, Clone)]
+pub struct Channel<T> {
+    sender: tokio::sync::broadcast::Sender<T>,
+    policy: LagPolicy,
+}
+
+impl<T: Clone> Channel<T> {
+    /// A channel keeping the last `capacity` items for slow subscribers
+    pub fn new(capacity: usize, policy: LagPolicy) -> Self {
+        let (sender, _) = tokio::sync::broadcast::channel(capacity.max(1));
+        Self { sender, policy }
+    }
+
+    /// Send `value` to every current subscriber; returns how many there are
+    ///
+    /// Never waits: with no subscribers the value is dropped.
+    pub fn send(&self, value: T) -> usize {
+        self.sender.send(value).unwrap_or(0)
+    }
+
+    /// Receive the items sent from now on
+    pub fn subscribe(&self) -> Subscriber<T> {
+        Subscriber {
+            receiver: self.sender.subscribe(),
+            policy: self.policy,
+            dropped: 0,
+            lagged: false,
+        }
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+/// One session's view of a [`Channel`]
+#[derive(Debug)]
+pub struct Subscriber<T> {
+    receiver: tokio::sync::broadcast::Receiver<T>,
+    policy: LagPolicy,
+    dropped: u64,
+    /// Disconnected for lagging; later calls keep failing
+    lagged: bool,
+}
+
+impl<T: Clone> Subscriber<T> {
+    /// Wait for the next item
+    pub async fn recv(&mut self) -> Result<T, BroadcastError> {
+        loop {
+            if self.lagged {
+                return Err(BroadcastError::Lagged { skipped: self.dropped });
+            }
+            match self.receiver.recv().await {
+                Ok(value) => return Ok(value),
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return Err(BroadcastError::Closed),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    self.dropped += skipped;
+                    match self.policy {
+                        LagPolicy::DropOldest => {
+                            tracing::debug!("Broadcast subscriber skipped {} items", skipped);
+                        }
+                        LagPolicy::Disconnect => self.lagged = true,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Items skipped so far because this subscriber was too slow
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl<T: Clone + AsRef<[u8]>> Subscriber<T> {
+    /// Write every item to `writer` until the channel closes
+    ///
+    /// Returns the bytes written; closing the channel is a normal end.
+    /// Fails if writing fails (usually the peer went away) or the subscriber
+    /// is disconnected for lagging.
+    pub async fn forward<W>(mut self, mut writer: W) -> Result<u64, BroadcastError>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let mut written = 0;
+        loop {
+            let item = match self.recv().await {
+                Ok(item) => item,
+                Err(BroadcastError::Closed) => break,
+                Err(e) => return Err(e),
+            };
+            writer
+                .write_all(item.as_ref())
+                .await
+                .map_err(|source| BroadcastError::Io { source })?;
+            written += item.as_ref().len() as u64;
+        }
+        writer.flush().await.map_err(|source| BroadcastError::Io { source })?;
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lag_policies() {
+        let channel = Channel::new(2, LagPolicy::DropOldest);
+        let mut slow = channel.subscribe();
+        for i in 0..5 {
+            assert_eq!(channel.send(i), 1);
+        }
+        assert_eq!(slow.recv().await.unwrap(), 3);
+        assert_eq!(slow.dropped(), 3);
+
+        let channel = Channel::new(2, LagPolicy::Disconnect);
+        let mut slow = channel.subscribe();
+        let mut fast = channel.subscribe();
+        for i in 0..5 {
+            channel.send(i);
+            assert_eq!(fast.recv().await.unwrap(), i);
+        }
+        assert!(matches!(slow.recv().await, Err(BroadcastError::Lagged { skipped: 3 })));
+        channel.send(5);
+        assert!(matches!(slow.recv().await, Err(BroadcastError::Lagged { .. })));
+        assert_eq!(fast.recv().await.unwrap(), 5);
+
+        drop(channel);
+        assert!(matches!(fast.recv().await, Err(BroadcastError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_many_writers() {
+        let channel: Channel<&'static [u8]> = Channel::new(8, LagPolicy::DropOldest);
+        let mut readers = Vec::new();
+        let mut forwards = Vec::new();
+        for _ in 0..3 {
+            let (writer, reader) = tokio::io::duplex(64);
+            readers.push(reader);
+            forwards.push(tokio::spawn(channel.subscribe().forward(writer)));
+        }
+        assert_eq!(channel.subscriber_count(), 3);
+
+        channel.send(b"live ");
+        channel.send(b"frames");
+        drop(channel);
+        for (forward, mut reader) in forwards.into_iter().zip(readers) {
+            assert_eq!(forward.await.unwrap().unwrap(), 11);
+            let mut received = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut received).await.unwrap();
+            assert_eq!(received, b"live frames");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Capability tokens: signed, expiring grants for peers not on any allowlist
+//!
+//! An identity mints a [`CapabilityToken`] granting some protocols or serve_all
+//! commands until it expires, e.g. "read-only files.fastn.com for 24h", and
+//! hands it out as a string, say in a sharing link. The holder attaches it to
+//! its ClientHello and the server checks it before any handler runs:
+//!
+//! ```rust,no_run
+//! # use std::time::Duration;
+//! # use fastn_p2p::capability::{CapabilityToken, Grant};
+//! # use fastn_p2p::client::HelloMetadata;
+//! # fn run(identity_key: fastn_p2p::SecretKey, key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//! let token = CapabilityToken::mint(
+//!     &identity_key,
+//!     None, // anyone holding the token may use it
+//!     vec![Grant::protocol("files.fastn.com").with_commands(["read", "list"])],
+//!     Duration::from_secs(24 * 60 * 60),
+//! );
+//! let link = token.to_string();
+//!
+//! // On the other side
+//! let hello = HelloMetadata::new().with_capability(&link.parse()?);
+//! let client = fastn_p2p::client::Client::new(key).with_hello_metadata(hello);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Servers trust tokens minted by their own identity and by issuers added with
+//! [`crate::server::ServerBuilder::with_capability_issuer`]. A valid token
+//! lets in a peer the connection auth hook refuses, but only for what it
+//! grants; streams it covers skip the stream auth hook. Invalid or expired
+//! tokens are refused with a [`crate::client::CallError::Denied`] saying why.
+
+#[derive(Debug, thiserror::Error)]
+pub enum CapabilityError {
+    #[error("Capability token is not valid base64 JSON")]
+    Malformed {
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("Capability token signature by {issuer} is not valid")]
+    InvalidSignature { issuer: String },
+
+    #[error("Capability token issued by {issuer}, which this server does not trust")]
+    UntrustedIssuer { issuer: String },
+
+    #[error("Capability token expired at {expires_at}")]
+    Expired {
+        expires_at: chrono::DateTime<chrono::Utc>,
+    },
+
+    #[error("Capability token is for {holder}, not this peer")]
+    WrongHolder { holder: String },
+}
+
+impl CapabilityError {
+    /// Code of the denial the client gets, see [`crate::server::AuthDecision`]
+    pub fn code(&self) -> &'static str {
+        match self {
+            CapabilityError::Expired { .. } => "capability-expired",
+            _ => "capability-invalid",
+        }
+    }
+}
+
+/// Access to one protocol, or to some of its serve_all commands
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Grant {
+    /// Protocol name; for serve_all, the command protocol like `files.fastn.com`
+    pub protocol: String,
+    /// serve_all commands granted, all of them when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+}
+
+impl Grant {
+    pub fn protocol(protocol: impl Into<String>) -> Self {
+        Self { protocol: protocol.into(), commands: Vec::new() }
+    }
+
+    pub fn with_commands(mut self, commands: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.commands = commands.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Whether the stream `request` opens falls under this grant
+    pub fn covers(&self, request: &crate::server::StreamAuthRequest) -> bool {
+        match &request.command {
+            Some(command) => {
+                command.protocol == self.protocol
+                    && (self.commands.is_empty() || self.commands.contains(&command.command))
+            }
+            None => {
+                let name = match request.protocol.as_str() {
+                    Some(name) => name.to_string(),
+                    None => request.protocol.to_string(),
+                };
+                self.commands.is_empty() && name == self.protocol
+            }
+        }
+    }
+}
+
+/// Signed grants, see the module docs
+///
+/// Sent as base64 (URL safe) JSON; `to_string()` and `parse()` convert.
+/// Whoever holds one can use it, so `Debug` leaves out the signature.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CapabilityToken {
+    pub issuer: fastn_id52::PublicKey,
+    /// Only this peer may use the token; anyone holding it when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder: Option<fastn_id52::PublicKey>,
+    pub grants: Vec<Grant>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub signature: fastn_id52::Signature,
+}
+
+impl std::fmt::Debug for CapabilityToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CapabilityToken")
+            .field("issuer", &self.issuer)
+            .field("holder", &self.holder)
+            .field("grants", &self.grants)
+            .field("expires_at", &self.expires_at)
+            .field("signature", &fastn_p2p_client::Sensitive(()))
+            .finish()
+    }
+}
+
+impl CapabilityToken {
+    /// Grant `grants` for `ttl`, signed by `issuer`
+    pub fn mint(
+        issuer: &fastn_id52::SecretKey,
+        holder: Option<fastn_id52::PublicKey>,
+        grants: Vec<Grant>,
+        ttl: std::time::Duration,
+    ) -> Self {
+        let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+        let expires_at = chrono::Utc::now().checked_add_signed(ttl).unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+        // Whole seconds keep the token short
+        let expires_at = chrono::DateTime::from_timestamp(expires_at.timestamp(), 0).unwrap_or(expires_at);
+        let issuer_key = issuer.public_key();
+        let message = message(&issuer_key, holder.as_ref(), &grants, &expires_at);
+        Self {
+            issuer: issuer_key,
+            holder,
+            grants,
+            expires_at,
+            signature: issuer.sign(&message),
+        }
+    }
+
+    /// Check the token for `peer` on a server trusting `issuers`
+    pub fn verify(&self, peer: &fastn_id52::PublicKey, issuers: &[fastn_id52::PublicKey]) -> Result<(), CapabilityError> {
+        if !issuers.contains(&self.issuer) {
+            return Err(CapabilityError::UntrustedIssuer { issuer: self.issuer.id52() });
+        }
+        let message = message(&self.issuer, self.holder.as_ref(), &self.grants, &self.expires_at);
+        self.issuer
+            .verify(&message, &self.signature)
+            .map_err(|_| CapabilityError::InvalidSignature { issuer: self.issuer.id52() })?;
+        if self.expires_at <= chrono::Utc::now() {
+            return Err(CapabilityError::Expired { expires_at: self.expires_at });
+        }
+        match &self.holder {
+            Some(holder) if holder != peer => Err(CapabilityError::WrongHolder { holder: holder.id52() }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether any grant covers the stream `request` opens
+    pub fn covers(&self, request: &crate::server::StreamAuthRequest) -> bool {
+        self.grants.iter().any(|grant| grant.covers(request))
+    }
+}
+
+impl std::fmt::Display for CapabilityToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_vec(self).map_err(|_| std::fmt::Error)?;
+        f.write_str(&base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, json))
+    }
+}
+
+impl std::str::FromStr for CapabilityToken {
+    type Err = CapabilityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json = base64::Engine::decode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, s.trim())
+            .map_err(|e| CapabilityError::Malformed { source: Box::new(e) })?;
+        serde_json::from_slice(&json).map_err(|e| CapabilityError::Malformed { source: Box::new(e) })
+    }
+}
+
+// Grants serialize deterministically, so both sides build the same bytes
+fn message(
+    issuer: &fastn_id52::PublicKey,
+    holder: Option<&fastn_id52::PublicKey>,
+    grants: &[Grant],
+    expires_at: &chrono::DateTime<chrono::Utc>,
+) -> Vec<u8> {
+    format!(
+        "fastn-p2p-capability-v1\n{}\n{}\n{}\n{}",
+        issuer.id52(),
+        holder.map(|holder| holder.id52()).unwrap_or_default(),
+        expires_at.timestamp(),
+        serde_json::to_string(grants).expect("grants serialize")
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(protocol: serde_json::Value) -> crate::server::StreamAuthRequest {
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        crate::server::StreamAuthRequest::new(peer, &protocol, &serde_json::Value::Null)
+    }
+
+    #[test]
+    fn test_token_round_trip_and_verify() {
+        let issuer = fastn_id52::SecretKey::generate();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let other = fastn_id52::SecretKey::generate().public_key();
+        let ttl = std::time::Duration::from_secs(60);
+
+        let token = CapabilityToken::mint(&issuer, None, vec![Grant::protocol("Echo")], ttl);
+        let parsed: CapabilityToken = token.to_string().parse().unwrap();
+        assert_eq!(parsed, token);
+        parsed.verify(&peer, &[issuer.public_key()]).unwrap();
+        assert!(matches!(parsed.verify(&peer, &[other]), Err(CapabilityError::UntrustedIssuer { .. })));
+
+        let mut widened = parsed.clone();
+        widened.grants.push(Grant::protocol("Admin"));
+        assert!(matches!(widened.verify(&peer, &[issuer.public_key()]), Err(CapabilityError::InvalidSignature { .. })));
+
+        let held = CapabilityToken::mint(&issuer, Some(peer), vec![Grant::protocol("Echo")], ttl);
+        held.verify(&peer, &[issuer.public_key()]).unwrap();
+        assert!(matches!(held.verify(&other, &[issuer.public_key()]), Err(CapabilityError::WrongHolder { .. })));
+
+        let expired = CapabilityToken::mint(&issuer, None, vec![Grant::protocol("Echo")], std::time::Duration::ZERO);
+        let error = expired.verify(&peer, &[issuer.public_key()]).unwrap_err();
+        assert_eq!(error.code(), "capability-expired");
+        assert!("not a token".parse::<CapabilityToken>().is_err());
+    }
+
+    #[test]
+    fn test_grants_cover_protocols_and_commands() {
+        let read_only = Grant::protocol("files.fastn.com").with_commands(["read"]);
+        let read = crate::server::serve_all::CommandProtocol::new("files.fastn.com", "default", "read");
+        let write = crate::server::serve_all::CommandProtocol::new("files.fastn.com", "default", "write");
+        assert!(read_only.covers(&request(serde_json::to_value(&read).unwrap())));
+        assert!(!read_only.covers(&request(serde_json::to_value(&write).unwrap())));
+        assert!(Grant::protocol("files.fastn.com").covers(&request(serde_json::to_value(&write).unwrap())));
+
+        assert!(Grant::protocol("Echo").covers(&request(serde_json::json!("Echo"))));
+        assert!(!Grant::protocol("Echo").covers(&request(serde_json::json!("Admin"))));
+        assert!(!read_only.covers(&request(serde_json::json!("files.fastn.com"))));
+    }
+}
@@ -0,0 +1,149 @@
+//! Streams that end with a BLAKE3 checksum
+//!
+//! `copy_from_verified` sends the reader as length-prefixed chunks while
+//! hashing them. A zero-length chunk marks the end and is followed by the
+//! 32-byte BLAKE3 hash of everything sent. `copy_to_verified` writes the
+//! chunks out as they arrive and fails with [`VerifyError::ChecksumMismatch`]
+//! if the hash doesn't match. The data is already in the writer by then, so
+//! callers should treat it as incomplete (e.g. write to a temporary file and
+//! rename it on success).
+//!
+//! Both sides must agree to use this framing; it is not negotiated.
+//!
+//! ```text
+//! [len: u32 BE][len bytes] ... [0: u32 BE][hash: 32 bytes]
+//! ```
+
+/// Largest chunk [`send`] writes and [`receive`] accepts
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    #[error("Verified copy failed: {source}")]
+    Io {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Checksum mismatch: sender has {expected}, received data hashes to {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+}
+
+impl From<std::io::Error> for VerifyError {
+    fn from(source: std::io::Error) -> Self {
+        VerifyError::Io { source }
+    }
+}
+
+/// Send `reader` to `writer` in verified framing; returns the payload bytes sent
+pub(crate) async fn send<R, W>(reader: &mut R, writer: &mut W) -> std::io::Result<u64>
+where
+    R: tokio::io::AsyncRead + Unpin + ?Sized,
+    W: tokio::io::AsyncWrite + Unpin + ?Sized,
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut sent = 0u64;
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_u32(read as u32).await?;
+        writer.write_all(&buffer[..read]).await?;
+        sent += read as u64;
+    }
+
+    writer.write_u32(0).await?;
+    writer.write_all(hasher.finalize().as_bytes()).await?;
+    writer.flush().await?;
+    Ok(sent)
+}
+
+/// Read verified framing from `reader` into `writer`; returns the payload bytes received
+pub(crate) async fn receive<R, W>(reader: &mut R, writer: &mut W) -> Result<u64, VerifyError>
+where
+    R: tokio::io::AsyncRead + Unpin + ?Sized,
+    W: tokio::io::AsyncWrite + Unpin + ?Sized,
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut received = 0u64;
+    loop {
+        let len = reader.read_u32().await? as usize;
+        if len == 0 {
+            break;
+        }
+        if len > CHUNK_SIZE {
+            return Err(VerifyError::Io {
+                source: std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Verified chunk of {len} bytes exceeds {CHUNK_SIZE}"),
+                ),
+            });
+        }
+        reader.read_exact(&mut buffer[..len]).await?;
+        hasher.update(&buffer[..len]);
+        writer.write_all(&buffer[..len]).await?;
+        received += len as u64;
+    }
+    writer.flush().await?;
+
+    let mut expected = [0u8; blake3::OUT_LEN];
+    reader.read_exact(&mut expected).await?;
+    let expected = blake3::Hash::from_bytes(expected);
+    let actual = hasher.finalize();
+    if actual != expected {
+        return Err(VerifyError::ChecksumMismatch {
+            expected: expected.to_hex().to_string(),
+            actual: actual.to_hex().to_string(),
+        });
+    }
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_verified_round_trip() {
+        let payload: Vec<u8> = (0..CHUNK_SIZE * 2 + 17).map(|i| i as u8).collect();
+        let mut framed = Vec::new();
+        assert_eq!(send(&mut payload.as_slice(), &mut framed).await.unwrap(), payload.len() as u64);
+
+        let mut output = Vec::new();
+        assert_eq!(receive(&mut framed.as_slice(), &mut output).await.unwrap(), payload.len() as u64);
+        assert_eq!(output, payload);
+
+        // Empty input still carries a checksum
+        let mut framed = Vec::new();
+        send(&mut &b""[..], &mut framed).await.unwrap();
+        assert_eq!(framed.len(), 4 + blake3::OUT_LEN);
+        assert_eq!(receive(&mut framed.as_slice(), &mut Vec::new()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_corruption_and_truncation() {
+        let mut framed = Vec::new();
+        send(&mut &b"important bytes"[..], &mut framed).await.unwrap();
+
+        let mut corrupted = framed.clone();
+        corrupted[4] ^= 0xff;
+        assert!(matches!(
+            receive(&mut corrupted.as_slice(), &mut Vec::new()).await,
+            Err(VerifyError::ChecksumMismatch { .. })
+        ));
+
+        let truncated = &framed[..framed.len() - 1];
+        assert!(matches!(
+            receive(&mut &truncated[..], &mut Vec::new()).await,
+            Err(VerifyError::Io { .. })
+        ));
+    }
+}
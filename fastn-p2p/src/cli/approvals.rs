@@ -0,0 +1,41 @@
+//! Approvals commands: accept or deny incoming streams waiting for the user
+//!
+//! Stream handlers ask with `Session::request_approval`, see
+//! [`fastn_p2p::server::approvals`]. The daemon finds them in FASTN_HOME,
+//! where `serve_all` hosts share them.
+
+use std::path::PathBuf;
+
+/// Show the streams waiting for approval, oldest first
+pub async fn list(fastn_home: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let approvals = fastn_p2p_client::admin::approvals(&fastn_home).await?;
+
+    if approvals.is_empty() {
+        say!("✅ Nothing waiting for approval");
+    }
+    let now_ms = chrono::Utc::now().timestamp_millis().max(0) as u64;
+    for approval in &approvals {
+        say!("⏳ {}  {}", approval.id, approval.summary);
+        say!("   From: {} ({})", approval.peer, approval.protocol);
+        say!("   To: {}", approval.identity);
+        say!("   Expires in: {}s", approval.expires_ms.saturating_sub(now_ms) / 1000);
+    }
+    crate::cli::output::result(approvals);
+    Ok(())
+}
+
+/// Let the stream waiting under `id` go on
+pub async fn accept(fastn_home: PathBuf, id: String) -> Result<(), Box<dyn std::error::Error>> {
+    let resolved = fastn_p2p_client::admin::accept_approval(&fastn_home, &id).await?;
+    say!("👍 Accepted {}", resolved.id);
+    crate::cli::output::result(resolved);
+    Ok(())
+}
+
+/// Reject the stream waiting under `id`
+pub async fn deny(fastn_home: PathBuf, id: String) -> Result<(), Box<dyn std::error::Error>> {
+    let resolved = fastn_p2p_client::admin::deny_approval(&fastn_home, &id).await?;
+    say!("👎 Denied {}", resolved.id);
+    crate::cli::output::result(resolved);
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! Audit command for querying an identity's audit log
+
+use std::path::PathBuf;
+
+/// Show audit records for an identity, optionally filtered by peer and time
+pub async fn show_audit(
+    fastn_home: PathBuf,
+    identity: String,
+    peer: Option<String>,
+    since: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let since = since.as_deref().map(parse_since).transpose()?;
+    let filter = fastn_p2p::server::audit::AuditFilter { peer, since };
+
+    let records = fastn_p2p::server::audit::query(&fastn_home, &identity, &filter).await?;
+
+    say!("📜 Audit log for identity '{}'", identity);
+    say!(
+        "   {}",
+        fastn_p2p::server::audit::audit_log_path(&fastn_home, &identity).display()
+    );
+    say!();
+
+    if records.is_empty() {
+        say!("   No matching audit records");
+        crate::cli::output::result(&records);
+        return Ok(());
+    }
+
+    for record in &records {
+        say!(
+            "{} {} {} {} {} (in: {} bytes, out: {} bytes)",
+            record.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            record.peer,
+            record.protocol,
+            record.command,
+            record.outcome,
+            record.bytes_in,
+            record.bytes_out,
+        );
+        if let Some(verified) = &record.verified {
+            say!("   🔏 signed by {} at {}", verified.peer.id52(), verified.timestamp.to_rfc3339());
+        }
+    }
+
+    say!();
+    say!("✅ {} records", records.len());
+    crate::cli::output::result(&records);
+    Ok(())
+}
+
+/// Parse `--since` as an RFC 3339 timestamp or a relative age like `30m`, `2h`, `7d`
+fn parse_since(since: &str) -> Result<chrono::DateTime<chrono::Utc>, Box<dyn std::error::Error>> {
+    if let Ok(timestamp) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(timestamp.with_timezone(&chrono::Utc));
+    }
+
+    let invalid = || {
+        crate::cli::output::UsageError::boxed(format!(
+            "Invalid --since value '{}': use an RFC 3339 timestamp or an age like 30s, 15m, 2h, 7d",
+            since
+        ))
+    };
+
+    let age = crate::cli::parse_age(since).ok_or_else(invalid)?;
+    Ok(chrono::Utc::now() - age)
+}
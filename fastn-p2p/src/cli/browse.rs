@@ -0,0 +1,113 @@
+//! Browse command: local HTTP server proxying to a peer's web.fastn.com site
+
+use std::path::PathBuf;
+
+type BrowseResponse = fastn_net::http::ProxyResponse<std::io::Error>;
+
+/// Serve a peer's `web.fastn.com` binding on `http://127.0.0.1:<port>`
+pub async fn browse(
+    fastn_home: PathBuf,
+    peer_id52: String,
+    bind_alias: String,
+    port: u16,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity = crate::cli::identity::resolve_identity(&fastn_home, as_identity).await?;
+    let identities_dir = fastn_home.join("identities");
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
+        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
+    let peer: fastn_id52::PublicKey = peer_id52.parse()
+        .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await
+        .map_err(|e| format!("Failed to bind 127.0.0.1:{}: {}", port, e))?;
+    let client = fastn_p2p::client::Client::new(identity_config.secret_key);
+
+    say!("🌐 Browsing {} {} of {} as {}", fastn_p2p::server::web::WEB_PROTOCOL, bind_alias, peer.id52(), identity);
+    say!("🔗 Open http://{}/ (Ctrl+C to stop)", listener.local_addr()?);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let client = client.clone();
+        let bind_alias = bind_alias.clone();
+
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let client = client.clone();
+                let bind_alias = bind_alias.clone();
+                async move {
+                    Ok::<_, std::convert::Infallible>(proxy(&client, peer, &bind_alias, request).await)
+                }
+            });
+
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Browser connection closed: {e}");
+            }
+        });
+    }
+}
+
+/// Forward one browser request to the peer and stream the file back
+async fn proxy(
+    client: &fastn_p2p::client::Client,
+    peer: fastn_id52::PublicKey,
+    bind_alias: &str,
+    request: hyper::Request<hyper::body::Incoming>,
+) -> BrowseResponse {
+    use futures_util::TryStreamExt;
+    use http_body_util::BodyExt;
+
+    if request.method() != hyper::Method::GET {
+        return fastn_net::http::bytes_to_resp(
+            b"Only GET is supported".to_vec(),
+            hyper::StatusCode::METHOD_NOT_ALLOWED,
+        );
+    }
+
+    let get = fastn_p2p::server::web::GetRequest {
+        path: request.uri().path().to_string(),
+        range: request
+            .headers()
+            .get(hyper::header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+    };
+    let protocol = fastn_p2p::server::CommandProtocol::new(fastn_p2p::server::web::WEB_PROTOCOL, bind_alias, "get");
+
+    let mut session = match client.connect(peer, protocol, &get).await {
+        Ok(session) => session,
+        Err(e) => return bad_gateway(format!("Could not reach peer: {e}")),
+    };
+    let response: fastn_p2p::server::web::GetResponse = match session.recv.next_json().await {
+        Ok(response) => response,
+        Err(e) => return bad_gateway(format!("Peer did not serve {}: {e}", get.path)),
+    };
+
+    say!("📄 {} {} ({} bytes)", response.status, get.path, response.content_length);
+
+    let body = tokio::io::AsyncReadExt::take(session.recv, response.content_length);
+    let body = http_body_util::StreamBody::new(
+        tokio_util::io::ReaderStream::new(body).map_ok(hyper::body::Frame::data),
+    );
+
+    let mut builder = hyper::Response::builder()
+        .status(response.status)
+        .header(hyper::header::CONTENT_TYPE, response.content_type)
+        .header(hyper::header::CONTENT_LENGTH, response.content_length)
+        .header(hyper::header::ACCEPT_RANGES, "bytes");
+    if let Some(content_range) = response.content_range {
+        builder = builder.header(hyper::header::CONTENT_RANGE, content_range);
+    }
+
+    builder
+        .body(body.boxed())
+        .unwrap_or_else(|e| bad_gateway(format!("Invalid response from peer: {e}")))
+}
+
+fn bad_gateway(message: String) -> BrowseResponse {
+    tracing::warn!("{message}");
+    fastn_net::http::bytes_to_resp(message.into_bytes(), hyper::StatusCode::BAD_GATEWAY)
+}
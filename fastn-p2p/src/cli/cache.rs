@@ -0,0 +1,33 @@
+//! Cache command: drop call answers the daemon cached
+//!
+//! What gets cached is set in `config.toml`, see [`crate::cli::daemon::cache`].
+
+use std::path::PathBuf;
+
+/// Clear the running daemon's call cache, all or only one peer's and/or protocol's answers
+pub async fn clear(
+    fastn_home: PathBuf,
+    peer: Option<String>,
+    protocol: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let peer: Option<fastn_id52::PublicKey> = peer
+        .map(|peer| {
+            peer.parse()
+                .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid peer ID '{}': {}", peer, e)))
+        })
+        .transpose()?;
+
+    // The cache lives in the daemon's memory; without a daemon it is empty
+    let cleared = match fastn_p2p_client::admin::clear_cache(&fastn_home, peer, protocol.as_deref()).await {
+        Ok(cleared) => cleared,
+        Err(fastn_p2p_client::ClientError::DaemonNotRunning { .. }) => {
+            say!("ℹ️  Daemon not running, nothing is cached");
+            fastn_p2p_client::admin::CacheCleared { cleared: 0 }
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    say!("🧹 Cleared {} cached answers", cleared.cleared);
+    crate::cli::output::result(cleared);
+    Ok(())
+}
@@ -3,35 +3,50 @@
 //! This module handles CLI client commands using the lightweight fastn-p2p-client crate.
 //! It provides the same functionality as the examples but via CLI interface.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::{self, Read};
 
+use crate::cli::output::UsageError;
+
+/// What a call or stream reaches on the peer
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub protocol: String,
+    pub bind_alias: String,
+    /// A serve_all command of the binding
+    pub command: Option<String>,
+    pub args: Vec<String>,
+}
+
+impl Target {
+    /// The binding itself, without a command or arguments
+    pub fn new(protocol: impl Into<String>, bind_alias: impl Into<String>) -> Self {
+        Target { protocol: protocol.into(), bind_alias: bind_alias.into(), command: None, args: Vec::new() }
+    }
+}
+
 /// Make a request/response call to a peer via the daemon
+///
+/// With a `command` this reaches a serve_all command, the same way
+/// `fastn-p2p sync` and `browse` do. Progress goes to stderr so stdout holds
+/// only the response: pretty-printed, or one compact line with `raw`. `path`
+/// restricts how the daemon reaches a single peer.
 pub async fn call(
     fastn_home: PathBuf,
     peer_id52: String,
-    protocol: String,
-    bind_alias: String,
+    target: Target,
     as_identity: Option<String>,
+    raw: bool,
+    path: fastn_p2p_client::client::PathPreference,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Check if daemon is running
+    // Check if daemon is running before waiting on stdin
     let socket_path = fastn_home.join("control.sock");
     if !socket_path.exists() {
-        return Err(format!("Daemon not running. Socket not found: {}. Start with: fastn-p2p daemon", socket_path.display()).into());
+        return Err(fastn_p2p_client::ClientError::DaemonNotRunning { path: socket_path }.into());
     }
     
-    // Determine identity to send from
-    let from_identity = match as_identity {
-        Some(identity) => identity,
-        None => {
-            // TODO: Auto-detect identity if only one configured
-            "alice".to_string() // Hardcoded for testing
-        }
-    };
-    
-    // Parse peer ID to PublicKey for type safety
-    let to_peer: fastn_id52::PublicKey = peer_id52.parse()
-        .map_err(|e| format!("Invalid peer ID '{}': {}", peer_id52, e))?;
+    // Anything that isn't an ID52 names a peer group
+    let to_peer: Option<fastn_id52::PublicKey> = peer_id52.parse().ok();
     
     // Read JSON request from stdin
     let mut stdin_input = String::new();
@@ -39,36 +54,176 @@ pub async fn call(
     let stdin_input = stdin_input.trim();
     
     if stdin_input.is_empty() {
-        return Err("No JSON input provided on stdin".into());
+        return Err(UsageError::boxed("No JSON input provided on stdin"));
     }
     
     // Parse JSON to validate it's valid
-    let request_json: serde_json::Value = serde_json::from_str(stdin_input)?;
+    let request_json: serde_json::Value = serde_json::from_str(stdin_input)
+        .map_err(|e| UsageError::boxed(format!("Invalid JSON on stdin: {}", e)))?;
     
-    println!("📤 Sending {} {} request from {} to {}", protocol, bind_alias, from_identity, to_peer.id52());
+    // Without --as-identity the daemon sends as the default identity
+    let reaching = match &target.command {
+        Some(command) => format!("{} {} {}", target.protocol, target.bind_alias, command),
+        None => format!("{} {}", target.protocol, target.bind_alias),
+    };
+    let Some(to_peer) = to_peer else {
+        if !path.is_any() {
+            return Err(UsageError::boxed("--path works with a single peer, not a group"));
+        }
+        eprintln!("📤 Sending {} request from {} to group {}", reaching,
+                as_identity.as_deref().unwrap_or("default identity"), peer_id52);
+        return call_group(&fastn_home, as_identity, peer_id52, target, request_json, raw).await;
+    };
+    eprintln!("📤 Sending {} request from {} to {}", reaching,
+            as_identity.as_deref().unwrap_or("default identity"), to_peer.id52());
     
-    // Connect to daemon control socket directly
-    use tokio::net::UnixStream;
-    use tokio::io::{AsyncWriteExt, AsyncReadExt, AsyncBufReadExt, BufReader};
+    let response = call_daemon(&fastn_home, as_identity, to_peer, target, request_json, path).await?;
+    ensure_success(&response)?;
+    if let Some(path) = response["data"]["path"].as_str() {
+        eprintln!("🛣️  Answered over a {} path", path);
+    }
     
-    let mut stream = UnixStream::connect(&socket_path).await
-        .map_err(|e| format!("Failed to connect to daemon: {}", e))?;
+    let cache = &response["data"]["cache"];
+    if cache["hit"] == serde_json::Value::Bool(true) {
+        eprintln!("📦 Cached answer, {}s old (fresh for {}s)", cache["age_secs"], cache["max_age_secs"]);
+    }
+    
+    let p2p_response = &response["data"]["p2p_response"];
+    if crate::cli::output::format() == crate::cli::output::Format::Json {
+        crate::cli::output::result(p2p_response);
+    } else if raw {
+        println!("{}", serde_json::to_string(p2p_response)?);
+    } else {
+        eprintln!("📥 Response:");
+        println!("{}", serde_json::to_string_pretty(p2p_response)?);
+    }
+    
+    Ok(())
+}
+
+/// Make a call to every member of a peer group and print each member's answer
+///
+/// Answers go to stdout in the group's order, each after a line on stderr
+/// naming the member. Members that failed are reported and skipped; the
+/// command fails only if no member answered.
+async fn call_group(
+    fastn_home: &Path,
+    from_identity: Option<String>,
+    group: String,
+    target: Target,
+    request_json: serde_json::Value,
+    raw: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Target { protocol, bind_alias, command, args } = target;
+    let daemon_request = fastn_p2p_client::DaemonRequest::GroupCall {
+        from_identity,
+        group,
+        protocol,
+        bind_alias,
+        command,
+        args,
+        request: fastn_p2p_client::Sensitive(request_json),
+        metadata: Default::default(),
+        traceparent: Some(fastn_net::TraceContext::new_root().traceparent()),
+    };
+    let response = send_daemon_request(fastn_home, &daemon_request).await?;
+    ensure_success(&response)?;
+    
+    let results = response["data"]["results"].as_array().cloned().unwrap_or_default();
+    let mut first_failure = None;
+    for result in &results {
+        let member = format!("{} ({})", result["member"].as_str().unwrap_or("?"), result["peer"].as_str().unwrap_or("?"));
+        if result["success"] != serde_json::Value::Bool(true) {
+            eprintln!("❌ {}: {}", member, fastn_p2p_client::ClientError::from_daemon_error(&result["data"]));
+            first_failure.get_or_insert(&result["data"]);
+            continue;
+        }
+        let p2p_response = &result["data"]["p2p_response"];
+        if crate::cli::output::format() == crate::cli::output::Format::Json {
+            continue;
+        }
+        if raw {
+            eprintln!("📥 {}", member);
+            println!("{}", serde_json::to_string(p2p_response)?);
+        } else {
+            eprintln!("📥 Response from {}:", member);
+            println!("{}", serde_json::to_string_pretty(p2p_response)?);
+        }
+    }
     
+    let answered = results.iter().filter(|result| result["success"] == serde_json::Value::Bool(true)).count();
+    eprintln!("👥 {} of {} members answered", answered, results.len());
+    if answered == 0
+        && let Some(failure) = first_failure
+    {
+        return Err(fastn_p2p_client::ClientError::from_daemon_error(failure).into());
+    }
+    crate::cli::output::result(&response["data"]["results"]);
+    Ok(())
+}
+
+/// Turn a `success: false` daemon response into the [`fastn_p2p_client::ClientError`] for its `kind`
+pub fn ensure_success(response: &serde_json::Value) -> Result<(), fastn_p2p_client::ClientError> {
+    if response["success"] == serde_json::Value::Bool(true) {
+        return Ok(());
+    }
+    Err(fastn_p2p_client::ClientError::from_daemon_error(&response["data"]))
+}
+
+/// Connect to the control socket of the daemon of `fastn_home`
+pub async fn connect_daemon(fastn_home: &Path) -> Result<tokio::net::UnixStream, fastn_p2p_client::ClientError> {
+    let socket_path = fastn_home.join("control.sock");
+    // A socket left behind by a daemon that died refuses connections
+    match tokio::net::UnixStream::connect(&socket_path).await {
+        Ok(stream) => Ok(stream),
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {
+            Err(fastn_p2p_client::ClientError::DaemonNotRunning { path: socket_path })
+        }
+        Err(e) => Err(fastn_p2p_client::ClientError::DaemonConnection(format!("Failed to connect to daemon: {}", e))),
+    }
+}
+
+/// Send a call request to the daemon and return its JSON response
+pub async fn call_daemon(
+    fastn_home: &Path,
+    from_identity: Option<String>,
+    to_peer: fastn_id52::PublicKey,
+    target: Target,
+    request_json: serde_json::Value,
+    path: fastn_p2p_client::client::PathPreference,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let Target { protocol, bind_alias, command, args } = target;
     // Create typed request using shared daemon protocol structure
     let daemon_request = fastn_p2p_client::DaemonRequest::Call {
         from_identity,
         to_peer,
         protocol,
         bind_alias,
-        request: request_json,
+        command,
+        args,
+        request: fastn_p2p_client::Sensitive(request_json),
+        metadata: Default::default(),
+        traceparent: Some(fastn_net::TraceContext::new_root().traceparent()),
+        path,
     };
+    send_daemon_request(fastn_home, &daemon_request).await
+}
+
+/// Send one request line to the daemon and return its JSON response line
+pub async fn send_daemon_request(
+    fastn_home: &Path,
+    daemon_request: &fastn_p2p_client::DaemonRequest<serde_json::Value>,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    use tokio::io::{AsyncWriteExt, AsyncBufReadExt, BufReader};
+    
+    let mut stream = connect_daemon(fastn_home).await?;
     
     // Send request to daemon
-    let request_data = serde_json::to_string(&daemon_request)?;
+    let request_data = fastn_p2p_client::version::encode_request(daemon_request)?;
     stream.write_all(request_data.as_bytes()).await?;
     stream.write_all(b"\n").await?;
     
-    println!("📡 Request sent to daemon, reading response...");
+    eprintln!("📡 Request sent to daemon, reading response...");
     
     // Read response from daemon
     let (reader, _writer) = stream.into_split();
@@ -76,23 +231,283 @@ pub async fn call(
     let mut response_line = String::new();
     
     match buf_reader.read_line(&mut response_line).await {
-        Ok(0) => return Err("Daemon closed connection without response".into()),
-        Ok(_) => {
-            let response: serde_json::Value = serde_json::from_str(response_line.trim())?;
-            println!("📥 Response from daemon:");
-            println!("{}", serde_json::to_string_pretty(&response)?);
+        Ok(0) => Err("Daemon closed connection without response".into()),
+        Ok(_) => Ok(serde_json::from_str(response_line.trim())?),
+        Err(e) => Err(format!("Failed to read daemon response: {}", e).into()),
+    }
+}
+
+/// Open a bidirectional stream to a peer via the daemon
+///
+/// stdin is sent to the peer and the peer's output written to stdout, so
+/// `tar c dir | fastn-p2p stream <peer> files.fastn.com put` works as a
+/// pipeline. End of stdin half-closes the stream; the peer can keep sending
+/// until it finishes. The process exits with the peer's exit code, see
+/// [`fastn_p2p_client::stream`].
+pub async fn stream(
+    fastn_home: PathBuf,
+    peer_id52: String,
+    target: Target,
+    as_identity: Option<String>,
+    data: Option<String>,
+    progress: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::AsyncWriteExt;
+    
+    let initial_data: serde_json::Value = match data {
+        Some(data) => serde_json::from_str(&data).map_err(|e| UsageError::boxed(format!("Invalid --data JSON: {}", e)))?,
+        None => serde_json::Value::Null,
+    };
+    // Anything that isn't an ID52 names a peer group
+    let Ok(to_peer) = peer_id52.parse::<fastn_id52::PublicKey>() else {
+        if progress {
+            return Err(UsageError::boxed("--progress works with a single peer, not a group"));
+        }
+        return stream_group(&fastn_home, as_identity, peer_id52, target, initial_data).await;
+    };
+    
+    let Target { protocol, bind_alias, command, args } = target;
+    let daemon_request = fastn_p2p_client::DaemonRequest::Stream {
+        from_identity: as_identity,
+        to_peer,
+        protocol,
+        bind_alias,
+        command,
+        args,
+        initial_data: fastn_p2p_client::Sensitive(initial_data),
+        metadata: Default::default(),
+        traceparent: Some(fastn_net::TraceContext::new_root().traceparent()),
+    };
+    let (mut reader, mut writer) = open_stream(&fastn_home, &daemon_request).await?;
+    eprintln!("🌊 Streaming to {}", to_peer.id52());
+    
+    let (sent_observer, sent_progress) = fastn_p2p::progress::Observer::watch();
+    let (received_observer, received_progress) = fastn_p2p::progress::Observer::watch();
+    let progress_bar = progress.then(|| {
+        tokio::spawn(render_stream_progress(sent_progress.clone(), received_progress.clone()))
+    });
+    
+    let upload = async {
+        let mut stdin = fastn_p2p::progress::Observed::new(tokio::io::stdin(), sent_observer.clone());
+        let sent = tokio::io::copy(&mut stdin, &mut writer).await;
+        sent_observer.finish();
+        // Half-close: the peer sees the end of its input
+        writer.shutdown().await?;
+        sent
+    };
+    let download = async {
+        let mut stdout = tokio::io::stdout();
+        loop {
+            match fastn_p2p_client::stream::read_frame(&mut reader).await? {
+                fastn_p2p_client::stream::Frame::Data(bytes) => {
+                    stdout.write_all(&bytes).await?;
+                    stdout.flush().await?;
+                    received_observer.add(bytes.len() as u64);
+                }
+                fastn_p2p_client::stream::Frame::End(end) => {
+                    received_observer.finish();
+                    return Ok::<_, std::io::Error>(end);
+                }
+            }
+        }
+    };
+    tokio::pin!(download);
+    
+    // Output ends the session; stdin may stay open, e.g. on a terminal
+    let (end, stdin_open) = tokio::select! {
+        sent = upload => {
+            sent?;
+            (download.await?, false)
         }
-        Err(e) => return Err(format!("Failed to read daemon response: {}", e).into()),
+        end = &mut download => (end?, true),
+    };
+    if let Some(progress_bar) = progress_bar {
+        progress_bar.abort();
+        eprintln!("\r{:<60}", stream_progress_line(&sent_progress.borrow(), &received_progress.borrow()));
     }
     
+    if let Some(error) = &end.error {
+        eprintln!("❌ Stream broke: {}", error);
+    }
+    // A pending stdin read would also keep the runtime from shutting down
+    if end.exit_code != 0 || stdin_open {
+        std::process::exit(end.exit_code);
+    }
     Ok(())
 }
 
-/// Open a bidirectional stream to a peer via the daemon  
-pub async fn stream(
-    _fastn_home: PathBuf,
-    _peer: String,
-    _protocol: String,
+/// Stream stdin to every member of a peer group
+///
+/// Every member gets the same input. Their output is collected and written
+/// to stdout once the streams end, member by member in the group's order
+/// and each after a line on stderr naming it, so outputs never interleave.
+/// Members that can't be reached are reported and skipped. Exits with the
+/// first non-zero exit code of a member.
+async fn stream_group(
+    fastn_home: &Path,
+    as_identity: Option<String>,
+    group: String,
+    target: Target,
+    initial_data: serde_json::Value,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    todo!("Use fastn-p2p-client::connect() to establish stream via daemon, pipe stdin/stdout bidirectionally");
-}
\ No newline at end of file
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    
+    let identity = crate::cli::peers::address_book_owner(fastn_home, as_identity.as_deref()).await?;
+    let store = fastn_p2p::server::state::StateStore::open(fastn_home).await?;
+    let Some(members) = store.group_members(&identity, &group).await? else {
+        return Err(UsageError::boxed(format!("'{}' is neither a peer ID52 nor a peer group of '{}'", group, identity)));
+    };
+    
+    let mut streaming = Vec::new();
+    let mut readers = Vec::new();
+    let mut writers = Vec::new();
+    let mut report = Vec::new();
+    let mut last_error = None;
+    for member in &members {
+        let daemon_request = fastn_p2p_client::DaemonRequest::Stream {
+            from_identity: Some(identity.clone()),
+            to_peer: member.peer,
+            protocol: target.protocol.clone(),
+            bind_alias: target.bind_alias.clone(),
+            command: target.command.clone(),
+            args: target.args.clone(),
+            initial_data: fastn_p2p_client::Sensitive(initial_data.clone()),
+            metadata: Default::default(),
+            traceparent: Some(fastn_net::TraceContext::new_root().traceparent()),
+        };
+        match open_stream(fastn_home, &daemon_request).await {
+            Ok((reader, writer)) => {
+                streaming.push(member);
+                readers.push(reader);
+                writers.push(Some(writer));
+            }
+            Err(e) => {
+                eprintln!("❌ {} ({}): {}", member.name, member.peer.id52(), e);
+                report.push(serde_json::json!({ "member": member.name, "peer": member.peer.id52(), "error": e.to_string() }));
+                last_error = Some(e);
+            }
+        }
+    }
+    if streaming.is_empty() {
+        return Err(last_error.unwrap_or_else(|| format!("Group '{}' has no members", group).into()));
+    }
+    eprintln!("🌊 Streaming to {} of {} members of {}", streaming.len(), members.len(), group);
+    
+    let upload = async {
+        let mut stdin = tokio::io::stdin();
+        let mut buf = vec![0u8; fastn_p2p_client::stream::MAX_CHUNK];
+        loop {
+            let n = stdin.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            // A member that stopped reading doesn't get the rest
+            for slot in writers.iter_mut() {
+                let Some(writer) = slot else { continue };
+                if writer.write_all(&buf[..n]).await.is_err() {
+                    *slot = None;
+                }
+            }
+        }
+        for writer in writers.iter_mut().flatten() {
+            let _ = writer.shutdown().await;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    let downloads = futures_util::future::join_all(readers.iter_mut().map(|reader| async move {
+        let mut output = Vec::new();
+        loop {
+            match fastn_p2p_client::stream::read_frame(reader).await? {
+                fastn_p2p_client::stream::Frame::Data(bytes) => output.extend_from_slice(&bytes),
+                fastn_p2p_client::stream::Frame::End(end) => return Ok::<_, std::io::Error>((output, end)),
+            }
+        }
+    }));
+    tokio::pin!(downloads);
+    
+    // Every member's output ends the session; stdin may stay open, e.g. on a terminal
+    let (outputs, stdin_open) = tokio::select! {
+        sent = upload => {
+            sent?;
+            (downloads.await, false)
+        }
+        outputs = &mut downloads => (outputs, true),
+    };
+    
+    let mut stdout = tokio::io::stdout();
+    let mut exit_code = 0;
+    for (member, output) in streaming.into_iter().zip(outputs) {
+        let (output, end) = match output {
+            Ok(output) => output,
+            Err(e) => (Vec::new(), fastn_p2p_client::stream::StreamEnd::failed(e)),
+        };
+        eprintln!("📥 {} ({}), exit code {}:", member.name, member.peer.id52(), end.exit_code);
+        if let Some(error) = &end.error {
+            eprintln!("❌ Stream broke: {}", error);
+        }
+        if crate::cli::output::format() == crate::cli::output::Format::Text {
+            stdout.write_all(&output).await?;
+            stdout.flush().await?;
+        }
+        if exit_code == 0 {
+            exit_code = end.exit_code;
+        }
+        report.push(serde_json::json!({
+            "member": member.name,
+            "peer": member.peer.id52(),
+            "exit_code": end.exit_code,
+            "error": end.error,
+            "output": String::from_utf8_lossy(&output),
+        }));
+    }
+    crate::cli::output::result(report);
+    
+    // A pending stdin read would also keep the runtime from shutting down
+    if exit_code != 0 || stdin_open {
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
+
+/// Keep one stderr line showing both directions of `fastn-p2p stream --progress`
+async fn render_stream_progress(
+    sent: tokio::sync::watch::Receiver<fastn_p2p::progress::Progress>,
+    received: tokio::sync::watch::Receiver<fastn_p2p::progress::Progress>,
+) {
+    let mut ticker = tokio::time::interval(fastn_p2p::progress::DEFAULT_INTERVAL);
+    loop {
+        ticker.tick().await;
+        eprint!("\r{:<60}", stream_progress_line(&sent.borrow(), &received.borrow()));
+    }
+}
+
+fn stream_progress_line(sent: &fastn_p2p::progress::Progress, received: &fastn_p2p::progress::Progress) -> String {
+    // Stream sizes are unknown up front: bytes and rate only
+    format!("⬆️  {}  ⬇️  {}", sent.bar(0), received.bar(0))
+}
+
+/// Send a stream request to the daemon and wait until the peer accepted it
+///
+/// Returns the socket halves, ready for the relay described in
+/// [`fastn_p2p_client::stream`].
+pub async fn open_stream(
+    fastn_home: &Path,
+    request: &fastn_p2p_client::DaemonRequest<serde_json::Value>,
+) -> Result<(tokio::io::BufReader<tokio::net::unix::OwnedReadHalf>, tokio::net::unix::OwnedWriteHalf), Box<dyn std::error::Error>> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+    
+    let stream = connect_daemon(fastn_home).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(fastn_p2p_client::version::encode_request(request)?.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    
+    // One response line says whether the peer accepted the stream
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut response_line = String::new();
+    if reader.read_line(&mut response_line).await? == 0 {
+        return Err("Daemon closed connection without response".into());
+    }
+    let response: serde_json::Value = serde_json::from_str(response_line.trim())?;
+    ensure_success(&response)?;
+    Ok((reader, writer))
+}
@@ -0,0 +1,160 @@
+//! Clipboard sharing commands (`fastn-p2p clip send` / `clip recv`)
+//!
+//! Clip contents are read from stdin and written to stdout as raw bytes, so
+//! they compose with platform tools like `pbcopy`/`pbpaste` or `wl-copy`/`wl-paste`.
+//! Status messages go to stderr to keep stdout clean.
+
+use std::path::PathBuf;
+
+/// Send stdin to a peer's clipboard via the daemon
+pub async fn send(
+    fastn_home: PathBuf,
+    peer_id52: String,
+    bind_alias: String,
+    as_identity: Option<String>,
+    mime_type: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let from_identity = crate::cli::identity::resolve_identity(&fastn_home, as_identity).await?;
+    let to_peer: fastn_id52::PublicKey = peer_id52.parse()
+        .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+
+    let mut contents = Vec::new();
+    tokio::io::AsyncReadExt::read_to_end(&mut tokio::io::stdin(), &mut contents).await?;
+    if contents.is_empty() {
+        return Err("No clipboard contents provided on stdin".into());
+    }
+
+    // Text unless told otherwise or the bytes are not UTF-8
+    let mime_type = mime_type.unwrap_or_else(|| {
+        if std::str::from_utf8(&contents).is_ok() {
+            "text/plain;charset=utf-8".to_string()
+        } else {
+            "application/octet-stream".to_string()
+        }
+    });
+
+    eprintln!("📋 Sending {} byte clip ({}) from {} to {}", contents.len(), mime_type, from_identity, to_peer.id52());
+
+    let clip = fastn_p2p::server::clipboard::Clip::from_bytes(mime_type, &contents);
+    let request = push_request(from_identity, to_peer, bind_alias, &clip)?;
+    let response = crate::cli::client::send_daemon_request(&fastn_home, &request).await?;
+
+    crate::cli::client::ensure_success(&response)?;
+
+    eprintln!("✅ Clip sent: {}", response["data"]["p2p_response"]);
+    crate::cli::output::result(&response["data"]["p2p_response"]);
+    Ok(())
+}
+
+/// The daemon call pushing `clip` to `to_peer`'s clipboard binding `bind_alias`
+fn push_request(
+    from_identity: String,
+    to_peer: fastn_id52::PublicKey,
+    bind_alias: String,
+    clip: &fastn_p2p::server::clipboard::Clip,
+) -> Result<fastn_p2p_client::DaemonRequest<serde_json::Value>, serde_json::Error> {
+    Ok(fastn_p2p_client::DaemonRequest::Call {
+        from_identity: Some(from_identity),
+        to_peer,
+        protocol: fastn_p2p::server::clipboard::CLIPBOARD_PROTOCOL.to_string(),
+        bind_alias,
+        command: Some(fastn_p2p::server::clipboard::PUSH_COMMAND.to_string()),
+        args: Vec::new(),
+        request: fastn_p2p_client::Sensitive(serde_json::to_value(clip)?),
+        metadata: Default::default(),
+        traceparent: Some(fastn_net::TraceContext::new_root().traceparent()),
+        path: Default::default(),
+    })
+}
+
+/// Write the latest clip received by an identity to stdout (or `output`)
+pub async fn recv(
+    fastn_home: PathBuf,
+    bind_alias: String,
+    as_identity: Option<String>,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity = crate::cli::identity::resolve_identity(&fastn_home, as_identity).await?;
+    let identities_dir = fastn_home.join("identities");
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
+        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
+
+    let binding = identity_config.protocols.iter()
+        .find(|p| p.protocol == fastn_p2p::server::clipboard::CLIPBOARD_PROTOCOL && p.bind_alias == bind_alias)
+        .ok_or_else(|| format!(
+            "Identity '{}' has no {} binding '{}'. Add one with: fastn-p2p add-protocol {} --protocol {} --config '{{\"allowed_peers\": [\"<peer>\"]}}'",
+            identity, fastn_p2p::server::clipboard::CLIPBOARD_PROTOCOL, bind_alias,
+            identity, fastn_p2p::server::clipboard::CLIPBOARD_PROTOCOL,
+        ))?;
+
+    let received = fastn_p2p::server::clipboard::latest(&binding.config_path).await?
+        .ok_or_else(|| format!("No clip received yet for identity '{}'", identity))?;
+    let contents = received.clip.bytes()?;
+
+    eprintln!("📋 {} byte clip ({}) from {} at {}",
+             contents.len(), received.clip.mime_type, received.from, received.received_at);
+
+    match output {
+        Some(path) => {
+            tokio::fs::write(&path, &contents).await?;
+            eprintln!("💾 Saved clip to: {}", path.display());
+        }
+        // The clip as stored, contents included, instead of raw bytes
+        None if crate::cli::output::format() == crate::cli::output::Format::Json => {
+            crate::cli::output::result(&received);
+        }
+        None => {
+            use tokio::io::AsyncWriteExt;
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&contents).await?;
+            stdout.flush().await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sent_clips_reach_the_push_handler() {
+        use fastn_p2p::server::clipboard;
+
+        let temp = tempfile::tempdir().unwrap();
+        let protocol_dir = temp.path().join("protocols").join(clipboard::CLIPBOARD_PROTOCOL).join("default");
+        tokio::fs::create_dir_all(&protocol_dir).await.unwrap();
+        let sender = fastn_id52::SecretKey::generate().public_key();
+        let config = clipboard::ClipboardConfig { allowed_peers: vec![sender.id52()], ..Default::default() };
+        tokio::fs::write(protocol_dir.join(fastn_p2p::server::subprocess::CONFIG_FILE), serde_json::to_string(&config).unwrap())
+            .await
+            .unwrap();
+
+        let clip = clipboard::Clip::from_bytes("text/plain;charset=utf-8", b"hello");
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let request = serde_json::to_value(push_request("alice".to_string(), peer, "default".to_string(), &clip).unwrap()).unwrap();
+
+        // The daemon addresses the command the request names on the peer's serve_all
+        let server = fastn_p2p::serve_all().protocol(clipboard::CLIPBOARD_PROTOCOL, clipboard::register);
+        server
+            .dispatch_request(
+                &sender,
+                "bob",
+                &fastn_p2p::server::CommandProtocol::new(
+                    request["protocol"].as_str().unwrap(),
+                    request["bind_alias"].as_str().unwrap(),
+                    request["command"].as_str().unwrap(),
+                ),
+                &protocol_dir,
+                request["request"].clone(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let received = clipboard::latest(&protocol_dir).await.unwrap().unwrap();
+        assert_eq!(received.from, sender.id52());
+        assert_eq!(received.clip, clip);
+    }
+}
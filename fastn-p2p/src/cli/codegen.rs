@@ -0,0 +1,48 @@
+//! Codegen command: typed client stubs from a protocol description
+//!
+//! The description comes from a peer (see [`crate::cli::describe`]) or from
+//! an OpenRPC document saved earlier; [`fastn_p2p::codegen`] does the rest.
+
+use std::path::PathBuf;
+
+/// Generate `lang` stubs for `protocol` and write them to `output` or stdout
+pub async fn codegen(
+    fastn_home: PathBuf,
+    protocol: Option<String>,
+    lang: String,
+    peer: Option<String>,
+    schema: Option<PathBuf>,
+    output: Option<PathBuf>,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let lang: fastn_p2p::codegen::Lang = lang.parse()?;
+
+    let document = match (schema, peer) {
+        (Some(schema), _) => {
+            let contents = tokio::fs::read_to_string(&schema).await
+                .map_err(|e| format!("Failed to read {}: {}", schema.display(), e))?;
+            serde_json::from_str(&contents)
+                .map_err(|e| format!("Invalid OpenRPC document {}: {}", schema.display(), e))?
+        }
+        (None, Some(peer_id52)) => {
+            let to_peer: fastn_id52::PublicKey = peer_id52.parse()
+                .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+            eprintln!("🔎 Describing {} on {}", protocol.as_deref().unwrap_or("all protocols"), to_peer.id52());
+            crate::cli::describe::fetch_description(&fastn_home, to_peer, protocol.clone(), as_identity).await?
+        }
+        (None, None) => return Err(crate::cli::output::UsageError::boxed("Either --peer or --schema is required")),
+    };
+
+    let code = fastn_p2p::codegen::generate(&document, protocol.as_deref(), lang)?;
+    match output {
+        Some(output) => {
+            tokio::fs::write(&output, code).await?;
+            eprintln!("✅ Wrote client stubs to {}", output.display());
+        }
+        None => match crate::cli::output::format() {
+            crate::cli::output::Format::Text => print!("{code}"),
+            crate::cli::output::Format::Json => crate::cli::output::result(serde_json::json!({ "code": code })),
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! Per-peer circuit breakers for outgoing calls and streams
+//!
+//! Each peer the daemon talks to has a circuit. While it is closed, calls go
+//! through and their outcomes are counted over a window; once enough of them
+//! fail (see [`fastn_p2p::client::CallError::is_peer_failure`]) the circuit
+//! opens and calls are refused right away instead of piling onto a peer that
+//! is overloaded or flapping. After the cooldown one probe is let through
+//! (half-open): its success closes the circuit, its failure opens it again.
+//!
+//! Thresholds come from the `[circuit_breaker]` section of `config.toml`,
+//! and `fastn-p2p status --peers` shows every circuit.
+
+use std::time::{Duration, Instant};
+
+/// `[circuit_breaker]` in `config.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakerConfig {
+    /// `false` lets every call through
+    pub enabled: bool,
+    /// Share of failed calls in the window that opens the circuit
+    pub failure_rate: f64,
+    /// Calls in the window before the failure rate counts
+    pub min_calls: u32,
+    pub window_secs: u64,
+    /// How long an open circuit refuses calls before probing the peer
+    pub cooldown_secs: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failure_rate: 0.5,
+            min_calls: 5,
+            window_secs: 60,
+            cooldown_secs: 30,
+        }
+    }
+}
+
+// Shared with clients reading `peer-status`
+pub use fastn_p2p_client::admin::{CircuitState, PeerCircuit};
+
+/// A call refused because the peer's circuit is open
+#[derive(Debug, thiserror::Error)]
+#[error("Circuit open for {peer} after repeated failures, retrying in {retry_in_secs}s")]
+pub struct CircuitOpen {
+    pub peer: String,
+    pub retry_in_secs: u64,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    window_start: Instant,
+    calls: u32,
+    failures: u32,
+    /// When the circuit opened, or when the half-open probe went out
+    since: Instant,
+}
+
+impl Circuit {
+    fn closed(now: Instant) -> Self {
+        Self { state: CircuitState::Closed, window_start: now, calls: 0, failures: 0, since: now }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.since = now;
+    }
+}
+
+/// Circuits of every peer the daemon called
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    config: BreakerConfig,
+    circuits: std::sync::Mutex<std::collections::HashMap<fastn_id52::PublicKey, Circuit>>,
+}
+
+static BREAKERS: std::sync::OnceLock<CircuitBreakers> = std::sync::OnceLock::new();
+
+/// Set the thresholds; only the first call (at daemon start) has an effect
+pub fn init(config: BreakerConfig) {
+    let _ = BREAKERS.set(CircuitBreakers::new(config));
+}
+
+/// The daemon's circuit breakers, with default thresholds if [`init`] wasn't called
+pub fn global() -> &'static CircuitBreakers {
+    BREAKERS.get_or_init(|| CircuitBreakers::new(BreakerConfig::default()))
+}
+
+impl CircuitBreakers {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self { config, circuits: Default::default() }
+    }
+
+    /// Whether a call to `peer` may go out now; record its outcome with [`Self::record`]
+    pub fn check(&self, peer: &fastn_id52::PublicKey) -> Result<(), CircuitOpen> {
+        self.check_at(peer, Instant::now())
+    }
+
+    /// Count the outcome of a call [`Self::check`] let through
+    pub fn record(&self, peer: &fastn_id52::PublicKey, error: Option<&fastn_p2p::client::CallError>) {
+        let failed = error.is_some_and(fastn_p2p::client::CallError::is_peer_failure);
+        self.record_at(peer, !failed, Instant::now());
+    }
+
+    /// Every circuit, ordered by peer
+    pub fn snapshot(&self) -> Vec<PeerCircuit> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().expect("circuit breaker lock poisoned");
+        let mut peers: Vec<PeerCircuit> = circuits
+            .iter()
+            .map(|(peer, circuit)| PeerCircuit {
+                peer: peer.id52(),
+                state: circuit.state,
+                calls: circuit.calls,
+                failures: circuit.failures,
+                retry_in_secs: match circuit.state {
+                    CircuitState::Closed => None,
+                    _ => Some(self.retry_in(circuit, now).as_secs()),
+                },
+            })
+            .collect();
+        peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+        peers
+    }
+
+    fn check_at(&self, peer: &fastn_id52::PublicKey, now: Instant) -> Result<(), CircuitOpen> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let mut circuits = self.circuits.lock().expect("circuit breaker lock poisoned");
+        let Some(circuit) = circuits.get_mut(peer) else { return Ok(()) };
+        match circuit.state {
+            CircuitState::Closed => Ok(()),
+            // Open: the cooldown is over. Half-open: a probe whose outcome never
+            // came back doesn't block the peer for good
+            _ if self.retry_in(circuit, now).is_zero() => {
+                circuit.state = CircuitState::HalfOpen;
+                circuit.since = now;
+                Ok(())
+            }
+            _ => Err(CircuitOpen { peer: peer.id52(), retry_in_secs: self.retry_in(circuit, now).as_secs().max(1) }),
+        }
+    }
+
+    fn record_at(&self, peer: &fastn_id52::PublicKey, success: bool, now: Instant) {
+        if !self.config.enabled {
+            return;
+        }
+        let mut circuits = self.circuits.lock().expect("circuit breaker lock poisoned");
+        let circuit = circuits.entry(*peer).or_insert_with(|| Circuit::closed(now));
+        match circuit.state {
+            CircuitState::HalfOpen if success => *circuit = Circuit::closed(now),
+            CircuitState::HalfOpen => circuit.open(now),
+            // Calls that went out before the circuit opened don't change it
+            CircuitState::Open => {}
+            CircuitState::Closed => {
+                if now.duration_since(circuit.window_start) >= Duration::from_secs(self.config.window_secs) {
+                    *circuit = Circuit::closed(now);
+                }
+                circuit.calls += 1;
+                if !success {
+                    circuit.failures += 1;
+                }
+                if circuit.calls >= self.config.min_calls
+                    && f64::from(circuit.failures) >= self.config.failure_rate * f64::from(circuit.calls)
+                {
+                    circuit.open(now);
+                }
+            }
+        }
+    }
+
+    fn retry_in(&self, circuit: &Circuit, now: Instant) -> Duration {
+        (circuit.since + Duration::from_secs(self.config.cooldown_secs)).saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_opens_probes_and_closes() {
+        let breakers = CircuitBreakers::new(BreakerConfig { min_calls: 4, ..Default::default() });
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let start = Instant::now();
+
+        // Below min_calls the failure rate doesn't count yet
+        for _ in 0..3 {
+            breakers.record_at(&peer, false, start);
+        }
+        assert!(breakers.check_at(&peer, start).is_ok());
+        breakers.record_at(&peer, true, start);
+        let open = breakers.check_at(&peer, start).unwrap_err();
+        assert_eq!(open.retry_in_secs, 30);
+        assert_eq!(breakers.snapshot()[0].state, CircuitState::Open);
+
+        // One probe after the cooldown; a failed probe opens the circuit again
+        let later = start + Duration::from_secs(30);
+        assert!(breakers.check_at(&peer, later).is_ok());
+        assert!(breakers.check_at(&peer, later).is_err());
+        breakers.record_at(&peer, false, later);
+        assert!(breakers.check_at(&peer, later + Duration::from_secs(10)).is_err());
+
+        let even_later = later + Duration::from_secs(30);
+        assert!(breakers.check_at(&peer, even_later).is_ok());
+        breakers.record_at(&peer, true, even_later);
+        assert!(breakers.check_at(&peer, even_later).is_ok());
+        assert_eq!(breakers.snapshot()[0].state, CircuitState::Closed);
+        assert_eq!(breakers.snapshot()[0].calls, 0);
+    }
+
+    #[test]
+    fn test_window_resets_counts() {
+        let breakers = CircuitBreakers::new(BreakerConfig { min_calls: 2, ..Default::default() });
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let start = Instant::now();
+
+        breakers.record_at(&peer, false, start);
+        breakers.record_at(&peer, false, start + Duration::from_secs(61));
+        assert!(breakers.check_at(&peer, start + Duration::from_secs(61)).is_ok());
+        assert_eq!(breakers.snapshot()[0].failures, 1);
+
+        let disabled = CircuitBreakers::new(BreakerConfig { enabled: false, min_calls: 1, ..Default::default() });
+        disabled.record_at(&peer, false, start);
+        assert!(disabled.check_at(&peer, start).is_ok());
+    }
+}
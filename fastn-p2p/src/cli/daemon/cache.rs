@@ -0,0 +1,278 @@
+//! Cache of call results for protocols that are pure reads
+//!
+//! Servers say how long their answers may be kept, per protocol, with
+//! [`fastn_p2p::server::ServerBuilder::with_protocol_max_age`]; each answer
+//! carries it, see [`fastn_p2p::client::CallReply::max_age`]. Calls are
+//! answered from memory while an earlier answer to the same call is fresh.
+//! Two calls are the same when they go to the same peer, protocol and
+//! command with the same request hash, which covers the request body, the
+//! command's arguments, the ClientHello metadata, the bind alias and the
+//! sending identity. Only successful answers are kept; handler errors and
+//! failed calls always go to the peer, as do calls that pin a connection path.
+//!
+//! ```toml
+//! [cache]
+//! max_entries = 1024
+//! max_bytes = 16777216
+//!
+//! [cache.protocols]
+//! "web.fastn.com" = 300               # seconds an answer stays fresh
+//! "discovery.fastn.com lookup" = 60   # one serve_all command of a protocol
+//! "mail.fastn.com" = 0                # never cached, whatever the server says
+//! ```
+//!
+//! `[cache.protocols]` overrides the server's `max_age`, or sets one for
+//! servers that send none; answers with neither aren't cached. The oldest
+//! entries are evicted once either limit is reached, and `fastn-p2p cache
+//! clear` empties the cache.
+
+use std::time::{Duration, Instant};
+
+/// `[cache]` in `config.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub max_entries: usize,
+    /// Total size of cached answers, as JSON
+    pub max_bytes: u64,
+    /// Seconds answers stay fresh, by `protocol` or `protocol command`,
+    /// overriding the server's `max_age`
+    pub protocols: std::collections::BTreeMap<String, u64>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1024,
+            max_bytes: 16 << 20,
+            protocols: Default::default(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// How long `config.toml` keeps answers of `command` on `protocol`; `None` if it doesn't say
+    ///
+    /// An entry for the command wins over one for the whole protocol.
+    pub fn ttl(&self, protocol: &str, command: Option<&str>) -> Option<Duration> {
+        command
+            .and_then(|command| self.protocols.get(&format!("{} {}", protocol, command)))
+            .or_else(|| self.protocols.get(protocol))
+            .map(|secs| Duration::from_secs(*secs))
+    }
+}
+
+/// What a call is looked up by
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub peer: fastn_id52::PublicKey,
+    pub protocol: String,
+    pub command: Option<String>,
+    /// BLAKE3 of everything else that can change the answer
+    pub request_hash: String,
+}
+
+impl CacheKey {
+    pub(super) fn new(peer: fastn_id52::PublicKey, from_identity: &str, call: &super::control::PeerRequest) -> Self {
+        let hashed = serde_json::json!([from_identity, call.bind_alias, call.args, call.request, call.metadata]);
+        Self {
+            peer,
+            protocol: call.protocol.clone(),
+            command: call.command.clone(),
+            request_hash: blake3::hash(hashed.to_string().as_bytes()).to_hex().to_string(),
+        }
+    }
+}
+
+/// Cache hint sent to the client with each answer of a cached protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct CacheHint {
+    /// Answered from the cache without calling the peer
+    pub hit: bool,
+    /// Seconds since the peer gave this answer
+    pub age_secs: u64,
+    /// Seconds the answer stays fresh in total
+    pub max_age_secs: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: serde_json::Value,
+    size: u64,
+    stored_at: Instant,
+    ttl: Duration,
+}
+
+/// Fresh answers of cached protocols
+#[derive(Debug)]
+pub struct CallCache {
+    config: CacheConfig,
+    entries: std::sync::Mutex<std::collections::HashMap<CacheKey, Entry>>,
+}
+
+static CACHE: std::sync::OnceLock<CallCache> = std::sync::OnceLock::new();
+
+/// Set what is cached; only the first call (at daemon start) has an effect
+pub fn init(config: CacheConfig) {
+    let _ = CACHE.set(CallCache::new(config));
+}
+
+/// The daemon's call cache, caching nothing if [`init`] wasn't called
+pub fn global() -> &'static CallCache {
+    CACHE.get_or_init(|| CallCache::new(CacheConfig::default()))
+}
+
+impl CallCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self { config, entries: Default::default() }
+    }
+
+    /// How long to keep an answer of `command` on `protocol`; `None` if it isn't kept
+    ///
+    /// `[cache.protocols]` wins over the `max_age` the server sent with it.
+    pub fn ttl(&self, protocol: &str, command: Option<&str>, max_age: Option<Duration>) -> Option<Duration> {
+        self.config.ttl(protocol, command).or(max_age).filter(|ttl| !ttl.is_zero())
+    }
+
+    /// The fresh answer to `key`, if there is one
+    pub fn get(&self, key: &CacheKey) -> Option<(serde_json::Value, CacheHint)> {
+        self.get_at(key, Instant::now())
+    }
+
+    /// Keep `response` for `ttl`; returns the hint for the answer just stored
+    pub fn insert(&self, key: CacheKey, response: &serde_json::Value, ttl: Duration) -> CacheHint {
+        self.insert_at(key, response, ttl, Instant::now())
+    }
+
+    /// Drop cached answers, all or those of one peer and/or protocol; returns how many
+    pub fn clear(&self, peer: Option<&fastn_id52::PublicKey>, protocol: Option<&str>) -> usize {
+        let mut entries = self.entries.lock().expect("call cache lock poisoned");
+        let before = entries.len();
+        entries.retain(|key, _| {
+            !(peer.is_none_or(|peer| key.peer == *peer) && protocol.is_none_or(|protocol| key.protocol == protocol))
+        });
+        before - entries.len()
+    }
+
+    fn get_at(&self, key: &CacheKey, now: Instant) -> Option<(serde_json::Value, CacheHint)> {
+        let mut entries = self.entries.lock().expect("call cache lock poisoned");
+        let entry = entries.get(key)?;
+        let age = now.saturating_duration_since(entry.stored_at);
+        if age >= entry.ttl {
+            entries.remove(key);
+            return None;
+        }
+        let hint = CacheHint { hit: true, age_secs: age.as_secs(), max_age_secs: entry.ttl.as_secs() };
+        Some((entry.response.clone(), hint))
+    }
+
+    fn insert_at(&self, key: CacheKey, response: &serde_json::Value, ttl: Duration, now: Instant) -> CacheHint {
+        let hint = CacheHint { hit: false, age_secs: 0, max_age_secs: ttl.as_secs() };
+        let size = response.to_string().len() as u64;
+        if size > self.config.max_bytes || self.config.max_entries == 0 {
+            return hint;
+        }
+
+        let mut entries = self.entries.lock().expect("call cache lock poisoned");
+        entries.retain(|_, entry| now.saturating_duration_since(entry.stored_at) < entry.ttl);
+        entries.remove(&key);
+        let mut used: u64 = entries.values().map(|entry| entry.size).sum();
+        while entries.len() >= self.config.max_entries || used + size > self.config.max_bytes {
+            let Some(oldest) = entries.iter().min_by_key(|(_, entry)| entry.stored_at).map(|(key, _)| key.clone()) else {
+                break;
+            };
+            used -= entries.remove(&oldest).map_or(0, |entry| entry.size);
+        }
+        entries.insert(key, Entry { response: response.clone(), size, stored_at: now, ttl });
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(request: serde_json::Value) -> super::super::control::PeerRequest {
+        super::super::control::PeerRequest {
+            protocol: "web.fastn.com".to_string(),
+            bind_alias: "default".to_string(),
+            command: None,
+            args: Vec::new(),
+            request,
+            metadata: Default::default(),
+        }
+    }
+
+    fn key(peer: fastn_id52::PublicKey, request: serde_json::Value) -> CacheKey {
+        CacheKey::new(peer, "alice", &call(request))
+    }
+
+    #[test]
+    fn test_fresh_answers_are_served_until_expired() {
+        let cache = CallCache::new(CacheConfig::default());
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let start = Instant::now();
+        let ttl = Duration::from_secs(60);
+
+        let stored = cache.insert_at(key(peer, serde_json::json!({"path": "/"})), &serde_json::json!("index"), ttl, start);
+        assert_eq!(stored, CacheHint { hit: false, age_secs: 0, max_age_secs: 60 });
+
+        let (response, hint) = cache.get_at(&key(peer, serde_json::json!({"path": "/"})), start + Duration::from_secs(10)).unwrap();
+        assert_eq!(response, serde_json::json!("index"));
+        assert_eq!(hint, CacheHint { hit: true, age_secs: 10, max_age_secs: 60 });
+
+        // Another request, or the same one as another identity, is another call
+        assert!(cache.get_at(&key(peer, serde_json::json!({"path": "/about"})), start).is_none());
+        let as_bob = CacheKey::new(peer, "bob", &call(serde_json::json!({"path": "/"})));
+        assert!(cache.get_at(&as_bob, start).is_none());
+        let metadata = std::collections::BTreeMap::from([("lang".to_string(), "fr".to_string())]);
+        let in_french = CacheKey::new(peer, "alice", &super::super::control::PeerRequest { metadata, ..call(serde_json::json!({"path": "/"})) });
+        assert!(cache.get_at(&in_french, start).is_none());
+
+        assert!(cache.get_at(&key(peer, serde_json::json!({"path": "/"})), start + ttl).is_none());
+        assert_eq!(cache.clear(None, None), 0);
+    }
+
+    #[test]
+    fn test_limits_evict_oldest() {
+        let cache = CallCache::new(CacheConfig { max_entries: 2, max_bytes: 16, ..Default::default() });
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let start = Instant::now();
+        let ttl = Duration::from_secs(60);
+
+        for (i, at) in [0, 1, 2].into_iter().enumerate() {
+            cache.insert_at(key(peer, serde_json::json!(i)), &serde_json::json!("abc"), ttl, start + Duration::from_secs(at));
+        }
+        assert!(cache.get_at(&key(peer, serde_json::json!(0)), start).is_none());
+        assert!(cache.get_at(&key(peer, serde_json::json!(2)), start).is_some());
+
+        // Over max_bytes on its own: not cached, nothing evicted
+        cache.insert_at(key(peer, serde_json::json!(3)), &serde_json::json!("much too long to cache"), ttl, start);
+        assert!(cache.get_at(&key(peer, serde_json::json!(3)), start).is_none());
+        assert_eq!(cache.clear(Some(&peer), Some("web.fastn.com")), 2);
+    }
+
+    #[test]
+    fn test_ttl_by_protocol_and_command() {
+        let config = CacheConfig {
+            protocols: [("web.fastn.com".to_string(), 300), ("web.fastn.com meta".to_string(), 60)].into(),
+            ..Default::default()
+        };
+        assert_eq!(config.ttl("web.fastn.com", Some("meta")), Some(Duration::from_secs(60)));
+        assert_eq!(config.ttl("web.fastn.com", Some("get")), Some(Duration::from_secs(300)));
+        assert_eq!(config.ttl("mail.fastn.com", None), None);
+    }
+
+    #[test]
+    fn test_config_overrides_server_max_age() {
+        let cache = CallCache::new(CacheConfig {
+            protocols: [("web.fastn.com".to_string(), 300), ("mail.fastn.com".to_string(), 0)].into(),
+            ..Default::default()
+        });
+        let max_age = Some(Duration::from_secs(60));
+        assert_eq!(cache.ttl("discovery.fastn.com", None, max_age), max_age);
+        assert_eq!(cache.ttl("discovery.fastn.com", None, None), None);
+        assert_eq!(cache.ttl("web.fastn.com", None, max_age), Some(Duration::from_secs(300)));
+        assert_eq!(cache.ttl("mail.fastn.com", None, max_age), None);
+    }
+}
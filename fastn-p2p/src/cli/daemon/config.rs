@@ -0,0 +1,122 @@
+//! Daemon settings from `FASTN_HOME/config.toml`
+//!
+//! The file is optional and every section falls back to its defaults:
+//!
+//! ```toml
+//! [circuit_breaker]
+//! failure_rate = 0.5
+//! min_calls = 5
+//! window_secs = 60
+//! cooldown_secs = 30
+//!
+//! [scheduler]
+//! max_in_flight = 64
+//! max_queued_per_client = 32
+//! max_queued = 256
+//!
+//! [cache.protocols]
+//! "web.fastn.com" = 300
+//!
+//! [metrics]
+//! listen = "127.0.0.1:9464"
+//!
+//! [endpoints]
+//! max_endpoints = 16
+//! lazy = false
+//! idle_stop_secs = 0
+//!
+//! [network]
+//! poll_secs = 5
+//!
+//! [usage]
+//! flush_secs = 60
+//! keep_days = 90
+//!
+//! [reputation]
+//! strike_threshold = 10
+//! ban_secs = 600
+//! decay_secs = 300
+//!
+//! [update]
+//! channel = "https://fastn.com/p2p/stable.json"
+//! check_hours = 24
+//! ```
+
+use std::path::{Path, PathBuf};
+
+/// Everything `config.toml` can set
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Backing off from failing peers, see [`super::breaker`]
+    pub circuit_breaker: super::breaker::BreakerConfig,
+    /// Sharing outgoing call slots between clients, see [`super::scheduler`]
+    pub scheduler: super::scheduler::SchedulerConfig,
+    /// Answers of read-only protocols kept for a while, see [`super::cache`]
+    pub cache: super::cache::CacheConfig,
+    /// Prometheus endpoint for handler latencies, see [`super::metrics`]
+    pub metrics: super::metrics::MetricsConfig,
+    /// How online identities map to endpoints, see [`super::endpoints`]
+    pub endpoints: super::endpoints::EndpointsConfig,
+    /// Rebinding endpoints when the host changes networks, see [`super::network`]
+    pub network: super::network::NetworkConfig,
+    /// Writing usage counts to `state.db`, see [`super::usage`]
+    pub usage: super::usage::UsageConfig,
+    /// Read by `serve_all` hosts, not the daemon, see [`fastn_p2p::server::reputation`]
+    pub reputation: fastn_p2p::server::reputation::ReputationSettings,
+    /// Looking for new releases, see [`super::version`]
+    pub update: super::version::UpdateConfig,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+}
+
+impl DaemonConfig {
+    /// Load `config.toml` from FASTN_HOME; defaults when there is none
+    pub async fn load(fastn_home: &Path) -> Result<Self, ConfigError> {
+        let path = fastn_home.join("config.toml");
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => return Err(ConfigError::Io { path, source }),
+        };
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse { path, source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        tokio::fs::create_dir_all(&fastn_home).await.unwrap();
+
+        let config = DaemonConfig::load(&fastn_home).await.unwrap();
+        assert_eq!(config.circuit_breaker.min_calls, super::super::breaker::BreakerConfig::default().min_calls);
+
+        tokio::fs::write(fastn_home.join("config.toml"), "[circuit_breaker]\ncooldown_secs = 5\n").await.unwrap();
+        let config = DaemonConfig::load(&fastn_home).await.unwrap();
+        assert_eq!(config.circuit_breaker.cooldown_secs, 5);
+        assert_eq!(config.circuit_breaker.window_secs, 60);
+
+        tokio::fs::write(fastn_home.join("config.toml"), "[circuit_breakr]\n").await.unwrap();
+        assert!(matches!(DaemonConfig::load(&fastn_home).await, Err(ConfigError::Parse { .. })));
+    }
+}
@@ -3,7 +3,7 @@
 //! This module handles the Unix domain socket that clients connect to.
 //! It parses JSON requests and coordinates with the P2P layer.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::sync::broadcast;
 use tokio::net::UnixListener;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -11,28 +11,113 @@ use serde::{Deserialize, Serialize};
 
 use super::{DaemonCommand, DaemonResponse};
 
+/// Calls of one group call in flight at once
+const GROUP_CONCURRENCY: usize = 8;
+
+/// Longest request line read from a client, the same as a P2P frame
+const MAX_REQUEST_LINE: usize = fastn_net::MAX_FRAME_LEN;
+
 /// Client request types - precise typing for each operation
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientRequest {
     #[serde(rename = "call")]
     Call {
-        from_identity: String,
+        /// Identity to send as; the daemon picks the default when absent
+        #[serde(default)]
+        from_identity: Option<String>,
         to_peer: fastn_id52::PublicKey,
         protocol: String,
         bind_alias: String,
-        request: serde_json::Value,
+        /// serve_all command to address, sent to the peer as a `CommandProtocol`
+        #[serde(default)]
+        command: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+        request: fastn_p2p_client::Sensitive<serde_json::Value>,
+        /// Headers added by client interceptors
+        #[serde(default)]
+        metadata: std::collections::BTreeMap<String, String>,
+        /// W3C trace context of the caller, continued on the call to the peer
+        #[serde(default)]
+        traceparent: Option<String>,
+        /// Network paths the call may take; the answer says which one it took
+        #[serde(default)]
+        path: fastn_p2p::client::PathPreference,
+    },
+    /// A call to every member of a peer group of the sending identity
+    #[serde(rename = "group-call")]
+    GroupCall {
+        #[serde(default)]
+        from_identity: Option<String>,
+        group: String,
+        protocol: String,
+        bind_alias: String,
+        #[serde(default)]
+        command: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+        request: fastn_p2p_client::Sensitive<serde_json::Value>,
+        #[serde(default)]
+        metadata: std::collections::BTreeMap<String, String>,
+        #[serde(default)]
+        traceparent: Option<String>,
     },
     #[serde(rename = "stream")]
     Stream {
-        from_identity: String,
+        #[serde(default)]
+        from_identity: Option<String>,
+        to_peer: fastn_id52::PublicKey,
+        protocol: String,
+        bind_alias: String,
+        #[serde(default)]
+        command: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+        initial_data: fastn_p2p_client::Sensitive<serde_json::Value>,
+        #[serde(default)]
+        metadata: std::collections::BTreeMap<String, String>,
+        #[serde(default)]
+        traceparent: Option<String>,
+    },
+    /// One ping round trip to a peer, see [`fastn_p2p::client::Client::ping`]
+    #[serde(rename = "ping")]
+    Ping {
+        #[serde(default)]
+        from_identity: Option<String>,
+        to_peer: fastn_id52::PublicKey,
+    },
+    /// Queue a message for delivery until the peer confirms it, see [`super::outbox`]
+    #[serde(rename = "send")]
+    Send {
+        #[serde(default)]
+        from_identity: Option<String>,
         to_peer: fastn_id52::PublicKey,
         protocol: String,
         bind_alias: String,
-        initial_data: serde_json::Value,
+        #[serde(default)]
+        command: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+        message: fastn_p2p_client::Sensitive<serde_json::Value>,
+    },
+    /// Where a message queued with `send` is
+    #[serde(rename = "delivery-status")]
+    DeliveryStatus {
+        #[serde(default)]
+        from_identity: Option<String>,
+        message_id: String,
     },
     #[serde(rename = "reload-identities")]
     ReloadIdentities,
+    #[serde(rename = "create-identity")]
+    CreateIdentity {
+        identity: String,
+        #[serde(default)]
+        secret_key: Option<fastn_id52::SecretKey>,
+    },
+    #[serde(rename = "list-identities")]
+    ListIdentities,
     #[serde(rename = "set-identity-state")]
     SetIdentityState {
         identity: String,
@@ -43,7 +128,7 @@ pub enum ClientRequest {
         identity: String,
         protocol: String,
         bind_alias: String,
-        config: serde_json::Value,
+        config: fastn_p2p_client::Sensitive<serde_json::Value>,
     },
     #[serde(rename = "remove-protocol")]
     RemoveProtocol {
@@ -51,6 +136,43 @@ pub enum ClientRequest {
         protocol: String,
         bind_alias: String,
     },
+    #[serde(rename = "peer-status")]
+    PeerStatus,
+    #[serde(rename = "protocol-metrics")]
+    ProtocolMetrics,
+    #[serde(rename = "endpoint-status")]
+    EndpointStatus,
+    #[serde(rename = "clear-cache")]
+    ClearCache {
+        #[serde(default)]
+        peer: Option<fastn_id52::PublicKey>,
+        #[serde(default)]
+        protocol: Option<String>,
+    },
+    #[serde(rename = "list-approvals")]
+    ListApprovals,
+    #[serde(rename = "resolve-approval")]
+    ResolveApproval {
+        id: String,
+        approve: bool,
+    },
+    #[serde(rename = "drain")]
+    Drain {
+        timeout_ms: u64,
+    },
+    #[serde(rename = "version")]
+    Version,
+    #[serde(rename = "list-reputation")]
+    ListReputation,
+    #[serde(rename = "clear-reputation")]
+    ClearReputation {
+        peer: fastn_id52::PublicKey,
+        #[serde(default)]
+        identity: Option<fastn_id52::PublicKey>,
+    },
+    /// Follow daemon events, one JSON line each, until the client disconnects
+    #[serde(rename = "subscribe-events")]
+    SubscribeEvents,
 }
 
 /// JSON response format to clients
@@ -62,12 +184,30 @@ struct ClientResponse {
     data: serde_json::Value,
 }
 
+/// What a call or stream asks of the peer, whoever it goes to
+#[derive(Debug, Clone)]
+pub(super) struct PeerRequest {
+    pub(super) protocol: String,
+    pub(super) bind_alias: String,
+    pub(super) command: Option<String>,
+    pub(super) args: Vec<String>,
+    /// The call's request, or the stream's initial data
+    pub(super) request: serde_json::Value,
+    pub(super) metadata: std::collections::BTreeMap<String, String>,
+}
+
+/// What every request line carries besides its `type`, see [`fastn_p2p_client::version`]
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    /// The request's `type`, all that is logged of it
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    wire_version: Option<u32>,
+}
+
 /// Run the control socket server
-pub async fn run(
-    fastn_home: PathBuf,
-    command_tx: broadcast::Sender<DaemonCommand>,
-    mut response_rx: broadcast::Receiver<DaemonResponse>,
-) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn run(fastn_home: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
     let socket_path = fastn_home.join("control.sock");
     
     // Remove existing socket if it exists
@@ -76,14 +216,7 @@ pub async fn run(
     }
 
     let listener = UnixListener::bind(&socket_path)?;
-    println!("🎧 Control socket listening on: {}", socket_path.display());
-
-    // Start response dispatcher task to handle P2P responses
-    let _response_task = tokio::spawn(async move {
-        while let Ok(response) = response_rx.recv().await {
-            todo!("Route response back to appropriate client connection based on request ID");
-        }
-    });
+    tracing::info!(target: fastn_p2p::console::TARGET, "🎧 Control socket listening on: {}", socket_path.display());
 
     loop {
         match listener.accept().await {
@@ -91,12 +224,12 @@ pub async fn run(
                 let fastn_home_clone = fastn_home.clone();
                 tokio::spawn(async move {
                     if let Err(e) = handle_client(stream, fastn_home_clone).await {
-                        eprintln!("Error handling client: {}", e);
+                        tracing::warn!("Error handling client: {}", e);
                     }
                 });
             }
             Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
+                tracing::warn!("Error accepting connection: {}", e);
             }
         }
     }
@@ -106,16 +239,18 @@ async fn handle_client(
     stream: tokio::net::UnixStream,
     fastn_home: PathBuf,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    println!("📨 Client connected to control socket");
+    // Calls are scheduled fairly between client processes
+    let client = stream.peer_cred().ok().and_then(|cred| cred.pid());
+    tracing::info!(target: fastn_p2p::console::TARGET, "📨 Client connected to control socket (pid {})", client.map_or("unknown".to_string(), |pid| pid.to_string()));
     
     let (reader, writer) = stream.into_split();
     let mut buf_reader = BufReader::new(reader);
     let mut line = String::new();
 
     // Read the first line to get request header and determine routing
-    match buf_reader.read_line(&mut line).await {
+    match read_request_line(&mut buf_reader, &mut line, MAX_REQUEST_LINE).await {
         Ok(0) => {
-            println!("📤 Client disconnected immediately");
+            tracing::info!(target: fastn_p2p::console::TARGET, "📤 Client disconnected immediately");
             return Ok(());
         }
         Ok(_) => {
@@ -124,207 +259,1873 @@ async fn handle_client(
                 return Ok(());
             }
 
-            println!("📥 Client request: {}", request_json);
-
             // Parse request header to determine routing strategy
-            match route_client_request(&fastn_home, request_json, buf_reader, writer).await {
-                Ok(_) => println!("✅ Request handled successfully"),
-                Err(e) => eprintln!("❌ Request failed: {}", e),
+            match route_client_request(&fastn_home, client, request_json, buf_reader, writer).await {
+                Ok(_) => tracing::info!(target: fastn_p2p::console::TARGET, "✅ Request handled successfully"),
+                Err(e) => tracing::error!("Request failed: {}", e),
             }
         }
         Err(e) => {
-            eprintln!("Error reading client request: {}", e);
+            tracing::warn!("Error reading client request: {}", e);
         }
     }
 
     Ok(())
 }
 
+/// Read one line into `line`, failing once it grows past `limit` bytes
+///
+/// Returns 0 at end of input, like `read_line`, which on its own buffers a
+/// client that never sends a newline until the daemon runs out of memory.
+async fn read_request_line<R: tokio::io::AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut String,
+    limit: usize,
+) -> std::io::Result<usize> {
+    use tokio::io::AsyncReadExt;
+
+    let read = (&mut *reader).take(limit as u64 + 1).read_line(line).await?;
+    if read > limit && !line.ends_with('\n') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Request line exceeds {} bytes", limit),
+        ));
+    }
+    Ok(read)
+}
+
 /// Route client request based on type: P2P (call/stream) or control (daemon management)
 async fn route_client_request(
-    fastn_home: &PathBuf,
+    fastn_home: &Path,
+    client: super::scheduler::ClientId,
     request_json: &str,
     unix_reader: BufReader<tokio::net::unix::OwnedReadHalf>,
-    unix_writer: tokio::net::unix::OwnedWriteHalf,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Clients say which control protocol they speak; those from before negotiation spoke 1
+    let envelope: Envelope = serde_json::from_str(request_json)?;
+    // Request lines carry payloads and keys, so only their kind is logged
+    tracing::info!(target: fastn_p2p::console::TARGET, "📥 Client request: {}", envelope.kind.as_deref().unwrap_or("(untyped)"));
+    let wire_version = envelope.wire_version.unwrap_or(1);
+    if !fastn_p2p_client::version::is_supported(wire_version) {
+        tracing::warn!("Client speaks control protocol {}, refusing", wire_version);
+        return write_version_mismatch(&mut unix_writer, wire_version).await;
+    }
+
     // Parse the client request to determine routing
     let request: ClientRequest = serde_json::from_str(request_json)?;
     
+    // Relayed calls and streams count towards a drain, and none start once it began
+    let relays = matches!(
+        request,
+        ClientRequest::Call { .. } | ClientRequest::GroupCall { .. } | ClientRequest::Stream { .. }
+    );
+    let _in_flight = relays.then(fastn_p2p::server::drain::InFlight::begin);
+    if relays && fastn_p2p::is_draining() {
+        tracing::info!(target: fastn_p2p::console::TARGET, "🚰 Draining, refusing new calls and streams");
+        return write_error(&mut unix_writer, "draining", "Daemon is draining, retry after its restart".to_string()).await;
+    }
+    
     match request {
-        ClientRequest::Call { from_identity, to_peer, protocol, bind_alias, request } => {
-            println!("🔀 Routing P2P call: {} {} from {} to {}", 
-                    protocol, bind_alias, from_identity, to_peer.id52());
+        ClientRequest::Call { from_identity, to_peer, protocol, bind_alias, command, args, request, metadata, traceparent, path } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing P2P call: {} {} {} from {} to {}", 
+                    protocol, bind_alias, command.as_deref().unwrap_or("-"), from_identity.as_deref().unwrap_or("(default)"), to_peer.id52());
+            
+            // The daemon's hop is a child of the caller's span, or starts the trace
+            let trace = traceparent.as_deref()
+                .and_then(fastn_net::TraceContext::from_traceparent)
+                .map(|caller| caller.child())
+                .unwrap_or_else(fastn_net::TraceContext::new_root);
+            tracing::debug!(trace_id = %trace.trace_id, "Daemon hop of the call's trace");
+            
+            // Wait our turn behind other clients' calls; held until the call is done
+            let _permit = match super::scheduler::global().acquire(client).await {
+                Ok(permit) => permit,
+                Err(busy) => {
+                    tracing::info!(target: fastn_p2p::console::TARGET, "⏳ {}", busy);
+                    return write_error(&mut unix_writer, "busy", busy.to_string()).await;
+                }
+            };
             
             // P2P call routing using fastn_net connection pooling
-            handle_p2p_call(fastn_home.clone(), from_identity, to_peer, protocol, bind_alias, request, unix_writer).await
+            trace.scope(
+                handle_p2p_call(
+                    fastn_home.to_path_buf(),
+                    from_identity,
+                    to_peer,
+                    PeerRequest { protocol, bind_alias, command, args, request: request.into_inner(), metadata },
+                    path,
+                    unix_writer,
+                ),
+            ).await
         }
-        ClientRequest::Stream { from_identity, to_peer, protocol, bind_alias, initial_data } => {
-            println!("🔀 Routing P2P stream: {} {} from {} to {}", 
-                    protocol, bind_alias, from_identity, to_peer.id52());
+        ClientRequest::GroupCall { from_identity, group, protocol, bind_alias, command, args, request, metadata, traceparent } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing group call: {} {} {} from {} to group {}",
+                    protocol, bind_alias, command.as_deref().unwrap_or("-"), from_identity.as_deref().unwrap_or("(default)"), group);
+            
+            let trace = traceparent.as_deref()
+                .and_then(fastn_net::TraceContext::from_traceparent)
+                .map(|caller| caller.child())
+                .unwrap_or_else(fastn_net::TraceContext::new_root);
+            
+            // The whole group waits its turn once, as one call of this client
+            let _permit = match super::scheduler::global().acquire(client).await {
+                Ok(permit) => permit,
+                Err(busy) => {
+                    tracing::info!(target: fastn_p2p::console::TARGET, "⏳ {}", busy);
+                    return write_error(&mut unix_writer, "busy", busy.to_string()).await;
+                }
+            };
+            
+            trace.scope(
+                handle_group_call(
+                    fastn_home.to_path_buf(),
+                    from_identity,
+                    group,
+                    PeerRequest { protocol, bind_alias, command, args, request: request.into_inner(), metadata },
+                    unix_writer,
+                ),
+            ).await
+        }
+        ClientRequest::Stream { from_identity, to_peer, protocol, bind_alias, command, args, initial_data, metadata, traceparent } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing P2P stream: {} {} {} from {} to {}", 
+                    protocol, bind_alias, command.as_deref().unwrap_or("-"), from_identity.as_deref().unwrap_or("(default)"), to_peer.id52());
+            
+            let trace = traceparent.as_deref()
+                .and_then(fastn_net::TraceContext::from_traceparent)
+                .map(|caller| caller.child())
+                .unwrap_or_else(fastn_net::TraceContext::new_root);
             
             // P2P streaming routing with bidirectional piping
-            handle_p2p_stream(from_identity, to_peer, protocol, bind_alias, initial_data, unix_reader, unix_writer).await
+            trace.scope(
+                handle_p2p_stream(
+                    fastn_home.to_path_buf(),
+                    from_identity,
+                    to_peer,
+                    PeerRequest { protocol, bind_alias, command, args, request: initial_data.into_inner(), metadata },
+                    unix_reader,
+                    unix_writer,
+                ),
+            ).await
+        }
+        ClientRequest::Ping { from_identity, to_peer } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing ping from {} to {}", from_identity.as_deref().unwrap_or("(default)"), to_peer.id52());
+            handle_ping(fastn_home.to_path_buf(), from_identity, to_peer, unix_writer).await
+        }
+        ClientRequest::Send { from_identity, to_peer, protocol, bind_alias, command, args, message } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing send: {} {} {} from {} to {}",
+                    protocol, bind_alias, command.as_deref().unwrap_or("-"), from_identity.as_deref().unwrap_or("(default)"), to_peer.id52());
+            let peer_protocol = call_protocol(&protocol, &bind_alias, command.as_deref(), args)?;
+            handle_send(fastn_home.to_path_buf(), from_identity, to_peer, peer_protocol, message.into_inner(), unix_writer).await
+        }
+        ClientRequest::DeliveryStatus { from_identity, message_id } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing delivery status of {}", message_id);
+            handle_delivery_status(fastn_home.to_path_buf(), from_identity, message_id, unix_writer).await
         }
         // Control commands (non-P2P)
         ClientRequest::ReloadIdentities => {
-            println!("🔀 Routing control: reload identities");
-            handle_control_command("reload-identities", serde_json::Value::Null, unix_writer).await
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: reload identities");
+            handle_control_command(fastn_home, DaemonCommand::ReloadIdentities, unix_writer).await
+        }
+        ClientRequest::CreateIdentity { identity, secret_key } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: create identity {}", identity);
+            let command = DaemonCommand::CreateIdentity { identity, secret_key };
+            handle_control_command(fastn_home, command, unix_writer).await
+        }
+        ClientRequest::ListIdentities => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: list identities");
+            handle_list_identities(fastn_home, unix_writer).await
         }
         ClientRequest::SetIdentityState { identity, online } => {
-            println!("🔀 Routing control: set {} {}", identity, if online { "online" } else { "offline" });
-            let data = serde_json::json!({ "identity": identity, "online": online });
-            handle_control_command("set-identity-state", data, unix_writer).await
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: set {} {}", identity, if online { "online" } else { "offline" });
+            let command = DaemonCommand::SetIdentityState { identity, online };
+            handle_control_command(fastn_home, command, unix_writer).await
         }
         ClientRequest::AddProtocol { identity, protocol, bind_alias, config } => {
-            println!("🔀 Routing control: add protocol {} {} to {}", protocol, bind_alias, identity);
-            let data = serde_json::json!({ "identity": identity, "protocol": protocol, "bind_alias": bind_alias, "config": config });
-            handle_control_command("add-protocol", data, unix_writer).await
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: add protocol {} {} to {}", protocol, bind_alias, identity);
+            let command = DaemonCommand::AddProtocol { identity, protocol, bind_alias, config };
+            handle_control_command(fastn_home, command, unix_writer).await
         }
         ClientRequest::RemoveProtocol { identity, protocol, bind_alias } => {
-            println!("🔀 Routing control: remove protocol {} {} from {}", protocol, bind_alias, identity);
-            let data = serde_json::json!({ "identity": identity, "protocol": protocol, "bind_alias": bind_alias });
-            handle_control_command("remove-protocol", data, unix_writer).await
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: remove protocol {} {} from {}", protocol, bind_alias, identity);
+            let command = DaemonCommand::RemoveProtocol { identity, protocol, bind_alias };
+            handle_control_command(fastn_home, command, unix_writer).await
+        }
+        ClientRequest::PeerStatus => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: peer status");
+            handle_peer_status(unix_writer).await
+        }
+        ClientRequest::ProtocolMetrics => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: protocol metrics");
+            handle_protocol_metrics(unix_writer).await
+        }
+        ClientRequest::EndpointStatus => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: endpoint status");
+            handle_endpoint_status(unix_writer).await
+        }
+        ClientRequest::ClearCache { peer, protocol } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: clear cache");
+            handle_clear_cache(peer, protocol, unix_writer).await
+        }
+        ClientRequest::ListApprovals => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: list approvals");
+            handle_list_approvals(fastn_home, unix_writer).await
+        }
+        ClientRequest::ResolveApproval { id, approve } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: {} approval {}", if approve { "accept" } else { "deny" }, id);
+            handle_resolve_approval(fastn_home, id, approve, unix_writer).await
+        }
+        ClientRequest::Drain { timeout_ms } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: drain within {}ms", timeout_ms);
+            handle_drain(std::time::Duration::from_millis(timeout_ms), unix_writer).await
+        }
+        ClientRequest::Version => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: version");
+            handle_version(unix_writer).await
+        }
+        ClientRequest::ListReputation => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: list reputation");
+            handle_list_reputation(fastn_home, unix_writer).await
+        }
+        ClientRequest::ClearReputation { peer, identity } => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: clear reputation of {}", peer.id52());
+            handle_clear_reputation(fastn_home, peer, identity, unix_writer).await
+        }
+        ClientRequest::SubscribeEvents => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔀 Routing control: subscribe events");
+            handle_subscribe_events(unix_reader, unix_writer).await
         }
     }
 }
 
-/// Handle P2P call request - use fastn_net::get_stream() for connection pooling
-async fn handle_p2p_call(
+/// Handle P2P call request through the process-global client for the identity
+async fn handle_p2p_call<W>(
     fastn_home: PathBuf,
-    from_identity: String,
+    from_identity: Option<String>,
     to_peer: fastn_id52::PublicKey,
-    protocol: String,
-    bind_alias: String,
-    request: serde_json::Value,
-    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    println!("📞 P2P call: {} {} from {} to {}", protocol, bind_alias, from_identity, to_peer.id52());
-    
-    // Load real identity private key from daemon identity management
-    let from_key = match load_identity_key(&fastn_home, &from_identity).await {
-        Ok(key) => {
-            println!("🔑 Loaded identity key for: {}", from_identity);
-            key
+    mut call: PeerRequest,
+    path: fastn_p2p::client::PathPreference,
+    mut unix_writer: W,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let peer_protocol = call_protocol(&call.protocol, &call.bind_alias, call.command.as_deref(), call.args.clone())?;
+    // Commands carry their binding in the protocol value, plain protocols in a header
+    if call.command.is_none() {
+        call.metadata
+            .entry(fastn_p2p::server::context::BIND_ALIAS_HEADER.to_string())
+            .or_insert_with(|| call.bind_alias.clone());
+    }
+
+    // Identities paired from another machine are used through their primary
+    match fastn_p2p::server::devices::resolve_remote_identity(&fastn_home, from_identity.as_deref()).await {
+        Ok(Some(remote)) => {
+            // The primary picks the path to the peer, which we can't check from here
+            if !path.is_any() {
+                return write_error(
+                    &mut unix_writer,
+                    "path-not-allowed",
+                    format!("Cannot make a {} call as {}, it is used through its primary", path, remote.alias),
+                )
+                .await;
+            }
+            if let Err(open) = super::breaker::global().check(&to_peer) {
+                tracing::info!(target: fastn_p2p::console::TARGET, "⛔ {}", open);
+                return write_error(&mut unix_writer, "circuit-open", open.to_string()).await;
+            }
+            return handle_device_call(remote, to_peer, peer_protocol, call, unix_writer).await;
         }
+        Ok(None) => {}
         Err(e) => {
-            println!("❌ Failed to load identity '{}': {}", from_identity, e);
-            let error_response = ClientResponse {
-                success: false,
-                data: serde_json::json!({
-                    "error": format!("Identity '{}' not found or offline: {}", from_identity, e)
-                }),
-            };
-            let response_json = serde_json::to_string(&error_response)?;
-            unix_writer.write_all(response_json.as_bytes()).await?;
-            return Ok(());
+            tracing::error!("Cannot load paired identity: {}", e);
+            return write_error(&mut unix_writer, "identity", e.to_string()).await;
+        }
+    }
+
+    // Pick the sending identity: explicit alias, then the default, then the only one
+    let identity = match fastn_p2p::server::resolve_identity(&fastn_home, from_identity.as_deref()).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::error!("Cannot send as {}: {}", from_identity.as_deref().unwrap_or("(default)"), e);
+            return write_error(&mut unix_writer, "identity", e.to_string()).await;
         }
     };
+    super::endpoints::touch(&identity.alias).await;
+    let from_identity = identity.alias;
+    let from_key = identity.secret_key;
     
-    // Create endpoint for this identity
-    println!("🔌 Creating P2P endpoint for identity: {}", from_key.public_key().id52());
-    let endpoint = fastn_net::get_endpoint(from_key).await?;
-    
-    // Create protocol header - for now use a basic protocol like Ping
-    // TODO: Map daemon protocol strings to fastn_net Protocol enum variants  
-    let protocol_header = fastn_net::ProtocolHeader {
-        protocol: fastn_net::Protocol::Ping,  // Use Ping as placeholder for daemon protocols
-        extra: Some(format!("{}:{}", protocol, bind_alias)),  // Include actual protocol info in extra
-    };
-    
-    // Use global singletons for connection pooling and graceful shutdown
-    let pool = fastn_p2p::pool();
-    let graceful = fastn_p2p::graceful();
+    // A peer that keeps failing gets a break instead of more calls
+    if let Err(open) = super::breaker::global().check(&to_peer) {
+        tracing::info!(target: fastn_p2p::console::TARGET, "⛔ {}", open);
+        return write_error(&mut unix_writer, "circuit-open", open.to_string()).await;
+    }
     
-    println!("🔌 Getting P2P stream to {} via connection pool", to_peer.id52());
-    let (mut p2p_sender, mut p2p_receiver) = fastn_net::get_stream(
-        endpoint, 
-        protocol_header, 
-        &to_peer, 
-        pool, 
-        graceful
-    ).await?;
+    // Answers the server or config.toml let us keep may come from the cache,
+    // unless the caller pinned a path, which only a real call can honour
+    let cache = super::cache::global();
+    let cached = path.is_any().then(|| super::cache::CacheKey::new(to_peer, &from_identity, &call));
+    let PeerRequest { protocol, bind_alias, request, metadata, .. } = call;
+    if let Some(key) = &cached
+        && let Some((p2p_response, hint)) = cache.get(key)
+    {
+        tracing::info!(target: fastn_p2p::console::TARGET, "📦 Cached answer for {} {} from {} ({}s old)", protocol, bind_alias, to_peer.id52(), hint.age_secs);
+        return write_call_response(&mut unix_writer, p2p_response, &protocol, &bind_alias, &from_identity, Some(hint), None).await;
+    }
     
-    // Send the request data to P2P
-    println!("📤 Sending request to P2P: {}", request);
-    let request_bytes = serde_json::to_vec(&request)?;
-    use tokio::io::AsyncWriteExt;
-    p2p_sender.write_all(&request_bytes).await?;
-    p2p_sender.finish()?; // Remove .await - it's not async
+    tracing::info!(target: fastn_p2p::console::TARGET, "📞 P2P call: {} {} from {} to {}", protocol, bind_alias, from_identity, to_peer.id52());
     
-    // Read response from P2P 
-    let response_bytes = p2p_receiver.read_to_end(1024 * 1024).await?; // 1MB limit
-    let response_str = String::from_utf8_lossy(&response_bytes);
+    // Same handshake and request envelope as fastn_p2p::client::call, so the
+    // peer's listen() handlers see the real protocol and our metadata. Identities
+    // a serve_all process of this host serves are called over its socket, see
+    // fastn_p2p::server::loopback
+    let client = metadata.into_iter().fold(
+        fastn_p2p::client::Client::global(from_key).with_loopback_in(&fastn_home),
+        |client, (key, value)| client.with_metadata(key, value),
+    );
+    let options = fastn_p2p::client::CallOptions::default().with_path_preference(path);
+    let bytes_out = request.to_string().len() as u64;
+    let result = client
+        .call_with_options::<_, _, serde_json::Value, serde_json::Value>(to_peer, peer_protocol, request, options)
+        .await;
+    super::breaker::global().record(&to_peer, result.as_ref().err());
     
-    println!("📥 Received P2P response: {} bytes", response_bytes.len());
+    // Only answers the handler gave are cached, not its errors
+    let (p2p_response, hint, path) = match result {
+        Ok(fastn_p2p::client::CallReply { result: Ok(value), path, max_age }) => {
+            let hint = cached.and_then(|key| {
+                let ttl = cache.ttl(&key.protocol, key.command.as_deref(), max_age)?;
+                Some(cache.insert(key, &value, ttl))
+            });
+            (value, hint, path)
+        }
+        Ok(fastn_p2p::client::CallReply { result: Err(error), .. }) => {
+            tracing::info!(target: fastn_p2p::console::TARGET, "📥 Received error from {}'s {} handler", to_peer.id52(), protocol);
+            return write_application_error(&mut unix_writer, error, &protocol, &bind_alias, &from_identity).await;
+        }
+        Err(e) => {
+            tracing::error!("P2P call failed: {}", e);
+            return write_call_error(&mut unix_writer, &e).await;
+        }
+    };
+    tracing::info!(target: fastn_p2p::console::TARGET, "📥 Received P2P response ({} path)", path);
+    fastn_p2p::server::usage::record(&from_identity, &to_peer, &protocol, p2p_response.to_string().len() as u64, bytes_out);
     
     // Send response back to Unix socket client
+    write_call_response(&mut unix_writer, p2p_response, &protocol, &bind_alias, &from_identity, hint, Some(path)).await?;
+    
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ P2P call completed and response sent to client");
+    Ok(())
+}
+
+/// Send a peer's answer as a `success: true` line
+///
+/// Answers of cached protocols carry a `cache` hint, see [`super::cache::CacheHint`];
+/// answers that came over the network the `path` they took.
+async fn write_call_response<W>(
+    unix_writer: &mut W,
+    p2p_response: serde_json::Value,
+    protocol: &str,
+    bind_alias: &str,
+    from_identity: &str,
+    hint: Option<super::cache::CacheHint>,
+    path: Option<fastn_p2p::client::PathType>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut data = serde_json::json!({
+        "p2p_response": p2p_response,
+        "protocol": protocol,
+        "bind_alias": bind_alias,
+        "from_identity": from_identity
+    });
+    if let Some(hint) = hint {
+        data["cache"] = serde_json::to_value(hint)?;
+    }
+    if let Some(path) = path {
+        data["path"] = serde_json::to_value(path)?;
+    }
+    let response = ClientResponse { success: true, data };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Send the error a peer's handler answered with as a `success: false` line of kind `application`
+///
+/// `error` is the handler's own error value, so clients can decode it into
+/// their protocol's error type.
+async fn write_application_error<W>(
+    unix_writer: &mut W,
+    error: serde_json::Value,
+    protocol: &str,
+    bind_alias: &str,
+    from_identity: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
     let response = ClientResponse {
-        success: true,
+        success: false,
         data: serde_json::json!({
-            "p2p_response": response_str,
+            "kind": "application",
+            "error": error,
             "protocol": protocol,
             "bind_alias": bind_alias,
             "from_identity": from_identity
         }),
     };
-    
     let response_json = serde_json::to_string(&response)?;
     unix_writer.write_all(response_json.as_bytes()).await?;
     unix_writer.write_all(b"\n").await?;
-    
-    println!("✅ P2P call completed and response sent to client");
+    Ok(())
+}
+
+/// Make the same call to every member of a peer group, answering with all their results
+///
+/// Each member's call goes through [`handle_p2p_call`], so device identities,
+/// the cache and circuit breakers apply per member. Up to
+/// [`GROUP_CONCURRENCY`] calls run at once; results keep the group's order.
+async fn handle_group_call(
+    fastn_home: PathBuf,
+    from_identity: Option<String>,
+    group: String,
+    call: PeerRequest,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use futures_util::StreamExt;
+
+    let from_identity = match crate::cli::peers::address_book_owner(&fastn_home, from_identity.as_deref()).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::error!("Cannot send as {}: {}", from_identity.as_deref().unwrap_or("(default)"), e);
+            return write_error(&mut unix_writer, "identity", e.to_string()).await;
+        }
+    };
+    let members = match state_store(&fastn_home).await {
+        Ok(store) => store.group_members(&from_identity, &group).await.map_err(|source| ControlError::State { source }),
+        Err(e) => Err(e),
+    };
+    let members = match members {
+        Ok(Some(members)) => members,
+        Ok(None) => {
+            let error = format!("'{}' is neither a peer ID52 nor a peer group of '{}'", group, from_identity);
+            tracing::error!("{}", error);
+            return write_error(&mut unix_writer, "group", error).await;
+        }
+        Err(ControlError::State { source: source @ fastn_p2p::server::state::StateError::UnknownMember { .. } }) => {
+            tracing::error!("{}", source);
+            return write_error(&mut unix_writer, "group", source.to_string()).await;
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            return write_error(&mut unix_writer, e.kind(), e.to_string()).await;
+        }
+    };
+    tracing::info!(target: fastn_p2p::console::TARGET, "👥 Group {} of {}: {} members", group, from_identity, members.len());
+
+    let results: Vec<serde_json::Value> = futures_util::stream::iter(members)
+        .map(|member| {
+            let (fastn_home, from_identity, call) = (fastn_home.clone(), Some(from_identity.clone()), call.clone());
+            async move {
+                // The member's answer is the line a single call would have written
+                let mut line = Vec::new();
+                let handled = handle_p2p_call(
+                    fastn_home, from_identity, member.peer, call, fastn_p2p::client::PathPreference::Any, &mut line,
+                )
+                .await;
+                let (success, data) = match handled.map(|()| serde_json::from_slice::<serde_json::Value>(&line)) {
+                    Ok(Ok(response)) => (response["success"] == serde_json::Value::Bool(true), response["data"].clone()),
+                    Ok(Err(e)) => (false, serde_json::json!({ "kind": "io", "error": e.to_string() })),
+                    Err(e) => (false, serde_json::json!({ "kind": "io", "error": e.to_string() })),
+                };
+                serde_json::json!({
+                    "member": member.name,
+                    "peer": member.peer.id52(),
+                    "success": success,
+                    "data": data,
+                })
+            }
+        })
+        .buffered(GROUP_CONCURRENCY)
+        .collect()
+        .await;
+    let answered = results.iter().filter(|result| result["success"] == serde_json::Value::Bool(true)).count();
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ Group call answered by {} of {} members", answered, results.len());
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({
+            "group": group,
+            "protocol": call.protocol,
+            "bind_alias": call.bind_alias,
+            "from_identity": from_identity,
+            "results": results,
+        }),
+    };
+    unix_writer.write_all(serde_json::to_string(&response)?.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// The protocol value sent to the peer for a CLI protocol name
+///
+/// Servers register handlers for serialized protocol values, so a name that
+/// is already JSON (e.g. `{"Mail": "inbox"}`) is sent as is and anything else
+/// as a JSON string, which is how unit enum variants like `Echo` serialize.
+fn wire_protocol(protocol: &str) -> serde_json::Value {
+    serde_json::from_str(protocol).unwrap_or_else(|_| serde_json::Value::String(protocol.to_string()))
+}
+
+/// The protocol value for a call, addressing a serve_all command when one is given
+fn call_protocol(
+    protocol: &str,
+    bind_alias: &str,
+    command: Option<&str>,
+    args: Vec<String>,
+) -> Result<serde_json::Value, serde_json::Error> {
+    match command {
+        Some(command) => serde_json::to_value(
+            fastn_p2p::server::CommandProtocol::new(protocol, bind_alias, command).with_args(args),
+        ),
+        None => Ok(wire_protocol(protocol)),
+    }
+}
+
+/// Handle a call as an identity hosted on another daemon this machine is paired with
+async fn handle_device_call<W>(
+    remote: fastn_p2p::server::devices::RemoteIdentity,
+    to_peer: fastn_id52::PublicKey,
+    peer_protocol: serde_json::Value,
+    call: PeerRequest,
+    mut unix_writer: W,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let PeerRequest { protocol, bind_alias, request, metadata, .. } = call;
+    tracing::info!(target: fastn_p2p::console::TARGET, "📞 P2P call: {} {} from {} (via primary {}) to {}",
+            protocol, bind_alias, remote.alias, remote.identity.id52(), to_peer.id52());
+
+    let client = metadata.into_iter().fold(
+        fastn_p2p::client::Client::global(remote.device_key),
+        |client, (key, value)| client.with_metadata(key, value),
+    );
+    let result = client
+        .call_as_device::<_, _, serde_json::Value, serde_json::Value>(remote.identity, to_peer, peer_protocol, request)
+        .await;
+    super::breaker::global().record(&to_peer, result.as_ref().err());
+
+    let p2p_response = match result {
+        Ok(Ok(value)) => value,
+        Ok(Err(error)) => {
+            return write_application_error(&mut unix_writer, error, &protocol, &bind_alias, &remote.alias).await;
+        }
+        Err(e) => {
+            tracing::error!("Call through primary failed: {}", e);
+            return write_call_error(&mut unix_writer, &e).await;
+        }
+    };
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({
+            "p2p_response": p2p_response,
+            "protocol": protocol,
+            "bind_alias": bind_alias,
+            "from_identity": remote.alias,
+            "via_primary": remote.identity.id52()
+        }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ P2P call through primary completed");
+    Ok(())
+}
+
+/// Send a `success: false` response line; `kind` lets clients tell failures apart
+async fn write_error<W>(
+    unix_writer: &mut W,
+    kind: &str,
+    error: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let error_response = ClientResponse {
+        success: false,
+        data: serde_json::json!({ "kind": kind, "error": error }),
+    };
+    let response_json = serde_json::to_string(&error_response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Refuse a client speaking control protocol `client_wire`, saying what this daemon speaks
+///
+/// Read by the client as `ClientError::VersionMismatch`.
+async fn write_version_mismatch<W>(
+    unix_writer: &mut W,
+    client_wire: u32,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let error_response = ClientResponse {
+        success: false,
+        data: serde_json::json!({
+            "kind": "version-mismatch",
+            "error": format!(
+                "control protocol {} is not supported, this daemon speaks {} to {}: {}",
+                client_wire,
+                fastn_p2p_client::version::MIN_WIRE_VERSION,
+                fastn_p2p_client::version::WIRE_VERSION,
+                fastn_p2p_client::version::upgrade_hint(fastn_p2p_client::version::WIRE_VERSION, client_wire),
+            ),
+            "daemon_version": env!("CARGO_PKG_VERSION"),
+            "wire_version": fastn_p2p_client::version::WIRE_VERSION,
+            "min_wire_version": fastn_p2p_client::version::MIN_WIRE_VERSION,
+        }),
+    };
+    let response_json = serde_json::to_string(&error_response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Send a failed P2P call as a `success: false` line, see [`fastn_p2p::client::CallError::kind`]
+///
+/// A rejected handshake also carries the server's `code`.
+async fn write_call_error<W>(
+    unix_writer: &mut W,
+    error: &fastn_p2p::client::CallError,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut data = serde_json::json!({ "kind": error.kind(), "error": error.to_string() });
+    match error {
+        fastn_p2p::client::CallError::HandshakeRejected { code } => data["code"] = serde_json::to_value(code)?,
+        fastn_p2p::client::CallError::Denied { code, .. } => data["code"] = serde_json::json!(code),
+        fastn_p2p::client::CallError::Throttled { retry_after, .. } => {
+            data["retry_after_ms"] = serde_json::json!(retry_after.as_millis() as u64)
+        }
+        _ => {}
+    }
+    let error_response = ClientResponse { success: false, data };
+    let response_json = serde_json::to_string(&error_response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
     Ok(())
 }
 
 /// Handle P2P streaming request - bidirectional piping
+///
+/// Once the peer accepted the stream the client gets a success line, then
+/// bytes are relayed as described in [`fastn_p2p_client::stream`]: the
+/// client's half-close finishes our send stream, and the peer's output is
+/// framed and followed by how its stream ended. The relay stops when the
+/// peer's side ends, even if the client is still sending.
 async fn handle_p2p_stream(
-    _from_identity: String,
-    _to_peer: fastn_id52::PublicKey,
-    _protocol: String,
-    _bind_alias: String,
-    _initial_data: serde_json::Value,
-    _unix_reader: BufReader<tokio::net::unix::OwnedReadHalf>,
-    _unix_writer: tokio::net::unix::OwnedWriteHalf,
+    fastn_home: PathBuf,
+    from_identity: Option<String>,
+    to_peer: fastn_id52::PublicKey,
+    stream: PeerRequest,
+    mut unix_reader: BufReader<tokio::net::unix::OwnedReadHalf>,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    todo!("Use fastn_net::get_stream() for P2P connection, pipe Unix socket ↔ P2P stream bidirectionally");
+    let peer_protocol = call_protocol(&stream.protocol, &stream.bind_alias, stream.command.as_deref(), stream.args)?;
+    let PeerRequest { protocol, request: initial_data, metadata, .. } = stream;
+    // Same identity choice as calls, including identities paired from another machine
+    let (from_identity, key, via_primary) =
+        match fastn_p2p::server::devices::resolve_remote_identity(&fastn_home, from_identity.as_deref()).await {
+            Ok(Some(remote)) => (remote.alias, remote.device_key, Some(remote.identity)),
+            Ok(None) => match fastn_p2p::server::resolve_identity(&fastn_home, from_identity.as_deref()).await {
+                Ok(identity) => (identity.alias, identity.secret_key, None),
+                Err(e) => {
+                    tracing::error!("Cannot stream as {}: {}", from_identity.as_deref().unwrap_or("(default)"), e);
+                    return write_error(&mut unix_writer, "identity", e.to_string()).await;
+                }
+            },
+            Err(e) => {
+                tracing::error!("Cannot load paired identity: {}", e);
+                return write_error(&mut unix_writer, "identity", e.to_string()).await;
+            }
+        };
+    super::endpoints::touch(&from_identity).await;
+    tracing::info!(target: fastn_p2p::console::TARGET, "🌊 P2P stream: {} from {} to {}", peer_protocol, from_identity, to_peer.id52());
+
+    if let Err(open) = super::breaker::global().check(&to_peer) {
+        tracing::info!(target: fastn_p2p::console::TARGET, "⛔ {}", open);
+        return write_error(&mut unix_writer, "circuit-open", open.to_string()).await;
+    }
+
+    let client = metadata.into_iter().fold(
+        fastn_p2p::client::Client::global(key),
+        |client, (key, value)| client.with_metadata(key, value),
+    );
+    let result = match via_primary {
+        Some(identity) => client.connect_as_device(identity, to_peer, &peer_protocol, initial_data).await,
+        None => client.connect(to_peer, &peer_protocol, initial_data).await,
+    };
+    super::breaker::global().record(&to_peer, result.as_ref().err());
+    let fastn_p2p::client::Session { mut send, recv, .. } = match result {
+        Ok(session) => session,
+        Err(e) => {
+            tracing::error!("P2P stream failed: {}", e);
+            return write_call_error(&mut unix_writer, &e).await;
+        }
+    };
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({
+            "protocol": peer_protocol,
+            "from_identity": from_identity,
+        }),
+    };
+    unix_writer.write_all(serde_json::to_string(&response)?.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+
+    // Counted as it goes, the upload is cut short when the peer's side ends first
+    let mut sent = 0u64;
+    let mut received = 0u64;
+    let upload = async {
+        let mut buf = vec![0u8; fastn_p2p_client::stream::MAX_CHUNK];
+        let result = loop {
+            let n = match tokio::io::AsyncReadExt::read(&mut unix_reader, &mut buf).await {
+                Ok(0) => break Ok(()),
+                Ok(n) => n,
+                Err(e) => break Err(e),
+            };
+            if let Err(e) = send.write_all(&buf[..n]).await {
+                break Err(std::io::Error::other(e));
+            }
+            sent += n as u64;
+        };
+        // The client shut down its write half: pass the half-close on
+        let _ = send.finish();
+        result
+    };
+    let end = {
+        let download = relay_peer_output(recv, &mut unix_writer, &mut received);
+        tokio::pin!(download);
+        tokio::select! {
+            result = upload => {
+                if let Err(e) = result {
+                    tracing::warn!("Stopped reading from client: {}", e);
+                }
+                download.await?
+            }
+            end = &mut download => end?,
+        }
+    };
+    unix_writer.shutdown().await?;
+    fastn_p2p::server::usage::record(&from_identity, &to_peer, &protocol, received, sent);
+
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ P2P stream ended with exit code {}", end.exit_code);
+    Ok(())
 }
 
-/// Handle control commands (daemon management, non-P2P)
-async fn handle_control_command(
-    _command: &str,
-    _data: serde_json::Value,
-    _unix_writer: tokio::net::unix::OwnedWriteHalf,
+/// Ping a peer once and answer with the round trip time and path
+///
+/// Pings skip the scheduler and the circuit breaker: they are how one checks
+/// whether a peer the breaker gave up on is back.
+async fn handle_ping(
+    fastn_home: PathBuf,
+    from_identity: Option<String>,
+    to_peer: fastn_id52::PublicKey,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    todo!("Handle daemon management commands: reload identities, add/remove protocols, set online/offline");
+    let identity = match fastn_p2p::server::resolve_identity(&fastn_home, from_identity.as_deref()).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::error!("Cannot ping as {}: {}", from_identity.as_deref().unwrap_or("(default)"), e);
+            return write_error(&mut unix_writer, "identity", e.to_string()).await;
+        }
+    };
+    super::endpoints::touch(&identity.alias).await;
+
+    let pong = match fastn_p2p::client::Client::global(identity.secret_key).ping(to_peer).await {
+        Ok(pong) => pong,
+        Err(e) => {
+            tracing::error!("Ping to {} failed: {}", to_peer.id52(), e);
+            return write_call_error(&mut unix_writer, &e).await;
+        }
+    };
+    tracing::info!(target: fastn_p2p::console::TARGET, "🏓 Pong from {} in {:.1}ms ({})", to_peer.id52(), pong.rtt.as_secs_f64() * 1000.0, pong.path);
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({
+            "peer": to_peer.id52(),
+            "from_identity": identity.alias,
+            "rtt_ms": pong.rtt.as_secs_f64() * 1000.0,
+            "path": pong.path,
+        }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
 }
 
-/// Load identity private key from daemon identity management
-async fn load_identity_key(
-    fastn_home: &PathBuf,
-    identity_name: &str,
-) -> Result<fastn_id52::SecretKey, Box<dyn std::error::Error + Send + Sync>> {
-    let identities_dir = fastn_home.join("identities");
-    let identity_dir = identities_dir.join(identity_name);
-    
-    // Check if identity exists
-    if !identity_dir.exists() {
-        return Err(format!("Identity '{}' not found in {}", identity_name, identity_dir.display()).into());
-    }
-    
-    // Check if identity is online
-    let online_marker = identity_dir.join("online");
-    if !online_marker.exists() {
-        return Err(format!("Identity '{}' is offline", identity_name).into());
-    }
-    
-    // Load the identity private key
-    match fastn_id52::SecretKey::load_from_dir(&identity_dir, "identity") {
-        Ok((_id52, secret_key)) => {
-            println!("🔑 Loaded key for identity '{}': {}", identity_name, secret_key.public_key().id52());
-            Ok(secret_key)
+/// Queue `message` in the sending identity's outbox and wake the delivery service
+async fn handle_send(
+    fastn_home: PathBuf,
+    from_identity: Option<String>,
+    to_peer: fastn_id52::PublicKey,
+    peer_protocol: serde_json::Value,
+    message: serde_json::Value,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let identity = match fastn_p2p::server::resolve_identity(&fastn_home, from_identity.as_deref()).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::error!("Cannot send as {}: {}", from_identity.as_deref().unwrap_or("(default)"), e);
+            return write_error(&mut unix_writer, "identity", e.to_string()).await;
         }
+    };
+    super::endpoints::touch(&identity.alias).await;
+
+    let store = match state_store(&fastn_home).await {
+        Ok(store) => store,
+        Err(e) => return write_error(&mut unix_writer, e.kind(), e.to_string()).await,
+    };
+    let entry = match store.enqueue(&identity.alias, &to_peer, &peer_protocol.to_string(), &message).await {
+        Ok(entry) => entry,
         Err(e) => {
-            Err(format!("Failed to load key for identity '{}': {}", identity_name, e).into())
+            tracing::error!("Cannot queue message: {}", e);
+            return write_error(&mut unix_writer, "io", e.to_string()).await;
         }
-    }
-}
\ No newline at end of file
+    };
+    tracing::info!(target: fastn_p2p::console::TARGET, "📮 Queued message {} from {} to {}", entry.message_id, identity.alias, to_peer.id52());
+    super::outbox::wake();
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({
+            "message_id": entry.message_id,
+            "from_identity": identity.alias,
+            "peer": to_peer.id52(),
+        }),
+    };
+    unix_writer.write_all(serde_json::to_string(&response)?.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Answer with the delivery status of a queued message
+async fn handle_delivery_status(
+    fastn_home: PathBuf,
+    from_identity: Option<String>,
+    message_id: String,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let identity = match fastn_p2p::server::resolve_identity(&fastn_home, from_identity.as_deref()).await {
+        Ok(identity) => identity,
+        Err(e) => return write_error(&mut unix_writer, "identity", e.to_string()).await,
+    };
+
+    let store = match state_store(&fastn_home).await {
+        Ok(store) => store,
+        Err(e) => return write_error(&mut unix_writer, e.kind(), e.to_string()).await,
+    };
+    let delivery = match store.delivery(&identity.alias, &message_id).await {
+        Ok(Some(delivery)) => delivery,
+        Ok(None) => {
+            let error = format!("No message {} in the outbox of {}", message_id, identity.alias);
+            return write_error(&mut unix_writer, "message-not-found", error).await;
+        }
+        Err(e) => return write_error(&mut unix_writer, "io", e.to_string()).await,
+    };
+
+    let response = ClientResponse { success: true, data: serde_json::to_value(&delivery)? };
+    unix_writer.write_all(serde_json::to_string(&response)?.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Answer with the circuit breaker state of every peer called so far
+async fn handle_peer_status(
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({ "peers": super::breaker::global().snapshot() }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Answer with the latency histograms of every request handler run by this daemon
+async fn handle_protocol_metrics(
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({ "handlers": fastn_p2p::metrics::snapshot() }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Answer with the endpoint limits and the endpoint of every online identity
+async fn handle_endpoint_status(
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::to_value(super::endpoints::global().snapshot().await)?,
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Answer with the streams waiting for approval, see [`fastn_p2p::server::approvals`]
+///
+/// Servers run in other processes and share their approvals in FASTN_HOME.
+async fn handle_list_approvals(
+    fastn_home: &std::path::Path,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({ "approvals": fastn_p2p::server::approvals::pending_in(fastn_home) }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Accept or deny a waiting stream; its handler goes on shortly
+async fn handle_resolve_approval(
+    fastn_home: &std::path::Path,
+    id: String,
+    approve: bool,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Err(e) = fastn_p2p::server::approvals::resolve_in(fastn_home, &id, approve) {
+        tracing::error!("{}", e);
+        return write_error(&mut unix_writer, "approval", e.to_string()).await;
+    }
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ Approval {} {}", id, if approve { "accepted" } else { "denied" });
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::to_value(fastn_p2p_client::admin::ApprovalResolved { id, approved: approve })?,
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Stop accepting and answer once in-flight streams finished or `timeout` passed
+async fn handle_drain(
+    timeout: std::time::Duration,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let report = fastn_p2p::server::drain::drain(timeout).await;
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::to_value(report)?,
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Answer with the daemon's release and protocol versions, see [`super::version`]
+async fn handle_version(
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::to_value(super::version::info())?,
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Answer with peers holding strikes or bans, see [`fastn_p2p::server::reputation`]
+///
+/// Servers run in other processes and share their records in FASTN_HOME.
+async fn handle_list_reputation(
+    fastn_home: &std::path::Path,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({ "peers": fastn_p2p::server::reputation::peers_in(fastn_home) }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Forgive a peer; a ban is lifted right away
+async fn handle_clear_reputation(
+    fastn_home: &std::path::Path,
+    peer: fastn_id52::PublicKey,
+    identity: Option<fastn_id52::PublicKey>,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cleared = fastn_p2p::server::reputation::clear_in(fastn_home, identity.as_ref(), &peer);
+    tracing::info!(target: fastn_p2p::console::TARGET, "🧹 Cleared {} reputation records of {}", cleared, peer.id52());
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::to_value(fastn_p2p_client::admin::ReputationCleared { cleared })?,
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Stream daemon events to the client until it disconnects
+///
+/// The first line acknowledges the subscription; every line after it is a
+/// [`fastn_p2p::events::DaemonEvent`]. A client too slow to keep up gets a
+/// `lagged` event in place of the events it missed.
+async fn handle_subscribe_events(
+    mut unix_reader: BufReader<tokio::net::unix::OwnedReadHalf>,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Subscribe before acknowledging so no event after the ack is missed
+    let mut events = fastn_p2p::events::subscribe();
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({ "subscribed": true }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+
+    let mut discard = String::new();
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    fastn_p2p::events::DaemonEvent::now(fastn_p2p::events::EventKind::Lagged { missed })
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            // Subscribers send nothing more; end of input means they left
+            read = unix_reader.read_line(&mut discard) => {
+                discard.clear();
+                match read {
+                    Ok(0) | Err(_) => {
+                        tracing::info!(target: fastn_p2p::console::TARGET, "📤 Event subscriber disconnected");
+                        return Ok(());
+                    }
+                    Ok(_) => continue,
+                }
+            }
+        };
+        let event_json = serde_json::to_string(&event)?;
+        unix_writer.write_all(event_json.as_bytes()).await?;
+        unix_writer.write_all(b"\n").await?;
+    }
+}
+
+/// Drop cached call answers, all or those of one peer and/or protocol
+async fn handle_clear_cache(
+    peer: Option<fastn_id52::PublicKey>,
+    protocol: Option<String>,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cleared = super::cache::global().clear(peer.as_ref(), protocol.as_deref());
+    tracing::info!(target: fastn_p2p::console::TARGET, "🧹 Cleared {} cached answers", cleared);
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({ "cleared": cleared }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Copy the peer's output to the client as frames, ending with how the peer's stream ended
+///
+/// Bytes read from the peer are added to `received`.
+async fn relay_peer_output<W>(
+    recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    writer: &mut W,
+    received: &mut u64,
+) -> std::io::Result<fastn_p2p_client::stream::StreamEnd>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    // Output read along with the peer's response header comes first
+    let (mut recv, buffered) = recv.into_parts();
+    *received += buffered.len() as u64;
+    fastn_p2p_client::stream::write_data(writer, &buffered).await?;
+
+    // The connection is bound to the old network; the client has to reconnect
+    let mut network_changes = fastn_p2p::network::subscribe();
+    let mut buf = vec![0u8; fastn_p2p_client::stream::MAX_CHUNK];
+    let end = loop {
+        let read = tokio::select! {
+            read = recv.read(&mut buf) => read,
+            Ok(_) = network_changes.recv() => {
+                break fastn_p2p_client::stream::StreamEnd::failed("Network changed, reconnect");
+            }
+        };
+        match read {
+            Ok(Some(n)) => {
+                *received += n as u64;
+                fastn_p2p_client::stream::write_data(writer, &buf[..n]).await?
+            }
+            Ok(None) => break fastn_p2p_client::stream::StreamEnd::finished(),
+            Err(iroh::endpoint::ReadError::Reset(code)) => {
+                break fastn_p2p_client::stream::StreamEnd::reset(code.into_inner());
+            }
+            Err(e) => break fastn_p2p_client::stream::StreamEnd::failed(e),
+        }
+    };
+    fastn_p2p_client::stream::write_end(writer, &end).await?;
+    Ok(end)
+}
+
+/// Why a daemon management command failed
+#[derive(Debug, thiserror::Error)]
+pub enum ControlError {
+    #[error("Identity '{identity}' not found, create it with: fastn-p2p create-identity {identity}")]
+    IdentityNotFound { identity: String },
+
+    #[error("Identity '{identity}' already exists")]
+    IdentityExists { identity: String },
+
+    #[error("Cannot import key as '{identity}': identity '{existing}' already uses it")]
+    KeyInUse { identity: String, existing: String },
+
+    #[error("Failed to save key of identity '{identity}': {message}")]
+    Key { identity: String, message: String },
+
+    #[error("Failed to load identities: {message}")]
+    Load { message: String },
+
+    #[error("Invalid name '{name}': must be a single path component")]
+    InvalidName { name: String },
+
+    #[error("Protocol binding '{protocol}' as '{bind_alias}' already exists for identity '{identity}'")]
+    BindingExists { identity: String, protocol: String, bind_alias: String },
+
+    #[error("Protocol binding '{protocol}' as '{bind_alias}' not found for identity '{identity}'")]
+    BindingNotFound { identity: String, protocol: String, bind_alias: String },
+
+    #[error("Failed to update {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to update daemon state: {source}")]
+    State {
+        #[source]
+        source: fastn_p2p::server::state::StateError,
+    },
+
+    #[error("Failed to update listeners of '{identity}': {message}")]
+    Listeners { identity: String, message: String },
+
+    #[error("Identity is online but unreachable: {source}")]
+    Endpoint {
+        #[from]
+        source: super::endpoints::EndpointError,
+    },
+}
+
+impl ControlError {
+    /// The `kind` reported to clients, see [`write_error`]
+    fn kind(&self) -> &'static str {
+        match self {
+            ControlError::IdentityNotFound { .. }
+            | ControlError::IdentityExists { .. }
+            | ControlError::KeyInUse { .. }
+            | ControlError::Key { .. }
+            | ControlError::Load { .. }
+            | ControlError::Endpoint { .. } => "identity",
+            ControlError::InvalidName { .. }
+            | ControlError::BindingExists { .. }
+            | ControlError::BindingNotFound { .. }
+            | ControlError::Listeners { .. } => "protocol",
+            ControlError::Io { .. } | ControlError::State { .. } => "io",
+        }
+    }
+}
+
+/// Answer with every identity, its bindings and their listener state here
+async fn handle_list_identities(
+    fastn_home: &Path,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let identities = match fastn_p2p::server::load_all_identities(fastn_home).await.map_err(|e| e.to_string()) {
+        Ok(identities) => identities,
+        Err(message) => return write_error(&mut unix_writer, "identity", message).await,
+    };
+
+    let identities: Vec<fastn_p2p_client::admin::IdentityStatus> = identities
+        .into_iter()
+        .map(|identity| {
+            let public_key = identity.secret_key.public_key();
+            let bindings = identity
+                .protocols
+                .into_iter()
+                .map(|binding| {
+                    let key = fastn_p2p::server::BindingKey {
+                        identity: public_key,
+                        protocol: binding.protocol.clone(),
+                        bind_alias: binding.bind_alias.clone(),
+                    };
+                    let state = fastn_p2p::server::binding_listener(&key).ok().map(|handle| match handle.state() {
+                        fastn_p2p::server::ListenerState::Running => fastn_p2p_client::admin::ListenerState::Running,
+                        fastn_p2p::server::ListenerState::Stopped => fastn_p2p_client::admin::ListenerState::Stopped,
+                    });
+                    fastn_p2p_client::admin::BindingStatus {
+                        protocol: binding.protocol,
+                        bind_alias: binding.bind_alias,
+                        state,
+                    }
+                })
+                .collect();
+            fastn_p2p_client::admin::IdentityStatus {
+                alias: identity.alias,
+                peer: public_key.id52(),
+                online: identity.online,
+                bindings,
+            }
+        })
+        .collect();
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::json!({ "identities": identities }),
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// Handle control commands (daemon management, non-P2P)
+///
+/// The change is written to FASTN_HOME first so it survives a restart, then
+/// this process's listeners and endpoints are brought in line. The client
+/// gets the typed [`DaemonResponse`], or a `success: false` line with the
+/// error.
+async fn handle_control_command(
+    fastn_home: &Path,
+    command: DaemonCommand,
+    mut unix_writer: tokio::net::unix::OwnedWriteHalf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let binding = match &command {
+        DaemonCommand::AddProtocol { identity, protocol, bind_alias, .. }
+        | DaemonCommand::RemoveProtocol { identity, protocol, bind_alias } => {
+            Some((identity.clone(), protocol.clone(), bind_alias.clone()))
+        }
+        _ => None,
+    };
+    let response = match apply_control_command(fastn_home, command).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("{}", e);
+            if let Some((identity, protocol, bind_alias)) = binding {
+                fastn_p2p::events::publish(fastn_p2p::events::EventKind::ProtocolError {
+                    identity: Some(identity),
+                    protocol: Some(protocol),
+                    bind_alias: Some(bind_alias),
+                    error: e.to_string(),
+                });
+            }
+            return write_error(&mut unix_writer, e.kind(), e.to_string()).await;
+        }
+    };
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ {:?}", response);
+    if let Some(event) = response_event(&response) {
+        fastn_p2p::events::publish(event);
+    }
+
+    let response = ClientResponse {
+        success: true,
+        data: serde_json::to_value(&response)?,
+    };
+    let response_json = serde_json::to_string(&response)?;
+    unix_writer.write_all(response_json.as_bytes()).await?;
+    unix_writer.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// The event subscribers see for a successful control command, if any
+fn response_event(response: &DaemonResponse) -> Option<fastn_p2p::events::EventKind> {
+    use fastn_p2p::events::EventKind;
+
+    match response.clone() {
+        DaemonResponse::IdentityCreated { identity, peer } => Some(EventKind::IdentityCreated { identity, peer }),
+        DaemonResponse::IdentityStateChanged { identity, online } => {
+            Some(EventKind::IdentityStateChanged { identity, online })
+        }
+        DaemonResponse::ProtocolAdded { identity, protocol, bind_alias, .. } => {
+            Some(EventKind::ProtocolAdded { identity, protocol, bind_alias })
+        }
+        DaemonResponse::ProtocolRemoved { identity, protocol, bind_alias } => {
+            Some(EventKind::ProtocolRemoved { identity, protocol, bind_alias })
+        }
+        _ => None,
+    }
+}
+
+/// Apply `command` to disk, listeners and endpoints
+async fn apply_control_command(
+    fastn_home: &Path,
+    command: DaemonCommand,
+) -> Result<DaemonResponse, ControlError> {
+    write_control_command(fastn_home, &command).await?;
+
+    match command {
+        DaemonCommand::ReloadIdentities => {
+            let identities = fastn_p2p::server::load_all_identities(fastn_home)
+                .await
+                .map_err(|e| ControlError::Load { message: e.to_string() })?;
+            let online = identities.iter().filter(|identity| identity.online).count();
+            Ok(DaemonResponse::IdentitiesReloaded { total: identities.len(), online })
+        }
+        DaemonCommand::CreateIdentity { identity, .. } => {
+            let identity_dir = identity_dir(fastn_home, &identity)?;
+            let (peer, _secret_key) = fastn_id52::SecretKey::load_from_dir(&identity_dir, "identity")
+                .map_err(|e| ControlError::Key { identity: identity.clone(), message: e.to_string() })?;
+            Ok(DaemonResponse::IdentityCreated { identity, peer })
+        }
+        DaemonCommand::SetIdentityState { identity, online } => {
+            let change = if online {
+                fastn_p2p::server::ConfigChange::IdentityOnline { identity: identity.clone() }
+            } else {
+                fastn_p2p::server::ConfigChange::IdentityOffline { identity: identity.clone() }
+            };
+            apply_to_listeners(fastn_home, &change).await?;
+            super::endpoints::apply(fastn_home, &change).await?;
+
+            Ok(DaemonResponse::IdentityStateChanged { identity, online })
+        }
+        DaemonCommand::AddProtocol { identity, protocol, bind_alias, .. } => {
+            let change = fastn_p2p::server::ConfigChange::BindingChanged {
+                identity: identity.clone(),
+                protocol: protocol.clone(),
+                bind_alias: bind_alias.clone(),
+                protocol_dir: fastn_home.join("identities").join(&identity).join("protocols").join(&protocol).join(&bind_alias),
+            };
+            apply_to_listeners(fastn_home, &change).await?;
+            let state = binding_state(fastn_home, &identity, &protocol, &bind_alias).await?;
+
+            Ok(DaemonResponse::ProtocolAdded { identity, protocol, bind_alias, state })
+        }
+        DaemonCommand::RemoveProtocol { identity, protocol, bind_alias } => {
+            let change = fastn_p2p::server::ConfigChange::BindingRemoved {
+                identity: identity.clone(),
+                protocol: protocol.clone(),
+                bind_alias: bind_alias.clone(),
+            };
+            apply_to_listeners(fastn_home, &change).await?;
+
+            Ok(DaemonResponse::ProtocolRemoved { identity, protocol, bind_alias })
+        }
+    }
+}
+
+/// Record `command` in FASTN_HOME, holding the update lock
+///
+/// This is the whole change as far as disk goes. The daemon runs it for its
+/// control commands and `fastn-p2p` runs it itself when no daemon is up, see
+/// [`fastn_p2p::server::acquire_update_lock`].
+pub async fn write_control_command(fastn_home: &Path, command: &DaemonCommand) -> Result<(), ControlError> {
+    let _lock = fastn_p2p::server::acquire_update_lock(fastn_home)
+        .await
+        .map_err(|source| ControlError::Io { path: fastn_home.join("update.lock"), source })?;
+
+    match command {
+        DaemonCommand::ReloadIdentities => Ok(()),
+        DaemonCommand::CreateIdentity { identity, secret_key } => {
+            // New identities start offline, without bindings
+            let identity_dir = fastn_home.join("identities").join(valid_name(identity)?);
+            if identity_dir.exists() {
+                return Err(ControlError::IdentityExists { identity: identity.clone() });
+            }
+            // Two identities with one key would fight over the same peer ID
+            if let Some(secret_key) = secret_key
+                && let Some(existing) = identity_with_key(fastn_home, &secret_key.id52()).await?
+            {
+                return Err(ControlError::KeyInUse { identity: identity.clone(), existing });
+            }
+            secret_key
+                .clone()
+                .unwrap_or_else(fastn_id52::SecretKey::generate)
+                .save_to_dir(&identity_dir, "identity")
+                .map_err(|e| ControlError::Key { identity: identity.clone(), message: e.to_string() })?;
+            Ok(())
+        }
+        DaemonCommand::SetIdentityState { identity, online } => {
+            identity_dir(fastn_home, identity)?;
+            state_store(fastn_home)
+                .await?
+                .set_online(identity, *online)
+                .await
+                .map_err(|source| ControlError::State { source })?;
+            Ok(())
+        }
+        DaemonCommand::AddProtocol { identity, protocol, bind_alias, config } => {
+            let protocol_dir = identity_dir(fastn_home, identity)?
+                .join("protocols")
+                .join(valid_name(protocol)?)
+                .join(valid_name(bind_alias)?);
+            // Registering first claims the binding; of two concurrent adds one gets BindingExists
+            let store = state_store(fastn_home).await?;
+            match store.add_binding(identity, protocol, bind_alias, config).await {
+                Ok(()) => {}
+                Err(fastn_p2p::server::state::StateError::BindingExists { .. }) => {
+                    return Err(ControlError::BindingExists {
+                        identity: identity.clone(),
+                        protocol: protocol.clone(),
+                        bind_alias: bind_alias.clone(),
+                    });
+                }
+                Err(source) => return Err(ControlError::State { source }),
+            }
+            if let Err(e) = write_binding_config(&protocol_dir, config).await {
+                if let Err(unregister) = store.remove_binding(identity, protocol, bind_alias).await {
+                    tracing::warn!("Failed to unregister {} {} of {}: {}", protocol, bind_alias, identity, unregister);
+                }
+                return Err(e);
+            }
+            Ok(())
+        }
+        DaemonCommand::RemoveProtocol { identity, protocol, bind_alias } => {
+            let config_file = identity_dir(fastn_home, identity)?
+                .join("protocols")
+                .join(valid_name(protocol)?)
+                .join(valid_name(bind_alias)?)
+                .join("config.json");
+            match state_store(fastn_home).await?.remove_binding(identity, protocol, bind_alias).await {
+                Ok(()) => {}
+                Err(fastn_p2p::server::state::StateError::BindingNotFound { .. }) => {
+                    return Err(ControlError::BindingNotFound {
+                        identity: identity.clone(),
+                        protocol: protocol.clone(),
+                        bind_alias: bind_alias.clone(),
+                    });
+                }
+                Err(source) => return Err(ControlError::State { source }),
+            }
+            // Only the config goes; data and logs next to it are left alone
+            match tokio::fs::remove_file(&config_file).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(ControlError::Io { path: config_file, source: e });
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+    }
+}
+
+/// `identities/<identity>`, which must already exist
+fn identity_dir(fastn_home: &Path, identity: &str) -> Result<PathBuf, ControlError> {
+    let identity_dir = fastn_home.join("identities").join(valid_name(identity)?);
+    if !identity_dir.is_dir() {
+        return Err(ControlError::IdentityNotFound { identity: identity.to_string() });
+    }
+    Ok(identity_dir)
+}
+
+/// The identity whose key has ID52 `id52`, without touching the keyring
+async fn identity_with_key(fastn_home: &Path, id52: &str) -> Result<Option<String>, ControlError> {
+    let identities_dir = fastn_home.join("identities");
+    let io = |source| ControlError::Io { path: identities_dir.clone(), source };
+    let mut entries = match tokio::fs::read_dir(&identities_dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(io(e)),
+    };
+    while let Some(entry) = entries.next_entry().await.map_err(io)? {
+        let dir = entry.path();
+        let existing_id52 = match tokio::fs::read_to_string(dir.join("identity.id52")).await {
+            Ok(existing_id52) => Some(existing_id52.trim().to_string()),
+            Err(_) => tokio::fs::read_to_string(dir.join("identity.private-key"))
+                .await
+                .ok()
+                .and_then(|key| key.trim().parse::<fastn_id52::SecretKey>().ok())
+                .map(|key| key.id52()),
+        };
+        if existing_id52.as_deref() == Some(id52) {
+            return Ok(Some(entry.file_name().to_string_lossy().into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+async fn state_store(fastn_home: &Path) -> Result<fastn_p2p::server::state::StateStore, ControlError> {
+    fastn_p2p::server::state::StateStore::open(fastn_home)
+        .await
+        .map_err(|source| ControlError::State { source })
+}
+
+/// Write a binding's `config.json`, the config its protocol reads
+async fn write_binding_config(protocol_dir: &Path, config: &serde_json::Value) -> Result<(), ControlError> {
+    tokio::fs::create_dir_all(protocol_dir)
+        .await
+        .map_err(|source| ControlError::Io { path: protocol_dir.to_path_buf(), source })?;
+    let config_file = protocol_dir.join("config.json");
+    let config_json = serde_json::to_string_pretty(config).expect("JSON values always serialize");
+    tokio::fs::write(&config_file, config_json)
+        .await
+        .map_err(|source| ControlError::Io { path: config_file, source })
+}
+
+/// Names end up as path components under FASTN_HOME
+fn valid_name(name: &str) -> Result<&str, ControlError> {
+    if name.is_empty() || name.contains('/') || name.contains('\\') || name == "." || name == ".." {
+        return Err(ControlError::InvalidName { name: name.to_string() });
+    }
+    Ok(name)
+}
+
+async fn apply_to_listeners(
+    fastn_home: &Path,
+    change: &fastn_p2p::server::ConfigChange,
+) -> Result<(), ControlError> {
+    fastn_p2p::server::watch::apply_to_listeners(fastn_home, change)
+        .await
+        .map_err(|e| ControlError::Listeners {
+            identity: change.identity().to_string(),
+            message: e.to_string(),
+        })
+}
+
+/// State of a binding's listener in this process, if it has one
+async fn binding_state(
+    fastn_home: &Path,
+    identity: &str,
+    protocol: &str,
+    bind_alias: &str,
+) -> Result<Option<fastn_p2p::server::ListenerState>, ControlError> {
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_conventional_dir(
+        &fastn_home.join("identities").join(identity),
+        identity,
+    )
+    .await
+    .map_err(|e| ControlError::Load { message: e.to_string() })?;
+    let binding = fastn_p2p::server::BindingKey {
+        identity: identity_config.secret_key.public_key(),
+        protocol: protocol.to_string(),
+        bind_alias: bind_alias.to_string(),
+    };
+    Ok(fastn_p2p::server::binding_listener(&binding).ok().map(|handle| handle.state()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest::proptest! {
+        #[test]
+        fn test_request_parser_never_panics(line in ".*") {
+            let _ = serde_json::from_str::<ClientRequest>(&line);
+        }
+
+        #[test]
+        fn test_request_parser_never_panics_on_near_misses(
+            kind in "call|stream|send|delivery-status|protocol-metrics|[a-z-]{0,12}",
+            field in "[a-z_]{0,12}",
+            value in ".*",
+        ) {
+            let line = serde_json::json!({ "type": kind, field: value, "to_peer": value }).to_string();
+            let _ = serde_json::from_str::<ClientRequest>(&line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_lines_are_bounded() {
+        let mut line = String::new();
+        let mut reader: &[u8] = b"{\"type\":\"call\"}\nmore";
+        assert_eq!(read_request_line(&mut reader, &mut line, 16).await.unwrap(), 16);
+        assert_eq!(reader, b"more");
+
+        // A client that never sends a newline
+        let mut reader = tokio::io::BufReader::new(tokio::io::repeat(b'a'));
+        let error = read_request_line(&mut reader, &mut String::new(), 1024).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_application_errors_are_failures_with_the_handler_error() {
+        let mut line = Vec::new();
+        let error = serde_json::json!({ "code": "mailbox-full", "retry": false });
+        write_application_error(&mut line, error.clone(), "Mail", "inbox", "alice").await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        assert_eq!(response["success"], false);
+        assert_eq!(response["data"]["bind_alias"], "inbox");
+        match fastn_p2p_client::ClientError::from_daemon_error(&response["data"]) {
+            fastn_p2p_client::ClientError::Application { error: decoded } => assert_eq!(decoded, error),
+            other => panic!("expected an application error, got {other}"),
+        }
+    }
+
+    #[test]
+    fn test_wire_protocol() {
+        assert_eq!(wire_protocol("Echo"), serde_json::json!("Echo"));
+        assert_eq!(wire_protocol(r#"{"Mail":"inbox"}"#), serde_json::json!({ "Mail": "inbox" }));
+        assert_eq!(wire_protocol(r#""Echo""#), serde_json::json!("Echo"));
+    }
+
+    #[test]
+    fn test_call_protocol_addresses_serve_all_commands() {
+        assert_eq!(call_protocol("Echo", "default", None, vec![]).unwrap(), serde_json::json!("Echo"));
+        assert_eq!(
+            call_protocol("mail.fastn.com", "work", Some("get-mails"), vec!["--unread".to_string()]).unwrap(),
+            serde_json::json!({
+                "protocol": "mail.fastn.com",
+                "bind_alias": "work",
+                "command": "get-mails",
+                "args": ["--unread"]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_frames_carry_output_and_exit_code() {
+        use fastn_p2p_client::stream::{Frame, StreamEnd};
+
+        let (mut daemon, mut client) = tokio::io::duplex(1024);
+        let output = vec![7u8; fastn_p2p_client::stream::MAX_CHUNK + 1];
+        let writer = async {
+            fastn_p2p_client::stream::write_data(&mut daemon, &output).await.unwrap();
+            fastn_p2p_client::stream::write_end(&mut daemon, &StreamEnd::reset(3)).await.unwrap();
+        };
+        let reader = async {
+            let mut frames = Vec::new();
+            loop {
+                match fastn_p2p_client::stream::read_frame(&mut client).await.unwrap() {
+                    Frame::Data(bytes) => frames.push(bytes.len()),
+                    Frame::End(end) => return (frames, end),
+                }
+            }
+        };
+        let ((), (frames, end)) = tokio::join!(writer, reader);
+
+        assert_eq!(frames, vec![fastn_p2p_client::stream::MAX_CHUNK, 1]);
+        assert_eq!(end, StreamEnd { exit_code: 3, error: None });
+        assert_eq!(StreamEnd::reset(0x1_0000).exit_code, 1);
+    }
+
+    #[tokio::test]
+    async fn test_control_commands_update_disk() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let identity_dir = fastn_home.join("identities").join("alice");
+        fastn_id52::SecretKey::generate().save_to_dir(&identity_dir, "identity").unwrap();
+
+        let set_online = DaemonCommand::SetIdentityState { identity: "alice".to_string(), online: true };
+        let response = apply_control_command(&fastn_home, set_online).await.unwrap();
+        assert!(matches!(response, DaemonResponse::IdentityStateChanged { online: true, .. }));
+        let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await.unwrap();
+        assert!(store.is_online("alice").await.unwrap());
+
+        let add = DaemonCommand::AddProtocol {
+            identity: "alice".to_string(),
+            protocol: "mail.fastn.com".to_string(),
+            bind_alias: "default".to_string(),
+            config: fastn_p2p_client::Sensitive(serde_json::json!({ "storage_dir": "/var/mail" })),
+        };
+        apply_control_command(&fastn_home, add.clone()).await.unwrap();
+        let config_file = identity_dir.join("protocols/mail.fastn.com/default/config.json");
+        let config: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&config_file).unwrap()).unwrap();
+        assert_eq!(config["storage_dir"], "/var/mail");
+        assert_eq!(store.bindings("alice").await.unwrap()[0].config, config);
+        assert!(matches!(
+            apply_control_command(&fastn_home, add).await,
+            Err(ControlError::BindingExists { .. })
+        ));
+
+        let response = apply_control_command(&fastn_home, DaemonCommand::ReloadIdentities).await.unwrap();
+        assert!(matches!(response, DaemonResponse::IdentitiesReloaded { total: 1, online: 1 }));
+
+        let remove = DaemonCommand::RemoveProtocol {
+            identity: "alice".to_string(),
+            protocol: "mail.fastn.com".to_string(),
+            bind_alias: "default".to_string(),
+        };
+        apply_control_command(&fastn_home, remove.clone()).await.unwrap();
+        assert!(!config_file.exists());
+        assert!(store.bindings("alice").await.unwrap().is_empty());
+        assert!(matches!(
+            apply_control_command(&fastn_home, remove).await,
+            Err(ControlError::BindingNotFound { .. })
+        ));
+
+        let create = DaemonCommand::CreateIdentity { identity: "carol".to_string(), secret_key: None };
+        let response = apply_control_command(&fastn_home, create.clone()).await.unwrap();
+        assert!(matches!(response, DaemonResponse::IdentityCreated { ref identity, .. } if identity == "carol"));
+        assert!(!store.is_online("carol").await.unwrap());
+        assert!(matches!(
+            apply_control_command(&fastn_home, create).await,
+            Err(ControlError::IdentityExists { .. })
+        ));
+
+        // A key can be imported once; a second identity with it is refused
+        let key = fastn_id52::SecretKey::generate();
+        let import = |identity: &str| DaemonCommand::CreateIdentity {
+            identity: identity.to_string(),
+            secret_key: Some(key.clone()),
+        };
+        let response = apply_control_command(&fastn_home, import("dave")).await.unwrap();
+        assert!(matches!(response, DaemonResponse::IdentityCreated { ref peer, .. } if *peer == key.id52()));
+        assert!(matches!(
+            apply_control_command(&fastn_home, import("erin")).await,
+            Err(ControlError::KeyInUse { ref existing, .. }) if existing == "dave"
+        ));
+        assert!(!fastn_home.join("identities/erin").exists());
+
+        // Of two racing adds of the same binding exactly one wins
+        let add = DaemonCommand::AddProtocol {
+            identity: "alice".to_string(),
+            protocol: "chat.fastn.com".to_string(),
+            bind_alias: "default".to_string(),
+            config: fastn_p2p_client::Sensitive(serde_json::json!({})),
+        };
+        let (first, second) =
+            tokio::join!(write_control_command(&fastn_home, &add), write_control_command(&fastn_home, &add));
+        let failed = if first.is_ok() { second } else { first };
+        assert!(matches!(failed, Err(ControlError::BindingExists { .. })));
+
+        // Unknown identities and path tricks are rejected before touching disk
+        let missing = DaemonCommand::SetIdentityState { identity: "bob".to_string(), online: true };
+        assert!(matches!(
+            apply_control_command(&fastn_home, missing).await,
+            Err(ControlError::IdentityNotFound { .. })
+        ));
+        let escape = DaemonCommand::SetIdentityState { identity: "..".to_string(), online: true };
+        assert!(matches!(
+            apply_control_command(&fastn_home, escape).await,
+            Err(ControlError::InvalidName { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add_protocol_config_reaches_clipboard_binding() {
+        use fastn_p2p::server::clipboard;
+
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let identity_dir = fastn_home.join("identities").join("alice");
+        fastn_id52::SecretKey::generate().save_to_dir(&identity_dir, "identity").unwrap();
+
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let add = DaemonCommand::AddProtocol {
+            identity: "alice".to_string(),
+            protocol: clipboard::CLIPBOARD_PROTOCOL.to_string(),
+            bind_alias: "default".to_string(),
+            config: fastn_p2p_client::Sensitive(serde_json::json!({ "max_bytes": 64, "allowed_peers": [peer.id52()] })),
+        };
+        apply_control_command(&fastn_home, add).await.unwrap();
+
+        let protocol_dir = identity_dir.join("protocols").join(clipboard::CLIPBOARD_PROTOCOL).join("default");
+        let config = clipboard::ClipboardConfig::load(&protocol_dir).await.unwrap();
+        assert_eq!(config.max_bytes, 64);
+        assert!(config.allows(&peer));
+    }
+
+    #[tokio::test]
+    async fn test_add_protocol_config_reaches_proc_binding() {
+        use fastn_p2p::server::proc;
+
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let identity_dir = fastn_home.join("identities").join("alice");
+        fastn_id52::SecretKey::generate().save_to_dir(&identity_dir, "identity").unwrap();
+
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let add = DaemonCommand::AddProtocol {
+            identity: "alice".to_string(),
+            protocol: proc::PROC_PROTOCOL.to_string(),
+            bind_alias: "default".to_string(),
+            config: fastn_p2p_client::Sensitive(serde_json::json!({
+                "allowed_peers": [peer.id52()],
+                "processes": { "web": { "command": "python3" } }
+            })),
+        };
+        apply_control_command(&fastn_home, add).await.unwrap();
+
+        let protocol_dir = identity_dir.join("protocols").join(proc::PROC_PROTOCOL).join("default");
+        let config = proc::ProcConfig::load(&protocol_dir).await.unwrap();
+        assert!(config.allows(&peer));
+        assert_eq!(config.processes["web"].command, "python3");
+    }
+
+    #[tokio::test]
+    async fn test_add_protocol_config_reaches_web_binding() {
+        use fastn_p2p::server::web;
+
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let identity_dir = fastn_home.join("identities").join("alice");
+        fastn_id52::SecretKey::generate().save_to_dir(&identity_dir, "identity").unwrap();
+
+        let add = DaemonCommand::AddProtocol {
+            identity: "alice".to_string(),
+            protocol: web::WEB_PROTOCOL.to_string(),
+            bind_alias: "default".to_string(),
+            config: fastn_p2p_client::Sensitive(serde_json::json!({ "root": "site" })),
+        };
+        apply_control_command(&fastn_home, add).await.unwrap();
+
+        let protocol_dir = identity_dir.join("protocols").join(web::WEB_PROTOCOL).join("default");
+        let config = web::WebConfig::load(&protocol_dir).await.unwrap();
+        assert_eq!(config.root, protocol_dir.join("site"));
+        assert_eq!(config.index, web::DEFAULT_INDEX);
+    }
+
+    #[tokio::test]
+    async fn test_add_protocol_config_reaches_sync_binding() {
+        use fastn_p2p::server::sync;
+
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let identity_dir = fastn_home.join("identities").join("alice");
+        fastn_id52::SecretKey::generate().save_to_dir(&identity_dir, "identity").unwrap();
+
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let add = DaemonCommand::AddProtocol {
+            identity: "alice".to_string(),
+            protocol: sync::SYNC_PROTOCOL.to_string(),
+            bind_alias: "default".to_string(),
+            config: fastn_p2p_client::Sensitive(serde_json::json!({ "root": "shared", "allowed_peers": [peer.id52()] })),
+        };
+        apply_control_command(&fastn_home, add).await.unwrap();
+
+        let protocol_dir = identity_dir.join("protocols").join(sync::SYNC_PROTOCOL).join("default");
+        let config = sync::SyncConfig::load(&protocol_dir).await.unwrap();
+        assert_eq!(config.root, protocol_dir.join("shared"));
+        assert!(config.allows(&peer));
+    }
+}
@@ -0,0 +1,365 @@
+//! How online identities map to iroh endpoints
+//!
+//! An iroh endpoint answers for exactly one key, so every endpoint costs a
+//! UDP port and a relay registration of its own. Calls leave from the
+//! identity's own key, so each online identity gets its own endpoint: the
+//! one its [`fastn_p2p::client::Client`] sends from, never a second one.
+//! Sharing one endpoint between identities isn't possible, so there is no
+//! strategy setting; `strategy` in the config is rejected as unknown. The
+//! `[endpoints]` section of `config.toml` limits how many are bound:
+//!
+//! ```toml
+//! [endpoints]
+//! max_endpoints = 16
+//! ```
+//!
+//! Identities that would need an endpoint past `max_endpoints` stay online on
+//! disk, and bind their endpoint once another one goes offline or they are used.
+//!
+//! Hosts with many rarely used identities don't have to keep all of them
+//! bound:
+//!
+//! ```toml
+//! [endpoints]
+//! lazy = true
+//! idle_stop_secs = 3600
+//! always_on = ["alice"]
+//! ```
+//!
+//! - `lazy`: an identity going online binds nothing yet. Its endpoint starts
+//!   on first use: an outbound call, stream, ping or send through the daemon
+//!   ([`touch`]).
+//! - `idle_stop_secs`: endpoints none of whose identities were used for that
+//!   long are closed, and start again on next use like lazy ones. 0 (the
+//!   default) keeps them running.
+//! - `always_on`: aliases bound when they go online and never stopped.
+//!
+//! These are the endpoints the daemon sends from. Peers reach an identity
+//! through the listeners [`fastn_p2p::serve_all`] or [`fastn_p2p::listen`]
+//! binds for it in the application's process, which stay up whatever the
+//! pool does, so a stopped endpoint doesn't make an identity unreachable and
+//! inbound traffic has nothing to wake here. Lazy start and idle stop cover
+//! outbound use only: served identities start listening eagerly and stop
+//! when they go offline or the server stops, since a listener that isn't
+//! running can't hear the first call.
+//!
+//! `fastn-p2p status --endpoints` shows which endpoint serves each identity,
+//! its ports and relay, or that it is stopped and for how long it was unused.
+
+/// `[endpoints]` in `config.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EndpointsConfig {
+    /// Endpoints bound at once
+    pub max_endpoints: usize,
+    /// Bind an identity's endpoint on first use instead of when it goes online
+    pub lazy: bool,
+    /// Close endpoints unused this long; 0 keeps them running
+    pub idle_stop_secs: u64,
+    /// Aliases bound when they go online and never stopped
+    pub always_on: Vec<String>,
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        Self {
+            max_endpoints: 16,
+            lazy: false,
+            idle_stop_secs: 0,
+            always_on: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EndpointError {
+    #[error("No endpoint for '{identity}': all {limit} endpoints are in use, see max_endpoints in config.toml")]
+    Limit { identity: String, limit: usize },
+
+    #[error("Failed to load the key of '{identity}': {message}")]
+    Key { identity: String, message: String },
+
+    #[error("Failed to bind an endpoint for '{identity}'")]
+    Bind {
+        identity: String,
+        #[source]
+        source: eyre::Report,
+    },
+}
+
+/// Endpoints bound by the daemon and the identities they serve
+pub struct EndpointPool {
+    config: EndpointsConfig,
+    state: tokio::sync::Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Bound endpoints, by the ID they answer for
+    endpoints: std::collections::BTreeMap<String, iroh::Endpoint>,
+    /// Online identities, bound or not, by alias
+    identities: std::collections::BTreeMap<String, OnlineIdentity>,
+}
+
+struct OnlineIdentity {
+    /// The identity's ID
+    peer: String,
+    secret_key: fastn_id52::SecretKey,
+    last_used: std::time::Instant,
+}
+
+impl OnlineIdentity {
+    /// ID of the endpoint serving it, bound or not
+    fn endpoint_id(&self) -> String {
+        self.peer.clone()
+    }
+}
+
+static POOL: std::sync::OnceLock<EndpointPool> = std::sync::OnceLock::new();
+
+/// Set the limits; only the first call (at daemon start) has an effect
+pub fn init(config: EndpointsConfig) {
+    let _ = POOL.set(EndpointPool::new(config));
+}
+
+/// The daemon's endpoints, with the default limits if [`init`] wasn't called
+pub fn global() -> &'static EndpointPool {
+    POOL.get_or_init(|| EndpointPool::new(EndpointsConfig::default()))
+}
+
+impl EndpointPool {
+    pub fn new(config: EndpointsConfig) -> Self {
+        Self { config, state: Default::default() }
+    }
+
+    /// Give identity `alias` an endpoint, binding one now unless it is lazy
+    ///
+    /// An identity refused a bound endpoint stays known to the pool and gets
+    /// one on its next use.
+    pub async fn bring_online(&self, alias: &str, secret_key: &fastn_id52::SecretKey) -> Result<(), EndpointError> {
+        let mut state = self.state.lock().await;
+        if state.identities.contains_key(alias) {
+            return Ok(());
+        }
+
+        state.identities.insert(
+            alias.to_string(),
+            OnlineIdentity {
+                peer: secret_key.public_key().id52(),
+                secret_key: secret_key.clone(),
+                last_used: std::time::Instant::now(),
+            },
+        );
+        if self.config.lazy && !self.is_always_on(alias) {
+            tracing::info!(target: fastn_p2p::console::TARGET, "💤 {} is online, its endpoint starts on first use", alias);
+            return Ok(());
+        }
+        self.bind(&mut state, alias).await
+    }
+
+    /// Note a use of identity `alias`, binding its endpoint if it is stopped
+    pub async fn touch(&self, alias: &str) -> Result<(), EndpointError> {
+        let mut state = self.state.lock().await;
+        let Some(identity) = state.identities.get_mut(alias) else {
+            return Ok(());
+        };
+        identity.last_used = std::time::Instant::now();
+        self.bind(&mut state, alias).await
+    }
+
+    /// Close endpoints none of whose identities was used for `idle`; returns their IDs
+    ///
+    /// Identities named in `always_on` keep their endpoint.
+    pub async fn stop_idle(&self, idle: std::time::Duration) -> Vec<String> {
+        let mut state = self.state.lock().await;
+        let now = std::time::Instant::now();
+        let in_use: std::collections::BTreeSet<String> = state
+            .identities
+            .iter()
+            .filter(|(alias, identity)| {
+                self.is_always_on(alias) || now.saturating_duration_since(identity.last_used) < idle
+            })
+            .map(|(_, identity)| identity.endpoint_id())
+            .collect();
+        let stopped: Vec<String> = state.endpoints.keys().filter(|id| !in_use.contains(*id)).cloned().collect();
+        for endpoint_id in &stopped {
+            if let Some(endpoint) = state.endpoints.remove(endpoint_id) {
+                tracing::info!(target: fastn_p2p::console::TARGET, "💤 Stopping endpoint {} after {:?} unused", endpoint_id, idle);
+                endpoint.close().await;
+            }
+        }
+        stopped
+    }
+
+    fn is_always_on(&self, alias: &str) -> bool {
+        self.config.always_on.iter().any(|always_on| always_on == alias)
+    }
+
+    /// Bind the endpoint of online identity `alias` unless it is bound already
+    async fn bind(&self, state: &mut PoolState, alias: &str) -> Result<(), EndpointError> {
+        let Some(identity) = state.identities.get(alias) else {
+            return Ok(());
+        };
+        let endpoint_id = identity.endpoint_id();
+        if state.endpoints.contains_key(&endpoint_id) {
+            return Ok(());
+        }
+        if state.endpoints.len() >= self.config.max_endpoints {
+            return Err(EndpointError::Limit { identity: alias.to_string(), limit: self.config.max_endpoints });
+        }
+        // The endpoint outbound calls use, so the identity binds only one
+        let endpoint = fastn_p2p::client::Client::global(identity.secret_key.clone())
+            .endpoint()
+            .await
+            .map_err(|source| EndpointError::Bind { identity: alias.to_string(), source: source.into() })?;
+        tracing::info!(target: fastn_p2p::console::TARGET, "🔌 Bound endpoint {} for {}", endpoint_id, alias);
+        state.endpoints.insert(endpoint_id, endpoint);
+        Ok(())
+    }
+
+    /// Drop identity `alias`'s endpoint, closing it
+    pub async fn take_offline(&self, alias: &str) {
+        let mut state = self.state.lock().await;
+        let Some(identity) = state.identities.remove(alias) else {
+            return;
+        };
+        let endpoint_id = identity.endpoint_id();
+        if let Some(endpoint) = state.endpoints.remove(&endpoint_id) {
+            tracing::info!(target: fastn_p2p::console::TARGET, "🔌 Closing endpoint {} of {}", endpoint_id, alias);
+            endpoint.close().await;
+        }
+    }
+
+    /// Rebind every endpoint after a network change, see [`super::network`]
+    pub async fn rebind_all(&self) {
+        let state = self.state.lock().await;
+        for endpoint in state.endpoints.values() {
+            fastn_p2p::network::rebind(endpoint).await;
+        }
+    }
+
+    /// Limits and the endpoint of every online identity, ordered by alias
+    pub async fn snapshot(&self) -> fastn_p2p_client::admin::EndpointsStatus {
+        use iroh::Watcher as _;
+
+        let state = self.state.lock().await;
+        let identities = state
+            .identities
+            .iter()
+            .map(|(alias, identity)| {
+                let endpoint_id = identity.endpoint_id();
+                let endpoint = state.endpoints.get(&endpoint_id);
+                fastn_p2p_client::admin::IdentityEndpoint {
+                    identity: alias.clone(),
+                    peer: identity.peer.clone(),
+                    ports: endpoint
+                        .map(|endpoint| endpoint.bound_sockets().into_iter().map(|addr| addr.port()).collect())
+                        .unwrap_or_default(),
+                    relay: endpoint
+                        .and_then(|endpoint| endpoint.home_relay().get().into_iter().next())
+                        .map(|url| url.to_string()),
+                    endpoint: endpoint_id,
+                    stopped: endpoint.is_none(),
+                    idle_secs: identity.last_used.elapsed().as_secs(),
+                }
+            })
+            .collect();
+        fastn_p2p_client::admin::EndpointsStatus {
+            max_endpoints: self.config.max_endpoints,
+            bound: state.endpoints.len(),
+            lazy: self.config.lazy,
+            idle_stop_secs: self.config.idle_stop_secs,
+            identities,
+        }
+    }
+}
+
+/// Note an outbound use of identity `alias`, see [`EndpointPool::touch`]
+///
+/// Calls go out over the client's own connections, so an endpoint that can't
+/// be bound is reported without failing them.
+pub async fn touch(alias: &str) {
+    if let Err(e) = global().touch(alias).await {
+        tracing::warn!("{}", e);
+    }
+}
+
+/// Stop idle endpoints as `idle_stop_secs` says; returns at once when it is 0
+pub async fn run() {
+    let pool = global();
+    if pool.config.idle_stop_secs == 0 {
+        return;
+    }
+    let idle = std::time::Duration::from_secs(pool.config.idle_stop_secs);
+    let mut interval = tokio::time::interval((idle / 4).max(std::time::Duration::from_secs(1)));
+    loop {
+        interval.tick().await;
+        pool.stop_idle(idle).await;
+    }
+}
+
+/// Bring the pool in line with an identity going online or offline
+pub async fn apply(
+    fastn_home: &std::path::Path,
+    change: &fastn_p2p::server::ConfigChange,
+) -> Result<(), EndpointError> {
+    match change {
+        fastn_p2p::server::ConfigChange::IdentityOnline { identity } => {
+            let identity_dir = fastn_home.join("identities").join(identity);
+            let (_, secret_key) = fastn_id52::SecretKey::load_from_dir(&identity_dir, "identity")
+                .map_err(|e| EndpointError::Key { identity: identity.clone(), message: e.to_string() })?;
+            global().bring_online(identity, &secret_key).await
+        }
+        fastn_p2p::server::ConfigChange::IdentityOffline { identity } => {
+            global().take_offline(identity).await;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints_config() {
+        let config: EndpointsConfig = toml::from_str("max_endpoints = 4\n").unwrap();
+        assert_eq!(config.max_endpoints, 4);
+        assert!(!config.lazy);
+        assert_eq!(config.idle_stop_secs, 0);
+        let config: EndpointsConfig =
+            toml::from_str("lazy = true\nidle_stop_secs = 600\nalways_on = [\"alice\"]\n").unwrap();
+        assert!(config.lazy);
+        assert_eq!((config.idle_stop_secs, config.always_on), (600, vec!["alice".to_string()]));
+        assert_eq!(EndpointsConfig::default().max_endpoints, 16);
+        assert!(toml::from_str::<EndpointsConfig>("strategy = \"shared\"\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limit_is_checked_before_binding() {
+        let pool = EndpointPool::new(EndpointsConfig { max_endpoints: 0, ..Default::default() });
+        let error = pool.bring_online("alice", &fastn_id52::SecretKey::generate()).await.unwrap_err();
+        assert!(matches!(error, EndpointError::Limit { limit: 0, .. }));
+        assert!(pool.snapshot().await.identities[0].stopped);
+
+        pool.take_offline("alice").await;
+        assert_eq!(pool.snapshot().await.identities, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_lazy_identities_bind_on_first_use() {
+        let pool = EndpointPool::new(EndpointsConfig { max_endpoints: 0, lazy: true, ..Default::default() });
+        let key = fastn_id52::SecretKey::generate();
+        pool.bring_online("alice", &key).await.unwrap();
+        let status = pool.snapshot().await;
+        assert_eq!(status.bound, 0);
+        assert!(status.identities[0].stopped);
+        assert_eq!(status.identities[0].ports, Vec::<u16>::new());
+
+        // The first use tries to bind, here into the limit
+        assert!(matches!(pool.touch("alice").await, Err(EndpointError::Limit { .. })));
+        pool.touch("bob").await.unwrap();
+        assert_eq!(pool.stop_idle(std::time::Duration::ZERO).await, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,54 @@
+//! Prometheus endpoint for the daemon's handler metrics
+//!
+//! Off unless the `[metrics]` section of `config.toml` gives an address:
+//!
+//! ```toml
+//! [metrics]
+//! listen = "127.0.0.1:9464"
+//! ```
+//!
+//! `GET /metrics` then answers with [`fastn_p2p::metrics::prometheus`].
+
+/// `[metrics]` in `config.toml`
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address to serve `/metrics` on; none by default
+    pub listen: Option<std::net::SocketAddr>,
+}
+
+type MetricsResponse = fastn_net::http::ProxyResponse<std::convert::Infallible>;
+
+/// Serve `/metrics` on `addr` until the daemon exits
+pub async fn serve(addr: std::net::SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!(target: fastn_p2p::console::TARGET, "📈 Prometheus metrics on http://{}/metrics", listener.local_addr()?);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(|request| async move {
+                Ok::<_, std::convert::Infallible>(respond(&request))
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Metrics connection closed: {e}");
+            }
+        });
+    }
+}
+
+fn respond(request: &hyper::Request<hyper::body::Incoming>) -> MetricsResponse {
+    if request.method() != hyper::Method::GET || request.uri().path() != "/metrics" {
+        return fastn_net::http::bytes_to_resp(b"Not found, try /metrics".to_vec(), hyper::StatusCode::NOT_FOUND);
+    }
+    let mut response =
+        fastn_net::http::bytes_to_resp(fastn_p2p::metrics::prometheus().into_bytes(), hyper::StatusCode::OK);
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    response
+}
@@ -1,13 +1,17 @@
 //! Daemon functionality for fastn-p2p
 //!
-//! The daemon runs two main services:
-//! 1. Control socket server - handles client requests via Unix domain socket
-//! 2. P2P listener - handles incoming P2P connections and protocols
+//! The daemon's main service is the control socket server, which handles
+//! client requests via a Unix domain socket and applies identity changes
+//! itself. Online identities get outbound endpoints, see [`endpoints`];
+//! incoming P2P connections are served by the applications, see
+//! [`fastn_p2p::server::serve_all`].
+//!
+//! A config watcher applies edits under `identities/` as they happen, and
+//! the outbox service delivers queued messages, see [`outbox`]. Protocol
+//! notifications reach the user as the binding configures, see [`notifier`].
+//! Daemon-wide settings are read from `config.toml` at start, see [`config`].
 
-use std::path::PathBuf;
-use std::fs::OpenOptions;
-use fs2::FileExt;
-use tokio::sync::broadcast;
+use std::path::{Path, PathBuf};
 
 /// Daemon context containing runtime state and lock
 #[derive(Debug)]
@@ -16,36 +20,34 @@ pub struct DaemonContext {
     pub _lock_file: std::fs::File, // Keep lock file open to maintain exclusive access
 }
 
-/// Coordination channels for daemon services
-#[derive(Debug)]
-pub struct CoordinationChannels {
-    pub command_tx: broadcast::Sender<DaemonCommand>,
-    pub response_tx: broadcast::Sender<DaemonResponse>,
-}
-
+pub mod breaker;
+pub mod cache;
+pub mod config;
 pub mod control;
-pub mod p2p;
+pub mod endpoints;
+pub mod metrics;
+pub mod network;
+pub mod notifier;
+pub mod outbox;
+#[allow(dead_code)] // Loaded through protocol_trait once the daemon serves them
 pub mod protocols;
+pub mod scheduler;
 pub mod test_protocols;
+pub mod usage;
+pub mod version;
+#[allow(dead_code)] // Not called until the daemon loads bindings
 pub mod protocol_trait;
 
-/// Daemon command for coordinating between control socket and P2P
+/// Identity and binding change, applied by the daemon or by `fastn-p2p` itself
 #[derive(Debug, Clone)]
 pub enum DaemonCommand {
-    /// Make a request/response call to a peer
-    Call {
-        peer: fastn_id52::PublicKey,
-        protocol: String,
-        request_data: serde_json::Value,
-    },
-    /// Open a stream to a peer  
-    Stream {
-        peer: fastn_id52::PublicKey,
-        protocol: String,
-        initial_data: serde_json::Value,
-    },
     /// Reload identity configurations from disk
     ReloadIdentities,
+    /// Create an identity with the given key, or a fresh one
+    CreateIdentity {
+        identity: String,
+        secret_key: Option<fastn_id52::SecretKey>,
+    },
     /// Set an identity online/offline
     SetIdentityState {
         identity: String,
@@ -56,7 +58,7 @@ pub enum DaemonCommand {
         identity: String,
         protocol: String,
         bind_alias: String,
-        config: serde_json::Value,
+        config: fastn_p2p_client::Sensitive<serde_json::Value>,
     },
     /// Remove a protocol binding from an identity
     RemoveProtocol {
@@ -64,34 +66,22 @@ pub enum DaemonCommand {
         protocol: String,
         bind_alias: String,
     },
-    /// Shutdown the daemon
-    Shutdown,
 }
 
 /// Daemon response back to control socket clients
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
 pub enum DaemonResponse {
-    /// Successful call response
-    CallResponse {
-        response_data: serde_json::Value,
-    },
-    /// Call error
-    CallError {
-        error: String,
-    },
-    /// Stream established
-    StreamReady {
-        stream_id: u64,
-    },
-    /// Stream error
-    StreamError {
-        error: String,
-    },
     /// Identity configurations reloaded
     IdentitiesReloaded {
         total: usize,
         online: usize,
     },
+    /// Identity created; its key stays in FASTN_HOME
+    IdentityCreated {
+        identity: String,
+        peer: String,
+    },
     /// Identity state changed successfully
     IdentityStateChanged {
         identity: String,
@@ -102,6 +92,8 @@ pub enum DaemonResponse {
         identity: String,
         protocol: String,
         bind_alias: String,
+        /// Listener state in the daemon; `None` until something serves it
+        state: Option<fastn_p2p::server::ListenerState>,
     },
     /// Protocol binding removed successfully
     ProtocolRemoved {
@@ -109,45 +101,101 @@ pub enum DaemonResponse {
         protocol: String,
         bind_alias: String,
     },
-    /// Operation error
-    OperationError {
-        error: String,
-    },
 }
 
-/// Run the fastn-p2p daemon with both control socket and P2P listener
+/// Run the fastn-p2p daemon: the control socket and the services behind it
 pub async fn run(fastn_home: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    // Status lines of the library crates print like ours, see fastn_p2p::console
+    let subscriber = tracing_subscriber::registry().with(crate::cli::output::console_layer().filtered());
+    #[cfg(feature = "otlp")]
+    let (subscriber, otlp) = match fastn_p2p::otlp::layer("fastn-p2p-daemon")? {
+        Some((layer, provider)) => (subscriber.with(Some(layer)), Some(provider)),
+        None => (subscriber.with(None), None),
+    };
+    subscriber.try_init()?;
+    #[cfg(feature = "otlp")]
+    if otlp.is_some() {
+        tracing::info!(target: fastn_p2p::console::TARGET, "📡 Exporting traces over OTLP");
+    }
+
     // Initialize daemon environment
     let daemon_context = initialize_daemon(&fastn_home).await?;
     
-    // Set up coordination channels
-    let coordination = setup_coordination_channels().await?;
+    // Settings from config.toml, defaults for anything left out
+    let daemon_config = config::DaemonConfig::load(&fastn_home).await?;
+    tracing::info!(target: fastn_p2p::console::TARGET, "⚙️  Circuit breaker: {:?}", daemon_config.circuit_breaker);
+    breaker::init(daemon_config.circuit_breaker);
+    tracing::info!(target: fastn_p2p::console::TARGET, "⚙️  Scheduler: {:?}", daemon_config.scheduler);
+    scheduler::init(daemon_config.scheduler);
+    tracing::info!(target: fastn_p2p::console::TARGET, "⚙️  Call cache: {:?}", daemon_config.cache);
+    cache::init(daemon_config.cache);
+    tracing::info!(target: fastn_p2p::console::TARGET, "⚙️  Endpoints: {:?}", daemon_config.endpoints);
+    endpoints::init(daemon_config.endpoints);
+    tokio::spawn(endpoints::run());
+    tokio::spawn(network::run(daemon_config.network));
+    tracing::info!(target: fastn_p2p::console::TARGET, "⚙️  Usage: {:?}", daemon_config.usage);
+    tokio::spawn(usage::run(fastn_home.clone(), daemon_config.usage));
+    tracing::info!(target: fastn_p2p::console::TARGET, "⚙️  Update: {:?}", daemon_config.update);
+    tokio::spawn(version::run(daemon_config.update));
+    if let Some(addr) = daemon_config.metrics.listen {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr).await {
+                tracing::error!("Prometheus endpoint on {} failed: {}", addr, e);
+            }
+        });
+    }
     
     // Start P2P networking layer
-    start_p2p_service(&daemon_context, &coordination).await?;
+    start_p2p_service(&daemon_context).await?;
+    
+    // Pick up config edits on disk
+    start_watch_service(fastn_home.clone()).await?;
+    
+    // Deliver queued messages in the background
+    tokio::spawn(outbox::run(fastn_home.clone()));
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ Outbox delivery task spawned");
+    
+    // Desktop notifications and hook commands for protocol events
+    tokio::spawn(notifier::run(fastn_home.clone()));
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ Notifier task spawned");
     
     // Start control socket service
-    start_control_service(fastn_home, &coordination).await?;
+    start_control_service(fastn_home).await?;
     
     // Run main coordination loop
-    run_coordination_loop(coordination).await?;
+    run_coordination_loop().await?;
     
     Ok(())
 }
 
 /// Initialize daemon environment with identity management
-async fn initialize_daemon(fastn_home: &PathBuf) -> Result<DaemonContext, Box<dyn std::error::Error>> {
+async fn initialize_daemon(fastn_home: &Path) -> Result<DaemonContext, Box<dyn std::error::Error>> {
     // Use generic server utilities
     fastn_p2p::server::ensure_fastn_home(fastn_home).await?;
     let lock_file = fastn_p2p::server::acquire_singleton_lock(fastn_home).await?;
     
+    // Older layouts would leave identities undiscovered
+    let migrated = fastn_p2p::server::home::migrate(fastn_home).await?;
+    if !migrated.is_up_to_date() {
+        tracing::info!(target: fastn_p2p::console::TARGET, "🧳 Migrated {} from layout version {} to {}", fastn_home.display(), migrated.from, migrated.to);
+        if let Some(backup) = &migrated.backup {
+            tracing::info!(target: fastn_p2p::console::TARGET, "   Backup: {}", backup.display());
+        }
+        for step in migrated.migrations.iter().flat_map(|migration| &migration.steps) {
+            tracing::info!(target: fastn_p2p::console::TARGET, "   {}", step);
+        }
+    }
+    
     // Load all available identity configurations  
     let all_identities = fastn_p2p::server::load_all_identities(fastn_home).await?;
     
     if all_identities.is_empty() {
-        println!("⚠️  No identities found in {}/identities/", fastn_home.display());
-        println!("   Daemon will start and wait for identities to be created");
-        println!("   Create an identity with: fastn-p2p create-identity <alias>");
+        tracing::warn!("No identities found in {}/identities/", fastn_home.display());
+        tracing::info!(target: fastn_p2p::console::TARGET, "   Daemon will start and wait for identities to be created");
+        tracing::info!(target: fastn_p2p::console::TARGET, "   Create an identity with: fastn-p2p create-identity <alias>");
     } else {
         // Show status of all identities
         let online_count = all_identities.iter().filter(|id| id.online).count();
@@ -156,13 +204,13 @@ async fn initialize_daemon(fastn_home: &PathBuf) -> Result<DaemonContext, Box<dy
             .map(|id| id.protocols.len())
             .sum();
             
-        println!("🔑 Loaded {} identities ({} online)", all_identities.len(), online_count);
+        tracing::info!(target: fastn_p2p::console::TARGET, "🔑 Loaded {} identities ({} online)", all_identities.len(), online_count);
         
         for identity in &all_identities {
             let status_icon = if identity.online { "🟢" } else { "🔴" };
             let status_text = if identity.online { "ONLINE" } else { "OFFLINE" };
             
-            println!("   {} {} ({}) - {} protocols", 
+            tracing::info!(target: fastn_p2p::console::TARGET, "   {} {} ({}) - {} protocols", 
                     status_icon, 
                     identity.alias, 
                     status_text,
@@ -170,39 +218,22 @@ async fn initialize_daemon(fastn_home: &PathBuf) -> Result<DaemonContext, Box<dy
         }
         
         if online_count == 0 {
-            println!("⚠️  No online identities - no P2P services will be started");
-            println!("   Enable identities with: fastn-p2p identity-online <alias>");
+            tracing::warn!("No online identities - no P2P services will be started");
+            tracing::info!(target: fastn_p2p::console::TARGET, "   Enable identities with: fastn-p2p identity-online <alias>");
         } else {
-            println!("✅ Will start {} P2P services for online identities", total_protocols);
+            tracing::info!(target: fastn_p2p::console::TARGET, "✅ Will start {} P2P services for online identities", total_protocols);
         }
     }
     
     Ok(DaemonContext {
-        fastn_home: fastn_home.clone(),
+        fastn_home: fastn_home.to_path_buf(),
         _lock_file: lock_file,
     })
 }
 
-/// Set up broadcast channels for coordination between services
-async fn setup_coordination_channels() -> Result<CoordinationChannels, Box<dyn std::error::Error>> {
-    // Create broadcast channels for communication between control socket and P2P services
-    let (command_tx, _command_rx) = broadcast::channel::<DaemonCommand>(100);
-    let (response_tx, _response_rx) = broadcast::channel::<DaemonResponse>(100);
-    
-    println!("📡 Created coordination channels");
-    println!("   Command channel: 100 message buffer");
-    println!("   Response channel: 100 message buffer");
-    
-    Ok(CoordinationChannels {
-        command_tx,
-        response_tx,
-    })
-}
-
-/// Start the P2P networking service
+/// Start the P2P networking service: endpoints for the online identities
 async fn start_p2p_service(
     daemon_context: &DaemonContext,
-    coordination: &CoordinationChannels,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Load current online identities for P2P services
     let online_identities: Vec<_> = fastn_p2p::server::load_all_identities(&daemon_context.fastn_home)
@@ -212,60 +243,111 @@ async fn start_p2p_service(
         .collect();
     
     if online_identities.is_empty() {
-        println!("📡 P2P service: No online identities - waiting for activation");
-        // Still spawn the P2P task to handle future commands
+        tracing::info!(target: fastn_p2p::console::TARGET, "📡 P2P service: No online identities - waiting for activation");
     } else {
         let total_protocols: usize = online_identities.iter().map(|id| id.protocols.len()).sum();
-        println!("📡 P2P service: Starting {} protocols for {} online identities", 
+        tracing::info!(target: fastn_p2p::console::TARGET, "📡 P2P service: Starting {} protocols for {} online identities", 
                 total_protocols, online_identities.len());
         
         for identity in &online_identities {
-            println!("   🟢 {} - {} protocols", identity.alias, identity.protocols.len());
+            tracing::info!(target: fastn_p2p::console::TARGET, "   🟢 {} - {} protocols", identity.alias, identity.protocols.len());
+            if let Err(e) = endpoints::global().bring_online(&identity.alias, &identity.secret_key).await {
+                tracing::info!(target: fastn_p2p::console::TARGET, "   ⚠️  {}", e);
+            }
         }
     }
     
-    // Spawn P2P service task
-    let command_rx = coordination.command_tx.subscribe();
-    let response_tx = coordination.response_tx.clone();
-    let fastn_home = daemon_context.fastn_home.clone();
-    
-    tokio::spawn(async move {
-        if let Err(e) = p2p::run(fastn_home, command_rx, response_tx).await {
-            eprintln!("❌ P2P service error: {}", e);
-        }
-    });
-    
-    println!("✅ P2P service task spawned");
     Ok(())
 }
 
 /// Start the control socket service
 async fn start_control_service(
     fastn_home: PathBuf,
-    coordination: &CoordinationChannels,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Spawn control socket server task
-    let command_tx = coordination.command_tx.clone();
-    let response_rx = coordination.response_tx.subscribe();
+    tokio::spawn(async move {
+        if let Err(e) = control::run(fastn_home).await {
+            tracing::error!("Control socket service error: {}", e);
+        }
+    });
     
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ Control socket service task spawned");
+    Ok(())
+}
+
+/// Start the service that applies binding config and online marker edits
+///
+/// Listeners and endpoints are restarted or stopped right away, as if
+/// `identity-online`/`identity-offline` had been run.
+async fn start_watch_service(
+    fastn_home: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut watcher = match fastn_p2p::server::ConfigWatcher::new(&fastn_home) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!("Config watcher unavailable ({}), use `fastn-p2p reload` after edits", e);
+            return Ok(());
+        }
+    };
     tokio::spawn(async move {
-        if let Err(e) = control::run(fastn_home, command_tx, response_rx).await {
-            eprintln!("❌ Control socket service error: {}", e);
+        while let Some(changes) = watcher.next().await {
+            for change in changes {
+                tracing::info!(target: fastn_p2p::console::TARGET, "🔁 Config change: {:?}", change);
+                if let Err(e) = fastn_p2p::server::watch::apply_to_listeners(&fastn_home, &change).await {
+                    tracing::warn!("{}", e);
+                    publish_change_error(&change, e.to_string());
+                }
+                if let Err(e) = endpoints::apply(&fastn_home, &change).await {
+                    tracing::warn!("{}", e);
+                    publish_change_error(&change, e.to_string());
+                }
+                match change {
+                    fastn_p2p::server::ConfigChange::IdentityOnline { identity } => {
+                        fastn_p2p::events::publish(fastn_p2p::events::EventKind::IdentityStateChanged {
+                            identity: identity.clone(),
+                            online: true,
+                        });
+                    }
+                    fastn_p2p::server::ConfigChange::IdentityOffline { identity } => {
+                        fastn_p2p::events::publish(fastn_p2p::events::EventKind::IdentityStateChanged {
+                            identity: identity.clone(),
+                            online: false,
+                        });
+                    }
+                    fastn_p2p::server::ConfigChange::BindingChanged { .. }
+                    | fastn_p2p::server::ConfigChange::BindingRemoved { .. } => {}
+                }
+            }
         }
     });
     
-    println!("✅ Control socket service task spawned");
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ Config watch service task spawned");
     Ok(())
 }
 
+/// Tell event subscribers that applying `change` failed with `error`
+fn publish_change_error(change: &fastn_p2p::server::ConfigChange, error: String) {
+    let (identity, protocol, bind_alias) = match change {
+        fastn_p2p::server::ConfigChange::IdentityOnline { identity }
+        | fastn_p2p::server::ConfigChange::IdentityOffline { identity } => (identity, None, None),
+        fastn_p2p::server::ConfigChange::BindingChanged { identity, protocol, bind_alias, .. }
+        | fastn_p2p::server::ConfigChange::BindingRemoved { identity, protocol, bind_alias } => {
+            (identity, Some(protocol.clone()), Some(bind_alias.clone()))
+        }
+    };
+    fastn_p2p::events::publish(fastn_p2p::events::EventKind::ProtocolError {
+        identity: Some(identity.clone()),
+        protocol,
+        bind_alias,
+        error,
+    });
+}
+
 /// Run the main coordination loop that handles service lifecycle
-async fn run_coordination_loop(
-    _coordination: CoordinationChannels,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("🔄 Starting main coordination loop");
-    println!("   - P2P service: Running in background");
-    println!("   - Control socket: Running in background");
-    println!("   - Coordination: Active via broadcast channels");
+async fn run_coordination_loop() -> Result<(), Box<dyn std::error::Error>> {
+    tracing::info!(target: fastn_p2p::console::TARGET, "🔄 Starting main coordination loop");
+    tracing::info!(target: fastn_p2p::console::TARGET, "   - P2P service: Running in background");
+    tracing::info!(target: fastn_p2p::console::TARGET, "   - Control socket: Running in background");
     
     // Keep the daemon running - both services are now spawned
     // TODO: Handle shutdown signals, coordinate service lifecycle
@@ -274,40 +356,4 @@ async fn run_coordination_loop(
         // Services are running in background tasks
         // Main loop keeps daemon alive and can handle coordination
     }
-}
-
-async fn get_or_create_daemon_key(
-    fastn_home: &PathBuf,
-) -> Result<fastn_id52::SecretKey, Box<dyn std::error::Error>> {
-    let key_file = fastn_home.join("daemon.key");
-
-    // Try to load existing key
-    if key_file.exists() {
-        if let Ok(key_bytes) = tokio::fs::read(&key_file).await {
-            if key_bytes.len() == 32 {
-                let mut bytes_array = [0u8; 32];
-                bytes_array.copy_from_slice(&key_bytes);
-                let secret_key = fastn_id52::SecretKey::from_bytes(&bytes_array);
-                println!("🔑 Loaded daemon key from: {}", key_file.display());
-                return Ok(secret_key);
-            }
-        }
-        println!("⚠️  Could not load key from {}, generating new one", key_file.display());
-    }
-
-    // Generate new key
-    let key = fastn_id52::SecretKey::generate();
-
-    // Try to save it
-    match tokio::fs::write(&key_file, &key.to_secret_bytes()).await {
-        Ok(_) => {
-            println!("🔑 Generated and saved daemon key to: {}", key_file.display());
-        }
-        Err(e) => {
-            println!("⚠️  Could not save key to {} ({})", key_file.display(), e);
-            println!("   Using temporary key - daemon ID will change on restart");
-        }
-    }
-
-    Ok(key)
 }
\ No newline at end of file
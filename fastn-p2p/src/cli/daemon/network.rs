@@ -0,0 +1,41 @@
+//! Rebinding the daemon's endpoints when the host changes networks
+//!
+//! The daemon polls the host's local addresses and, once they change (e.g.
+//! the laptop joined another Wi-Fi network), rebinds every endpoint, has it
+//! re-register with its relay and drops cached peer connections; streams
+//! relayed for clients end with an error telling them to reconnect. See
+//! [`fastn_p2p::network`]. The interval is set in `config.toml`:
+//!
+//! ```toml
+//! [network]
+//! poll_secs = 5   # 0 turns the monitor off
+//! ```
+
+/// `[network]` in `config.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Seconds between looks at the local addresses; 0 turns the monitor off
+    pub poll_secs: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self { poll_secs: 5 }
+    }
+}
+
+/// Rebind after every network change until the daemon exits
+pub async fn run(config: NetworkConfig) {
+    if config.poll_secs == 0 {
+        tracing::info!(target: fastn_p2p::console::TARGET, "📶 Network monitor off");
+        return;
+    }
+    let mut monitor = fastn_p2p::network::NetworkMonitor::new(std::time::Duration::from_secs(config.poll_secs));
+    loop {
+        let change = monitor.next().await;
+        tracing::info!(target: fastn_p2p::console::TARGET, "📶 Network changed: {:?} → {:?}, rebinding endpoints", change.before, change.after);
+        super::endpoints::global().rebind_all().await;
+        fastn_p2p::network::network_changed(change).await;
+    }
+}
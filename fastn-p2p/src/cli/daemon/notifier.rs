@@ -0,0 +1,272 @@
+//! Telling the user about protocol events, until GUI clients exist
+//!
+//! Protocols publish an [`EventKind::Notification`] when something needs the
+//! user's attention: new mail, an incoming file offer, a device pairing. A
+//! binding decides what happens with a `notify.json` next to its
+//! `config.json`; bindings without one stay quiet:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "on": "new-mail", "desktop": true },
+//!     { "on": "*", "command": "/usr/local/bin/on-fastn-event", "args": ["--log"] }
+//!   ]
+//! }
+//! ```
+//!
+//! Every rule whose `on` matches the notification name (`*` matches all)
+//! fires. Commands get the notification in `FASTN_NOTIFY_*` environment
+//! variables rather than as arguments, so peer-supplied text never ends up
+//! on a command line. Desktop notifications need the
+//! `desktop-notifications` feature.
+//!
+//! Device pairings are notified for the `fastn-p2p-device` protocol and bind
+//! alias `default`.
+
+use std::path::{Path, PathBuf};
+
+use fastn_p2p::events::EventKind;
+
+/// Per-binding notification settings file
+pub const NOTIFY_FILE: &str = "notify.json";
+
+/// Commands still running after this long are killed
+const COMMAND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// `notify.json` of a binding
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub rules: Vec<NotifyRule>,
+}
+
+/// What to do for notifications named `on`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyRule {
+    /// Notification name, e.g. `new-mail`; `*` for every notification
+    pub on: String,
+    /// Show a desktop notification
+    #[serde(default)]
+    pub desktop: bool,
+    /// Program to run
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl NotifyRule {
+    pub fn matches(&self, name: &str) -> bool {
+        self.on == "*" || self.on == name
+    }
+}
+
+/// One notification, as handed to the rules of its binding
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub identity: String,
+    pub protocol: String,
+    pub bind_alias: String,
+    pub name: String,
+    pub peer: Option<String>,
+    pub title: String,
+    pub body: String,
+}
+
+impl Notification {
+    fn from_event(kind: EventKind) -> Option<Self> {
+        match kind {
+            EventKind::Notification { identity, protocol, bind_alias, name, peer, title, body } => {
+                Some(Self { identity, protocol, bind_alias, name, peer, title, body })
+            }
+            _ => None,
+        }
+    }
+
+    /// `FASTN_NOTIFY_*` variables commands are run with
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("FASTN_NOTIFY_NAME", self.name.clone()),
+            ("FASTN_NOTIFY_IDENTITY", self.identity.clone()),
+            ("FASTN_NOTIFY_PROTOCOL", self.protocol.clone()),
+            ("FASTN_NOTIFY_BIND_ALIAS", self.bind_alias.clone()),
+            ("FASTN_NOTIFY_PEER", self.peer.clone().unwrap_or_default()),
+            ("FASTN_NOTIFY_TITLE", self.title.clone()),
+            ("FASTN_NOTIFY_BODY", self.body.clone()),
+        ]
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NotifyError {
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Failed to run '{command}': {source}")]
+    Spawn {
+        command: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("'{command}' did not finish within {timeout:?}")]
+    Timeout { command: String, timeout: std::time::Duration },
+
+    #[cfg(feature = "desktop-notifications")]
+    #[error("Desktop notification failed: {message}")]
+    Desktop { message: String },
+
+    #[error("Desktop notifications need the desktop-notifications feature")]
+    DesktopUnavailable,
+}
+
+/// Load the `notify.json` of a binding; `None` when it has none
+pub async fn load(
+    fastn_home: &Path,
+    identity: &str,
+    protocol: &str,
+    bind_alias: &str,
+) -> Result<Option<NotifyConfig>, NotifyError> {
+    let path = fastn_home
+        .join("identities")
+        .join(identity)
+        .join("protocols")
+        .join(protocol)
+        .join(bind_alias)
+        .join(NOTIFY_FILE);
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => return Err(NotifyError::Io { path, source }),
+    };
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|source| NotifyError::Parse { path, source })
+}
+
+/// Act on notifications published in the daemon until it exits
+pub async fn run(fastn_home: PathBuf) {
+    let mut events = fastn_p2p::events::subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!("Notifier fell behind, {} events missed", missed);
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+        let Some(notification) = Notification::from_event(event.kind) else {
+            continue;
+        };
+        // A slow command must not hold up the notifications after it
+        let fastn_home = fastn_home.clone();
+        tokio::spawn(async move {
+            if let Err(e) = notify(&fastn_home, &notification).await {
+                tracing::warn!("Notification '{}' of {}: {}", notification.name, notification.identity, e);
+            }
+        });
+    }
+}
+
+/// Fire every rule of the notification's binding that matches it
+async fn notify(fastn_home: &Path, notification: &Notification) -> Result<(), NotifyError> {
+    let config = match load(fastn_home, &notification.identity, &notification.protocol, &notification.bind_alias).await? {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    for rule in config.rules.iter().filter(|rule| rule.matches(&notification.name)) {
+        tracing::info!(target: fastn_p2p::console::TARGET, "🔔 {} for {} {} {}", notification.name, notification.identity, notification.protocol, notification.bind_alias);
+        if rule.desktop
+            && let Err(e) = show_desktop(notification).await
+        {
+            tracing::warn!("{}", e);
+        }
+        if let Some(command) = &rule.command
+            && let Err(e) = run_command(command, &rule.args, notification).await
+        {
+            tracing::warn!("{}", e);
+        }
+    }
+    Ok(())
+}
+
+async fn run_command(command: &str, args: &[String], notification: &Notification) -> Result<(), NotifyError> {
+    let mut child = tokio::process::Command::new(command)
+        .args(args)
+        .envs(notification.env())
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|source| NotifyError::Spawn { command: command.to_string(), source })?;
+    match tokio::time::timeout(COMMAND_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if !status.success() => tracing::warn!("'{}' exited with {}", command, status),
+        Ok(Ok(_)) => {}
+        Ok(Err(source)) => return Err(NotifyError::Spawn { command: command.to_string(), source }),
+        Err(_elapsed) => return Err(NotifyError::Timeout { command: command.to_string(), timeout: COMMAND_TIMEOUT }),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "desktop-notifications")]
+async fn show_desktop(notification: &Notification) -> Result<(), NotifyError> {
+    let title = notification.title.clone();
+    let body = notification.body.clone();
+    // Talks to the notification service synchronously
+    tokio::task::spawn_blocking(move || {
+        notify_rust::Notification::new()
+            .appname("fastn-p2p")
+            .summary(&title)
+            .body(&body)
+            .show()
+            .map(|_| ())
+            .map_err(|e| NotifyError::Desktop { message: e.to_string() })
+    })
+    .await
+    .map_err(|e| NotifyError::Desktop { message: e.to_string() })?
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+async fn show_desktop(_notification: &Notification) -> Result<(), NotifyError> {
+    Err(NotifyError::DesktopUnavailable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rules_of_binding_match_notification_name() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let binding_dir = fastn_home.join("identities/alice/protocols/mail.fastn.com/default");
+        tokio::fs::create_dir_all(&binding_dir).await.unwrap();
+
+        assert!(load(&fastn_home, "alice", "mail.fastn.com", "default").await.unwrap().is_none());
+
+        let rules = r#"{"rules": [{"on": "new-mail", "desktop": true}, {"on": "*", "command": "true"}]}"#;
+        tokio::fs::write(binding_dir.join(NOTIFY_FILE), rules).await.unwrap();
+        let config = load(&fastn_home, "alice", "mail.fastn.com", "default").await.unwrap().unwrap();
+        let matching = |name: &str| config.rules.iter().filter(|rule| rule.matches(name)).count();
+        assert_eq!(matching("new-mail"), 2);
+        assert_eq!(matching("file-offer"), 1);
+
+        tokio::fs::write(binding_dir.join(NOTIFY_FILE), r#"{"rules": [{"on": "new-mail", "sound": true}]}"#).await.unwrap();
+        assert!(matches!(
+            load(&fastn_home, "alice", "mail.fastn.com", "default").await,
+            Err(NotifyError::Parse { .. })
+        ));
+    }
+}
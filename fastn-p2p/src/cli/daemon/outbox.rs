@@ -0,0 +1,49 @@
+//! Delivery of queued messages
+//!
+//! Messages queued with `fastn-p2p send` wait in the outbox of their
+//! identity in `state.db`. This service hands them to
+//! [`fastn_p2p::server::delivery::deliver_pending`] for every online
+//! identity, right after something was queued and every
+//! [`RETRY_INTERVAL`] after that, until each one has a receipt.
+
+use std::path::{Path, PathBuf};
+
+/// Wait between delivery rounds while messages are pending
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+static WAKE: std::sync::LazyLock<tokio::sync::Notify> = std::sync::LazyLock::new(tokio::sync::Notify::new);
+
+/// Start a delivery round now instead of at the next retry
+pub fn wake() {
+    WAKE.notify_one();
+}
+
+/// Deliver pending messages until the daemon exits
+pub async fn run(fastn_home: PathBuf) {
+    loop {
+        if let Err(e) = deliver_all(&fastn_home).await {
+            tracing::warn!("Outbox delivery failed: {}", e);
+        }
+        tokio::select! {
+            _ = WAKE.notified() => {}
+            _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+        }
+    }
+}
+
+/// One delivery round over the outboxes of all online identities
+async fn deliver_all(fastn_home: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let store = fastn_p2p::server::state::StateStore::open(fastn_home).await?;
+    let identities = fastn_p2p::server::load_all_identities(fastn_home).await?;
+    for identity in identities {
+        if !identity.online {
+            continue;
+        }
+        let client = fastn_p2p::client::Client::global(identity.secret_key.clone());
+        let report = fastn_p2p::server::delivery::deliver_pending(&store, &identity.alias, &client).await?;
+        if report.delivered > 0 || report.failed > 0 {
+            tracing::info!(target: fastn_p2p::console::TARGET, "📬 Outbox of {}: {} delivered, {} to retry", identity.alias, report.delivered, report.failed);
+        }
+    }
+    Ok(())
+}
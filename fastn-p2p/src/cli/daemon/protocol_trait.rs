@@ -3,7 +3,7 @@
 //! This trait defines the standard interface that all protocols must implement
 //! for proper integration with the fastn-p2p daemon.
 
-use std::path::PathBuf;
+use std::path::Path;
 
 /// Protocol lifecycle management trait
 /// 
@@ -34,7 +34,7 @@ pub trait Protocol {
     /// ```
     async fn init(
         bind_alias: &str,
-        config_path: &PathBuf,
+        config_path: &Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
     
     /// Load protocol and start P2P services
@@ -49,7 +49,7 @@ pub trait Protocol {
     /// * `identity_key` - The identity's secret key for P2P operations
     async fn load(
         bind_alias: &str,
-        config_path: &PathBuf,
+        config_path: &Path,
         identity_key: &fastn_id52::SecretKey,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
     
@@ -63,7 +63,7 @@ pub trait Protocol {
     /// * `config_path` - Directory path containing updated config files
     async fn reload(
         bind_alias: &str,
-        config_path: &PathBuf,
+        config_path: &Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
     
     /// Stop protocol services cleanly
@@ -89,7 +89,7 @@ pub trait Protocol {
     /// * `config_path` - Directory path containing config files to validate
     async fn check(
         bind_alias: &str,
-        config_path: &PathBuf,
+        config_path: &Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
 }
 
@@ -111,7 +111,7 @@ pub fn get_available_protocols() -> Vec<&'static str> {
 pub async fn load_protocol(
     protocol_name: &str,
     bind_alias: &str,
-    config_path: &PathBuf,
+    config_path: &Path,
     identity_key: &fastn_id52::SecretKey,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     match protocol_name {
@@ -131,7 +131,7 @@ pub async fn load_protocol(
 pub async fn init_protocol(
     protocol_name: &str,
     bind_alias: &str,
-    config_path: &PathBuf,
+    config_path: &Path,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     match protocol_name {
         "Echo" => {
@@ -150,7 +150,7 @@ pub async fn init_protocol(
 pub async fn check_protocol(
     protocol_name: &str,
     bind_alias: &str,
-    config_path: &PathBuf,
+    config_path: &Path,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     match protocol_name {
         "Echo" => {
@@ -14,14 +14,14 @@ impl Protocol for EchoProtocol {
     
     async fn init(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        _config_path: &std::path::Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Create Echo config directory, write default echo config.json, set up Echo workspace for bind_alias: {}", bind_alias);
     }
     
     async fn load(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        config_path: &std::path::Path,
         identity_key: &fastn_id52::SecretKey,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Load Echo config from {}, start P2P Echo listener for identity {}, bind_alias: {}", config_path.display(), identity_key.public_key().id52(), bind_alias);
@@ -29,7 +29,7 @@ impl Protocol for EchoProtocol {
     
     async fn reload(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        config_path: &std::path::Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Reload Echo config from {}, restart Echo services for bind_alias: {}", config_path.display(), bind_alias);
     }
@@ -42,7 +42,7 @@ impl Protocol for EchoProtocol {
     
     async fn check(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        config_path: &std::path::Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Check Echo config at {} for bind_alias: {} - validate config.json, report issues", config_path.display(), bind_alias);
     }
@@ -50,7 +50,7 @@ impl Protocol for EchoProtocol {
 
 /// Handle Echo protocol requests
 pub async fn echo_handler(request: EchoRequest) -> Result<EchoResponse, EchoError> {
-    println!("📢 Echo request: {}", request.message);
+    tracing::info!(target: fastn_p2p::console::TARGET, "📢 Echo request: {}", request.message);
     
     // Simple validation
     if request.message.is_empty() {
@@ -65,7 +65,7 @@ pub async fn echo_handler(request: EchoRequest) -> Result<EchoResponse, EchoErro
         echoed: format!("Echo: {}", request.message),
     };
     
-    println!("📤 Echo response: {}", response.echoed);
+    tracing::info!(target: fastn_p2p::console::TARGET, "📤 Echo response: {}", response.echoed);
     Ok(response)
 }
 
@@ -1,6 +1,8 @@
 //! Shell protocol handler
 //!
-//! Streaming protocol for remote command execution.
+//! Streaming protocol for remote command execution. Only a few harmless
+//! commands are allowed, and their output is simulated: it is a test protocol.
+//! `cat` sends the peer's input back, which makes it interactive.
 
 use crate::cli::daemon::protocol_trait::Protocol;
 
@@ -19,6 +21,9 @@ pub struct ShellResponse {
     pub stderr: String,
 }
 
+/// Commands the Shell protocol runs
+const ALLOWED_COMMANDS: [&str; 6] = ["echo", "whoami", "pwd", "ls", "date", "cat"];
+
 /// Shell error types
 #[derive(Debug, thiserror::Error, serde::Serialize, serde::Deserialize)]
 pub enum ShellError {
@@ -39,14 +44,14 @@ impl Protocol for ShellProtocol {
     
     async fn init(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        _config_path: &std::path::Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Create Shell config directory, write default shell config.json with security settings for bind_alias: {}", bind_alias);
     }
     
     async fn load(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        config_path: &std::path::Path,
         identity_key: &fastn_id52::SecretKey,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Load Shell config from {}, start P2P Shell streaming listener for identity {}, bind_alias: {}", config_path.display(), identity_key.public_key().id52(), bind_alias);
@@ -54,7 +59,7 @@ impl Protocol for ShellProtocol {
     
     async fn reload(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        config_path: &std::path::Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Reload Shell config from {}, restart Shell services for bind_alias: {}", config_path.display(), bind_alias);
     }
@@ -67,54 +72,46 @@ impl Protocol for ShellProtocol {
     
     async fn check(
         bind_alias: &str,
-        config_path: &std::path::PathBuf,
+        config_path: &std::path::Path,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         todo!("Check Shell config at {} for bind_alias: {} - validate security settings, allowed commands", config_path.display(), bind_alias);
     }
 }
 
 /// Handle Shell protocol streaming sessions
+///
+/// The command's output is streamed back and the stream finished; `cat`
+/// copies the peer's input back until the peer finishes its side.
 pub async fn shell_stream_handler(
-    mut _session: fastn_p2p::Session<&'static str>,
+    mut session: fastn_p2p::Session<String>,
     command: ShellCommand,
     _state: (),
 ) -> Result<(), ShellError> {
-    println!("🐚 Shell command requested: {} {:?}", command.command, command.args);
-    
-    // Basic security checks - only allow safe commands for testing
-    let allowed_commands = ["echo", "whoami", "pwd", "ls", "date"];
-    if !allowed_commands.contains(&command.command.as_str()) {
-        return Err(ShellError::CommandNotAllowed { 
-            command: command.command.clone() 
-        });
-    }
-    
-    // TODO: Execute command and stream output bidirectionally
-    // For now, simulate command execution
-    let simulated_output = match command.command.as_str() {
-        "whoami" => "daemon_user".to_string(),
-        "pwd" => "/tmp/fastn-daemon".to_string(), 
-        "date" => chrono::Utc::now().to_rfc3339(),
-        "echo" => command.args.join(" "),
-        "ls" => "file1.txt\nfile2.txt\ndir1/".to_string(),
-        _ => "Command output".to_string(),
+    tracing::info!(target: fastn_p2p::console::TARGET, "🐚 Shell command requested: {} {:?}", command.command, command.args);
+    
+    let failed = |e: std::io::Error| ShellError::ExecutionFailed { message: e.to_string() };
+    let sent = match command.command.as_str() {
+        "cat" => {
+            let fastn_p2p::Session { send, recv, .. } = &mut session;
+            tokio::io::copy(recv, send).await.map_err(failed)?
+        }
+        _ => {
+            let response = execute_command(command).await?;
+            session.copy_from(response.stdout.as_bytes()).await.map_err(failed)?
+        }
     };
+    session.send.finish().map_err(|e| ShellError::ExecutionFailed { message: e.to_string() })?;
     
-    println!("📤 Shell output: {}", simulated_output);
-    
-    // TODO: Stream the output back to client via session
-    // session.copy_from(&mut simulated_output.as_bytes()).await?;
-    
+    tracing::info!(target: fastn_p2p::console::TARGET, "📤 Shell streamed {} bytes", sent);
     Ok(())
 }
 
 /// Execute a shell command safely (for request/response mode)
 pub async fn execute_command(command: ShellCommand) -> Result<ShellResponse, ShellError> {
-    println!("⚡ Executing shell command: {} {:?}", command.command, command.args);
+    tracing::info!(target: fastn_p2p::console::TARGET, "⚡ Executing shell command: {} {:?}", command.command, command.args);
     
     // Security check
-    let allowed_commands = ["echo", "whoami", "pwd", "ls", "date"];
-    if !allowed_commands.contains(&command.command.as_str()) {
+    if !ALLOWED_COMMANDS.contains(&command.command.as_str()) {
         return Err(ShellError::CommandNotAllowed { 
             command: command.command.clone() 
         });
@@ -127,6 +124,8 @@ pub async fn execute_command(command: ShellCommand) -> Result<ShellResponse, She
         "date" => (0, format!("{}\n", chrono::Utc::now().to_rfc3339())),
         "echo" => (0, format!("{}\n", command.args.join(" "))),
         "ls" => (0, "file1.txt\nfile2.txt\ndir1/\n".to_string()),
+        // Without a stream there is no input to copy
+        "cat" => (0, "".to_string()),
         _ => (1, "".to_string()),
     };
     
@@ -136,7 +135,7 @@ pub async fn execute_command(command: ShellCommand) -> Result<ShellResponse, She
         stderr: "".to_string(),
     };
     
-    println!("✅ Shell command completed with exit code: {}", exit_code);
+    tracing::info!(target: fastn_p2p::console::TARGET, "✅ Shell command completed with exit code: {}", exit_code);
     Ok(response)
 }
 
@@ -0,0 +1,217 @@
+//! Fair scheduling of outgoing calls across control socket clients
+//!
+//! At most `max_in_flight` calls go out at once. Calls beyond that wait in
+//! a bounded queue per client (the process on the other end of the control
+//! socket), and a freed slot goes to the next client in round-robin order,
+//! so one script issuing hundreds of calls can't starve the others. A client
+//! whose queue is full, or any client once every queue together is full, is
+//! turned away with [`Busy`] instead of waiting.
+//!
+//! Streams are long-lived and don't take slots. Limits come from the
+//! `[scheduler]` section of `config.toml`.
+
+/// `[scheduler]` in `config.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// Calls to peers in flight at once
+    pub max_in_flight: usize,
+    /// Calls one client may have waiting for a slot
+    pub max_queued_per_client: usize,
+    /// Calls all clients together may have waiting
+    pub max_queued: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 64,
+            max_queued_per_client: 32,
+            max_queued: 256,
+        }
+    }
+}
+
+/// A call turned away instead of queued
+#[derive(Debug, thiserror::Error)]
+pub enum Busy {
+    #[error("Too many calls from this client waiting ({limit}), retry later")]
+    ClientQueueFull { limit: usize },
+
+    #[error("Daemon is busy with {limit} calls waiting, retry later")]
+    QueueFull { limit: usize },
+}
+
+/// Who is calling: the pid of the client process, when the OS tells us
+pub type ClientId = Option<i32>;
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    /// Waiting calls per client, oldest first
+    queues: std::collections::HashMap<ClientId, std::collections::VecDeque<tokio::sync::oneshot::Sender<Permit>>>,
+    /// Clients with waiting calls, in the order they get the next slots
+    ring: std::collections::VecDeque<ClientId>,
+    queued: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: SchedulerConfig,
+    state: std::sync::Mutex<State>,
+}
+
+/// The daemon's outgoing call slots
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    inner: std::sync::Arc<Inner>,
+}
+
+/// A slot for one call; dropping it hands the slot to the next waiting call
+#[derive(Debug)]
+pub struct Permit {
+    inner: Option<std::sync::Arc<Inner>>,
+}
+
+static SCHEDULER: std::sync::OnceLock<Scheduler> = std::sync::OnceLock::new();
+
+/// Set the limits; only the first call (at daemon start) has an effect
+pub fn init(config: SchedulerConfig) {
+    let _ = SCHEDULER.set(Scheduler::new(config));
+}
+
+/// The daemon's scheduler, with default limits if [`init`] wasn't called
+pub fn global() -> &'static Scheduler {
+    SCHEDULER.get_or_init(|| Scheduler::new(SchedulerConfig::default()))
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self { inner: std::sync::Arc::new(Inner { config, state: Default::default() }) }
+    }
+
+    /// Wait for a slot for `client`'s call, or fail right away if its queue is full
+    pub async fn acquire(&self, client: ClientId) -> Result<Permit, Busy> {
+        let waiter = {
+            let mut guard = self.inner.state.lock().expect("scheduler lock poisoned");
+            let state = &mut *guard;
+            let config = &self.inner.config;
+
+            if state.in_flight < config.max_in_flight {
+                state.in_flight += 1;
+                return Ok(Permit { inner: Some(self.inner.clone()) });
+            }
+            if state.queues.get(&client).is_some_and(|queue| queue.len() >= config.max_queued_per_client) {
+                return Err(Busy::ClientQueueFull { limit: config.max_queued_per_client });
+            }
+            if state.queued >= config.max_queued {
+                return Err(Busy::QueueFull { limit: config.max_queued });
+            }
+
+            let (sender, waiter) = tokio::sync::oneshot::channel();
+            let queue = state.queues.entry(client).or_default();
+            if queue.is_empty() {
+                state.ring.push_back(client);
+            }
+            queue.push_back(sender);
+            state.queued += 1;
+            waiter
+        };
+        // Senders are only dropped after sending, or once this waiter is gone
+        Ok(waiter.await.expect("queued calls are always handed a permit"))
+    }
+}
+
+/// Give a freed slot to the next client in turn, or return it
+fn release(inner: &std::sync::Arc<Inner>) {
+    let mut guard = inner.state.lock().expect("scheduler lock poisoned");
+    let state = &mut *guard;
+    while let Some(client) = state.ring.pop_front() {
+        let queue = state.queues.get_mut(&client).expect("clients in the ring have a queue");
+        let sender = queue.pop_front().expect("queues in the ring are not empty");
+        state.queued -= 1;
+        if queue.is_empty() {
+            state.queues.remove(&client);
+        } else {
+            state.ring.push_back(client);
+        }
+        match sender.send(Permit { inner: Some(inner.clone()) }) {
+            Ok(()) => return,
+            // The caller went away while waiting; its slot goes to the next one
+            Err(mut permit) => permit.inner = None,
+        }
+    }
+    state.in_flight -= 1;
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            release(&inner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(max_queued_per_client: usize) -> Scheduler {
+        Scheduler::new(SchedulerConfig { max_in_flight: 1, max_queued_per_client, max_queued: 3 })
+    }
+
+    #[tokio::test]
+    async fn test_slots_go_round_robin() {
+        let scheduler = scheduler(2);
+        let first = scheduler.acquire(Some(1)).await.unwrap();
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        for client in [1, 1, 2] {
+            let scheduler = scheduler.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire(Some(client)).await.unwrap();
+                order_tx.send(client).unwrap();
+            });
+            // Queue them in this order
+            tokio::task::yield_now().await;
+        }
+
+        drop(first);
+        let mut order = Vec::new();
+        for _ in 0..3 {
+            order.push(order_rx.recv().await.unwrap());
+        }
+        assert_eq!(order, vec![1, 2, 1]);
+        assert!(scheduler.acquire(Some(3)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_full_queues_are_busy() {
+        let scheduler = scheduler(1);
+        let slot = scheduler.acquire(None).await.unwrap();
+
+        let waiting = scheduler.clone();
+        let gone = tokio::spawn(async move { waiting.acquire(Some(1)).await.map(drop) });
+        tokio::task::yield_now().await;
+        assert!(matches!(scheduler.acquire(Some(1)).await, Err(Busy::ClientQueueFull { limit: 1 })));
+
+        let mut waiters = Vec::new();
+        for client in [2, 3] {
+            let waiting = scheduler.clone();
+            waiters.push(tokio::spawn(async move { waiting.acquire(Some(client)).await.map(drop) }));
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(scheduler.acquire(Some(4)).await, Err(Busy::QueueFull { limit: 3 })));
+
+        // A caller that gives up doesn't hold on to the slot it is handed
+        gone.abort();
+        let _ = gone.await;
+        drop(slot);
+        for waiter in waiters {
+            waiter.await.unwrap().unwrap();
+        }
+        let state = scheduler.inner.state.lock().unwrap();
+        assert_eq!((state.in_flight, state.queued), (0, 0));
+    }
+}
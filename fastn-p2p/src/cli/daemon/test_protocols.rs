@@ -35,7 +35,7 @@ pub type EchoResult = Result<EchoResponse, EchoError>;
 
 /// Echo request handler (moved from request_response example)
 pub async fn echo_handler(req: EchoRequest) -> Result<EchoResponse, EchoError> {
-    println!("💬 Received: {}", req.message);
+    tracing::info!(target: fastn_p2p::console::TARGET, "💬 Received: {}", req.message);
     
     // Basic validation  
     if req.message.is_empty() {
@@ -47,4 +47,5 @@ pub async fn echo_handler(req: EchoRequest) -> Result<EchoResponse, EchoError> {
     })
 }
 
-// TODO: Add Shell protocol for interactive testing
\ No newline at end of file
+/// Shell Protocol Types, with their handlers in `protocols::shell`
+pub use super::protocols::shell::{ShellCommand, shell_stream_handler};
\ No newline at end of file
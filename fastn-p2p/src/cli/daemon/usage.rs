@@ -0,0 +1,68 @@
+//! Writing usage counts to `state.db`
+//!
+//! Counts gathered by [`fastn_p2p::server::usage`] are flushed every
+//! `flush_secs`, and days older than `keep_days` are rolled up into their
+//! month once a day. Both are set in `config.toml`:
+//!
+//! ```toml
+//! [usage]
+//! flush_secs = 60
+//! keep_days = 90
+//! ```
+
+use std::path::{Path, PathBuf};
+
+/// `[usage]` in `config.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsageConfig {
+    /// Seconds between writes of the counts gathered in memory
+    pub flush_secs: u64,
+    /// Days kept by day; older ones only count towards their month
+    pub keep_days: u32,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self { flush_secs: 60, keep_days: 90 }
+    }
+}
+
+/// Between roll-ups of old days into months
+const ROLL_UP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Flush and roll up usage until the daemon exits
+pub async fn run(fastn_home: PathBuf, config: UsageConfig) {
+    let mut flush = tokio::time::interval(std::time::Duration::from_secs(config.flush_secs.max(1)));
+    let mut roll_up = tokio::time::interval(ROLL_UP_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = flush.tick() => {
+                if let Err(e) = flush_usage(&fastn_home).await {
+                    tracing::warn!("Failed to write usage: {}", e);
+                }
+            }
+            _ = roll_up.tick() => {
+                if let Err(e) = roll_up_usage(&fastn_home, config.keep_days).await {
+                    tracing::warn!("Failed to roll up usage: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn flush_usage(fastn_home: &Path) -> Result<(), fastn_p2p::server::state::StateError> {
+    let store = fastn_p2p::server::state::StateStore::open(fastn_home).await?;
+    fastn_p2p::server::usage::flush(&store).await?;
+    Ok(())
+}
+
+async fn roll_up_usage(fastn_home: &Path, keep_days: u32) -> Result<(), fastn_p2p::server::state::StateError> {
+    let store = fastn_p2p::server::state::StateStore::open(fastn_home).await?;
+    let before = chrono::Utc::now().date_naive() - chrono::Days::new(keep_days.into());
+    let days = store.roll_up_usage(before).await?;
+    if days > 0 {
+        tracing::info!(target: fastn_p2p::console::TARGET, "📊 Rolled {} days of usage up into months", days);
+    }
+    Ok(())
+}
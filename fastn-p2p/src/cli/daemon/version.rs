@@ -0,0 +1,154 @@
+//! What the daemon tells clients about itself, and looking for new releases
+//!
+//! `fastn-p2p status` and [`fastn_p2p_client::admin::daemon_info`] show the
+//! daemon's release, the control protocol it speaks (see
+//! [`fastn_p2p_client::version`]) and what it was built with. With an update
+//! channel in `config.toml`, the daemon also looks for newer releases:
+//!
+//! ```toml
+//! [update]
+//! channel = "https://fastn.com/p2p/stable.json"
+//! check_hours = 24
+//! ```
+//!
+//! The channel answers with `{"version": "0.2.0", "url": "…", "notes": "…"}`.
+//! Nothing is downloaded or installed; a newer release is only reported.
+//! Checking needs the `update-check` feature.
+
+pub use fastn_p2p_client::admin::{DaemonInfo, UpdateInfo};
+
+/// `[update]` in `config.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpdateConfig {
+    /// URL of the release channel; no checks without one
+    pub channel: Option<String>,
+    /// Hours between checks
+    pub check_hours: u64,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self { channel: None, check_hours: 24 }
+    }
+}
+
+static STARTED_MS: std::sync::LazyLock<u64> =
+    std::sync::LazyLock::new(|| chrono::Utc::now().timestamp_millis().max(0) as u64);
+
+/// Newest release seen on the update channel, if newer than this one
+static LATEST: std::sync::Mutex<Option<UpdateInfo>> = std::sync::Mutex::new(None);
+
+/// This daemon, as answered to `version` requests
+pub fn info() -> DaemonInfo {
+    DaemonInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        wire_version: fastn_p2p_client::version::WIRE_VERSION,
+        min_wire_version: fastn_p2p_client::version::MIN_WIRE_VERSION,
+        target: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+        features: features(),
+        started_ms: *STARTED_MS,
+        update: LATEST
+            .lock()
+            .expect("Update lock poisoned")
+            .clone()
+            .filter(|update| is_newer(&update.version, env!("CARGO_PKG_VERSION"))),
+    }
+}
+
+fn features() -> Vec<String> {
+    [
+        ("otlp", cfg!(feature = "otlp")),
+        ("schema", cfg!(feature = "schema")),
+        ("desktop-notifications", cfg!(feature = "desktop-notifications")),
+        ("cbor", cfg!(feature = "cbor")),
+        ("msgpack", cfg!(feature = "msgpack")),
+        ("update-check", cfg!(feature = "update-check")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature.to_string())
+    .collect()
+}
+
+/// Whether release `candidate` is newer than `current`, comparing dotted numbers
+///
+/// Pre-release and build suffixes (`-rc.1`, `+abc`) are ignored.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().unwrap_or_default())
+            .collect()
+    }
+    let (mut candidate, mut current) = (parts(candidate), parts(current));
+    let len = candidate.len().max(current.len());
+    candidate.resize(len, 0);
+    current.resize(len, 0);
+    candidate > current
+}
+
+/// Check the update channel every `check_hours` until the daemon exits
+pub async fn run(config: UpdateConfig) {
+    // Counted from daemon start, not from the first `version` request
+    std::sync::LazyLock::force(&STARTED_MS);
+    let Some(channel) = config.channel else {
+        return;
+    };
+    #[cfg(not(feature = "update-check"))]
+    {
+        tracing::warn!("[update] channel {} is set, but this build has no `update-check` feature", channel);
+    }
+    #[cfg(feature = "update-check")]
+    {
+        let mut checks = tokio::time::interval(std::time::Duration::from_secs(config.check_hours.max(1) * 60 * 60));
+        loop {
+            checks.tick().await;
+            match check(&channel).await {
+                Ok(Some(update)) => {
+                    tracing::info!(target: fastn_p2p::console::TARGET, "⬆️  fastn-p2p {} is available (running {})", update.version, env!("CARGO_PKG_VERSION"));
+                    *LATEST.lock().expect("Update lock poisoned") = Some(update);
+                }
+                Ok(None) => tracing::info!(target: fastn_p2p::console::TARGET, "✅ fastn-p2p {} is the latest release", env!("CARGO_PKG_VERSION")),
+                Err(e) => tracing::warn!("Failed to check {} for updates: {}", channel, e),
+            }
+        }
+    }
+}
+
+/// The release announced on `channel`, if newer than this one
+#[cfg(feature = "update-check")]
+async fn check(channel: &str) -> Result<Option<UpdateInfo>, reqwest::Error> {
+    let release: UpdateInfo = reqwest::Client::new()
+        .get(channel)
+        .timeout(std::time::Duration::from_secs(30))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(is_newer(&release.version, env!("CARGO_PKG_VERSION")).then_some(release))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("0.2.0", "0.1.9"));
+        assert!(is_newer("v1.0", "0.9.12"));
+        assert!(is_newer("0.1.10", "0.1.9"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0-rc.1", "0.1.0"));
+        assert!(!is_newer("0.1", "0.1.0"));
+
+        let info = info();
+        assert_eq!(info.wire_version, fastn_p2p_client::version::WIRE_VERSION);
+        assert!(info.update.is_none());
+    }
+}
@@ -0,0 +1,51 @@
+//! Describe command: fetch a peer's protocol description
+//!
+//! The peer answers over [`fastn_p2p::server::describe::DESCRIBE_PROTOCOL`]
+//! with an OpenRPC document of the handlers it described.
+
+use std::path::{Path, PathBuf};
+
+/// Print the OpenRPC document of `protocol` (or all described protocols) on `peer`
+pub async fn describe(
+    fastn_home: PathBuf,
+    peer_id52: String,
+    protocol: Option<String>,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let to_peer: fastn_id52::PublicKey = peer_id52.parse()
+        .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+
+    eprintln!("🔎 Describing {} on {}", protocol.as_deref().unwrap_or("all protocols"), to_peer.id52());
+    let document = fetch_description(&fastn_home, to_peer, protocol, as_identity).await?;
+    match crate::cli::output::format() {
+        crate::cli::output::Format::Text => println!("{}", serde_json::to_string_pretty(&document)?),
+        crate::cli::output::Format::Json => crate::cli::output::result(document),
+    }
+    Ok(())
+}
+
+/// Ask `peer` for its OpenRPC document through the daemon
+pub async fn fetch_description(
+    fastn_home: &Path,
+    to_peer: fastn_id52::PublicKey,
+    protocol: Option<String>,
+    as_identity: Option<String>,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let request = fastn_p2p::server::describe::DescribeRequest { protocol };
+    let response = crate::cli::client::call_daemon(
+        fastn_home,
+        as_identity,
+        to_peer,
+        crate::cli::client::Target::new(fastn_p2p::server::describe::DESCRIBE_PROTOCOL, "default"),
+        serde_json::to_value(&request)?,
+        Default::default(),
+    ).await?;
+    // Peers without descriptions refuse the introspection protocol (protocol-not-accepted)
+    crate::cli::client::ensure_success(&response)?;
+
+    let document = &response["data"]["p2p_response"];
+    if let Some(error) = document.get("error") {
+        return Err(format!("Peer could not describe its protocols: {}", error).into());
+    }
+    Ok(document.clone())
+}
@@ -0,0 +1,108 @@
+//! Device commands: pair a second machine with an identity hosted here
+
+use std::path::PathBuf;
+
+/// Issue a pairing code for `identity` (run on the primary)
+pub async fn pair(
+    fastn_home: PathBuf,
+    identity: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identities_dir = fastn_home.join("identities");
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
+        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
+    let identity_dir = identities_dir.join(&identity);
+
+    let mut registry = fastn_p2p::server::devices::DeviceRegistry::load(&identity_dir).await?;
+    let code = registry.start_pairing();
+    registry.save(&identity_dir).await?;
+
+    let uri = fastn_p2p::server::devices::PairingUri {
+        identity: identity_config.secret_key.public_key(),
+        code: code.clone(),
+    };
+
+    say!("🔗 Pairing code for '{}': {}", identity, code);
+    say!("   Valid for {} minutes, single use", fastn_p2p::server::devices::PAIRING_CODE_TTL.as_secs() / 60);
+    say!();
+    say!("On the other machine run:");
+    say!("   fastn-p2p device join '{}'", uri);
+    Ok(())
+}
+
+/// Pair this machine with an identity on another daemon (run on the secondary)
+pub async fn join(
+    fastn_home: PathBuf,
+    uri: String,
+    alias: String,
+    name: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let uri: fastn_p2p::server::devices::PairingUri = uri.parse()?;
+
+    if fastn_home.join("identities").join(&alias).exists()
+        || fastn_p2p::server::devices::RemoteIdentity::dir(&fastn_home, &alias).exists()
+    {
+        return Err(format!("Identity '{}' already exists, choose another with --alias", alias).into());
+    }
+
+    // The device gets its own key; the identity's key stays on the primary
+    let remote = fastn_p2p::server::devices::RemoteIdentity {
+        alias: alias.clone(),
+        identity: uri.identity,
+        device_key: fastn_id52::SecretKey::generate(),
+    };
+
+    say!("🔗 Pairing as '{}' with {}", name, uri.identity.id52());
+    fastn_p2p::client::Client::new(remote.device_key.clone())
+        .pair(uri.identity, &uri.code, &name)
+        .await?
+        .map_err(|e| format!("Primary refused pairing: {}", e))?;
+
+    remote.save(&fastn_home).await?;
+
+    say!("✅ Paired: '{}' is now usable here as --as-identity {}", uri.identity.id52(), alias);
+    say!("   Device ID: {}", remote.device_key.public_key().id52());
+    Ok(())
+}
+
+/// List the devices paired with `identity`
+pub async fn list(
+    fastn_home: PathBuf,
+    identity: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity_dir = fastn_home.join("identities").join(&identity);
+    if !identity_dir.exists() {
+        return Err(format!("Identity '{}' not found", identity).into());
+    }
+
+    let registry = fastn_p2p::server::devices::DeviceRegistry::load(&identity_dir).await?;
+    if registry.devices.is_empty() {
+        say!("No devices paired with '{}'", identity);
+        crate::cli::output::result(&registry.devices);
+        return Ok(());
+    }
+
+    say!("📱 Devices paired with '{}':", identity);
+    for device in &registry.devices {
+        say!("   {} {} (paired {})", device.id52, device.name, device.paired_at.format("%Y-%m-%d %H:%M"));
+    }
+    crate::cli::output::result(&registry.devices);
+    Ok(())
+}
+
+/// Revoke a device's access to `identity`
+pub async fn remove(
+    fastn_home: PathBuf,
+    identity: String,
+    device_id52: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity_dir = fastn_home.join("identities").join(&identity);
+    let mut registry = fastn_p2p::server::devices::DeviceRegistry::load(&identity_dir).await?;
+
+    if !registry.remove(&device_id52) {
+        return Err(format!("Device {} is not paired with '{}'", device_id52, identity).into());
+    }
+    registry.save(&identity_dir).await?;
+
+    say!("✅ Device {} removed from '{}'", device_id52, identity);
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! Drain command: let in-flight streams finish before the daemon restarts
+//!
+//! See [`fastn_p2p::server::drain`]. The daemon stops accepting for good;
+//! restart it once this reports it drained. Exits with an error when streams
+//! are still running at the timeout.
+
+use std::path::PathBuf;
+
+/// Make the daemon stop accepting and wait up to `timeout` for in-flight streams
+pub async fn drain(fastn_home: PathBuf, timeout: String) -> Result<(), Box<dyn std::error::Error>> {
+    let timeout = crate::cli::parse_age(&timeout)
+        .and_then(|age| age.to_std().ok())
+        .ok_or_else(|| {
+            crate::cli::output::UsageError::boxed(format!(
+                "Invalid --timeout value '{}': use an age like 30s, 5m",
+                timeout
+            ))
+        })?;
+
+    say!("🚰 Draining, waiting up to {:?} for in-flight streams...", timeout);
+    let report = fastn_p2p_client::admin::drain(&fastn_home, timeout).await?;
+    if !report.drained {
+        say!("   Restarting now drops them; run drain again to keep waiting");
+        // Fails, so `fastn-p2p drain && restart` only restarts a drained daemon
+        return Err(format!(
+            "{} of {} streams still running after {}ms",
+            report.remaining, report.in_flight_at_start, report.elapsed_ms
+        )
+        .into());
+    }
+    say!("✅ Drained {} streams in {}ms, safe to restart", report.in_flight_at_start, report.elapsed_ms);
+    crate::cli::output::result(report);
+    Ok(())
+}
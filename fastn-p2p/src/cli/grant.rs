@@ -0,0 +1,47 @@
+//! Grant command: mint capability tokens for sharing an identity's protocols
+
+use std::path::PathBuf;
+
+/// Mint a token granting `protocol` (optionally only `commands`) of `identity`
+pub async fn grant(
+    fastn_home: PathBuf,
+    identity: String,
+    protocol: String,
+    commands: Vec<String>,
+    valid_for: String,
+    holder: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ttl = crate::cli::parse_age(&valid_for)
+        .and_then(|age| age.to_std().ok())
+        .ok_or_else(|| {
+            crate::cli::output::UsageError::boxed(format!(
+                "Invalid --for value '{}': use an age like 30m, 24h, 7d",
+                valid_for
+            ))
+        })?;
+    let holder = holder
+        .map(|holder| {
+            holder
+                .parse::<fastn_id52::PublicKey>()
+                .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid --holder '{}': {}", holder, e)))
+        })
+        .transpose()?;
+
+    let identities_dir = fastn_home.join("identities");
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
+        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
+
+    let grant = fastn_p2p::capability::Grant::protocol(protocol).with_commands(commands);
+    let token = fastn_p2p::capability::CapabilityToken::mint(&identity_config.secret_key, holder, vec![grant], ttl);
+
+    say!("🎟️  Capability token from '{}', valid until {}:", identity, token.expires_at);
+    say!("{}", token);
+    say!();
+    say!("Holders attach it with HelloMetadata::with_capability");
+    crate::cli::output::result(serde_json::json!({
+        "identity": identity,
+        "token": token.to_string(),
+        "expires_at": token.expires_at,
+    }));
+    Ok(())
+}
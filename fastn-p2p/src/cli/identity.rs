@@ -2,37 +2,74 @@
 //!
 //! Handles creation, storage, and loading of persistent identities.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Where `create-identity` takes an existing key from
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// A key file such as the `.fastn-*.key` files the examples write
+    File(PathBuf),
+    /// An environment variable holding the key
+    Env(String),
+    /// The key itself, as hex
+    Seed(String),
+}
+
+impl KeySource {
+    /// Read and validate the key; bad input is a usage error
+    fn load(&self) -> Result<fastn_id52::SecretKey, Box<dyn std::error::Error>> {
+        let (key, from) = match self {
+            KeySource::File(path) => {
+                let key = std::fs::read_to_string(path).map_err(|e| {
+                    crate::cli::output::UsageError::boxed(format!("Cannot read key file {}: {}", path.display(), e))
+                })?;
+                (key, format!("key file {}", path.display()))
+            }
+            KeySource::Env(var) => {
+                let key = std::env::var(var).map_err(|_| {
+                    crate::cli::output::UsageError::boxed(format!("Environment variable {} is not set", var))
+                })?;
+                (key, format!("environment variable {}", var))
+            }
+            KeySource::Seed(seed) => (seed.clone(), "--from-seed".to_string()),
+        };
+        key.trim()
+            .parse::<fastn_id52::SecretKey>()
+            .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid secret key in {}: {}", from, e)))
+    }
+}
 
 /// Create a new identity and save it with the given alias
+///
+/// With a [`KeySource`] the identity keeps that key, and with it the peer ID
+/// of an existing server; otherwise a fresh key is generated.
 pub async fn create_identity(
     fastn_home: PathBuf,
     alias: String,
+    source: Option<KeySource>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let secret_key = source.as_ref().map(KeySource::load).transpose()?;
+    let imported = secret_key.is_some();
+
     // Ensure identities directory exists
     let identities_dir = fastn_home.join("identities");
     tokio::fs::create_dir_all(&identities_dir).await?;
     
-    // Generate new identity
-    let secret_key = fastn_id52::SecretKey::generate();
-    let public_key = secret_key.public_key();
+    // Conventional layout the daemon loads: identities/<alias>/identity.private-key
+    let command = crate::cli::daemon::DaemonCommand::CreateIdentity { identity: alias.clone(), secret_key };
+    let applied_by = update(&fastn_home, command).await?;
     
-    println!("🔑 Generated new identity: {}", alias);
-    println!("   Peer ID: {}", public_key.id52());
-    
-    // Save to identities directory using alias name
-    let identity_path = identities_dir.join(format!("{}.key", alias));
-    
-    if identity_path.exists() {
-        return Err(format!("Identity '{}' already exists at: {}", alias, identity_path.display()).into());
+    let identity_dir = identities_dir.join(&alias);
+    let (id52, _secret_key) = fastn_id52::SecretKey::load_from_dir(&identity_dir, "identity")?;
+    match imported {
+        true => say!("🔑 Imported identity: {} ({})", alias, applied_by),
+        false => say!("🔑 Generated new identity: {} ({})", alias, applied_by),
     }
+    say!("   Peer ID: {}", id52);
+    say!("💾 Saved identity to: {}", identity_dir.display());
+    say!("✅ Identity '{}' created successfully", alias);
     
-    // Use save_to_dir method for proper storage
-    secret_key.save_to_dir(&identities_dir, &alias)?;
-    
-    println!("💾 Saved identity to: {}", identity_path.display());
-    println!("✅ Identity '{}' created successfully", alias);
-    
+    crate::cli::output::result(fastn_p2p_client::admin::IdentityCreated { identity: alias, peer: id52 });
     Ok(())
 }
 
@@ -44,46 +81,30 @@ pub async fn add_protocol(
     bind_alias: String,
     config_json: String,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let identities_dir = fastn_home.join("identities");
-    
     // Parse JSON config for initial setup
     let config: serde_json::Value = serde_json::from_str(&config_json)
-        .map_err(|e| format!("Invalid JSON config: {}", e))?;
-    
-    // Create protocol config directory
-    let protocol_config_path = identities_dir.join(&identity).join("protocols").join(&bind_alias);
-    tokio::fs::create_dir_all(&protocol_config_path).await?;
-    
-    // Load existing identity config
-    let mut identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
-        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
-    
-    // Check if binding already exists
-    if identity_config.protocols.iter().any(|p| p.protocol == protocol && p.bind_alias == bind_alias) {
-        return Err(format!("Protocol binding '{}' as '{}' already exists for identity '{}'", protocol, bind_alias, identity).into());
-    }
-    
-    // Initialize the protocol handler using trait interface
-    if let Err(e) = crate::cli::daemon::protocol_trait::init_protocol(&protocol, &bind_alias, &protocol_config_path).await {
-        return Err(format!("Failed to initialize {} protocol: {}", protocol, e).into());
-    }
-    
-    // Write the initial config JSON to the protocol directory
-    let config_file = protocol_config_path.join(format!("{}.json", protocol.to_lowercase()));
-    tokio::fs::write(&config_file, serde_json::to_string_pretty(&config)?).await?;
-    
-    // Add protocol binding with config path
-    identity_config = identity_config.add_protocol(protocol.clone(), bind_alias.clone(), protocol_config_path.clone());
-    
-    // Save updated identity config
-    identity_config.save_to_dir(&identities_dir).await?;
-    
-    println!("➕ Added protocol binding to identity '{}'", identity);
-    println!("   Protocol: {} as '{}'", protocol, bind_alias);
-    println!("   Config path: {}", protocol_config_path.display());
-    println!("   Config file: {}", config_file.display());
-    println!("✅ Protocol binding saved");
-    
+        .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid JSON config: {}", e)))?;
+    
+    let command = crate::cli::daemon::DaemonCommand::AddProtocol {
+        identity: identity.clone(),
+        protocol: protocol.clone(),
+        bind_alias: bind_alias.clone(),
+        config: fastn_p2p_client::Sensitive(config),
+    };
+    let applied_by = update(&fastn_home, command).await?;
+    
+    let protocol_config_path = fastn_home.join("identities").join(&identity).join("protocols").join(&protocol).join(&bind_alias);
+    say!("➕ Added protocol binding to identity '{}' ({})", identity, applied_by);
+    say!("   Protocol: {} as '{}'", protocol, bind_alias);
+    say!("   Config file: {}", protocol_config_path.join("config.json").display());
+    say!("✅ Protocol binding saved");
+    
+    crate::cli::output::result(serde_json::json!({
+        "identity": identity,
+        "protocol": protocol,
+        "bind_alias": bind_alias,
+        "config_path": protocol_config_path.join("config.json"),
+    }));
     Ok(())
 }
 
@@ -94,27 +115,18 @@ pub async fn remove_protocol(
     protocol: String,
     bind_alias: String,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let identities_dir = fastn_home.join("identities");
-    
-    // Load existing identity config
-    let mut identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
-        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
-    
-    // Find and remove the protocol binding
-    let original_count = identity_config.protocols.len();
-    identity_config.protocols.retain(|p| !(p.protocol == protocol && p.bind_alias == bind_alias));
-    
-    if identity_config.protocols.len() == original_count {
-        return Err(format!("Protocol binding '{}' as '{}' not found for identity '{}'", protocol, bind_alias, identity).into());
-    }
-    
-    // Save updated config
-    identity_config.save_to_dir(&identities_dir).await?;
-    
-    println!("➖ Removed protocol binding from identity '{}'", identity);
-    println!("   Protocol: {} as '{}'", protocol, bind_alias);
-    println!("✅ Protocol binding removed");
-    
+    let command = crate::cli::daemon::DaemonCommand::RemoveProtocol {
+        identity: identity.clone(),
+        protocol: protocol.clone(),
+        bind_alias: bind_alias.clone(),
+    };
+    let applied_by = update(&fastn_home, command).await?;
+    
+    say!("➖ Removed protocol binding from identity '{}' ({})", identity, applied_by);
+    say!("   Protocol: {} as '{}'", protocol, bind_alias);
+    say!("✅ Protocol binding removed");
+    
+    crate::cli::output::result(fastn_p2p_client::admin::ProtocolRemoved { identity, protocol, bind_alias });
     Ok(())
 }
 
@@ -123,24 +135,20 @@ pub async fn set_identity_online(
     fastn_home: PathBuf,
     identity: String,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let identities_dir = fastn_home.join("identities");
-    
-    // Load identity config
-    let mut identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
-        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
-    
-    if identity_config.online {
-        println!("ℹ️  Identity '{}' is already online", identity);
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    if store.is_online(&identity).await? {
+        say!("ℹ️  Identity '{}' is already online", identity);
+        crate::cli::output::result(fastn_p2p_client::admin::IdentityStateChanged { identity, online: true });
         return Ok(());
     }
     
-    // Set online and save
-    identity_config.online = true;
-    identity_config.save_to_dir(&identities_dir).await?;
+    let command = crate::cli::daemon::DaemonCommand::SetIdentityState { identity: identity.clone(), online: true };
+    let applied_by = update(&fastn_home, command).await?;
     
-    println!("🟢 Identity '{}' is now ONLINE", identity);
-    println!("   {} protocols will be enabled when daemon starts", identity_config.protocols.len());
+    say!("🟢 Identity '{}' is now ONLINE ({})", identity, applied_by);
+    say!("   {} protocols enabled", store.bindings(&identity).await?.len());
     
+    crate::cli::output::result(fastn_p2p_client::admin::IdentityStateChanged { identity, online: true });
     Ok(())
 }
 
@@ -149,60 +157,97 @@ pub async fn set_identity_offline(
     fastn_home: PathBuf,
     identity: String,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let identities_dir = fastn_home.join("identities");
-    
-    // Load identity config
-    let mut identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
-        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
-    
-    if !identity_config.online {
-        println!("ℹ️  Identity '{}' is already offline", identity);
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    if !store.is_online(&identity).await? {
+        say!("ℹ️  Identity '{}' is already offline", identity);
+        crate::cli::output::result(fastn_p2p_client::admin::IdentityStateChanged { identity, online: false });
         return Ok(());
     }
     
-    // Set offline and save
-    identity_config.online = false;
-    identity_config.save_to_dir(&identities_dir).await?;
+    let command = crate::cli::daemon::DaemonCommand::SetIdentityState { identity: identity.clone(), online: false };
+    let applied_by = update(&fastn_home, command).await?;
     
-    println!("🔴 Identity '{}' is now OFFLINE", identity);
-    println!("   {} protocols will be disabled", identity_config.protocols.len());
-    println!("   Restart daemon for changes to take effect");
+    say!("🔴 Identity '{}' is now OFFLINE ({})", identity, applied_by);
+    say!("   {} protocols disabled", store.bindings(&identity).await?.len());
     
+    crate::cli::output::result(fastn_p2p_client::admin::IdentityStateChanged { identity, online: false });
     Ok(())
 }
 
-/// Load all identities from FASTN_HOME/identities/ directory
-pub async fn load_all_identities(
-    fastn_home: &PathBuf,
-) -> Result<Vec<(String, fastn_id52::SecretKey)>, Box<dyn std::error::Error>> {
-    let identities_dir = fastn_home.join("identities");
-    
-    if !identities_dir.exists() {
-        println!("📁 No identities directory found: {}", identities_dir.display());
-        return Ok(vec![]);
+/// Apply an identity or binding change; returns who applied it, for messages
+///
+/// A running daemon gets the change over its control socket, so its own
+/// reads and writes of FASTN_HOME can't interleave with ours. Without one
+/// the change is written here, under the same update lock the daemon takes.
+async fn update(fastn_home: &Path, command: crate::cli::daemon::DaemonCommand) -> Result<&'static str, Box<dyn std::error::Error>> {
+    let sent = match &command {
+        crate::cli::daemon::DaemonCommand::CreateIdentity { identity, secret_key: None } => {
+            fastn_p2p_client::admin::create_identity(fastn_home, identity).await.map(drop)
+        }
+        crate::cli::daemon::DaemonCommand::CreateIdentity { identity, secret_key: Some(secret_key) } => {
+            fastn_p2p_client::admin::import_identity(fastn_home, identity, secret_key).await.map(drop)
+        }
+        crate::cli::daemon::DaemonCommand::SetIdentityState { identity, online } => {
+            fastn_p2p_client::admin::set_identity_online(fastn_home, identity, *online).await.map(drop)
+        }
+        crate::cli::daemon::DaemonCommand::AddProtocol { identity, protocol, bind_alias, config } => {
+            fastn_p2p_client::admin::add_protocol(fastn_home, identity, protocol, bind_alias, config.0.clone()).await.map(drop)
+        }
+        crate::cli::daemon::DaemonCommand::RemoveProtocol { identity, protocol, bind_alias } => {
+            fastn_p2p_client::admin::remove_protocol(fastn_home, identity, protocol, bind_alias).await.map(drop)
+        }
+        command => return Err(format!("Not an identity update: {:?}", command).into()),
+    };
+    
+    match sent {
+        Ok(()) => Ok("applied by the running daemon"),
+        Err(fastn_p2p_client::ClientError::DaemonNotRunning { .. }) => {
+            crate::cli::daemon::control::write_control_command(fastn_home, &command).await?;
+            Ok("daemon not running, written to FASTN_HOME")
+        }
+        Err(e) => Err(e.into()),
     }
-    
-    let mut identities = Vec::new();
-    let mut dir_entries = tokio::fs::read_dir(&identities_dir).await?;
-    
-    while let Some(entry) = dir_entries.next_entry().await? {
-        let path = entry.path();
-        
-        if path.extension().and_then(|s| s.to_str()) == Some("private-key") {
-            if let Some(file_stem) = path.file_stem().and_then(|s| s.to_str()) {
-                match fastn_id52::SecretKey::load_from_dir(&identities_dir, file_stem) {
-                    Ok((_id52, secret_key)) => {
-                        println!("🔑 Loaded identity '{}': {}", file_stem, secret_key.public_key().id52());
-                        identities.push((file_stem.to_string(), secret_key));
-                    }
-                    Err(e) => {
-                        eprintln!("⚠️  Failed to load identity '{}': {}", file_stem, e);
-                    }
-                }
+}
+
+/// Use the given identity, the default identity, or the only configured one
+pub async fn resolve_identity(
+    fastn_home: &Path,
+    as_identity: Option<String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let identity = fastn_p2p::server::resolve_identity(fastn_home, as_identity.as_deref()).await?;
+    Ok(identity.alias)
+}
+
+/// Show, set or clear the default identity used when --as-identity is omitted
+pub async fn default_identity(
+    fastn_home: PathBuf,
+    alias: Option<String>,
+    clear: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if clear {
+        fastn_p2p_client::identity::clear_default_identity(&fastn_home).await?;
+        say!("✅ Default identity cleared");
+        crate::cli::output::result(serde_json::json!({ "default_identity": null }));
+        return Ok(());
+    }
+
+    match alias {
+        Some(alias) => {
+            let identities_dir = fastn_home.join("identities");
+            fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &alias).await
+                .map_err(|e| format!("Identity '{}' not found: {}", alias, e))?;
+            fastn_p2p_client::identity::set_default_identity(&fastn_home, &alias).await?;
+            say!("✅ Default identity set to '{}'", alias);
+            crate::cli::output::result(serde_json::json!({ "default_identity": alias }));
+        }
+        None => {
+            let alias = fastn_p2p_client::identity::default_identity(&fastn_home).await?;
+            match &alias {
+                Some(alias) => say!("{}", alias),
+                None => say!("No default identity set"),
             }
+            crate::cli::output::result(serde_json::json!({ "default_identity": alias }));
         }
     }
-    
-    println!("📋 Loaded {} identities from {}", identities.len(), identities_dir.display());
-    Ok(identities)
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Invite commands: first contact with someone whose ID52 we don't have yet
+
+use std::path::PathBuf;
+
+/// Issue a single-use invite from `identity`, valid for `valid_for`
+pub async fn create(
+    fastn_home: PathBuf,
+    identity: String,
+    valid_for: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ttl = crate::cli::parse_age(&valid_for)
+        .and_then(|age| age.to_std().ok())
+        .ok_or_else(|| {
+            crate::cli::output::UsageError::boxed(format!(
+                "Invalid --for value '{}': use an age like 30m, 24h, 7d",
+                valid_for
+            ))
+        })?;
+
+    let identities_dir = fastn_home.join("identities");
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
+        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
+    let identity_dir = identities_dir.join(&identity);
+
+    let mut registry = fastn_p2p::server::invites::InviteRegistry::load(&identity_dir).await?;
+    let invite = registry.create(&identity_config.secret_key, ttl);
+    registry.save(&identity_dir).await?;
+
+    say!("💌 Invite from '{}', valid until {}, single use:", identity, invite.token.expires_at);
+    say!("{}", invite);
+    say!();
+    say!("The other side runs:");
+    say!("   fastn-p2p invite accept '{}'", invite);
+    crate::cli::output::result(serde_json::json!({
+        "identity": identity,
+        "invite": invite.to_string(),
+        "expires_at": invite.token.expires_at,
+    }));
+    Ok(())
+}
+
+/// Accept `invite` as `as_identity`, adding the inviter to its address book as `name`
+pub async fn accept(
+    fastn_home: PathBuf,
+    invite: String,
+    name: Option<String>,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let invite: fastn_p2p::server::invites::Invite = invite
+        .parse()
+        .map_err(|e: fastn_p2p::server::invites::InviteError| crate::cli::output::UsageError::boxed(e.to_string()))?;
+    invite.verify()?;
+
+    // The identity's own key answers the inviter, so it has to live here
+    let identity = crate::cli::peers::address_book_owner(&fastn_home, as_identity.as_deref()).await?;
+    let identities_dir = fastn_home.join("identities");
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
+        .map_err(|_| format!("Identity '{}' is paired from another machine, accept the invite there", identity))?;
+
+    say!("💌 Accepting invite from {} as '{}'", invite.identity().id52(), identity);
+    let accepted = fastn_p2p::client::Client::new(identity_config.secret_key)
+        .accept_invite(&invite, &identity)
+        .await?
+        .map_err(|e| format!("Inviter refused: {}", e))?;
+
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    let name = name.unwrap_or(accepted.name);
+    let saved_as = fastn_p2p::server::invites::save_contact(&store, &identity, &name, &invite.identity()).await?;
+
+    say!("📇 Added '{}' to the address book of '{}'", saved_as, identity);
+    say!("   They saved you as '{}'", accepted.saved_as);
+    crate::cli::output::result(serde_json::json!({
+        "identity": identity,
+        "name": saved_as,
+        "peer": invite.identity().id52(),
+        "saved_as": accepted.saved_as,
+    }));
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! Migrate command: bring an older FASTN_HOME layout up to date
+
+use std::path::PathBuf;
+
+/// Show or apply the migrations `fastn_home` needs, see [`fastn_p2p::server::home`]
+///
+/// Applying them needs the daemon stopped; it migrates on start as well.
+pub async fn migrate(fastn_home: PathBuf, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if !fastn_home.exists() {
+        return Err(crate::cli::output::UsageError::boxed(format!("{} does not exist", fastn_home.display())));
+    }
+
+    let plan = match dry_run {
+        true => fastn_p2p::server::home::plan(&fastn_home).await?,
+        false => {
+            // Held while migrating, so a daemon can't start on a half-moved home
+            let _lock = fastn_p2p::server::acquire_singleton_lock(&fastn_home).await?;
+            fastn_p2p::server::home::migrate(&fastn_home).await?
+        }
+    };
+
+    if plan.is_up_to_date() {
+        say!("✅ {} is at layout version {}, nothing to migrate", fastn_home.display(), plan.to);
+    } else {
+        let verb = if dry_run { "Would migrate" } else { "Migrated" };
+        say!("🧳 {} {} from layout version {} to {}", verb, fastn_home.display(), plan.from, plan.to);
+        for migration in &plan.migrations {
+            say!("   v{}: {}", migration.version, migration.description);
+            if migration.steps.is_empty() {
+                say!("      nothing to change");
+            }
+            for step in &migration.steps {
+                say!("      {}", step);
+            }
+        }
+        if let Some(backup) = &plan.backup {
+            say!("💾 Backup: {}", backup.display());
+        }
+        if dry_run {
+            say!();
+            say!("Nothing was changed. Apply with: fastn-p2p migrate");
+        }
+    }
+    crate::cli::output::result(serde_json::json!({
+        "dry_run": dry_run,
+        "plan": plan,
+    }));
+    Ok(())
+}
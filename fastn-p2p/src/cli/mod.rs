@@ -2,10 +2,39 @@
 
 use std::path::PathBuf;
 
+/// `println!` for command output; goes to stderr under `--output json`, see [`output`]
+macro_rules! say {
+    () => { $crate::cli::output::say(format_args!("")) };
+    ($($arg:tt)*) => { $crate::cli::output::say(format_args!($($arg)*)) };
+}
+
+pub mod approvals;
+pub mod audit;
+pub mod browse;
+pub mod cache;
 pub mod client;
+pub mod clip;
+pub mod codegen;
 pub mod daemon;
+pub mod describe;
+pub mod device;
+pub mod drain;
+pub mod grant;
 pub mod identity;
+pub mod invite;
+pub mod migrate;
+pub mod outbox;
+pub mod output;
+pub mod peers;
+pub mod ping;
+pub mod quota;
+pub mod repl;
+pub mod reputation;
+pub mod scaffold;
+pub mod selftest;
 pub mod status;
+pub mod sync;
+pub mod usage;
 
 /// Get the FASTN_HOME directory from clap args, environment variable, or default
 pub fn get_fastn_home(custom_home: Option<PathBuf>) -> Result<PathBuf, Box<dyn std::error::Error>> {
@@ -20,4 +49,17 @@ pub fn get_fastn_home(custom_home: Option<PathBuf>) -> Result<PathBuf, Box<dyn s
         .to_path_buf();
 
     Ok(home_dir.join(".fastn"))
+}
+
+/// Parse an age like `30s`, `15m`, `2h` or `7d`
+pub fn parse_age(age: &str) -> Option<chrono::Duration> {
+    let (amount, unit) = age.split_at(age.len().saturating_sub(1));
+    let amount: i64 = amount.parse().ok()?;
+    match unit {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        _ => None,
+    }
 }
\ No newline at end of file
@@ -0,0 +1,101 @@
+//! Send and delivery commands: store-and-forward messages
+//!
+//! `send` queues a message with the daemon, which delivers it until the peer
+//! confirms it, see [`fastn_p2p::server::delivery`]. `delivery` reads the
+//! receipts from `state.db` directly, so it works while the daemon is down.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::cli::output::UsageError;
+
+/// Queue the JSON message on stdin for `peer`
+pub async fn send(
+    fastn_home: PathBuf,
+    peer_id52: String,
+    protocol: String,
+    command: Option<String>,
+    bind_alias: String,
+    as_identity: Option<String>,
+    args: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let to_peer: fastn_id52::PublicKey = peer_id52.parse()
+        .map_err(|e| UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+    // Check if daemon is running before waiting on stdin
+    let socket_path = fastn_home.join("control.sock");
+    if !socket_path.exists() {
+        return Err(fastn_p2p_client::ClientError::DaemonNotRunning { path: socket_path }.into());
+    }
+
+    let mut stdin_input = String::new();
+    std::io::stdin().read_to_string(&mut stdin_input)?;
+    if stdin_input.trim().is_empty() {
+        return Err(UsageError::boxed("No JSON message provided on stdin"));
+    }
+    let message: serde_json::Value = serde_json::from_str(stdin_input.trim())
+        .map_err(|e| UsageError::boxed(format!("Invalid JSON on stdin: {}", e)))?;
+
+    let daemon_request = fastn_p2p_client::DaemonRequest::Send {
+        from_identity: as_identity,
+        to_peer,
+        protocol,
+        bind_alias,
+        command,
+        args,
+        message: fastn_p2p_client::Sensitive(message),
+    };
+    let response = crate::cli::client::send_daemon_request(&fastn_home, &daemon_request).await?;
+    crate::cli::client::ensure_success(&response)?;
+    let queued: fastn_p2p_client::outbox::Queued = serde_json::from_value(response["data"].clone())?;
+
+    say!("📮 Queued message {} from {} to {}", queued.message_id, queued.from_identity, queued.peer);
+    say!("   Check on it with: fastn-p2p delivery {}", queued.message_id);
+    crate::cli::output::result(queued);
+    Ok(())
+}
+
+/// Show the delivery status of `message_id`, or of every message sent
+pub async fn delivery(
+    fastn_home: PathBuf,
+    message_id: Option<String>,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity = crate::cli::peers::address_book_owner(&fastn_home, as_identity.as_deref()).await?;
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+
+    let deliveries = match &message_id {
+        Some(message_id) => match store.delivery(&identity, message_id).await? {
+            Some(delivery) => vec![delivery],
+            None => return Err(format!("No message {} in the outbox of '{}'", message_id, identity).into()),
+        },
+        None => store.deliveries(&identity).await?,
+    };
+
+    say!("📬 Messages sent by '{}'", identity);
+    if deliveries.is_empty() {
+        say!("   None, queue one with: fastn-p2p send <peer> <protocol>");
+    }
+    for delivery in &deliveries {
+        match &delivery.status {
+            fastn_p2p::server::state::DeliveryStatus::Pending { attempts, last_error } => say!(
+                "   ⏳ {} to {}: pending, {} attempts{}",
+                delivery.message_id,
+                delivery.peer,
+                attempts,
+                last_error.as_deref().map(|e| format!(" ({})", e)).unwrap_or_default()
+            ),
+            fastn_p2p::server::state::DeliveryStatus::Delivered { delivered_at, .. } => say!(
+                "   ✅ {} to {}: delivered {}",
+                delivery.message_id,
+                delivery.peer,
+                delivered_at.to_rfc3339()
+            ),
+        }
+    }
+
+    match message_id {
+        Some(_) => crate::cli::output::result(&deliveries[0]),
+        None => crate::cli::output::result(serde_json::json!({ "identity": identity, "messages": deliveries })),
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+//! Output format and exit codes shared by every command
+//!
+//! With `--output text` (the default) commands print their usual lines. With
+//! `--output json` those lines go to stderr and stdout gets exactly one JSON
+//! object when the command ends:
+//!
+//! ```json
+//! {"ok": true, "data": {...}}
+//! {"ok": false, "error": {"kind": "peer-unreachable", "message": "..."}}
+//! ```
+//!
+//! `data` is what the command reported with [`result`], or `null`. The exit
+//! code follows [`Exit`] in both formats, so scripts can branch on it
+//! without parsing anything.
+
+/// `--output`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Exit codes of `fastn-p2p`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Success = 0,
+    /// The command ran and failed: a rejected change, an error from the peer's handler, bad input data
+    AppError = 1,
+    /// Bad arguments; clap uses the same code for the ones it catches
+    Usage = 2,
+    /// No daemon listening on FASTN_HOME's control socket
+    DaemonUnreachable = 3,
+    /// The daemon is up but the peer could not be reached or timed out
+    PeerUnreachable = 4,
+}
+
+impl Exit {
+    /// How `error` is reported: exit code and the `kind` in JSON output
+    pub fn of(error: &(dyn std::error::Error + 'static)) -> (Exit, &'static str) {
+        let mut source = Some(error);
+        while let Some(error) = source {
+            if error.is::<UsageError>() {
+                return (Exit::Usage, "usage");
+            }
+            if let Some(error) = error.downcast_ref::<fastn_p2p_client::ClientError>() {
+                return match error {
+                    fastn_p2p_client::ClientError::DaemonNotRunning { .. }
+                    | fastn_p2p_client::ClientError::DaemonConnection(_) => (Exit::DaemonUnreachable, "daemon-unreachable"),
+                    fastn_p2p_client::ClientError::VersionMismatch { .. } => (Exit::DaemonUnreachable, "version-mismatch"),
+                    fastn_p2p_client::ClientError::PeerUnreachable(_)
+                    | fastn_p2p_client::ClientError::Timeout(_)
+                    | fastn_p2p_client::ClientError::CircuitOpen(_) => (Exit::PeerUnreachable, "peer-unreachable"),
+                    _ => (Exit::AppError, "app-error"),
+                };
+            }
+            if let Some(error) = error.downcast_ref::<fastn_p2p::client::CallError>()
+                && error.is_peer_failure()
+            {
+                return (Exit::PeerUnreachable, "peer-unreachable");
+            }
+            source = error.source();
+        }
+        (Exit::AppError, "app-error")
+    }
+}
+
+/// Arguments that parse but make no sense, e.g. a malformed peer ID
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct UsageError(pub String);
+
+impl UsageError {
+    pub fn boxed(message: impl Into<String>) -> Box<dyn std::error::Error> {
+        Box::new(UsageError(message.into()))
+    }
+}
+
+static FORMAT: std::sync::OnceLock<Format> = std::sync::OnceLock::new();
+static VERBOSITY: std::sync::OnceLock<fastn_p2p::console::Verbosity> = std::sync::OnceLock::new();
+static RESULT: std::sync::Mutex<Option<serde_json::Value>> = std::sync::Mutex::new(None);
+
+/// Pick the format and verbosity for this process; called once from `main`
+pub fn init(format: Format, verbosity: fastn_p2p::console::Verbosity) {
+    let _ = FORMAT.set(format);
+    let _ = VERBOSITY.set(verbosity);
+}
+
+pub fn format() -> Format {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// `-q` / `-v`
+pub fn verbosity() -> fastn_p2p::console::Verbosity {
+    VERBOSITY.get().copied().unwrap_or_default()
+}
+
+/// Prints the library's status lines and logs where [`say`] puts ours
+pub fn console_layer() -> fastn_p2p::console::ConsoleLayer {
+    fastn_p2p::console::ConsoleLayer::new(verbosity()).lines_to_stderr(format() == Format::Json)
+}
+
+/// A line for people; to stderr in JSON mode so stdout stays parseable, nothing with `-q`
+pub fn say(line: std::fmt::Arguments<'_>) {
+    if verbosity() == fastn_p2p::console::Verbosity::Quiet {
+        return;
+    }
+    match format() {
+        Format::Text => println!("{}", line),
+        Format::Json => eprintln!("{}", line),
+    }
+}
+
+/// The command's structured result, printed as `data` in JSON mode
+pub fn result(data: impl serde::Serialize) {
+    let data = serde_json::to_value(data).expect("command results serialize to JSON");
+    *RESULT.lock().expect("Failed to acquire lock on command result") = Some(data);
+}
+
+/// Report how the command went and exit with its [`Exit`] code
+pub fn finish(outcome: Result<(), Box<dyn std::error::Error>>) -> ! {
+    let exit = match outcome {
+        Ok(()) => {
+            if format() == Format::Json {
+                let data = RESULT.lock().expect("Failed to acquire lock on command result").take();
+                println!("{}", serde_json::json!({ "ok": true, "data": data }));
+            }
+            Exit::Success
+        }
+        Err(error) => {
+            let (exit, kind) = Exit::of(error.as_ref());
+            match format() {
+                Format::Text => eprintln!("❌ {}", error),
+                Format::Json => println!(
+                    "{}",
+                    serde_json::json!({ "ok": false, "error": { "kind": kind, "message": error.to_string() } })
+                ),
+            }
+            exit
+        }
+    };
+    std::process::exit(exit as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let usage = UsageError::boxed("Invalid peer ID 'x'");
+        assert_eq!(Exit::of(usage.as_ref()), (Exit::Usage, "usage"));
+
+        let not_running = fastn_p2p_client::ClientError::DaemonNotRunning { path: "/tmp/control.sock".into() };
+        assert_eq!(Exit::of(&not_running).0, Exit::DaemonUnreachable);
+
+        let mismatch = fastn_p2p_client::ClientError::VersionMismatch {
+            daemon_version: "0.0.9".to_string(),
+            daemon_wire: 0,
+            client_wire: 1,
+        };
+        assert_eq!(Exit::of(&mismatch), (Exit::DaemonUnreachable, "version-mismatch"));
+
+        let unreachable = fastn_p2p_client::ClientError::PeerUnreachable("no route".to_string());
+        assert_eq!(Exit::of(&unreachable), (Exit::PeerUnreachable, "peer-unreachable"));
+
+        let rejected = fastn_p2p_client::ClientError::Rejected { kind: "io".to_string(), error: "disk full".to_string() };
+        assert_eq!(Exit::of(&rejected).0, Exit::AppError);
+        let plain: Box<dyn std::error::Error> = "Identity 'alice' not found".into();
+        assert_eq!(Exit::of(plain.as_ref()).0, Exit::AppError);
+    }
+}
@@ -0,0 +1,146 @@
+//! Peer commands: an identity's address book and its peer groups
+//!
+//! Contacts and groups live in `state.db`, see
+//! [`fastn_p2p::server::state`]. `call` and `stream` take a group name
+//! wherever they take a peer ID52 and reach every member.
+
+use std::path::{Path, PathBuf};
+
+use crate::cli::output::UsageError;
+
+/// Identity whose address book `requested` means: the alias, the default identity or the only one
+///
+/// Unlike calls this doesn't need the identity online, and identities
+/// paired from another machine have an address book here too.
+pub async fn address_book_owner(
+    fastn_home: &Path,
+    requested: Option<&str>,
+) -> Result<String, fastn_p2p::server::IdentityError> {
+    match fastn_p2p::server::devices::resolve_remote_identity(fastn_home, requested).await {
+        Ok(Some(remote)) => return Ok(remote.alias),
+        Ok(None) => {}
+        Err(e) => return Err(fastn_p2p::server::IdentityError::Load { message: e.to_string() }),
+    }
+    match fastn_p2p::server::resolve_identity(fastn_home, requested).await {
+        Ok(identity) => Ok(identity.alias),
+        Err(fastn_p2p::server::IdentityError::Offline { alias }) => Ok(alias),
+        Err(e) => Err(e),
+    }
+}
+
+/// Add `name` for `peer` to the address book, replacing an entry of that name
+pub async fn add(
+    fastn_home: PathBuf,
+    name: String,
+    peer_id52: String,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let peer: fastn_id52::PublicKey = peer_id52.parse()
+        .map_err(|e| UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+    let identity = address_book_owner(&fastn_home, as_identity.as_deref()).await?;
+
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    store.set_contact(&identity, &name, &peer).await?;
+
+    say!("📇 Added '{}' to the address book of '{}'", name, identity);
+    say!("   Peer ID: {}", peer.id52());
+    crate::cli::output::result(serde_json::json!({ "identity": identity, "name": name, "peer": peer.id52() }));
+    Ok(())
+}
+
+/// Remove `name` from the address book; groups naming it can't reach it any more
+pub async fn remove(
+    fastn_home: PathBuf,
+    name: String,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity = address_book_owner(&fastn_home, as_identity.as_deref()).await?;
+
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    if !store.remove_contact(&identity, &name).await? {
+        return Err(format!("'{}' is not in the address book of '{}'", name, identity).into());
+    }
+
+    say!("🗑️  Removed '{}' from the address book of '{}'", name, identity);
+    crate::cli::output::result(serde_json::json!({ "identity": identity, "name": name }));
+    Ok(())
+}
+
+/// Show the address book and the peer groups
+pub async fn list(
+    fastn_home: PathBuf,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity = address_book_owner(&fastn_home, as_identity.as_deref()).await?;
+
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    let contacts = store.contacts(&identity).await?;
+    let groups = store.groups(&identity).await?;
+
+    say!("📇 Address book of '{}'", identity);
+    if contacts.is_empty() {
+        say!("   No contacts, add one with: fastn-p2p peers add <name> <id52>");
+    }
+    for contact in &contacts {
+        say!("   {} {}", contact.name, contact.peer.id52());
+    }
+    if !groups.is_empty() {
+        say!();
+        say!("👥 Groups");
+    }
+    for group in &groups {
+        say!("   {}: {}", group.name, group.members.join(", "));
+    }
+
+    let contacts: Vec<_> = contacts
+        .iter()
+        .map(|contact| serde_json::json!({ "name": contact.name, "peer": contact.peer.id52() }))
+        .collect();
+    crate::cli::output::result(serde_json::json!({ "identity": identity, "contacts": contacts, "groups": groups }));
+    Ok(())
+}
+
+/// Define group `name` as `members`, contact names or ID52s
+pub async fn create_group(
+    fastn_home: PathBuf,
+    name: String,
+    members: Vec<String>,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Group names stand in for a peer in call and stream
+    if name.parse::<fastn_id52::PublicKey>().is_ok() {
+        return Err(UsageError::boxed(format!("Group name '{}' is a peer ID52, choose another", name)));
+    }
+    let identity = address_book_owner(&fastn_home, as_identity.as_deref()).await?;
+
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    match store.set_group(&identity, &name, &members).await {
+        Ok(()) => {}
+        Err(e @ fastn_p2p::server::state::StateError::UnknownMember { .. }) => {
+            return Err(UsageError::boxed(format!("{}, add it with: fastn-p2p peers add <name> <id52>", e)));
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    say!("👥 Group '{}' of '{}': {}", name, identity, members.join(", "));
+    crate::cli::output::result(fastn_p2p::server::state::PeerGroup { name, members });
+    Ok(())
+}
+
+/// Remove group `name`; its members stay in the address book
+pub async fn delete_group(
+    fastn_home: PathBuf,
+    name: String,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity = address_book_owner(&fastn_home, as_identity.as_deref()).await?;
+
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    if !store.remove_group(&identity, &name).await? {
+        return Err(format!("'{}' is not a peer group of '{}'", name, identity).into());
+    }
+
+    say!("🗑️  Removed group '{}' of '{}'", name, identity);
+    crate::cli::output::result(serde_json::json!({ "identity": identity, "group": name }));
+    Ok(())
+}
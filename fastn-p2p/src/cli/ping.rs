@@ -0,0 +1,96 @@
+//! Ping command: round trip times to a peer through the daemon
+//!
+//! Every fastn-p2p server answers pings without a handler, see
+//! [`fastn_p2p::ping`], so this works against any peer that is online.
+
+use std::path::PathBuf;
+
+use crate::cli::output::UsageError;
+
+/// Wait between two pings
+const PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A ping without an answer by then counts as lost; the first one includes connecting
+const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Ping `peer` `count` times and report round trip statistics and the path taken
+///
+/// Fails if no ping was answered.
+pub async fn ping(
+    fastn_home: PathBuf,
+    peer_id52: String,
+    count: u32,
+    as_identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let peer: fastn_id52::PublicKey = peer_id52.parse()
+        .map_err(|e| UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+    if count == 0 {
+        return Err(UsageError::boxed("--count must be at least 1"));
+    }
+
+    say!("🏓 Pinging {} from {}", peer.id52(), as_identity.as_deref().unwrap_or("default identity"));
+
+    let mut samples = Vec::new();
+    let mut paths = Vec::new();
+    let mut last_error = None;
+    for seq in 1..=count {
+        if seq > 1 {
+            tokio::time::sleep(PING_INTERVAL).await;
+        }
+        let reply = tokio::time::timeout(
+            PING_TIMEOUT,
+            fastn_p2p_client::admin::ping(&fastn_home, as_identity.as_deref(), peer),
+        )
+        .await;
+        match reply {
+            Ok(Ok(reply)) => {
+                say!("   seq={} time={:.1}ms path={}", seq, reply.rtt_ms, reply.path);
+                samples.push(Some(std::time::Duration::from_secs_f64(reply.rtt_ms / 1000.0)));
+                paths.push(reply.path);
+            }
+            // Nothing to retry without a daemon or a usable identity
+            Ok(Err(e @ (fastn_p2p_client::ClientError::DaemonNotRunning { .. }
+                | fastn_p2p_client::ClientError::Identity(_)))) => return Err(e.into()),
+            Ok(Err(e)) => {
+                say!("   seq={} lost: {}", seq, e);
+                samples.push(None);
+                last_error = Some(e);
+            }
+            Err(_) => {
+                say!("   seq={} lost: no answer within {}s", seq, PING_TIMEOUT.as_secs());
+                samples.push(None);
+                last_error = Some(fastn_p2p_client::ClientError::Timeout(format!("No pong from {}", peer.id52())));
+            }
+        }
+    }
+
+    let stats = fastn_p2p::ping::PingStats::from_samples(samples.iter().copied());
+    let ms = |rtt: Option<std::time::Duration>| rtt.map(|rtt| rtt.as_secs_f64() * 1000.0);
+    say!();
+    say!("📊 {} sent, {} received, {:.0}% loss", stats.sent, stats.received, stats.loss_percent());
+    if let (Some(min), Some(avg), Some(max)) = (ms(stats.min), ms(stats.avg), ms(stats.max)) {
+        say!("   rtt min/avg/max = {:.1}/{:.1}/{:.1} ms", min, avg, max);
+    }
+    // The path can change mid-run, e.g. relay to direct once hole punching works
+    if let Some(path) = paths.last() {
+        say!("   path: {}", path);
+    }
+
+    if stats.received == 0
+        && let Some(e) = last_error
+    {
+        return Err(e.into());
+    }
+    crate::cli::output::result(serde_json::json!({
+        "peer": peer.id52(),
+        "sent": stats.sent,
+        "received": stats.received,
+        "loss_percent": stats.loss_percent(),
+        "min_ms": ms(stats.min),
+        "avg_ms": ms(stats.avg),
+        "max_ms": ms(stats.max),
+        "path": paths.last(),
+        "samples_ms": samples.iter().map(|sample| ms(*sample)).collect::<Vec<_>>(),
+    }));
+    Ok(())
+}
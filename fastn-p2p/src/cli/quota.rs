@@ -0,0 +1,112 @@
+//! Quota commands: show and set storage limits of an identity
+
+use std::path::{Path, PathBuf};
+
+/// Show storage used by `identity` and each of its bindings
+pub async fn show(fastn_home: PathBuf, identity: String) -> Result<(), Box<dyn std::error::Error>> {
+    let identity_dir = existing_identity_dir(&fastn_home, &identity)?;
+    let report = fastn_p2p::server::quota::report(&identity_dir).await?;
+
+    say!("💾 Storage of identity '{}'", identity);
+    say!("   {} of {}", fastn_p2p::progress::format_bytes(report.used), format_limit(report.limit));
+    say!();
+
+    if report.bindings.is_empty() {
+        say!("   No protocol bindings");
+    }
+    for binding in &report.bindings {
+        let over = binding.limit.is_some_and(|limit| binding.used > limit);
+        say!(
+            "   {} {} {}: {} of {}",
+            if over { "⚠️ " } else { "📁" },
+            binding.protocol,
+            binding.bind_alias,
+            fastn_p2p::progress::format_bytes(binding.used),
+            format_limit(binding.limit),
+        );
+    }
+    if report.limit.is_some_and(|limit| report.used > limit) {
+        say!();
+        say!("⚠️  Over the identity quota; writes fail until space is freed");
+    }
+    crate::cli::output::result(&report);
+    Ok(())
+}
+
+/// Set (or with `none`, remove) the quota of `identity` or of one of its bindings
+pub async fn set(
+    fastn_home: PathBuf,
+    identity: String,
+    limit: String,
+    protocol: Option<String>,
+    bind_alias: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity_dir = existing_identity_dir(&fastn_home, &identity)?;
+    let limit = parse_limit(&limit)?;
+
+    let mut config = fastn_p2p::server::quota::QuotaConfig::load(&identity_dir).await?;
+    let scope = match &protocol {
+        Some(protocol) => {
+            let key = fastn_p2p::server::quota::binding_key(protocol, &bind_alias);
+            match limit {
+                Some(limit) => config.bindings.insert(key, limit),
+                None => config.bindings.remove(&key),
+            };
+            format!("{} {}", protocol, bind_alias)
+        }
+        None => {
+            config.max_bytes = limit;
+            format!("identity '{}'", identity)
+        }
+    };
+    config.save(&identity_dir).await?;
+
+    // Read on every write, so no daemon reload is needed
+    say!("✅ Quota of {} set to {}", scope, format_limit(limit));
+    crate::cli::output::result(serde_json::json!({
+        "identity": identity,
+        "protocol": &protocol,
+        "bind_alias": protocol.as_ref().map(|_| &bind_alias),
+        "limit": limit,
+    }));
+    Ok(())
+}
+
+fn existing_identity_dir(fastn_home: &Path, identity: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let identity_dir = fastn_home.join("identities").join(identity);
+    if !identity_dir.is_dir() {
+        return Err(format!("Identity '{}' not found", identity).into());
+    }
+    Ok(identity_dir)
+}
+
+/// Parse `500M`, `10G`, `1048576` (bytes) or `none`
+fn parse_limit(limit: &str) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    if limit.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+
+    let invalid = || crate::cli::output::UsageError::boxed(format!("Invalid quota '{}': use bytes, a size like 500M or 10G, or none", limit));
+    let (number, multiplier) = match limit.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier: u64 = match unit.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(invalid()),
+            };
+            (&limit[..i], multiplier)
+        }
+        _ => (limit, 1),
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    Ok(Some(number.checked_mul(multiplier).ok_or_else(invalid)?))
+}
+
+fn format_limit(limit: Option<u64>) -> String {
+    match limit {
+        Some(limit) => fastn_p2p::progress::format_bytes(limit),
+        None => "unlimited".to_string(),
+    }
+}
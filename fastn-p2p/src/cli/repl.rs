@@ -0,0 +1,418 @@
+//! Interactive prompt for exploring peers through the daemon
+//!
+//! `fastn-p2p repl` reads lines like
+//!
+//! ```text
+//! call <peer> <protocol> [command] [--alias X] {"inline": "json"}
+//! stream <peer> <protocol> [command] [--alias X] [json]
+//! ```
+//!
+//! and prints responses (or stream output as it arrives) without crafting
+//! echo pipelines. Peers, protocols and commands that answered are remembered
+//! in `FASTN_HOME/repl.json` and offered for tab completion, along with local
+//! identities and the protocols they serve. Line history is kept in
+//! `FASTN_HOME/repl_history`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+const REPL_COMMANDS: &[&str] = &["call", "stream", "as", "peers", "history", "help", "exit"];
+
+/// Protocols and commands that answered, per peer ID52
+#[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+struct KnownPeers {
+    peers: BTreeMap<String, BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl KnownPeers {
+    async fn load(path: &Path) -> Self {
+        match tokio::fs::read_to_string(path).await {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("⚠️  Ignoring unreadable {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    async fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        tokio::fs::write(path, serde_json::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+
+    fn record(&mut self, peer: &fastn_id52::PublicKey, protocol: &str, command: Option<&str>) {
+        let commands = self.peers.entry(peer.id52()).or_default().entry(protocol.to_string()).or_default();
+        if let Some(command) = command {
+            commands.insert(command.to_string());
+        }
+    }
+}
+
+/// One line typed at the prompt
+#[derive(Debug, PartialEq)]
+enum ReplCommand {
+    Call {
+        stream: bool,
+        peer: String,
+        protocol: String,
+        command: Option<String>,
+        alias: String,
+        data: serde_json::Value,
+    },
+    As(String),
+    Peers,
+    History,
+    Help,
+    Exit,
+    Empty,
+}
+
+/// Parse a prompt line; inline JSON starts at the first `{` or `[`
+fn parse_line(line: &str) -> Result<ReplCommand, String> {
+    let (words, json) = match line.find(['{', '[']) {
+        Some(start) => (&line[..start], Some(line[start..].trim())),
+        None => (line, None),
+    };
+    let mut words = words.split_whitespace();
+    let Some(name) = words.next() else {
+        return Ok(ReplCommand::Empty);
+    };
+
+    match name {
+        "call" | "stream" => {
+            let mut positional = Vec::new();
+            let mut alias = "default".to_string();
+            while let Some(word) = words.next() {
+                if word == "--alias" {
+                    alias = words.next().ok_or("--alias needs a value")?.to_string();
+                } else {
+                    positional.push(word.to_string());
+                }
+            }
+            let mut positional = positional.into_iter();
+            let (Some(peer), Some(protocol)) = (positional.next(), positional.next()) else {
+                return Err(format!("Usage: {} <peer> <protocol> [command] [--alias X] [json]", name));
+            };
+            let command = positional.next();
+            if let Some(extra) = positional.next() {
+                return Err(format!("Unexpected '{}', JSON must start with {{ or [", extra));
+            }
+
+            let stream = name == "stream";
+            let data = match json {
+                Some(json) => serde_json::from_str(json).map_err(|e| format!("Invalid JSON: {}", e))?,
+                // Calls need a request body, streams may start without one
+                None if stream => serde_json::Value::Null,
+                None => serde_json::json!({}),
+            };
+            Ok(ReplCommand::Call { stream, peer, protocol, command, alias, data })
+        }
+        "as" => words
+            .next()
+            .map(|identity| ReplCommand::As(identity.to_string()))
+            .ok_or_else(|| "Usage: as <identity>".to_string()),
+        "peers" => Ok(ReplCommand::Peers),
+        "history" => Ok(ReplCommand::History),
+        "help" | "?" => Ok(ReplCommand::Help),
+        "exit" | "quit" => Ok(ReplCommand::Exit),
+        other => Err(format!("Unknown command '{}', try: help", other)),
+    }
+}
+
+/// Tab completion of REPL commands, peers, protocols and commands
+#[derive(Default)]
+struct ReplHelper {
+    known: KnownPeers,
+    /// Local identities: alias and ID52
+    identities: Vec<(String, String)>,
+    /// Protocols served by local identities, worth trying on peers
+    local_protocols: BTreeSet<String>,
+}
+
+impl ReplHelper {
+    fn candidates(&self, words: &[&str]) -> Vec<String> {
+        match words {
+            [] => REPL_COMMANDS.iter().map(|c| c.to_string()).collect(),
+            ["as"] => self.identities.iter().map(|(alias, _)| alias.clone()).collect(),
+            ["call" | "stream"] => {
+                let mut peers: BTreeSet<String> = self.known.peers.keys().cloned().collect();
+                peers.extend(self.identities.iter().map(|(_, id52)| id52.clone()));
+                peers.into_iter().collect()
+            }
+            ["call" | "stream", peer] => {
+                let mut protocols = self.local_protocols.clone();
+                if let Some(known) = self.known.peers.get(*peer) {
+                    protocols.extend(known.keys().cloned());
+                }
+                protocols.into_iter().collect()
+            }
+            ["call" | "stream", peer, protocol] => self
+                .known
+                .peers
+                .get(*peer)
+                .and_then(|protocols| protocols.get(*protocol))
+                .map(|commands| commands.iter().cloned().collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl rustyline::completion::Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let partial = &before[start..];
+        // Options and inline JSON are not completed
+        if partial.starts_with(['-', '{', '[']) {
+            return Ok((pos, Vec::new()));
+        }
+
+        let mut words = Vec::new();
+        let mut typed = before[..start].split_whitespace();
+        while let Some(word) = typed.next() {
+            if word == "--alias" {
+                typed.next();
+            } else {
+                words.push(word);
+            }
+        }
+        let candidates = self
+            .candidates(&words)
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(partial))
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl rustyline::hint::Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl rustyline::highlight::Highlighter for ReplHelper {}
+
+impl rustyline::validate::Validator for ReplHelper {}
+
+impl rustyline::Helper for ReplHelper {}
+
+/// Run the interactive prompt until `exit` or end of input
+pub async fn run(fastn_home: PathBuf, as_identity: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let known_path = fastn_home.join("repl.json");
+    let history_path = fastn_home.join("repl_history");
+
+    let mut helper = ReplHelper {
+        known: KnownPeers::load(&known_path).await,
+        ..Default::default()
+    };
+    for identity in fastn_p2p::server::load_all_identities(&fastn_home).await? {
+        helper.identities.push((identity.alias, identity.secret_key.public_key().id52()));
+        helper.local_protocols.extend(identity.protocols.into_iter().map(|binding| binding.protocol));
+    }
+
+    let mut editor = rustyline::Editor::<ReplHelper, rustyline::history::DefaultHistory>::new()?;
+    editor.set_helper(Some(helper));
+    // No history yet on first use
+    let _ = editor.load_history(&history_path);
+
+    let mut identity = as_identity;
+    println!("🧪 fastn-p2p repl, sending as {} (type help, Tab completes)",
+            identity.as_deref().unwrap_or("the default identity"));
+
+    loop {
+        let prompt = format!("{}> ", identity.as_deref().unwrap_or("p2p"));
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(rustyline::error::ReadlineError::Interrupted) => continue,
+            Err(rustyline::error::ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+
+        match parse_line(&line) {
+            Ok(ReplCommand::Call { stream, peer, protocol, command, alias, data }) => {
+                let to_peer: fastn_id52::PublicKey = match peer.parse() {
+                    Ok(to_peer) => to_peer,
+                    Err(e) => {
+                        println!("❌ Invalid peer ID '{}': {}", peer, e);
+                        continue;
+                    }
+                };
+                let result = if stream {
+                    run_stream(&fastn_home, identity.clone(), to_peer, &protocol, command.clone(), alias, data).await
+                } else {
+                    run_call(&fastn_home, identity.clone(), to_peer, &protocol, command.clone(), alias, data).await
+                };
+                match result {
+                    Ok(()) => {
+                        let helper = editor.helper_mut().expect("Helper is set before the loop");
+                        helper.known.record(&to_peer, &protocol, command.as_deref());
+                        if let Err(e) = helper.known.save(&known_path).await {
+                            println!("⚠️  Could not save {}: {}", known_path.display(), e);
+                        }
+                    }
+                    Err(e) => println!("❌ {}", e),
+                }
+            }
+            Ok(ReplCommand::As(alias)) => {
+                match crate::cli::identity::resolve_identity(&fastn_home, Some(alias)).await {
+                    Ok(alias) => {
+                        println!("👤 Sending as {}", alias);
+                        identity = Some(alias);
+                    }
+                    Err(e) => println!("❌ {}", e),
+                }
+            }
+            Ok(ReplCommand::Peers) => {
+                let helper = editor.helper().expect("Helper is set before the loop");
+                if helper.known.peers.is_empty() {
+                    println!("   No peers yet, they are remembered once a call succeeds");
+                }
+                for (peer, protocols) in &helper.known.peers {
+                    println!("🌐 {}", peer);
+                    for (protocol, commands) in protocols {
+                        let commands: Vec<&str> = commands.iter().map(String::as_str).collect();
+                        println!("   {} {}", protocol, commands.join(" "));
+                    }
+                }
+            }
+            Ok(ReplCommand::History) => {
+                for (i, entry) in editor.history().iter().enumerate() {
+                    println!("{:>4}  {}", i + 1, entry);
+                }
+            }
+            Ok(ReplCommand::Help) => print_help(),
+            Ok(ReplCommand::Exit) => break,
+            Ok(ReplCommand::Empty) => {}
+            Err(e) => println!("❌ {}", e),
+        }
+    }
+
+    if let Err(e) = editor.save_history(&history_path) {
+        eprintln!("⚠️  Could not save history to {}: {}", history_path.display(), e);
+    }
+    Ok(())
+}
+
+fn print_help() {
+    println!("call <peer> <protocol> [command] [--alias X] [json]    request/response call, body defaults to {{}}");
+    println!("stream <peer> <protocol> [command] [--alias X] [json]  open a stream and show its output");
+    println!("as <identity>                                          send as another local identity");
+    println!("peers                                                  peers, protocols and commands seen so far");
+    println!("history                                                lines entered, kept across sessions");
+    println!("exit                                                   leave (Ctrl-D works too)");
+}
+
+async fn run_call(
+    fastn_home: &Path,
+    identity: Option<String>,
+    to_peer: fastn_id52::PublicKey,
+    protocol: &str,
+    command: Option<String>,
+    alias: String,
+    data: serde_json::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = crate::cli::client::Target { command, ..crate::cli::client::Target::new(protocol, alias) };
+    let response = crate::cli::client::call_daemon(fastn_home, identity, to_peer, target, data, Default::default()).await?;
+    crate::cli::client::ensure_success(&response)?;
+    println!("{}", serde_json::to_string_pretty(&response["data"]["p2p_response"])?);
+    Ok(())
+}
+
+/// Open a stream without sending input and print its output until the peer ends it
+async fn run_stream(
+    fastn_home: &Path,
+    identity: Option<String>,
+    to_peer: fastn_id52::PublicKey,
+    protocol: &str,
+    command: Option<String>,
+    alias: String,
+    data: serde_json::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::AsyncWriteExt;
+
+    let request = fastn_p2p_client::DaemonRequest::Stream {
+        from_identity: identity,
+        to_peer,
+        protocol: protocol.to_string(),
+        bind_alias: alias,
+        command,
+        args: Vec::new(),
+        initial_data: fastn_p2p_client::Sensitive(data),
+        metadata: Default::default(),
+        traceparent: Some(fastn_net::TraceContext::new_root().traceparent()),
+    };
+    let (mut reader, mut writer) = crate::cli::client::open_stream(fastn_home, &request).await?;
+    writer.shutdown().await?;
+
+    let mut stdout = tokio::io::stdout();
+    let end = loop {
+        match fastn_p2p_client::stream::read_frame(&mut reader).await? {
+            fastn_p2p_client::stream::Frame::Data(bytes) => {
+                stdout.write_all(&bytes).await?;
+                stdout.flush().await?;
+            }
+            fastn_p2p_client::stream::Frame::End(end) => break end,
+        }
+    };
+    match end.error {
+        Some(error) => Err(format!("Stream broke: {}", error).into()),
+        None => {
+            println!("⏹  Stream ended with exit code {}", end.exit_code);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line(r#"call abc mail.fastn.com get-mails --alias work {"folder": "inbox"}"#),
+            Ok(ReplCommand::Call {
+                stream: false,
+                peer: "abc".to_string(),
+                protocol: "mail.fastn.com".to_string(),
+                command: Some("get-mails".to_string()),
+                alias: "work".to_string(),
+                data: serde_json::json!({"folder": "inbox"}),
+            })
+        );
+        assert!(matches!(
+            parse_line("stream abc Echo"),
+            Ok(ReplCommand::Call { stream: true, command: None, data: serde_json::Value::Null, .. })
+        ));
+        assert!(parse_line("call abc").is_err());
+        assert!(parse_line("call abc Echo {broken").is_err());
+        assert_eq!(parse_line("   "), Ok(ReplCommand::Empty));
+    }
+
+    #[test]
+    fn test_completion_candidates() {
+        let mut helper = ReplHelper::default();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        helper.known.record(&peer, "mail.fastn.com", Some("get-mails"));
+        helper.local_protocols.insert("echo.fastn.com".to_string());
+
+        let id52 = peer.id52();
+        assert!(helper.candidates(&[]).contains(&"stream".to_string()));
+        assert_eq!(helper.candidates(&["call"]), vec![id52.clone()]);
+        assert_eq!(
+            helper.candidates(&["call", id52.as_str()]),
+            vec!["echo.fastn.com".to_string(), "mail.fastn.com".to_string()]
+        );
+        assert_eq!(helper.candidates(&["stream", id52.as_str(), "mail.fastn.com"]), vec!["get-mails".to_string()]);
+    }
+}
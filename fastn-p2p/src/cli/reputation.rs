@@ -0,0 +1,45 @@
+//! Reputation commands: show peers with strikes or bans, forgive them
+//!
+//! Limits are set in `config.toml`, see [`fastn_p2p::server::reputation`].
+//! Strikes live in the memory of the process serving the identity.
+
+use std::path::PathBuf;
+
+/// Show peers the daemon's identities hold strikes against, banned ones first
+pub async fn list(fastn_home: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let peers = fastn_p2p_client::admin::reputation(&fastn_home).await?;
+
+    if peers.is_empty() {
+        say!("✅ No peer has strikes");
+    }
+    let now_ms = chrono::Utc::now().timestamp_millis().max(0) as u64;
+    for peer in &peers {
+        match peer.banned_until_ms {
+            Some(until_ms) => say!("🚫 {}  banned for {}s", peer.peer, until_ms.saturating_sub(now_ms) / 1000),
+            None => say!("⚠️  {}  {}/{} strikes", peer.peer, peer.strikes, peer.threshold),
+        }
+        say!("   With: {}", peer.identity);
+        say!("   Last offense: {}", peer.last_offense);
+    }
+    crate::cli::output::result(peers);
+    Ok(())
+}
+
+/// Forget the strikes of `peer` and lift its ban, with one identity or all of them
+pub async fn clear(
+    fastn_home: PathBuf,
+    peer: String,
+    identity: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fn parse(what: &str, id52: String) -> Result<fastn_id52::PublicKey, Box<dyn std::error::Error>> {
+        id52.parse()
+            .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid {} ID '{}': {}", what, id52, e)))
+    }
+    let peer = parse("peer", peer)?;
+    let identity = identity.map(|identity| parse("identity", identity)).transpose()?;
+
+    let cleared = fastn_p2p_client::admin::clear_reputation(&fastn_home, peer, identity).await?;
+    say!("🧹 Cleared {} reputation records of {}", cleared.cleared, peer.id52());
+    crate::cli::output::result(cleared);
+    Ok(())
+}
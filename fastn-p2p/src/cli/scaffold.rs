@@ -0,0 +1,44 @@
+//! New-protocol command: a starter crate for a serve_all protocol
+//!
+//! See [`fastn_p2p::scaffold`] for what the crate contains.
+
+use std::path::PathBuf;
+
+/// Write the crate for protocol `name` to `dir`, or `./<crate name>`
+///
+/// Refuses to write into a directory that isn't empty.
+pub async fn new_protocol(name: String, dir: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let scaffold = fastn_p2p::scaffold::generate(&name)
+        .map_err(|e| crate::cli::output::UsageError::boxed(e.to_string()))?;
+    let dir = dir.unwrap_or_else(|| PathBuf::from(&scaffold.crate_name));
+
+    if let Ok(mut entries) = tokio::fs::read_dir(&dir).await
+        && entries.next_entry().await?.is_some()
+    {
+        return Err(crate::cli::output::UsageError::boxed(format!(
+            "{} already exists and is not empty",
+            dir.display()
+        )));
+    }
+
+    for (path, contents) in &scaffold.files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, contents).await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        say!("📝 {}", path.display());
+    }
+    say!("✅ Created protocol {} in {}", scaffold.protocol, dir.display());
+    say!("   Next: cd {} && cargo test", dir.display());
+
+    let files: Vec<_> = scaffold.files.iter().map(|(path, _)| dir.join(path)).collect();
+    crate::cli::output::result(serde_json::json!({
+        "protocol": scaffold.protocol,
+        "crate": scaffold.crate_name,
+        "dir": dir,
+        "files": files,
+    }));
+    Ok(())
+}
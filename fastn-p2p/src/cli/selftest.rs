@@ -0,0 +1,176 @@
+//! Selftest command: one-command health check of this machine's P2P setup
+//!
+//! Starts a throwaway identity in this process serving the Echo and Shell
+//! test protocols (see [`crate::cli::daemon::test_protocols`]), then calls and
+//! streams to it: first straight from the library, then through the daemon as
+//! the configured identity. Daemon checks are skipped when no daemon runs.
+
+use std::path::{Path, PathBuf};
+
+/// A check without an answer by then fails; the first one includes connecting
+const CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Sent through every check and expected back
+const PROBE: &str = "fastn-p2p selftest";
+
+enum Outcome {
+    Passed(String),
+    Failed(String),
+    Skipped(String),
+}
+
+type CheckResult = Result<String, Box<dyn std::error::Error>>;
+
+/// Run every check against an in-process responder and report pass/fail
+///
+/// Fails if any check failed.
+pub async fn selftest(fastn_home: PathBuf, as_identity: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::daemon::test_protocols::{ECHO_PROTOCOL, SHELL_PROTOCOL};
+
+    let responder = fastn_p2p::listen(fastn_id52::SecretKey::generate())
+        .handle_requests(ECHO_PROTOCOL, crate::cli::daemon::test_protocols::echo_handler)
+        .handle_streams(SHELL_PROTOCOL.to_string(), (), crate::cli::daemon::test_protocols::shell_stream_handler)
+        .start()?;
+    let peer = responder.public_key();
+    say!("🩺 Self-test against in-process identity {}", peer.id52());
+
+    let client = fastn_p2p::client::Client::new(fastn_id52::SecretKey::generate());
+    let daemon_running = crate::cli::client::connect_daemon(&fastn_home).await.is_ok();
+    let mut checks: Vec<(&str, Outcome)> = Vec::new();
+
+    checks.push(("library call", run(library_call(&client, peer)).await));
+    checks.push(("library stream", run(library_stream(&client, peer)).await));
+    match daemon_running {
+        true => {
+            checks.push(("daemon call", run(daemon_call(&fastn_home, as_identity.clone(), peer)).await));
+            checks.push(("daemon stream", run(daemon_stream(&fastn_home, as_identity, peer)).await));
+        }
+        false => {
+            for name in ["daemon call", "daemon stream"] {
+                checks.push((name, Outcome::Skipped(format!("no daemon at {}", fastn_home.display()))));
+            }
+        }
+    }
+    responder.stop();
+
+    let mut failed = 0;
+    let mut report = Vec::new();
+    for (name, outcome) in &checks {
+        let (status, detail) = match outcome {
+            Outcome::Passed(detail) => {
+                say!("   ✅ {}: {}", name, detail);
+                ("passed", detail)
+            }
+            Outcome::Failed(detail) => {
+                say!("   ❌ {}: {}", name, detail);
+                failed += 1;
+                ("failed", detail)
+            }
+            Outcome::Skipped(detail) => {
+                say!("   ⏭️  {}: skipped, {}", name, detail);
+                ("skipped", detail)
+            }
+        };
+        report.push(serde_json::json!({ "check": name, "status": status, "detail": detail }));
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} self-test checks failed", failed, checks.len()).into());
+    }
+    say!();
+    say!("🎉 All checks passed");
+    crate::cli::output::result(serde_json::json!({ "peer": peer.id52(), "checks": report }));
+    Ok(())
+}
+
+async fn run(check: impl std::future::Future<Output = CheckResult>) -> Outcome {
+    let started = std::time::Instant::now();
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(detail)) => Outcome::Passed(format!("{} in {:.0?}", detail, started.elapsed())),
+        Ok(Err(e)) => Outcome::Failed(e.to_string()),
+        Err(_) => Outcome::Failed(format!("no answer within {}s", CHECK_TIMEOUT.as_secs())),
+    }
+}
+
+/// The echo the Echo protocol answers [`PROBE`] with
+fn echoed() -> String {
+    format!("Echo: {}", PROBE)
+}
+
+async fn library_call(client: &fastn_p2p::client::Client, peer: fastn_id52::PublicKey) -> CheckResult {
+    let request = crate::cli::daemon::test_protocols::EchoRequest { message: PROBE.to_string() };
+    let response: crate::cli::daemon::test_protocols::EchoResult = client
+        .call(peer, crate::cli::daemon::test_protocols::ECHO_PROTOCOL, request)
+        .await?;
+    let response = response?;
+    expect(&response.echoed, &echoed())?;
+    Ok("Echo answered".to_string())
+}
+
+async fn library_stream(client: &fastn_p2p::client::Client, peer: fastn_id52::PublicKey) -> CheckResult {
+    let command = crate::cli::daemon::test_protocols::ShellCommand { command: "cat".to_string(), args: vec![] };
+    let mut session = client.connect(peer, crate::cli::daemon::test_protocols::SHELL_PROTOCOL, command).await?;
+    session.copy_from(PROBE.as_bytes()).await?;
+    session.send.finish()?;
+    let mut output = Vec::new();
+    session.copy_to(&mut output).await?;
+    expect(&String::from_utf8_lossy(&output), PROBE)?;
+    Ok("Shell cat streamed input back".to_string())
+}
+
+async fn daemon_call(fastn_home: &Path, as_identity: Option<String>, peer: fastn_id52::PublicKey) -> CheckResult {
+    let response = crate::cli::client::call_daemon(
+        fastn_home,
+        as_identity,
+        peer,
+        crate::cli::client::Target::new(crate::cli::daemon::test_protocols::ECHO_PROTOCOL, "default"),
+        serde_json::json!({ "message": PROBE }),
+        Default::default(),
+    )
+    .await?;
+    crate::cli::client::ensure_success(&response)?;
+    let echoed = response["data"]["p2p_response"]["echoed"]
+        .as_str()
+        .ok_or_else(|| format!("Unexpected daemon response: {}", response["data"]))?;
+    expect(echoed, &self::echoed())?;
+    Ok("Echo answered through the daemon".to_string())
+}
+
+async fn daemon_stream(fastn_home: &Path, as_identity: Option<String>, peer: fastn_id52::PublicKey) -> CheckResult {
+    use tokio::io::AsyncWriteExt;
+
+    let daemon_request = fastn_p2p_client::DaemonRequest::Stream {
+        from_identity: as_identity,
+        to_peer: peer,
+        protocol: crate::cli::daemon::test_protocols::SHELL_PROTOCOL.to_string(),
+        bind_alias: "default".to_string(),
+        command: None,
+        args: vec![],
+        initial_data: fastn_p2p_client::Sensitive(serde_json::json!({ "command": "cat", "args": [] })),
+        metadata: Default::default(),
+        traceparent: Some(fastn_net::TraceContext::new_root().traceparent()),
+    };
+    let (mut reader, mut writer) = crate::cli::client::open_stream(fastn_home, &daemon_request).await?;
+    writer.write_all(PROBE.as_bytes()).await?;
+    writer.shutdown().await?;
+
+    let mut output = Vec::new();
+    let end = loop {
+        match fastn_p2p_client::stream::read_frame(&mut reader).await? {
+            fastn_p2p_client::stream::Frame::Data(bytes) => output.extend_from_slice(&bytes),
+            fastn_p2p_client::stream::Frame::End(end) => break end,
+        }
+    };
+    if let Some(error) = end.error {
+        return Err(format!("Stream broke: {}", error).into());
+    }
+    expect(&String::from_utf8_lossy(&output), PROBE)?;
+    Ok("Shell cat streamed input back through the daemon".to_string())
+}
+
+fn expect(got: &str, expected: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match got == expected {
+        true => Ok(()),
+        false => Err(format!("expected {:?}, got {:?}", expected, got).into()),
+    }
+}
@@ -1,29 +1,68 @@
 //! Status command for showing comprehensive daemon and identity information
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Show comprehensive daemon and identity status
-pub async fn show_status(fastn_home: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    println!("📊 fastn-p2p Status");
-    println!("📁 FASTN_HOME: {}", fastn_home.display());
-    println!();
+///
+/// `peers` adds the daemon's circuit breakers, `protocols` its handler
+/// latencies, `endpoints` the endpoint serving each online identity.
+pub async fn show_status(
+    fastn_home: PathBuf,
+    peers: bool,
+    protocols: bool,
+    endpoints: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    say!("📊 fastn-p2p Status");
+    say!("📁 FASTN_HOME: {}", fastn_home.display());
+    say!();
     
     // Check if daemon is running
     let daemon_status = check_daemon_status(&fastn_home).await;
-    println!("🚀 Daemon: {}", daemon_status);
+    say!("🚀 Daemon: {}", daemon_status);
+    let daemon = show_daemon_info(&fastn_home).await?;
     
     // Show lock file status
     show_lock_status(&fastn_home).await?;
-    println!();
+    say!();
     
     // Show all identities and their configurations
-    show_identities_status(&fastn_home).await?;
+    let identities = show_identities_status(&fastn_home).await?;
     
+    let peers = if peers {
+        say!();
+        show_peer_status(&fastn_home).await?
+    } else {
+        None
+    };
+    
+    let protocols = if protocols {
+        say!();
+        show_protocol_metrics(&fastn_home).await?
+    } else {
+        None
+    };
+    
+    let endpoints = if endpoints {
+        say!();
+        show_endpoint_status(&fastn_home).await?
+    } else {
+        None
+    };
+    
+    crate::cli::output::result(serde_json::json!({
+        "fastn_home": fastn_home,
+        "daemon_running": fastn_home.join("control.sock").exists(),
+        "daemon": daemon,
+        "identities": identities,
+        "peers": peers,
+        "protocols": protocols,
+        "endpoints": endpoints,
+    }));
     Ok(())
 }
 
 /// Check if daemon is currently running
-async fn check_daemon_status(fastn_home: &PathBuf) -> String {
+async fn check_daemon_status(fastn_home: &Path) -> String {
     let socket_path = fastn_home.join("control.sock");
     let lock_path = fastn_home.join("lock.file");
     
@@ -37,8 +76,37 @@ async fn check_daemon_status(fastn_home: &PathBuf) -> String {
     }
 }
 
+/// Show the running daemon's release and control protocol, and any newer release it found
+///
+/// A daemon this CLI can't talk to is reported with what to upgrade.
+async fn show_daemon_info(
+    fastn_home: &Path,
+) -> Result<Option<fastn_p2p_client::admin::DaemonInfo>, Box<dyn std::error::Error>> {
+    let info = match fastn_p2p_client::admin::daemon_info(fastn_home).await {
+        Ok(info) => info,
+        Err(fastn_p2p_client::ClientError::DaemonNotRunning { .. }) => return Ok(None),
+        Err(e @ fastn_p2p_client::ClientError::VersionMismatch { .. }) => {
+            say!("⚠️  {}", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    say!("🏷️  Version: {} (control protocol {}, {})", info.version, info.wire_version, info.target);
+    if !info.features.is_empty() {
+        say!("   Features: {}", info.features.join(", "));
+    }
+    if let Some(update) = &info.update {
+        say!("⬆️  Update available: {}", update.version);
+        if let Some(url) = &update.url {
+            say!("   {}", url);
+        }
+    }
+    Ok(Some(info))
+}
+
 /// Show lock file information
-async fn show_lock_status(fastn_home: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+async fn show_lock_status(fastn_home: &Path) -> Result<(), Box<dyn std::error::Error>> {
     let lock_path = fastn_home.join("lock.file");
     
     if lock_path.exists() {
@@ -46,42 +114,42 @@ async fn show_lock_status(fastn_home: &PathBuf) -> Result<(), Box<dyn std::error
         let modified = metadata.modified()?;
         let duration = std::time::SystemTime::now().duration_since(modified)?;
         
-        println!("🔒 Lock file: {} (created {} seconds ago)", 
+        say!("🔒 Lock file: {} (created {} seconds ago)", 
                 lock_path.display(), 
                 duration.as_secs());
     } else {
-        println!("🔓 No lock file found");
+        say!("🔓 No lock file found");
     }
     
     Ok(())
 }
 
 /// Show all identities with their online/offline status and protocol configurations
-async fn show_identities_status(fastn_home: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+async fn show_identities_status(fastn_home: &Path) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
     let identity_configs = fastn_p2p::server::load_all_identities(fastn_home).await?;
     
     if identity_configs.is_empty() {
-        println!("📭 No identities configured");
-        println!("   Create an identity with: fastn-p2p create-identity <alias>");
-        return Ok(());
+        say!("📭 No identities configured");
+        say!("   Create an identity with: fastn-p2p create-identity <alias>");
+        return Ok(Vec::new());
     }
     
-    println!("🔑 Identities: {}", identity_configs.len());
+    say!("🔑 Identities: {}", identity_configs.len());
     
     for identity in &identity_configs {
         let status_icon = if identity.online { "🟢" } else { "🔴" };
         let status_text = if identity.online { "ONLINE" } else { "OFFLINE" };
         
-        println!();
-        println!("{} {} ({}) - {}", status_icon, identity.alias, status_text, identity.secret_key.public_key().id52());
+        say!();
+        say!("{} {} ({}) - {}", status_icon, identity.alias, status_text, identity.secret_key.public_key().id52());
         
         if identity.protocols.is_empty() {
-            println!("     📭 No protocols configured");
+            say!("     📭 No protocols configured");
         } else {
-            println!("     📡 Protocols: {}", identity.protocols.len());
+            say!("     📡 Protocols: {}", identity.protocols.len());
             for protocol in &identity.protocols {
                 let protocol_status = if identity.online { "🟢" } else { "⏸️" };
-                println!("       {} {} as '{}' (config: {})", 
+                say!("       {} {} as '{}' (config: {})", 
                         protocol_status,
                         protocol.protocol, 
                         protocol.bind_alias,
@@ -90,11 +158,123 @@ async fn show_identities_status(fastn_home: &PathBuf) -> Result<(), Box<dyn std:
         }
     }
     
-    println!();
-    println!("💡 Commands:");
-    println!("   fastn-p2p daemon                     # Start daemon");
-    println!("   fastn-p2p identity-online <name>     # Enable identity");
-    println!("   fastn-p2p identity-offline <name>    # Disable identity");
+    say!();
+    say!("💡 Commands:");
+    say!("   fastn-p2p daemon                     # Start daemon");
+    say!("   fastn-p2p identity-online <name>     # Enable identity");
+    say!("   fastn-p2p identity-offline <name>    # Disable identity");
     
-    Ok(())
-}
\ No newline at end of file
+    Ok(identity_configs.iter().map(|identity| serde_json::json!({
+        "alias": identity.alias,
+        "peer": identity.secret_key.public_key().id52(),
+        "online": identity.online,
+        "bindings": identity.protocols.iter().map(|protocol| serde_json::json!({
+            "protocol": protocol.protocol,
+            "bind_alias": protocol.bind_alias,
+            "config_path": protocol.config_path,
+        })).collect::<Vec<_>>(),
+    })).collect())
+}
+
+/// Show the circuit breaker of every peer the running daemon called
+async fn show_peer_status(
+    fastn_home: &Path,
+) -> Result<Option<Vec<fastn_p2p_client::admin::PeerCircuit>>, Box<dyn std::error::Error>> {
+    let peers = match fastn_p2p_client::admin::peer_status(fastn_home).await {
+        Ok(peers) => peers,
+        Err(fastn_p2p_client::ClientError::DaemonNotRunning { .. }) => {
+            say!("🔌 Peers: daemon not running");
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    
+    if peers.is_empty() {
+        say!("🔌 Peers: none called since the daemon started");
+        return Ok(Some(peers));
+    }
+    
+    say!("🔌 Peers: {}", peers.len());
+    for peer in &peers {
+        let (icon, state) = match peer.state {
+            fastn_p2p_client::admin::CircuitState::Closed => ("🟢", "closed"),
+            fastn_p2p_client::admin::CircuitState::HalfOpen => ("🟡", "half-open"),
+            fastn_p2p_client::admin::CircuitState::Open => ("🔴", "open"),
+        };
+        let probe = match peer.retry_in_secs {
+            Some(secs) => format!(", next probe in {}s", secs),
+            None => String::new(),
+        };
+        say!("   {} {} {} ({}/{} calls failed){}", icon, peer.peer, state, peer.failures, peer.calls, probe);
+    }
+    
+    Ok(Some(peers))
+}
+
+/// Show latency percentiles and errors of every request handler the running daemon ran
+async fn show_protocol_metrics(
+    fastn_home: &Path,
+) -> Result<Option<Vec<fastn_p2p_client::admin::HandlerStats>>, Box<dyn std::error::Error>> {
+    let handlers = match fastn_p2p_client::admin::protocol_metrics(fastn_home).await {
+        Ok(handlers) => handlers,
+        Err(fastn_p2p_client::ClientError::DaemonNotRunning { .. }) => {
+            say!("⏱️  Protocols: daemon not running");
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    
+    if handlers.is_empty() {
+        say!("⏱️  Protocols: no requests handled since the daemon started");
+        return Ok(Some(handlers));
+    }
+    
+    say!("⏱️  Protocols: {}", handlers.len());
+    for handler in &handlers {
+        let name = match &handler.command {
+            Some(command) => format!("{} {}", handler.protocol, command),
+            None => handler.protocol.clone(),
+        };
+        say!("   {} {} calls, {} errors, p50 {:.1}ms p95 {:.1}ms p99 {:.1}ms",
+                name, handler.calls, handler.errors, handler.p50_ms, handler.p95_ms, handler.p99_ms);
+    }
+    
+    Ok(Some(handlers))
+}
+
+/// Show the daemon's endpoint limits and the endpoint of every online identity
+async fn show_endpoint_status(
+    fastn_home: &Path,
+) -> Result<Option<fastn_p2p_client::admin::EndpointsStatus>, Box<dyn std::error::Error>> {
+    let status = match fastn_p2p_client::admin::endpoint_status(fastn_home).await {
+        Ok(status) => status,
+        Err(fastn_p2p_client::ClientError::DaemonNotRunning { .. }) => {
+            say!("🔌 Endpoints: daemon not running");
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    
+    let mut mode = Vec::new();
+    if status.lazy {
+        mode.push("lazy".to_string());
+    }
+    if status.idle_stop_secs > 0 {
+        mode.push(format!("stop after {}s idle", status.idle_stop_secs));
+    }
+    let mode = if mode.is_empty() { String::new() } else { format!(" ({})", mode.join(", ")) };
+    say!("🔌 Endpoints: {} of {} bound{}", status.bound, status.max_endpoints, mode);
+    for identity in &status.identities {
+        if identity.stopped {
+            say!("   {}: stopped, unused for {}s", identity.identity, identity.idle_secs);
+            continue;
+        }
+        let ports: Vec<String> = identity.ports.iter().map(|port| port.to_string()).collect();
+        say!("   {}: ports {}, relay {}",
+                identity.identity,
+                ports.join(","),
+                identity.relay.as_deref().unwrap_or("none"));
+    }
+    
+    Ok(Some(status))
+}
@@ -0,0 +1,89 @@
+//! Sync command: mirror a peer's sync.fastn.com directory into a local directory
+
+use std::path::PathBuf;
+
+/// Bring `local_dir` up to date with the peer's `remote_binding` sync directory
+///
+/// Only chunks that differ are transferred; rerunning after an interruption
+/// picks up where the previous run stopped.
+pub async fn sync(
+    fastn_home: PathBuf,
+    peer_id52: String,
+    remote_binding: String,
+    local_dir: PathBuf,
+    as_identity: Option<String>,
+    progress: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let identity = crate::cli::identity::resolve_identity(&fastn_home, as_identity).await?;
+    let identities_dir = fastn_home.join("identities");
+    let identity_config = fastn_p2p::server::IdentityConfig::load_from_dir(&identities_dir, &identity).await
+        .map_err(|e| format!("Identity '{}' not found: {}", identity, e))?;
+    let to_peer: fastn_id52::PublicKey = peer_id52.parse()
+        .map_err(|e| crate::cli::output::UsageError::boxed(format!("Invalid peer ID '{}': {}", peer_id52, e)))?;
+
+    say!("🔄 Syncing {} {} from {} into {}", fastn_p2p::server::sync::SYNC_PROTOCOL, remote_binding, to_peer.id52(), local_dir.display());
+
+    let local_manifest = fastn_p2p::server::sync::build_manifest(&local_dir).await?;
+    say!("📁 Local copy: {} files", local_manifest.files.len());
+
+    let client = fastn_p2p::client::Client::new(identity_config.secret_key);
+    let diff = fastn_p2p::server::CommandProtocol::new(fastn_p2p::server::sync::SYNC_PROTOCOL, &remote_binding, "diff");
+    let plan: fastn_p2p::server::sync::SyncPlan = client
+        .call::<_, _, _, serde_json::Value>(to_peer, diff, &local_manifest)
+        .await?
+        .map_err(|e| format!("Peer rejected sync: {}", e))?;
+
+    if plan.is_empty() {
+        say!("✅ Already up to date");
+        return Ok(());
+    }
+
+    let total = plan.transfer_size();
+    say!("📦 {} files changed, {} to delete, {} bytes to transfer",
+            plan.files.len(), plan.deleted.len(), total);
+
+    let requests = plan.chunk_requests();
+    if !requests.is_empty() {
+        let chunks = fastn_p2p::server::CommandProtocol::new(fastn_p2p::server::sync::SYNC_PROTOCOL, &remote_binding, "chunks");
+        let mut session = client.connect(to_peer, chunks, &requests).await?;
+
+        let expected: usize = requests.iter().map(|r| r.chunks.len()).sum();
+        let mut received = 0u64;
+        let observer = fastn_p2p::progress::Observer::new(|progress| {
+            eprint!("\r   📥 {}", progress.bar(30));
+            if progress.done {
+                eprintln!();
+            }
+        })
+        .with_total(total);
+        for _ in 0..expected {
+            let header: fastn_p2p::server::sync::ChunkHeader = session.recv.next_json().await
+                .map_err(|e| format!("Failed to read chunk header: {}", e))?;
+            if plan.planned_chunk_len(&header.path, header.index) != Some(header.len) {
+                return Err(format!("Peer sent chunk {} of '{}' that was not requested", header.index, header.path).into());
+            }
+
+            let mut bytes = vec![0u8; header.len as usize];
+            tokio::io::AsyncReadExt::read_exact(&mut session.recv, &mut bytes).await?;
+            fastn_p2p::server::sync::write_chunk(&local_dir, &header, &bytes).await?;
+
+            received += header.len;
+            if progress {
+                observer.add(header.len);
+            } else {
+                say!("   📥 {} chunk {} ({}/{} bytes)", header.path, header.index, received, total);
+            }
+        }
+        if progress {
+            observer.finish();
+        }
+    }
+
+    fastn_p2p::server::sync::finish_plan(&local_dir, &plan).await?;
+
+    for deleted in &plan.deleted {
+        say!("   🗑️  {}", deleted);
+    }
+    say!("✅ Sync complete");
+    Ok(())
+}
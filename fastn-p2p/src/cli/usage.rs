@@ -0,0 +1,82 @@
+//! Usage command: bandwidth and requests per identity, protocol and peer
+//!
+//! Reads the counts the daemon keeps in `state.db`, see
+//! [`fastn_p2p::server::usage`]. The daemon writes them once a minute, so
+//! the last minute may be missing.
+
+use std::path::PathBuf;
+
+/// Report `month` (`YYYY-MM`, default this month) for `identity` or every identity
+pub async fn show(
+    fastn_home: PathBuf,
+    identity: Option<String>,
+    month: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let month = match month {
+        Some(month) => {
+            chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").map_err(|_| {
+                crate::cli::output::UsageError::boxed(format!("Invalid --month '{}': use YYYY-MM, e.g. 2024-06", month))
+            })?;
+            month
+        }
+        None => chrono::Utc::now().format("%Y-%m").to_string(),
+    };
+
+    let store = fastn_p2p::server::state::StateStore::open(&fastn_home).await?;
+    let rows = store.usage(identity.as_deref(), &month).await?;
+    let days = store.daily_usage(identity.as_deref(), &month).await?;
+
+    say!("📊 Usage in {} of {}", month, identity.as_deref().unwrap_or("all identities"));
+    say!();
+    if rows.is_empty() {
+        say!("   Nothing recorded");
+    }
+
+    let mut total = fastn_p2p::server::usage::Usage::default();
+    let mut current_identity = None;
+    for row in &rows {
+        if current_identity != Some(&row.identity) {
+            say!("🆔 {}", row.identity);
+            current_identity = Some(&row.identity);
+        }
+        say!(
+            "   {} {}: {} requests, {} in, {} out",
+            row.protocol,
+            row.peer,
+            row.usage.requests,
+            fastn_p2p::progress::format_bytes(row.usage.bytes_in),
+            fastn_p2p::progress::format_bytes(row.usage.bytes_out),
+        );
+        total.add(&row.usage);
+    }
+
+    if !days.is_empty() {
+        say!();
+        say!("📅 By day");
+        for day in &days {
+            say!(
+                "   {}: {} requests, {} in, {} out",
+                day.day,
+                day.usage.requests,
+                fastn_p2p::progress::format_bytes(day.usage.bytes_in),
+                fastn_p2p::progress::format_bytes(day.usage.bytes_out),
+            );
+        }
+    }
+
+    say!();
+    say!(
+        "✅ {} requests, {} in, {} out",
+        total.requests,
+        fastn_p2p::progress::format_bytes(total.bytes_in),
+        fastn_p2p::progress::format_bytes(total.bytes_out),
+    );
+    crate::cli::output::result(serde_json::json!({
+        "month": month,
+        "identity": identity,
+        "total": total,
+        "usage": rows,
+        "days": days,
+    }));
+    Ok(())
+}
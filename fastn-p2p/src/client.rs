@@ -0,0 +1,1609 @@
+//! Client-side P2P calls with connection reuse
+//!
+//! [`Client`] caches the endpoint for one identity and keeps a handshaken
+//! connection per peer, so repeated calls to the same peer only cost one
+//! bi-stream round trip. The free [`call`] function uses a process-global
+//! client per sender key and keeps the original one-shot semantics.
+
+pub use crate::coordination::CallError;
+pub use crate::fan_out::{FanOut, FanOutReport, PeerReply};
+pub use crate::handshake::HelloMetadata;
+pub use crate::ping::{PathType, PingStats, Pong};
+pub use crate::time_sync::TimeOffset;
+pub use fastn_p2p_client::client::PathPreference;
+
+/// Settings for one [`Client::call_with_options`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallOptions {
+    /// Network paths the request may take; a call that can't get one fails
+    /// with [`CallError::PathNotAllowed`] before the request is sent
+    pub path_preference: PathPreference,
+}
+
+impl CallOptions {
+    pub fn with_path_preference(mut self, path_preference: PathPreference) -> Self {
+        self.path_preference = path_preference;
+        self
+    }
+}
+
+/// What [`Client::call_with_options`] got back, and how
+#[derive(Debug)]
+pub struct CallReply<OUTPUT, ERROR> {
+    pub result: Result<OUTPUT, ERROR>,
+    /// Path the connection to the peer took when the response came in
+    pub path: PathType,
+    /// How long the server lets clients cache the answer, see
+    /// [`crate::server::ServerBuilder::with_protocol_max_age`]
+    pub max_age: Option<std::time::Duration>,
+}
+
+/// What came with a response besides its result
+#[derive(Debug, Clone, Copy)]
+struct Received {
+    /// The server sent a tagged envelope, see [`crate::wire`]
+    tagged: bool,
+    /// The answer's [`crate::wire::MAX_AGE`]
+    max_age: Option<std::time::Duration>,
+}
+
+/// Process-global clients, one per sender identity
+static GLOBAL_CLIENTS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<fastn_id52::PublicKey, Client>>,
+> = std::sync::LazyLock::new(|| std::sync::Mutex::new(std::collections::HashMap::new()));
+
+/// Every process-global client made so far, see [`Client::global`]
+pub(crate) fn global_clients() -> Vec<Client> {
+    GLOBAL_CLIENTS
+        .lock()
+        .expect("Failed to acquire lock on GLOBAL_CLIENTS")
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// Reusable P2P client bound to one identity
+///
+/// Cloning is cheap; clones share the same endpoint and connection cache.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[derive(serde::Serialize)]
+/// # enum EchoProtocol { Echo }
+/// # #[derive(serde::Serialize)]
+/// # struct EchoRequest { message: String }
+/// # type EchoResult = Result<String, String>;
+/// # async fn run(private_key: fastn_p2p::SecretKey, target: fastn_p2p::PublicKey) -> Result<(), fastn_p2p::client::CallError> {
+/// let client = fastn_p2p::client::Client::new(private_key);
+/// for message in ["one", "two", "three"] {
+///     let result: EchoResult = client
+///         .call(target, EchoProtocol::Echo, EchoRequest { message: message.into() })
+///         .await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Client {
+    inner: std::sync::Arc<ClientInner>,
+    /// Attach a detached signature to every request, see [`crate::signing`]
+    sign_requests: bool,
+    /// Run around every `call()` and `connect()`, see [`crate::interceptor`]
+    interceptors: std::sync::Arc<Vec<crate::interceptor::Interceptor>>,
+    /// Headers added to every request before the interceptors run
+    metadata: std::collections::BTreeMap<String, String>,
+    /// Largest chunked response accepted, see [`crate::wire::complete_response`]
+    max_response_size: usize,
+    /// How long `call()`s wait for their response, see [`Client::with_timeout`]
+    timeout: Option<std::time::Duration>,
+    /// Sent in ClientHello on new connections, see [`Client::with_hello_metadata`]
+    hello: crate::handshake::HelloMetadata,
+    /// Call servers of this process in-process, see [`Client::with_loopback`]
+    loopback: bool,
+    /// Also those of other processes serving from this FASTN_HOME, see [`Client::with_loopback_in`]
+    loopback_home: Option<std::sync::Arc<std::path::PathBuf>>,
+}
+
+struct ClientInner {
+    secret_key: fastn_id52::SecretKey,
+    /// Bound on first use, and again if it was closed since
+    endpoint: tokio::sync::Mutex<Option<iroh::Endpoint>>,
+    connections: tokio::sync::Mutex<
+        std::collections::HashMap<fastn_id52::PublicKey, crate::coordination::PeerConnection>,
+    >,
+    /// Last resumption token per peer, kept after the connection itself is dropped
+    resumption_tokens: std::sync::Mutex<std::collections::HashMap<fastn_id52::PublicKey, String>>,
+}
+
+impl Client {
+    /// Create a client that sends requests as `secret_key`
+    pub fn new(secret_key: fastn_id52::SecretKey) -> Self {
+        Self {
+            inner: std::sync::Arc::new(ClientInner {
+                secret_key,
+                endpoint: tokio::sync::Mutex::new(None),
+                connections: tokio::sync::Mutex::new(std::collections::HashMap::new()),
+                resumption_tokens: std::sync::Mutex::new(std::collections::HashMap::new()),
+            }),
+            sign_requests: false,
+            interceptors: std::sync::Arc::new(Vec::new()),
+            metadata: std::collections::BTreeMap::new(),
+            max_response_size: crate::wire::DEFAULT_MAX_RESPONSE_SIZE,
+            timeout: None,
+            hello: crate::handshake::HelloMetadata::default(),
+            loopback: false,
+            loopback_home: None,
+        }
+    }
+
+    /// Sign every request and require signed responses
+    ///
+    /// Servers verify the signature and expose it to handlers as
+    /// `request.verified_sender()`; responses that are unsigned or not signed
+    /// by the target fail with [`CallError::Signature`]. Shares the endpoint and
+    /// connections with the client it was made from.
+    pub fn with_signed_requests(mut self) -> Self {
+        self.sign_requests = true;
+        self
+    }
+
+    /// Run `interceptor` around every `call()` and `connect()` made with this handle
+    ///
+    /// Interceptors run in the order they are added, the first one outermost;
+    /// see [`crate::interceptor`]. Shares the endpoint and connections with the
+    /// client it was made from.
+    pub fn with_interceptor<F, Fut>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(crate::interceptor::OutgoingCall, crate::interceptor::CallNext) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = crate::interceptor::InterceptorResult> + Send + 'static,
+    {
+        let mut interceptors = (*self.interceptors).clone();
+        interceptors.push(crate::interceptor::boxed(interceptor));
+        self.interceptors = std::sync::Arc::new(interceptors);
+        self
+    }
+
+    /// Send `key: value` as request metadata with every `call()` and `connect()`
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Fail calls whose response is larger than `bytes` with [`CallError::TooLarge`]
+    ///
+    /// Applies to responses the server sends in chunks; the default is
+    /// [`crate::wire::DEFAULT_MAX_RESPONSE_SIZE`]. Shares the endpoint and
+    /// connections with the client it was made from.
+    pub fn with_max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = bytes;
+        self
+    }
+
+    /// Give up on `call()`s that take longer than `timeout` with [`CallError::Timeout`]
+    ///
+    /// The deadline is sent along with the request, so the server stops the
+    /// handler and skips the response once nobody waits for it; handlers see
+    /// it as `ctx.deadline()`. Interceptors can move it, see
+    /// [`crate::interceptor::OutgoingCall::deadline`]. Shares the endpoint and
+    /// connections with the client it was made from.
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Tell servers about this client in the handshake, for their auth hooks
+    ///
+    /// Only connections made afterwards carry it; connections are shared with
+    /// the client this was made from, so set it before the first call. See
+    /// [`crate::handshake::HelloMetadata`].
+    pub fn with_hello_metadata(mut self, hello: HelloMetadata) -> Self {
+        self.hello = hello;
+        self
+    }
+
+    /// Hand `call()`s to servers of this process straight to their handlers
+    ///
+    /// Auth hooks and middleware still run, see [`crate::server::loopback`]
+    /// for what stays on the network. Shares the endpoint and connections
+    /// with the client it was made from.
+    pub fn with_loopback(mut self) -> Self {
+        self.loopback = true;
+        self
+    }
+
+    /// Like [`Client::with_loopback`], also reaching identities `serve_all` serves from `fastn_home`
+    ///
+    /// Calls to them go over the serving process's socket instead of iroh,
+    /// and pass the same checks, see [`crate::server::loopback`]. The daemon
+    /// sends this way.
+    pub fn with_loopback_in(mut self, fastn_home: impl Into<std::path::PathBuf>) -> Self {
+        self.loopback = true;
+        self.loopback_home = Some(std::sync::Arc::new(fastn_home.into()));
+        self
+    }
+
+    /// Get the process-global client for `secret_key`, creating it on first use
+    pub fn global(secret_key: fastn_id52::SecretKey) -> Self {
+        let mut clients = GLOBAL_CLIENTS
+            .lock()
+            .expect("Failed to acquire lock on GLOBAL_CLIENTS");
+        clients
+            .entry(secret_key.public_key())
+            .or_insert_with(|| Client::new(secret_key))
+            .clone()
+    }
+
+    /// Public key of the identity this client sends as
+    pub fn public_key(&self) -> fastn_id52::PublicKey {
+        self.inner.secret_key.public_key()
+    }
+
+    /// Make a request/response call, reusing an existing connection to `target` if possible
+    pub async fn call<P, INPUT, OUTPUT, ERROR>(
+        &self,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        input: INPUT,
+    ) -> Result<Result<OUTPUT, ERROR>, CallError>
+    where
+        P: serde::Serialize,
+        INPUT: serde::Serialize,
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        let call = self.outgoing_call(target, protocol, input, false)?;
+        self.typed_call(call).await
+    }
+
+    /// Like [`Client::call`] with per-call `options`, also returning the path the call took
+    ///
+    /// ```rust,no_run
+    /// # use fastn_p2p::client::{CallOptions, CallReply, Client, PathPreference};
+    /// # #[derive(serde::Serialize)]
+    /// # enum Vault { Get }
+    /// # async fn run(client: Client, target: fastn_p2p::PublicKey, request: String) -> Result<(), fastn_p2p::client::CallError> {
+    /// let options = CallOptions::default().with_path_preference(PathPreference::DirectOnly);
+    /// let reply: CallReply<String, String> = client.call_with_options(target, Vault::Get, request, options).await?;
+    /// println!("answered over a {} path", reply.path);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn call_with_options<P, INPUT, OUTPUT, ERROR>(
+        &self,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        input: INPUT,
+        options: CallOptions,
+    ) -> Result<CallReply<OUTPUT, ERROR>, CallError>
+    where
+        P: serde::Serialize,
+        INPUT: serde::Serialize,
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        let mut call = self.outgoing_call(target, protocol, input, false)?;
+        call.path_preference = options.path_preference;
+        let local = self.local_server(&call).is_some()
+            || self.loopback_home(&call).is_some_and(|fastn_home| crate::server::loopback::host_serves(fastn_home, &target));
+        let (result, max_age) = self.typed_reply(call).await?;
+        let path = match local {
+            true => PathType::Local,
+            false => self.path_to(&target).await,
+        };
+        Ok(CallReply { result, path, max_age })
+    }
+
+    /// Like [`Client::call`], sending `attachments` alongside the request
+    ///
+    /// The handler reads them from its [`crate::server::RequestContext`], see
+    /// [`crate::attachment`].
+    pub async fn call_with_attachments<P, INPUT, OUTPUT, ERROR>(
+        &self,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        input: INPUT,
+        attachments: Vec<crate::attachment::Attachment>,
+    ) -> Result<Result<OUTPUT, ERROR>, CallError>
+    where
+        P: serde::Serialize,
+        INPUT: serde::Serialize,
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        let mut call = self.outgoing_call(target, protocol, input, false)?;
+        call.attachments = attachments;
+        self.typed_call(call).await
+    }
+
+    async fn typed_call<OUTPUT, ERROR>(
+        &self,
+        call: crate::interceptor::OutgoingCall,
+    ) -> Result<Result<OUTPUT, ERROR>, CallError>
+    where
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        self.typed_reply(call).await.map(|(result, _)| result)
+    }
+
+    /// Like [`Client::typed_call`], also returning the answer's max age
+    async fn typed_reply<OUTPUT, ERROR>(
+        &self,
+        call: crate::interceptor::OutgoingCall,
+    ) -> Result<(Result<OUTPUT, ERROR>, Option<std::time::Duration>), CallError>
+    where
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        match self.intercepted_received(call).await? {
+            (crate::interceptor::Reply::Response(result), received) => {
+                let result = typed_result(result, received.tagged)?;
+                let max_age = received.max_age.filter(|_| result.is_ok());
+                Ok((result, max_age))
+            }
+            (crate::interceptor::Reply::Session(_), _) => Err(CallError::Protocol {
+                message: "Interceptor answered a call with a session".to_string(),
+            }),
+        }
+    }
+
+    /// Make several request/response calls to `target` in one stream round trip
+    ///
+    /// The server runs the calls concurrently and the results come back in
+    /// the order of `calls`, see [`crate::batch`]. Interceptors see the batch
+    /// as one call to [`crate::batch::BATCH_PROTOCOL`]. A server that predates
+    /// batches gets the calls one by one instead.
+    pub async fn call_batch<P, INPUT, OUTPUT, ERROR>(
+        &self,
+        target: fastn_id52::PublicKey,
+        calls: Vec<(P, INPUT)>,
+    ) -> Result<Vec<Result<OUTPUT, ERROR>>, CallError>
+    where
+        P: serde::Serialize,
+        INPUT: serde::Serialize,
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        let calls = calls
+            .into_iter()
+            .map(|(protocol, input)| {
+                Ok(crate::batch::BatchCall {
+                    protocol: crate::protocol_key::of(&protocol).map_err(|source| CallError::Serialization { source })?,
+                    data: serde_json::to_value(&input).map_err(|source| CallError::Serialization { source })?,
+                })
+            })
+            .collect::<Result<Vec<_>, CallError>>()?;
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let request = crate::batch::BatchRequest { calls };
+        let call = self.outgoing_call(target, crate::batch::protocol_json(), &request, false)?;
+        let result = match self.intercepted(call).await {
+            Ok(crate::interceptor::Reply::Response(result)) => result,
+            Ok(crate::interceptor::Reply::Session(_)) => {
+                return Err(CallError::Protocol {
+                    message: "Interceptor answered a batch with a session".to_string(),
+                });
+            }
+            Err(CallError::ProtocolNotAccepted { protocol }) if protocol == crate::batch::protocol_json() => {
+                tracing::debug!("{} does not accept batches, making {} calls", target.id52(), request.calls.len());
+                let calls = request
+                    .calls
+                    .into_iter()
+                    .map(|call| self.call(target, call.protocol, call.data));
+                return futures_util::future::join_all(calls).await.into_iter().collect();
+            }
+            Err(e) => return Err(e),
+        };
+
+        let response: crate::batch::BatchResponse = match result {
+            Ok(response) => serde_json::from_value(response).map_err(|source| CallError::Deserialization { source })?,
+            Err(error) => {
+                return Err(CallError::Protocol { message: format!("Server refused the batch: {}", error) });
+            }
+        };
+        if response.results.len() != request.calls.len() {
+            return Err(CallError::Protocol {
+                message: format!(
+                    "Server answered {} of {} batched calls",
+                    response.results.len(),
+                    request.calls.len()
+                ),
+            });
+        }
+        response
+            .results
+            .into_iter()
+            .map(|envelope| typed_result(envelope.into(), true))
+            .collect()
+    }
+
+    /// Make the same request/response call to every peer in `peers`
+    ///
+    /// At most `fan_out`'s concurrency of calls are in flight at once, each
+    /// with its timeout. Replies come in the order they arrive, one per peer;
+    /// a peer that can't be reached shows up as a failed reply, the others
+    /// carry on. Dropping the stream cancels the calls still running. See
+    /// [`crate::fan_out`].
+    pub fn call_many<P, INPUT, OUTPUT, ERROR>(
+        &self,
+        peers: impl IntoIterator<Item = fastn_id52::PublicKey>,
+        protocol: P,
+        input: INPUT,
+        fan_out: crate::fan_out::FanOut,
+    ) -> Result<crate::fan_out::Replies<OUTPUT, ERROR>, CallError>
+    where
+        P: serde::Serialize,
+        INPUT: serde::Serialize,
+        OUTPUT: for<'de> serde::Deserialize<'de> + Send + 'static,
+        ERROR: for<'de> serde::Deserialize<'de> + Send + 'static,
+    {
+        use futures_util::StreamExt;
+
+        let protocol = crate::protocol_key::of(&protocol).map_err(|source| CallError::Serialization { source })?;
+        let input = serde_json::to_value(&input).map_err(|source| CallError::Serialization { source })?;
+        let client = self.clone();
+        let peers: Vec<_> = peers.into_iter().collect();
+
+        let replies = futures_util::stream::iter(peers)
+            .map(move |peer| {
+                let (client, protocol, input) = (client.clone(), protocol.clone(), input.clone());
+                async move {
+                    let call = client.call(peer, protocol, input);
+                    let result = match fan_out.timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, call).await.unwrap_or(Err(CallError::Timeout)),
+                        None => call.await,
+                    };
+                    crate::fan_out::PeerReply { peer, result }
+                }
+            })
+            .buffer_unordered(fan_out.concurrency);
+        Ok(Box::pin(replies))
+    }
+
+    /// Time one ping round trip to `target`, see [`crate::ping`]
+    ///
+    /// Goes over the cached connection to `target`, connecting first if
+    /// there is none, so only the first ping includes connection setup.
+    pub async fn ping(&self, target: fastn_id52::PublicKey) -> Result<crate::ping::Pong, CallError> {
+        let peer = self.any_connection(&target).await?;
+        let started = std::time::Instant::now();
+        if let Err(e) = fastn_net::ping(&peer.conn).await {
+            self.forget(&target).await;
+            return Err(CallError::from_net(e));
+        }
+        let rtt = started.elapsed();
+
+        let endpoint = self.endpoint().await?;
+        Ok(crate::ping::Pong { rtt, path: crate::ping::PathType::of(&endpoint, &peer.conn) })
+    }
+
+    /// How far `target`'s clock is from ours, see [`crate::time_sync`]
+    ///
+    /// Takes [`crate::time_sync::DEFAULT_SAMPLES`] samples over the cached
+    /// connection to `target`, connecting first if there is none.
+    pub async fn time_offset(&self, target: fastn_id52::PublicKey) -> Result<crate::time_sync::TimeOffset, CallError> {
+        let peer = self.any_connection(&target).await?;
+        let mut samples = Vec::with_capacity(crate::time_sync::DEFAULT_SAMPLES);
+        for _ in 0..crate::time_sync::DEFAULT_SAMPLES {
+            match crate::time_sync::sample(&peer.conn).await {
+                Ok(sample) => samples.push(sample),
+                Err(e) => {
+                    self.forget(&target).await;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(crate::time_sync::TimeOffset::from_samples(&samples).expect("At least one sample was taken"))
+    }
+
+    /// Open a streaming session with `target`, reusing an existing connection if possible
+    ///
+    /// `data` is delivered to the server's stream handler as its initial data;
+    /// everything after that is raw bytes in both directions.
+    pub async fn connect<P, DATA>(
+        &self,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        data: DATA,
+    ) -> Result<Session, CallError>
+    where
+        P: serde::Serialize,
+        DATA: serde::Serialize,
+    {
+        let call = self.outgoing_call(target, protocol, data, true)?;
+        match self.intercepted(call).await? {
+            crate::interceptor::Reply::Session(session) => Ok(session),
+            crate::interceptor::Reply::Response(_) => Err(CallError::Protocol {
+                message: "Interceptor answered a connect with a response".to_string(),
+            }),
+        }
+    }
+
+    /// Download a resumable stream of `target` into `writer`, resuming after dropped connections
+    ///
+    /// For protocols served with
+    /// [`crate::server::ServerBuilder::handle_resumable_streams`]. When the
+    /// connection drops, this reconnects and asks for the bytes after those
+    /// already written, up to [`MAX_RESUME_ATTEMPTS`] times without progress.
+    /// `progress` is called with the bytes written so far after every chunk.
+    /// Returns the total bytes written.
+    pub async fn download<P, DATA, W>(
+        &self,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        data: DATA,
+        mut writer: W,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64, CallError>
+    where
+        P: serde::Serialize,
+        DATA: serde::Serialize,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let protocol = crate::protocol_key::of(&protocol)
+            .map_err(|source| CallError::Serialization { source })?;
+        let data = serde_json::to_value(&data)
+            .map_err(|source| CallError::Serialization { source })?;
+
+        let mut resume: Option<crate::server::resumable::Resume> = None;
+        let mut attempts = 0;
+        loop {
+            let before = resume.as_ref().map_or(0, |resume| resume.offset);
+            let error = match self
+                .download_once(target, &protocol, &data, &mut resume, &mut writer, &mut progress)
+                .await
+                .map_err(|source| CallError::Io { source })?
+            {
+                Ok(written) => return Ok(written),
+                Err(error) => error,
+            };
+
+            let written = resume.as_ref().map_or(0, |resume| resume.offset);
+            if written > before {
+                attempts = 0;
+            }
+            attempts += 1;
+            if !error.is_peer_failure() || resume.is_none() || attempts > MAX_RESUME_ATTEMPTS {
+                return Err(error);
+            }
+            tracing::debug!("Download from {} interrupted at byte {}, resuming: {}", target.id52(), written, error);
+            self.forget(&target).await;
+            tokio::time::sleep(RESUME_BACKOFF * attempts).await;
+        }
+    }
+
+    /// One connection's worth of [`Self::download`], picking up at `resume`
+    ///
+    /// `resume` is kept at the bytes written so far. The outer error is
+    /// `writer` failing, which resuming can't fix; the inner one is the
+    /// stream failing.
+    async fn download_once<W>(
+        &self,
+        target: fastn_id52::PublicKey,
+        protocol: &serde_json::Value,
+        data: &serde_json::Value,
+        resume: &mut Option<crate::server::resumable::Resume>,
+        writer: &mut W,
+        progress: &mut impl FnMut(u64),
+    ) -> std::io::Result<Result<u64, CallError>>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let request = crate::server::resumable::ResumableRequest { data, resume: resume.clone() };
+        let mut session = match self.connect(target, protocol, &request).await {
+            Ok(session) => session,
+            Err(e) => return Ok(Err(e)),
+        };
+        let header: crate::server::resumable::ResumeHeader = match session.recv.next_json().await {
+            Ok(header) => header,
+            Err(e) => return Ok(Err(CallError::from_net(e))),
+        };
+        let mut written = resume.as_ref().map_or(0, |resume| resume.offset);
+        if header.offset != written {
+            return Ok(Err(CallError::Protocol {
+                message: format!("Server resumed the download at byte {} instead of {}", header.offset, written),
+            }));
+        }
+        let resume = resume.insert(crate::server::resumable::Resume { token: header.token, offset: written });
+
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = match session.recv.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => return Ok(Err(CallError::from_net(e))),
+            };
+            writer.write_all(&buffer[..read]).await?;
+            written += read as u64;
+            resume.offset = written;
+            progress(written);
+        }
+        writer.flush().await?;
+        Ok(Ok(written))
+    }
+
+    fn outgoing_call<P, DATA>(
+        &self,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        data: DATA,
+        is_stream: bool,
+    ) -> Result<crate::interceptor::OutgoingCall, CallError>
+    where
+        P: serde::Serialize,
+        DATA: serde::Serialize,
+    {
+        let protocol_json = crate::protocol_key::of(&protocol)
+            .map_err(|source| CallError::Serialization { source })?;
+        let data = serde_json::to_value(&data)
+            .map_err(|source| CallError::Serialization { source })?;
+        let mut call = crate::interceptor::OutgoingCall::new(
+            target,
+            protocol_json,
+            data,
+            self.metadata.clone(),
+            is_stream,
+        );
+        if !is_stream {
+            call.deadline = self.timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        }
+        Ok(call)
+    }
+
+    /// Run `call` through the interceptors and then over the network
+    async fn intercepted(&self, call: crate::interceptor::OutgoingCall) -> crate::interceptor::InterceptorResult {
+        self.intercepted_received(call).await.map(|(reply, _)| reply)
+    }
+
+    /// Like [`Client::intercepted`], also returning what came with the last response sent
+    ///
+    /// Replies made up by an interceptor count as tagged, without a max age.
+    async fn intercepted_received(
+        &self,
+        call: crate::interceptor::OutgoingCall,
+    ) -> Result<(crate::interceptor::Reply, Received), CallError> {
+        let received = std::sync::Arc::new(std::sync::Mutex::new(Received { tagged: true, max_age: None }));
+        let client = self.clone();
+        let transport_received = received.clone();
+        let transport: crate::interceptor::Transport = std::sync::Arc::new(move |call| {
+            let client = client.clone();
+            let transport_received = transport_received.clone();
+            Box::pin(async move {
+                if call.is_stream() {
+                    return client.send_connect(call).await.map(crate::interceptor::Reply::Session);
+                }
+                let (result, received) = client.send_call(call).await?;
+                *transport_received.lock().expect("Failed to acquire lock on received") = received;
+                Ok(crate::interceptor::Reply::Response(result))
+            })
+        });
+        let reply = crate::interceptor::CallNext::new(self.interceptors.clone(), transport)
+            .run(call)
+            .await?;
+        let received = *received.lock().expect("Failed to acquire lock on received");
+        Ok((reply, received))
+    }
+
+    /// Send `call`, returning its result and what came with it
+    async fn send_call(
+        &self,
+        call: crate::interceptor::OutgoingCall,
+    ) -> Result<(Result<serde_json::Value, serde_json::Value>, Received), CallError> {
+        let target = *call.target();
+        let result = match call.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, self.send_call_inner(call))
+                .await
+                .unwrap_or(Err(CallError::Timeout)),
+            None => self.send_call_inner(call).await,
+        };
+        if let Err(ref e) = result {
+            tracing::debug!("Call to {} failed, dropping cached connection: {e}", target.id52());
+            self.forget(&target).await;
+        }
+        result
+    }
+
+    async fn send_call_inner(
+        &self,
+        call: crate::interceptor::OutgoingCall,
+    ) -> Result<(Result<serde_json::Value, serde_json::Value>, Received), CallError> {
+        let target = *call.target();
+        if let Some(server) = self.local_server(&call) {
+            tracing::debug!("Calling {} in-process", target.id52());
+            let max_age = server.max_age(call.protocol());
+            let result = server.call(self.public_key(), &self.hello, call).await?;
+            let max_age = max_age.filter(|_| result.is_ok());
+            return Ok((result, Received { tagged: true, max_age }));
+        }
+        if let Some(fastn_home) = self.loopback_home(&call)
+            && let Some(result) = crate::server::loopback::call_host(fastn_home, self.public_key(), &self.hello, &call).await
+        {
+            tracing::debug!("Called {} through its process on this host", target.id52());
+            return result.map(|(result, max_age)| (result, Received { tagged: true, max_age }));
+        }
+        let signature = self.request_signature(&target, call.protocol(), &call.data);
+        let deadline_ms = crate::wire::deadline_to_ms(call.deadline);
+        // Connects without an early request, so nothing is sent before the path is checked
+        self.require_path(&target, call.protocol(), call.path_preference).await?;
+
+        // If we end up connecting, the request rides along with the handshake;
+        // metadata and attachments need the wrapper
+        if call.metadata.is_empty() && call.attachments.is_empty() {
+            let early_request = crate::handshake::EarlyRequest {
+                protocol: call.protocol().clone(),
+                data: call.data.clone(),
+                trace: Some(call.trace().clone()),
+                deadline_ms,
+                signature: signature.clone(),
+            };
+            let (peer, early_response) = self
+                .peer_connection(&target, call.protocol(), Some(early_request))
+                .await?;
+            if let Some(response_json) = early_response {
+                // Signed responses are always tagged
+                let tagged = peer.tagged_responses || signature.is_some();
+                return decode_received(&response_json, &target, tagged, signature.as_ref());
+            }
+        }
+
+        // Protocols the server registered with another codec send their payload after the line
+        let protocol = call.protocol().clone();
+        let trace = call.trace().clone();
+        let (peer, _) = self.peer_connection(&target, &protocol, None).await?;
+        let (data, body) = match peer.codec(&protocol) {
+            crate::codec::Codec::Json => (call.data, None),
+            codec => {
+                let body = codec.encode(&call.data).map_err(|source| CallError::Codec { source })?;
+                (serde_json::Value::Null, Some((codec, body)))
+            }
+        };
+        let wrapper = crate::wire::WrapperRequest {
+            signature: signature.clone(),
+            trace: Some(trace),
+            metadata: call.metadata,
+            deadline_ms: crate::wire::deadline_to_ms(call.deadline),
+            codec: body.as_ref().map(|(codec, _)| *codec),
+            attachments: call.attachments.iter().map(|attachment| attachment.info().clone()).collect(),
+            ..crate::wire::WrapperRequest::new(protocol, data)
+        };
+        let (mut session, tagged) = self.open_session(target, app_header(), wrapper).await?;
+        if let Some((_, body)) = body {
+            crate::wire::write_encoded_body(&mut session.send, &body)
+                .await
+                .map_err(|source| CallError::Io { source })?;
+        }
+        let response = async {
+            let response_json = session.recv.next_string().await.map_err(CallError::from_net)?;
+            crate::wire::complete_response(response_json, &mut session.recv, self.max_response_size).await
+        };
+        let response_json = crate::attachment::upload_while(&peer.conn, &call.attachments, response).await?;
+
+        // Signed responses are always tagged
+        let tagged = tagged || signature.is_some();
+        decode_received(&response_json, &target, tagged, signature.as_ref())
+    }
+
+    async fn send_connect(&self, call: crate::interceptor::OutgoingCall) -> Result<Session, CallError> {
+        let target = *call.target();
+        let protocol = call.protocol().clone();
+        self.require_path(&target, &protocol, call.path_preference).await?;
+        let wrapper = crate::wire::WrapperRequest {
+            signature: self.request_signature(&target, &protocol, &call.data),
+            trace: Some(call.trace().clone()),
+            metadata: call.metadata,
+            ..crate::wire::WrapperRequest::new(protocol, call.data)
+        };
+        self.connect_with_header(target, app_header(), wrapper).await
+    }
+
+    /// Make a request/response call to `target`, relayed through `relay`
+    ///
+    /// `relay` must relay for both this identity and `target`, and `target`
+    /// must trust `relay`; see [`crate::server::relay`].
+    pub async fn call_via<P, INPUT, OUTPUT, ERROR>(
+        &self,
+        relay: fastn_id52::PublicKey,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        input: INPUT,
+    ) -> Result<Result<OUTPUT, ERROR>, CallError>
+    where
+        P: serde::Serialize,
+        INPUT: serde::Serialize,
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        let header = fastn_net::Protocol::Relay {
+            target: target.id52(),
+            from: None,
+        };
+        let (mut session, signature) = self
+            .connect_forwarded(relay, header, target, protocol, input, |message| CallError::Relay { message })
+            .await?;
+        let response_json = session
+            .recv
+            .next_string()
+            .await
+            .map_err(CallError::from_net)?;
+        let response_json =
+            crate::wire::complete_response(response_json, &mut session.recv, self.max_response_size).await?;
+
+        // Relay-aware servers always negotiate tagged responses with the relay
+        decode_response(&response_json, &target, true, signature.as_ref())
+    }
+
+    /// Open a streaming session with `target`, relayed through `relay`
+    pub async fn connect_via<P, DATA>(
+        &self,
+        relay: fastn_id52::PublicKey,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        data: DATA,
+    ) -> Result<Session, CallError>
+    where
+        P: serde::Serialize,
+        DATA: serde::Serialize,
+    {
+        let header = fastn_net::Protocol::Relay {
+            target: target.id52(),
+            from: None,
+        };
+        let (session, _) = self
+            .connect_forwarded(relay, header, target, protocol, data, |message| CallError::Relay { message })
+            .await?;
+
+        tracing::debug!("Relaying to {} via {}", target.id52(), relay.id52());
+        Ok(session)
+    }
+
+    /// Pair this client's key as a device of `identity`, using a code from the primary
+    ///
+    /// The outer error is a transport failure, the inner one the primary's
+    /// refusal (wrong or expired code). See [`crate::server::devices`].
+    pub async fn pair(
+        &self,
+        identity: fastn_id52::PublicKey,
+        code: &str,
+        name: &str,
+    ) -> Result<Result<(), String>, CallError> {
+        let request = crate::server::devices::PairRequest {
+            code: code.to_string(),
+            name: name.to_string(),
+        };
+        let data = serde_json::to_value(&request)
+            .map_err(|source| CallError::Serialization { source })?;
+        let wrapper = crate::wire::WrapperRequest::new(serde_json::json!("pair"), data);
+        let mut session = self
+            .connect_with_header(identity, fastn_net::Protocol::DevicePair, wrapper)
+            .await?;
+        read_status(&mut session).await
+    }
+
+    /// Accept `invite`, asking to be saved as `name` in the inviter's address book
+    ///
+    /// The connection presents the invite's capability token, so the inviter
+    /// lets us in without knowing us. See [`crate::server::invites`].
+    pub async fn accept_invite(
+        &self,
+        invite: &crate::server::invites::Invite,
+        name: &str,
+    ) -> Result<Result<crate::server::invites::InviteAccepted, crate::server::invites::InviteRefused>, CallError> {
+        if let Err(e) = invite.verify() {
+            return Ok(Err(crate::server::invites::InviteRefused { message: e.to_string() }));
+        }
+        let request = crate::server::invites::AcceptInvite {
+            code: invite.code.clone(),
+            name: name.to_string(),
+        };
+        let hello = self.hello.clone().with_capability(&invite.token);
+        self.clone()
+            .with_hello_metadata(hello)
+            .call(invite.identity(), crate::server::invites::INVITE_PROTOCOL, request)
+            .await
+    }
+
+    /// Make a request/response call to `target` as `identity`, through its primary
+    ///
+    /// This client's key must be paired with `identity`; the primary opens the
+    /// onward stream, so `target` sees `identity` as the caller.
+    pub async fn call_as_device<P, INPUT, OUTPUT, ERROR>(
+        &self,
+        identity: fastn_id52::PublicKey,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        input: INPUT,
+    ) -> Result<Result<OUTPUT, ERROR>, CallError>
+    where
+        P: serde::Serialize,
+        INPUT: serde::Serialize,
+        OUTPUT: for<'de> serde::Deserialize<'de>,
+        ERROR: for<'de> serde::Deserialize<'de>,
+    {
+        let header = fastn_net::Protocol::DeviceProxy { target: target.id52() };
+        let (mut session, signature) = self
+            .connect_forwarded(identity, header, target, protocol, input, |message| CallError::Device { message })
+            .await?;
+        let response_json = session
+            .recv
+            .next_string()
+            .await
+            .map_err(CallError::from_net)?;
+        let response_json =
+            crate::wire::complete_response(response_json, &mut session.recv, self.max_response_size).await?;
+
+        // The primary's onward client always negotiates tagged responses
+        decode_response(&response_json, &target, true, signature.as_ref())
+    }
+
+    /// Open a streaming session with `target` as `identity`, through its primary
+    pub async fn connect_as_device<P, DATA>(
+        &self,
+        identity: fastn_id52::PublicKey,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        data: DATA,
+    ) -> Result<Session, CallError>
+    where
+        P: serde::Serialize,
+        DATA: serde::Serialize,
+    {
+        let header = fastn_net::Protocol::DeviceProxy { target: target.id52() };
+        let (session, _) = self
+            .connect_forwarded(identity, header, target, protocol, data, |message| CallError::Device { message })
+            .await?;
+
+        tracing::debug!("Calling {} as {} through its primary", target.id52(), identity.id52());
+        Ok(session)
+    }
+
+    /// Open a stream to `target` through the forwarding peer `hop`
+    ///
+    /// The hop reports whether it reached `target` before piping anything;
+    /// a refusal is turned into an error with `refused`. Returns the request
+    /// signature so callers can verify the signed response.
+    async fn connect_forwarded<P, DATA>(
+        &self,
+        hop: fastn_id52::PublicKey,
+        header: fastn_net::Protocol,
+        target: fastn_id52::PublicKey,
+        protocol: P,
+        data: DATA,
+        refused: fn(String) -> CallError,
+    ) -> Result<(Session, Option<crate::signing::PayloadSignature>), CallError>
+    where
+        P: serde::Serialize,
+        DATA: serde::Serialize,
+    {
+        let protocol_json = crate::protocol_key::of(&protocol)
+            .map_err(|source| CallError::Serialization { source })?;
+        let data = serde_json::to_value(&data)
+            .map_err(|source| CallError::Serialization { source })?;
+
+        // Signed for the final target, the hop passes the signature on untouched
+        let signature = self.request_signature(&target, &protocol_json, &data);
+        let wrapper = crate::wire::WrapperRequest {
+            signature: signature.clone(),
+            metadata: self.metadata.clone(),
+            ..crate::wire::WrapperRequest::new(protocol_json, data)
+        };
+        let mut session = self.connect_with_header(hop, header, wrapper).await?;
+        read_status(&mut session).await?.map_err(refused)?;
+
+        Ok((session, signature))
+    }
+
+    /// Open a stream to `target` with a custom stream header
+    ///
+    /// Application streams use the `"fastn-p2p"` header; this is also used for
+    /// [`fastn_net::Protocol::Relay`] and device headers. With a relay header
+    /// the connection is negotiated for [`crate::server::relay::RELAY_PROTOCOL`]
+    /// unless this is the last hop, which negotiates the wrapped protocol
+    /// itself; device headers negotiate [`crate::server::devices::DEVICE_PROTOCOL`].
+    pub(crate) async fn connect_with_header(
+        &self,
+        target: fastn_id52::PublicKey,
+        header: fastn_net::Protocol,
+        wrapper: crate::wire::WrapperRequest,
+    ) -> Result<Session, CallError> {
+        self.open_session(target, header, wrapper).await.map(|(session, _)| session)
+    }
+
+    /// Like [`Client::connect_with_header`], also returning whether the server sends tagged responses
+    async fn open_session(
+        &self,
+        target: fastn_id52::PublicKey,
+        header: fastn_net::Protocol,
+        wrapper: crate::wire::WrapperRequest,
+    ) -> Result<(Session, bool), CallError> {
+        let negotiated = match &header {
+            fastn_net::Protocol::Relay { target: final_target, .. } if *final_target != target.id52() => {
+                crate::server::relay::RelayConfig::protocol_json()
+            }
+            fastn_net::Protocol::DevicePair | fastn_net::Protocol::DeviceProxy { .. } => {
+                crate::server::devices::DeviceConfig::protocol_json()
+            }
+            _ => wrapper.protocol.clone(),
+        };
+
+        let (peer, _) = self.peer_connection(&target, &negotiated, None).await?;
+        if !peer.accepts(&negotiated) {
+            return Err(peer.not_accepted(negotiated));
+        }
+
+        match crate::coordination::open_stream_with_header(&peer.conn, &header, &wrapper).await {
+            Ok((send, recv)) => {
+                // Relayed and device streams end at another peer than this connection's
+                let datagrams = (matches!(header, fastn_net::Protocol::Generic(_))
+                    && peer.accepts_datagrams(&wrapper.protocol))
+                .then(|| crate::datagram::Datagrams::open(&peer.conn, &send));
+                Ok((Session { send, recv, datagrams }, peer.tagged_responses))
+            }
+            Err(e) => {
+                self.forget(&target).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sign a request for `target` if this client is in signed mode
+    fn request_signature(
+        &self,
+        target: &fastn_id52::PublicKey,
+        protocol_json: &serde_json::Value,
+        data: &serde_json::Value,
+    ) -> Option<crate::signing::PayloadSignature> {
+        self.sign_requests
+            .then(|| crate::signing::sign_request(&self.inner.secret_key, target, protocol_json, data))
+    }
+
+    /// Drop the cached connection to `target`, if any
+    ///
+    /// The session resumption token is kept so the next connection can resume.
+    pub async fn forget(&self, target: &fastn_id52::PublicKey) {
+        self.inner.connections.lock().await.remove(target);
+    }
+
+    /// Rebind the endpoint after a network change and drop every cached connection
+    ///
+    /// Calls made afterwards reconnect over the new network. See [`crate::network`].
+    pub async fn rebind(&self) {
+        let endpoint = self.inner.endpoint.lock().await.clone();
+        if let Some(endpoint) = endpoint {
+            crate::network::rebind(&endpoint).await;
+        }
+        let dropped = std::mem::take(&mut *self.inner.connections.lock().await);
+        tracing::debug!("Dropped {} connections of {} after a network change", dropped.len(), self.public_key().id52());
+    }
+
+    /// Connect to `target` and wait for the path `preference` asks for, if any
+    async fn require_path(
+        &self,
+        target: &fastn_id52::PublicKey,
+        protocol_json: &serde_json::Value,
+        preference: PathPreference,
+    ) -> Result<(), CallError> {
+        if preference.is_any() {
+            return Ok(());
+        }
+        let (peer, _) = self.peer_connection(target, protocol_json, None).await?;
+        let endpoint = self.endpoint().await?;
+        let path = crate::coordination::require_path(&endpoint, &peer.conn, preference).await?;
+        tracing::debug!("Call to {} is {} and goes {}", target.id52(), preference, path);
+        Ok(())
+    }
+
+    /// The server of this process to answer `call` in-process, if loopback applies to it
+    fn local_server(&self, call: &crate::interceptor::OutgoingCall) -> Option<std::sync::Arc<crate::server::loopback::LocalServer>> {
+        if !self.loops_back(call) {
+            return None;
+        }
+        crate::server::loopback::server(call.target())
+    }
+
+    /// FASTN_HOME whose serving processes may answer `call`, if loopback applies to it
+    fn loopback_home(&self, call: &crate::interceptor::OutgoingCall) -> Option<&std::path::Path> {
+        self.loopback_home.as_deref().map(|home| home.as_path()).filter(|_| self.loops_back(call))
+    }
+
+    /// Whether `call` may skip the network, see [`crate::server::loopback`]
+    fn loops_back(&self, call: &crate::interceptor::OutgoingCall) -> bool {
+        let needs_network = call.is_stream()
+            || self.sign_requests
+            || !call.attachments.is_empty()
+            || !call.path_preference.is_any();
+        self.loopback && !needs_network
+    }
+
+    /// Path the cached connection to `target` takes right now
+    async fn path_to(&self, target: &fastn_id52::PublicKey) -> PathType {
+        let peer = self.inner.connections.lock().await.get(target).cloned();
+        let endpoint = self.inner.endpoint.lock().await.clone();
+        match (peer, endpoint) {
+            (Some(peer), Some(endpoint)) => PathType::of(&endpoint, &peer.conn),
+            _ => PathType::Unknown,
+        }
+    }
+
+    /// Number of peers with a cached connection
+    pub async fn connection_count(&self) -> usize {
+        self.inner.connections.lock().await.len()
+    }
+
+    /// The open connection to `target` whatever it was negotiated for, or a new one
+    ///
+    /// For streams every server answers itself, like pings.
+    async fn any_connection(&self, target: &fastn_id52::PublicKey) -> Result<crate::coordination::PeerConnection, CallError> {
+        let cached = {
+            let connections = self.inner.connections.lock().await;
+            connections.get(target).filter(|peer| !peer.is_closed()).cloned()
+        };
+        match cached {
+            Some(peer) => Ok(peer),
+            // Every fastn-p2p server accepts batches, so offering it always completes the handshake
+            None => Ok(self.peer_connection(target, &crate::batch::protocol_json(), None).await?.0),
+        }
+    }
+
+    /// The endpoint this client sends from, binding it on first use
+    ///
+    /// Hosts that track endpoints themselves, like the daemon, take this one
+    /// rather than binding a second endpoint for the same key. Once closed, the
+    /// next call binds a new one.
+    pub async fn endpoint(&self) -> Result<iroh::Endpoint, CallError> {
+        let mut endpoint = self.inner.endpoint.lock().await;
+        if let Some(endpoint) = endpoint.as_ref().filter(|endpoint| !endpoint.is_closed()) {
+            return Ok(endpoint.clone());
+        }
+        let bound = fastn_net::get_endpoint(self.inner.secret_key.clone())
+            .await
+            .map_err(|source| CallError::Endpoint { source: source.into() })?;
+        *endpoint = Some(bound.clone());
+        Ok(bound)
+    }
+
+    /// Get a handshaken connection to `target` that accepts `protocol_json`
+    ///
+    /// `early_request` is only sent if a new connection has to be made; the
+    /// returned response line is `Some` when the server answered it already.
+    async fn peer_connection(
+        &self,
+        target: &fastn_id52::PublicKey,
+        protocol_json: &serde_json::Value,
+        early_request: Option<crate::handshake::EarlyRequest>,
+    ) -> Result<(crate::coordination::PeerConnection, Option<String>), CallError> {
+        // Protocols to offer if we have to (re)connect - keep what the old connection had
+        let mut protocols = vec![protocol_json.clone()];
+        {
+            let connections = self.inner.connections.lock().await;
+            if let Some(existing) = connections.get(target) {
+                if !existing.is_closed() && existing.accepts(protocol_json) {
+                    return Ok((existing.clone(), None));
+                }
+                protocols.extend(
+                    existing
+                        .accepted_protocols
+                        .iter()
+                        .filter(|p| *p != protocol_json)
+                        .cloned(),
+                );
+            }
+        }
+
+        let resumption_token = self
+            .inner
+            .resumption_tokens
+            .lock()
+            .expect("Failed to acquire lock on resumption_tokens")
+            .get(target)
+            .cloned();
+
+        // Connect without holding the lock so calls to other peers are not blocked
+        let endpoint = self.endpoint().await?;
+        let (peer, early_response) = crate::coordination::connect_peer(
+            &endpoint,
+            target,
+            protocols,
+            resumption_token,
+            early_request,
+            self.max_response_size,
+            &self.hello,
+        )
+        .await?;
+
+        if let Some(ref token) = peer.resumption_token {
+            self.inner
+                .resumption_tokens
+                .lock()
+                .expect("Failed to acquire lock on resumption_tokens")
+                .insert(*target, token.clone());
+        }
+        tracing::debug!(
+            "Connected to {} with {} protocols",
+            target.id52(),
+            peer.accepted_protocols.len()
+        );
+
+        self.inner
+            .connections
+            .lock()
+            .await
+            .insert(*target, peer.clone());
+        Ok((peer, early_response))
+    }
+}
+
+/// Reconnects [`Client::download`] makes in a row without receiving anything
+pub const MAX_RESUME_ATTEMPTS: u32 = 5;
+
+/// Wait before the first reconnect of a download; doubles, triples... after that
+const RESUME_BACKOFF: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Client side of a streaming session opened with [`Client::connect`]
+pub struct Session {
+    /// Stream to the server
+    pub send: iroh::endpoint::SendStream,
+    /// Stream from the server; buffered, read frames or raw bytes via `AsyncRead`
+    pub recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    /// Unreliable datagrams, if the server enabled them for the protocol
+    pub datagrams: Option<crate::datagram::Datagrams>,
+}
+
+impl Session {
+    /// Copy everything the server sends into `writer` (download pattern)
+    pub async fn copy_to<W>(&mut self, mut writer: W) -> std::io::Result<u64>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        tokio::io::copy(&mut self.recv, &mut writer).await
+    }
+
+    /// Copy `reader` to the server (upload pattern)
+    pub async fn copy_from<R>(&mut self, mut reader: R) -> std::io::Result<u64>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        tokio::io::copy(&mut reader, &mut self.send).await
+    }
+
+    /// Receive a stream sent with `copy_from_verified` into `writer` and check its checksum
+    ///
+    /// Fails with [`crate::checksum::VerifyError::ChecksumMismatch`] if the data
+    /// doesn't hash to what the server computed, see [`crate::checksum`].
+    pub async fn copy_to_verified<W>(&mut self, mut writer: W) -> Result<u64, crate::checksum::VerifyError>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        crate::checksum::receive(&mut self.recv, &mut writer).await
+    }
+
+    /// Send `reader` followed by its BLAKE3 checksum, for `copy_to_verified` on the server
+    pub async fn copy_from_verified<R>(&mut self, mut reader: R) -> std::io::Result<u64>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        crate::checksum::send(&mut reader, &mut self.send).await
+    }
+
+    /// [`Self::copy_to`], reporting to `observer`, see [`crate::progress`]
+    pub async fn copy_to_with_progress<W>(
+        &mut self,
+        mut writer: W,
+        observer: &crate::progress::Observer,
+    ) -> std::io::Result<u64>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        crate::progress::copy(&mut self.recv, &mut writer, observer).await
+    }
+
+    /// [`Self::copy_from`], reporting to `observer`, see [`crate::progress`]
+    pub async fn copy_from_with_progress<R>(
+        &mut self,
+        mut reader: R,
+        observer: &crate::progress::Observer,
+    ) -> std::io::Result<u64>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        crate::progress::copy(&mut reader, &mut self.send, observer).await
+    }
+
+    /// Upload `reader` and download into `writer` at the same time, reporting each direction
+    pub async fn copy_both_with_progress<R, W>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+        upload: &crate::progress::Observer,
+        download: &crate::progress::Observer,
+    ) -> std::io::Result<(u64, u64)>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let to_remote = crate::progress::copy(&mut reader, &mut self.send, upload);
+        let from_remote = crate::progress::copy(&mut self.recv, &mut writer, download);
+
+        futures_util::try_join!(to_remote, from_remote)
+    }
+
+    /// Largest datagram [`Self::send_datagram`] takes right now, see [`crate::datagram`]
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.datagrams.as_ref().and_then(crate::datagram::Datagrams::max_size)
+    }
+
+    /// Send an unreliable datagram to the server
+    pub fn send_datagram(&self, payload: &[u8]) -> Result<(), crate::datagram::DatagramError> {
+        self.datagrams.as_ref().ok_or(crate::datagram::DatagramError::NotEnabled)?.send(payload)
+    }
+
+    /// Wait for the next datagram from the server
+    pub async fn recv_datagram(&mut self) -> Result<Vec<u8>, crate::datagram::DatagramError> {
+        self.datagrams.as_mut().ok_or(crate::datagram::DatagramError::NotEnabled)?.recv().await
+    }
+}
+
+/// Open a streaming session with a peer
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub async fn connect<P, DATA>(
+    sender: fastn_id52::SecretKey,
+    target: fastn_id52::PublicKey,
+    protocol: P,
+    data: DATA,
+) -> Result<Session, CallError>
+where
+    P: serde::Serialize,
+    DATA: serde::Serialize,
+{
+    Client::global(sender).connect(target, protocol, data).await
+}
+
+/// Download a resumable stream from a peer into `writer`, see [`Client::download`]
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub async fn download<P, DATA, W>(
+    sender: fastn_id52::SecretKey,
+    target: fastn_id52::PublicKey,
+    protocol: P,
+    data: DATA,
+    writer: W,
+    progress: impl FnMut(u64),
+) -> Result<u64, CallError>
+where
+    P: serde::Serialize,
+    DATA: serde::Serialize,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    Client::global(sender).download(target, protocol, data, writer, progress).await
+}
+
+/// Make a request/response call to a peer
+///
+/// Uses the process-global [`Client`] for `sender`, so connections are reused
+/// across calls.
+pub async fn call<P, INPUT, OUTPUT, ERROR>(
+    sender: fastn_id52::SecretKey,
+    target: fastn_id52::PublicKey,
+    protocol: P,
+    input: INPUT,
+) -> Result<Result<OUTPUT, ERROR>, CallError>
+where
+    P: serde::Serialize,
+    INPUT: serde::Serialize,
+    OUTPUT: for<'de> serde::Deserialize<'de>,
+    ERROR: for<'de> serde::Deserialize<'de>,
+{
+    Client::global(sender).call(target, protocol, input).await
+}
+
+/// Make several request/response calls to a peer in one stream round trip, see [`Client::call_batch`]
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub async fn call_batch<P, INPUT, OUTPUT, ERROR>(
+    sender: fastn_id52::SecretKey,
+    target: fastn_id52::PublicKey,
+    calls: Vec<(P, INPUT)>,
+) -> Result<Vec<Result<OUTPUT, ERROR>>, CallError>
+where
+    P: serde::Serialize,
+    INPUT: serde::Serialize,
+    OUTPUT: for<'de> serde::Deserialize<'de>,
+    ERROR: for<'de> serde::Deserialize<'de>,
+{
+    Client::global(sender).call_batch(target, calls).await
+}
+
+/// Make the same call to many peers, see [`Client::call_many`]
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub fn call_many<P, INPUT, OUTPUT, ERROR>(
+    sender: fastn_id52::SecretKey,
+    peers: impl IntoIterator<Item = fastn_id52::PublicKey>,
+    protocol: P,
+    input: INPUT,
+    fan_out: crate::fan_out::FanOut,
+) -> Result<crate::fan_out::Replies<OUTPUT, ERROR>, CallError>
+where
+    P: serde::Serialize,
+    INPUT: serde::Serialize,
+    OUTPUT: for<'de> serde::Deserialize<'de> + Send + 'static,
+    ERROR: for<'de> serde::Deserialize<'de> + Send + 'static,
+{
+    Client::global(sender).call_many(peers, protocol, input, fan_out)
+}
+
+/// Time one ping round trip to `target`, see [`Client::ping`]
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub async fn ping(
+    sender: fastn_id52::SecretKey,
+    target: fastn_id52::PublicKey,
+) -> Result<crate::ping::Pong, CallError> {
+    Client::global(sender).ping(target).await
+}
+
+/// How far `target`'s clock is from ours, see [`Client::time_offset`]
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub async fn time_offset(
+    sender: fastn_id52::SecretKey,
+    target: fastn_id52::PublicKey,
+) -> Result<crate::time_sync::TimeOffset, CallError> {
+    Client::global(sender).time_offset(target).await
+}
+
+/// Stream header of plain application streams
+fn app_header() -> fastn_net::Protocol {
+    fastn_net::Protocol::Generic(serde_json::Value::String("fastn-p2p".to_string()))
+}
+
+/// Turn an application result from the interceptor chain into the caller's types
+///
+/// Old untagged servers can't tell OUTPUT and ERROR apart, so from them a
+/// success that isn't an OUTPUT is tried as an ERROR before giving up. A
+/// tagged success is always an OUTPUT.
+fn typed_result<OUTPUT, ERROR>(
+    result: Result<serde_json::Value, serde_json::Value>,
+    tagged: bool,
+) -> Result<Result<OUTPUT, ERROR>, CallError>
+where
+    OUTPUT: for<'de> serde::Deserialize<'de>,
+    ERROR: for<'de> serde::Deserialize<'de>,
+{
+    match result {
+        Ok(output) if tagged => serde_json::from_value::<OUTPUT>(output)
+            .map(Ok)
+            .map_err(|source| CallError::Deserialization { source }),
+        Ok(output) => match serde_json::from_value::<OUTPUT>(output.clone()) {
+            Ok(output) => Ok(Ok(output)),
+            Err(source) => serde_json::from_value::<ERROR>(output)
+                .map(Err)
+                .map_err(|_| CallError::Deserialization { source }),
+        },
+        Err(error) => serde_json::from_value::<ERROR>(error)
+            .map(Err)
+            .map_err(|source| CallError::Deserialization { source }),
+    }
+}
+
+/// Decode a response line, checking `target`'s signature if the request was signed
+pub(crate) fn decode_response<OUTPUT, ERROR>(
+    response_json: &str,
+    target: &fastn_id52::PublicKey,
+    tagged: bool,
+    request_signature: Option<&crate::signing::PayloadSignature>,
+) -> Result<Result<OUTPUT, ERROR>, CallError>
+where
+    OUTPUT: for<'de> serde::Deserialize<'de>,
+    ERROR: for<'de> serde::Deserialize<'de>,
+{
+    let Some(request_signature) = request_signature else {
+        return crate::wire::decode_response(response_json, tagged)
+            .map_err(|source| CallError::Deserialization { source });
+    };
+
+    let envelope = verified_envelope(response_json, target, request_signature)?;
+    let envelope: crate::wire::ResponseEnvelope<OUTPUT, ERROR> = serde_json::from_value(envelope)
+        .map_err(|source| CallError::Deserialization { source })?;
+    Ok(envelope.into())
+}
+
+/// Like [`decode_response`] into JSON values, also returning the answer's [`crate::wire::MAX_AGE`]
+fn decode_received(
+    response_json: &str,
+    target: &fastn_id52::PublicKey,
+    tagged: bool,
+    request_signature: Option<&crate::signing::PayloadSignature>,
+) -> Result<(Result<serde_json::Value, serde_json::Value>, Received), CallError> {
+    if !tagged {
+        let result = decode_response(response_json, target, tagged, request_signature)?;
+        return Ok((result, Received { tagged, max_age: None }));
+    }
+
+    let mut envelope = match request_signature {
+        Some(request_signature) => verified_envelope(response_json, target, request_signature)?,
+        None => serde_json::from_str(response_json).map_err(|source| CallError::Deserialization { source })?,
+    };
+    let max_age = crate::wire::take_max_age(&mut envelope);
+    let envelope: crate::wire::ResponseEnvelope<serde_json::Value, serde_json::Value> = serde_json::from_value(envelope)
+        .map_err(|source| CallError::Deserialization { source })?;
+    Ok((envelope.into(), Received { tagged, max_age }))
+}
+
+/// The tagged envelope of a signed response line, once its signature by `target` checks out
+fn verified_envelope(
+    response_json: &str,
+    target: &fastn_id52::PublicKey,
+    request_signature: &crate::signing::PayloadSignature,
+) -> Result<serde_json::Value, CallError> {
+    let (envelope, signature) = crate::wire::split_signed_response(response_json)
+        .map_err(|source| CallError::Deserialization { source })?;
+    let signature = signature.ok_or(CallError::Signature {
+        source: crate::signing::SignatureError::Missing,
+    })?;
+    crate::signing::verify_response(&signature, target, request_signature, &envelope)
+        .map_err(|source| CallError::Signature { source })?;
+    Ok(envelope)
+}
+
+/// Read the status line a relay or primary sends before piping
+async fn read_status(session: &mut Session) -> Result<Result<(), String>, CallError> {
+    let status_json = session
+        .recv
+        .next_string()
+        .await
+        .map_err(CallError::from_net)?;
+    crate::wire::decode_response(&status_json, true)
+        .map_err(|source| CallError::Deserialization { source })
+}
+
+/// Make a request/response call to a peer through a relay
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub async fn call_via<P, INPUT, OUTPUT, ERROR>(
+    sender: fastn_id52::SecretKey,
+    relay: fastn_id52::PublicKey,
+    target: fastn_id52::PublicKey,
+    protocol: P,
+    input: INPUT,
+) -> Result<Result<OUTPUT, ERROR>, CallError>
+where
+    P: serde::Serialize,
+    INPUT: serde::Serialize,
+    OUTPUT: for<'de> serde::Deserialize<'de>,
+    ERROR: for<'de> serde::Deserialize<'de>,
+{
+    Client::global(sender).call_via(relay, target, protocol, input).await
+}
+
+/// Open a streaming session with a peer through a relay
+///
+/// Uses the process-global [`Client`] for `sender`.
+pub async fn connect_via<P, DATA>(
+    sender: fastn_id52::SecretKey,
+    relay: fastn_id52::PublicKey,
+    target: fastn_id52::PublicKey,
+    protocol: P,
+    data: DATA,
+) -> Result<Session, CallError>
+where
+    P: serde::Serialize,
+    DATA: serde::Serialize,
+{
+    Client::global(sender).connect_via(relay, target, protocol, data).await
+}
+
+/// Ping `target` until it answers; a server just started takes a moment to be discovered
+#[cfg(test)]
+pub(crate) async fn wait_until_reachable(client: &Client, target: fastn_id52::PublicKey) {
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(20);
+    while let Err(e) = client.ping(target).await {
+        assert!(tokio::time::Instant::now() < deadline, "{} is not reachable: {}", target.id52(), e);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
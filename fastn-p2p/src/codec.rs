@@ -0,0 +1,149 @@
+//! Serialization formats for request/response payloads
+//!
+//! JSON is the default. Protocols that move numeric-heavy or binary data can
+//! ask for CBOR or MessagePack when they are registered:
+//!
+//! ```rust,no_run
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum Telemetry { Samples }
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # #[error("rejected")]
+//! # struct Rejected;
+//! # async fn samples(input: Vec<f64>) -> Result<usize, Rejected> { Ok(input.len()) }
+//! # async fn run(key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//! fastn_p2p::listen(key)
+//!     .handle_requests(Telemetry::Samples, samples)
+//!     .with_codec(fastn_p2p::codec::Codec::Cbor)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Clients list the codecs they can decode in ClientHello and the server
+//! answers with the codec of each accepted protocol both sides support, so
+//! [`crate::client::call`] picks it without being told. Payloads are still
+//! `serde_json::Value`s on both ends: handlers, middleware and interceptors
+//! see no difference, only the bytes on the wire change. The first call on a
+//! new connection rides along with the handshake, before the codec is known,
+//! and is always JSON.
+//!
+//! CBOR needs the `cbor` feature and MessagePack the `msgpack` feature; a
+//! peer built without them keeps talking JSON.
+
+/// How a protocol's request and response payloads are encoded on the wire
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Codec {
+    #[default]
+    Json,
+    Cbor,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("This build has no {codec} support")]
+    Unavailable { codec: Codec },
+
+    #[error("Failed to encode {codec}: {message}")]
+    Encode { codec: Codec, message: String },
+
+    #[error("Failed to decode {codec}: {message}")]
+    Decode { codec: Codec, message: String },
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Codec::Json => write!(f, "json"),
+            Codec::Cbor => write!(f, "cbor"),
+            Codec::MessagePack => write!(f, "msgpack"),
+        }
+    }
+}
+
+impl Codec {
+    /// Codecs this build can encode and decode, JSON first
+    pub fn supported() -> Vec<Codec> {
+        [
+            Some(Codec::Json),
+            cfg!(feature = "cbor").then_some(Codec::Cbor),
+            cfg!(feature = "msgpack").then_some(Codec::MessagePack),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    pub fn is_supported(self) -> bool {
+        Self::supported().contains(&self)
+    }
+
+    pub fn encode(self, value: &serde_json::Value) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        self.encode_into(value, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Encode `value` straight into `writer`, without building it in memory first
+    pub fn encode_into(self, value: &impl serde::Serialize, mut writer: impl std::io::Write) -> Result<(), CodecError> {
+        let encode_error = |message: String| CodecError::Encode { codec: self, message };
+        match self {
+            Codec::Json => serde_json::to_writer(&mut writer, value).map_err(|e| encode_error(e.to_string())),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => ciborium::into_writer(value, &mut writer).map_err(|e| encode_error(e.to_string())),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => rmp_serde::encode::write_named(&mut writer, value).map_err(|e| encode_error(e.to_string())),
+            #[allow(unreachable_patterns)]
+            codec => Err(CodecError::Unavailable { codec }),
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Result<serde_json::Value, CodecError> {
+        let decode_error = |message: String| CodecError::Decode { codec: self, message };
+        match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| decode_error(e.to_string())),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => ciborium::from_reader(bytes).map_err(|e| decode_error(e.to_string())),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| decode_error(e.to_string())),
+            #[allow(unreachable_patterns)]
+            codec => Err(CodecError::Unavailable { codec }),
+        }
+    }
+}
+
+/// The codec a server uses for one protocol, sent in ServerHello
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProtocolCodec {
+    pub protocol: serde_json::Value,
+    pub codec: Codec,
+}
+
+/// The codec to use for `protocol`, JSON unless `codecs` says otherwise
+pub fn for_protocol(codecs: &[ProtocolCodec], protocol: &serde_json::Value) -> Codec {
+    codecs
+        .iter()
+        .find(|entry| entry.protocol == *protocol)
+        .map_or(Codec::Json, |entry| entry.codec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_codecs_round_trip() {
+        let value = serde_json::json!({"samples": [1, -2, 3.5, 1e300], "name": "probe", "ok": true, "none": null});
+        for codec in Codec::supported() {
+            let bytes = codec.encode(&value).unwrap();
+            assert_eq!(codec.decode(&bytes).unwrap(), value, "{codec}");
+        }
+        assert_eq!(serde_json::to_value(Codec::MessagePack).unwrap(), "msgpack");
+
+        let codecs = [ProtocolCodec { protocol: serde_json::json!("Samples"), codec: Codec::Cbor }];
+        assert_eq!(for_protocol(&codecs, &serde_json::json!("Samples")), Codec::Cbor);
+        assert_eq!(for_protocol(&codecs, &serde_json::json!("Echo")), Codec::Json);
+    }
+}
@@ -0,0 +1,641 @@
+//! Typed client stubs generated from protocol descriptions
+//!
+//! [`generate`] turns an OpenRPC document served on
+//! [`crate::server::describe::DESCRIBE_PROTOCOL`] (or saved from
+//! `fastn-p2p describe`) into client code, so request, response and error
+//! structs don't have to be copied between repos. `fastn-p2p codegen` wraps
+//! it, and build scripts can call it on a checked-in document:
+//!
+//! ```rust,no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // build.rs
+//! let document = serde_json::from_str(&std::fs::read_to_string("mail.openrpc.json")?)?;
+//! let code = fastn_p2p::codegen::generate(&document, Some("mail.fastn.com"), fastn_p2p::codegen::Lang::Rust)?;
+//! std::fs::write(std::path::Path::new(&std::env::var("OUT_DIR")?).join("mail.rs"), code)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Rust stubs call [`fastn_p2p_client::call`] and [`fastn_p2p_client::connect`].
+//! TypeScript stubs take a `Transport` that hands protocol values and inputs
+//! to the daemon. Types are generated for the schema shapes `schemars` emits
+//! for structs, unit enums, options, lists and maps; anything else (e.g. enums
+//! carrying data) stays untyped JSON.
+
+/// Language of the generated stubs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Rust,
+    TypeScript,
+}
+
+impl std::str::FromStr for Lang {
+    type Err = CodegenError;
+
+    fn from_str(lang: &str) -> Result<Self, Self::Err> {
+        match lang {
+            "rust" | "rs" => Ok(Lang::Rust),
+            "typescript" | "ts" => Ok(Lang::TypeScript),
+            other => Err(CodegenError::UnknownLang { lang: other.to_string() }),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodegenError {
+    #[error("Unknown language '{lang}' (expected rust or typescript)")]
+    UnknownLang { lang: String },
+    #[error("Not a protocol description: {reason}")]
+    InvalidDocument { reason: String },
+    #[error("No described methods belong to '{protocol}'")]
+    NoMethods { protocol: String },
+}
+
+/// Client code for the methods of `document`, limited to `protocol` if given
+///
+/// See [`crate::server::describe::MethodDescription::belongs_to`] for which
+/// methods a protocol covers.
+pub fn generate(document: &serde_json::Value, protocol: Option<&str>, lang: Lang) -> Result<String, CodegenError> {
+    let methods = document
+        .get("methods")
+        .and_then(serde_json::Value::as_array)
+        .ok_or_else(|| CodegenError::InvalidDocument { reason: "no methods array".to_string() })?;
+
+    let mut types = Types::default();
+    let methods: Vec<Method> = methods
+        .iter()
+        .filter_map(crate::server::describe::MethodDescription::from_openrpc)
+        .filter(|method| protocol.is_none_or(|protocol| method.belongs_to(protocol)))
+        .map(|description| types.method(description))
+        .collect();
+    if methods.is_empty() {
+        return Err(CodegenError::NoMethods { protocol: protocol.unwrap_or("any protocol").to_string() });
+    }
+
+    let title = document["info"]["title"].as_str().unwrap_or("a fastn-p2p server");
+    Ok(match lang {
+        Lang::Rust => rust::render(title, &types.defs, &methods),
+        Lang::TypeScript => typescript::render(title, &types.defs, &methods),
+    })
+}
+
+/// A type as both languages see it
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Any,
+    Unit,
+    Bool,
+    Int,
+    UInt,
+    Float,
+    String,
+    List(Box<Ty>),
+    Map(Box<Ty>),
+    Option(Box<Ty>),
+    Named(String),
+}
+
+struct Field {
+    /// The JSON name
+    name: String,
+    ty: Ty,
+    required: bool,
+    doc: Option<String>,
+}
+
+enum Shape {
+    Struct(Vec<Field>),
+    /// JSON strings of the variants
+    Enum(Vec<String>),
+}
+
+struct Def {
+    name: String,
+    doc: Option<String>,
+    shape: Shape,
+}
+
+struct Method {
+    description: crate::server::describe::MethodDescription,
+    /// Words of the method name, e.g. `["get", "mails"]`
+    words: Vec<String>,
+    input: Ty,
+    output: Option<Ty>,
+    error: Option<Ty>,
+}
+
+impl Method {
+    /// The protocol argument for the daemon, which parses JSON and keeps plain names as they are
+    fn protocol(&self) -> String {
+        match &self.description.protocol {
+            serde_json::Value::String(name) => name.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// Named types collected from every method's schemas, in definition order
+#[derive(Default)]
+struct Types {
+    defs: Vec<Def>,
+    names: std::collections::HashSet<String>,
+}
+
+type Defs = serde_json::Map<String, serde_json::Value>;
+
+/// The schema of `items` or `additionalProperties` when it's left out
+static ANYTHING: serde_json::Value = serde_json::Value::Bool(true);
+
+impl Types {
+    fn method(&mut self, description: crate::server::describe::MethodDescription) -> Method {
+        let words = method_words(&description.protocol);
+        let base = pascal_case(&words);
+        let input = self.root(&description.input, &format!("{base}Input"));
+        let output = description.output.as_ref().map(|schema| self.root(schema, &format!("{base}Output")));
+        let error = description.error.as_ref().map(|schema| self.root(schema, &format!("{base}Error")));
+        Method { description, words, input, output, error }
+    }
+
+    /// A root schema, named after its `title` (the Rust type `schemars` saw) when it has one
+    fn root(&mut self, schema: &serde_json::Value, fallback: &str) -> Ty {
+        let defs = schema.get("$defs").and_then(serde_json::Value::as_object);
+        let name = match schema.get("title").and_then(serde_json::Value::as_str) {
+            Some(title) => pascal_case(&words(title)),
+            None => fallback.to_string(),
+        };
+        self.ty(schema, &name, defs)
+    }
+
+    fn ty(&mut self, schema: &serde_json::Value, name: &str, defs: Option<&Defs>) -> Ty {
+        // `true` and `{}` accept anything
+        let Some(object) = schema.as_object() else { return Ty::Any };
+
+        if let Some(reference) = object.get("$ref").and_then(serde_json::Value::as_str) {
+            let def = reference.rsplit('/').next().unwrap_or(reference);
+            return match defs.and_then(|defs| defs.get(def)) {
+                Some(schema) => self.shape(schema, &pascal_case(&words(def)), defs),
+                None => Ty::Any,
+            };
+        }
+
+        // `Option<T>` of a referenced type is `T` or null
+        for key in ["anyOf", "oneOf"] {
+            if let Some(options) = object.get(key).and_then(serde_json::Value::as_array) {
+                return match options.as_slice() {
+                    [some, null] if is_null(null) => Ty::Option(Box::new(self.ty(some, name, defs))),
+                    [null, some] if is_null(null) => Ty::Option(Box::new(self.ty(some, name, defs))),
+                    _ => Ty::Any,
+                };
+            }
+        }
+
+        self.shape(schema, name, defs)
+    }
+
+    fn shape(&mut self, schema: &serde_json::Value, name: &str, defs: Option<&Defs>) -> Ty {
+        let Some(object) = schema.as_object() else { return Ty::Any };
+        let doc = object.get("description").and_then(serde_json::Value::as_str).map(str::to_string);
+
+        if let Some(values) = object.get("enum").and_then(serde_json::Value::as_array) {
+            let variants: Option<Vec<String>> = values.iter().map(|value| value.as_str().map(str::to_string)).collect();
+            return match variants {
+                Some(variants) => self.define(name, doc, |_| Shape::Enum(variants)),
+                None => Ty::Any,
+            };
+        }
+
+        let json_types: Vec<&str> = match object.get("type") {
+            Some(serde_json::Value::String(ty)) => vec![ty.as_str()],
+            Some(serde_json::Value::Array(types)) => types.iter().filter_map(serde_json::Value::as_str).collect(),
+            _ => return Ty::Any,
+        };
+
+        let ty = match json_types.iter().find(|ty| **ty != "null").copied() {
+            None => return Ty::Unit,
+            Some("boolean") => Ty::Bool,
+            Some("integer") => {
+                let unsigned = object.get("format").and_then(serde_json::Value::as_str).is_some_and(|format| format.starts_with("uint"))
+                    || object.get("minimum").and_then(serde_json::Value::as_f64).is_some_and(|minimum| minimum >= 0.0);
+                if unsigned { Ty::UInt } else { Ty::Int }
+            }
+            Some("number") => Ty::Float,
+            Some("string") => Ty::String,
+            Some("array") => {
+                let items = object.get("items").unwrap_or(&ANYTHING);
+                Ty::List(Box::new(self.ty(items, &format!("{name}Item"), defs)))
+            }
+            Some("object") => match object.get("properties").and_then(serde_json::Value::as_object) {
+                Some(properties) => {
+                    let required: std::collections::HashSet<&str> = object
+                        .get("required")
+                        .and_then(serde_json::Value::as_array)
+                        .map(|required| required.iter().filter_map(serde_json::Value::as_str).collect())
+                        .unwrap_or_default();
+                    self.define(name, doc, |types| {
+                        Shape::Struct(
+                            properties
+                                .iter()
+                                .map(|(field, schema)| Field {
+                                    name: field.clone(),
+                                    ty: types.ty(schema, &format!("{name}{}", pascal_case(&words(field))), defs),
+                                    required: required.contains(field.as_str()),
+                                    doc: schema.get("description").and_then(serde_json::Value::as_str).map(str::to_string),
+                                })
+                                .collect(),
+                        )
+                    })
+                }
+                None => {
+                    let values = object.get("additionalProperties").unwrap_or(&ANYTHING);
+                    Ty::Map(Box::new(self.ty(values, &format!("{name}Value"), defs)))
+                }
+            },
+            Some(_) => Ty::Any,
+        };
+
+        if json_types.contains(&"null") { Ty::Option(Box::new(ty)) } else { ty }
+    }
+
+    /// Define `name` once; the first schema wins when methods share a type
+    fn define(&mut self, name: &str, doc: Option<String>, shape: impl FnOnce(&mut Self) -> Shape) -> Ty {
+        // Reserved before the fields are visited, so recursive types stop here
+        if self.names.insert(name.to_string()) {
+            let shape = shape(self);
+            self.defs.push(Def { name: name.to_string(), doc, shape });
+        }
+        Ty::Named(name.to_string())
+    }
+}
+
+fn is_null(schema: &serde_json::Value) -> bool {
+    schema.get("type").and_then(serde_json::Value::as_str) == Some("null")
+}
+
+/// Words naming the method of `protocol`: the command of a serve_all
+/// [`crate::server::CommandProtocol`], `Variant` and its value for enum
+/// variants like `{"Mail": "inbox"}`, the name itself otherwise
+fn method_words(protocol: &serde_json::Value) -> Vec<String> {
+    let words = match protocol {
+        serde_json::Value::String(name) => words(name),
+        serde_json::Value::Object(fields) => match fields.get("command").and_then(serde_json::Value::as_str) {
+            Some(command) => words(command),
+            None => {
+                let mut all = Vec::new();
+                for (variant, value) in fields {
+                    all.extend(words(variant));
+                    if let Some(value) = value.as_str() {
+                        all.extend(words(value));
+                    }
+                }
+                all
+            }
+        },
+        other => words(&other.to_string()),
+    };
+    if words.is_empty() { vec!["method".to_string()] } else { words }
+}
+
+/// Lowercase words of an identifier in any case style
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            previous_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && previous_lower {
+            words.push(std::mem::take(&mut current));
+        }
+        previous_lower = c.is_lowercase() || c.is_numeric();
+        current.extend(c.to_lowercase());
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn pascal_case(words: &[String]) -> String {
+    let name: String = words
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect();
+    identifier(name, "Value")
+}
+
+fn snake_case(words: &[String]) -> String {
+    identifier(words.join("_"), "value")
+}
+
+fn camel_case(words: &[String]) -> String {
+    let pascal = pascal_case(words);
+    let mut chars = pascal.chars();
+    chars.next().map(|first| first.to_lowercase().chain(chars).collect()).unwrap_or(pascal)
+}
+
+/// Identifiers can't be empty or start with a digit
+fn identifier(name: String, empty: &str) -> String {
+    match name.chars().next() {
+        None => empty.to_string(),
+        Some(first) if first.is_numeric() => format!("_{name}"),
+        Some(_) => name,
+    }
+}
+
+mod rust {
+    use super::*;
+
+    /// Keywords usable as raw identifiers; `self`, `Self`, `super` and `crate` are not
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false", "fn", "for",
+        "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static",
+        "struct", "trait", "true", "try", "type", "unsafe", "use", "where", "while", "abstract", "become", "box", "do",
+        "final", "macro", "override", "priv", "typeof", "unsized", "virtual", "yield",
+    ];
+
+    pub(super) fn render(title: &str, defs: &[Def], methods: &[Method]) -> String {
+        let mut out = format!("// Generated by `fastn-p2p codegen` from {title:?}; do not edit.\n");
+        for def in defs {
+            out.push('\n');
+            render_def(&mut out, def);
+        }
+        for method in methods {
+            out.push('\n');
+            render_method(&mut out, method);
+        }
+        out
+    }
+
+    fn render_def(out: &mut String, def: &Def) {
+        doc(out, "", def.doc.as_deref());
+        match &def.shape {
+            Shape::Struct(fields) => {
+                out.push_str("#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]\n");
+                out.push_str(&format!("pub struct {} {{\n", def.name));
+                for field in fields {
+                    doc(out, "    ", field.doc.as_deref());
+                    let name = field_name(&field.name);
+                    let optional = !field.required;
+                    let mut attributes = Vec::new();
+                    if name.trim_start_matches("r#") != field.name {
+                        attributes.push(format!("rename = {:?}", field.name));
+                    }
+                    let ty = match &field.ty {
+                        Ty::Option(_) => ty(&field.ty),
+                        other if optional => format!("Option<{}>", ty(other)),
+                        other => ty(other),
+                    };
+                    if optional {
+                        attributes.push("default, skip_serializing_if = \"Option::is_none\"".to_string());
+                    }
+                    if !attributes.is_empty() {
+                        out.push_str(&format!("    #[serde({})]\n", attributes.join(", ")));
+                    }
+                    out.push_str(&format!("    pub {name}: {ty},\n"));
+                }
+                out.push_str("}\n");
+            }
+            Shape::Enum(variants) => {
+                out.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]\n");
+                out.push_str(&format!("pub enum {} {{\n", def.name));
+                for variant in variants {
+                    let name = pascal_case(&words(variant));
+                    if &name != variant {
+                        out.push_str(&format!("    #[serde(rename = {variant:?})]\n"));
+                    }
+                    out.push_str(&format!("    {name},\n"));
+                }
+                out.push_str("}\n");
+            }
+        }
+    }
+
+    fn render_method(out: &mut String, method: &Method) {
+        let name = field_name(&snake_case(&method.words));
+        let protocol = method.protocol();
+        let input = ty(&method.input);
+        match (&method.output, &method.error) {
+            (Some(output), error) => {
+                let error = error.as_ref().map(ty).unwrap_or_else(|| ty(&Ty::Any));
+                out.push_str(&format!("/// Call `{}` on `target`\n", method.description.name()));
+                out.push_str(&format!(
+                    "pub async fn {name}(\n    target: fastn_p2p_client::PublicKey,\n    bind_alias: &str,\n    input: {input},\n) -> Result<Result<{}, {error}>, fastn_p2p_client::ClientError> {{\n",
+                    ty(output),
+                ));
+                out.push_str(&format!("    fastn_p2p_client::call(target, {protocol:?}, bind_alias, input).await\n}}\n"));
+            }
+            (None, _) => {
+                out.push_str(&format!("/// Open a `{}` stream to `target`\n", method.description.name()));
+                out.push_str(&format!(
+                    "pub async fn {name}(\n    target: fastn_p2p_client::PublicKey,\n    bind_alias: &str,\n    data: {input},\n) -> Result<fastn_p2p_client::Session, fastn_p2p_client::ConnectionError> {{\n",
+                ));
+                out.push_str(&format!("    fastn_p2p_client::connect(target, {protocol:?}, bind_alias, data).await\n}}\n"));
+            }
+        }
+    }
+
+    fn ty(ty: &Ty) -> String {
+        match ty {
+            Ty::Any => "serde_json::Value".to_string(),
+            Ty::Unit => "()".to_string(),
+            Ty::Bool => "bool".to_string(),
+            Ty::Int => "i64".to_string(),
+            Ty::UInt => "u64".to_string(),
+            Ty::Float => "f64".to_string(),
+            Ty::String => "String".to_string(),
+            Ty::List(item) => format!("Vec<{}>", self::ty(item)),
+            Ty::Map(value) => format!("std::collections::HashMap<String, {}>", self::ty(value)),
+            Ty::Option(some) => format!("Option<{}>", self::ty(some)),
+            Ty::Named(name) => name.clone(),
+        }
+    }
+
+    fn field_name(json: &str) -> String {
+        let name = snake_case(&words(json));
+        match name.as_str() {
+            "self" | "super" | "crate" => format!("{name}_"),
+            _ if KEYWORDS.contains(&name.as_str()) => format!("r#{name}"),
+            _ => name,
+        }
+    }
+
+    fn doc(out: &mut String, indent: &str, doc: Option<&str>) {
+        for line in doc.into_iter().flat_map(str::lines) {
+            out.push_str(&format!("{indent}/// {line}\n").replace("/// \n", "///\n"));
+        }
+    }
+}
+
+mod typescript {
+    use super::*;
+
+    const TRANSPORT: &str = "\
+/** Sends protocol values and inputs to the fastn-p2p daemon */
+export interface Transport<Session = unknown> {
+  /** Resolves with the peer's response, rejects with its error */
+  call(protocol: unknown, input: unknown): Promise<unknown>;
+  connect(protocol: unknown, data: unknown): Promise<Session>;
+}
+";
+
+    pub(super) fn render(title: &str, defs: &[Def], methods: &[Method]) -> String {
+        let mut out = format!("// Generated by `fastn-p2p codegen` from {title:?}; do not edit.\n\n{TRANSPORT}");
+        for def in defs {
+            out.push('\n');
+            render_def(&mut out, def);
+        }
+        for method in methods {
+            out.push('\n');
+            render_method(&mut out, method);
+        }
+        out
+    }
+
+    fn render_def(out: &mut String, def: &Def) {
+        doc(out, "", def.doc.as_deref());
+        match &def.shape {
+            Shape::Struct(fields) => {
+                out.push_str(&format!("export interface {} {{\n", def.name));
+                for field in fields {
+                    doc(out, "  ", field.doc.as_deref());
+                    let optional = if field.required { "" } else { "?" };
+                    out.push_str(&format!("  {}{optional}: {};\n", property(&field.name), ty(&field.ty)));
+                }
+                out.push_str("}\n");
+            }
+            Shape::Enum(variants) => {
+                let variants: Vec<String> = variants.iter().map(|variant| format!("{variant:?}")).collect();
+                out.push_str(&format!("export type {} = {};\n", def.name, variants.join(" | ")));
+            }
+        }
+    }
+
+    fn render_method(out: &mut String, method: &Method) {
+        let name = camel_case(&method.words);
+        let protocol = method.description.protocol.to_string();
+        let input = ty(&method.input);
+        match &method.output {
+            Some(output) => {
+                let rejects = method.error.as_ref().map(|error| format!(", rejects with a {}", ty(error))).unwrap_or_default();
+                out.push_str(&format!("/** Call `{}`{rejects} */\n", method.description.name()));
+                out.push_str(&format!(
+                    "export function {name}(transport: Transport, input: {input}): Promise<{output}> {{\n  return transport.call({protocol}, input) as Promise<{output}>;\n}}\n",
+                    output = ty(output),
+                ));
+            }
+            None => {
+                out.push_str(&format!("/** Open a `{}` stream */\n", method.description.name()));
+                out.push_str(&format!(
+                    "export function {name}<Session>(transport: Transport<Session>, data: {input}): Promise<Session> {{\n  return transport.connect({protocol}, data);\n}}\n",
+                ));
+            }
+        }
+    }
+
+    fn ty(ty: &Ty) -> String {
+        match ty {
+            Ty::Any => "unknown".to_string(),
+            Ty::Unit => "null".to_string(),
+            Ty::Bool => "boolean".to_string(),
+            Ty::Int | Ty::UInt | Ty::Float => "number".to_string(),
+            Ty::String => "string".to_string(),
+            Ty::List(item) => format!("Array<{}>", self::ty(item)),
+            Ty::Map(value) => format!("Record<string, {}>", self::ty(value)),
+            Ty::Option(some) => format!("{} | null", self::ty(some)),
+            Ty::Named(name) => name.clone(),
+        }
+    }
+
+    /// Property names that aren't identifiers are quoted
+    fn property(name: &str) -> String {
+        let identifier = name.chars().next().is_some_and(|first| first.is_alphabetic() || first == '_' || first == '$')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '$');
+        if identifier { name.to_string() } else { format!("{name:?}") }
+    }
+
+    fn doc(out: &mut String, indent: &str, doc: Option<&str>) {
+        if let Some(doc) = doc {
+            out.push_str(&format!("{indent}/** {} */\n", doc.replace("*/", "*\\/").replace('\n', " ")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> serde_json::Value {
+        let send = crate::server::describe::MethodDescription {
+            protocol: serde_json::json!({"Mail": "send"}),
+            kind: crate::server::describe::MethodKind::Request,
+            input: serde_json::json!({
+                "title": "SendInput",
+                "type": "object",
+                "properties": {
+                    "to": { "type": "string", "description": "ID52 of the recipient" },
+                    "cc": { "type": ["string", "null"] },
+                    "priority": { "$ref": "#/$defs/Priority" },
+                    "type": { "type": "integer", "format": "uint32", "minimum": 0 },
+                },
+                "required": ["to", "priority", "type"],
+                "$defs": { "Priority": { "type": "string", "enum": ["low", "high"] } },
+            }),
+            output: Some(serde_json::json!({ "title": "Vec_of_string", "type": "array", "items": { "type": "string" } })),
+            error: Some(serde_json::json!({
+                "title": "MailError",
+                "type": "object",
+                "properties": { "message": { "type": "string" } },
+                "required": ["message"],
+            })),
+        };
+        let upload = crate::server::describe::MethodDescription {
+            protocol: serde_json::to_value(crate::server::CommandProtocol::new("files.fastn.com", "default", "put-file")).unwrap(),
+            kind: crate::server::describe::MethodKind::Stream,
+            input: serde_json::json!({ "type": "string" }),
+            output: None,
+            error: None,
+        };
+        crate::server::describe::document(&[send, upload], None)
+    }
+
+    #[test]
+    fn test_generate_rust() {
+        let code = generate(&document(), None, Lang::Rust).unwrap();
+
+        assert!(code.contains("pub struct SendInput {\n"));
+        assert!(code.contains("    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n    pub cc: Option<String>,\n"));
+        assert!(code.contains("    /// ID52 of the recipient\n    pub to: String,\n"));
+        assert!(code.contains("    pub priority: Priority,\n"));
+        assert!(code.contains("    pub r#type: u64,\n"));
+        assert!(code.contains("pub enum Priority {\n    #[serde(rename = \"low\")]\n    Low,\n"));
+        assert!(code.contains("pub async fn mail_send(\n"));
+        assert!(code.contains(") -> Result<Result<Vec<String>, MailError>, fastn_p2p_client::ClientError> {\n"));
+        assert!(code.contains("fastn_p2p_client::call(target, \"{\\\"Mail\\\":\\\"send\\\"}\", bind_alias, input)"));
+        assert!(code.contains("pub async fn put_file(\n"));
+        assert!(code.contains("    data: String,\n) -> Result<fastn_p2p_client::Session, fastn_p2p_client::ConnectionError>"));
+    }
+
+    #[test]
+    fn test_generate_typescript() {
+        let code = generate(&document(), Some("Mail"), Lang::TypeScript).unwrap();
+
+        assert!(code.contains("export interface SendInput {\n"));
+        assert!(code.contains("  cc?: string | null;\n"));
+        assert!(code.contains("  /** ID52 of the recipient */\n  to: string;\n"));
+        assert!(code.contains("export type Priority = \"low\" | \"high\";\n"));
+        assert!(code.contains("export function mailSend(transport: Transport, input: SendInput): Promise<Array<string>> {\n"));
+        assert!(code.contains("  return transport.call({\"Mail\":\"send\"}, input) as Promise<Array<string>>;\n"));
+        assert!(!code.contains("putFile"));
+
+        assert!(matches!(generate(&document(), Some("Chat"), Lang::TypeScript), Err(CodegenError::NoMethods { .. })));
+        assert!(matches!("go".parse::<Lang>(), Err(CodegenError::UnknownLang { .. })));
+    }
+}
@@ -0,0 +1,215 @@
+//! Status lines for people, as tracing events
+//!
+//! Library code never writes to stdout. What a person running the daemon
+//! wants to see, like the listening banner or a binding coming online, is
+//! logged at info level under [`TARGET`]; everything else uses the module's
+//! own target. Programs embedding the crate get both through whatever
+//! subscriber they install, or nothing.
+//!
+//! With the `console` feature (on by default), [`ConsoleLayer`] prints them
+//! the way the `fastn-p2p` binary does: [`TARGET`] lines as they are, other
+//! events on stderr prefixed by their level, filtered by [`Verbosity`]:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "console")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let layer = fastn_p2p::console::ConsoleLayer::new(fastn_p2p::console::Verbosity::Verbose);
+//! fastn_p2p::console::init(layer)?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "console"))]
+//! # fn main() {}
+//! ```
+
+/// Target of the lines the daemon shows by default
+pub const TARGET: &str = "fastn_p2p::console";
+
+/// How much [`ConsoleLayer`] shows; `-q` and `-v`/`-vv`/`-vvv` on the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Errors only
+    Quiet,
+    /// [`TARGET`] lines and warnings
+    #[default]
+    Normal,
+    /// Also info events of the fastn crates
+    Verbose,
+    /// Also their debug events
+    Debug,
+    /// Every event of every crate, iroh included
+    Trace,
+}
+
+impl Verbosity {
+    /// From the number of `-v` flags and whether `-q` was given
+    pub fn from_flags(verbose: u8, quiet: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, 2) => Verbosity::Debug,
+            (false, _) => Verbosity::Trace,
+        }
+    }
+
+    /// Whether an event or span with `metadata` is shown
+    pub fn shows(self, metadata: &tracing::Metadata<'_>) -> bool {
+        if metadata.target() == TARGET {
+            return self != Verbosity::Quiet;
+        }
+        let (max, fastn_only) = match self {
+            Verbosity::Quiet => (tracing::Level::ERROR, false),
+            Verbosity::Normal => (tracing::Level::WARN, false),
+            Verbosity::Verbose => (tracing::Level::INFO, true),
+            Verbosity::Debug => (tracing::Level::DEBUG, true),
+            Verbosity::Trace => (tracing::Level::TRACE, false),
+        };
+        *metadata.level() <= max
+            && (!fastn_only || *metadata.level() <= tracing::Level::WARN || metadata.target().starts_with("fastn"))
+    }
+}
+
+#[cfg(feature = "console")]
+pub use layer::{ConsoleLayer, init};
+
+#[cfg(feature = "console")]
+mod layer {
+    use super::{TARGET, Verbosity};
+
+    /// Prints events for people, see the module docs
+    #[derive(Debug, Clone, Copy)]
+    pub struct ConsoleLayer {
+        verbosity: Verbosity,
+        lines_to_stderr: bool,
+    }
+
+    impl ConsoleLayer {
+        pub fn new(verbosity: Verbosity) -> Self {
+            Self { verbosity, lines_to_stderr: false }
+        }
+
+        /// Print [`TARGET`] lines on stderr too, e.g. when stdout is for JSON
+        pub fn lines_to_stderr(mut self, lines_to_stderr: bool) -> Self {
+            self.lines_to_stderr = lines_to_stderr;
+            self
+        }
+
+        /// This layer with its [`Verbosity`] as a per-layer filter
+        ///
+        /// Events the layer would not print are then not even built, unless
+        /// another layer of the subscriber wants them.
+        pub fn filtered<S>(self) -> impl tracing_subscriber::Layer<S>
+        where
+            S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+        {
+            use tracing_subscriber::Layer;
+
+            let verbosity = self.verbosity;
+            self.with_filter(tracing_subscriber::filter::filter_fn(move |metadata| verbosity.shows(metadata)))
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for ConsoleLayer {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let metadata = event.metadata();
+
+            if metadata.target() == TARGET {
+                if self.lines_to_stderr {
+                    eprintln!("{}", fields);
+                } else {
+                    println!("{}", fields);
+                }
+                return;
+            }
+            match *metadata.level() {
+                tracing::Level::ERROR => eprintln!("❌ {}", fields),
+                tracing::Level::WARN => eprintln!("⚠️  {}", fields),
+                level => eprintln!("{:>5} {}: {}", level, metadata.target(), fields),
+            }
+        }
+    }
+
+    /// An event's message followed by its other fields as `name=value`
+    #[derive(Default)]
+    struct Fields {
+        message: String,
+        rest: Vec<String>,
+    }
+
+    impl tracing::field::Visit for Fields {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            if field.name() == "message" {
+                self.message = value.to_string();
+            } else {
+                self.rest.push(format!("{}={}", field.name(), value));
+            }
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = format!("{:?}", value);
+            } else {
+                self.rest.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+    }
+
+    impl std::fmt::Display for Fields {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.message)?;
+            for field in &self.rest {
+                write!(f, " {}", field)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Install a subscriber with only `layer`
+    pub fn init(layer: ConsoleLayer) -> Result<(), tracing_subscriber::util::TryInitError> {
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::util::SubscriberInitExt;
+
+        tracing_subscriber::registry().with(layer.filtered()).try_init()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbosity_filter() {
+        fn shows(verbosity: Verbosity, target: &str, level: tracing::Level) -> bool {
+            struct Callsite;
+            impl tracing::callsite::Callsite for Callsite {
+                fn set_interest(&self, _: tracing::subscriber::Interest) {}
+                fn metadata(&self) -> &tracing::Metadata<'_> {
+                    unreachable!()
+                }
+            }
+            static CALLSITE: Callsite = Callsite;
+            let fields = tracing::field::FieldSet::new(&[], tracing::callsite::Identifier(&CALLSITE));
+            let metadata = tracing::Metadata::new(
+                "event", target, level, None, None, None, fields, tracing::metadata::Kind::EVENT,
+            );
+            verbosity.shows(&metadata)
+        }
+
+        assert!(shows(Verbosity::Normal, TARGET, tracing::Level::INFO));
+        assert!(!shows(Verbosity::Quiet, TARGET, tracing::Level::INFO));
+        assert!(shows(Verbosity::Quiet, "iroh", tracing::Level::ERROR));
+        assert!(shows(Verbosity::Normal, "iroh", tracing::Level::WARN));
+        assert!(!shows(Verbosity::Normal, "fastn_p2p::server", tracing::Level::INFO));
+        assert!(shows(Verbosity::Verbose, "fastn_p2p::server", tracing::Level::INFO));
+        assert!(!shows(Verbosity::Verbose, "iroh", tracing::Level::INFO));
+        assert!(!shows(Verbosity::Debug, "fastn_net", tracing::Level::TRACE));
+        assert!(shows(Verbosity::Trace, "iroh", tracing::Level::TRACE));
+
+        assert_eq!(Verbosity::from_flags(0, false), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(2, false), Verbosity::Debug);
+        assert_eq!(Verbosity::from_flags(7, false), Verbosity::Trace);
+        assert_eq!(Verbosity::from_flags(3, true), Verbosity::Quiet);
+    }
+}
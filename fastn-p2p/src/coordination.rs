@@ -3,36 +3,188 @@
 //! This module encapsulates ALL graceful access and fastn_net::get_stream usage
 //! to ensure complete singleton access control.
 
-/// Coordination operation errors (internal to coordination module)
+/// Why a call or connect to a peer failed
+///
+/// Transport failures from fastn-net and iroh are sorted into the variants
+/// below (see [`CallError::from_net`]), so callers can tell an offline peer
+/// from one that refused the handshake without parsing messages.
 #[derive(Debug, thiserror::Error)]
-pub enum CoordinationError {
-    #[error("Connection failed: {source}")]
-    Connection { source: eyre::Error },
+pub enum CallError {
+    #[error("Peer is unreachable: {source}")]
+    PeerUnreachable {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 
-    #[error("Request/response error: {source}")]
-    RequestResponse { source: eyre::Error },
+    #[error("Peer rejected the handshake: {code:?}")]
+    HandshakeRejected { code: crate::handshake::HandshakeError },
 
-    #[error("Serialization error: {source}")]
-    Serialization { source: serde_json::Error },
+    #[error("Peer does not accept protocol {protocol}")]
+    ProtocolNotAccepted { protocol: serde_json::Value },
 
-    #[error("Endpoint error: {source}")]
-    Endpoint { source: eyre::Error },
+    #[error("Timed out waiting for the peer")]
+    Timeout,
 
-    #[error("Stream error: {source}")]
-    Stream { source: eyre::Error },
+    #[error("Message exceeds the limit of {limit} bytes")]
+    TooLarge { limit: usize },
 
-    #[error("Send error: {source}")]
-    Send { source: eyre::Error },
+    #[error("IO error: {source}")]
+    Io { source: std::io::Error },
 
-    #[error("Receive error: {source}")]
-    Receive { source: eyre::Error },
+    /// The peer sent something other than what the wire protocol expects
+    #[error("Unexpected reply from peer: {message}")]
+    Protocol { message: String },
+
+    #[error("Endpoint error: {source}")]
+    Endpoint {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("Serialization error: {source}")]
+    Serialization { source: serde_json::Error },
 
     #[error("Deserialization error: {source}")]
     Deserialization { source: serde_json::Error },
+
+    #[error("Relay refused the stream: {message}")]
+    Relay { message: String },
+
+    #[error("Primary refused the device stream: {message}")]
+    Device { message: String },
+
+    #[error("Response signature check failed: {source}")]
+    Signature { source: crate::signing::SignatureError },
+
+    #[error("Codec error: {source}")]
+    Codec { source: crate::codec::CodecError },
+
+    /// The server's auth hook refused the connection or request
+    #[error("Denied by the peer ({code}): {message}")]
+    Denied { code: String, message: String },
+
+    /// The server's auth hook wants the client to back off
+    #[error("Throttled by the peer, retry after {retry_after:?}: {message}")]
+    Throttled { retry_after: std::time::Duration, message: String },
+
+    #[error("Call is {preference} but the connection to the peer is {path}")]
+    PathNotAllowed {
+        preference: fastn_p2p_client::client::PathPreference,
+        path: crate::ping::PathType,
+    },
 }
 
-/// Type alias for coordination call results
-type CallError = CoordinationError;
+impl CallError {
+    /// Short machine-readable name of the variant, e.g. `"peer-unreachable"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CallError::PeerUnreachable { .. } => "peer-unreachable",
+            CallError::HandshakeRejected { .. } => "handshake-rejected",
+            CallError::ProtocolNotAccepted { .. } => "protocol-not-accepted",
+            CallError::Timeout => "timeout",
+            CallError::TooLarge { .. } => "too-large",
+            CallError::Io { .. } => "io",
+            CallError::Protocol { .. } => "protocol",
+            CallError::Endpoint { .. } => "endpoint",
+            CallError::Serialization { .. } => "serialization",
+            CallError::Deserialization { .. } => "deserialization",
+            CallError::Relay { .. } => "relay",
+            CallError::Device { .. } => "device",
+            CallError::Signature { .. } => "signature",
+            CallError::Codec { .. } => "codec",
+            CallError::PathNotAllowed { .. } => "path-not-allowed",
+            CallError::Denied { .. } => "denied",
+            CallError::Throttled { .. } => "throttled",
+        }
+    }
+
+    /// How long the peer asked to wait before trying again, if it throttled the call
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            CallError::Throttled { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// The error for a refusal from the server's auth hook
+    pub(crate) fn from_denial(denial: crate::handshake::AuthDenial) -> Self {
+        match denial.retry_after_ms {
+            Some(ms) => CallError::Throttled {
+                retry_after: std::time::Duration::from_millis(ms),
+                message: denial.message,
+            },
+            None => CallError::Denied { code: denial.code, message: denial.message },
+        }
+    }
+
+    /// Whether the peer (or the way to it) is failing, rather than this call
+    ///
+    /// Lost connections, timeouts and a peer refusing work because it is
+    /// full or broken count; a protocol it doesn't accept or a request too
+    /// large for it says nothing about the peer's health.
+    pub fn is_peer_failure(&self) -> bool {
+        match self {
+            CallError::PeerUnreachable { .. }
+            | CallError::Timeout
+            | CallError::Io { .. }
+            | CallError::Endpoint { .. } => true,
+            CallError::HandshakeRejected { code } => matches!(
+                code,
+                crate::handshake::HandshakeError::ServerFull | crate::handshake::HandshakeError::InternalError
+            ),
+            _ => false,
+        }
+    }
+
+    /// Sort an error from fastn-net or iroh into a variant
+    ///
+    /// A lost connection is `PeerUnreachable` (or `Timeout` if it idled out),
+    /// an oversized frame is `TooLarge`, unreadable JSON is `Deserialization`
+    /// and anything unrecognised is `Protocol`.
+    pub(crate) fn from_net(error: impl Into<eyre::Report>) -> Self {
+        let error = error.into();
+        if let Some(too_large) = error.downcast_ref::<fastn_net::errors::FrameTooLargeError>() {
+            return CallError::TooLarge { limit: too_large.limit };
+        }
+        if let Some(lost) = lost_connection(&error) {
+            return CallError::from_connection(lost.clone());
+        }
+        if let Some(stream) = error.downcast_ref::<iroh::endpoint::ReadError>() {
+            return CallError::Io { source: std::io::Error::other(stream.clone()) };
+        }
+        if let Some(stream) = error.downcast_ref::<iroh::endpoint::WriteError>() {
+            return CallError::Io { source: std::io::Error::other(stream.clone()) };
+        }
+        let error = match error.downcast::<std::io::Error>() {
+            Ok(source) if source.kind() == std::io::ErrorKind::TimedOut => return CallError::Timeout,
+            Ok(source) => return CallError::Io { source },
+            Err(error) => error,
+        };
+        match error.downcast::<serde_json::Error>() {
+            Ok(source) => CallError::Deserialization { source },
+            Err(error) => CallError::Protocol { message: error.to_string() },
+        }
+    }
+
+    fn from_connection(error: iroh::endpoint::ConnectionError) -> Self {
+        match error {
+            iroh::endpoint::ConnectionError::TimedOut => CallError::Timeout,
+            other => CallError::PeerUnreachable { source: Box::new(other) },
+        }
+    }
+}
+
+/// The connection error behind a failed connection, read or write, if any
+fn lost_connection(error: &eyre::Report) -> Option<&iroh::endpoint::ConnectionError> {
+    if let Some(connection) = error.downcast_ref::<iroh::endpoint::ConnectionError>() {
+        return Some(connection);
+    }
+    if let Some(iroh::endpoint::ReadError::ConnectionLost(connection)) = error.downcast_ref::<iroh::endpoint::ReadError>() {
+        return Some(connection);
+    }
+    match error.downcast_ref::<iroh::endpoint::WriteError>() {
+        Some(iroh::endpoint::WriteError::ConnectionLost(connection)) => Some(connection),
+        _ => None,
+    }
+}
 
 /// Global graceful shutdown coordinator (accessible within crate)
 pub(crate) static GRACEFUL: std::sync::LazyLock<fastn_net::Graceful> =
@@ -49,6 +201,26 @@ where
     GRACEFUL.spawn(task)
 }
 
+/// Run CPU-heavy or blocking work (hashing, compression, media decoding) off the async runtime
+///
+/// The closure runs on tokio's blocking pool, so handlers awaiting it don't
+/// stall other connections. Like [`spawn`], shutdown waits for it; long
+/// closures should check [`is_cancelled`] and return early.
+pub fn spawn_blocking<F, T>(task: F) -> tokio::task::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    GRACEFUL.spawn_blocking(task)
+}
+
+/// A group of tasks that ends with one piece of work, see [`fastn_net::Scope`]
+///
+/// Stream handlers get one as [`crate::Session::spawn`].
+pub fn scope() -> fastn_net::Scope {
+    GRACEFUL.scope()
+}
+
 /// Check for graceful shutdown signal
 ///
 /// This is the ONLY way to check for cancellation.
@@ -56,6 +228,29 @@ pub async fn cancelled() {
     GRACEFUL.cancelled().await
 }
 
+/// Whether shutdown wants running work to stop; for [`spawn_blocking`] closures
+pub fn is_cancelled() -> bool {
+    GRACEFUL.is_cancelled()
+}
+
+/// Resolves once shutdown has started and no new work should be accepted
+///
+/// Accept loops stop on this; in-flight handlers keep running until
+/// [`cancelled`] fires.
+pub async fn draining() {
+    GRACEFUL.draining().await
+}
+
+/// Stop accepting connections and streams without shutting down, see [`fastn_net::Graceful::stop_accepting`]
+pub async fn stop_accepting() {
+    GRACEFUL.stop_accepting().await
+}
+
+/// Whether [`draining`] has fired
+pub fn is_draining() -> bool {
+    GRACEFUL.is_draining()
+}
+
 /// Trigger graceful shutdown of all spawned tasks
 ///
 /// This is used by the main macro to initiate shutdown after user main completes
@@ -64,42 +259,147 @@ pub async fn shutdown() -> eyre::Result<()> {
     GRACEFUL.shutdown().await
 }
 
-/// Internal P2P call implementation with localized graceful access
-///
-/// This function contains the ONLY internal access to graceful for fastn_net compatibility.
-/// All P2P calls go through this function to maintain singleton access control.
-pub async fn internal_call<P, INPUT, OUTPUT, ERROR>(
-    sender: fastn_id52::SecretKey,
-    target: &fastn_id52::PublicKey,
-    protocol: P,
-    input: INPUT,
-) -> Result<Result<OUTPUT, ERROR>, CallError>
+/// Shut down now: stop accepting, wait up to `timeout` for in-flight handlers, then cancel the rest
+pub async fn shutdown_with_timeout(timeout: std::time::Duration) -> fastn_net::ShutdownReport {
+    GRACEFUL.shutdown_with_timeout(timeout).await
+}
+
+/// Run `hook` when shutdown reaches `phase`, e.g. to flush a store once handlers are done
+pub fn on_shutdown<F, Fut>(phase: fastn_net::ShutdownPhase, hook: F)
 where
-    P: serde::Serialize
-        + for<'de> serde::Deserialize<'de>
-        + Clone
-        + PartialEq
-        + std::fmt::Debug
-        + Send
-        + Sync
-        + 'static,
-    INPUT: serde::Serialize,
-    OUTPUT: for<'de> serde::Deserialize<'de>,
-    ERROR: for<'de> serde::Deserialize<'de>,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
 {
-    // First establish connection and do handshake
-    let endpoint = fastn_net::get_endpoint(sender.clone())
-        .await
-        .map_err(|source| CallError::Endpoint { source })?;
-    
+    GRACEFUL.on_shutdown(phase, hook)
+}
+
+/// An established connection to a peer with the handshake already completed
+///
+/// Held by [`crate::client::Client`] so repeated calls to the same peer skip
+/// connection setup and the ClientHello/ServerHello exchange.
+#[derive(Debug, Clone)]
+pub struct PeerConnection {
+    pub conn: iroh::endpoint::Connection,
+    /// Protocols the server accepted in ServerHello
+    pub accepted_protocols: Vec<serde_json::Value>,
+    /// Whether the server agreed to send tagged responses
+    pub tagged_responses: bool,
+    /// Token to present in ClientHello when reconnecting to this peer
+    pub resumption_token: Option<String>,
+    /// Accepted protocols whose sessions may exchange datagrams, see [`crate::datagram`]
+    pub datagram_protocols: Vec<serde_json::Value>,
+    /// Accepted protocols whose payloads are not JSON, see [`crate::codec`]
+    pub codecs: Vec<crate::codec::ProtocolCodec>,
+}
+
+impl PeerConnection {
+    /// Whether the server accepted this protocol during the handshake
+    pub fn accepts(&self, protocol_json: &serde_json::Value) -> bool {
+        self.accepted_protocols.contains(protocol_json)
+    }
+
+    /// The error for a protocol the server did not accept, warning if it accepted a similar one
+    pub fn not_accepted(&self, protocol_json: serde_json::Value) -> CallError {
+        if let Some(accepted) = crate::protocol_key::similar(&protocol_json, &self.accepted_protocols) {
+            tracing::warn!(
+                "Server did not accept protocol {} but accepted {}; check both sides serialize it the same way",
+                crate::protocol_key::to_key_string(&protocol_json),
+                crate::protocol_key::to_key_string(accepted),
+            );
+        }
+        CallError::ProtocolNotAccepted { protocol: protocol_json }
+    }
+
+    /// Whether sessions of this protocol may exchange datagrams
+    pub fn accepts_datagrams(&self, protocol_json: &serde_json::Value) -> bool {
+        self.datagram_protocols.contains(protocol_json)
+    }
+
+    /// The codec the server wants for this protocol's requests and responses
+    pub fn codec(&self, protocol_json: &serde_json::Value) -> crate::codec::Codec {
+        crate::codec::for_protocol(&self.codecs, protocol_json)
+    }
+
+    /// Whether the underlying QUIC connection has been closed
+    pub fn is_closed(&self) -> bool {
+        self.conn.close_reason().is_some()
+    }
+}
+
+/// How long a call with a path preference waits for the connection to take that path
+///
+/// Hole punching usually upgrades a relayed connection within a few seconds.
+pub const PATH_WAIT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Wait until `conn` travels a path `preference` allows, returning that path
+///
+/// Fails with [`CallError::PathNotAllowed`] if it doesn't within [`PATH_WAIT`].
+/// A relay-only call accepts a mixed path, which still sends through the relay.
+pub async fn require_path(
+    endpoint: &iroh::Endpoint,
+    conn: &iroh::endpoint::Connection,
+    preference: fastn_p2p_client::client::PathPreference,
+) -> Result<crate::ping::PathType, CallError> {
+    use crate::ping::PathType;
+    use fastn_p2p_client::client::PathPreference;
+
+    let deadline = tokio::time::Instant::now() + PATH_WAIT;
+    loop {
+        let path = PathType::of(endpoint, conn);
+        let allowed = match preference {
+            PathPreference::Any => true,
+            PathPreference::DirectOnly => path == PathType::Direct,
+            PathPreference::RelayOnly => matches!(path, PathType::Relay | PathType::Mixed),
+        };
+        if allowed {
+            return Ok(path);
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(CallError::PathNotAllowed { preference, path });
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+/// Connect to a peer and complete the handshake, offering the given protocols
+///
+/// Pass the token from a previous [`PeerConnection`] to resume that session.
+/// If `early_request` is given and the server answered it during the handshake,
+/// the raw response line is returned alongside the connection; `None` means the
+/// server predates early requests and the caller must send it normally. A
+/// chunked early response is put together up to `max_response_size` bytes.
+/// `hello` goes into ClientHello; if it's over the limits the call fails
+/// with [`CallError::TooLarge`] before connecting.
+pub async fn connect_peer(
+    endpoint: &iroh::Endpoint,
+    target: &fastn_id52::PublicKey,
+    protocols: Vec<serde_json::Value>,
+    resumption_token: Option<String>,
+    early_request: Option<crate::handshake::EarlyRequest>,
+    max_response_size: usize,
+    hello: &crate::handshake::HelloMetadata,
+) -> Result<(PeerConnection, Option<String>), CallError> {
+    let mut client_hello = crate::handshake::ClientHello::new(
+        "fastn-p2p-client",
+        env!("CARGO_PKG_VERSION")
+    )
+    .with_resumption_token(resumption_token)
+    .with_early_request(early_request)
+    .with_hello_metadata(hello)
+    .with_supported_codecs();
+    client_hello.supported_protocols = protocols;
+    if !client_hello.metadata_within_limits() {
+        return Err(CallError::TooLarge { limit: crate::handshake::MAX_HELLO_METADATA_BYTES });
+    }
+
     // Connect to target
     let target_node_id = iroh::NodeId::from(
         iroh::PublicKey::from_bytes(&target.to_bytes())
-            .map_err(|e| CallError::Stream { source: eyre::Error::from(e) })?
+            .map_err(|e| CallError::PeerUnreachable { source: Box::new(e) })?
     );
-    let conn = endpoint.connect(target_node_id, &fastn_net::APNS_IDENTITY)
+    let conn = endpoint.connect(target_node_id, fastn_net::APNS_IDENTITY)
         .await
-        .map_err(|e| CallError::Stream { source: eyre::Error::from(e) })?;
+        .map_err(|e| CallError::PeerUnreachable { source: Box::new(e) })?;
     
     // Send handshake first
     let handshake_protocol = fastn_net::Protocol::Generic(
@@ -107,135 +407,199 @@ where
     );
     
     let (mut hs_send, mut hs_recv) = conn.open_bi().await
-        .map_err(|e| CallError::Stream { source: eyre::Error::from(e) })?;
+        .map_err(CallError::from_net)?;
     
     // Send handshake protocol identifier
     let protocol_json = serde_json::to_string(&handshake_protocol)
         .map_err(|source| CallError::Serialization { source })?;
     hs_send.write_all(protocol_json.as_bytes()).await
-        .map_err(|e| CallError::Send { source: eyre::Error::from(e) })?;
+        .map_err(CallError::from_net)?;
     hs_send.write_all(b"\n").await
-        .map_err(|e| CallError::Send { source: eyre::Error::from(e) })?;
+        .map_err(CallError::from_net)?;
     
     // Wait for ACK
     let ack = fastn_net::next_string(&mut hs_recv).await
-        .map_err(|source| CallError::Receive { source })?;
+        .map_err(CallError::from_net)?;
     if ack != fastn_net::ACK {
-        return Err(CallError::Receive { 
-            source: eyre::anyhow!("Expected ACK, got: {}", ack) 
+        return Err(CallError::Protocol {
+            message: format!("Expected ACK, got: {}", ack),
         });
     }
     
     // Send ClientHello
-    let client_hello = crate::handshake::ClientHello::new(
-        "fastn-p2p-client",
-        env!("CARGO_PKG_VERSION")
-    ).with_protocol(&protocol);
-    
     let hello_json = serde_json::to_string(&client_hello)
         .map_err(|source| CallError::Serialization { source })?;
     hs_send.write_all(hello_json.as_bytes()).await
-        .map_err(|e| CallError::Send { source: eyre::Error::from(e) })?;
+        .map_err(CallError::from_net)?;
     hs_send.write_all(b"\n").await
-        .map_err(|e| CallError::Send { source: eyre::Error::from(e) })?;
+        .map_err(CallError::from_net)?;
     
     // Read ServerHello
     let server_hello: crate::handshake::ServerHello = fastn_net::next_json(&mut hs_recv).await
-        .map_err(|source| CallError::Receive { source })?;
+        .map_err(CallError::from_net)?;
     
     // Check if handshake succeeded
-    let accepted_protocols = match server_hello {
+    let (accepted_protocols, tagged_responses, resumption_token, early_response, datagram_protocols, codecs) = match server_hello {
         crate::handshake::ServerHello::Success { 
-            accepted_protocols, ..
-        } => accepted_protocols,
-        crate::handshake::ServerHello::Failure { code } => {
-            return Err(CallError::Receive { 
-                source: eyre::anyhow!("Server rejected handshake: {:?}", code)
-            });
+            accepted_protocols, tagged_responses, resumption_token, resumed, early_response, datagram_protocols, codecs, ..
+        } => {
+            tracing::debug!("Handshake with {} complete (resumed: {resumed})", target.id52());
+            (accepted_protocols, tagged_responses, resumption_token, early_response, datagram_protocols, codecs)
+        }
+        crate::handshake::ServerHello::Failure { denial: Some(denial), .. } => {
+            return Err(CallError::from_denial(denial));
+        }
+        crate::handshake::ServerHello::Failure { code, denial: None } => {
+            return Err(CallError::HandshakeRejected { code });
         }
     };
     
-    // Check if our protocol is accepted
-    let protocol_json = serde_json::to_value(&protocol)
-        .map_err(|e| CallError::Serialization { source: e })?;
-    if !accepted_protocols.contains(&protocol_json) {
-        return Err(CallError::Receive { 
-            source: eyre::anyhow!("Server doesn't support requested protocol")
-        });
-    }
+    // The early response, if any, follows ServerHello on the same stream
+    let early_response = if early_response {
+        let line = fastn_net::next_string(&mut hs_recv)
+            .await
+            .map_err(CallError::from_net)?;
+        Some(crate::wire::complete_response(line, &mut hs_recv, max_response_size).await?)
+    } else {
+        None
+    };
     
     hs_send.finish()
-        .map_err(|e| CallError::Send { source: eyre::Error::from(e) })?;
-    
-    // Now open the actual application protocol stream
-    let app_protocol = fastn_net::Protocol::Generic(serde_json::Value::String("fastn-p2p".to_string()));
-    
-    let (mut send_stream, mut recv_stream) = conn.open_bi().await
-        .map_err(|e| CallError::Stream { source: eyre::Error::from(e) })?;
+        .map_err(CallError::from_net)?;
+
+    let peer = PeerConnection {
+        conn,
+        accepted_protocols,
+        tagged_responses,
+        resumption_token,
+        datagram_protocols,
+        // Only what this build can decode, whatever the server sent
+        codecs: codecs.into_iter().filter(|entry| entry.codec.is_supported()).collect(),
+    };
+    Ok((peer, early_response))
+}
+
+/// Open a stream with the given stream header and send the wrapper request
+///
+/// Application streams use the `"fastn-p2p"` header; relayed streams use
+/// [`fastn_net::Protocol::Relay`] instead. No accepted-protocol check is done.
+pub async fn open_stream_with_header(
+    conn: &iroh::endpoint::Connection,
+    header: &fastn_net::Protocol,
+    wrapper_request: &crate::wire::WrapperRequest,
+) -> Result<
+    (
+        iroh::endpoint::SendStream,
+        fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    ),
+    CallError,
+> {
+    let (mut send_stream, recv_stream) = conn.open_bi().await
+        .map_err(CallError::from_net)?;
+    // FrameReader hands any bytes read past the last frame back through AsyncRead
+    let mut recv_stream = fastn_net::FrameReader::new(recv_stream);
     
-    // Send app protocol identifier  
-    let app_protocol_json = serde_json::to_string(&app_protocol)
+    // Send stream header
+    let header_json = serde_json::to_string(header)
         .map_err(|source| CallError::Serialization { source })?;
-    send_stream.write_all(app_protocol_json.as_bytes()).await
-        .map_err(|e| CallError::Send { source: eyre::Error::from(e) })?;
+    send_stream.write_all(header_json.as_bytes()).await
+        .map_err(CallError::from_net)?;
     send_stream.write_all(b"\n").await
-        .map_err(|e| CallError::Send { source: eyre::Error::from(e) })?;
+        .map_err(CallError::from_net)?;
     
     // Wait for ACK
-    let ack = fastn_net::next_string(&mut recv_stream).await
-        .map_err(|source| CallError::Receive { source })?;
+    let ack = recv_stream.next_string().await
+        .map_err(CallError::from_net)?;
     if ack != fastn_net::ACK {
-        return Err(CallError::Receive { 
-            source: eyre::anyhow!("Expected ACK for app protocol, got: {}", ack) 
+        return Err(CallError::Protocol {
+            message: format!("Expected ACK for {}, got: {}", header, ack),
         });
     }
 
-    // Convert user protocol to JSON for embedding in request
-    let protocol_json =
-        serde_json::to_value(&protocol).map_err(|e| CallError::Serialization { source: e })?;
-
-    // Create wrapper request with protocol and data
-    let wrapper_request = serde_json::json!({
-        "protocol": protocol_json,
-        "data": input
-    });
-    let request_json = serde_json::to_string(&wrapper_request)
+    let request_json = serde_json::to_string(wrapper_request)
         .map_err(|source| CallError::Serialization { source })?;
 
     // Send JSON followed by newline
     send_stream
         .write_all(request_json.as_bytes())
         .await
-        .map_err(|e| CallError::Send {
-            source: eyre::Error::from(e),
-        })?;
+        .map_err(CallError::from_net)?;
     send_stream
         .write_all(b"\n")
         .await
-        .map_err(|e| CallError::Send {
-            source: eyre::Error::from(e),
-        })?;
+        .map_err(CallError::from_net)?;
 
-    // Receive and deserialize response
-    // We use next_string here because we need to try deserializing as two different types
-    let response_json = fastn_net::next_string(&mut recv_stream)
-        .await
-        .map_err(|source| CallError::Receive { source })?;
+    Ok((send_stream, recv_stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_net_errors_map_to_call_errors() {
+        let too_large = fastn_net::errors::FrameTooLargeError { limit: fastn_net::MAX_FRAME_LEN };
+        assert!(matches!(CallError::from_net(too_large), CallError::TooLarge { limit } if limit == fastn_net::MAX_FRAME_LEN));
 
-    // Try to deserialize as success response first
-    if let Ok(success_response) = serde_json::from_str::<OUTPUT>(&response_json) {
-        return Ok(Ok(success_response));
+        // A dropped connection means the peer is gone, however it surfaced
+        assert!(matches!(
+            CallError::from_net(iroh::endpoint::ConnectionError::TimedOut),
+            CallError::Timeout
+        ));
+        assert!(matches!(
+            CallError::from_net(iroh::endpoint::ReadError::ConnectionLost(iroh::endpoint::ConnectionError::Reset)),
+            CallError::PeerUnreachable { .. }
+        ));
+
+        let closed = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed while reading response header");
+        assert!(matches!(CallError::from_net(closed), CallError::Io { .. }));
+        let invalid = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert!(matches!(CallError::from_net(invalid), CallError::Deserialization { .. }));
+
+        let unexpected = CallError::from_net(eyre::anyhow!("Expected ACK, got: nope"));
+        assert_eq!(unexpected.kind(), "protocol");
+        assert_eq!(
+            CallError::HandshakeRejected { code: crate::handshake::HandshakeError::Unauthorized }.kind(),
+            "handshake-rejected"
+        );
+
+        assert!(CallError::Timeout.is_peer_failure());
+        assert!(CallError::HandshakeRejected { code: crate::handshake::HandshakeError::ServerFull }.is_peer_failure());
+        assert!(!CallError::HandshakeRejected { code: crate::handshake::HandshakeError::Unauthorized }.is_peer_failure());
+        assert!(!CallError::ProtocolNotAccepted { protocol: serde_json::json!("Echo") }.is_peer_failure());
     }
 
-    // If that fails, try to deserialize as ERROR type
-    if let Ok(error_response) = serde_json::from_str::<ERROR>(&response_json) {
-        return Ok(Err(error_response));
+    #[test]
+    fn test_path_not_allowed() {
+        let error = CallError::PathNotAllowed {
+            preference: fastn_p2p_client::client::PathPreference::DirectOnly,
+            path: crate::ping::PathType::Relay,
+        };
+        assert_eq!(error.to_string(), "Call is direct-only but the connection to the peer is relay");
+        assert_eq!(error.kind(), "path-not-allowed");
+        // Another path may work where this one didn't, the peer is fine
+        assert!(!error.is_peer_failure());
+        assert_eq!("relay-only".parse(), Ok(fastn_p2p_client::client::PathPreference::RelayOnly));
+        assert!("tor".parse::<fastn_p2p_client::client::PathPreference>().is_err());
     }
 
-    // If both fail, it's a deserialization error
-    Err(CallError::Deserialization {
-        source: serde_json::Error::io(std::io::Error::other(format!(
-            "Response doesn't match expected OUTPUT or ERROR types: {response_json}"
-        ))),
-    })
+    #[test]
+    fn test_denials_map_to_call_errors() {
+        let denied = CallError::from_denial(crate::handshake::AuthDenial {
+            code: "expired-token".to_string(),
+            message: "Log in again".to_string(),
+            retry_after_ms: None,
+        });
+        assert_eq!(denied.kind(), "denied");
+        assert_eq!(denied.to_string(), "Denied by the peer (expired-token): Log in again");
+        assert_eq!(denied.retry_after(), None);
+
+        let throttled = CallError::from_denial(crate::handshake::AuthDenial {
+            code: "throttled".to_string(),
+            message: "Slow down".to_string(),
+            retry_after_ms: Some(1500),
+        });
+        assert_eq!(throttled.retry_after(), Some(std::time::Duration::from_millis(1500)));
+        assert!(!throttled.is_peer_failure());
+    }
 }
@@ -0,0 +1,195 @@
+//! Unreliable datagrams alongside a streaming session
+//!
+//! Real-time protocols (audio, video, game state) would rather lose a packet
+//! than wait for its retransmission, which is what a reliable stream does to
+//! everything queued behind it. Sessions of protocols the server enabled with
+//! [`crate::server::ServerBuilder::enable_datagrams`] can also exchange QUIC
+//! datagrams: unordered, unacknowledged and limited to
+//! [`Datagrams::max_size`] bytes.
+//!
+//! Datagrams belong to the QUIC connection, which a client shares between all
+//! its sessions with a peer, so each one starts with the id of its session's
+//! stream ([`HEADER_LEN`] bytes, big endian) and one task per connection hands
+//! incoming datagrams to the matching session. Datagrams for sessions that are
+//! gone, or that arrive while a session's buffer is full, are dropped.
+
+/// Bytes in front of every datagram naming its session
+pub const HEADER_LEN: usize = 8;
+
+/// Datagrams buffered per session before new ones are dropped
+const INCOMING_BUFFER: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DatagramError {
+    #[error("Datagrams are not enabled for this session's protocol")]
+    NotEnabled,
+
+    #[error("Peer does not accept datagrams")]
+    Unsupported,
+
+    #[error("Datagram of {size} bytes exceeds the maximum of {max}")]
+    TooLarge { size: usize, max: usize },
+
+    #[error("Failed to send datagram: {source}")]
+    Send {
+        #[source]
+        source: iroh::endpoint::SendDatagramError,
+    },
+
+    #[error("Connection closed")]
+    Closed,
+}
+
+/// One session's datagrams
+#[derive(Debug)]
+pub struct Datagrams {
+    id: u64,
+    router: std::sync::Arc<Router>,
+    incoming: tokio::sync::mpsc::Receiver<Vec<u8>>,
+}
+
+impl Datagrams {
+    /// Datagrams of the session on `stream`, a stream of `conn`
+    pub(crate) fn open(conn: &iroh::endpoint::Connection, stream: &iroh::endpoint::SendStream) -> Self {
+        let id = iroh::endpoint::VarInt::from(stream.id()).into_inner();
+        let router = Router::for_connection(conn);
+        let (sender, incoming) = tokio::sync::mpsc::channel(INCOMING_BUFFER);
+        router.routes.lock().expect("datagram routes lock poisoned").insert(id, sender);
+        Self { id, router, incoming }
+    }
+
+    /// Largest payload that can be sent right now; `None` if the peer takes no datagrams
+    ///
+    /// Depends on the path MTU, so it can change over the life of the connection.
+    pub fn max_size(&self) -> Option<usize> {
+        self.router.conn.max_datagram_size().map(|max| max.saturating_sub(HEADER_LEN))
+    }
+
+    /// Send `payload` as one datagram; it may be lost, duplicated or reordered
+    pub fn send(&self, payload: &[u8]) -> Result<(), DatagramError> {
+        let max = self.max_size().ok_or(DatagramError::Unsupported)?;
+        if payload.len() > max {
+            return Err(DatagramError::TooLarge { size: payload.len(), max });
+        }
+        self.router
+            .conn
+            .send_datagram(frame(self.id, payload).into())
+            .map_err(|source| DatagramError::Send { source })
+    }
+
+    /// Wait for the next datagram from the peer
+    pub async fn recv(&mut self) -> Result<Vec<u8>, DatagramError> {
+        self.incoming.recv().await.ok_or(DatagramError::Closed)
+    }
+}
+
+impl Drop for Datagrams {
+    fn drop(&mut self) {
+        self.router.routes.lock().expect("datagram routes lock poisoned").remove(&self.id);
+    }
+}
+
+/// Sessions with datagrams on one connection
+#[derive(Debug)]
+struct Router {
+    conn: iroh::endpoint::Connection,
+    routes: std::sync::Mutex<std::collections::HashMap<u64, tokio::sync::mpsc::Sender<Vec<u8>>>>,
+    /// Stops the routing task once no session is left
+    stop: tokio_util::sync::CancellationToken,
+}
+
+/// Routers by [`iroh::endpoint::Connection::stable_id`]
+///
+/// A live router holds its connection, so a live entry's id can't be reused
+/// by another connection.
+static ROUTERS: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<usize, std::sync::Weak<Router>>>> =
+    std::sync::LazyLock::new(Default::default);
+
+impl Router {
+    /// The router of `conn`, started on first use
+    fn for_connection(conn: &iroh::endpoint::Connection) -> std::sync::Arc<Self> {
+        let mut routers = ROUTERS.lock().expect("datagram routers lock poisoned");
+        if let Some(router) = routers.get(&conn.stable_id()).and_then(std::sync::Weak::upgrade) {
+            return router;
+        }
+        routers.retain(|_, router| router.strong_count() > 0);
+
+        let router = std::sync::Arc::new(Self {
+            conn: conn.clone(),
+            routes: Default::default(),
+            stop: tokio_util::sync::CancellationToken::new(),
+        });
+        routers.insert(conn.stable_id(), std::sync::Arc::downgrade(&router));
+        crate::spawn(route(std::sync::Arc::downgrade(&router), conn.clone(), router.stop.clone()));
+        router
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// Hand `conn`'s incoming datagrams to their sessions until it closes or `stop`
+async fn route(
+    router: std::sync::Weak<Router>,
+    conn: iroh::endpoint::Connection,
+    stop: tokio_util::sync::CancellationToken,
+) {
+    loop {
+        let datagram = tokio::select! {
+            datagram = conn.read_datagram() => datagram,
+            _ = stop.cancelled() => return,
+            _ = crate::cancelled() => return,
+        };
+        let Some(router) = router.upgrade() else { return };
+        let mut routes = router.routes.lock().expect("datagram routes lock poisoned");
+        let datagram = match datagram {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                tracing::debug!("Stopped routing datagrams: {}", e);
+                // Sessions waiting in recv() see the connection is gone
+                routes.clear();
+                return;
+            }
+        };
+        let Some((id, payload)) = parse(&datagram) else {
+            tracing::debug!("Dropped a {} byte datagram without a session id", datagram.len());
+            continue;
+        };
+        if let Some(session) = routes.get(&id) {
+            // Full or closed: dropping it is what datagrams are for
+            let _ = session.try_send(payload.to_vec());
+        }
+    }
+}
+
+/// `payload` prefixed with the session id
+fn frame(id: u64, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_LEN + payload.len());
+    datagram.extend_from_slice(&id.to_be_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// Split a datagram into its session id and payload
+fn parse(datagram: &[u8]) -> Option<(u64, &[u8])> {
+    let (id, payload) = datagram.split_first_chunk::<HEADER_LEN>()?;
+    Some((u64::from_be_bytes(*id), payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let datagram = frame(4, b"audio");
+        assert_eq!(datagram.len(), HEADER_LEN + 5);
+        assert_eq!(parse(&datagram), Some((4, &b"audio"[..])));
+
+        assert_eq!(parse(&frame(u64::MAX, b"")), Some((u64::MAX, &b""[..])));
+        assert_eq!(parse(b"short"), None);
+    }
+}
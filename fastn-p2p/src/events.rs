@@ -0,0 +1,56 @@
+//! Process-wide bus of server events, for dashboards and the daemon
+//!
+//! Servers publish peers connecting and leaving and every request handled;
+//! the daemon adds identity and binding changes. Protocols publish an
+//! [`EventKind::Notification`] when the user should hear about something,
+//! which the daemon turns into a desktop notification or a command run. Anyone in the process can
+//! [`subscribe`]; the daemon streams the bus to control socket clients that
+//! send `subscribe-events`, see [`fastn_p2p_client::events`].
+//!
+//! Publishing costs next to nothing while nobody subscribes.
+
+// Shared with clients reading `subscribe-events`
+pub use fastn_p2p_client::events::{DaemonEvent, EventKind};
+
+/// Events buffered per subscriber before it starts missing them
+pub const EVENT_BUFFER: usize = 1024;
+
+static BUS: std::sync::LazyLock<tokio::sync::broadcast::Sender<DaemonEvent>> =
+    std::sync::LazyLock::new(|| tokio::sync::broadcast::channel(EVENT_BUFFER).0);
+
+/// Send `kind` to every current subscriber
+pub fn publish(kind: EventKind) {
+    if BUS.receiver_count() == 0 {
+        return;
+    }
+    // Subscribers leaving in between is no error
+    let _ = BUS.send(DaemonEvent::now(kind));
+}
+
+/// Receive events published from now on
+///
+/// A receiver more than [`EVENT_BUFFER`] events behind gets
+/// `RecvError::Lagged` with the number it missed.
+pub fn subscribe() -> tokio::sync::broadcast::Receiver<DaemonEvent> {
+    BUS.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn test_published_events_reach_subscribers() {
+        let mut events = super::subscribe();
+        let kind = super::EventKind::IdentityStateChanged { identity: "alice".to_string(), online: true };
+        super::publish(kind.clone());
+        // Other tests publish on the same bus
+        loop {
+            if events.recv().await.unwrap().kind == kind {
+                break;
+            }
+        }
+
+        let line = serde_json::to_value(super::DaemonEvent::now(kind)).unwrap();
+        assert_eq!(line["event"], "identity-state-changed");
+        assert!(line["at_ms"].as_u64().unwrap() > 0);
+    }
+}
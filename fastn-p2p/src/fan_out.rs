@@ -0,0 +1,138 @@
+//! The same call to many peers at once
+//!
+//! Presence checks, distributed search and quorum writes send one request to
+//! a list of peers and act on the answers as they come in.
+//! [`crate::client::Client::call_many`] makes those calls with at most
+//! [`FanOut::with_concurrency`] in flight, gives each one
+//! [`FanOut::with_timeout`] to answer, and yields one [`PeerReply`] per peer
+//! in the order the answers arrive:
+//!
+//! ```rust,no_run
+//! use futures_util::StreamExt;
+//! # use fastn_p2p::fan_out::FanOut;
+//! # #[derive(serde::Serialize)]
+//! # enum Store { Put }
+//! # async fn run(client: fastn_p2p::client::Client, replicas: Vec<fastn_p2p::PublicKey>, entry: String) -> Result<(), fastn_p2p::client::CallError> {
+//!
+//! let fan_out = FanOut::default().with_timeout(std::time::Duration::from_secs(2));
+//! let mut replies = client.call_many::<_, _, (), String>(replicas.iter().copied(), Store::Put, entry, fan_out)?;
+//!
+//! // Quorum write: done once a majority stored it; dropping the stream cancels the rest
+//! let mut stored = 0;
+//! while let Some(reply) = replies.next().await {
+//!     if matches!(reply.result, Ok(Ok(()))) {
+//!         stored += 1;
+//!         if stored > replicas.len() / 2 { break; }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`FanOutReport::collect`] waits for every peer instead and sorts the
+//! answers from the peers that could not be reached.
+
+/// Calls in flight at once unless [`FanOut::with_concurrency`] says otherwise
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+/// How [`crate::client::Client::call_many`] spreads its calls
+#[derive(Debug, Clone, Copy)]
+pub struct FanOut {
+    pub(crate) concurrency: usize,
+    pub(crate) timeout: Option<std::time::Duration>,
+}
+
+impl Default for FanOut {
+    fn default() -> Self {
+        Self { concurrency: DEFAULT_CONCURRENCY, timeout: None }
+    }
+}
+
+impl FanOut {
+    /// Make at most `concurrency` calls at once (at least one)
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Give each peer `timeout` to answer; later answers count as [`crate::client::CallError::Timeout`]
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// One peer's outcome of a fan-out call
+#[derive(Debug)]
+pub struct PeerReply<OUTPUT, ERROR> {
+    pub peer: fastn_id52::PublicKey,
+    /// Outer error: the peer was not reached or timed out; inner: its handler's answer
+    pub result: Result<Result<OUTPUT, ERROR>, crate::client::CallError>,
+}
+
+/// Replies of a fan-out call, in the order they arrive
+pub type Replies<OUTPUT, ERROR> =
+    std::pin::Pin<Box<dyn futures_core::Stream<Item = PeerReply<OUTPUT, ERROR>> + Send>>;
+
+/// Every peer's outcome of a fan-out call, see [`FanOutReport::collect`]
+#[derive(Debug)]
+pub struct FanOutReport<OUTPUT, ERROR> {
+    /// Peers whose handler answered, with its `Ok` or `Err`
+    pub answered: Vec<(fastn_id52::PublicKey, Result<OUTPUT, ERROR>)>,
+    /// Peers that were not reached, timed out or answered with something unreadable
+    pub failed: Vec<(fastn_id52::PublicKey, crate::client::CallError)>,
+}
+
+impl<OUTPUT, ERROR> FanOutReport<OUTPUT, ERROR> {
+    /// Wait for every reply
+    pub async fn collect(mut replies: Replies<OUTPUT, ERROR>) -> Self {
+        use futures_util::StreamExt;
+
+        let mut report = Self { answered: Vec::new(), failed: Vec::new() };
+        while let Some(reply) = replies.next().await {
+            match reply.result {
+                Ok(answer) => report.answered.push((reply.peer, answer)),
+                Err(e) => report.failed.push((reply.peer, e)),
+            }
+        }
+        report
+    }
+
+    /// Answers whose handler returned `Ok`
+    pub fn outputs(&self) -> impl Iterator<Item = (&fastn_id52::PublicKey, &OUTPUT)> {
+        self.answered.iter().filter_map(|(peer, answer)| answer.as_ref().ok().map(|output| (peer, output)))
+    }
+
+    /// Whether every peer was reached and answered, `Ok` or `Err`
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_separates_failures() {
+        let peers: Vec<_> = (0..3).map(|_| fastn_id52::SecretKey::generate().public_key()).collect();
+        let replies: Replies<u32, String> = Box::pin(futures_util::stream::iter(vec![
+            PeerReply { peer: peers[0], result: Ok(Ok(7)) },
+            PeerReply { peer: peers[1], result: Err(crate::client::CallError::Timeout) },
+            PeerReply { peer: peers[2], result: Ok(Err("busy".to_string())) },
+        ]));
+
+        let report = FanOutReport::collect(replies).await;
+        assert!(!report.is_complete());
+        assert_eq!(report.answered.len(), 2);
+        assert_eq!(report.outputs().collect::<Vec<_>>(), vec![(&peers[0], &7)]);
+        assert_eq!(report.failed[0].0, peers[1]);
+        assert!(matches!(report.failed[0].1, crate::client::CallError::Timeout));
+    }
+
+    #[test]
+    fn test_concurrency_is_at_least_one() {
+        assert_eq!(FanOut::default().with_concurrency(0).concurrency, 1);
+        assert_eq!(FanOut::default().concurrency, DEFAULT_CONCURRENCY);
+    }
+}
@@ -0,0 +1,17 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`
+//!
+//! Only built under `cargo fuzz`, which sets `--cfg fuzzing`, so the wire
+//! types can stay private to the crate.
+
+/// Parse `data` as each handshake message
+pub fn handshake(data: &[u8]) {
+    let _ = serde_json::from_slice::<crate::handshake::ClientHello>(data);
+    let _ = serde_json::from_slice::<crate::handshake::ServerHello>(data);
+}
+
+/// Parse `data` as a wrapped request, then its protocol as a serve_all command
+pub fn wrapper_request(data: &[u8]) {
+    if let Ok(wrapper) = serde_json::from_slice::<crate::wire::WrapperRequest>(data) {
+        let _ = serde_json::from_value::<crate::server::CommandProtocol>(wrapper.protocol);
+    }
+}
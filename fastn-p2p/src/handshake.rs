@@ -1,7 +1,7 @@
-/// Handshake protocol for fastn-p2p connections
-/// 
-/// Every connection must complete a handshake before any application protocols can be used.
-/// This allows for authentication, protocol negotiation, and client information exchange.
+//! Handshake protocol for fastn-p2p connections
+//! 
+//! Every connection must complete a handshake before any application protocols can be used.
+//! This allows for authentication, protocol negotiation, and client information exchange.
 
 use serde::{Deserialize, Serialize};
 
@@ -19,10 +19,50 @@ pub enum HandshakeError {
     ServerFull,
     /// Internal server error
     InternalError,
+    /// `ClientHello::metadata` is over [`MAX_HELLO_METADATA_ENTRIES`] or [`MAX_HELLO_METADATA_BYTES`]
+    MetadataTooLarge,
+    /// The connection auth hook wants the client to back off, see `AuthDenial::retry_after_ms`
+    Throttled,
 }
 
+/// Why the server's auth hook refused, see [`crate::server::AuthDecision`]
+///
+/// Sent with a failed ServerHello, or in place of a response when stream
+/// auth refuses a request (see [`crate::wire::DeniedResponse`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthDenial {
+    /// Machine-readable reason, e.g. `"expired-token"`
+    pub code: String,
+    /// For the user
+    pub message: String,
+    /// Set when the client is throttled: try again after this many milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+/// Most entries a ClientHello may carry in `metadata`
+pub const MAX_HELLO_METADATA_ENTRIES: usize = 32;
+
+/// Most bytes of keys and values together a ClientHello may carry in `metadata`
+pub const MAX_HELLO_METADATA_BYTES: usize = 4096;
+
+/// `metadata` key for the client's build, e.g. a commit hash
+pub const CLIENT_BUILD: &str = "client-build";
+
+/// `metadata` key for a human-readable name of the client's device
+pub const DEVICE_NAME: &str = "device-name";
+
+/// `metadata` key for a [`crate::capability::CapabilityToken`]
+pub const CAPABILITY: &str = "capability";
+
+/// `metadata` key the server sets to the relay's ID52 when asking auth hooks
+/// about the sender of a relayed stream, see [`ClientHello::relayed_by`]
+pub const RELAYED_BY: &str = "relayed-by";
+
 /// Client's initial handshake message
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// `Debug` leaves out tokens, the capability in `metadata` and the early request's data.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ClientHello {
     /// Client application name (e.g., "malai", "fastn-cli")
     pub client_name: String,
@@ -35,6 +75,90 @@ pub struct ClientHello {
     
     /// Optional authentication token
     pub auth_token: Option<String>,
+
+    /// Client understands tagged `{"status": "ok" | "err", "data": ...}` responses
+    #[serde(default)]
+    pub tagged_responses: bool,
+
+    /// Token from a previous ServerHello, to restore that session's protocols
+    ///
+    /// The handshake still takes its full round trip, see [`crate::server::resumption`].
+    #[serde(default)]
+    pub resumption_token: Option<String>,
+
+    /// First request, sent along with the hello to save a stream round trip
+    ///
+    /// Servers that support this answer it on the handshake stream right after
+    /// ServerHello; older servers ignore it and the client resends it normally.
+    #[serde(default)]
+    pub early_request: Option<EarlyRequest>,
+
+    /// Free-form information about the client for the server's auth hooks
+    ///
+    /// Keys this crate knows are [`CLIENT_BUILD`] and [`DEVICE_NAME`]; others
+    /// are up to the application. Servers reject hellos over the size limits
+    /// with [`HandshakeError::MetadataTooLarge`]. Like every hello field it is
+    /// optional: servers and clients skip fields they don't know.
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub metadata: std::collections::BTreeMap<String, String>,
+
+    /// Codecs besides JSON the client can use, see [`crate::codec`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codecs: Vec<crate::codec::Codec>,
+}
+
+/// Request/response call piggybacked on ClientHello
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EarlyRequest {
+    pub protocol: serde_json::Value,
+    pub data: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<fastn_net::TraceContext>,
+    /// See [`crate::wire::WrapperRequest`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    /// Present in signed mode, see [`crate::signing`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<crate::signing::PayloadSignature>,
+}
+
+impl std::fmt::Debug for ClientHello {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let metadata: std::collections::BTreeMap<&str, &dyn std::fmt::Debug> = self
+            .metadata
+            .iter()
+            .map(|(key, value)| {
+                let value: &dyn std::fmt::Debug = match key.as_str() {
+                    CAPABILITY => &fastn_p2p_client::Sensitive(()),
+                    _ => value,
+                };
+                (key.as_str(), value)
+            })
+            .collect();
+        f.debug_struct("ClientHello")
+            .field("client_name", &self.client_name)
+            .field("client_version", &self.client_version)
+            .field("supported_protocols", &self.supported_protocols)
+            .field("auth_token", &self.auth_token.as_ref().map(fastn_p2p_client::Sensitive))
+            .field("tagged_responses", &self.tagged_responses)
+            .field("resumption_token", &self.resumption_token.as_ref().map(fastn_p2p_client::Sensitive))
+            .field("early_request", &self.early_request)
+            .field("metadata", &metadata)
+            .field("codecs", &self.codecs)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for EarlyRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EarlyRequest")
+            .field("protocol", &self.protocol)
+            .field("data", &fastn_p2p_client::Sensitive(&self.data))
+            .field("trace", &self.trace)
+            .field("deadline_ms", &self.deadline_ms)
+            .field("signature", &self.signature)
+            .finish()
+    }
 }
 
 /// Server's response to ClientHello
@@ -50,10 +174,41 @@ pub enum ServerHello {
         
         /// Protocols accepted by server (subset of client's list)
         accepted_protocols: Vec<serde_json::Value>,
+
+        /// Server will send tagged responses (only if the client asked for them)
+        #[serde(default)]
+        tagged_responses: bool,
+
+        /// Token the client can present on reconnect to resume this session
+        #[serde(default)]
+        resumption_token: Option<String>,
+
+        /// Whether the client's resumption token was accepted
+        #[serde(default)]
+        resumed: bool,
+
+        /// The response to `ClientHello::early_request` follows on this stream
+        ///
+        /// It is answered like a request on a stream of its own, in the
+        /// protocol's negotiated codec and signed if the request was.
+        #[serde(default)]
+        early_response: bool,
+
+        /// Accepted protocols whose sessions may also exchange datagrams
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        datagram_protocols: Vec<serde_json::Value>,
+
+        /// Accepted protocols whose payloads are not JSON, see [`crate::codec`]
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        codecs: Vec<crate::codec::ProtocolCodec>,
     },
     Failure {
         /// Error code for programmatic handling
         code: HandshakeError,
+
+        /// Why the connection auth hook refused, when it did
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        denial: Option<AuthDenial>,
     },
 }
 
@@ -70,11 +225,36 @@ impl ClientHello {
             client_version: client_version.into(),
             supported_protocols: Vec::new(),
             auth_token: None,
+            tagged_responses: true,
+            resumption_token: None,
+            early_request: None,
+            metadata: std::collections::BTreeMap::new(),
+            codecs: Vec::new(),
         }
     }
     
+    /// The hello auth hooks see for the sender of a stream relayed by `relay`
+    ///
+    /// `self` is the relay's own hello. Its client name, version and protocols
+    /// stay, but its credentials don't: no auth token, capability or other
+    /// metadata, resumption token or early request. [`RELAYED_BY`] names the
+    /// relay instead.
+    pub fn relayed_by(&self, relay: &fastn_id52::PublicKey) -> Self {
+        Self {
+            client_name: self.client_name.clone(),
+            client_version: self.client_version.clone(),
+            supported_protocols: self.supported_protocols.clone(),
+            auth_token: None,
+            tagged_responses: self.tagged_responses,
+            resumption_token: None,
+            early_request: None,
+            metadata: std::collections::BTreeMap::from([(RELAYED_BY.to_string(), relay.id52())]),
+            codecs: self.codecs.clone(),
+        }
+    }
+
     pub fn with_protocol(mut self, protocol: impl Serialize) -> Self {
-        if let Ok(json) = serde_json::to_value(protocol) {
+        if let Ok(json) = crate::protocol_key::of(&protocol) {
             self.supported_protocols.push(json);
         }
         self
@@ -84,6 +264,116 @@ impl ClientHello {
         self.auth_token = Some(token);
         self
     }
+    
+    pub fn with_resumption_token(mut self, token: Option<String>) -> Self {
+        self.resumption_token = token;
+        self
+    }
+    
+    pub fn with_early_request(mut self, request: Option<EarlyRequest>) -> Self {
+        self.early_request = request;
+        self
+    }
+
+    /// Put every protocol in canonical form, see [`crate::protocol_key`]
+    pub fn canonicalize_protocols(&mut self) {
+        for protocol in &mut self.supported_protocols {
+            *protocol = crate::protocol_key::canonicalize(std::mem::take(protocol));
+        }
+        if let Some(early) = &mut self.early_request {
+            early.protocol = crate::protocol_key::canonicalize(std::mem::take(&mut early.protocol));
+        }
+    }
+
+    /// Offer every codec besides JSON this build supports
+    pub fn with_supported_codecs(mut self) -> Self {
+        self.codecs = crate::codec::Codec::supported()
+            .into_iter()
+            .filter(|codec| *codec != crate::codec::Codec::Json)
+            .collect();
+        self
+    }
+
+    /// Send what `hello` says about the client: its auth token and metadata
+    pub fn with_hello_metadata(mut self, hello: &HelloMetadata) -> Self {
+        if hello.auth_token.is_some() {
+            self.auth_token = hello.auth_token.clone();
+        }
+        self.metadata.extend(hello.entries.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+
+    /// Whether `metadata` fits [`MAX_HELLO_METADATA_ENTRIES`] and [`MAX_HELLO_METADATA_BYTES`]
+    pub fn metadata_within_limits(&self) -> bool {
+        let bytes: usize = self.metadata.iter().map(|(k, v)| k.len() + v.len()).sum();
+        self.metadata.len() <= MAX_HELLO_METADATA_ENTRIES && bytes <= MAX_HELLO_METADATA_BYTES
+    }
+
+    /// The [`CLIENT_BUILD`] entry of `metadata`
+    pub fn client_build(&self) -> Option<&str> {
+        self.metadata.get(CLIENT_BUILD).map(String::as_str)
+    }
+
+    /// The [`DEVICE_NAME`] entry of `metadata`
+    pub fn device_name(&self) -> Option<&str> {
+        self.metadata.get(DEVICE_NAME).map(String::as_str)
+    }
+
+    /// The [`CAPABILITY`] entry of `metadata`, parsed but not verified
+    pub fn capability(&self) -> Option<Result<crate::capability::CapabilityToken, crate::capability::CapabilityError>> {
+        self.metadata.get(CAPABILITY).map(|token| token.parse())
+    }
+}
+
+/// What a [`crate::client::Client`] tells servers about itself in ClientHello
+///
+/// ```rust,no_run
+/// # use fastn_p2p::client::{Client, HelloMetadata};
+/// # fn run(key: fastn_p2p::SecretKey, token: String) {
+/// let hello = HelloMetadata::new()
+///     .with_client_build(env!("CARGO_PKG_VERSION"))
+///     .with_device_name("alice-laptop")
+///     .with_auth_token(token);
+/// let client = Client::new(key).with_hello_metadata(hello);
+/// # }
+/// ```
+///
+/// Servers read it in [`crate::server::ServerBuilder::with_connection_auth`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelloMetadata {
+    auth_token: Option<String>,
+    entries: std::collections::BTreeMap<String, String>,
+}
+
+impl HelloMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_client_build(self, build: impl Into<String>) -> Self {
+        self.with(CLIENT_BUILD, build)
+    }
+
+    pub fn with_device_name(self, name: impl Into<String>) -> Self {
+        self.with(DEVICE_NAME, name)
+    }
+
+    /// Present `token` to servers, see [`crate::capability`]
+    pub fn with_capability(self, token: &crate::capability::CapabilityToken) -> Self {
+        self.with(CAPABILITY, token.to_string())
+    }
+
+    /// Sent as `ClientHello::auth_token`
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Any other `key: value` entry
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.entries.insert(key.into(), value.into());
+        self
+    }
 }
 
 impl ServerHello {
@@ -92,12 +382,119 @@ impl ServerHello {
             server_name: "fastn-p2p-server".to_string(),
             server_version: env!("CARGO_PKG_VERSION").to_string(),
             accepted_protocols: Vec::new(),
+            tagged_responses: false,
+            resumption_token: None,
+            resumed: false,
+            early_response: false,
+            datagram_protocols: Vec::new(),
+            codecs: Vec::new(),
         }
     }
     
     pub fn failure(code: HandshakeError) -> Self {
         Self::Failure {
             code,
+            denial: None,
+        }
+    }
+
+    /// Refuse the connection as the auth hook decided
+    pub fn denied(code: HandshakeError, denial: AuthDenial) -> Self {
+        Self::Failure {
+            code,
+            denial: Some(denial),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_hello_debug_leaves_out_secrets() {
+        let mut hello = ClientHello::new("fastn-p2p-client", "0.1.0")
+            .with_resumption_token(Some("resume-secret".to_string()))
+            .with_early_request(Some(EarlyRequest {
+                protocol: serde_json::json!("Echo"),
+                data: serde_json::json!("data-secret"),
+                trace: None,
+                deadline_ms: None,
+                signature: None,
+            }));
+        hello.auth_token = Some("auth-secret".to_string());
+        hello.metadata.insert(CAPABILITY.to_string(), "capability-secret".to_string());
+        hello.metadata.insert(DEVICE_NAME.to_string(), "laptop".to_string());
+
+        let debug = format!("{hello:?}");
+        for secret in ["resume-secret", "data-secret", "auth-secret", "capability-secret"] {
+            assert!(!debug.contains(secret), "{secret} in {debug}");
         }
+        assert!(debug.contains("laptop") && debug.contains("Echo"), "{debug}");
     }
-}
\ No newline at end of file
+
+    proptest::proptest! {
+        #[test]
+        fn test_hellos_never_panic_on_garbage(input in proptest::collection::vec(proptest::num::u8::ANY, 0..512)) {
+            let _ = serde_json::from_slice::<ClientHello>(&input);
+            let _ = serde_json::from_slice::<ServerHello>(&input);
+            let _ = serde_json::from_slice::<crate::wire::WrapperRequest>(&input);
+        }
+
+        #[test]
+        fn test_client_hello_roundtrips(
+            name in ".*",
+            version in ".*",
+            protocols in proptest::collection::vec(".*", 0..8),
+            token in proptest::option::of(".*"),
+        ) {
+            let mut hello = ClientHello::new(name.clone(), version).with_resumption_token(token.clone());
+            for protocol in &protocols {
+                hello = hello.with_protocol(protocol);
+            }
+            let line = serde_json::to_vec(&hello).unwrap();
+            // A hello is one frame: serde_json escapes every newline inside strings
+            proptest::prop_assert!(!line.contains(&b'\n'));
+            let parsed: ClientHello = serde_json::from_slice(&line).unwrap();
+            proptest::prop_assert_eq!(parsed.client_name, name);
+            proptest::prop_assert_eq!(parsed.supported_protocols, protocols.iter().map(|p| serde_json::json!(p)).collect::<Vec<_>>());
+            proptest::prop_assert_eq!(parsed.resumption_token, token);
+        }
+    }
+
+    #[test]
+    fn test_hello_metadata() {
+        let metadata = HelloMetadata::new()
+            .with_client_build("abc123")
+            .with_device_name("laptop")
+            .with_auth_token("secret")
+            .with("team", "ops");
+        let hello = ClientHello::new("app", "1.0").with_hello_metadata(&metadata);
+        assert_eq!(hello.client_build(), Some("abc123"));
+        assert_eq!(hello.device_name(), Some("laptop"));
+        assert_eq!(hello.auth_token.as_deref(), Some("secret"));
+        assert!(hello.metadata_within_limits());
+
+        // Unknown fields, from newer clients, are skipped
+        let mut json = serde_json::to_value(&hello).unwrap();
+        json["from_the_future"] = serde_json::json!({"nested": true});
+        let parsed: ClientHello = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.metadata, hello.metadata);
+
+        let too_many = (0..=MAX_HELLO_METADATA_ENTRIES).fold(HelloMetadata::new(), |m, i| m.with(i.to_string(), ""));
+        assert!(!ClientHello::new("app", "1.0").with_hello_metadata(&too_many).metadata_within_limits());
+        let too_big = HelloMetadata::new().with("blob", "x".repeat(MAX_HELLO_METADATA_BYTES));
+        assert!(!ClientHello::new("app", "1.0").with_hello_metadata(&too_big).metadata_within_limits());
+    }
+
+    #[test]
+    fn test_deeply_nested_protocols_are_rejected() {
+        // serde_json's recursion limit turns this into an error instead of a stack overflow
+        let depth = 100_000;
+        let hello = format!(
+            r#"{{"client_name":"x","client_version":"1","supported_protocols":[{}{}],"auth_token":null}}"#,
+            "[".repeat(depth),
+            "]".repeat(depth)
+        );
+        assert!(serde_json::from_str::<ClientHello>(&hello).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Client interceptors: cross-cutting logic around every `call()` and `connect()`
+//!
+//! The client-side counterpart of [`crate::server::middleware`]. An
+//! interceptor is an async fn taking the outgoing call and the rest of the
+//! chain; it can add metadata headers, time the call, retry or short-circuit
+//! it, and sees every error the same way regardless of protocol:
+//!
+//! ```rust,no_run
+//! # fn new_request_id() -> String { String::new() }
+//! # mod metrics { pub fn record(_: std::time::Duration, _: bool) {} }
+//! # fn run(key: fastn_p2p::SecretKey) {
+//! let client = fastn_p2p::client::Client::new(key)
+//!     .with_interceptor(|mut call, next| async move {
+//!         call.metadata.insert("request-id".to_string(), new_request_id());
+//!         let started = std::time::Instant::now();
+//!         let reply = next.run(call).await;
+//!         metrics::record(started.elapsed(), reply.is_ok());
+//!         reply
+//!     });
+//! # }
+//! ```
+//!
+//! Interceptors run in the order they were added, the first one outermost.
+//! [`CallNext`] can be run more than once, which is how retries are written.
+
+use std::future::Future;
+use std::pin::Pin;
+
+/// What the rest of the chain produced
+pub enum Reply {
+    /// Application result of a `call()`: serialized OUTPUT or ERROR
+    Response(Result<serde_json::Value, serde_json::Value>),
+    /// Session opened by `connect()`
+    Session(crate::client::Session),
+}
+
+pub type InterceptorResult = Result<Reply, crate::client::CallError>;
+
+type BoxFuture = Pin<Box<dyn Future<Output = InterceptorResult> + Send>>;
+
+pub(crate) type Interceptor = std::sync::Arc<dyn Fn(OutgoingCall, CallNext) -> BoxFuture + Send + Sync>;
+
+/// Sends the call once all interceptors have run
+pub(crate) type Transport = std::sync::Arc<dyn Fn(OutgoingCall) -> BoxFuture + Send + Sync>;
+
+/// Wrap a typed interceptor fn for storage on a client
+pub(crate) fn boxed<F, Fut>(interceptor: F) -> Interceptor
+where
+    F: Fn(OutgoingCall, CallNext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = InterceptorResult> + Send + 'static,
+{
+    std::sync::Arc::new(move |call, next| Box::pin(interceptor(call, next)))
+}
+
+/// A `call()` or `connect()` as seen by interceptors
+#[derive(Debug, Clone)]
+pub struct OutgoingCall {
+    target: fastn_id52::PublicKey,
+    protocol: serde_json::Value,
+    /// Request input (or initial stream data), may be rewritten
+    pub data: serde_json::Value,
+    /// Headers delivered to the server alongside the request
+    pub metadata: std::collections::BTreeMap<String, String>,
+    is_stream: bool,
+    trace: fastn_net::TraceContext,
+    /// When the caller gives up on the response; sent to the server, which
+    /// stops working on the call then. Ignored for `connect()`
+    pub deadline: Option<tokio::time::Instant>,
+    /// Network paths the request may take, see [`crate::client::CallOptions`]
+    pub path_preference: crate::client::PathPreference,
+    /// Blobs sent alongside the request, see [`crate::attachment`]. Ignored for `connect()`
+    pub attachments: Vec<crate::attachment::Attachment>,
+}
+
+impl OutgoingCall {
+    pub(crate) fn new(
+        target: fastn_id52::PublicKey,
+        protocol: serde_json::Value,
+        data: serde_json::Value,
+        metadata: std::collections::BTreeMap<String, String>,
+        is_stream: bool,
+    ) -> Self {
+        Self {
+            target,
+            protocol,
+            data,
+            metadata,
+            is_stream,
+            trace: fastn_net::TraceContext::for_outgoing(),
+            deadline: None,
+            path_preference: crate::client::PathPreference::Any,
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn target(&self) -> &fastn_id52::PublicKey {
+        &self.target
+    }
+
+    pub fn protocol(&self) -> &serde_json::Value {
+        &self.protocol
+    }
+
+    /// Whether this is a `connect()` rather than a `call()`
+    pub fn is_stream(&self) -> bool {
+        self.is_stream
+    }
+
+    /// Trace context sent with the call; retries of a clone share it
+    pub fn trace(&self) -> &fastn_net::TraceContext {
+        &self.trace
+    }
+}
+
+/// The rest of the chain: the remaining interceptors, then the transport
+#[derive(Clone)]
+pub struct CallNext {
+    interceptors: std::sync::Arc<Vec<Interceptor>>,
+    index: usize,
+    transport: Transport,
+}
+
+impl CallNext {
+    pub(crate) fn new(interceptors: std::sync::Arc<Vec<Interceptor>>, transport: Transport) -> Self {
+        Self {
+            interceptors,
+            index: 0,
+            transport,
+        }
+    }
+
+    pub async fn run(&self, call: OutgoingCall) -> InterceptorResult {
+        match self.interceptors.get(self.index) {
+            Some(interceptor) => {
+                let next = CallNext {
+                    interceptors: self.interceptors.clone(),
+                    index: self.index + 1,
+                    transport: self.transport.clone(),
+                };
+                interceptor(call, next).await
+            }
+            None => (self.transport)(call).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_interceptors_can_add_metadata_and_retry() {
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let transport_attempts = attempts.clone();
+        let transport: Transport = std::sync::Arc::new(move |call: OutgoingCall| {
+            let attempt = transport_attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Box::pin(async move {
+                if attempt == 0 {
+                    return Err(crate::client::CallError::Relay { message: "flaky".to_string() });
+                }
+                Ok(Reply::Response(Ok(serde_json::json!(call.metadata))))
+            })
+        });
+
+        let interceptors = std::sync::Arc::new(vec![
+            boxed(|call: OutgoingCall, next: CallNext| async move {
+                match next.run(call.clone()).await {
+                    Err(_) => next.run(call).await,
+                    reply => reply,
+                }
+            }),
+            boxed(|mut call: OutgoingCall, next: CallNext| async move {
+                call.metadata.insert("trace".to_string(), "abc".to_string());
+                next.run(call).await
+            }),
+        ]);
+
+        let target = fastn_id52::SecretKey::generate().public_key();
+        let call = OutgoingCall::new(target, serde_json::json!("Echo"), serde_json::json!("hi"), Default::default(), false);
+        let reply = CallNext::new(interceptors, transport).run(call).await.unwrap();
+
+        assert!(matches!(reply, Reply::Response(Ok(metadata)) if metadata == serde_json::json!({"trace": "abc"})));
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+}
@@ -149,9 +149,40 @@ mod coordination;
 mod globals;
 mod handshake;
 mod macros;
+mod wire;
+#[cfg(test)]
+mod wire_golden;
 
-// Export server module (client is now separate fastn-p2p-client crate)
+// Direct P2P client (the daemon-routed client is the separate fastn-p2p-client crate)
+pub mod attachment;
+pub mod batch;
+pub mod broadcast;
+pub mod capability;
+pub mod checksum;
+pub mod client;
+pub mod codec;
+pub mod codegen;
+pub mod console;
+pub mod datagram;
+pub mod events;
+pub mod fan_out;
+#[cfg(fuzzing)]
+pub mod fuzz;
+pub mod interceptor;
+pub mod media;
+pub mod metrics;
+pub mod network;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod ping;
+pub mod progress;
+pub mod protocol_key;
+pub mod scaffold;
 pub mod server;
+pub mod signing;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod time_sync;
 
 // Re-export modern server API for convenience
 pub use server::{serve_all, echo_request_handler};
@@ -167,15 +198,20 @@ pub use fastn_context::main;
 pub use fastn_id52::{PublicKey, SecretKey};
 
 // Global singleton access - graceful is completely encapsulated in coordination module
-pub use coordination::{cancelled, shutdown, spawn};
+pub use coordination::{cancelled, draining, is_cancelled, is_draining, on_shutdown, scope, shutdown, shutdown_with_timeout, spawn, spawn_blocking, stop_accepting};
+pub use fastn_net::{ShutdownPhase, ShutdownReport};
 pub use globals::{graceful, pool};
 
 // Server builder API - new clean interface
 pub use server::builder_listen as listen;
+pub use server::{DeferredResponse, PeerInfo, PeerSession, PeerSessions, PeerStats, PendingResponses, ProtocolModule, RegistrationError, RequestContext, Responder, ServerHandle, SignedRequest};
 
-// Legacy API exports (TODO: phase out in favor of builder API)
+// Legacy API exports
 pub use server::{
     GetInputError, HandleRequestError, ListenerAlreadyActiveError, ListenerNotFoundError, Request,
     ResponseHandle, SendError, Session, active_listener_count, active_listeners, is_listening,
-    listen as legacy_listen, stop_listening,
+    stop_listening,
 };
+// Deprecated shim over the builder, kept for existing callers
+#[allow(deprecated)]
+pub use server::listen as legacy_listen;
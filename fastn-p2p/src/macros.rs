@@ -1,7 +1,7 @@
 /// Convenience macro for starting a P2P listener with automatic pinning
 ///
-/// This macro combines `listen()` and `std::pin::pin!()` into a single call,
-/// eliminating boilerplate for the common case.
+/// This macro combines the deprecated `legacy_listen()` and `std::pin::pin!()`
+/// into a single call; new code should use [`crate::listen`] with handlers.
 ///
 /// # Example
 ///
@@ -9,7 +9,7 @@
 /// use futures_util::stream::StreamExt;
 ///
 /// // Before: Two lines with mystifying pin! macro
-/// let stream = fastn_p2p::listen(secret_key, &protocols)?;
+/// let stream = fastn_p2p::legacy_listen(secret_key, &protocols)?;
 /// let mut stream = std::pin::pin!(stream);
 ///
 /// // After: One clean line
@@ -22,7 +22,7 @@
 #[macro_export]
 macro_rules! listen {
     ($secret_key:expr, $protocols:expr) => {{
-        let stream = $crate::listen($secret_key, $protocols)?;
+        let stream = $crate::legacy_listen($secret_key, $protocols)?;
         std::pin::pin!(stream)
     }};
 }
@@ -12,6 +12,15 @@ mod cli;
 #[command(name = "fastn-p2p")]
 #[command(about = "P2P daemon and client for fastn")]
 struct Cli {
+    /// Output format: text for people, json for scripts (one object on stdout)
+    #[arg(long, global = true, value_enum, default_value_t)]
+    output: cli::output::Format,
+    /// Show more on stderr: -v info, -vv debug, -vvv everything, iroh included
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Show only errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -26,26 +35,172 @@ enum Commands {
     },
     /// Make a request/response call to a peer
     Call {
-        /// Target peer ID52
+        /// Target peer ID52, or a peer group to call every member
         peer: String,
         /// Protocol name
         protocol: String,
-        /// Protocol bind alias (defaults to "default")
-        #[arg(default_value = "default")]
-        bind_alias: String,
-        /// Identity to send from (auto-detected if only one identity)
+        /// serve_all command to call (omit for plain protocol handlers)
+        command: Option<String>,
+        /// Protocol bind alias
+        #[arg(long, default_value = "default")]
+        alias: String,
+        /// Identity to send from (defaults to the default identity)
         #[arg(long)]
         as_identity: Option<String>,
+        /// Print the response as compact JSON on one line
+        #[arg(long)]
+        raw: bool,
+        /// Network path to the peer: any, direct-only or relay-only
+        #[arg(long, default_value = "any")]
+        path: fastn_p2p_client::client::PathPreference,
+        /// Extra arguments passed to the command (after `--`)
+        #[arg(last = true)]
+        args: Vec<String>,
         /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
         #[arg(long, env = "FASTN_HOME")]
         home: Option<PathBuf>,
     },
     /// Open a bidirectional stream to a peer
     Stream {
+        /// Target peer ID52, or a peer group to send stdin to every member
+        peer: String,
+        /// Protocol name
+        protocol: String,
+        /// serve_all command to stream to (omit for plain protocol handlers)
+        command: Option<String>,
+        /// Protocol bind alias
+        #[arg(long, default_value = "default")]
+        alias: String,
+        /// Identity to send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// JSON sent with the stream request (stdin is the stream itself)
+        #[arg(long)]
+        data: Option<String>,
+        /// Show bytes sent/received, rate and ETA on stderr
+        #[arg(long)]
+        progress: bool,
+        /// Extra arguments passed to the command (after `--`)
+        #[arg(last = true)]
+        args: Vec<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Queue a message (JSON on stdin) for delivery until the peer confirms it
+    Send {
         /// Target peer ID52
         peer: String,
         /// Protocol name
         protocol: String,
+        /// serve_all command to address (omit for plain protocol handlers)
+        command: Option<String>,
+        /// Protocol bind alias
+        #[arg(long, default_value = "default")]
+        alias: String,
+        /// Identity to send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Extra arguments passed to the command (after `--`)
+        #[arg(last = true)]
+        args: Vec<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Show whether queued messages were delivered
+    Delivery {
+        /// Message ID printed by `send` (defaults to every message sent)
+        message_id: Option<String>,
+        /// Identity that sent the messages (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Measure round trip times to a peer and show whether the path is direct or relayed
+    Ping {
+        /// Target peer ID52
+        peer: String,
+        /// Number of pings, one per second
+        #[arg(short, long, default_value_t = 4)]
+        count: u32,
+        /// Identity to send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Print the OpenRPC description of a peer's protocols
+    Describe {
+        /// Target peer ID52
+        peer: String,
+        /// Protocol to describe (defaults to every described protocol)
+        protocol: Option<String>,
+        /// Identity to send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Generate typed client stubs from a protocol description
+    Codegen {
+        /// Protocol to generate stubs for (defaults to every described protocol)
+        #[arg(long)]
+        protocol: Option<String>,
+        /// Language of the stubs: rust or typescript
+        #[arg(long, default_value = "rust")]
+        lang: String,
+        /// Peer ID52 to fetch the description from
+        #[arg(long, required_unless_present = "schema")]
+        peer: Option<String>,
+        /// OpenRPC document saved from `fastn-p2p describe`, instead of asking a peer
+        #[arg(long, conflicts_with = "peer")]
+        schema: Option<PathBuf>,
+        /// File to write the stubs to (defaults to stdout)
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// Identity to send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Upgrade an older FASTN_HOME layout (the daemon also does this on start)
+    Migrate {
+        /// Show what would change without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Check calls and streams end to end against a throwaway in-process identity
+    Selftest {
+        /// Identity the daemon checks send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Create a starter crate for a new serve_all protocol
+    NewProtocol {
+        /// Protocol name, e.g. mail or mail.example.com
+        name: String,
+        /// Directory to create the crate in (defaults to ./<crate name>)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Interactive prompt for calling and streaming to peers
+    Repl {
+        /// Identity to send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
         /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
         #[arg(long, env = "FASTN_HOME")]
         home: Option<PathBuf>,
@@ -54,6 +209,15 @@ enum Commands {
     CreateIdentity {
         /// Identity alias name
         alias: String,
+        /// Import the key from a file, e.g. an example's .fastn-*.key
+        #[arg(long, group = "key_source")]
+        from_file: Option<PathBuf>,
+        /// Import the key from an environment variable
+        #[arg(long, value_name = "VAR", group = "key_source")]
+        from_env: Option<String>,
+        /// Import the key given as hex
+        #[arg(long, value_name = "HEX", group = "key_source")]
+        from_seed: Option<String>,
         /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
         #[arg(long, env = "FASTN_HOME")]
         home: Option<PathBuf>,
@@ -91,6 +255,24 @@ enum Commands {
     },
     /// Show comprehensive daemon and identity status
     Status {
+        /// Also ask the daemon for the circuit breaker state of peers it called
+        #[arg(long)]
+        peers: bool,
+        /// Also ask the daemon for handler latencies (p50/p95/p99) and errors per protocol command
+        #[arg(long)]
+        protocols: bool,
+        /// Also ask the daemon which endpoint serves each online identity, with its ports and relay
+        #[arg(long)]
+        endpoints: bool,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Stop the daemon accepting connections and wait for in-flight streams, before a restart
+    Drain {
+        /// How long to wait for in-flight streams, e.g. 30s, 5m
+        #[arg(long, default_value = "60s")]
+        timeout: String,
         /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
         #[arg(long, env = "FASTN_HOME")]
         home: Option<PathBuf>,
@@ -111,30 +293,486 @@ enum Commands {
         #[arg(long, env = "FASTN_HOME")]
         home: Option<PathBuf>,
     },
+    /// Show, set or clear the identity used when --as-identity is omitted
+    DefaultIdentity {
+        /// Identity alias to make the default (shows the current default if omitted)
+        alias: Option<String>,
+        /// Remove the default identity setting
+        #[arg(long, conflicts_with = "alias")]
+        clear: bool,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Show the audit log of requests served by an identity
+    Audit {
+        /// Identity alias name
+        identity: String,
+        /// Only show requests from this peer ID52
+        #[arg(long)]
+        peer: Option<String>,
+        /// Only show requests since an RFC 3339 timestamp or an age like 2h, 7d
+        #[arg(long)]
+        since: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Show bandwidth and requests per identity, protocol and peer for a month
+    Usage {
+        /// Only this identity alias (defaults to every identity)
+        #[arg(long)]
+        identity: Option<String>,
+        /// Month as YYYY-MM (defaults to this month)
+        #[arg(long)]
+        month: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Mint a capability token that lets peers use an identity's protocol without an allowlist entry
+    Grant {
+        /// Identity alias name
+        identity: String,
+        /// Protocol to grant, e.g. files.fastn.com
+        #[arg(long)]
+        protocol: String,
+        /// serve_all command to grant; repeat for more (defaults to every command)
+        #[arg(long = "command")]
+        commands: Vec<String>,
+        /// How long the token is valid, e.g. 30m, 24h, 7d
+        #[arg(long = "for", default_value = "24h")]
+        valid_for: String,
+        /// Only this peer ID52 may use the token (defaults to whoever holds it)
+        #[arg(long)]
+        holder: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Manage the address book and peer groups of an identity
+    Peers {
+        #[command(subcommand)]
+        command: PeersCommands,
+    },
+    /// Show or set storage quotas of an identity
+    Quota {
+        #[command(subcommand)]
+        command: QuotaCommands,
+    },
+    /// Manage the daemon's cache of call answers
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// Accept or deny incoming streams waiting for approval
+    Approvals {
+        #[command(subcommand)]
+        command: ApprovalsCommands,
+    },
+    /// Show or forgive peers with strikes against them
+    Reputation {
+        #[command(subcommand)]
+        command: ReputationCommands,
+    },
+    /// Share clipboard contents with peers
+    Clip {
+        #[command(subcommand)]
+        command: ClipCommands,
+    },
+    /// Pair other machines with an identity hosted here, or join one
+    Device {
+        #[command(subcommand)]
+        command: DeviceCommands,
+    },
+    /// Invite someone you have no ID52 for, or accept their invite
+    Invite {
+        #[command(subcommand)]
+        command: InviteCommands,
+    },
+    /// Browse a peer's web.fastn.com site through a local HTTP server
+    Browse {
+        /// Peer ID52 hosting the site
+        peer: String,
+        /// web.fastn.com bind alias on the peer (defaults to "default")
+        #[arg(long, default_value = "default")]
+        alias: String,
+        /// Local port to listen on (0 picks a free port)
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// Identity to browse as (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Mirror a peer's sync.fastn.com directory into a local directory
+    Sync {
+        /// Source peer ID52
+        peer: String,
+        /// sync.fastn.com bind alias on the peer
+        binding: String,
+        /// Local directory to bring up to date
+        local_dir: PathBuf,
+        /// Identity to sync as (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Show a progress bar instead of one line per chunk
+        #[arg(long)]
+        progress: bool,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum InviteCommands {
+    /// Make a single-use invite from an identity
+    Create {
+        /// Identity alias name
+        identity: String,
+        /// How long the invite is valid, e.g. 30m, 24h, 7d
+        #[arg(long = "for", default_value = "24h")]
+        valid_for: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Accept an invite, adding both sides to each other's address book
+    Accept {
+        /// Invite printed by `invite create` (fastn-p2p-invite:<token>?code=<code>)
+        invite: String,
+        /// Name for the inviter in our address book (defaults to the name they go by)
+        #[arg(long)]
+        name: Option<String>,
+        /// Identity to accept as (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeviceCommands {
+    /// Issue a one-time pairing code for an identity (run on the primary)
+    Pair {
+        /// Identity alias name
+        identity: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Pair this machine using the URI printed by `device pair` (run on the secondary)
+    Join {
+        /// Pairing URI (fastn-p2p-pair:<id52>?code=<code>)
+        uri: String,
+        /// Local alias for the paired identity
+        #[arg(long)]
+        alias: String,
+        /// Name of this device, shown on the primary
+        #[arg(long, default_value = "device")]
+        name: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// List devices paired with an identity
+    List {
+        /// Identity alias name
+        identity: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Revoke a device's access to an identity
+    Remove {
+        /// Identity alias name
+        identity: String,
+        /// Device ID52 as shown by `device list`
+        device: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PeersCommands {
+    /// Add a named peer to the address book, replacing an entry of that name
+    Add {
+        /// Name to use for the peer, e.g. in groups
+        name: String,
+        /// Peer ID52
+        peer: String,
+        /// Identity whose address book to change (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Remove a named peer from the address book
+    Remove {
+        /// Name of the peer
+        name: String,
+        /// Identity whose address book to change (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// List the address book and peer groups
+    List {
+        /// Identity whose address book to show (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Create or delete peer groups, usable as the peer of call and stream
+    Group {
+        #[command(subcommand)]
+        command: GroupCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum GroupCommands {
+    /// Define a group, replacing an existing group of that name
+    Create {
+        /// Group name
+        name: String,
+        /// Members: names from the address book or peer ID52s
+        #[arg(required = true)]
+        members: Vec<String>,
+        /// Identity whose address book to change (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Delete a group; its members stay in the address book
+    Delete {
+        /// Group name
+        name: String,
+        /// Identity whose address book to change (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum QuotaCommands {
+    /// Show storage used by an identity and its bindings, with their limits
+    Show {
+        /// Identity alias name
+        identity: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Limit the storage of an identity, or of one binding with --protocol
+    Set {
+        /// Identity alias name
+        identity: String,
+        /// Limit like 500M, 10G or 1048576 (bytes); "none" removes it
+        limit: String,
+        /// Limit this protocol's binding instead of the whole identity
+        #[arg(long)]
+        protocol: Option<String>,
+        /// Bind alias of the binding (with --protocol)
+        #[arg(long, default_value = "default")]
+        alias: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Drop cached answers, all or those of one peer and/or protocol
+    Clear {
+        /// Only answers from this peer ID52
+        #[arg(long)]
+        peer: Option<String>,
+        /// Only answers of this protocol
+        #[arg(long)]
+        protocol: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReputationCommands {
+    /// Show peers with strikes or bans, banned ones first
+    List {
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Forget a peer's strikes and lift its ban
+    Clear {
+        /// Peer ID52
+        peer: String,
+        /// Only with this identity ID52 (defaults to every identity)
+        #[arg(long)]
+        identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApprovalsCommands {
+    /// Show streams waiting for approval, oldest first
+    List {
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Let a waiting stream go on
+    Accept {
+        /// Approval ID as shown by `approvals list`
+        id: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Reject a waiting stream
+    Deny {
+        /// Approval ID as shown by `approvals list`
+        id: String,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ClipCommands {
+    /// Send stdin to a peer's clipboard
+    Send {
+        /// Target peer ID52
+        peer: String,
+        /// MIME type of the contents (defaults to text if stdin is UTF-8, binary otherwise)
+        #[arg(long)]
+        mime: Option<String>,
+        /// Clipboard bind alias on the peer (defaults to "default")
+        #[arg(long, default_value = "default")]
+        alias: String,
+        /// Identity to send from (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
+    /// Write the latest received clip to stdout
+    Recv {
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// Clipboard bind alias (defaults to "default")
+        #[arg(long, default_value = "default")]
+        alias: String,
+        /// Identity that received the clip (defaults to the default identity)
+        #[arg(long)]
+        as_identity: Option<String>,
+        /// Custom FASTN_HOME directory (defaults to FASTN_HOME env var or ~/.fastn)
+        #[arg(long, env = "FASTN_HOME")]
+        home: Option<PathBuf>,
+    },
 }
 
-#[fastn_p2p::main]
+#[fastn_p2p::main(logging = false)]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
-    match cli.command {
+    // Exits with the code for how the command went, see cli::output::Exit
+    cli::output::init(cli.output, fastn_p2p::console::Verbosity::from_flags(cli.verbose, cli.quiet));
+    // The daemon adds OTLP export to its subscriber, see cli::daemon::run
+    if !matches!(cli.command, Commands::Daemon { .. })
+        && let Err(e) = fastn_p2p::console::init(cli::output::console_layer())
+    {
+        eprintln!("⚠️  {}", e);
+    }
+    cli::output::finish(run(cli.command).await)
+}
+
+async fn run(command: Commands) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
         Commands::Daemon { home } => {
             let fastn_home = cli::get_fastn_home(home)?;
             println!("🚀 Starting fastn-p2p daemon");
             println!("📁 FASTN_HOME: {}", fastn_home.display());
             cli::daemon::run(fastn_home).await
         }
-        Commands::Call { peer, protocol, bind_alias, as_identity, home } => {
+        Commands::Call { peer, protocol, command, alias, as_identity, raw, path, args, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            let target = cli::client::Target { protocol, bind_alias: alias, command, args };
+            cli::client::call(fastn_home, peer, target, as_identity, raw, path).await
+        }
+        Commands::Stream { peer, protocol, command, alias, as_identity, data, progress, args, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            let target = cli::client::Target { protocol, bind_alias: alias, command, args };
+            cli::client::stream(fastn_home, peer, target, as_identity, data, progress).await
+        }
+        Commands::Send { peer, protocol, command, alias, as_identity, args, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::outbox::send(fastn_home, peer, protocol, command, alias, as_identity, args).await
+        }
+        Commands::Delivery { message_id, as_identity, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::outbox::delivery(fastn_home, message_id, as_identity).await
+        }
+        Commands::Ping { peer, count, as_identity, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::ping::ping(fastn_home, peer, count, as_identity).await
+        }
+        Commands::Describe { peer, protocol, as_identity, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::describe::describe(fastn_home, peer, protocol, as_identity).await
+        }
+        Commands::Codegen { protocol, lang, peer, schema, out, as_identity, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::codegen::codegen(fastn_home, protocol, lang, peer, schema, out, as_identity).await
+        }
+        Commands::Migrate { dry_run, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::migrate::migrate(fastn_home, dry_run).await
+        }
+        Commands::Selftest { as_identity, home } => {
             let fastn_home = cli::get_fastn_home(home)?;
-            cli::client::call(fastn_home, peer, protocol, bind_alias, as_identity).await
+            cli::selftest::selftest(fastn_home, as_identity).await
         }
-        Commands::Stream { peer, protocol, home } => {
+        Commands::NewProtocol { name, dir } => {
+            cli::scaffold::new_protocol(name, dir).await
+        }
+        Commands::Repl { as_identity, home } => {
             let fastn_home = cli::get_fastn_home(home)?;
-            cli::client::stream(fastn_home, peer, protocol).await
+            cli::repl::run(fastn_home, as_identity).await
         }
-        Commands::CreateIdentity { alias, home } => {
+        Commands::CreateIdentity { alias, from_file, from_env, from_seed, home } => {
             let fastn_home = cli::get_fastn_home(home)?;
-            cli::identity::create_identity(fastn_home, alias).await
+            let source = from_file
+                .map(cli::identity::KeySource::File)
+                .or(from_env.map(cli::identity::KeySource::Env))
+                .or(from_seed.map(cli::identity::KeySource::Seed));
+            cli::identity::create_identity(fastn_home, alias, source).await
         }
         Commands::AddProtocol { identity, protocol, alias, config, home } => {
             let fastn_home = cli::get_fastn_home(home)?;
@@ -144,9 +782,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let fastn_home = cli::get_fastn_home(home)?;
             cli::identity::remove_protocol(fastn_home, identity, protocol, alias).await
         }
-        Commands::Status { home } => {
+        Commands::Status { peers, protocols, endpoints, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::status::show_status(fastn_home, peers, protocols, endpoints).await
+        }
+        Commands::Drain { timeout, home } => {
             let fastn_home = cli::get_fastn_home(home)?;
-            cli::status::show_status(fastn_home).await
+            cli::drain::drain(fastn_home, timeout).await
         }
         Commands::IdentityOnline { identity, home } => {
             let fastn_home = cli::get_fastn_home(home)?;
@@ -156,5 +798,129 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let fastn_home = cli::get_fastn_home(home)?;
             cli::identity::set_identity_offline(fastn_home, identity).await
         }
+        Commands::DefaultIdentity { alias, clear, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::identity::default_identity(fastn_home, alias, clear).await
+        }
+        Commands::Audit { identity, peer, since, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::audit::show_audit(fastn_home, identity, peer, since).await
+        }
+        Commands::Usage { identity, month, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::usage::show(fastn_home, identity, month).await
+        }
+        Commands::Grant { identity, protocol, commands, valid_for, holder, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::grant::grant(fastn_home, identity, protocol, commands, valid_for, holder).await
+        }
+        Commands::Peers { command } => match command {
+            PeersCommands::Add { name, peer, as_identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::peers::add(fastn_home, name, peer, as_identity).await
+            }
+            PeersCommands::Remove { name, as_identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::peers::remove(fastn_home, name, as_identity).await
+            }
+            PeersCommands::List { as_identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::peers::list(fastn_home, as_identity).await
+            }
+            PeersCommands::Group { command: GroupCommands::Create { name, members, as_identity, home } } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::peers::create_group(fastn_home, name, members, as_identity).await
+            }
+            PeersCommands::Group { command: GroupCommands::Delete { name, as_identity, home } } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::peers::delete_group(fastn_home, name, as_identity).await
+            }
+        },
+        Commands::Quota { command } => match command {
+            QuotaCommands::Show { identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::quota::show(fastn_home, identity).await
+            }
+            QuotaCommands::Set { identity, limit, protocol, alias, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::quota::set(fastn_home, identity, limit, protocol, alias).await
+            }
+        },
+        Commands::Cache { command } => match command {
+            CacheCommands::Clear { peer, protocol, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::cache::clear(fastn_home, peer, protocol).await
+            }
+        },
+        Commands::Approvals { command } => match command {
+            ApprovalsCommands::List { home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::approvals::list(fastn_home).await
+            }
+            ApprovalsCommands::Accept { id, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::approvals::accept(fastn_home, id).await
+            }
+            ApprovalsCommands::Deny { id, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::approvals::deny(fastn_home, id).await
+            }
+        },
+        Commands::Reputation { command } => match command {
+            ReputationCommands::List { home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::reputation::list(fastn_home).await
+            }
+            ReputationCommands::Clear { peer, identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::reputation::clear(fastn_home, peer, identity).await
+            }
+        },
+        Commands::Clip { command } => match command {
+            ClipCommands::Send { peer, mime, alias, as_identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::clip::send(fastn_home, peer, alias, as_identity, mime).await
+            }
+            ClipCommands::Recv { out, alias, as_identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::clip::recv(fastn_home, alias, as_identity, out).await
+            }
+        },
+        Commands::Invite { command } => match command {
+            InviteCommands::Create { identity, valid_for, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::invite::create(fastn_home, identity, valid_for).await
+            }
+            InviteCommands::Accept { invite, name, as_identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::invite::accept(fastn_home, invite, name, as_identity).await
+            }
+        },
+        Commands::Device { command } => match command {
+            DeviceCommands::Pair { identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::device::pair(fastn_home, identity).await
+            }
+            DeviceCommands::Join { uri, alias, name, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::device::join(fastn_home, uri, alias, name).await
+            }
+            DeviceCommands::List { identity, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::device::list(fastn_home, identity).await
+            }
+            DeviceCommands::Remove { identity, device, home } => {
+                let fastn_home = cli::get_fastn_home(home)?;
+                cli::device::remove(fastn_home, identity, device).await
+            }
+        },
+        Commands::Browse { peer, alias, port, as_identity, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::browse::browse(fastn_home, peer, alias, port, as_identity).await
+        }
+        Commands::Sync { peer, binding, local_dir, as_identity, progress, home } => {
+            let fastn_home = cli::get_fastn_home(home)?;
+            cli::sync::sync(fastn_home, peer, binding, local_dir, as_identity, progress).await
+        }
     }
 }
\ No newline at end of file
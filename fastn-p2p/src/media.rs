@@ -0,0 +1,389 @@
+//! Receiver feedback for adapting media bitrate to the network
+//!
+//! A [`Publisher`] puts a small header (sequence number and send time) in
+//! front of every media packet, usually sent as a datagram (see
+//! [`crate::datagram`]). The [`Subscriber`] unwraps packets, tracks loss and
+//! jitter, and periodically sends a [`ReceiverReport`] back over a control
+//! stream, typically the session's reliable stream. The publisher derives the
+//! round trip time from the reports and adjusts a target bitrate, which it
+//! polls to pick chunk sizes or encoder settings:
+//!
+//! ```rust,no_run
+//! # use tokio::io::AsyncReadExt;
+//! # fn play(_: &[u8]) {}
+//! # async fn publish(mut session: fastn_p2p::Session<()>, mut source: tokio::fs::File, interval: std::time::Duration) -> Result<(), Box<dyn std::error::Error>> {
+//! // Publisher, in the stream handler
+//! let publisher = fastn_p2p::media::Publisher::new(Default::default());
+//! let datagrams = session.datagrams.take().ok_or("datagrams not enabled")?;
+//! fastn_p2p::spawn(publisher.clone().read_reports(session.recv));
+//! loop {
+//!     let mut chunk = vec![0; publisher.chunk_size(interval)];
+//!     let len = source.read(&mut chunk).await?;
+//!     datagrams.send(&publisher.packet(&chunk[..len]))?;
+//!     tokio::time::sleep(interval).await;
+//! }
+//! # }
+//! # async fn subscribe(mut session: fastn_p2p::Session<()>) -> Result<(), Box<dyn std::error::Error>> {
+//!
+//! // Subscriber
+//! let subscriber = fastn_p2p::media::Subscriber::new();
+//! let mut datagrams = session.datagrams.take().ok_or("datagrams not enabled")?;
+//! fastn_p2p::spawn(subscriber.clone().send_reports(session.send, std::time::Duration::from_secs(1)));
+//! while let Ok(datagram) = datagrams.recv().await {
+//!     if let Some(packet) = subscriber.receive(&datagram) {
+//!         play(packet.payload);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The bitrate follows loss: it grows slowly while the subscriber sees little
+//! loss and backs off in proportion to the loss once it gets heavy.
+
+use std::time::{Duration, Instant};
+
+/// Bytes in front of every media packet
+pub const HEADER_LEN: usize = 16;
+
+/// Bounds and steps of the bitrate a [`Publisher`] aims for, in bits per second
+#[derive(Debug, Clone)]
+pub struct RateConfig {
+    pub min_bitrate: u64,
+    pub max_bitrate: u64,
+    pub start_bitrate: u64,
+    /// Loss below this share of packets lets the bitrate grow
+    pub low_loss: f64,
+    /// Loss above this share of packets makes the bitrate back off
+    pub high_loss: f64,
+    /// Growth per report while loss is low, as a share of the bitrate
+    pub increase: f64,
+}
+
+impl Default for RateConfig {
+    fn default() -> Self {
+        Self {
+            min_bitrate: 32_000,
+            max_bitrate: 8_000_000,
+            start_bitrate: 256_000,
+            low_loss: 0.02,
+            high_loss: 0.1,
+            increase: 0.05,
+        }
+    }
+}
+
+/// What the subscriber saw since its previous report, sent as one JSON line
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ReceiverReport {
+    /// Highest sequence number received so far
+    pub highest_sequence: u64,
+    /// Packets received in total
+    pub received: u64,
+    /// Interarrival jitter (RFC 3550), in microseconds
+    pub jitter_us: u64,
+    /// Send time of the packet received last, echoed for the round trip time
+    pub echo_sent_at_us: u64,
+    /// How long the subscriber held that packet before this report
+    pub echo_delay_us: u64,
+}
+
+/// The network as the publisher sees it, from the latest report
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Feedback {
+    /// Bitrate to aim for, in bits per second
+    pub bitrate: u64,
+    /// `None` until the first report arrives
+    pub rtt: Option<Duration>,
+    /// Share of packets lost between the last two reports
+    pub loss: f64,
+    pub jitter: Duration,
+    /// Reports received so far
+    pub reports: u64,
+}
+
+/// A media packet unwrapped by [`Subscriber::receive`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub sequence: u64,
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug)]
+struct PublisherState {
+    config: RateConfig,
+    epoch: Instant,
+    next_sequence: u64,
+    feedback: Feedback,
+    /// `(highest_sequence, received)` of the previous report
+    last_report: Option<(u64, u64)>,
+}
+
+/// Sending half: stamps packets and adapts the bitrate to receiver reports
+///
+/// Clones share their state, so one can read reports while another sends.
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    state: std::sync::Arc<std::sync::Mutex<PublisherState>>,
+}
+
+impl Publisher {
+    pub fn new(config: RateConfig) -> Self {
+        let feedback = Feedback { bitrate: config.start_bitrate, ..Default::default() };
+        let state = PublisherState { config, epoch: Instant::now(), next_sequence: 0, feedback, last_report: None };
+        Self { state: std::sync::Arc::new(std::sync::Mutex::new(state)) }
+    }
+
+    /// `payload` with the header the subscriber reports on
+    pub fn packet(&self, payload: &[u8]) -> Vec<u8> {
+        let mut state = self.lock();
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        let sent_at = micros(state.epoch.elapsed());
+
+        let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&sent_at.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// The latest feedback and target bitrate
+    pub fn feedback(&self) -> Feedback {
+        self.lock().feedback.clone()
+    }
+
+    /// Bytes to send per `interval` at the target bitrate
+    pub fn chunk_size(&self, interval: Duration) -> usize {
+        (self.lock().feedback.bitrate as f64 * interval.as_secs_f64() / 8.0) as usize
+    }
+
+    /// Apply reports read from `control` until it ends or fails
+    pub async fn read_reports<R>(self, control: R)
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        let mut control = fastn_net::FrameReader::new(control);
+        loop {
+            match control.next_json::<ReceiverReport>().await {
+                Ok(report) => self.on_report(&report),
+                Err(e) => {
+                    tracing::debug!("Stopped reading receiver reports: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Apply one receiver report
+    pub fn on_report(&self, report: &ReceiverReport) {
+        self.on_report_at(report, Instant::now());
+    }
+
+    fn on_report_at(&self, report: &ReceiverReport, now: Instant) {
+        let mut state = self.lock();
+        let now_us = micros(now.saturating_duration_since(state.epoch));
+        let rtt_us = now_us.saturating_sub(report.echo_sent_at_us).saturating_sub(report.echo_delay_us);
+
+        // Loss between this report and the previous one; the first report counts from the start
+        let (previous_highest, previous_received) = state.last_report.map_or((None, 0), |(h, r)| (Some(h), r));
+        let expected = match previous_highest {
+            Some(highest) => report.highest_sequence.saturating_sub(highest),
+            None => report.highest_sequence + 1,
+        };
+        let received = report.received.saturating_sub(previous_received);
+        let loss = if expected == 0 { 0.0 } else { 1.0 - (received.min(expected) as f64 / expected as f64) };
+        state.last_report = Some((report.highest_sequence, report.received));
+
+        let config = &state.config;
+        let bitrate = state.feedback.bitrate as f64;
+        let bitrate = if loss > config.high_loss {
+            bitrate * (1.0 - loss / 2.0)
+        } else if loss < config.low_loss {
+            bitrate * (1.0 + config.increase)
+        } else {
+            bitrate
+        };
+        let bitrate = (bitrate as u64).clamp(config.min_bitrate, config.max_bitrate);
+
+        state.feedback = Feedback {
+            bitrate,
+            rtt: Some(Duration::from_micros(rtt_us)),
+            loss,
+            jitter: Duration::from_micros(report.jitter_us),
+            reports: state.feedback.reports + 1,
+        };
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PublisherState> {
+        self.state.lock().expect("publisher lock poisoned")
+    }
+}
+
+#[derive(Debug)]
+struct SubscriberState {
+    epoch: Instant,
+    report: ReceiverReport,
+    /// Jitter in microseconds, kept fractional between packets
+    jitter: f64,
+    /// `(arrived_at_us, sent_at_us)` of the packet received last
+    last_packet: Option<(u64, u64)>,
+}
+
+/// Receiving half: unwraps packets and reports on them
+///
+/// Clones share their state, so one can send reports while another receives.
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    state: std::sync::Arc<std::sync::Mutex<SubscriberState>>,
+}
+
+impl Default for Subscriber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Subscriber {
+    pub fn new() -> Self {
+        let state = SubscriberState { epoch: Instant::now(), report: Default::default(), jitter: 0.0, last_packet: None };
+        Self { state: std::sync::Arc::new(std::sync::Mutex::new(state)) }
+    }
+
+    /// Unwrap a packet from a [`Publisher`], counting it for the next report
+    ///
+    /// `None` if it is too short to carry the header.
+    pub fn receive<'a>(&self, packet: &'a [u8]) -> Option<Packet<'a>> {
+        self.receive_at(packet, Instant::now())
+    }
+
+    fn receive_at<'a>(&self, packet: &'a [u8], now: Instant) -> Option<Packet<'a>> {
+        let (header, payload) = packet.split_first_chunk::<HEADER_LEN>()?;
+        let (sequence, sent_at) = header.split_at(8);
+        let sequence = u64::from_be_bytes(sequence.try_into().expect("header halves are 8 bytes"));
+        let sent_at = u64::from_be_bytes(sent_at.try_into().expect("header halves are 8 bytes"));
+
+        let mut state = self.lock();
+        let arrived_at = micros(now.saturating_duration_since(state.epoch));
+        if let Some((last_arrived, last_sent)) = state.last_packet {
+            let transit = (arrived_at as f64 - last_arrived as f64) - (sent_at as f64 - last_sent as f64);
+            state.jitter += (transit.abs() - state.jitter) / 16.0;
+        }
+        state.last_packet = Some((arrived_at, sent_at));
+        state.report.received += 1;
+        state.report.highest_sequence = state.report.highest_sequence.max(sequence);
+
+        Some(Packet { sequence, payload })
+    }
+
+    /// The report to send now
+    pub fn report(&self) -> ReceiverReport {
+        self.report_at(Instant::now())
+    }
+
+    fn report_at(&self, now: Instant) -> ReceiverReport {
+        let state = self.lock();
+        let mut report = state.report.clone();
+        report.jitter_us = state.jitter as u64;
+        if let Some((arrived_at, sent_at)) = state.last_packet {
+            report.echo_sent_at_us = sent_at;
+            report.echo_delay_us = micros(now.saturating_duration_since(state.epoch)).saturating_sub(arrived_at);
+        }
+        report
+    }
+
+    /// Write a report to `control` every `interval` until writing fails
+    ///
+    /// Nothing is sent before the first packet arrives.
+    pub async fn send_reports<W>(self, mut control: W, interval: Duration) -> std::io::Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let report = self.report();
+            if report.received == 0 {
+                continue;
+            }
+            let mut line = serde_json::to_vec(&report)?;
+            line.push(b'\n');
+            control.write_all(&line).await?;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SubscriberState> {
+        self.state.lock().expect("subscriber lock poisoned")
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_carry_loss_and_round_trip() {
+        let publisher = Publisher::new(RateConfig::default());
+        let subscriber = Subscriber::new();
+        let start = Instant::now();
+
+        // Every other packet is lost
+        let packets: Vec<_> = (0..10).map(|i| publisher.packet(&[i])).collect();
+        for packet in packets.iter().step_by(2) {
+            let unwrapped = subscriber.receive_at(packet, start).unwrap();
+            assert_eq!(unwrapped.payload.len(), 1);
+        }
+        assert!(subscriber.receive(b"short").is_none());
+
+        let report = subscriber.report_at(start + Duration::from_millis(5));
+        assert_eq!((report.highest_sequence, report.received), (8, 5));
+        assert_eq!(report.echo_delay_us, 5_000);
+
+        let echoed_at = publisher.lock().epoch + Duration::from_micros(report.echo_sent_at_us);
+        publisher.on_report_at(&report, echoed_at + Duration::from_millis(25));
+        let feedback = publisher.feedback();
+        assert_eq!(feedback.rtt, Some(Duration::from_millis(20)));
+        assert!((feedback.loss - 4.0 / 9.0).abs() < 1e-9);
+        assert!(feedback.bitrate < RateConfig::default().start_bitrate);
+        assert_eq!(feedback.reports, 1);
+    }
+
+    #[test]
+    fn test_bitrate_grows_without_loss_within_bounds() {
+        let config = RateConfig { start_bitrate: 100_000, max_bitrate: 110_000, ..Default::default() };
+        let publisher = Publisher::new(config);
+        let now = Instant::now();
+
+        for (highest, received) in [(9, 10), (19, 20), (29, 30)] {
+            publisher.on_report_at(&ReceiverReport { highest_sequence: highest, received, ..Default::default() }, now);
+        }
+        let feedback = publisher.feedback();
+        assert_eq!(feedback.loss, 0.0);
+        assert_eq!(feedback.bitrate, 110_000);
+        assert_eq!(publisher.chunk_size(Duration::from_millis(100)), 1_375);
+    }
+
+    #[tokio::test]
+    async fn test_reports_over_control_stream() {
+        let publisher = Publisher::new(RateConfig::default());
+        let subscriber = Subscriber::new();
+        subscriber.receive(&publisher.packet(b"frame")).unwrap();
+
+        let (writer, reader) = tokio::io::duplex(1024);
+        let reading = tokio::spawn(publisher.clone().read_reports(reader));
+        let sending = tokio::spawn(subscriber.send_reports(writer, Duration::from_millis(10)));
+        while publisher.feedback().reports == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        sending.abort();
+        reading.await.unwrap();
+        assert_eq!(publisher.feedback().loss, 0.0);
+    }
+}
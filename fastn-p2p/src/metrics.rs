@@ -0,0 +1,262 @@
+//! Latency histograms of request handlers, per protocol and command
+//!
+//! Every request handler run by a [`crate::server::ServerBuilder`] or
+//! `serve_all` is timed, middleware included, and counted in a fixed-bucket
+//! histogram of its protocol (and serve_all command). Nothing has to be
+//! registered; the numbers are kept per process. Streams are not counted,
+//! their duration says little about how the handler is doing.
+//!
+//! [`snapshot`] sums the histograms up with estimated p50/p95/p99, which the
+//! daemon answers `fastn-p2p status --protocols` with, and [`prometheus`]
+//! renders them in the Prometheus text format:
+//!
+//! ```text
+//! fastn_p2p_handler_duration_seconds_bucket{protocol="mail.fastn.com",command="get-mails",le="0.005"} 12
+//! fastn_p2p_handler_duration_seconds_sum{protocol="mail.fastn.com",command="get-mails"} 0.0713
+//! fastn_p2p_handler_duration_seconds_count{protocol="mail.fastn.com",command="get-mails"} 14
+//! fastn_p2p_handler_errors_total{protocol="mail.fastn.com",command="get-mails"} 1
+//! ```
+//!
+//! The Prometheus output also has the concurrency budgets of serve_all
+//! bindings, see [`crate::server::concurrency`].
+
+// Shared with clients reading `protocol-metrics`
+pub use fastn_p2p_client::admin::HandlerStats;
+
+/// Upper bounds of the histogram buckets, in seconds; one more bucket takes the rest
+const BUCKETS: [f64; 14] = [
+    0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static HANDLERS: std::sync::LazyLock<std::sync::Mutex<std::collections::BTreeMap<HandlerKey, Histogram>>> =
+    std::sync::LazyLock::new(Default::default);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct HandlerKey {
+    protocol: String,
+    command: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Calls per bucket, not cumulative; the last one is above every bound
+    counts: [u64; BUCKETS.len() + 1],
+    sum: std::time::Duration,
+    errors: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: std::time::Duration, ok: bool) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += elapsed;
+        if !ok {
+            self.errors += 1;
+        }
+    }
+
+    fn calls(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Estimated `q` quantile in seconds, interpolating inside the bucket it falls in
+    ///
+    /// Like Prometheus' `histogram_quantile`, a quantile in the last bucket is
+    /// reported as the highest bound.
+    fn quantile(&self, q: f64) -> f64 {
+        let calls = self.calls();
+        if calls == 0 {
+            return 0.0;
+        }
+        let rank = q * calls as f64;
+        let mut below = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            if *count > 0 && (below + count) as f64 >= rank {
+                let Some(upper) = BUCKETS.get(bucket) else {
+                    return BUCKETS[BUCKETS.len() - 1];
+                };
+                let lower = if bucket == 0 { 0.0 } else { BUCKETS[bucket - 1] };
+                return lower + (upper - lower) * (rank - below as f64) / *count as f64;
+            }
+            below += count;
+        }
+        BUCKETS[BUCKETS.len() - 1]
+    }
+
+    fn stats(&self, key: &HandlerKey) -> HandlerStats {
+        let calls = self.calls();
+        let ms = |seconds: f64| seconds * 1000.0;
+        HandlerStats {
+            protocol: key.protocol.clone(),
+            command: key.command.clone(),
+            calls,
+            errors: self.errors,
+            mean_ms: if calls == 0 { 0.0 } else { ms(self.sum.as_secs_f64() / calls as f64) },
+            p50_ms: ms(self.quantile(0.5)),
+            p95_ms: ms(self.quantile(0.95)),
+            p99_ms: ms(self.quantile(0.99)),
+        }
+    }
+}
+
+/// Count one handler call of `protocol` (and serve_all `command`) that took `elapsed`
+pub fn record(protocol: &str, command: Option<&str>, elapsed: std::time::Duration, ok: bool) {
+    let key = HandlerKey { protocol: protocol.to_string(), command: command.map(str::to_string) };
+    HANDLERS
+        .lock()
+        .expect("Failed to acquire lock on handler metrics")
+        .entry(key)
+        .or_default()
+        .observe(elapsed, ok);
+}
+
+/// Every handler called so far, ordered by protocol and command
+pub fn snapshot() -> Vec<HandlerStats> {
+    let handlers = HANDLERS.lock().expect("Failed to acquire lock on handler metrics");
+    handlers.iter().map(|(key, histogram)| histogram.stats(key)).collect()
+}
+
+/// Every handler called so far in the Prometheus text exposition format
+pub fn prometheus() -> String {
+    use std::fmt::Write as _;
+
+    let handlers = HANDLERS.lock().expect("Failed to acquire lock on handler metrics").clone();
+    let mut out = String::new();
+    out.push_str("# HELP fastn_p2p_handler_duration_seconds Time taken by request handlers, middleware included\n");
+    out.push_str("# TYPE fastn_p2p_handler_duration_seconds histogram\n");
+    for (key, histogram) in &handlers {
+        let labels = key.labels();
+        let mut cumulative = 0;
+        for (bucket, count) in histogram.counts.iter().enumerate() {
+            cumulative += count;
+            let le = BUCKETS.get(bucket).map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "fastn_p2p_handler_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "fastn_p2p_handler_duration_seconds_sum{{{labels}}} {}", histogram.sum.as_secs_f64());
+        let _ = writeln!(out, "fastn_p2p_handler_duration_seconds_count{{{labels}}} {cumulative}");
+    }
+    out.push_str("# HELP fastn_p2p_handler_errors_total Request handler calls that failed\n");
+    out.push_str("# TYPE fastn_p2p_handler_errors_total counter\n");
+    for (key, histogram) in &handlers {
+        let _ = writeln!(out, "fastn_p2p_handler_errors_total{{{}}} {}", key.labels(), histogram.errors);
+    }
+    crate::server::concurrency::prometheus(&mut out);
+    out
+}
+
+impl HandlerKey {
+    fn labels(&self) -> String {
+        let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        match &self.command {
+            Some(command) => format!("protocol=\"{}\",command=\"{}\"", escape(&self.protocol), escape(command)),
+            None => format!("protocol=\"{}\"", escape(&self.protocol)),
+        }
+    }
+}
+
+/// Name of a `listen()` protocol in the metrics: a string as is, anything else as compact JSON
+pub(crate) fn protocol_name(protocol: &serde_json::Value) -> String {
+    match protocol {
+        serde_json::Value::String(name) => name.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Times a handler call; one dropped before [`Timer::finish`] counts as failed
+///
+/// That is how timeouts and peers that stop waiting show up in the error count.
+/// Calls also go out as [`crate::events::EventKind::IncomingRequest`].
+pub(crate) struct Timer {
+    key: Option<(String, Option<String>)>,
+    identity: String,
+    peer: fastn_id52::PublicKey,
+    started: std::time::Instant,
+}
+
+impl Timer {
+    /// Time a call `peer` made to `identity`, its alias or ID52
+    pub(crate) fn start(
+        protocol: String,
+        command: Option<String>,
+        identity: String,
+        peer: fastn_id52::PublicKey,
+    ) -> Self {
+        Self { key: Some((protocol, command)), identity, peer, started: std::time::Instant::now() }
+    }
+
+    pub(crate) fn finish(mut self, ok: bool) {
+        self.done(ok);
+    }
+
+    fn done(&mut self, ok: bool) {
+        let Some((protocol, command)) = self.key.take() else {
+            return;
+        };
+        let elapsed = self.started.elapsed();
+        record(&protocol, command.as_deref(), elapsed, ok);
+        crate::events::publish(crate::events::EventKind::IncomingRequest {
+            identity: std::mem::take(&mut self.identity),
+            peer: self.peer.id52(),
+            protocol,
+            command,
+            duration_ms: elapsed.as_millis() as u64,
+            ok,
+        });
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.done(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles_interpolate_within_buckets() {
+        let ms = std::time::Duration::from_millis;
+        let mut histogram = Histogram::default();
+        for _ in 0..90 {
+            histogram.observe(ms(3), true);
+        }
+        for _ in 0..10 {
+            histogram.observe(ms(200), false);
+        }
+        let stats = histogram.stats(&HandlerKey { protocol: "Echo".to_string(), command: None });
+        assert_eq!((stats.calls, stats.errors), (100, 10));
+        // 90 calls in (2.5ms, 5ms], 10 in (100ms, 250ms]
+        assert!((stats.p50_ms - 3.888).abs() < 0.01, "{}", stats.p50_ms);
+        assert!((stats.p95_ms - 175.0).abs() < 0.01, "{}", stats.p95_ms);
+        assert!((stats.mean_ms - 22.7).abs() < 0.01, "{}", stats.mean_ms);
+
+        histogram.observe(std::time::Duration::from_secs(60), true);
+        assert_eq!(histogram.quantile(1.0), 10.0);
+        assert_eq!(Histogram::default().quantile(0.5), 0.0);
+    }
+
+    #[test]
+    fn test_prometheus_buckets_are_cumulative() {
+        let protocol = format!("metrics-test-{}", rand::random::<u64>());
+        record(&protocol, Some("get \"x\""), std::time::Duration::from_millis(1), true);
+        record(&protocol, Some("get \"x\""), std::time::Duration::from_secs(30), false);
+        // A dropped timer counts as a failure
+        drop(Timer::start(protocol.clone(), None, "alice".to_string(), fastn_id52::SecretKey::generate().public_key()));
+
+        let text = prometheus();
+        let labels = format!("protocol=\"{protocol}\",command=\"get \\\"x\\\"\"");
+        assert!(text.contains(&format!("fastn_p2p_handler_duration_seconds_bucket{{{labels},le=\"0.001\"}} 1\n")));
+        assert!(text.contains(&format!("fastn_p2p_handler_duration_seconds_bucket{{{labels},le=\"10\"}} 1\n")));
+        assert!(text.contains(&format!("fastn_p2p_handler_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 2\n")));
+        assert!(text.contains(&format!("fastn_p2p_handler_errors_total{{{labels}}} 1\n")));
+        assert!(text.contains(&format!("fastn_p2p_handler_errors_total{{protocol=\"{protocol}\"}} 1\n")));
+
+        let stats: Vec<_> = snapshot().into_iter().filter(|stats| stats.protocol == protocol).collect();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].command, None);
+        assert_eq!(stats[1].command.as_deref(), Some("get \"x\""));
+    }
+}
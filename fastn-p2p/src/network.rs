@@ -0,0 +1,159 @@
+//! Surviving network changes in long-running processes
+//!
+//! A laptop that moves to another Wi-Fi network keeps its endpoints bound to
+//! addresses that are gone. [`NetworkMonitor`] notices by polling the local
+//! addresses the host would send from; [`network_changed`] then has every
+//! process-global [`crate::client::Client`] and every running server
+//! ([`crate::listen`], [`crate::serve_all`]) rebind its endpoint and
+//! re-register with its relay, drops the clients' cached connections so the
+//! next call reconnects, and tells [`subscribe`]rs, so long-lived sessions
+//! can reconnect too:
+//!
+//! ```rust,no_run
+//! # use std::time::Duration;
+//! # async fn run() {
+//! let mut monitor = fastn_p2p::network::NetworkMonitor::new(Duration::from_secs(5));
+//! loop {
+//!     let change = monitor.next().await;
+//!     fastn_p2p::network::network_changed(change).await;
+//! }
+//! # }
+//! ```
+//!
+//! Endpoints made outside [`crate::client::Client::global`] are rebound with
+//! [`rebind`].
+
+/// The local addresses changed, e.g. the host joined another network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkChange {
+    pub before: std::collections::BTreeSet<std::net::IpAddr>,
+    pub after: std::collections::BTreeSet<std::net::IpAddr>,
+}
+
+/// Watches the host's local addresses for changes by polling
+pub struct NetworkMonitor {
+    interval: std::time::Duration,
+    addresses: std::collections::BTreeSet<std::net::IpAddr>,
+}
+
+impl NetworkMonitor {
+    /// Look at the local addresses every `interval`, starting from the current ones
+    pub fn new(interval: std::time::Duration) -> Self {
+        Self { interval, addresses: local_addresses() }
+    }
+
+    /// Wait for the local addresses to change
+    pub async fn next(&mut self) -> NetworkChange {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            let addresses = local_addresses();
+            if addresses != self.addresses {
+                let before = std::mem::replace(&mut self.addresses, addresses.clone());
+                return NetworkChange { before, after: addresses };
+            }
+        }
+    }
+}
+
+/// Addresses the host would send from to the internet, over IPv4 and IPv6
+///
+/// Connecting a UDP socket picks the route without sending anything; a
+/// family without a route is left out.
+pub fn local_addresses() -> std::collections::BTreeSet<std::net::IpAddr> {
+    ["0.0.0.0:0", "[::]:0"]
+        .into_iter()
+        .zip(["192.0.2.1:9", "[2001:db8::1]:9"])
+        .filter_map(|(bind, remote)| {
+            let socket = std::net::UdpSocket::bind(bind).ok()?;
+            socket.connect(remote).ok()?;
+            Some(socket.local_addr().ok()?.ip())
+        })
+        .filter(|addr| !addr.is_unspecified())
+        .collect()
+}
+
+static CHANGES: std::sync::LazyLock<tokio::sync::broadcast::Sender<NetworkChange>> =
+    std::sync::LazyLock::new(|| tokio::sync::broadcast::channel(16).0);
+
+/// Hear about every change passed to [`network_changed`]
+pub fn subscribe() -> tokio::sync::broadcast::Receiver<NetworkChange> {
+    CHANGES.subscribe()
+}
+
+/// Endpoints of the running servers, by registration
+static SERVER_ENDPOINTS: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<u64, iroh::Endpoint>>> =
+    std::sync::LazyLock::new(Default::default);
+
+static NEXT_REGISTRATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+fn server_endpoints() -> std::sync::MutexGuard<'static, std::collections::HashMap<u64, iroh::Endpoint>> {
+    SERVER_ENDPOINTS.lock().expect("Failed to acquire lock on SERVER_ENDPOINTS")
+}
+
+/// Keeps a server's endpoint rebinding on network changes until dropped
+pub(crate) struct ServerEndpoint {
+    id: u64,
+}
+
+impl Drop for ServerEndpoint {
+    fn drop(&mut self) {
+        server_endpoints().remove(&self.id);
+    }
+}
+
+/// Rebind `endpoint` in [`network_changed`] while the returned guard lives
+pub(crate) fn register_server(endpoint: iroh::Endpoint) -> ServerEndpoint {
+    let id = NEXT_REGISTRATION.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    server_endpoints().insert(id, endpoint);
+    ServerEndpoint { id }
+}
+
+/// Rebind all process-global clients and running servers after `change`,
+/// then tell subscribers
+pub async fn network_changed(change: NetworkChange) {
+    tracing::info!("Network changed from {:?} to {:?}, rebinding", change.before, change.after);
+    for client in crate::client::global_clients() {
+        client.rebind().await;
+    }
+    let servers: Vec<_> = server_endpoints().values().cloned().collect();
+    for endpoint in servers {
+        rebind(&endpoint).await;
+    }
+    // Nobody subscribed is fine
+    let _ = CHANGES.send(change);
+}
+
+/// Have `endpoint` rebind its sockets and re-register with its relay
+pub async fn rebind(endpoint: &iroh::Endpoint) {
+    endpoint.network_change().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribers_hear_changes() {
+        let mut changes = subscribe();
+        let change = NetworkChange {
+            before: [std::net::Ipv4Addr::new(192, 168, 1, 2).into()].into(),
+            after: [std::net::Ipv4Addr::new(10, 0, 0, 7).into()].into(),
+        };
+        network_changed(change.clone()).await;
+        assert_eq!(changes.recv().await.unwrap(), change);
+    }
+
+    #[tokio::test]
+    async fn test_server_endpoints_are_rebound_until_dropped() {
+        let endpoint = fastn_net::get_endpoint(fastn_id52::SecretKey::generate()).await.unwrap();
+        let node_id = endpoint.node_id();
+        let serving = |node_id| server_endpoints().values().any(|endpoint| endpoint.node_id() == node_id);
+
+        let registration = register_server(endpoint);
+        assert!(serving(node_id));
+        network_changed(NetworkChange { before: Default::default(), after: local_addresses() }).await;
+
+        drop(registration);
+        assert!(!serving(node_id));
+    }
+}
@@ -0,0 +1,103 @@
+//! Optional OpenTelemetry export of handler spans (the `otlp` feature)
+//!
+//! When `OTEL_EXPORTER_OTLP_ENDPOINT` is set, [`init`] installs a tracing
+//! subscriber that ships spans to that collector over OTLP/HTTP. Handler spans
+//! are parented on the caller's span from the propagated
+//! [`fastn_net::TraceContext`], so one call shows up as one trace across
+//! daemons.
+
+#[derive(Debug, thiserror::Error)]
+pub enum OtlpError {
+    #[error("Failed to build OTLP exporter")]
+    Exporter {
+        #[source]
+        source: opentelemetry_otlp::ExporterBuildError,
+    },
+
+    #[error("A tracing subscriber is already installed")]
+    Subscriber {
+        #[source]
+        source: tracing_subscriber::util::TryInitError,
+    },
+}
+
+/// Install the OTLP exporter if `OTEL_EXPORTER_OTLP_ENDPOINT` is set
+///
+/// Returns the provider so the caller can flush it on shutdown; `None` means
+/// export is not configured.
+pub fn init(
+    service_name: &str,
+) -> Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>, OtlpError> {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let Some((layer, provider)) = layer(service_name)? else {
+        return Ok(None);
+    };
+    tracing_subscriber::registry()
+        .with(layer)
+        .try_init()
+        .map_err(|source| OtlpError::Subscriber { source })?;
+
+    Ok(Some(provider))
+}
+
+/// The exporting layer, to combine with others such as [`crate::console::ConsoleLayer`]
+///
+/// Like [`init`], but the caller installs the subscriber.
+pub fn layer<S>(
+    service_name: &str,
+) -> Result<
+    Option<(
+        tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::SdkTracer>,
+        opentelemetry_sdk::trace::SdkTracerProvider,
+    )>,
+    OtlpError,
+>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider;
+
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        return Ok(None);
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|source| OtlpError::Exporter { source })?;
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build();
+
+    opentelemetry::global::set_text_map_propagator(
+        opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+    );
+    opentelemetry::global::set_tracer_provider(provider.clone());
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("fastn-p2p"));
+    Ok(Some((layer, provider)))
+}
+
+/// Parent `span` on the caller's span recorded in `trace`
+pub(crate) fn set_parent(span: &tracing::Span, trace: &fastn_net::TraceContext) {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let Some(parent_span_id) = &trace.parent_span_id else {
+        return;
+    };
+    let parent = fastn_net::TraceContext {
+        trace_id: trace.trace_id.clone(),
+        span_id: parent_span_id.clone(),
+        parent_span_id: None,
+    };
+    let carrier = std::collections::HashMap::from([("traceparent".to_string(), parent.traceparent())]);
+    let context = opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    span.set_parent(context);
+}
@@ -0,0 +1,138 @@
+//! Round trips to a peer, for smoke tests and latency checks
+//!
+//! Every server built with [`crate::server::ServerBuilder`] answers
+//! [`fastn_net::Protocol::Ping`] streams itself, before and after the
+//! handshake, without any handler being registered.
+//! [`crate::client::Client::ping`] times one such round trip over the cached
+//! connection to the peer and reports which [`PathType`] it took;
+//! [`PingStats`] sums up a series of them:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::ping::PingStats;
+//! # async fn run(client: fastn_p2p::client::Client, peer: fastn_p2p::PublicKey) {
+//! let mut samples = Vec::new();
+//! for _ in 0..5 {
+//!     samples.push(client.ping(peer).await.ok().map(|pong| pong.rtt));
+//! }
+//! let stats = PingStats::from_samples(samples);
+//! println!("{}/{} answered, avg {:?}", stats.received, stats.sent, stats.avg);
+//! # }
+//! ```
+
+/// How packets to a peer travel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathType {
+    /// Straight to the peer's address, hole-punched or on the same network
+    Direct,
+    /// Through a relay server
+    Relay,
+    /// Through a relay while a direct path is being tried
+    Mixed,
+    /// The endpoint doesn't know (yet)
+    Unknown,
+    /// Not over the network: the peer is a server of this process, see [`crate::server::loopback`]
+    Local,
+}
+
+impl PathType {
+    pub(crate) fn of(endpoint: &iroh::Endpoint, conn: &iroh::endpoint::Connection) -> Self {
+        use iroh::Watcher as _;
+
+        let Some(mut conn_type) = conn.remote_node_id().ok().and_then(|node_id| endpoint.conn_type(node_id)) else {
+            return PathType::Unknown;
+        };
+        match conn_type.get() {
+            iroh::endpoint::ConnectionType::Direct(_) => PathType::Direct,
+            iroh::endpoint::ConnectionType::Relay(_) => PathType::Relay,
+            iroh::endpoint::ConnectionType::Mixed(..) => PathType::Mixed,
+            iroh::endpoint::ConnectionType::None => PathType::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for PathType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathType::Direct => write!(f, "direct"),
+            PathType::Relay => write!(f, "relay"),
+            PathType::Mixed => write!(f, "mixed"),
+            PathType::Unknown => write!(f, "unknown"),
+            PathType::Local => write!(f, "local"),
+        }
+    }
+}
+
+/// One answered ping
+#[derive(Debug, Clone, Copy)]
+pub struct Pong {
+    /// Time from opening the ping stream to reading the peer's answer
+    pub rtt: std::time::Duration,
+    /// Path the connection took when the answer came in
+    pub path: PathType,
+}
+
+/// Summary of a series of pings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingStats {
+    pub sent: u32,
+    pub received: u32,
+    /// `None` when no ping was answered
+    pub min: Option<std::time::Duration>,
+    pub avg: Option<std::time::Duration>,
+    pub max: Option<std::time::Duration>,
+}
+
+impl PingStats {
+    /// Sum up round trip times, `None` for pings that got no answer
+    pub fn from_samples(samples: impl IntoIterator<Item = Option<std::time::Duration>>) -> Self {
+        let mut sent = 0;
+        let mut answered = Vec::new();
+        for sample in samples {
+            sent += 1;
+            answered.extend(sample);
+        }
+        let received = answered.len() as u32;
+        Self {
+            sent,
+            received,
+            min: answered.iter().min().copied(),
+            avg: (received > 0).then(|| answered.iter().sum::<std::time::Duration>() / received),
+            max: answered.iter().max().copied(),
+        }
+    }
+
+    /// Share of pings without an answer, 0 to 100
+    pub fn loss_percent(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        f64::from(self.sent - self.received) * 100.0 / f64::from(self.sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_skip_lost_pings() {
+        let ms = std::time::Duration::from_millis;
+        let stats = PingStats::from_samples([Some(ms(10)), None, Some(ms(30)), Some(ms(20))]);
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.min, Some(ms(10)));
+        assert_eq!(stats.avg, Some(ms(20)));
+        assert_eq!(stats.max, Some(ms(30)));
+        assert_eq!(stats.loss_percent(), 25.0);
+    }
+
+    #[test]
+    fn test_stats_of_no_answers() {
+        let stats = PingStats::from_samples([None, None]);
+        assert_eq!(stats.received, 0);
+        assert_eq!(stats.avg, None);
+        assert_eq!(stats.loss_percent(), 100.0);
+        assert_eq!(PingStats::from_samples([]).loss_percent(), 0.0);
+    }
+}
@@ -0,0 +1,347 @@
+//! Progress of long transfers
+//!
+//! An [`Observer`] counts the bytes moved by a transfer and reports a
+//! [`Progress`] snapshot (bytes so far, average rate, ETA when the total is
+//! known) at most once per interval, plus once when the transfer ends. Hand it
+//! to the `*_with_progress` copy methods of [`crate::Session`] and
+//! [`crate::client::Session`], or wrap any reader/writer in [`Observed`].
+//!
+//! ```rust,no_run
+//! # async fn run(session: &mut fastn_p2p::Session<()>, mut file: tokio::fs::File) -> std::io::Result<()> {
+//! # let file_len = file.metadata().await?.len();
+//! let observer = fastn_p2p::progress::Observer::new(|progress| {
+//!     eprint!("\r{}", progress.bar(30));
+//! })
+//! .with_total(file_len);
+//! session.copy_from_with_progress(&mut file, &observer).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Observer::watch`] publishes the snapshots on a `tokio::sync::watch`
+//! channel instead, for UIs that poll.
+
+use std::time::{Duration, Instant};
+
+/// How often an [`Observer`] reports by default
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Snapshot of a transfer
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Progress {
+    /// Bytes transferred so far
+    pub bytes: u64,
+    /// Bytes expected in total, if known
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+    /// Average bytes per second since the start
+    pub rate: f64,
+    /// Time left at the current rate; `None` without a total or before any bytes moved
+    pub eta: Option<Duration>,
+    /// This is the last report of the transfer
+    pub done: bool,
+}
+
+impl Progress {
+    /// Share of the total transferred, `0.0..=1.0`
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|total| match total {
+            0 => 1.0,
+            total => (self.bytes as f64 / total as f64).min(1.0),
+        })
+    }
+
+    /// One-line progress bar, `width` characters wide, for terminals
+    ///
+    /// Without a known total only the byte count and rate are shown.
+    pub fn bar(&self, width: usize) -> String {
+        let rate = format!("{}/s", format_bytes(self.rate as u64));
+        let Some(fraction) = self.fraction() else {
+            return format!("{} {}", format_bytes(self.bytes), rate);
+        };
+
+        let filled = ((fraction * width as f64) as usize).min(width);
+        let eta = match self.eta {
+            Some(eta) if !self.done => format!(" ETA {}", format_duration(eta)),
+            _ => String::new(),
+        };
+        format!(
+            "[{}{}] {:>3}% {}/{} {}{}",
+            "#".repeat(filled),
+            "-".repeat(width - filled),
+            (fraction * 100.0) as u32,
+            format_bytes(self.bytes),
+            format_bytes(self.total.unwrap_or_default()),
+            rate,
+            eta,
+        )
+    }
+}
+
+/// Counts the bytes of a transfer and reports its [`Progress`]
+///
+/// Clones share the count, so one observer can follow a transfer made of
+/// several copies (e.g. across reconnects).
+#[derive(Clone)]
+pub struct Observer {
+    state: std::sync::Arc<std::sync::Mutex<State>>,
+}
+
+struct State {
+    callback: Box<dyn FnMut(&Progress) + Send>,
+    total: Option<u64>,
+    interval: Duration,
+    started: Instant,
+    last_report: Option<Instant>,
+    bytes: u64,
+}
+
+impl State {
+    fn progress(&self, now: Instant, done: bool) -> Progress {
+        let elapsed = now.duration_since(self.started);
+        let rate = match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 / secs,
+            _ => 0.0,
+        };
+        let eta = match self.total {
+            Some(total) if rate > 0.0 => Some(Duration::from_secs_f64(total.saturating_sub(self.bytes) as f64 / rate)),
+            _ => None,
+        };
+        Progress { bytes: self.bytes, total: self.total, elapsed, rate, eta, done }
+    }
+
+    fn report(&mut self, now: Instant, done: bool) {
+        let progress = self.progress(now, done);
+        self.last_report = Some(now);
+        (self.callback)(&progress);
+    }
+}
+
+impl Observer {
+    /// Call `callback` with every report
+    pub fn new(callback: impl FnMut(&Progress) + Send + 'static) -> Self {
+        Self {
+            state: std::sync::Arc::new(std::sync::Mutex::new(State {
+                callback: Box::new(callback),
+                total: None,
+                interval: DEFAULT_INTERVAL,
+                started: Instant::now(),
+                last_report: None,
+                bytes: 0,
+            })),
+        }
+    }
+
+    /// Publish every report on a watch channel
+    pub fn watch() -> (Self, tokio::sync::watch::Receiver<Progress>) {
+        let (sender, receiver) = tokio::sync::watch::channel(Progress::default());
+        let observer = Self::new(move |progress| {
+            sender.send_replace(progress.clone());
+        });
+        (observer, receiver)
+    }
+
+    /// Bytes the transfer is expected to move, for the percentage and ETA
+    pub fn with_total(self, total: u64) -> Self {
+        self.lock().total = Some(total);
+        self
+    }
+
+    /// Report at most once per `interval` (default [`DEFAULT_INTERVAL`])
+    pub fn with_interval(self, interval: Duration) -> Self {
+        self.lock().interval = interval;
+        self
+    }
+
+    /// Count `bytes` more; reports if the interval has passed
+    pub fn add(&self, bytes: u64) {
+        self.add_at(bytes, Instant::now());
+    }
+
+    fn add_at(&self, bytes: u64, now: Instant) {
+        let mut state = self.lock();
+        state.bytes += bytes;
+        let due = match state.last_report {
+            Some(last) => now.duration_since(last) >= state.interval,
+            None => true,
+        };
+        if due {
+            state.report(now, false);
+        }
+    }
+
+    /// Report the final [`Progress`], with `done` set
+    pub fn finish(&self) {
+        self.lock().report(Instant::now(), true);
+    }
+
+    /// Current progress, without reporting it
+    pub fn progress(&self) -> Progress {
+        self.lock().progress(Instant::now(), false)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("Failed to acquire lock on progress state")
+    }
+}
+
+impl std::fmt::Debug for Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Observer").field("progress", &self.progress()).finish()
+    }
+}
+
+/// A reader or writer that counts the bytes passing through it
+#[derive(Debug)]
+pub struct Observed<T> {
+    inner: T,
+    observer: Observer,
+}
+
+impl<T> Observed<T> {
+    pub fn new(inner: T, observer: Observer) -> Self {
+        Self { inner, observer }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Observed<T> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = std::pin::Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        if read > 0 {
+            self.observer.add(read as u64);
+        }
+        poll
+    }
+}
+
+impl<T: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for Observed<T> {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let poll = std::pin::Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let std::task::Poll::Ready(Ok(written)) = poll {
+            self.observer.add(written as u64);
+        }
+        poll
+    }
+
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Copy `reader` to `writer`, counting into `observer`, and report the end
+pub(crate) async fn copy<R, W>(reader: &mut R, writer: &mut W, observer: &Observer) -> std::io::Result<u64>
+where
+    R: tokio::io::AsyncRead + Unpin + ?Sized,
+    W: tokio::io::AsyncWrite + Unpin + ?Sized,
+{
+    let copied = tokio::io::copy(&mut Observed::new(reader, observer.clone()), writer).await;
+    observer.finish();
+    copied
+}
+
+/// `bytes` in binary units, e.g. `1.5 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttled_reports() {
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let observer = Observer::new(move |progress: &Progress| seen.lock().unwrap().push(progress.clone()))
+            .with_total(1000)
+            .with_interval(Duration::from_secs(1));
+
+        let start = observer.lock().started;
+        observer.add_at(100, start + Duration::from_secs(1));
+        observer.add_at(100, start + Duration::from_millis(1500));
+        observer.add_at(300, start + Duration::from_secs(2));
+        observer.finish();
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.iter().map(|p| p.bytes).collect::<Vec<_>>(), vec![100, 500, 500]);
+        assert_eq!(reports[0].rate, 100.0);
+        assert_eq!(reports[0].eta, Some(Duration::from_secs(9)));
+        assert_eq!(reports[1].fraction(), Some(0.5));
+        assert!(reports[2].done);
+    }
+
+    #[test]
+    fn test_bar() {
+        let progress = Progress {
+            bytes: 512 * 1024,
+            total: Some(2048 * 1024),
+            elapsed: Duration::from_secs(2),
+            rate: 256.0 * 1024.0,
+            eta: Some(Duration::from_secs(6)),
+            done: false,
+        };
+        assert_eq!(progress.bar(8), "[##------]  25% 512.0 KiB/2.0 MiB 256.0 KiB/s ETA 6s");
+
+        let unknown = Progress { total: None, ..progress };
+        assert_eq!(unknown.bar(8), "512.0 KiB 256.0 KiB/s");
+    }
+
+    #[tokio::test]
+    async fn test_observed_copy() {
+        let (observer, mut watch) = Observer::watch();
+        let observer = observer.with_total(11);
+
+        let mut output = Vec::new();
+        let copied = copy(&mut &b"hello world"[..], &mut output, &observer).await.unwrap();
+        assert_eq!(copied, 11);
+        assert_eq!(output, b"hello world");
+
+        let last = watch.borrow_and_update().clone();
+        assert_eq!(last.bytes, 11);
+        assert_eq!(last.fraction(), Some(1.0));
+        assert!(last.done);
+    }
+}
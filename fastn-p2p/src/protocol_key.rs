@@ -0,0 +1,123 @@
+//! Canonical form of protocol values
+//!
+//! Handlers are looked up by the JSON of their protocol, so two peers must
+//! produce exactly the same value for it. Both sides pass it through
+//! [`canonicalize`] first, which turns whole floats into integers. Field
+//! order needs nothing: without serde_json's `preserve_order` feature an
+//! object keeps its fields sorted by name, so `{"b": 1.0, "a": "x"}` and
+//! `{"a": "x", "b": 1}` find the same handler.
+//!
+//! Differences in serde representation, such as `rename_all` or an
+//! internally tagged enum on one side only, still give different protocols.
+//! When a protocol finds no handler but a [`similar`] one exists, the server
+//! and client log a warning naming both.
+
+/// The protocol value both peers agree on
+pub fn canonicalize(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(fields) => {
+            serde_json::Value::Object(fields.into_iter().map(|(name, value)| (name, canonicalize(value))).collect())
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.into_iter().map(canonicalize).collect()),
+        serde_json::Value::Number(number) => serde_json::Value::Number(canonical_number(number)),
+        other => other,
+    }
+}
+
+/// The canonical protocol value of `protocol`
+pub fn of<P: serde::Serialize + ?Sized>(protocol: &P) -> Result<serde_json::Value, serde_json::Error> {
+    serde_json::to_value(protocol).map(canonicalize)
+}
+
+/// Stable string form of `protocol`, the same on every peer
+pub fn to_key_string(protocol: &serde_json::Value) -> String {
+    canonicalize(protocol.clone()).to_string()
+}
+
+/// A protocol among `known` that `wanted` was probably meant to be
+///
+/// Two protocols are similar when they differ but name the same variant,
+/// ignoring case, `_` and `-`: `"FileTransfer"`, `"file_transfer"`,
+/// `{"FileTransfer": null}` and `{"type": "FileTransfer", ...}` all name
+/// `filetransfer`.
+pub fn similar<'a>(
+    wanted: &serde_json::Value,
+    known: impl IntoIterator<Item = &'a serde_json::Value>,
+) -> Option<&'a serde_json::Value> {
+    let name = variant_name(wanted)?;
+    known
+        .into_iter()
+        .find(|candidate| *candidate != wanted && variant_name(candidate).as_deref() == Some(name.as_str()))
+}
+
+fn variant_name(protocol: &serde_json::Value) -> Option<String> {
+    let name = match protocol {
+        serde_json::Value::String(name) => name.as_str(),
+        // An internally tagged unit variant is a single field too, so tags come first
+        serde_json::Value::Object(fields) => match ["type", "tag", "kind"]
+            .iter()
+            .find_map(|tag| fields.get(*tag).and_then(|value| value.as_str()))
+        {
+            Some(name) => name,
+            None if fields.len() == 1 => fields.keys().next()?.as_str(),
+            None => return None,
+        },
+        _ => return None,
+    };
+    Some(name.chars().filter(|c| *c != '_' && *c != '-').flat_map(char::to_lowercase).collect())
+}
+
+/// Whole floats within the exactly representable range become integers
+fn canonical_number(number: serde_json::Number) -> serde_json::Number {
+    const EXACT: f64 = 9_007_199_254_740_992.0;
+    match number.as_f64() {
+        Some(float) if number.is_f64() && float.fract() == 0.0 && float.abs() <= EXACT => {
+            if float >= 0.0 {
+                serde_json::Number::from(float as u64)
+            } else {
+                serde_json::Number::from(float as i64)
+            }
+        }
+        _ => number,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Serialize)]
+    enum Storage {
+        Get { bucket: String, key: String },
+        Put(String, u32),
+        List,
+    }
+
+    #[derive(serde::Serialize)]
+    #[serde(tag = "type")]
+    enum TaggedStorage {
+        List,
+    }
+
+    #[test]
+    fn test_enum_with_data_protocols() {
+        let get = of(&Storage::Get { bucket: "photos".to_string(), key: "a.jpg".to_string() }).unwrap();
+        let reordered: serde_json::Value =
+            serde_json::from_str(r#"{"Get": {"key": "a.jpg", "bucket": "photos"}}"#).unwrap();
+        assert_eq!(canonicalize(reordered.clone()), get);
+        assert_eq!(to_key_string(&reordered), r#"{"Get":{"bucket":"photos","key":"a.jpg"}}"#);
+
+        let put = of(&Storage::Put("photos".to_string(), 3)).unwrap();
+        assert_eq!(canonicalize(serde_json::json!({"Put": ["photos", 3.0]})), put);
+        assert_ne!(canonicalize(serde_json::json!({"Put": ["photos", 3.5]})), put);
+
+        // Same variant, different representation: not equal, but similar
+        let list = of(&Storage::List).unwrap();
+        let tagged = of(&TaggedStorage::List).unwrap();
+        assert_ne!(list, tagged);
+        assert_eq!(similar(&tagged, [&get, &list]), Some(&list));
+        assert_eq!(similar(&serde_json::json!("list"), [&list]), Some(&list));
+        assert_eq!(similar(&list, [&list]), None);
+        assert_eq!(similar(&serde_json::json!("Echo"), [&get, &put, &list]), None);
+    }
+}
@@ -0,0 +1,374 @@
+//! Starter crates for new serve_all protocols
+//!
+//! [`generate`] lays out a protocol crate that is wired to
+//! [`crate::serve_all`] and builds as is: typed request, response and error
+//! structs for one `hello` command, lifecycle handlers, a per-binding config
+//! with a JSON Schema, tests that dispatch in-process through
+//! [`crate::server::serve_all::ServeAllBuilder::dispatch_request`], and a
+//! README. `fastn-p2p new-protocol` writes it to disk.
+
+/// A generated protocol crate, not yet written anywhere
+#[derive(Debug, Clone)]
+pub struct Scaffold {
+    /// Protocol name bindings are added under, e.g. `mail.example.com`
+    pub protocol: String,
+    /// Cargo package name, e.g. `mail-example-com`
+    pub crate_name: String,
+    /// Paths relative to the crate root, with their contents
+    pub files: Vec<(std::path::PathBuf, String)>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScaffoldError {
+    #[error(
+        "Invalid protocol name '{name}': use lowercase letters, digits, '-', '_' and '.', starting with a letter"
+    )]
+    InvalidName { name: String },
+}
+
+/// The crate for protocol `name`, e.g. `mail` or `mail.example.com`
+pub fn generate(name: &str) -> Result<Scaffold, ScaffoldError> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'))
+        && !name.ends_with('.')
+        && !name.contains("..");
+    if !valid {
+        return Err(ScaffoldError::InvalidName { name: name.to_string() });
+    }
+
+    let crate_name = name.replace(['.', '_'], "-");
+    let crate_ident = crate_name.replace('-', "_");
+    // `file-share.example.com` -> `FileShare`
+    let type_prefix: String = name
+        .split('.')
+        .next()
+        .unwrap_or(name)
+        .split(['-', '_'])
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_ascii_uppercase()).into_iter().chain(chars).collect::<String>()
+        })
+        .collect();
+
+    let render = |template: &str| {
+        template
+            .replace("{{protocol}}", name)
+            .replace("{{crate_name}}", &crate_name)
+            .replace("{{crate_ident}}", &crate_ident)
+            .replace("{{Type}}", &type_prefix)
+            .replace("{{fastn_p2p_version}}", env!("CARGO_PKG_VERSION"))
+    };
+    let files = [
+        ("Cargo.toml", CARGO_TOML),
+        ("README.md", README),
+        ("config.schema.json", CONFIG_SCHEMA),
+        ("src/lib.rs", LIB_RS),
+        ("src/main.rs", MAIN_RS),
+        ("tests/protocol.rs", TESTS_RS),
+    ]
+    .into_iter()
+    .map(|(path, template)| (std::path::PathBuf::from(path), render(template)))
+    .collect();
+
+    Ok(Scaffold { protocol: name.to_string(), crate_name, files })
+}
+
+const CARGO_TOML: &str = r###"[package]
+name = "{{crate_name}}"
+version = "0.1.0"
+edition = "2024"
+description = "{{protocol}} protocol for fastn-p2p"
+
+[dependencies]
+fastn-p2p = "{{fastn_p2p_version}}"
+fastn-id52 = "0.1"
+serde = { version = "1", features = ["derive"] }
+serde_json = "1"
+thiserror = "2"
+tokio = { version = "1", features = ["full"] }
+tracing = "0.1"
+"###;
+
+const README: &str = r###"# {{crate_name}}
+
+The `{{protocol}}` protocol for [fastn-p2p](https://github.com/fastn-stack/p2p).
+
+## Commands
+
+| Command | Request | Response |
+|---------|---------|----------|
+| `hello` | `HelloRequest { name }` | `HelloResponse { greeting }` |
+
+Failures are `{{Type}}Error` values.
+
+## Configuration
+
+Each binding reads `config.json` from its protocol directory; see
+`config.schema.json` for the fields. Missing fields take their defaults, and
+`on_create` writes the defaults for new bindings.
+
+## Running
+
+```bash
+fastn-p2p create-identity alice
+fastn-p2p add-protocol alice --protocol {{protocol}} --config '{"greeting": "Hi"}'
+fastn-p2p identity-online alice
+cargo run
+
+echo '{"name": "Bob"}' | fastn-p2p call <alice-peer-id> {{protocol}} hello
+```
+
+## Testing
+
+`cargo test` runs the commands in-process, the way serve_all dispatches
+them for peers, against a throwaway FASTN_HOME.
+"###;
+
+const CONFIG_SCHEMA: &str = r###"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "{{protocol}} binding config",
+  "type": "object",
+  "additionalProperties": false,
+  "properties": {
+    "greeting": {
+      "description": "Prefix of every greeting",
+      "type": "string",
+      "default": "Hello"
+    },
+    "max_name_length": {
+      "description": "Longest name accepted",
+      "type": "integer",
+      "minimum": 1,
+      "default": 64
+    }
+  }
+}
+"###;
+
+const LIB_RS: &str = r###"//! The {{protocol}} protocol
+//!
+//! [`register`] adds its commands and lifecycle to `fastn_p2p::serve_all()`,
+//! see `src/main.rs`.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+/// Protocol name bindings are added under
+pub const PROTOCOL: &str = "{{protocol}}";
+
+type Reply<T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+
+/// Settings of one binding, read from `<protocol_dir>/config.json`
+///
+/// Keep `config.schema.json` in step with this struct.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Prefix of every greeting
+    pub greeting: String,
+    /// Longest name accepted
+    pub max_name_length: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { greeting: "Hello".to_string(), max_name_length: 64 }
+    }
+}
+
+impl Config {
+    /// The binding's config; defaults if it has no `config.json`
+    pub async fn load(protocol_dir: &Path) -> Result<Self, {{Type}}Error> {
+        match tokio::fs::read_to_string(protocol_dir.join("config.json")).await {
+            Ok(contents) => {
+                serde_json::from_str(&contents).map_err(|e| {{Type}}Error::InvalidConfig { message: e.to_string() })
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err({{Type}}Error::InvalidConfig { message: e.to_string() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HelloRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HelloResponse {
+    pub greeting: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+pub enum {{Type}}Error {
+    #[error("Name must be 1 to {max} characters")]
+    InvalidName { max: usize },
+    #[error("Invalid config.json: {message}")]
+    InvalidConfig { message: String },
+}
+
+/// Commands and lifecycle of [`PROTOCOL`]
+pub fn register(
+    protocol: fastn_p2p::server::serve_all::ProtocolBuilder,
+) -> fastn_p2p::server::serve_all::ProtocolBuilder {
+    protocol
+        .handle_requests("hello", hello)
+        .on_create(on_create)
+        .on_check(on_check)
+        .on_activate(on_activate)
+        .on_reload(on_reload)
+        .on_deactivate(on_deactivate)
+}
+
+/// `hello`: greet the caller by name
+pub fn hello(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    _peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    Box::pin(async move {
+        let config = Config::load(&protocol_dir).await?;
+        let request: HelloRequest = serde_json::from_value(request)?;
+        if request.name.is_empty() || request.name.chars().count() > config.max_name_length {
+            return Err({{Type}}Error::InvalidName { max: config.max_name_length }.into());
+        }
+        let response = HelloResponse { greeting: format!("{}, {}!", config.greeting, request.name) };
+        Ok(serde_json::to_value(response)?)
+    })
+}
+
+/// New binding: write the default config unless `add-protocol` gave one
+fn on_create(ctx: fastn_p2p::server::serve_all::BindingContext) -> Reply<()> {
+    Box::pin(async move {
+        let config_file = ctx.protocol_dir.join("config.json");
+        if !tokio::fs::try_exists(&config_file).await? {
+            tokio::fs::create_dir_all(&ctx.protocol_dir).await?;
+            tokio::fs::write(&config_file, serde_json::to_string_pretty(&Config::default())?).await?;
+        }
+        Ok(())
+    })
+}
+
+/// Health check: the config must parse
+fn on_check(ctx: fastn_p2p::server::serve_all::BindingContext) -> Reply<()> {
+    Box::pin(async move {
+        Config::load(&ctx.protocol_dir).await?;
+        Ok(())
+    })
+}
+
+fn on_activate(ctx: fastn_p2p::server::serve_all::BindingContext) -> Reply<()> {
+    Box::pin(async move {
+        tracing::info!("🚀 {} active for {} as {}", PROTOCOL, ctx.identity.id52(), ctx.bind_alias);
+        Ok(())
+    })
+}
+
+/// The config is read per request, so a reload only has to validate it
+fn on_reload(ctx: fastn_p2p::server::serve_all::BindingContext) -> Reply<()> {
+    Box::pin(async move {
+        Config::load(&ctx.protocol_dir).await?;
+        tracing::info!("🔄 {} reloaded for {} as {}", PROTOCOL, ctx.identity.id52(), ctx.bind_alias);
+        Ok(())
+    })
+}
+
+fn on_deactivate(ctx: fastn_p2p::server::serve_all::BindingContext) -> Reply<()> {
+    Box::pin(async move {
+        tracing::info!("🛑 {} inactive for {} as {}", PROTOCOL, ctx.identity.id52(), ctx.bind_alias);
+        Ok(())
+    })
+}
+"###;
+
+const MAIN_RS: &str = r###"//! Serves every {{protocol}} binding configured in FASTN_HOME
+
+#[fastn_p2p::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    fastn_p2p::serve_all()
+        .protocol({{crate_ident}}::PROTOCOL, {{crate_ident}}::register)
+        .serve()
+        .await
+}
+"###;
+
+const TESTS_RS: &str = r###"//! Commands run in-process, the way serve_all dispatches them for peers
+
+use std::path::{Path, PathBuf};
+
+/// A throwaway FASTN_HOME and the protocol dir of alice's `default` binding
+async fn home(test: &str) -> (PathBuf, PathBuf) {
+    let home = std::env::temp_dir().join(format!("{{crate_name}}-{}-{}", test, std::process::id()));
+    let protocol_dir = home.join("identities/alice/protocols").join({{crate_ident}}::PROTOCOL).join("default");
+    tokio::fs::create_dir_all(&protocol_dir).await.unwrap();
+    (home, protocol_dir)
+}
+
+async fn hello(
+    home: &Path,
+    protocol_dir: &Path,
+    request: serde_json::Value,
+) -> Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>> {
+    let server = fastn_p2p::serve_all()
+        .with_fastn_home(home.clone())
+        .protocol({{crate_ident}}::PROTOCOL, {{crate_ident}}::register);
+    let peer = fastn_id52::SecretKey::from_seed_phrase("{{crate_name}} test peer").public_key();
+    let command = fastn_p2p::server::CommandProtocol::new({{crate_ident}}::PROTOCOL, "default", "hello");
+    server
+        .dispatch_request(&peer, "alice", &command, protocol_dir, request, None)
+        .await
+}
+
+#[tokio::test]
+async fn test_hello_uses_binding_config() {
+    let (home, protocol_dir) = home("config").await;
+
+    let response = hello(&home, &protocol_dir, serde_json::json!({ "name": "Bob" })).await.unwrap();
+    let response: {{crate_ident}}::HelloResponse = serde_json::from_value(response).unwrap();
+    assert_eq!(response.greeting, "Hello, Bob!");
+
+    tokio::fs::write(protocol_dir.join("config.json"), r#"{"greeting": "Hi"}"#).await.unwrap();
+    let response = hello(&home, &protocol_dir, serde_json::json!({ "name": "Bob" })).await.unwrap();
+    assert_eq!(response["greeting"], "Hi, Bob!");
+
+    let error = hello(&home, &protocol_dir, serde_json::json!({ "name": "" })).await.unwrap_err();
+    assert!(error.to_string().contains("Name must be"), "{error}");
+
+    tokio::fs::remove_dir_all(&home).await.ok();
+}
+
+#[test]
+fn test_config_schema_lists_every_field() {
+    let schema: serde_json::Value = serde_json::from_str(include_str!("../config.schema.json")).unwrap();
+    let config = serde_json::to_value({{crate_ident}}::Config::default()).unwrap();
+    for (field, value) in config.as_object().unwrap() {
+        assert_eq!(&schema["properties"][field]["default"], value, "config.schema.json default of {field}");
+    }
+}
+"###;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_generate_fills_every_placeholder() {
+        let scaffold = super::generate("file-share.example.com").unwrap();
+        assert_eq!(scaffold.crate_name, "file-share-example-com");
+
+        let files: std::collections::BTreeMap<_, _> = scaffold.files.into_iter().collect();
+        assert!(files.values().all(|contents| !contents.contains("{{")));
+        assert!(files[std::path::Path::new("src/lib.rs")].contains("pub enum FileShareError"));
+        assert!(files[std::path::Path::new("src/main.rs")].contains("file_share_example_com::register"));
+        serde_json::from_str::<serde_json::Value>(&files[std::path::Path::new("config.schema.json")]).unwrap();
+
+        for name in ["", "Mail", "1mail", "mail/../x", "mail.", "mail..com"] {
+            assert!(super::generate(name).is_err(), "{name}");
+        }
+    }
+}
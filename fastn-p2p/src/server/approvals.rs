@@ -0,0 +1,308 @@
+//! Human-in-the-loop approval of incoming streams
+//!
+//! A stream handler for something like a file drop calls
+//! [`super::Session::request_approval`] before reading any bytes. The request
+//! waits here until the user accepts or denies it, through
+//! `fastn-p2p approvals accept/deny` or anything else calling [`resolve`], or
+//! until it expires:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::Session;
+//! # use std::path::PathBuf;
+//! # type Error = Box<dyn std::error::Error + Send + Sync>;
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum FileDrop { Send }
+//! # struct Offer { name: String, size: u64 }
+//! async fn receive(mut session: Session<FileDrop>, offer: Offer, dir: PathBuf) -> Result<(), Error> {
+//!     session.request_approval(format!("{} ({} bytes)", offer.name, offer.size)).await?;
+//!     session.copy_to(tokio::fs::File::create(dir.join(&offer.name)).await?).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Waiting approvals live in the memory of the serving process. A process
+//! that calls [`share_in`], as `serve_all` does, also writes them to
+//! `FASTN_HOME/approvals/`; the daemon answers `fastn-p2p approvals` from
+//! there ([`pending_in`]) and writes the user's decision next to them
+//! ([`resolve_in`]) for the serving process to pick up. They are announced
+//! on the serving process's [`crate::events`] bus as well. The peer just sees
+//! its stream stall until the decision.
+
+pub use fastn_p2p_client::admin::PendingApproval;
+
+/// How long a stream waits for the user before it is denied
+pub const APPROVAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Shared approvals inside FASTN_HOME, see [`share_in`]
+pub const APPROVALS_DIR: &str = "approvals";
+
+/// How often a shared approval looks for a decision made by another process
+const DECISION_POLL: std::time::Duration = std::time::Duration::from_millis(250);
+
+static SHARED_IN: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+/// Why a stream was not approved
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ApprovalError {
+    #[error("Denied by the user")]
+    Denied,
+
+    #[error("Not approved within {timeout:?}")]
+    Expired { timeout: std::time::Duration },
+
+    #[error("Peer left while waiting for approval: {reason}")]
+    Closed { reason: super::CloseReason },
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("No approval '{id}' is waiting")]
+pub struct ApprovalNotFound {
+    pub id: String,
+}
+
+struct Waiting {
+    approval: PendingApproval,
+    decision: tokio::sync::oneshot::Sender<bool>,
+}
+
+static WAITING: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<String, Waiting>>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// Approvals waiting right now, oldest first
+pub fn pending() -> Vec<PendingApproval> {
+    let waiting = WAITING.lock().expect("Approvals lock poisoned");
+    let mut approvals: Vec<_> = waiting.values().map(|waiting| waiting.approval.clone()).collect();
+    approvals.sort_by(|a, b| (a.requested_ms, &a.id).cmp(&(b.requested_ms, &b.id)));
+    approvals
+}
+
+/// Accept (`approve`) or deny the approval `id`; the waiting handler goes on
+pub fn resolve(id: &str, approve: bool) -> Result<PendingApproval, ApprovalNotFound> {
+    let waiting = WAITING
+        .lock()
+        .expect("Approvals lock poisoned")
+        .remove(id)
+        .ok_or_else(|| ApprovalNotFound { id: id.to_string() })?;
+    // The handler may have given up in between; it then publishes the outcome itself
+    if waiting.decision.send(approve).is_ok() {
+        crate::events::publish(crate::events::EventKind::ApprovalResolved { id: id.to_string(), approved: approve });
+    }
+    Ok(waiting.approval)
+}
+
+/// Also write this process's waiting approvals to `fastn_home`, for the daemon
+///
+/// Only the first call has an effect.
+pub fn share_in(fastn_home: &std::path::Path) {
+    let _ = SHARED_IN.set(fastn_home.join(APPROVALS_DIR));
+}
+
+/// Where [`share_in`] said to write approvals, if anywhere
+pub(crate) fn shared_dir() -> Option<&'static std::path::Path> {
+    SHARED_IN.get().map(std::path::PathBuf::as_path)
+}
+
+/// Approvals other processes shared in `fastn_home` and nobody decided yet, oldest first
+pub fn pending_in(fastn_home: &std::path::Path) -> Vec<PendingApproval> {
+    let dir = fastn_home.join(APPROVALS_DIR);
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let now = now_ms();
+    let mut approvals: Vec<PendingApproval> = entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "json"))
+        .filter_map(|entry| serde_json::from_slice(&std::fs::read(entry.path()).ok()?).ok())
+        .filter(|approval: &PendingApproval| approval.expires_ms > now && !decision_file(&dir, &approval.id).exists())
+        .collect();
+    approvals.sort_by(|a, b| (a.requested_ms, &a.id).cmp(&(b.requested_ms, &b.id)));
+    approvals
+}
+
+/// Accept or deny approval `id` shared in `fastn_home`; its process goes on shortly
+pub fn resolve_in(fastn_home: &std::path::Path, id: &str, approve: bool) -> Result<PendingApproval, ApprovalNotFound> {
+    let not_found = || ApprovalNotFound { id: id.to_string() };
+    // IDs become file names, so nothing but what `request` makes is looked up
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(not_found());
+    }
+    let dir = fastn_home.join(APPROVALS_DIR);
+    let approval = pending_in(fastn_home).into_iter().find(|approval| approval.id == id).ok_or_else(not_found)?;
+    // Written aside and renamed, so the waiting process never reads half a decision
+    let partial = dir.join(format!("{id}.partial"));
+    std::fs::write(&partial, if approve { "accept" } else { "deny" })
+        .and_then(|()| std::fs::rename(&partial, decision_file(&dir, id)))
+        .map_err(|e| {
+            tracing::warn!("Failed to write decision of approval {}: {}", id, e);
+            not_found()
+        })?;
+    Ok(approval)
+}
+
+fn decision_file(dir: &std::path::Path, id: &str) -> std::path::PathBuf {
+    dir.join(format!("{id}.decision"))
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// The decision another process made on shared approval `id`; never resolves if not shared
+async fn shared_decision(dir: Option<&std::path::Path>, id: &str) -> bool {
+    let Some(dir) = dir else {
+        return std::future::pending().await;
+    };
+    let file = decision_file(dir, id);
+    let mut poll = tokio::time::interval(DECISION_POLL);
+    loop {
+        poll.tick().await;
+        if let Ok(decision) = tokio::fs::read_to_string(&file).await {
+            return decision == "accept";
+        }
+    }
+}
+
+/// Wait for the user to approve `summary`, or for `closed` or the timeout
+///
+/// With `shared`, the approval is also written there for other processes, see [`share_in`].
+pub(crate) async fn request(
+    identity: fastn_id52::PublicKey,
+    peer: fastn_id52::PublicKey,
+    protocol: String,
+    summary: String,
+    timeout: std::time::Duration,
+    closed: impl std::future::Future<Output = super::CloseReason>,
+    shared: Option<&std::path::Path>,
+) -> Result<(), ApprovalError> {
+    let now = now_ms();
+    let approval = PendingApproval {
+        id: format!("{:016x}", rand::random::<u64>()),
+        identity: identity.id52(),
+        peer: peer.id52(),
+        protocol,
+        summary,
+        requested_ms: now,
+        expires_ms: now + timeout.as_millis() as u64,
+    };
+    let id = approval.id.clone();
+    let (decision, decided) = tokio::sync::oneshot::channel();
+    tracing::info!("Stream from {} waits for approval {}: {}", approval.peer, id, approval.summary);
+    crate::events::publish(crate::events::EventKind::ApprovalRequested {
+        id: id.clone(),
+        identity: approval.identity.clone(),
+        peer: approval.peer.clone(),
+        protocol: approval.protocol.clone(),
+        summary: approval.summary.clone(),
+        expires_ms: approval.expires_ms,
+    });
+    if let Some(dir) = shared {
+        let written = match serde_json::to_vec(&approval) {
+            Ok(json) => match tokio::fs::create_dir_all(dir).await {
+                Ok(()) => tokio::fs::write(dir.join(format!("{id}.json")), json).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = written {
+            tracing::warn!("Failed to share approval {} in {}: {}", id, dir.display(), e);
+        }
+    }
+    WAITING
+        .lock()
+        .expect("Approvals lock poisoned")
+        .insert(id.clone(), Waiting { approval, decision });
+
+    let result = tokio::select! {
+        decided = decided => match decided {
+            Ok(true) => Ok(()),
+            // The sender only goes away through `resolve`
+            Ok(false) | Err(_) => Err(ApprovalError::Denied),
+        },
+        approved = shared_decision(shared, &id) => if approved { Ok(()) } else { Err(ApprovalError::Denied) },
+        _ = tokio::time::sleep(timeout) => Err(ApprovalError::Expired { timeout }),
+        reason = closed => Err(ApprovalError::Closed { reason }),
+    };
+    if let Some(dir) = shared {
+        let _ = tokio::fs::remove_file(dir.join(format!("{id}.json"))).await;
+        let _ = tokio::fs::remove_file(decision_file(dir, &id)).await;
+    }
+    // Still waiting means `resolve` didn't decide: another process did, or nobody;
+    // otherwise `resolve` published its decision, which may have lost the race
+    if WAITING.lock().expect("Approvals lock poisoned").remove(&id).is_some() {
+        crate::events::publish(crate::events::EventKind::ApprovalResolved { id, approved: result.is_ok() });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn test_approval_waits_for_the_user() {
+        let identity = fastn_id52::SecretKey::generate().public_key();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let timeout = std::time::Duration::from_secs(60);
+        let request = |summary: &str| {
+            super::request(identity, peer, "drop".to_string(), summary.to_string(), timeout, std::future::pending(), None)
+        };
+
+        let accepted = tokio::spawn(request("a.txt"));
+        let denied = tokio::spawn(request("b.txt"));
+        let waiting = loop {
+            let waiting: Vec<_> = super::pending().into_iter().filter(|a| a.peer == peer.id52()).collect();
+            if waiting.len() == 2 {
+                break waiting;
+            }
+            tokio::task::yield_now().await;
+        };
+        let id_of = |summary: &str| waiting.iter().find(|a| a.summary == summary).unwrap().id.clone();
+
+        super::resolve(&id_of("a.txt"), true).unwrap();
+        super::resolve(&id_of("b.txt"), false).unwrap();
+        assert_eq!(accepted.await.unwrap(), Ok(()));
+        assert_eq!(denied.await.unwrap(), Err(super::ApprovalError::Denied));
+        assert!(super::resolve(&id_of("a.txt"), true).is_err());
+
+        let expired = super::request(
+            identity, peer, "drop".to_string(), "c.txt".to_string(),
+            std::time::Duration::from_millis(10), std::future::pending(), None,
+        );
+        assert!(matches!(expired.await, Err(super::ApprovalError::Expired { .. })));
+        assert!(super::pending().iter().all(|a| a.peer != peer.id52()));
+    }
+    #[tokio::test]
+    async fn test_shared_approvals_are_decided_by_another_process() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join(super::APPROVALS_DIR);
+        let identity = fastn_id52::SecretKey::generate().public_key();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let request = |summary: &str| {
+            let (dir, summary) = (dir.clone(), summary.to_string());
+            tokio::spawn(async move {
+                let timeout = std::time::Duration::from_secs(60);
+                super::request(identity, peer, "drop".to_string(), summary, timeout, std::future::pending(), Some(&dir)).await
+            })
+        };
+
+        let accepted = request("a.txt");
+        let denied = request("b.txt");
+        let waiting = loop {
+            let waiting = super::pending_in(temp.path());
+            if waiting.len() == 2 {
+                break waiting;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        let id_of = |summary: &str| waiting.iter().find(|a| a.summary == summary).unwrap().id.clone();
+
+        super::resolve_in(temp.path(), &id_of("a.txt"), true).unwrap();
+        super::resolve_in(temp.path(), &id_of("b.txt"), false).unwrap();
+        assert!(super::pending_in(temp.path()).is_empty());
+        assert!(super::resolve_in(temp.path(), &id_of("a.txt"), true).is_err());
+        assert!(super::resolve_in(temp.path(), "../a", true).is_err());
+        assert_eq!(accepted.await.unwrap(), Ok(()));
+        assert_eq!(denied.await.unwrap(), Err(super::ApprovalError::Denied));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,325 @@
+//! Audit log of requests served by an identity
+//!
+//! Every request handled on behalf of an identity, or refused, is appended as
+//! one JSON line to `FASTN_HOME/identities/<alias>/audit.log`, so operators can
+//! answer "who ran what" for sensitive protocols like Shell and Admin. When the log grows
+//! past [`MAX_AUDIT_LOG_BYTES`] it is rotated to `audit.log.1`, `audit.log.2`,
+//! ... keeping at most [`MAX_ROTATED_AUDIT_LOGS`] old files.
+
+use std::path::{Path, PathBuf};
+
+/// File name of the current audit log inside the identity directory
+pub const AUDIT_LOG_FILE: &str = "audit.log";
+
+/// Size at which the current audit log is rotated
+pub const MAX_AUDIT_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Number of rotated audit logs kept next to the current one
+pub const MAX_ROTATED_AUDIT_LOGS: usize = 5;
+
+/// Serializes appends and rotation across all identities in this process
+static AUDIT_WRITE_LOCK: std::sync::LazyLock<tokio::sync::Mutex<()>> =
+    std::sync::LazyLock::new(|| tokio::sync::Mutex::new(()));
+
+/// How a request ended
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Ok,
+    Error { message: String },
+    Denied,
+    Timeout,
+}
+
+impl std::fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditOutcome::Ok => write!(f, "ok"),
+            AuditOutcome::Error { message } => write!(f, "error: {message}"),
+            AuditOutcome::Denied => write!(f, "denied"),
+            AuditOutcome::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+/// One audit log entry
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// ID52 of the peer that made the request
+    pub peer: String,
+    pub protocol: String,
+    pub command: String,
+    pub outcome: AuditOutcome,
+    /// Size of the serialized request
+    pub bytes_in: u64,
+    /// Size of the serialized response
+    pub bytes_out: u64,
+    /// Payload signature checked by the framework, for signed requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified: Option<crate::signing::VerifiedSender>,
+}
+
+/// Filter for [`query`]
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    /// Only records from this peer ID52
+    pub peer: Option<String>,
+    /// Only records at or after this time
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl AuditFilter {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        if let Some(ref peer) = self.peer
+            && &record.peer != peer
+        {
+            return false;
+        }
+        if let Some(since) = self.since
+            && record.timestamp < since
+        {
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error("Audit log I/O failed: {source}")]
+    Io { source: std::io::Error },
+
+    #[error("Failed to serialize audit record: {source}")]
+    Serialization { source: serde_json::Error },
+}
+
+/// Where a server records the requests it serves, see [`crate::server::ServerBuilder::with_audit_log`]
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    fastn_home: PathBuf,
+    identity: String,
+}
+
+impl AuditLog {
+    pub fn new(fastn_home: impl Into<PathBuf>, identity: impl Into<String>) -> Self {
+        Self { fastn_home: fastn_home.into(), identity: identity.into() }
+    }
+
+    /// Start the record of a request for `protocol` with `data` from `peer`
+    pub fn start(
+        &self,
+        peer: &fastn_id52::PublicKey,
+        protocol: &serde_json::Value,
+        data: &serde_json::Value,
+        verified: Option<&crate::signing::VerifiedSender>,
+    ) -> PendingAudit {
+        let (protocol, command) = protocol_and_command(protocol);
+        PendingAudit {
+            log: self.clone(),
+            peer: peer.id52(),
+            protocol,
+            command,
+            bytes_in: data.to_string().len() as u64,
+            verified: verified.cloned(),
+        }
+    }
+
+    /// Record a request refused before it reached a handler
+    pub async fn denied(&self, peer: &fastn_id52::PublicKey, protocol: &serde_json::Value, data: &serde_json::Value) {
+        self.start(peer, protocol, data, None).finish(AuditOutcome::Denied, 0).await;
+    }
+
+    /// Record a peer refused at the handshake, before it could send a request
+    pub async fn denied_connection(&self, peer: &fastn_id52::PublicKey) {
+        let handshake = serde_json::Value::String(crate::handshake::HANDSHAKE_PROTOCOL.to_string());
+        self.denied(peer, &handshake, &serde_json::Value::Null).await;
+    }
+}
+
+/// A request being served, appended to the audit log by [`PendingAudit::finish`]
+#[derive(Debug)]
+pub struct PendingAudit {
+    log: AuditLog,
+    peer: String,
+    protocol: String,
+    command: String,
+    bytes_in: u64,
+    verified: Option<crate::signing::VerifiedSender>,
+}
+
+impl PendingAudit {
+    /// Append the record; failing to is logged, the request is not failed for it
+    pub async fn finish(self, outcome: AuditOutcome, bytes_out: u64) {
+        let audit_record = AuditRecord {
+            timestamp: chrono::Utc::now(),
+            peer: self.peer,
+            protocol: self.protocol,
+            command: self.command,
+            outcome,
+            bytes_in: self.bytes_in,
+            bytes_out,
+            verified: self.verified,
+        };
+        if let Err(e) = record(&self.log.fastn_home, &self.log.identity, &audit_record).await {
+            tracing::warn!("Failed to write audit record for {}: {}", self.log.identity, e);
+        }
+    }
+}
+
+/// Protocol and command names of a request's protocol value
+///
+/// Daemon commands carry both; other protocols are recorded by name, as in the metrics.
+fn protocol_and_command(protocol: &serde_json::Value) -> (String, String) {
+    let field = |name| protocol.get(name).and_then(serde_json::Value::as_str);
+    match (field("protocol"), field("command")) {
+        (Some(protocol), Some(command)) => (protocol.to_string(), command.to_string()),
+        _ => (crate::metrics::protocol_name(protocol), String::new()),
+    }
+}
+
+/// Path of the current audit log for `identity`
+pub fn audit_log_path(fastn_home: &Path, identity: &str) -> PathBuf {
+    fastn_home.join("identities").join(identity).join(AUDIT_LOG_FILE)
+}
+
+/// Append `record` to the audit log of `identity`, rotating it if needed
+pub async fn record(
+    fastn_home: &Path,
+    identity: &str,
+    record: &AuditRecord,
+) -> Result<(), AuditError> {
+    append(&audit_log_path(fastn_home, identity), record, MAX_AUDIT_LOG_BYTES).await
+}
+
+/// Read the audit records of `identity` matching `filter`, oldest first
+///
+/// Rotated logs are included. Lines that fail to parse are skipped.
+pub async fn query(
+    fastn_home: &Path,
+    identity: &str,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditRecord>, AuditError> {
+    let current = audit_log_path(fastn_home, identity);
+
+    // Oldest rotated file first, current log last
+    let mut files: Vec<PathBuf> = (1..=MAX_ROTATED_AUDIT_LOGS)
+        .rev()
+        .map(|n| rotated_path(&current, n))
+        .collect();
+    files.push(current);
+
+    let mut records = Vec::new();
+    for file in files {
+        let contents = match tokio::fs::read_to_string(&file).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(source) => return Err(AuditError::Io { source }),
+        };
+
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<AuditRecord>(line) {
+                Ok(record) if filter.matches(&record) => records.push(record),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Skipping malformed audit line in {}: {e}", file.display()),
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+async fn append(path: &Path, record: &AuditRecord, max_bytes: u64) -> Result<(), AuditError> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut line =
+        serde_json::to_string(record).map_err(|source| AuditError::Serialization { source })?;
+    line.push('\n');
+
+    let _guard = AUDIT_WRITE_LOCK.lock().await;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|source| AuditError::Io { source })?;
+    }
+
+    if let Ok(metadata) = tokio::fs::metadata(path).await
+        && metadata.len() + line.len() as u64 > max_bytes
+    {
+        rotate(path).await.map_err(|source| AuditError::Io { source })?;
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|source| AuditError::Io { source })?;
+    file.write_all(line.as_bytes())
+        .await
+        .map_err(|source| AuditError::Io { source })?;
+    file.flush().await.map_err(|source| AuditError::Io { source })
+}
+
+/// Shift `audit.log.N` to `audit.log.N+1` and the current log to `audit.log.1`
+async fn rotate(path: &Path) -> Result<(), std::io::Error> {
+    for n in (1..MAX_ROTATED_AUDIT_LOGS).rev() {
+        let from = rotated_path(path, n);
+        if tokio::fs::try_exists(&from).await? {
+            tokio::fs::rename(&from, rotated_path(path, n + 1)).await?;
+        }
+    }
+    tokio::fs::rename(path, rotated_path(path, 1)).await
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_record(peer: &str, minutes_ago: i64) -> AuditRecord {
+        AuditRecord {
+            timestamp: chrono::Utc::now() - chrono::Duration::minutes(minutes_ago),
+            peer: peer.to_string(),
+            protocol: "Shell".to_string(),
+            command: "exec".to_string(),
+            outcome: AuditOutcome::Ok,
+            bytes_in: 12,
+            bytes_out: 34,
+            verified: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_rotate_and_query() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let path = audit_log_path(&fastn_home, "alice");
+
+        // Tiny limit so every append after the first rotates
+        let old = test_record("peer-a", 60);
+        let line_len = serde_json::to_string(&old).unwrap().len() as u64 + 1;
+        append(&path, &old, line_len).await.unwrap();
+        append(&path, &test_record("peer-b", 5), line_len).await.unwrap();
+        append(&path, &test_record("peer-a", 1), line_len).await.unwrap();
+        assert!(rotated_path(&path, 2).exists());
+
+        let all = query(&fastn_home, "alice", &AuditFilter::default()).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0], old);
+
+        let filter = AuditFilter {
+            peer: Some("peer-a".to_string()),
+            since: Some(chrono::Utc::now() - chrono::Duration::minutes(30)),
+        };
+        let recent = query(&fastn_home, "alice", &filter).await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].peer, "peer-a");
+    }
+}
@@ -0,0 +1,143 @@
+//! Authorization hooks for connections and streams
+//!
+//! Both hooks are async, so they can look the peer up in a database or ask
+//! another service before answering. The connection hook sees the peer's
+//! ClientHello, including its auth token and
+//! [`crate::handshake::HelloMetadata`]; the stream hook sees each stream the
+//! peer opens as a [`StreamAuthRequest`]:
+//!
+//! ```rust,no_run
+//! # struct Db;
+//! # impl Db {
+//! #     async fn is_allowed(&self, peer: &fastn_p2p::PublicKey, token: Option<&str>) -> bool { true }
+//! # }
+//! # let (key, db) = (fastn_p2p::SecretKey::generate(), std::sync::Arc::new(Db));
+//! fastn_p2p::listen(key)
+//!     .with_connection_auth(move |peer, hello| {
+//!         let db = db.clone();
+//!         async move { db.is_allowed(&peer, hello.auth_token.as_deref()).await }
+//!     })
+//!     .with_stream_auth(|request| async move {
+//!         request.command.is_none_or(|command| !command.command.starts_with("admin."))
+//!     })
+//! # ;
+//! ```
+//!
+//! Hooks answer with an [`AuthDecision`] (or a bool). Refusals reach the
+//! client as [`crate::client::CallError::Denied`] or, for throttling,
+//! [`crate::client::CallError::Throttled`] with the time to wait.
+//!
+//! Sync closures written for the earlier hooks keep working through
+//! `with_sync_connection_auth` and `with_sync_stream_auth`.
+
+/// What an auth hook decided about a connection or stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthDecision {
+    Allow,
+    /// Refuse, telling the client why
+    Deny { code: String, message: String },
+    /// Refuse for now; the client should try again after `retry_after`
+    Throttle { retry_after: std::time::Duration },
+}
+
+impl AuthDecision {
+    pub fn deny(code: impl Into<String>, message: impl Into<String>) -> Self {
+        AuthDecision::Deny { code: code.into(), message: message.into() }
+    }
+
+    pub fn throttle(retry_after: std::time::Duration) -> Self {
+        AuthDecision::Throttle { retry_after }
+    }
+
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, AuthDecision::Allow)
+    }
+
+    /// What the client is told, `None` when allowed
+    pub fn denial(&self) -> Option<crate::handshake::AuthDenial> {
+        match self {
+            AuthDecision::Allow => None,
+            AuthDecision::Deny { code, message } => Some(crate::handshake::AuthDenial {
+                code: code.clone(),
+                message: message.clone(),
+                retry_after_ms: None,
+            }),
+            AuthDecision::Throttle { retry_after } => Some(crate::handshake::AuthDenial {
+                code: "throttled".to_string(),
+                message: format!("Too many requests, try again in {:?}", retry_after),
+                retry_after_ms: Some(retry_after.as_millis() as u64),
+            }),
+        }
+    }
+
+    /// ServerHello failure code for a refused connection
+    pub(crate) fn handshake_error(&self) -> crate::handshake::HandshakeError {
+        match self {
+            AuthDecision::Throttle { .. } => crate::handshake::HandshakeError::Throttled,
+            _ => crate::handshake::HandshakeError::Unauthorized,
+        }
+    }
+}
+
+/// `false` is a plain deny, as hooks returning a bool always meant
+impl From<bool> for AuthDecision {
+    fn from(allowed: bool) -> Self {
+        match allowed {
+            true => AuthDecision::Allow,
+            false => AuthDecision::deny("unauthorized", "Authorization denied"),
+        }
+    }
+}
+
+/// A stream a peer wants to open, as seen by stream auth hooks
+#[derive(Debug, Clone)]
+pub struct StreamAuthRequest {
+    pub peer: fastn_id52::PublicKey,
+    pub protocol: serde_json::Value,
+    /// `protocol` parsed as a serve_all command, if it addresses one
+    pub command: Option<crate::server::serve_all::CommandProtocol>,
+    /// Request input or initial stream data
+    pub data: serde_json::Value,
+    /// The verified capability token of the connection, see [`crate::capability`]
+    pub capability: Option<crate::capability::CapabilityToken>,
+}
+
+impl StreamAuthRequest {
+    pub(crate) fn new(peer: fastn_id52::PublicKey, protocol: &serde_json::Value, data: &serde_json::Value) -> Self {
+        Self {
+            peer,
+            protocol: protocol.clone(),
+            command: serde_json::from_value(protocol.clone()).ok(),
+            data: data.clone(),
+            capability: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decisions_become_denials() {
+        assert_eq!(AuthDecision::from(true).denial(), None);
+        let denial = AuthDecision::from(false).denial().unwrap();
+        assert_eq!((denial.code.as_str(), denial.retry_after_ms), ("unauthorized", None));
+
+        let throttle = AuthDecision::throttle(std::time::Duration::from_secs(3));
+        assert_eq!(throttle.denial().unwrap().retry_after_ms, Some(3000));
+        assert!(matches!(throttle.handshake_error(), crate::handshake::HandshakeError::Throttled));
+    }
+
+    #[test]
+    fn test_commands_are_parsed() {
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let command = crate::server::serve_all::CommandProtocol::new("mail.fastn.com", "default", "inbox.list");
+        let protocol = serde_json::to_value(&command).unwrap();
+        let request = StreamAuthRequest::new(peer, &protocol, &serde_json::json!({}));
+        assert_eq!(request.command, Some(command));
+
+        let request = StreamAuthRequest::new(peer, &serde_json::json!("Echo"), &serde_json::json!({}));
+        assert_eq!(request.command, None);
+    }
+}
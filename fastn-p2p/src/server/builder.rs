@@ -1,17 +1,256 @@
 /// Server builder for clean multi-protocol server setup
 ///
-/// Also implements Future so you can .await on it to start the server
+/// Also implements Future so you can .await on it to start the server.
+/// Pings and `WhatTimeIsIt` are answered on every connection without a handler,
+/// see [`crate::ping`] and [`crate::time_sync`].
 pub struct ServerBuilder {
     private_key: fastn_id52::SecretKey,
     request_handlers: std::collections::HashMap<serde_json::Value, RequestHandler>,
     stream_handlers: std::collections::HashMap<serde_json::Value, StreamHandler>,
     connection_auth: Option<ConnectionAuthHook>,
     stream_auth: Option<StreamAuthHook>,
-    server_task: Option<std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error>>> + Send>>>,
+    capability_issuers: Vec<fastn_id52::PublicKey>, // Trusted besides the server's own key
+    reputation: Option<crate::server::reputation::ReputationConfig>,
+    reputation_shared_in: Option<std::path::PathBuf>, // FASTN_HOME, see `share_reputation_in`
+    peer_hooks: crate::server::PeerHooks,
+    peer_sessions: crate::server::PeerSessions,
+    resumption_ttl: std::time::Duration,
+    max_concurrent_streams: usize,
+    request_timeout: Option<std::time::Duration>,
+    max_response_size: Option<usize>,
+    audit: Option<crate::server::audit::AuditLog>,
+    deferred_timeouts: std::collections::HashMap<serde_json::Value, std::time::Duration>,
+    idle_policies: IdlePolicies,
+    max_ages: MaxAges, // Sent with answers, see `with_protocol_max_age`
+    layers: Vec<crate::server::middleware::Layer>,
+    relay: crate::server::relay::RelayConfig,
+    devices: Option<crate::server::devices::DeviceConfig>,
+    registration_errors: Vec<RegistrationError>,
+    module: Option<&'static str>, // Module currently registering, see `module()`
+    protocol_modules: std::collections::HashMap<serde_json::Value, &'static str>, // Which module registered what
+    descriptions: Vec<crate::server::describe::MethodDescription>, // Served over the introspection protocol
+    datagram_protocols: std::collections::HashSet<serde_json::Value>, // Sessions that may exchange datagrams
+    codecs: std::collections::HashMap<serde_json::Value, crate::codec::Codec>, // Payload codecs other than JSON
+    last_registered: Option<serde_json::Value>, // What `with_codec` applies to
+    server_task: Option<ServerTask>,
 }
 
+/// Default cap on concurrently handled streams per connection
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: usize = 64;
+
+/// Sent to the client when a request handler does not finish in time
+#[derive(Debug, thiserror::Error)]
+#[error("Request timed out after {timeout:?}")]
+pub struct RequestTimeoutError {
+    pub timeout: std::time::Duration,
+}
+
+/// Sent to the client instead of a response over the server's maximum size
+#[derive(Debug, thiserror::Error)]
+#[error("Response of {size} bytes exceeds the limit of {limit} bytes")]
+pub struct ResponseTooLargeError {
+    pub size: usize,
+    pub limit: usize,
+}
+
+/// A handler or callback was registered twice
+///
+/// Registration methods record the conflict instead of panicking, keep the
+/// first registration and the server refuses to start with the error. The
+/// `try_` variants return it right away.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RegistrationError {
+    #[error("Duplicate handler for protocol {protocol}")]
+    DuplicateHandler { protocol: serde_json::Value },
+
+    #[error("Duplicate registration for protocol '{protocol}'")]
+    DuplicateProtocol { protocol: String },
+
+    #[error("Duplicate handler for protocol '{protocol}' command '{command}'")]
+    DuplicateCommand { protocol: String, command: String },
+
+    #[error("Duplicate {callback} for protocol '{protocol}'")]
+    DuplicateCallback { protocol: String, callback: &'static str },
+
+    #[error("Protocol {protocol} is registered by both {first} and {second}")]
+    DuplicateModuleHandler {
+        protocol: serde_json::Value,
+        first: &'static str,
+        second: &'static str,
+    },
+
+    #[error("No handler registered for protocol {protocol}")]
+    NotRegistered { protocol: serde_json::Value },
+
+    #[error("{setting} must follow the registration it applies to")]
+    NothingRegistered { setting: &'static str },
+}
+
+/// A protocol, or a group of them, that registers its own handlers
+///
+/// Lets large apps keep each protocol in its own module or crate and
+/// assemble the server from them:
+///
+/// ```rust,no_run
+/// # #[derive(Debug, serde::Serialize)]
+/// # enum MailProtocol { Send, Fetch }
+/// # #[derive(Clone)]
+/// # pub struct MailStore;
+/// # #[derive(Debug, serde::Serialize, thiserror::Error)]
+/// # #[error("mail failed")]
+/// # struct MailError;
+/// # async fn send(mail: String, store: MailStore) -> Result<(), MailError> { Ok(()) }
+/// # async fn fetch(folder: String, store: MailStore) -> Result<Vec<String>, MailError> { Ok(Vec::new()) }
+/// # #[derive(Default)]
+/// # struct Chat;
+/// # impl fastn_p2p::server::ProtocolModule for Chat {
+/// #     fn register(self, builder: fastn_p2p::server::ServerBuilder) -> fastn_p2p::server::ServerBuilder { builder }
+/// # }
+/// pub struct Mail { pub store: MailStore }
+///
+/// impl fastn_p2p::server::ProtocolModule for Mail {
+///     fn register(self, builder: fastn_p2p::server::ServerBuilder) -> fastn_p2p::server::ServerBuilder {
+///         builder
+///             .handle_requests_with_state(MailProtocol::Send, self.store.clone(), send)
+///             .handle_requests_with_state(MailProtocol::Fetch, self.store, fetch)
+///     }
+/// }
+///
+/// # async fn run(key: fastn_p2p::SecretKey, store: MailStore) -> Result<(), Box<dyn std::error::Error>> {
+/// fastn_p2p::listen(key).module(Mail { store }).module(Chat::default()).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Two modules claiming the same protocol is a
+/// [`RegistrationError::DuplicateModuleHandler`] naming both.
+pub trait ProtocolModule {
+    fn register(self, builder: ServerBuilder) -> ServerBuilder;
+
+    /// Name used in registration errors; the type name by default
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// How long request handlers may run, per protocol
+///
+/// Deferred protocols wait for their reply instead of the server-wide timeout.
+#[derive(Clone)]
+struct RequestTimeouts {
+    default: Option<std::time::Duration>,
+    deferred: Registry<std::time::Duration>,
+}
+
+impl RequestTimeouts {
+    fn for_protocol(&self, protocol: &serde_json::Value) -> Option<std::time::Duration> {
+        self.deferred.with(protocol, |timeout| *timeout).or(self.default)
+    }
+}
+
+/// How long connections may idle and live, per protocol
+#[derive(Clone, Default)]
+struct IdlePolicies {
+    default: fastn_net::IdlePolicy,
+    protocols: std::sync::Arc<std::collections::HashMap<serde_json::Value, fastn_net::IdlePolicy>>,
+}
+
+impl IdlePolicies {
+    fn for_protocol(&self, protocol: &serde_json::Value) -> fastn_net::IdlePolicy {
+        route(&self.protocols, protocol).copied().unwrap_or(self.default)
+    }
+}
+
+/// How long clients may cache answers, per protocol
+type MaxAges = std::sync::Arc<std::collections::HashMap<serde_json::Value, std::time::Duration>>;
+
+/// Idle bookkeeping of one connection, shared with its stream tasks
+#[derive(Clone)]
+struct ConnectionIdle {
+    timer: std::sync::Arc<std::sync::Mutex<fastn_net::IdleTimer>>,
+    policies: IdlePolicies,
+}
+
+impl ConnectionIdle {
+    fn new(policies: IdlePolicies) -> Self {
+        let timer = fastn_net::IdleTimer::new(policies.default);
+        Self { timer: std::sync::Arc::new(std::sync::Mutex::new(timer)), policies }
+    }
+
+    fn timer(&self) -> std::sync::MutexGuard<'_, fastn_net::IdleTimer> {
+        self.timer.lock().expect("Failed to acquire lock on idle timer")
+    }
+
+    /// Mark the connection active under `protocol` now and again when the guard drops
+    fn active(&self, protocol: &serde_json::Value) -> IdleGuard {
+        let policy = self.policies.for_protocol(protocol);
+        self.timer().touch(&policy);
+        IdleGuard { idle: self.clone(), policy }
+    }
+}
+
+struct IdleGuard {
+    idle: ConnectionIdle,
+    policy: fastn_net::IdlePolicy,
+}
+
+impl Drop for IdleGuard {
+    fn drop(&mut self) {
+        self.idle.timer().touch(&self.policy);
+    }
+}
+
+/// Values by protocol, shared with the running server
+///
+/// A [`ServerHandle`] changes the entries while the server runs; streams look
+/// their handler up when they arrive, so changes apply to new streams.
+struct Registry<T>(std::sync::Arc<std::sync::RwLock<std::collections::HashMap<serde_json::Value, T>>>);
+
+impl<T> Clone for Registry<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Registry<T> {
+    fn new(entries: std::collections::HashMap<serde_json::Value, T>) -> Self {
+        Self(std::sync::Arc::new(std::sync::RwLock::new(entries)))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, std::collections::HashMap<serde_json::Value, T>> {
+        self.0.read().expect("Failed to acquire lock on handler registry")
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, std::collections::HashMap<serde_json::Value, T>> {
+        self.0.write().expect("Failed to acquire lock on handler registry")
+    }
+
+    fn contains(&self, protocol: &serde_json::Value) -> bool {
+        route(&self.read(), protocol).is_some()
+    }
+
+    /// Run `f` on the entry for `protocol` without holding the lock afterwards
+    fn with<R>(&self, protocol: &serde_json::Value, f: impl FnOnce(&T) -> R) -> Option<R> {
+        route(&self.read(), protocol).map(f)
+    }
+}
+
+/// The entry `protocol` is served by
+///
+/// serve_all commands carry their arguments in the protocol value but are
+/// registered without them, see [`crate::server::CommandProtocol`].
+fn route<'a, T>(entries: &'a std::collections::HashMap<serde_json::Value, T>, protocol: &serde_json::Value) -> Option<&'a T> {
+    entries
+        .get(protocol)
+        .or_else(|| entries.get(&crate::server::CommandProtocol::without_args(protocol)?))
+}
+
+/// Handler output: serialized OUTPUT on success, serialized ERROR on application error
+type HandlerResult = Result<serde_json::Value, serde_json::Value>;
+
+/// Type-erased request handler, called with the request JSON and its context
 type RequestHandler = Box<
-    dyn Fn(String) -> std::pin::Pin<Box<dyn std::future::Future<Output = String> + Send>>
+    dyn Fn(String, crate::server::RequestContext) -> std::pin::Pin<Box<dyn std::future::Future<Output = HandlerResult> + Send>>
         + Send
         + Sync,
 >;
@@ -19,35 +258,35 @@ type RequestHandler = Box<
 type StreamHandler = Box<
     dyn Fn(
         iroh::endpoint::SendStream,
-        iroh::endpoint::RecvStream,
+        fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+        fastn_id52::PublicKey,
         fastn_id52::PublicKey,
         String,
+        Option<crate::datagram::Datagrams>,
+        fastn_net::Scope,
+        std::sync::Arc<std::sync::OnceLock<crate::server::CloseReason>>,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error>>> + Send>>
         + Send
         + Sync,
 >;
 
-/// Connection authorization hook - called when a peer connects
-/// Returns true to allow connection, false to deny
+/// The running server, polled by the builder's own `Future` impl
+type ServerTask = std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error>>> + Send>>;
+
+type AuthFuture = std::pin::Pin<Box<dyn std::future::Future<Output = crate::server::AuthDecision> + Send>>;
+
+/// Connection authorization hook - awaited when a peer connects, see [`crate::server::auth`]
 type ConnectionAuthHook = Box<
     dyn Fn(
-        &fastn_id52::PublicKey,  // peer connecting
-    ) -> bool
+        fastn_id52::PublicKey,               // peer connecting
+        crate::handshake::ClientHello,       // what the client said about itself
+    ) -> AuthFuture
         + Send
         + Sync,
 >;
 
-/// Stream authorization hook - called when a peer opens a stream
-/// Returns true to allow stream, false to deny
-type StreamAuthHook = Box<
-    dyn Fn(
-        &fastn_id52::PublicKey,  // peer making the request
-        &serde_json::Value,      // protocol being requested
-        &serde_json::Value,      // initial data sent with request
-    ) -> bool
-        + Send
-        + Sync,
->;
+/// Stream authorization hook - awaited when a peer opens a stream, see [`crate::server::auth`]
+type StreamAuthHook = Box<dyn Fn(crate::server::StreamAuthRequest) -> AuthFuture + Send + Sync>;
 
 impl ServerBuilder {
     pub fn new(private_key: fastn_id52::SecretKey) -> Self {
@@ -57,58 +296,403 @@ impl ServerBuilder {
             stream_handlers: std::collections::HashMap::new(),
             connection_auth: None,
             stream_auth: None,
+            capability_issuers: Vec::new(),
+            reputation: None,
+            reputation_shared_in: None,
+            peer_hooks: crate::server::PeerHooks::default(),
+            peer_sessions: crate::server::PeerSessions::new(),
+            resumption_ttl: crate::server::resumption::DEFAULT_RESUMPTION_TTL,
+            max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+            request_timeout: None,
+            max_response_size: None,
+            audit: None,
+            deferred_timeouts: std::collections::HashMap::new(),
+            idle_policies: IdlePolicies::default(),
+            max_ages: MaxAges::default(),
+            layers: Vec::new(),
+            relay: crate::server::relay::RelayConfig::default(),
+            devices: None,
+            registration_errors: Vec::new(),
+            module: None,
+            protocol_modules: std::collections::HashMap::new(),
+            descriptions: Vec::new(),
+            datagram_protocols: std::collections::HashSet::new(),
+            codecs: std::collections::HashMap::new(),
+            last_registered: None,
             server_task: None,
         }
     }
 
-    /// Set connection authorization hook - called when any peer connects
-    /// 
+    /// Set connection authorization hook - awaited when any peer connects
+    ///
+    /// Gets the peer and its ClientHello, with the client's auth token and
+    /// metadata, see [`crate::handshake::HelloMetadata`]. Runs before
+    /// ServerHello; the peer waits for the answer. Answers with a bool or an
+    /// [`crate::server::AuthDecision`], whose reason the client gets.
+    ///
+    /// The sender of a relayed stream is asked about too, with a hello that
+    /// carries none of the relay's credentials: no auth token or capability,
+    /// and its metadata only holds `"relayed-by"`, the relay's ID52.
+    ///
     /// # Example
-    /// ```rust
+    /// ```rust,no_run
+    /// # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    /// # enum Protocol { Echo }
+    /// # #[derive(Debug, serde::Serialize, thiserror::Error)]
+    /// # #[error("echo failed")]
+    /// # struct EchoError;
+    /// # async fn echo_handler(input: String) -> Result<String, EchoError> { Ok(input) }
+    /// # struct Db;
+    /// # impl Db {
+    /// #     async fn allows(&self, peer: &fastn_p2p::PublicKey, token: Option<&str>) -> bool { true }
+    /// # }
+    /// # async fn run(key: fastn_p2p::SecretKey, db: std::sync::Arc<Db>) -> Result<(), Box<dyn std::error::Error>> {
     /// fastn_p2p::listen(key)
-    ///     .with_connection_auth(|peer| {
-    ///         // Only allow connections from known peers
-    ///         ALLOWED_PEERS.contains(peer)
+    ///     .with_connection_auth(move |peer, hello| {
+    ///         let db = db.clone();
+    ///         async move { db.allows(&peer, hello.auth_token.as_deref()).await }
     ///     })
     ///     .handle_requests(Protocol::Echo, echo_handler)
     ///     .await?;
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn with_connection_auth<F>(mut self, auth_fn: F) -> Self
+    pub fn with_connection_auth<F, Fut>(mut self, auth_fn: F) -> Self
     where
-        F: Fn(&fastn_id52::PublicKey) -> bool + Send + Sync + 'static,
+        F: Fn(fastn_id52::PublicKey, crate::handshake::ClientHello) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: Into<crate::server::AuthDecision>,
     {
-        self.connection_auth = Some(Box::new(auth_fn));
+        self.connection_auth = Some(Box::new(move |peer, hello| -> AuthFuture {
+            let decision = auth_fn(peer, hello);
+            Box::pin(async move { decision.await.into() })
+        }));
         self
     }
+
+    /// [`ServerBuilder::with_connection_auth`] for a sync closure that only looks at the peer
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    /// # enum Protocol { Echo }
+    /// # #[derive(Debug, serde::Serialize, thiserror::Error)]
+    /// # #[error("echo failed")]
+    /// # struct EchoError;
+    /// # async fn echo_handler(input: String) -> Result<String, EchoError> { Ok(input) }
+    /// # async fn run(
+    /// #     key: fastn_p2p::SecretKey,
+    /// #     allowed_peers: std::collections::HashSet<fastn_p2p::PublicKey>,
+    /// # ) -> Result<(), Box<dyn std::error::Error>> {
+    /// fastn_p2p::listen(key)
+    ///     .with_sync_connection_auth(move |peer| {
+    ///         // Only allow connections from known peers
+    ///         allowed_peers.contains(peer)
+    ///     })
+    ///     .handle_requests(Protocol::Echo, echo_handler)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_sync_connection_auth<F, D>(self, auth_fn: F) -> Self
+    where
+        F: Fn(&fastn_id52::PublicKey) -> D + Send + Sync + 'static,
+        D: Into<crate::server::AuthDecision> + Send + 'static,
+    {
+        self.with_connection_auth(move |peer, _| std::future::ready(auth_fn(&peer)))
+    }
     
-    /// Set stream authorization hook - called when a peer opens a stream
-    /// 
+    /// Set stream authorization hook - awaited when a peer opens a stream
+    ///
+    /// Also runs for early requests and for every call in a batch. The
+    /// request has serve_all commands parsed, see [`crate::server::StreamAuthRequest`].
+    ///
     /// # Example
-    /// ```rust
+    /// ```rust,no_run
+    /// # use fastn_p2p::server::AuthDecision;
+    /// # use serde_json::json;
+    /// # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    /// # enum Protocol { Echo }
+    /// # #[derive(Debug, serde::Serialize, thiserror::Error)]
+    /// # #[error("echo failed")]
+    /// # struct EchoError;
+    /// # async fn echo_handler(input: String) -> Result<String, EchoError> { Ok(input) }
+    /// # async fn run(
+    /// #     key: fastn_p2p::SecretKey,
+    /// #     admin_peers: std::collections::HashSet<fastn_p2p::PublicKey>,
+    /// # ) -> Result<(), Box<dyn std::error::Error>> {
     /// fastn_p2p::listen(key)
-    ///     .with_stream_auth(|peer, protocol, data| {
+    ///     .with_stream_auth(move |request| {
     ///         // Allow different access based on protocol
-    ///         match protocol {
-    ///             p if p == &json!("Admin") => ADMIN_PEERS.contains(peer),
-    ///             _ => true
-    ///         }
+    ///         let decision = match request.protocol {
+    ///             p if p == json!("Admin") && !admin_peers.contains(&request.peer) => {
+    ///                 AuthDecision::deny("not-admin", "Ask an admin to add your ID")
+    ///             }
+    ///             _ => AuthDecision::Allow,
+    ///         };
+    ///         async move { decision }
     ///     })
     ///     .handle_requests(Protocol::Echo, echo_handler)
     ///     .await?;
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn with_stream_auth<F>(mut self, auth_fn: F) -> Self
+    pub fn with_stream_auth<F, Fut>(mut self, auth_fn: F) -> Self
     where
-        F: Fn(&fastn_id52::PublicKey, &serde_json::Value, &serde_json::Value) -> bool
+        F: Fn(crate::server::StreamAuthRequest) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: Into<crate::server::AuthDecision>,
+    {
+        self.stream_auth = Some(Box::new(move |request| -> AuthFuture {
+            let decision = auth_fn(request);
+            Box::pin(async move { decision.await.into() })
+        }));
+        self
+    }
+
+    /// [`ServerBuilder::with_stream_auth`] for a sync closure of peer, protocol and data
+    pub fn with_sync_stream_auth<F, D>(self, auth_fn: F) -> Self
+    where
+        F: Fn(&fastn_id52::PublicKey, &serde_json::Value, &serde_json::Value) -> D
             + Send
             + Sync
             + 'static,
+        D: Into<crate::server::AuthDecision> + Send + 'static,
+    {
+        self.with_stream_auth(move |request| {
+            std::future::ready(auth_fn(&request.peer, &request.protocol, &request.data))
+        })
+    }
+
+    /// Also accept capability tokens minted by `issuer`, see [`crate::capability`]
+    ///
+    /// Tokens minted by the server's own identity are always accepted.
+    pub fn with_capability_issuer(mut self, issuer: fastn_id52::PublicKey) -> Self {
+        self.capability_issuers.push(issuer);
+        self
+    }
+
+    /// Call `hook` whenever a peer completes the handshake
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    /// # enum Protocol { Echo }
+    /// # #[derive(Debug, serde::Serialize, thiserror::Error)]
+    /// # #[error("echo failed")]
+    /// # struct EchoError;
+    /// # async fn echo_handler(input: String) -> Result<String, EchoError> { Ok(input) }
+    /// # async fn run(key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+    /// fastn_p2p::listen(key)
+    ///     .on_peer_connected(|info| {
+    ///         println!("{} connected with {:?}", info.peer, info.protocols);
+    ///     })
+    ///     .on_peer_disconnected(|info| {
+    ///         println!("{} left after {:?}, {} streams", info.peer, info.stats.connected_for, info.stats.streams);
+    ///     })
+    ///     .handle_requests(Protocol::Echo, echo_handler)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_peer_connected<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&crate::server::PeerInfo) + Send + Sync + 'static,
+    {
+        self.peer_hooks.on_connected(hook);
+        self
+    }
+
+    /// Call `hook` once a connected peer is gone, however the connection ended
+    ///
+    /// Only peers reported to [`Self::on_peer_connected`] are reported here;
+    /// `info.stats` covers the whole connection.
+    pub fn on_peer_disconnected<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&crate::server::PeerInfo) + Send + Sync + 'static,
+    {
+        self.peer_hooks.on_disconnected(hook);
+        self
+    }
+
+    /// Report peers to `hooks`, shared with other servers, instead of the builder's own
+    pub(crate) fn with_peer_hooks(mut self, hooks: crate::server::PeerHooks) -> Self {
+        self.peer_hooks = hooks;
+        self
+    }
+
+    /// Keep per-peer sessions in `sessions` instead of a fresh set
+    ///
+    /// Lets servers share sessions, or code outside request handlers (like
+    /// stream handler state) look them up. See [`crate::server::peer_sessions`].
+    pub fn with_peer_sessions(mut self, sessions: crate::server::PeerSessions) -> Self {
+        self.peer_sessions = sessions;
+        self
+    }
+
+    /// Set how long session resumption tokens issued in ServerHello stay valid
+    ///
+    /// Clients reconnecting within this window can present their token to get
+    /// the previously negotiated protocols back without renegotiation.
+    pub fn with_resumption_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.resumption_ttl = ttl;
+        self
+    }
+
+    /// Set how many streams from a single connection are handled concurrently
+    ///
+    /// Requests on one connection run in parallel up to this limit; further
+    /// streams are not accepted until a running one finishes. Defaults to
+    /// [`DEFAULT_MAX_CONCURRENT_STREAMS`].
+    pub fn with_max_concurrent_streams(mut self, max: usize) -> Self {
+        self.max_concurrent_streams = max.max(1);
+        self
+    }
+
+    /// Abort request handlers that run longer than `timeout`
+    ///
+    /// The handler future is dropped, the client receives a
+    /// [`RequestTimeoutError`] as the error response and the stream is closed.
+    /// Streaming handlers are long-lived by design and are not affected, and
+    /// deferred handlers use the timeout they were registered with.
+    pub fn with_request_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Refuse to send responses larger than `bytes`, encoded
+    ///
+    /// The client receives a [`ResponseTooLargeError`] as the error response
+    /// instead. Unlimited by default; responses that don't fit in one frame
+    /// are sent in chunks, see [`crate::server::responder`].
+    pub fn with_max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    /// Record requests served and refused in `identity`'s audit log under `fastn_home`
+    ///
+    /// See [`crate::server::audit`]; the daemon keeps one per identity.
+    pub fn with_audit_log(mut self, fastn_home: impl Into<std::path::PathBuf>, identity: impl Into<String>) -> Self {
+        self.audit = Some(crate::server::audit::AuditLog::new(fastn_home, identity));
+        self
+    }
+
+    /// Close connections that idle or live longer than `policy` allows
+    ///
+    /// A connection counts as idle while none of its streams are open; pings
+    /// don't count, so a peer can't keep a connection with keepalives alone.
+    /// Defaults to [`fastn_net::IdlePolicy::default`], one idle minute and no
+    /// lifetime limit. See [`Self::with_protocol_idle_policy`] for protocols
+    /// whose sessions idle for longer, like chat.
+    pub fn with_idle_policy(mut self, policy: fastn_net::IdlePolicy) -> Self {
+        self.idle_policies.default = policy;
+        self
+    }
+
+    /// Use `policy` for connections once they serve `protocol`
+    ///
+    /// A connection serving several protocols gets the most lenient of their
+    /// policies, see [`fastn_net::IdleTimer`].
+    pub fn with_protocol_idle_policy<P>(mut self, protocol: P, policy: fastn_net::IdlePolicy) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        std::sync::Arc::make_mut(&mut self.idle_policies.protocols).insert(protocol_key, policy);
+        self
+    }
+
+    /// Let clients cache successful answers of `protocol` for `max_age`
+    ///
+    /// Sent as `max_age` with each answer; errors are never cached. The
+    /// daemon's call cache keeps answers that long unless `[cache.protocols]`
+    /// in its `config.toml` says otherwise, see `fastn-p2p`'s `cache` docs.
+    pub fn with_protocol_max_age<P>(mut self, protocol: P, max_age: std::time::Duration) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        std::sync::Arc::make_mut(&mut self.max_ages).insert(protocol_key, max_age);
+        self
+    }
+
+    /// Wrap every request and stream handler with `layer`
+    ///
+    /// Layers run in the order they are added, the first one outermost; see
+    /// [`crate::server::middleware`].
+    pub fn layer<F, Fut>(mut self, layer: F) -> Self
+    where
+        F: Fn(crate::server::LayerRequest, crate::server::Next) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = crate::server::LayerResult> + Send + 'static,
     {
-        self.stream_auth = Some(Box::new(auth_fn));
+        self.layers.push(crate::server::middleware::boxed(layer));
+        self
+    }
+
+    /// Count offenses of peers and ban those with too many strikes
+    ///
+    /// See [`crate::server::reputation`]; off unless set here. The records
+    /// are the server's own, see [`ServerHandle::reputation`].
+    pub fn with_reputation(mut self, config: crate::server::reputation::ReputationConfig) -> Self {
+        self.reputation = Some(config);
+        self
+    }
+
+    /// Also write the reputation records to `fastn_home`, where the daemon
+    /// lists and clears them; `serve_all` does this for every identity
+    pub fn share_reputation_in(mut self, fastn_home: impl Into<std::path::PathBuf>) -> Self {
+        self.reputation_shared_in = Some(fastn_home.into());
+        self
+    }
+
+    /// Forward streams between any two of `peers`
+    ///
+    /// Peers that cannot reach each other directly can then connect through
+    /// this server with [`crate::client::call_via`] / [`crate::client::connect_via`].
+    /// See [`crate::server::relay`] for how relaying works.
+    pub fn with_relay(mut self, peers: impl IntoIterator<Item = fastn_id52::PublicKey>) -> Self {
+        self.relay.relay_for.extend(peers);
+        self
+    }
+
+    /// Accept relayed streams from these relays as coming from the original sender
+    ///
+    /// Streams relayed by anyone else are rejected.
+    pub fn with_trusted_relays(mut self, relays: impl IntoIterator<Item = fastn_id52::PublicKey>) -> Self {
+        self.relay.trusted_relays.extend(relays);
+        self
+    }
+
+    /// Let paired devices use this identity, with the registry in `identity_dir`
+    ///
+    /// Devices pair with a one-time code and then have their calls opened as
+    /// this identity. See [`crate::server::devices`].
+    pub fn with_devices(mut self, identity_dir: impl Into<std::path::PathBuf>) -> Self {
+        self.devices = Some(crate::server::devices::DeviceConfig {
+            identity_dir: identity_dir.into(),
+        });
         self
     }
 
+    /// Let strangers holding an invite from `identity` add themselves to its address book
+    ///
+    /// Invites are kept in the identity's directory under `fastn_home`, where
+    /// `fastn-p2p invite create` puts them. See [`crate::server::invites`].
+    pub fn with_invites(self, fastn_home: impl Into<std::path::PathBuf>, identity: impl Into<String>) -> Self {
+        let config = std::sync::Arc::new(crate::server::invites::InviteConfig {
+            fastn_home: fastn_home.into(),
+            identity: identity.into(),
+        });
+        self.handle_requests_with_context(crate::server::invites::INVITE_PROTOCOL, move |request, context| {
+            crate::server::invites::accept(config.clone(), request, *context.peer())
+        })
+    }
+
     /// Add a request/response handler for a protocol
-    pub fn handle_requests<P, F, Fut, INPUT, OUTPUT, ERROR>(mut self, protocol: P, handler: F) -> Self
+    pub fn handle_requests<P, F, Fut, INPUT, OUTPUT, ERROR>(self, protocol: P, handler: F) -> Self
     where
         P: serde::Serialize + std::fmt::Debug,
         F: Fn(INPUT) -> Fut + Send + Sync + 'static,
@@ -117,124 +701,680 @@ impl ServerBuilder {
         OUTPUT: serde::Serialize,
         ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
     {
-        // Convert protocol to JSON value for lookup
-        let protocol_key = serde_json::to_value(&protocol)
+        // Signatures are still verified when present, the handler just doesn't see them
+        self.insert_request_handler(protocol, move |input, _context| Some(handler(input)))
+    }
+
+    /// Add a request/response handler that also gets the request's context
+    ///
+    /// The [`crate::server::RequestContext`] carries the peer, deadline,
+    /// metadata and a cancellation signal that fires when the peer goes away,
+    /// so long handlers can stop early.
+    pub fn handle_requests_with_context<P, F, Fut, INPUT, OUTPUT, ERROR>(self, protocol: P, handler: F) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        F: Fn(INPUT, crate::server::RequestContext) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+        INPUT: serde::de::DeserializeOwned,
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+    {
+        self.insert_request_handler(protocol, move |input, context| Some(handler(input, context)))
+    }
+
+    /// Add a request/response handler that gets a clone of `state` with every request
+    ///
+    /// Mirrors [`Self::handle_streams`]: share a database pool, config or
+    /// counters with the handler without globals. Put anything mutable behind
+    /// an `Arc`, since each request gets its own clone.
+    pub fn handle_requests_with_state<P, F, Fut, INPUT, STATE, OUTPUT, ERROR>(self, protocol: P, state: STATE, handler: F) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        STATE: Clone + Send + Sync + 'static,
+        F: Fn(INPUT, STATE) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+        INPUT: serde::de::DeserializeOwned,
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+    {
+        self.insert_request_handler(protocol, move |input, _context| Some(handler(input, state.clone())))
+    }
+
+    /// Add a request handler that only accepts signed requests
+    ///
+    /// Unsigned requests are refused before the handler runs; the handler gets
+    /// the signer via [`crate::server::SignedRequest::verified_sender`]. The
+    /// response is signed with this server's key. See [`crate::signing`].
+    pub fn handle_signed_requests<P, F, Fut, INPUT, OUTPUT, ERROR>(self, protocol: P, handler: F) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        F: Fn(crate::server::SignedRequest<INPUT>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+        INPUT: serde::de::DeserializeOwned,
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+    {
+        self.insert_request_handler(protocol, move |input, context| {
+            context.verified.map(|verified_sender| handler(crate::server::SignedRequest::new(input, verified_sender)))
+        })
+    }
+
+    /// Add a request handler that may reply later, from any task
+    ///
+    /// The handler gets a [`crate::server::DeferredResponse`] it can complete
+    /// right away, keep, or park in a [`crate::server::PendingResponses`]
+    /// registry (e.g. until a human approves the action). The peer waits up to
+    /// `timeout` for the reply, instead of the server-wide request timeout; if
+    /// it disconnects first the response is cancelled.
+    pub fn handle_deferred_requests<P, F, Fut, INPUT>(
+        mut self,
+        protocol: P,
+        timeout: std::time::Duration,
+        handler: F,
+    ) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        F: Fn(INPUT, crate::server::DeferredResponse) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ()> + Send,
+        INPUT: serde::de::DeserializeOwned,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
             .expect("Protocol must be serializable");
 
-        // Create a type-erased handler that works with JSON strings
         let boxed_handler: RequestHandler = {
             let handler = std::sync::Arc::new(handler);
-            Box::new(move |request_json: String| {
+            Box::new(move |request_json: String, _context: crate::server::RequestContext| {
                 let handler = handler.clone();
                 Box::pin(async move {
-                    // Deserialize request
                     let input: INPUT = match serde_json::from_str(&request_json) {
                         Ok(input) => input,
                         Err(e) => {
                             let error_msg = format!("Failed to deserialize request: {}", e);
-                            return serde_json::to_string(&error_msg).unwrap_or_else(|_| error_msg);
+                            return Err(serde_json::Value::String(error_msg));
                         }
                     };
 
-                    // Call handler
-                    let result = handler(input).await;
+                    let (response, reply) = crate::server::DeferredResponse::new();
+                    handler(input, response).await;
 
-                    // Serialize response (success or error)
-                    match result {
-                        Ok(output) => serde_json::to_string(&output)
-                            .unwrap_or_else(|e| format!("Failed to serialize response: {}", e)),
-                        Err(error) => serde_json::to_string(&error)
-                            .unwrap_or_else(|e| format!("Failed to serialize error: {}", e)),
-                    }
+                    // The handler may have handed the response to another task
+                    reply.await.unwrap_or_else(|_| {
+                        Err(serde_json::Value::String("Request was dropped without a response".to_string()))
+                    })
                 })
             })
         };
 
-        self.request_handlers.insert(protocol_key, boxed_handler);
+        if self.register(&protocol_key) {
+            self.deferred_timeouts.insert(protocol_key.clone(), timeout);
+            self.request_handlers.insert(protocol_key, boxed_handler);
+        }
         self
     }
 
-    /// Add a streaming handler for a protocol
-    pub fn handle_streams<P, F, Fut, DATA, STATE, ERROR>(mut self, protocol: P, state: STATE, handler: F) -> Self
+    /// Add a request handler that sends its output item by item
+    ///
+    /// For outputs too big to build in memory: the handler gets a
+    /// [`crate::server::Responder`] and the client receives a `Vec<ITEM>`, see
+    /// [`crate::server::responder`]. The server-wide request timeout does not
+    /// apply, like for streams.
+    pub fn handle_chunked_requests<P, F, Fut, INPUT, ITEM, ERROR>(self, protocol: P, handler: F) -> Self
     where
         P: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug + Clone + Send + Sync + 'static,
-        DATA: serde::de::DeserializeOwned + Send + 'static,
-        STATE: Clone + Send + Sync + 'static,
-        F: Fn(crate::server::Session<P>, DATA, STATE) -> Fut + Send + Sync + 'static,
+        F: Fn(INPUT, crate::server::Responder<ITEM>) -> Fut + Send + Sync + 'static,
         Fut: std::future::Future<Output = Result<(), ERROR>> + Send + 'static,
-        ERROR: std::error::Error + Send + Sync + 'static,
+        INPUT: serde::de::DeserializeOwned + Send + 'static,
+        ITEM: serde::Serialize + Send + 'static,
+        ERROR: serde::Serialize + Send + 'static,
     {
-        // Convert protocol to JSON value for lookup
-        let protocol_key = serde_json::to_value(&protocol)
+        let handler = std::sync::Arc::new(handler);
+        self.handle_streams(protocol, (), move |session: crate::server::Session<P>, input: INPUT, ()| {
+            crate::server::responder::respond(session.send, input, handler.clone())
+        })
+    }
+
+    /// Hand `protocol`'s requests to whoever reads `requests`
+    ///
+    /// Backs the deprecated stream-based [`crate::server::listen`]: each
+    /// request becomes a [`crate::server::Request`] whose response completes
+    /// the call, so handshake, auth, limits and timeouts are this server's.
+    pub(crate) fn handle_requests_on_channel<P>(
+        mut self,
+        protocol: P,
+        requests: tokio::sync::mpsc::Sender<crate::server::Request<P>>,
+    ) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug + Clone + Send + Sync + 'static,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
             .expect("Protocol must be serializable");
 
-        // Create a type-erased stream handler
-        let boxed_handler: StreamHandler = {
-            let handler = std::sync::Arc::new(handler);
-            let state = std::sync::Arc::new(state);
+        let boxed_handler: RequestHandler = Box::new(move |request_json: String, context: crate::server::RequestContext| {
+            let requests = requests.clone();
             let protocol = protocol.clone();
-            Box::new(move |send, recv, peer, data_json: String| {
-                let handler = handler.clone();
-                let state = state.clone();
-                let protocol = protocol.clone();
-                Box::pin(async move {
-                    // Deserialize the initial data
-                    let data: DATA = match serde_json::from_str(&data_json) {
-                        Ok(data) => data,
-                        Err(e) => {
-                            return Err(Box::new(e) as Box<dyn std::error::Error>);
-                        }
-                    };
-                    
-                    // Create the session
-                    let session = crate::server::Session {
-                        protocol: protocol.clone(),
-                        send,
-                        recv,
-                        peer,
-                        context: fastn_context::Context::new("stream"),
-                    };
-                    
-                    // Call the handler with session, data, and state
-                    match handler(session, data, (*state).clone()).await {
-                        Ok(()) => Ok(()),
-                        Err(e) => Err(Box::new(e) as Box<dyn std::error::Error>),
-                    }
+            Box::pin(async move {
+                let (response, reply) = crate::server::DeferredResponse::new();
+                let request = crate::server::Request::new(*context.peer(), protocol, request_json, response);
+                if requests.send(request).await.is_err() {
+                    return Err(serde_json::Value::String("Listener is no longer accepting requests".to_string()));
+                }
+                reply.await.unwrap_or_else(|_| {
+                    Err(serde_json::Value::String("Request was dropped without a response".to_string()))
                 })
             })
-        };
+        });
 
-        self.stream_handlers.insert(protocol_key, boxed_handler);
+        if self.register(&protocol_key) {
+            self.request_handlers.insert(protocol_key, boxed_handler);
+        }
         self
     }
-}
 
-// Implement Future for ServerBuilder so it can be awaited
-impl std::future::Future for ServerBuilder {
-    type Output = Result<(), Box<dyn std::error::Error>>;
+    /// Register a typed handler; returning `None` refuses an unsigned request
+    fn insert_request_handler<P, F, Fut, INPUT, OUTPUT, ERROR>(mut self, protocol: P, handler: F) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        F: Fn(INPUT, crate::server::RequestContext) -> Option<Fut> + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+        INPUT: serde::de::DeserializeOwned,
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+    {
+        // Convert protocol to JSON value for lookup
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
 
-    fn poll(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        // If we haven't created the server task yet, create it
-        if self.server_task.is_none() {
-            let private_key = self.private_key.clone();
-            let request_handlers = std::mem::take(&mut self.request_handlers);
-            let stream_handlers = std::mem::take(&mut self.stream_handlers);
-            let connection_auth = self.connection_auth.take();
-            let stream_auth = self.stream_auth.take();
-            
-            println!("🎧 Server listening on: {}", private_key.id52());
-            
-            // Create the server future
-            self.server_task = Some(Box::pin(run_server(
-                private_key, 
-                request_handlers, 
-                stream_handlers, 
-                connection_auth,
-                stream_auth
-            )));
+        let boxed_handler = boxed_request_handler(handler);
+
+        if self.register(&protocol_key) {
+            self.request_handlers.insert(protocol_key, boxed_handler);
+        }
+        self
+    }
+
+    /// Add a streaming handler for a protocol
+    pub fn handle_streams<P, F, Fut, DATA, STATE, ERROR>(mut self, protocol: P, state: STATE, handler: F) -> Self
+    where
+        P: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug + Clone + Send + Sync + 'static,
+        DATA: serde::de::DeserializeOwned + Send + 'static,
+        STATE: Clone + Send + Sync + 'static,
+        F: Fn(crate::server::Session<P>, DATA, STATE) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), ERROR>> + Send + 'static,
+        ERROR: std::error::Error + Send + Sync + 'static,
+    {
+        // Convert protocol to JSON value for lookup
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+
+        let boxed_handler = boxed_stream_handler(protocol, state, handler);
+
+        if self.register(&protocol_key) {
+            self.stream_handlers.insert(protocol_key, boxed_handler);
+        }
+        self
+    }
+
+    /// Add a streaming handler whose downloads resume after a dropped connection
+    ///
+    /// `handler` also gets the byte offset to start sending from: 0 for a new
+    /// download, or the bytes the client already has when it resumes. See
+    /// [`crate::server::resumable`].
+    pub fn handle_resumable_streams<P, F, Fut, DATA, STATE, ERROR>(self, protocol: P, state: STATE, handler: F) -> Self
+    where
+        P: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug + Clone + Send + Sync + 'static,
+        DATA: serde::Serialize + serde::de::DeserializeOwned + Send + 'static,
+        STATE: Clone + Send + Sync + 'static,
+        F: Fn(crate::server::Session<P>, DATA, u64, STATE) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), ERROR>> + Send + 'static,
+        ERROR: std::error::Error + Send + Sync + 'static,
+    {
+        let handler = std::sync::Arc::new(handler);
+        self.handle_streams(
+            protocol,
+            state,
+            move |mut session: crate::server::Session<P>, request: crate::server::resumable::ResumableRequest<DATA>, state: STATE| {
+                let handler = handler.clone();
+                async move {
+                    let data = serde_json::to_value(&request.data).unwrap_or(serde_json::Value::Null);
+                    let offset = crate::server::resumable::announce(&mut session, &data, request.resume.as_ref()).await?;
+                    handler(session, request.data, offset, state)
+                        .await
+                        .map_err(|e| crate::server::resumable::ResumableError::Handler { source: Box::new(e) })
+                }
+            },
+        )
+    }
+
+    /// Let sessions of `protocol` also exchange unreliable datagrams
+    ///
+    /// For real-time streams that would rather drop data than stall on a
+    /// retransmission; see [`crate::datagram`]. Clients learn which protocols
+    /// have datagrams in the handshake, and sessions of other protocols get
+    /// [`crate::datagram::DatagramError::NotEnabled`].
+    pub fn enable_datagrams<P>(mut self, protocol: P) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        self.datagram_protocols.insert(protocol_key);
+        self
+    }
+
+    /// Encode the payloads of the protocol registered last with `codec`
+    ///
+    /// For request/response protocols moving numeric-heavy or binary data;
+    /// clients without the codec keep using JSON. See [`crate::codec`].
+    ///
+    /// ```rust,no_run
+    /// # #[derive(Debug, serde::Serialize)]
+    /// # enum Telemetry { Samples }
+    /// # #[derive(Debug, serde::Serialize, thiserror::Error)]
+    /// # #[error("no samples")]
+    /// # struct NoSamples;
+    /// # async fn samples(since: u64) -> Result<Vec<f64>, NoSamples> { Ok(Vec::new()) }
+    /// # let key = fastn_p2p::SecretKey::generate();
+    /// fastn_p2p::listen(key)
+    ///     .handle_requests(Telemetry::Samples, samples)
+    ///     .with_codec(fastn_p2p::codec::Codec::Cbor)
+    /// # ;
+    /// ```
+    pub fn with_codec(mut self, codec: crate::codec::Codec) -> Self {
+        match self.last_registered.clone() {
+            Some(protocol_key) => {
+                self.codecs.insert(protocol_key, codec);
+            }
+            None => self.registration_errors.push(RegistrationError::NothingRegistered { setting: "with_codec" }),
+        }
+        self
+    }
+
+    /// Serve `protocol`'s requests with a helper process
+    ///
+    /// See [`crate::server::subprocess`] for the wire format between the
+    /// server and the helper.
+    pub fn handle_subprocess_requests<P>(mut self, protocol: P, subprocess: crate::server::Subprocess) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+
+        if self.register(&protocol_key) {
+            self.request_handlers.insert(protocol_key, subprocess_request_handler(subprocess));
+        }
+        self
+    }
+
+    /// Serve `protocol`'s streams with a helper process, bytes forwarded both ways
+    pub fn handle_subprocess_streams<P>(mut self, protocol: P, subprocess: crate::server::Subprocess) -> Self
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+
+        if self.register(&protocol_key) {
+            let handler = subprocess_stream_handler(protocol_key.clone(), subprocess);
+            self.stream_handlers.insert(protocol_key, handler);
+        }
+        self
+    }
+
+    /// Describe `protocol`'s request, response and error types
+    ///
+    /// Doesn't register a handler; pair it with any of the `handle_*requests`
+    /// methods. See [`crate::server::describe`].
+    #[cfg(feature = "schema")]
+    pub fn describe_requests<P, INPUT, OUTPUT, ERROR>(mut self, protocol: P) -> Self
+    where
+        P: serde::Serialize,
+        INPUT: schemars::JsonSchema,
+        OUTPUT: schemars::JsonSchema,
+        ERROR: schemars::JsonSchema,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        self.descriptions.push(crate::server::describe::MethodDescription::request::<INPUT, OUTPUT, ERROR>(protocol_key));
+        self
+    }
+
+    /// Describe the initial data of `protocol`'s streams
+    #[cfg(feature = "schema")]
+    pub fn describe_streams<P, DATA>(mut self, protocol: P) -> Self
+    where
+        P: serde::Serialize,
+        DATA: schemars::JsonSchema,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        self.descriptions.push(crate::server::describe::MethodDescription::stream::<DATA>(protocol_key));
+        self
+    }
+
+    /// Answer the introspection protocol if anything was described
+    fn register_describe_handler(&mut self) {
+        let protocol_key = serde_json::json!(crate::server::describe::DESCRIBE_PROTOCOL);
+        if self.descriptions.is_empty() || self.request_handlers.contains_key(&protocol_key) {
+            return;
+        }
+
+        let methods = std::sync::Arc::new(std::mem::take(&mut self.descriptions));
+        let boxed_handler: RequestHandler = Box::new(move |request_json: String, _context: crate::server::RequestContext| {
+            let methods = methods.clone();
+            Box::pin(async move {
+                let request: crate::server::describe::DescribeRequest = serde_json::from_str(&request_json)
+                    .map_err(|e| serde_json::Value::String(format!("Failed to deserialize request: {}", e)))?;
+                Ok(crate::server::describe::document(&methods, request.protocol.as_deref()))
+            })
+        });
+        self.request_handlers.insert(protocol_key, boxed_handler);
+    }
+
+    /// Start the server in the background and return a handle to it
+    ///
+    /// Unlike awaiting the builder, this lets protocols be added and removed
+    /// while the server runs, see [`ServerHandle`]. The server stops on
+    /// [`ServerHandle::stop`] or graceful shutdown.
+    pub fn start(mut self) -> Result<ServerHandle, RegistrationError> {
+        if let Some(error) = self.registration_errors.first() {
+            return Err(error.clone());
+        }
+
+        let (handle, server) = self.server();
+        crate::spawn(async move {
+            if let Err(e) = server.await {
+                tracing::error!("Server error: {}", e);
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Take the handlers and settings out of the builder into a server future
+    fn server(&mut self) -> (ServerHandle, impl std::future::Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'static) {
+        self.register_describe_handler();
+        let private_key = self.private_key.clone();
+        let mut reputation = crate::server::reputation::Reputation::new(private_key.public_key(), self.reputation.take());
+        if let Some(fastn_home) = self.reputation_shared_in.take() {
+            reputation = reputation.share_in(&fastn_home);
+        }
+        let handle = ServerHandle {
+            public_key: private_key.public_key(),
+            reputation: std::sync::Arc::new(reputation),
+            request_handlers: Registry::new(std::mem::take(&mut self.request_handlers)),
+            stream_handlers: Registry::new(std::mem::take(&mut self.stream_handlers)),
+            deferred_timeouts: Registry::new(std::mem::take(&mut self.deferred_timeouts)),
+            peer_sessions: self.peer_sessions.clone(),
+            stop: tokio_util::sync::CancellationToken::new(),
+        };
+        let connection_auth = self.connection_auth.take();
+        let stream_auth = self.stream_auth.take();
+        let mut capability_issuers = std::mem::take(&mut self.capability_issuers);
+        capability_issuers.push(private_key.public_key());
+        let capability_issuers = std::sync::Arc::new(capability_issuers);
+        let peer_hooks = std::mem::take(&mut self.peer_hooks);
+        let resumption_ttl = self.resumption_ttl;
+        let max_concurrent_streams = self.max_concurrent_streams;
+        let request_timeouts = RequestTimeouts {
+            default: self.request_timeout,
+            deferred: handle.deferred_timeouts.clone(),
+        };
+        let max_response_size = self.max_response_size;
+        let audit = self.audit.take();
+        let idle_policies = std::mem::take(&mut self.idle_policies);
+        let max_ages = std::mem::take(&mut self.max_ages);
+        let layers = crate::server::middleware::Layers::new(std::mem::take(&mut self.layers));
+        let relay = std::mem::take(&mut self.relay);
+        let devices = self.devices.take();
+        let datagram_protocols = std::mem::take(&mut self.datagram_protocols);
+        let codecs = std::mem::take(&mut self.codecs);
+        
+        tracing::info!(target: crate::console::TARGET, "🎧 Server listening on: {}", private_key.id52());
+        
+        let server = run_server(ServerContext {
+            server_secret: private_key,
+            request_handlers: handle.request_handlers.clone(),
+            stream_handlers: handle.stream_handlers.clone(),
+            connection_auth: connection_auth.map(std::sync::Arc::new),
+            stream_auth: stream_auth.map(std::sync::Arc::new),
+            capability_issuers,
+            peer_hooks,
+            peer_sessions: handle.peer_sessions.clone(),
+            reputation: handle.reputation.clone(),
+            resumption_ttl,
+            max_concurrent_streams,
+            request_timeouts,
+            max_response_size,
+            audit,
+            idle_policies,
+            max_ages,
+            layers,
+            relay,
+            devices: devices.map(std::sync::Arc::new),
+            datagram_protocols,
+            codecs,
+            stop: handle.stop.clone(),
+        });
+        (handle, server)
+    }
+
+    /// Like [`Self::handle_requests`], but fails if `protocol` already has a handler
+    pub fn try_handle_requests<P, F, Fut, INPUT, OUTPUT, ERROR>(self, protocol: P, handler: F) -> Result<Self, RegistrationError>
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        F: Fn(INPUT) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+        INPUT: serde::de::DeserializeOwned,
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+    {
+        let errors_before = self.registration_errors.len();
+        self.handle_requests(protocol, handler).registered(errors_before)
+    }
+
+    /// Like [`Self::handle_streams`], but fails if `protocol` already has a handler
+    pub fn try_handle_streams<P, F, Fut, DATA, STATE, ERROR>(self, protocol: P, state: STATE, handler: F) -> Result<Self, RegistrationError>
+    where
+        P: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug + Clone + Send + Sync + 'static,
+        DATA: serde::de::DeserializeOwned + Send + 'static,
+        STATE: Clone + Send + Sync + 'static,
+        F: Fn(crate::server::Session<P>, DATA, STATE) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), ERROR>> + Send + 'static,
+        ERROR: std::error::Error + Send + Sync + 'static,
+    {
+        let errors_before = self.registration_errors.len();
+        self.handle_streams(protocol, state, handler).registered(errors_before)
+    }
+
+    /// Let `module` register its handlers, see [`ProtocolModule`]
+    pub fn module<M: ProtocolModule>(mut self, module: M) -> Self {
+        let outer = self.module.replace(module.name());
+        let mut builder = module.register(self);
+        builder.module = outer;
+        builder
+    }
+
+    /// Like [`Self::module`], but fails if the module claims an already registered protocol
+    pub fn try_module<M: ProtocolModule>(self, module: M) -> Result<Self, RegistrationError> {
+        let errors_before = self.registration_errors.len();
+        self.module(module).registered(errors_before)
+    }
+
+    /// Conflicts recorded so far; awaiting the builder fails with the first one
+    pub fn registration_errors(&self) -> &[RegistrationError] {
+        &self.registration_errors
+    }
+
+    /// Whether `protocol_key` is still free; records a conflict if not
+    ///
+    /// Request and stream handlers share one namespace, since the wrapper
+    /// request is routed by protocol alone.
+    fn register(&mut self, protocol_key: &serde_json::Value) -> bool {
+        if self.request_handlers.contains_key(protocol_key) || self.stream_handlers.contains_key(protocol_key) {
+            tracing::warn!("Duplicate handler for protocol {}", protocol_key);
+            let first = self.protocol_modules.get(protocol_key).copied();
+            let error = match (first, self.module) {
+                (None, None) => RegistrationError::DuplicateHandler { protocol: protocol_key.clone() },
+                (first, second) => RegistrationError::DuplicateModuleHandler {
+                    protocol: protocol_key.clone(),
+                    first: first.unwrap_or("the builder"),
+                    second: second.unwrap_or("the builder"),
+                },
+            };
+            self.registration_errors.push(error);
+            return false;
+        }
+        if let Some(module) = self.module {
+            self.protocol_modules.insert(protocol_key.clone(), module);
+        }
+        self.last_registered = Some(protocol_key.clone());
+        true
+    }
+
+    /// Fail with the conflict recorded since `errors_before`, if any
+    fn registered(mut self, errors_before: usize) -> Result<Self, RegistrationError> {
+        if self.registration_errors.len() > errors_before {
+            return Err(self.registration_errors.swap_remove(errors_before));
+        }
+        Ok(self)
+    }
+}
+
+/// Type-erase a typed request handler; returning `None` refuses an unsigned request
+fn boxed_request_handler<F, Fut, INPUT, OUTPUT, ERROR>(handler: F) -> RequestHandler
+where
+    F: Fn(INPUT, crate::server::RequestContext) -> Option<Fut> + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+    INPUT: serde::de::DeserializeOwned,
+    OUTPUT: serde::Serialize,
+    ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+{
+    let handler = std::sync::Arc::new(handler);
+    Box::new(move |request_json: String, context: crate::server::RequestContext| {
+        let handler = handler.clone();
+        Box::pin(async move {
+            // Deserialize request
+            let input: INPUT = match serde_json::from_str(&request_json) {
+                Ok(input) => input,
+                Err(e) => {
+                    let error_msg = format!("Failed to deserialize request: {}", e);
+                    return Err(serde_json::Value::String(error_msg));
+                }
+            };
+
+            // Call handler
+            let Some(future) = handler(input, context) else {
+                return Err(serde_json::Value::String("Request must be signed".to_string()));
+            };
+            let result = future.await;
+
+            // Serialize response (success or error), keeping the two apart
+            match result {
+                Ok(output) => serde_json::to_value(&output).map_err(|e| {
+                    serde_json::Value::String(format!("Failed to serialize response: {}", e))
+                }),
+                Err(error) => Err(serde_json::to_value(&error).unwrap_or_else(|e| {
+                    serde_json::Value::String(format!("Failed to serialize error: {}", e))
+                })),
+            }
+        })
+    })
+}
+
+/// Type-erase a typed stream handler, handing it a [`crate::server::Session`]
+fn boxed_stream_handler<P, F, Fut, DATA, STATE, ERROR>(protocol: P, state: STATE, handler: F) -> StreamHandler
+where
+    P: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug + Clone + Send + Sync + 'static,
+    DATA: serde::de::DeserializeOwned + Send + 'static,
+    STATE: Clone + Send + Sync + 'static,
+    F: Fn(crate::server::Session<P>, DATA, STATE) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ERROR>> + Send + 'static,
+    ERROR: std::error::Error + Send + Sync + 'static,
+{
+    let handler = std::sync::Arc::new(handler);
+    let state = std::sync::Arc::new(state);
+    Box::new(move |send, recv, peer, identity, data_json: String, datagrams, tasks, close_reason| {
+        let handler = handler.clone();
+        let state = state.clone();
+        let protocol = protocol.clone();
+        Box::pin(async move {
+            // Deserialize the initial data
+            let data: DATA = match serde_json::from_str(&data_json) {
+                Ok(data) => data,
+                Err(e) => {
+                    return Err(Box::new(e) as Box<dyn std::error::Error>);
+                }
+            };
+            
+            // Create the session
+            let session = crate::server::Session {
+                protocol: protocol.clone(),
+                send,
+                recv,
+                peer,
+                identity,
+                context: fastn_context::Context::new("stream"),
+                datagrams,
+                tasks,
+                close_reason,
+            };
+            
+            // Call the handler with session, data, and state
+            match handler(session, data, (*state).clone()).await {
+                Ok(()) => Ok(()),
+                Err(e) => Err(Box::new(e) as Box<dyn std::error::Error>),
+            }
+        })
+    })
+}
+
+/// Forward requests to a helper process, see [`crate::server::subprocess`]
+fn subprocess_request_handler(subprocess: crate::server::Subprocess) -> RequestHandler {
+    Box::new(move |request_json: String, context: crate::server::RequestContext| {
+        let subprocess = subprocess.clone();
+        Box::pin(async move {
+            let data = serde_json::from_str(&request_json).map_err(|e| {
+                serde_json::Value::String(format!("Failed to deserialize request: {}", e))
+            })?;
+            subprocess
+                .request(*context.peer(), context.protocol().clone(), data, context.metadata().clone())
+                .await
+        })
+    })
+}
+
+/// Forward streams of `protocol_key` to a helper process
+fn subprocess_stream_handler(protocol_key: serde_json::Value, subprocess: crate::server::Subprocess) -> StreamHandler {
+    Box::new(move |send, recv, peer, _identity, data_json: String, _datagrams, _tasks, _close_reason| {
+        let subprocess = subprocess.clone();
+        let protocol_key = protocol_key.clone();
+        Box::pin(async move {
+            let data = serde_json::from_str(&data_json)?;
+            subprocess.stream(peer, protocol_key, data, send, recv).await?;
+            Ok::<(), Box<dyn std::error::Error>>(())
+        })
+    })
+}
+
+// Implement Future for ServerBuilder so it can be awaited
+impl std::future::Future for ServerBuilder {
+    type Output = Result<(), Box<dyn std::error::Error>>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        // Refuse to start with conflicting registrations
+        if self.server_task.is_none()
+            && let Some(error) = self.registration_errors.first()
+        {
+            return std::task::Poll::Ready(Err(Box::new(error.clone())));
+        }
+
+        // If we haven't created the server task yet, create it
+        if self.server_task.is_none() {
+            let (_handle, server) = self.server();
+            self.server_task = Some(Box::pin(server));
         }
         
         // Poll the server task
@@ -246,29 +1386,253 @@ impl std::future::Future for ServerBuilder {
     }
 }
 
-async fn run_server(
-    private_key: fastn_id52::SecretKey,
-    request_handlers: std::collections::HashMap<serde_json::Value, RequestHandler>,
-    stream_handlers: std::collections::HashMap<serde_json::Value, StreamHandler>,
-    connection_auth: Option<ConnectionAuthHook>,
-    stream_auth: Option<StreamAuthHook>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let server_public_key = private_key.public_key();
+/// Control over a server started with [`ServerBuilder::start`]
+///
+/// Protocols registered or unregistered here apply to streams opened from
+/// then on, also on connections that are already up; streams in flight keep
+/// the handler they started with. Clones control the same server.
+#[derive(Clone)]
+pub struct ServerHandle {
+    public_key: fastn_id52::PublicKey,
+    reputation: std::sync::Arc<crate::server::reputation::Reputation>,
+    request_handlers: Registry<RequestHandler>,
+    stream_handlers: Registry<StreamHandler>,
+    deferred_timeouts: Registry<std::time::Duration>,
+    peer_sessions: crate::server::PeerSessions,
+    stop: tokio_util::sync::CancellationToken,
+}
+
+/// A handler about to be added to a running server
+enum Handler {
+    Request(RequestHandler),
+    Stream(StreamHandler),
+}
+
+impl ServerHandle {
+    /// The identity the server listens as
+    pub fn public_key(&self) -> fastn_id52::PublicKey {
+        self.public_key
+    }
+
+    /// Strikes and bans of the server's peers, see [`ServerBuilder::with_reputation`]
+    pub fn reputation(&self) -> &crate::server::reputation::Reputation {
+        &self.reputation
+    }
+
+    /// Add a request/response protocol, like [`ServerBuilder::handle_requests`]
+    pub fn register_protocol<P, F, Fut, INPUT, OUTPUT, ERROR>(&self, protocol: P, handler: F) -> Result<(), RegistrationError>
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        F: Fn(INPUT) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+        INPUT: serde::de::DeserializeOwned,
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        let handler = boxed_request_handler(move |input, _context| Some(handler(input)));
+        self.register(protocol_key, Handler::Request(handler))
+    }
+
+    /// Add a request/response protocol, like [`ServerBuilder::handle_requests_with_context`]
+    pub fn register_protocol_with_context<P, F, Fut, INPUT, OUTPUT, ERROR>(&self, protocol: P, handler: F) -> Result<(), RegistrationError>
+    where
+        P: serde::Serialize + std::fmt::Debug,
+        F: Fn(INPUT, crate::server::RequestContext) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OUTPUT, ERROR>> + Send,
+        INPUT: serde::de::DeserializeOwned,
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize + std::error::Error + Send + Sync + 'static,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        let handler = boxed_request_handler(move |input, context| Some(handler(input, context)));
+        self.register(protocol_key, Handler::Request(handler))
+    }
+
+    /// Add a streaming protocol, like [`ServerBuilder::handle_streams`]
+    pub fn register_stream_protocol<P, F, Fut, DATA, STATE, ERROR>(&self, protocol: P, state: STATE, handler: F) -> Result<(), RegistrationError>
+    where
+        P: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug + Clone + Send + Sync + 'static,
+        DATA: serde::de::DeserializeOwned + Send + 'static,
+        STATE: Clone + Send + Sync + 'static,
+        F: Fn(crate::server::Session<P>, DATA, STATE) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), ERROR>> + Send + 'static,
+        ERROR: std::error::Error + Send + Sync + 'static,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        let handler = boxed_stream_handler(protocol, state, handler);
+        self.register(protocol_key, Handler::Stream(handler))
+    }
+
+    /// Add a protocol served by a helper, like [`ServerBuilder::handle_subprocess_requests`]
+    pub fn register_subprocess_requests<P>(&self, protocol: P, subprocess: crate::server::Subprocess) -> Result<(), RegistrationError>
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        self.register(protocol_key, Handler::Request(subprocess_request_handler(subprocess)))
+    }
+
+    /// Abort requests of `protocol` that run longer than `timeout`
+    ///
+    /// Like [`ServerBuilder::with_request_timeout`] for one protocol; lasts
+    /// until the protocol is unregistered.
+    pub fn set_request_timeout<P>(&self, protocol: P, timeout: std::time::Duration)
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        self.deferred_timeouts.write().insert(protocol_key, timeout);
+    }
+
+    /// Add a streaming protocol served by a helper, like [`ServerBuilder::handle_subprocess_streams`]
+    pub fn register_subprocess_streams<P>(&self, protocol: P, subprocess: crate::server::Subprocess) -> Result<(), RegistrationError>
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        let handler = subprocess_stream_handler(protocol_key.clone(), subprocess);
+        self.register(protocol_key, Handler::Stream(handler))
+    }
+
+    /// Remove a protocol; new streams for it are refused with "No handler"
+    pub fn unregister_protocol<P>(&self, protocol: P) -> Result<(), RegistrationError>
+    where
+        P: serde::Serialize + std::fmt::Debug,
+    {
+        let protocol_key = crate::protocol_key::of(&protocol)
+            .expect("Protocol must be serializable");
+        let mut requests = self.request_handlers.write();
+        let mut streams = self.stream_handlers.write();
+        let removed = requests.remove(&protocol_key).is_some() | streams.remove(&protocol_key).is_some();
+        if !removed {
+            return Err(RegistrationError::NotRegistered { protocol: protocol_key });
+        }
+        self.deferred_timeouts.write().remove(&protocol_key);
+        tracing::info!("Unregistered protocol {} on {}", protocol_key, self.public_key);
+        Ok(())
+    }
+
+    /// Protocols currently served, request/response and streaming
+    pub fn protocols(&self) -> Vec<serde_json::Value> {
+        let mut protocols: Vec<_> = self.request_handlers.read().keys().cloned().collect();
+        protocols.extend(self.stream_handlers.read().keys().cloned());
+        protocols
+    }
+
+    /// Stop accepting connections and streams; streams in flight finish
+    pub fn stop(&self) {
+        tracing::info!("Stopping server {}", self.public_key);
+        self.stop.cancel();
+    }
+
+    /// Whether [`Self::stop`] was called
+    pub fn is_stopped(&self) -> bool {
+        self.stop.is_cancelled()
+    }
+
+    /// Sessions of the peers connected to this server
+    pub fn peer_sessions(&self) -> &crate::server::PeerSessions {
+        &self.peer_sessions
+    }
+
+    /// Add `handler` unless `protocol_key` is taken by either kind of handler
+    fn register(&self, protocol_key: serde_json::Value, handler: Handler) -> Result<(), RegistrationError> {
+        let mut requests = self.request_handlers.write();
+        let mut streams = self.stream_handlers.write();
+        if requests.contains_key(&protocol_key) || streams.contains_key(&protocol_key) {
+            return Err(RegistrationError::DuplicateHandler { protocol: protocol_key });
+        }
+        tracing::info!("Registered protocol {} on {}", protocol_key, self.public_key);
+        match handler {
+            Handler::Request(handler) => {
+                requests.insert(protocol_key, handler);
+            }
+            Handler::Stream(handler) => {
+                streams.insert(protocol_key, handler);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the connections of one running server share
+struct ServerContext {
+    server_secret: fastn_id52::SecretKey,
+    request_handlers: Registry<RequestHandler>,
+    stream_handlers: Registry<StreamHandler>,
+    connection_auth: Option<std::sync::Arc<ConnectionAuthHook>>,
+    stream_auth: Option<std::sync::Arc<StreamAuthHook>>,
+    capability_issuers: std::sync::Arc<Vec<fastn_id52::PublicKey>>,
+    peer_hooks: crate::server::PeerHooks,
+    peer_sessions: crate::server::PeerSessions,
+    reputation: std::sync::Arc<crate::server::reputation::Reputation>,
+    resumption_ttl: std::time::Duration,
+    max_concurrent_streams: usize,
+    request_timeouts: RequestTimeouts,
+    max_response_size: Option<usize>,
+    audit: Option<crate::server::audit::AuditLog>,
+    idle_policies: IdlePolicies,
+    max_ages: MaxAges,
+    layers: crate::server::middleware::Layers,
+    relay: crate::server::relay::RelayConfig,
+    devices: Option<std::sync::Arc<crate::server::devices::DeviceConfig>>,
+    datagram_protocols: std::collections::HashSet<serde_json::Value>,
+    codecs: std::collections::HashMap<serde_json::Value, crate::codec::Codec>,
+    stop: tokio_util::sync::CancellationToken,
+}
+
+/// What the streams of one connection share
+struct ConnectionContext {
+    server: std::sync::Arc<ServerContext>,
+    /// The server's, or one that honors the peer's capability token
+    stream_auth: Option<std::sync::Arc<StreamAuthHook>>,
+    tagged_responses: bool,
+    idle: ConnectionIdle,
+    /// Slots for the streams of the connection, `max_concurrent_streams` of them
+    stream_limit: std::sync::Arc<tokio::sync::Semaphore>,
+}
+
+async fn run_server(server: ServerContext) -> Result<(), Box<dyn std::error::Error>> {
     // Get endpoint for listening
-    let endpoint = fastn_net::get_endpoint(private_key).await?;
+    let endpoint = fastn_net::get_endpoint(server.server_secret.clone()).await?;
+    let server_key = server.server_secret.public_key();
+    // Rebound along with the clients when the host changes networks, see crate::network
+    let _rebinding = crate::network::register_server(endpoint.clone());
     
-    // Wrap handlers in Arc for sharing across tasks
-    let request_handlers = std::sync::Arc::new(request_handlers);
-    let stream_handlers = std::sync::Arc::new(stream_handlers);
-    let connection_auth = connection_auth.map(std::sync::Arc::new);
-    let stream_auth = stream_auth.map(std::sync::Arc::new);
+    // Calls from this process skip the network while the server runs, see crate::server::loopback
+    let _loopback = crate::server::loopback::register(server_key, LocalServer {
+        server_key,
+        request_handlers: server.request_handlers.clone(),
+        connection_auth: server.connection_auth.clone(),
+        stream_auth: server.stream_auth.clone(),
+        capability_issuers: server.capability_issuers.clone(),
+        reputation: server.reputation.clone(),
+        request_timeouts: server.request_timeouts.clone(),
+        max_response_size: server.max_response_size,
+        audit: server.audit.clone(),
+        layers: server.layers.clone(),
+        max_ages: server.max_ages.clone(),
+    });
     
+    // Shared with the task of every connection
+    let server = std::sync::Arc::new(server);
     loop {
         tokio::select! {
-            _ = crate::cancelled() => {
+            _ = crate::draining() => {
                 tracing::info!("Server shutting down");
                 break;
             }
+            _ = server.stop.cancelled() => {
+                tracing::info!("Server stopped");
+                break;
+            }
             conn = endpoint.accept() => {
                 let conn = match conn {
                     Some(conn) => conn,
@@ -278,20 +1642,9 @@ async fn run_server(
                     }
                 };
                 
-                let request_handlers = request_handlers.clone();
-                let stream_handlers = stream_handlers.clone();
-                let connection_auth = connection_auth.clone();
-                let stream_auth = stream_auth.clone();
-                let server_key = server_public_key.clone();
+                let server = server.clone();
                 crate::spawn(async move {
-                    if let Err(e) = handle_connection(
-                        conn, 
-                        server_key,
-                        &request_handlers, 
-                        &stream_handlers, 
-                        connection_auth.as_deref(),
-                        stream_auth.as_deref()
-                    ).await {
+                    if let Err(e) = handle_connection(conn, server).await {
                         tracing::error!("Connection error: {}", e);
                     }
                 });
@@ -302,22 +1655,68 @@ async fn run_server(
     Ok(())
 }
 
-// Structure of the wrapper request sent by client
-#[derive(serde::Deserialize)]
-struct WrapperRequest {
-    protocol: serde_json::Value,
-    data: serde_json::Value,
+/// Stream auth for a connection that presented a valid capability `token`
+///
+/// Streams the token covers are allowed without asking `stream_auth`; peers
+/// let in only because of the token (`confined`) get nothing else.
+fn capability_stream_auth(
+    stream_auth: Option<std::sync::Arc<StreamAuthHook>>,
+    token: crate::capability::CapabilityToken,
+    confined: bool,
+) -> std::sync::Arc<StreamAuthHook> {
+    std::sync::Arc::new(Box::new(move |mut request: crate::server::StreamAuthRequest| -> AuthFuture {
+        let covered = token.covers(&request);
+        request.capability = Some(token.clone());
+        let stream_auth = stream_auth.clone();
+        Box::pin(async move {
+            match stream_auth {
+                _ if covered => crate::server::AuthDecision::Allow,
+                _ if confined => crate::server::AuthDecision::deny(
+                    "not-granted",
+                    "The capability token does not grant this protocol",
+                ),
+                Some(auth) => auth(request).await,
+                None => crate::server::AuthDecision::Allow,
+            }
+        })
+    }))
+}
+
+/// Whether the sender of a relayed stream gets in, like a peer connecting directly
+///
+/// The sender never connected here, so the handshake only checked the relay:
+/// a banned sender is throttled and `connection_auth` is asked about it with
+/// the relay's hello stripped of the relay's credentials, see
+/// [`crate::handshake::ClientHello::relayed_by`]. Refusals count against the
+/// sender, not the relay.
+async fn admit_relayed_origin(
+    connection_auth: Option<&ConnectionAuthHook>,
+    reputation: &crate::server::reputation::Reputation,
+    origin: fastn_id52::PublicKey,
+    relay: &fastn_id52::PublicKey,
+    relay_hello: &crate::handshake::ClientHello,
+) -> crate::server::AuthDecision {
+    if let Some(left) = reputation.banned_for(&origin) {
+        return crate::server::AuthDecision::throttle(left);
+    }
+    let Some(auth) = connection_auth else {
+        return crate::server::AuthDecision::Allow;
+    };
+    let decision = auth(origin, relay_hello.relayed_by(relay)).await;
+    if let Some(offense) = crate::server::reputation::Offense::of_decision(&decision) {
+        reputation.record(&origin, offense);
+    }
+    decision
 }
 
 async fn handle_connection(
     conn: iroh::endpoint::Incoming,
-    server_key: fastn_id52::PublicKey,
-    request_handlers: &std::collections::HashMap<serde_json::Value, RequestHandler>,
-    stream_handlers: &std::collections::HashMap<serde_json::Value, StreamHandler>,
-    connection_auth: Option<&ConnectionAuthHook>,
-    stream_auth: Option<&StreamAuthHook>,
+    server: std::sync::Arc<ServerContext>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let conn = conn.await?;
+    let server_secret = &server.server_secret;
+    let server_key = server_secret.public_key();
+    let server_id52 = server_key.id52();
     
     // Get peer's ID52 for logging and security
     let peer_key = fastn_net::get_remote_id52(&conn).await?;
@@ -337,32 +1736,89 @@ async fn handle_connection(
         }
         other => {
             tracing::warn!("First stream was not handshake: {:?}", other);
+            server.reputation.record(&peer_key, crate::server::reputation::Offense::MalformedFrame);
             conn.close(0u8.into(), b"Handshake required");
             return Ok(());
         }
     };
     
     // Read ClientHello
-    let client_hello: crate::handshake::ClientHello = match fastn_net::next_json(&mut recv_stream).await {
+    let mut client_hello: crate::handshake::ClientHello = match recv_stream.next_json().await {
         Ok(hello) => hello,
         Err(e) => {
             tracing::warn!("Failed to read ClientHello: {}", e);
+            server.reputation.record(&peer_key, crate::server::reputation::Offense::MalformedFrame);
             conn.close(0u8.into(), b"Invalid handshake");
             return Ok(());
         }
     };
+    client_hello.canonicalize_protocols();
+    
+    // Banned peers are told how long to wait, like a throttling auth hook would
+    if let Some(left) = server.reputation.banned_for(&peer_key) {
+        tracing::debug!("Refusing banned peer {} for another {:?}", peer_key.id52(), left);
+        if let Some(audit) = &server.audit {
+            audit.denied_connection(&peer_key).await;
+        }
+        let decision = crate::server::AuthDecision::throttle(left);
+        let denial = decision.denial().expect("throttling is a denial");
+        let response = crate::handshake::ServerHello::denied(decision.handshake_error(), denial);
+        let json = serde_json::to_string(&response)?;
+        send_stream.write_all(json.as_bytes()).await?;
+        send_stream.write_all(b"\n").await?;
+        send_stream.finish()?;
+        conn.close(0u8.into(), b"Banned");
+        return Ok(());
+    }
     
     tracing::debug!("Received ClientHello from {} ({}): {} protocols supported", 
                    client_hello.client_name, client_hello.client_version, 
                    client_hello.supported_protocols.len());
     
+    // Oversized metadata is refused before any hook has to look at it
+    if !client_hello.metadata_within_limits() {
+        tracing::warn!("ClientHello metadata of {} is over the limits", peer_key.id52());
+        server.reputation.record(&peer_key, crate::server::reputation::Offense::LimitViolation);
+        let response = crate::handshake::ServerHello::failure(
+            crate::handshake::HandshakeError::MetadataTooLarge
+        );
+        let json = serde_json::to_string(&response)?;
+        send_stream.write_all(json.as_bytes()).await?;
+        send_stream.write_all(b"\n").await?;
+        send_stream.finish()?;
+        conn.close(0u8.into(), b"Metadata too large");
+        return Ok(());
+    }
+    
+    // A capability token can let in a peer the hook refuses, see crate::capability
+    let capability = client_hello
+        .capability()
+        .map(|token| token.and_then(|token| token.verify(&peer_key, &server.capability_issuers).map(|()| token)));
+    let mut confined = false;
+    
     // Check connection-level authorization with client info
-    if let Some(auth) = connection_auth {
-        if !auth(&peer_key) {
-            tracing::warn!("Connection denied for peer {}", peer_key.id52());
-            let response = crate::handshake::ServerHello::failure(
-                crate::handshake::HandshakeError::Unauthorized
-            );
+    if let Some(auth) = &server.connection_auth {
+        let mut decision = auth(peer_key, client_hello.clone()).await;
+        if let crate::server::AuthDecision::Deny { .. } = decision {
+            match &capability {
+                Some(Ok(_)) => {
+                    tracing::debug!("Admitting peer {} on its capability token", peer_key.id52());
+                    decision = crate::server::AuthDecision::Allow;
+                    confined = true;
+                }
+                Some(Err(e)) => decision = crate::server::AuthDecision::deny(e.code(), e.to_string()),
+                None => {}
+            }
+        }
+        if let Some(denial) = decision.denial() {
+            tracing::warn!("Connection denied for peer {}: {}", peer_key.id52(), denial.message);
+            if let Some(offense) = crate::server::reputation::Offense::of_decision(&decision) {
+                server.reputation.record(&peer_key, offense);
+            }
+            if let Some(audit) = &server.audit {
+                audit.denied_connection(&peer_key).await;
+            }
+            let response = crate::handshake::ServerHello::denied(decision.handshake_error(), denial);
             let json = serde_json::to_string(&response)?;
             send_stream.write_all(json.as_bytes()).await?;
             send_stream.write_all(b"\n").await?;
@@ -371,20 +1827,92 @@ async fn handle_connection(
             return Ok(());
         }
     }
+    let stream_auth = match capability {
+        Some(Ok(token)) => Some(capability_stream_auth(server.stream_auth.clone(), token, confined)),
+        Some(Err(e)) => {
+            tracing::debug!("Ignoring capability token of {}: {}", peer_key.id52(), e);
+            server.stream_auth.clone()
+        }
+        None => server.stream_auth.clone(),
+    };
+    
+    // A valid resumption token restores the protocols negotiated last time
+    let resumed_protocols = client_hello
+        .resumption_token
+        .as_deref()
+        .and_then(|token| crate::server::resumption::redeem(&server_key, token, &peer_key));
+    let resumed = resumed_protocols.is_some();
+    if resumed {
+        tracing::debug!("Resuming session for peer {}", peer_key.id52());
+    }
     
     // Filter protocols - only include ones we actually support
-    let mut accepted_protocols = Vec::new();
+    let relay_protocol = crate::server::relay::RelayConfig::protocol_json();
+    let device_protocol = crate::server::devices::DeviceConfig::protocol_json();
+    let batch_protocol = crate::batch::protocol_json();
+    let supports = |p: &serde_json::Value| {
+        server.request_handlers.contains(p)
+            || server.stream_handlers.contains(p)
+            || *p == batch_protocol
+            || (server.relay.is_relay() && *p == relay_protocol)
+            || (server.devices.is_some() && *p == device_protocol)
+    };
+    // Protocols from a valid token are kept while still served; only new ones are negotiated
+    let mut accepted_protocols = resumed_protocols.unwrap_or_default();
+    accepted_protocols.retain(&supports);
     for protocol in &client_hello.supported_protocols {
-        if request_handlers.contains_key(protocol) || stream_handlers.contains_key(protocol) {
+        if accepted_protocols.contains(protocol) {
+            continue;
+        }
+        if supports(protocol) {
             accepted_protocols.push(protocol.clone());
+        } else {
+            warn_if_similar(protocol, &peer_key, &server.request_handlers, &server.stream_handlers);
         }
     }
     
+    // Early (0-RTT style) request: only request/response protocols we just accepted
+    let early_request = client_hello.early_request.clone().filter(|early| {
+        accepted_protocols.contains(&early.protocol) && server.request_handlers.contains(&early.protocol)
+    });
+    
     // Send ServerHello
     let server_hello = if !accepted_protocols.is_empty() {
         let mut hello = crate::handshake::ServerHello::success();
-        if let crate::handshake::ServerHello::Success { accepted_protocols: ref mut protocols, .. } = hello {
+        if let crate::handshake::ServerHello::Success {
+            accepted_protocols: ref mut protocols,
+            ref mut tagged_responses,
+            ref mut resumption_token,
+            resumed: ref mut was_resumed,
+            ref mut early_response,
+            datagram_protocols: ref mut datagrams,
+            codecs: ref mut protocol_codecs,
+            ..
+        } = hello {
+            *early_response = early_request.is_some();
+            *datagrams = accepted_protocols
+                .iter()
+                .filter(|p| server.datagram_protocols.contains(*p))
+                .cloned()
+                .collect();
+            // Only codecs both sides have; the rest of the client's calls stay JSON
+            *protocol_codecs = accepted_protocols
+                .iter()
+                .filter_map(|p| {
+                    let codec = *server.codecs.get(p)?;
+                    (codec.is_supported() && client_hello.codecs.contains(&codec))
+                        .then(|| crate::codec::ProtocolCodec { protocol: p.clone(), codec })
+                })
+                .collect();
+            *resumption_token = Some(crate::server::resumption::issue(
+                server_key,
+                peer_key,
+                accepted_protocols.clone(),
+                server.resumption_ttl,
+            ));
             *protocols = accepted_protocols;
+            *tagged_responses = client_hello.tagged_responses;
+            *was_resumed = resumed;
         }
         hello
     } else {
@@ -396,9 +1924,14 @@ async fn handle_connection(
     let json = serde_json::to_string(&server_hello)?;
     send_stream.write_all(json.as_bytes()).await?;
     send_stream.write_all(b"\n").await?;
-    send_stream.finish()?;
+    
+    // The peer's session lasts until this function returns, see `peer_sessions`
+    let peer_session = server.peer_sessions.open(peer_key);
+    // Attachment streams are accepted as long as the connection is served, see crate::attachment
+    let _attachments = crate::attachment::Inbox::for_connection(&conn);
     
     if matches!(server_hello, crate::handshake::ServerHello::Failure { .. }) {
+        send_stream.finish()?;
         conn.close(0u8.into(), b"No compatible protocols");
         return Ok(());
     }
@@ -411,121 +1944,931 @@ async fn handle_connection(
     tracing::info!("Handshake complete with {} - {} protocols enabled", 
                   client_hello.client_name, protocol_count);
     
-    // Now we can accept application protocol streams
+    // Reports the disconnect when this function returns, errors and panics included
+    let accepted_protocols = match server_hello {
+        crate::handshake::ServerHello::Success { accepted_protocols, .. } => accepted_protocols,
+        crate::handshake::ServerHello::Failure { .. } => Vec::new(),
+    };
+    let peer_connection = server.peer_hooks.connected(
+        server_secret.public_key(),
+        crate::server::PeerInfo {
+            peer: peer_key,
+            protocols: accepted_protocols,
+            client_name: client_hello.client_name.clone(),
+            client_version: client_hello.client_version.clone(),
+            metadata: client_hello.metadata.clone(),
+            resumed,
+            stats: Default::default(),
+        },
+        Some(conn.clone()),
+    );
+    
+    // Now we can accept application protocol streams. Each stream is served by
+    // its own task so one slow handler does not hold up the peer's other
+    // requests; the semaphore caps how many run at once for this connection.
+    let max_concurrent_streams = server.max_concurrent_streams;
+    let stream_limit = std::sync::Arc::new(tokio::sync::Semaphore::new(max_concurrent_streams));
+    let connection = std::sync::Arc::new(ConnectionContext {
+        server: server.clone(),
+        stream_auth,
+        tagged_responses: client_hello.tagged_responses,
+        idle: ConnectionIdle::new(server.idle_policies.clone()),
+        stream_limit: stream_limit.clone(),
+    });
+    let idle = &connection.idle;
+    
+    // The early request is answered on the handshake stream, served like any other stream
+    match early_request {
+        Some(early) => {
+            let permit = stream_limit.clone().acquire_owned().await?;
+            peer_connection.stream_accepted();
+            let in_flight = crate::server::drain::InFlight::begin();
+            let codec = server
+                .codecs
+                .get(&early.protocol)
+                .copied()
+                .filter(|codec| codec.is_supported() && client_hello.codecs.contains(codec));
+            let wrapper = crate::wire::WrapperRequest {
+                signature: early.signature,
+                trace: early.trace,
+                deadline_ms: early.deadline_ms,
+                codec,
+                ..crate::wire::WrapperRequest::new(early.protocol, early.data)
+            };
+            let session = Some(peer_session.session().clone());
+            let datagram_conn = Some(conn.clone());
+            let connection = connection.clone();
+            crate::spawn(async move {
+                if let Err(e) = serve_wrapper(send_stream, recv_stream, wrapper, &connection, &peer_key, session, datagram_conn).await {
+                    tracing::error!("Early request error for peer {}: {}", peer_key.id52(), e);
+                }
+                drop(in_flight);
+                drop(permit);
+            });
+        }
+        None => send_stream.finish()?,
+    }
+    
     loop {
-        // Accept bidirectional stream - accept fastn-p2p protocol
-        let (protocol, mut send_stream, mut recv_stream) = 
-            fastn_net::accept_bi(&conn, &[fastn_net::Protocol::Generic(serde_json::Value::String("fastn-p2p".to_string()))]).await?;
-            
-        // Verify this is fastn-p2p protocol
-        match protocol {
+        // Wait for a free slot before accepting, so excess streams stay queued
+        // in QUIC flow control instead of piling up as tasks
+        // Idle means no stream is open; relay and device streams hold permits too
+        let busy = stream_limit.available_permits() < max_concurrent_streams;
+        let expiry = idle.timer().expired(busy);
+        if let Some(expiry) = expiry {
+            tracing::info!("Closing connection from {}: {}", peer_key.id52(), expiry);
+            conn.close(0u8.into(), expiry.to_string().as_bytes());
+            break;
+        }
+        
+        let next_check = idle.timer().next_check();
+        let permit = tokio::select! {
+            permit = stream_limit.clone().acquire_owned() => permit?,
+            _ = crate::draining() => break,
+            _ = server.stop.cancelled() => break,
+            _ = tokio::time::sleep(next_check) => continue,
+        };
+        
+        // Accept the next stream: fastn-p2p application streams, relay
+        // streams whose header names the target, or device streams
+        let next_check = idle.timer().next_check();
+        let (protocol, mut send_stream, recv_stream) = tokio::select! {
+            accepted = fastn_net::accept_any_bi(&conn) => accepted?,
+            _ = crate::draining() => break,
+            _ = server.stop.cancelled() => break,
+            _ = tokio::time::sleep(next_check) => continue,
+        };
+        peer_connection.stream_accepted();
+        // Counted until its task ends, so a drain waits for it, see crate::server::drain
+        let in_flight = crate::server::drain::InFlight::begin();
+        
+        // Offenses on earlier streams may have banned the peer since
+        if server.reputation.banned_for(&peer_key).is_some() {
+            tracing::info!("Closing connection from banned peer {}", peer_key.id52());
+            conn.close(0u8.into(), b"Banned");
+            break;
+        }
+        
+        let stream_peer = match protocol {
             fastn_net::Protocol::Generic(json) if json == serde_json::Value::String("fastn-p2p".to_string()) => {
                 // Good, this is our protocol
+                peer_key
+            }
+            fastn_net::Protocol::Relay { target, from } if target == server_id52 => {
+                // Last hop of a relayed stream: serve it as the original sender
+                match server.relay.origin(&peer_key, from.as_deref()) {
+                    Ok(origin) if origin == peer_key => origin,
+                    Ok(origin) => {
+                        let decision = admit_relayed_origin(
+                            server.connection_auth.as_deref(), &server.reputation, origin, &peer_key, &client_hello,
+                        ).await;
+                        match decision.denial() {
+                            None => origin,
+                            Some(denial) => {
+                                tracing::warn!("Refused relayed stream from {} via {}: {}", origin.id52(), peer_key.id52(), denial.message);
+                                if let Some(audit) = &server.audit {
+                                    audit.denied_connection(&origin).await;
+                                }
+                                send_stream.write_all(format!("{}\n", denial.message).as_bytes()).await?;
+                                send_stream.finish()?;
+                                continue;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("Rejected relayed stream from {}: {}", peer_key.id52(), e);
+                        send_stream.write_all(format!("{}\n", e).as_bytes()).await?;
+                        send_stream.finish()?;
+                        continue;
+                    }
+                }
+            }
+            fastn_net::Protocol::Relay { target, from } => {
+                let server = server.clone();
+                let active = idle.active(&crate::server::relay::RelayConfig::protocol_json());
+                crate::spawn(async move {
+                    if let Err(e) = crate::server::relay::forward(
+                        send_stream, recv_stream, peer_key, target, from, &server.relay, server.server_secret.clone(),
+                    ).await {
+                        tracing::error!("Relay error for peer {}: {}", peer_key.id52(), e);
+                    }
+                    drop(active);
+                    drop(in_flight);
+                    drop(permit);
+                });
+                continue;
+            }
+            header @ (fastn_net::Protocol::DevicePair | fastn_net::Protocol::DeviceProxy { .. }) => {
+                let Some(devices) = server.devices.clone() else {
+                    tracing::warn!("Device stream from {} but devices are not enabled", peer_key.id52());
+                    send_stream.write_all(b"Devices are not enabled on this identity\n").await?;
+                    send_stream.finish()?;
+                    continue;
+                };
+                let server_secret = server_secret.clone();
+                let active = idle.active(&crate::server::devices::DeviceConfig::protocol_json());
+                crate::spawn(async move {
+                    let result = match header {
+                        fastn_net::Protocol::DeviceProxy { target } => crate::server::devices::forward(
+                            send_stream, recv_stream, peer_key, target, &devices, server_secret,
+                        ).await,
+                        _ => crate::server::devices::pair(send_stream, recv_stream, peer_key, &devices).await,
+                    };
+                    if let Err(e) = result {
+                        tracing::error!("Device stream error for {}: {}", peer_key.id52(), e);
+                    }
+                    drop(active);
+                    drop(in_flight);
+                    drop(permit);
+                });
+                continue;
+            }
+            fastn_net::Protocol::WhatTimeIsIt => {
+                // Answered here like pings, so every server can be asked
+                if let Err(e) = crate::time_sync::answer(&mut send_stream).await {
+                    tracing::warn!("Failed to tell {} the time: {}", peer_key.id52(), e);
+                }
+                continue;
             }
             other => {
                 tracing::warn!("Unsupported protocol for request/response: {:?}", other);
                 continue;
             }
-        };
-        
-        // Read and parse the wrapper request directly as typed struct
-        let wrapper: WrapperRequest = match fastn_net::next_json(&mut recv_stream).await {
-            Ok(wrapper) => wrapper,
-            Err(e) => {
-                tracing::warn!("Failed to read/parse wrapper request: {}", e);
-                let error_msg = format!("Failed to parse wrapper request: {}", e);
-                send_stream.write_all(error_msg.as_bytes()).await?;
-                send_stream.write_all(b"\n").await?;
-                continue;
+        };
+        
+        // Relayed senders aren't connected here, so they have no session, datagrams or attachments
+        let session = (stream_peer == peer_key).then(|| peer_session.session().clone());
+        let datagram_conn = (stream_peer == peer_key).then(|| conn.clone());
+        let connection = connection.clone();
+        crate::spawn(async move {
+            if let Err(e) = handle_stream(send_stream, recv_stream, &connection, &stream_peer, session, datagram_conn).await {
+                tracing::error!("Stream error for peer {}: {}", stream_peer.id52(), e);
+            }
+            drop(in_flight);
+            drop(permit);
+        });
+        
+        // Keep the connection alive by continuing to accept streams
+        // We'll break when accept_any_bi fails (client closes connection)
+    }
+    
+    // Shutting down: streams already accepted finish in their own tasks
+    tracing::debug!("Stopped accepting streams from {}", peer_key.id52());
+    Ok(())
+}
+
+/// Serve one application stream: read the wrapper request and dispatch it
+async fn handle_stream(
+    mut send_stream: iroh::endpoint::SendStream,
+    mut recv_stream: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    connection: &ConnectionContext,
+    peer_key: &fastn_id52::PublicKey,
+    session: Option<crate::server::PeerSession>,
+    datagram_conn: Option<iroh::endpoint::Connection>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = &*connection.server;
+    let tagged_responses = connection.tagged_responses;
+    // Read and parse the wrapper request directly as typed struct
+    let mut wrapper: crate::wire::WrapperRequest = match recv_stream.next_json().await {
+        Ok(wrapper) => wrapper,
+        Err(e) => {
+            tracing::warn!("Failed to read/parse wrapper request: {}", e);
+            server.reputation.record(peer_key, crate::server::reputation::Offense::MalformedFrame);
+            let error_msg = format!("Failed to parse wrapper request: {}", e);
+            send_stream.write_all(error_msg.as_bytes()).await?;
+            send_stream.write_all(b"\n").await?;
+            return Ok(());
+        }
+    };
+    wrapper.protocol = crate::protocol_key::canonicalize(wrapper.protocol);
+    // Payloads in another codec follow the line, as big as a JSON line may be
+    if let Err(e) = wrapper.read_encoded_data(&mut recv_stream, fastn_net::MAX_FRAME_LEN).await {
+        tracing::warn!("Failed to read {:?} payload from peer {}: {}", wrapper.protocol, peer_key.id52(), e);
+        server.reputation.record(peer_key, crate::server::reputation::Offense::MalformedFrame);
+        let response_json = crate::wire::encode_response(Err(serde_json::Value::String(e.to_string())), tagged_responses)?;
+        send_stream.write_all(response_json.as_bytes()).await?;
+        send_stream.write_all(b"\n").await?;
+        send_stream.finish()?;
+        return Ok(());
+    }
+    serve_wrapper(send_stream, recv_stream, wrapper, connection, peer_key, session, datagram_conn).await
+}
+
+/// Serve a request read from a stream or sent early, with its payload already in `data`
+///
+/// `wrapper.codec` is the codec the response goes out in.
+async fn serve_wrapper(
+    mut send_stream: iroh::endpoint::SendStream,
+    recv_stream: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    mut wrapper: crate::wire::WrapperRequest,
+    connection: &ConnectionContext,
+    peer_key: &fastn_id52::PublicKey,
+    session: Option<crate::server::PeerSession>,
+    datagram_conn: Option<iroh::endpoint::Connection>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = &*connection.server;
+    let server_key = &server.server_secret;
+    let tagged_responses = connection.tagged_responses;
+    // Keeps the connection from idling out while this stream is served
+    let _active = connection.idle.active(&wrapper.protocol);
+    
+    // Signed requests must verify before anything else sees them
+    let verified = match &wrapper.signature {
+        Some(signature) => {
+            match crate::signing::verify_request(signature, &server_key.public_key(), &wrapper.protocol, &wrapper.data) {
+                Ok(verified) => Some(verified),
+                Err(e) => {
+                    tracing::warn!("Rejected signed request from peer {}: {}", peer_key.id52(), e);
+                    server.reputation.record(peer_key, crate::server::reputation::Offense::AuthFailure);
+                    if let Some(audit) = &server.audit {
+                        audit.denied(peer_key, &wrapper.protocol, &wrapper.data).await;
+                    }
+                    let response_json = crate::wire::encode_response(Err(serde_json::Value::String(e.to_string())), tagged_responses)?;
+                    send_stream.write_all(response_json.as_bytes()).await?;
+                    send_stream.write_all(b"\n").await?;
+                    send_stream.finish()?;
+                    return Ok(());
+                }
+            }
+        }
+        None => None,
+    };
+    // Past it the client no longer waits, so the handler is stopped and nothing is sent
+    let client_deadline = crate::wire::deadline_from_ms(wrapper.deadline_ms);
+    
+    // The calls of a batch are authorized and dispatched one by one
+    if wrapper.protocol == crate::batch::protocol_json() {
+        let signature = wrapper.signature.take();
+        let codec = wrapper.codec.unwrap_or_default();
+        let protocol = wrapper.protocol.clone();
+        let batch = run_batch(connection, *peer_key, session, wrapper, verified, client_deadline);
+        let batch = until_client_gives_up(client_deadline, batch);
+        let Some(result) = until_peer_gone(&mut send_stream, batch).await.flatten() else {
+            tracing::debug!("Peer {} stopped waiting before its batch finished", peer_key.id52());
+            return Ok(());
+        };
+        let signed = signature.as_ref().map(|signature| (server_key, signature));
+        let response = encode_for_peer(result, tagged_responses, signed, codec, server.max_response_size, None)?;
+        send_response(&mut send_stream, &response, tagged_responses, codec, peer_key, &protocol).await?;
+        send_stream.finish()?;
+        return Ok(());
+    }
+    
+    // Check stream-level authorization if hook is provided
+    if let Some(auth) = &connection.stream_auth {
+        let decision = auth(crate::server::StreamAuthRequest::new(*peer_key, &wrapper.protocol, &wrapper.data)).await;
+        if let Some(denial) = decision.denial() {
+            tracing::warn!("Stream authorization denied for peer {} protocol {:?}: {}", 
+                        peer_key.id52(), wrapper.protocol, denial.message);
+            if let Some(offense) = crate::server::reputation::Offense::of_decision(&decision) {
+                server.reputation.record(peer_key, offense);
+            }
+            if let Some(audit) = &server.audit {
+                audit.denied(peer_key, &wrapper.protocol, &wrapper.data).await;
+            }
+            let line = crate::wire::encode_denied(denial, tagged_responses)?;
+            send_stream.write_all(line.as_bytes()).await?;
+            send_stream.write_all(b"\n").await?;
+            send_stream.finish()?;
+            return Ok(());
+        }
+    }
+    
+    // Check if it's a streaming or request handler
+    let is_streaming = server.stream_handlers.contains(&wrapper.protocol);
+    let is_request = server.request_handlers.contains(&wrapper.protocol);
+    
+    if !is_streaming && !is_request {
+        tracing::warn!("No handler for protocol {:?} from peer {}", wrapper.protocol, peer_key.id52());
+        warn_if_similar(&wrapper.protocol, peer_key, &server.request_handlers, &server.stream_handlers);
+        let error_msg = format!("No handler for protocol: {:?}", wrapper.protocol);
+        send_stream.write_all(error_msg.as_bytes()).await?;
+        send_stream.write_all(b"\n").await?;
+        return Ok(());
+    }
+    
+    let audit = server.audit.as_ref().map(|audit| audit.start(peer_key, &wrapper.protocol, &wrapper.data, verified.as_ref()));
+    let mut request = crate::server::middleware::LayerRequest::new(
+        *peer_key, wrapper.protocol.clone(), wrapper.data, wrapper.metadata, verified, is_streaming, wrapper.trace,
+    );
+    request.session = session;
+    if let Some(conn) = datagram_conn.as_ref().filter(|_| !is_streaming) {
+        request.attachments = crate::attachment::IncomingAttachment::for_request(conn, wrapper.attachments);
+    }
+    
+    if is_streaming {
+        // Subtasks spawned through the session end with the handler or the connection
+        let tasks = crate::coordination::scope();
+        let close_reason = std::sync::Arc::new(std::sync::OnceLock::new());
+        if let Some(conn) = datagram_conn.clone() {
+            let scope = tasks.clone();
+            let close_reason = close_reason.clone();
+            tasks.spawn(async move {
+                let error = conn.closed().await;
+                // Before cancelling, so `Session::closed` sees why
+                let _ = close_reason.set(crate::server::CloseReason::ConnectionLost { error });
+                scope.cancel();
+            });
+        }
+        // Registered before the handler runs, so early datagrams aren't dropped
+        let datagrams = datagram_conn
+            .filter(|_| server.datagram_protocols.contains(&wrapper.protocol))
+            .map(|conn| crate::datagram::Datagrams::open(&conn, &send_stream));
+        // The handler takes the streams; if they're still here afterwards a layer refused
+        let streams = std::sync::Arc::new(std::sync::Mutex::new(Some((send_stream, recv_stream))));
+        let endpoint_streams = streams.clone();
+        let stream_handlers = server.stream_handlers.clone();
+        let peer = *peer_key;
+        let identity = server_key.public_key();
+        let handler_tasks = tasks.clone();
+        let handler_close_reason = close_reason.clone();
+        let result = server.layers.run(request, move |request| async move {
+            // The protocol may have been unregistered while the layers ran
+            if !stream_handlers.contains(request.protocol()) {
+                return Err(serde_json::Value::String("No handler for protocol".to_string()));
+            }
+            let Some((send_stream, recv_stream)) = endpoint_streams
+                .lock()
+                .expect("Failed to acquire lock on stream")
+                .take()
+            else {
+                return Err(serde_json::Value::String("Stream already handled".to_string()));
+            };
+            let data_json = serde_json::to_string(&request.data).unwrap_or_else(|e| {
+                format!("Failed to serialize data: {}", e)
+            });
+            
+            // Call the streaming handler with the streams
+            let Some(handler_future) = stream_handlers.with(request.protocol(), |handler| {
+                handler(send_stream, recv_stream, peer, identity, data_json, datagrams, handler_tasks, handler_close_reason)
+            }) else {
+                return Err(serde_json::Value::String("No handler for protocol".to_string()));
+            };
+            match handler_future.await {
+                Ok(()) => Ok(serde_json::Value::Null),
+                Err(e) => Err(serde_json::Value::String(e.to_string())),
             }
-        };
+        }).await;
+        tasks.close().await;
         
-        // Check stream-level authorization if hook is provided
-        if let Some(auth) = stream_auth {
-            if !auth(&peer_key, &wrapper.protocol, &wrapper.data) {
-                tracing::warn!("Stream authorization denied for peer {} protocol {:?}", 
-                            peer_key.id52(), wrapper.protocol);
-                let error_msg = "Authorization denied";
-                send_stream.write_all(error_msg.as_bytes()).await?;
+        let refused = streams.lock().expect("Failed to acquire lock on stream").take();
+        if let Some(audit) = audit {
+            let outcome = match (&refused, &result) {
+                (Some(_), _) => crate::server::audit::AuditOutcome::Denied,
+                (None, Ok(_)) => crate::server::audit::AuditOutcome::Ok,
+                (None, Err(error)) => crate::server::audit::AuditOutcome::Error { message: crate::metrics::protocol_name(error) },
+            };
+            audit.finish(outcome, 0).await;
+        }
+        match (refused, result) {
+            (Some((mut send_stream, _)), result) => {
+                let error = result.err().unwrap_or(serde_json::Value::Null);
+                tracing::warn!("Middleware refused stream {:?} from peer {}: {}", wrapper.protocol, peer_key.id52(), error);
+                let response_json = crate::wire::encode_response(Err(error), tagged_responses)?;
+                send_stream.write_all(response_json.as_bytes()).await?;
                 send_stream.write_all(b"\n").await?;
                 send_stream.finish()?;
-                continue;
             }
+            (None, Err(e)) => tracing::error!("Streaming handler error: {}", e),
+            (None, Ok(_)) => {}
         }
+        // For streaming, the handler manages the streams, so we're done
+    } else {
+        // Handle request/response protocol
+        let timeout = server.request_timeouts.for_protocol(&wrapper.protocol);
+        let deadline = handler_deadline(timeout, client_deadline);
+        // Handlers watching their context stop once the peer is gone, the timeout fires or the reply is sent
+        let cancellation = tokio_util::sync::CancellationToken::new();
+        let _cancel_on_exit = cancellation.clone().drop_guard();
+        let server_public_key = server_key.public_key();
+        let handler_future = until_client_gives_up(client_deadline, run_request_handler(
+            &wrapper.protocol,
+            &server_public_key,
+            peer_key,
+            server.layers.run(request, request_endpoint(server.request_handlers.clone(), deadline, cancellation)),
+            timeout,
+            audit,
+        ));
+        let Some(result) = until_peer_gone(&mut send_stream, handler_future).await.flatten() else {
+            tracing::debug!("Peer {} stopped waiting before {:?} finished", peer_key.id52(), wrapper.protocol);
+            return Ok(());
+        };
+        let signed = wrapper.signature.as_ref().map(|signature| (server_key, signature));
+        let codec = wrapper.codec.unwrap_or_default();
+        let max_age = route(&server.max_ages, &wrapper.protocol).copied();
+        let response = encode_for_peer(result, tagged_responses, signed, codec, server.max_response_size, max_age)?;
         
-        // Check if it's a streaming or request handler
-        let is_streaming = stream_handlers.contains_key(&wrapper.protocol);
-        let is_request = request_handlers.contains_key(&wrapper.protocol);
+        // Send response
+        send_response(&mut send_stream, &response, tagged_responses, codec, peer_key, &wrapper.protocol).await?;
         
-        if !is_streaming && !is_request {
-            tracing::warn!("No handler for protocol {:?} from peer {}", wrapper.protocol, peer_key.id52());
-            let error_msg = format!("No handler for protocol: {:?}", wrapper.protocol);
-            send_stream.write_all(error_msg.as_bytes()).await?;
-            send_stream.write_all(b"\n").await?;
-            continue;
+        // Signal that we're done sending by calling finish()
+        // This tells the client no more data will be sent on this stream
+        send_stream.finish()?;
+    }
+    
+    Ok(())
+}
+
+/// Warn when `protocol` has no handler but a similar one does, see [`crate::protocol_key::similar`]
+fn warn_if_similar(
+    protocol: &serde_json::Value,
+    peer_key: &fastn_id52::PublicKey,
+    request_handlers: &Registry<RequestHandler>,
+    stream_handlers: &Registry<StreamHandler>,
+) {
+    let requests = request_handlers.read();
+    let streams = stream_handlers.read();
+    if let Some(registered) = crate::protocol_key::similar(protocol, requests.keys().chain(streams.keys())) {
+        tracing::warn!(
+            "Peer {} asked for protocol {} but the handler is registered as {}; check both sides serialize it the same way",
+            peer_key.id52(),
+            crate::protocol_key::to_key_string(protocol),
+            crate::protocol_key::to_key_string(registered),
+        );
+    }
+}
+
+/// Run the calls of a batch concurrently, see [`crate::batch`]
+///
+/// Each call goes through stream auth, the middleware and its timeout like
+/// a call of its own; its outcome becomes one result of the batch. The batch
+/// itself only fails when it can't be read or is too large.
+///
+/// One call runs on the batch's own stream slot; each further one at the
+/// same time takes a free slot of the connection, without waiting for one,
+/// so batches stay within `max_concurrent_streams` like separate streams.
+async fn run_batch(
+    connection: &ConnectionContext,
+    peer_key: fastn_id52::PublicKey,
+    session: Option<crate::server::PeerSession>,
+    wrapper: crate::wire::WrapperRequest,
+    verified: Option<crate::signing::VerifiedSender>,
+    client_deadline: Option<tokio::time::Instant>,
+) -> HandlerResult {
+    let server = &*connection.server;
+    let server_key = server.server_secret.public_key();
+    let crate::wire::WrapperRequest { data, metadata, trace, .. } = wrapper;
+    let batch: crate::batch::BatchRequest = serde_json::from_value(data)
+        .map_err(|e| serde_json::Value::String(format!("Invalid batch request: {}", e)))?;
+    if batch.calls.len() > crate::batch::MAX_BATCH_CALLS {
+        server.reputation.record(&peer_key, crate::server::reputation::Offense::LimitViolation);
+        return Err(serde_json::Value::String(format!(
+            "Batch of {} calls exceeds the maximum of {}",
+            batch.calls.len(),
+            crate::batch::MAX_BATCH_CALLS
+        )));
+    }
+    
+    let count = batch.calls.len();
+    let calls = batch.calls.into_iter().map(|mut call| {
+        // Resolved to the same handler as the call made on its own stream
+        call.protocol = crate::protocol_key::canonicalize(call.protocol);
+        let audit = server.audit.as_ref().map(|audit| audit.start(&peer_key, &call.protocol, &call.data, verified.as_ref()));
+        let mut request = crate::server::middleware::LayerRequest::new(
+            peer_key, call.protocol.clone(), call.data.clone(), metadata.clone(), verified.clone(), false, trace.clone(),
+        );
+        request.session = session.clone();
+        async move {
+            let decision = match &connection.stream_auth {
+                Some(auth) => auth(crate::server::StreamAuthRequest::new(peer_key, &call.protocol, &call.data)).await,
+                None => crate::server::AuthDecision::Allow,
+            };
+            // A batch result is the call's OUTPUT or ERROR, so the reason goes in as text
+            if let Some(denial) = decision.denial() {
+                tracing::warn!("Stream authorization denied for peer {} protocol {:?} in batch", peer_key.id52(), call.protocol);
+                if let Some(offense) = crate::server::reputation::Offense::of_decision(&decision) {
+                    server.reputation.record(&peer_key, offense);
+                }
+                if let Some(audit) = audit {
+                    audit.finish(crate::server::audit::AuditOutcome::Denied, 0).await;
+                }
+                return Err(serde_json::Value::String(format!("Authorization denied: {}", denial.message)));
+            }
+            if !server.request_handlers.contains(&call.protocol) {
+                return Err(serde_json::Value::String(format!("No handler for protocol: {:?}", call.protocol)));
+            }
+            let timeout = server.request_timeouts.for_protocol(&call.protocol);
+            let deadline = handler_deadline(timeout, client_deadline);
+            let cancellation = tokio_util::sync::CancellationToken::new();
+            let _cancel_on_exit = cancellation.clone().drop_guard();
+            run_request_handler(
+                &call.protocol,
+                &server_key,
+                &peer_key,
+                server.layers.run(request, request_endpoint(server.request_handlers.clone(), deadline, cancellation)),
+                timeout,
+                audit,
+            ).await
         }
-        
-        // Convert data back to JSON string
-        let data_json = serde_json::to_string(&wrapper.data).unwrap_or_else(|e| {
-            format!("Failed to serialize data: {}", e)
-        });
-        
-        if is_streaming {
-            // Handle streaming protocol
-            let handler = stream_handlers.get(&wrapper.protocol).unwrap();
-            
-            // Call the streaming handler with the streams
-            match handler(send_stream, recv_stream, peer_key.clone(), data_json).await {
-                Ok(()) => {
-                    // Streaming completed successfully
+    });
+    let mut calls = calls.enumerate();
+    let mut running = futures_util::stream::FuturesUnordered::new();
+    let mut results: Vec<Option<HandlerResult>> = (0..count).map(|_| None).collect();
+    loop {
+        while let Some(permit) = match running.is_empty() {
+            true => Some(None),
+            false => connection.stream_limit.clone().try_acquire_owned().ok().map(Some),
+        } {
+            let Some((index, call)) = calls.next() else {
+                break;
+            };
+            running.push(async move {
+                let result = call.await;
+                drop(permit);
+                (index, result)
+            });
+        }
+        let Some((index, result)) = futures_util::StreamExt::next(&mut running).await else {
+            break;
+        };
+        results[index] = Some(result);
+    }
+    
+    let response = crate::batch::BatchResponse {
+        results: results
+            .into_iter()
+            .map(|result| crate::wire::ResponseEnvelope::from(result.expect("every call of the batch ran")))
+            .collect(),
+    };
+    serde_json::to_value(response).map_err(|e| serde_json::Value::String(e.to_string()))
+}
+
+/// What a running server needs to answer calls from its own process, see [`crate::server::loopback`]
+pub(crate) struct LocalServer {
+    server_key: fastn_id52::PublicKey,
+    request_handlers: Registry<RequestHandler>,
+    connection_auth: Option<std::sync::Arc<ConnectionAuthHook>>,
+    stream_auth: Option<std::sync::Arc<StreamAuthHook>>,
+    capability_issuers: std::sync::Arc<Vec<fastn_id52::PublicKey>>,
+    reputation: std::sync::Arc<crate::server::reputation::Reputation>,
+    request_timeouts: RequestTimeouts,
+    max_response_size: Option<usize>,
+    audit: Option<crate::server::audit::AuditLog>,
+    layers: crate::server::middleware::Layers,
+    max_ages: MaxAges,
+}
+
+impl LocalServer {
+    /// How long clients may cache answers of `protocol`, see [`ServerBuilder::with_protocol_max_age`]
+    pub(crate) fn max_age(&self, protocol: &serde_json::Value) -> Option<std::time::Duration> {
+        route(&self.max_ages, protocol).copied()
+    }
+
+    /// Answer `call` from `peer_key` the way [`handle_connection`] and [`handle_stream`] would
+    ///
+    /// `hello` is what the client would have sent in its ClientHello.
+    pub(crate) async fn call(
+        &self,
+        peer_key: fastn_id52::PublicKey,
+        hello: &crate::handshake::HelloMetadata,
+        call: crate::interceptor::OutgoingCall,
+    ) -> Result<HandlerResult, crate::client::CallError> {
+        let server_key = self.server_key;
+        if let Some(left) = self.reputation.banned_for(&peer_key) {
+            if let Some(audit) = &self.audit {
+                audit.denied_connection(&peer_key).await;
+            }
+            let decision = crate::server::AuthDecision::throttle(left);
+            return Err(crate::client::CallError::from_denial(decision.denial().expect("throttling is a denial")));
+        }
+        let protocol = crate::protocol_key::canonicalize(call.protocol().clone());
+        let client_hello = crate::handshake::ClientHello::new("fastn-p2p-client", env!("CARGO_PKG_VERSION"))
+            .with_protocol(&protocol)
+            .with_hello_metadata(hello);
+        if !client_hello.metadata_within_limits() {
+            self.reputation.record(&peer_key, crate::server::reputation::Offense::LimitViolation);
+            return Err(crate::client::CallError::HandshakeRejected {
+                code: crate::handshake::HandshakeError::MetadataTooLarge,
+            });
+        }
+
+        // Connection auth and capability tokens as in handle_connection
+        let capability = client_hello
+            .capability()
+            .map(|token| token.and_then(|token| token.verify(&peer_key, &self.capability_issuers).map(|()| token)));
+        let mut confined = false;
+        if let Some(auth) = self.connection_auth.as_deref() {
+            let mut decision = auth(peer_key, client_hello.clone()).await;
+            if let crate::server::AuthDecision::Deny { .. } = decision {
+                match &capability {
+                    Some(Ok(_)) => {
+                        decision = crate::server::AuthDecision::Allow;
+                        confined = true;
+                    }
+                    Some(Err(e)) => decision = crate::server::AuthDecision::deny(e.code(), e.to_string()),
+                    None => {}
                 }
-                Err(e) => {
-                    tracing::error!("Streaming handler error: {}", e);
+            }
+            if let Some(denial) = decision.denial() {
+                tracing::warn!("Loopback call denied for peer {}: {}", peer_key.id52(), denial.message);
+                if let Some(offense) = crate::server::reputation::Offense::of_decision(&decision) {
+                    self.reputation.record(&peer_key, offense);
+                }
+                if let Some(audit) = &self.audit {
+                    audit.denied_connection(&peer_key).await;
                 }
+                return Err(crate::client::CallError::from_denial(denial));
             }
-            // For streaming, the handler manages the streams, so we're done
-        } else {
-            // Handle request/response protocol
-            let handler = request_handlers.get(&wrapper.protocol).unwrap();
-            
-            let response_json = handler(data_json).await;
-            
-            // Send response
-            match send_response(&mut send_stream, &response_json, &peer_key, &wrapper.protocol).await {
-                Ok(_) => {
-                    // Response sent successfully
+        }
+        let stream_auth = match capability {
+            Some(Ok(token)) => Some(capability_stream_auth(self.stream_auth.clone(), token, confined)),
+            _ => self.stream_auth.clone(),
+        };
+
+        if !self.request_handlers.contains(&protocol) {
+            return Err(crate::client::CallError::ProtocolNotAccepted { protocol });
+        }
+        if let Some(auth) = stream_auth.as_deref() {
+            let decision = auth(crate::server::StreamAuthRequest::new(peer_key, &protocol, &call.data)).await;
+            if let Some(denial) = decision.denial() {
+                tracing::warn!("Stream authorization denied for peer {} protocol {:?}: {}",
+                            peer_key.id52(), protocol, denial.message);
+                if let Some(offense) = crate::server::reputation::Offense::of_decision(&decision) {
+                    self.reputation.record(&peer_key, offense);
                 }
-                Err(e) => {
-                    tracing::error!("Failed to send response to peer {}: {}", peer_key.id52(), e);
-                    break;
+                if let Some(audit) = &self.audit {
+                    audit.denied(&peer_key, &protocol, &call.data).await;
                 }
+                return Err(crate::client::CallError::from_denial(denial));
             }
-            
-            // Signal that we're done sending by calling finish()
-            // This tells the client no more data will be sent on this stream
-            send_stream.finish()?;
         }
-        
-        // Keep the connection alive by continuing to accept streams
-        // We'll break when accept_bi fails (client closes connection)
+
+        // Counted like a stream, so a drain waits for it
+        let _in_flight = crate::server::drain::InFlight::begin();
+        let trace = Some(call.trace().clone());
+        let audit = self.audit.as_ref().map(|audit| audit.start(&peer_key, &protocol, &call.data, None));
+        let request = crate::server::middleware::LayerRequest::new(
+            peer_key, protocol.clone(), call.data, call.metadata, None, false, trace,
+        );
+        let timeout = self.request_timeouts.for_protocol(&protocol);
+        let deadline = handler_deadline(timeout, call.deadline);
+        let cancellation = tokio_util::sync::CancellationToken::new();
+        let _cancel_on_exit = cancellation.clone().drop_guard();
+        let result = run_request_handler(
+            &protocol,
+            &server_key,
+            &peer_key,
+            self.layers.run(request, request_endpoint(self.request_handlers.clone(), deadline, cancellation)),
+            timeout,
+            audit,
+        )
+        .await;
+
+        // The same limit encode_for_peer applies to responses going out on a stream
+        let mut measured = LimitedBuffer::new(Some(0));
+        let size = match &result {
+            Ok(value) | Err(value) => serde_json::to_writer(&mut measured, value).map_or(0, |()| measured.size),
+        };
+        match self.max_response_size {
+            Some(limit) if size > limit => {
+                let error = ResponseTooLargeError { size, limit };
+                tracing::warn!("{}", error);
+                Ok(Err(serde_json::Value::String(error.to_string())))
+            }
+            _ => Ok(result),
+        }
+    }
+}
+
+/// The handler's deadline: the server's timeout from now or the client's, whichever comes first
+///
+/// Taken before any layer runs.
+fn handler_deadline(
+    timeout: Option<std::time::Duration>,
+    client_deadline: Option<tokio::time::Instant>,
+) -> Option<tokio::time::Instant> {
+    let server_deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    match (server_deadline, client_deadline) {
+        (Some(server), Some(client)) => Some(server.min(client)),
+        (server, client) => server.or(client),
+    }
+}
+
+/// The innermost step of the middleware chain: call the registered request handler
+fn request_endpoint(
+    request_handlers: Registry<RequestHandler>,
+    deadline: Option<tokio::time::Instant>,
+    cancellation: tokio_util::sync::CancellationToken,
+) -> impl FnOnce(crate::server::LayerRequest) -> std::pin::Pin<Box<dyn std::future::Future<Output = HandlerResult> + Send>> + Send + 'static {
+    move |request| {
+        Box::pin(async move {
+            let context = crate::server::RequestContext::new(&request, deadline, cancellation);
+            let data_json = serde_json::to_string(&request.data).unwrap_or_else(|e| {
+                format!("Failed to serialize data: {}", e)
+            });
+            let Some(handler_future) = request_handlers.with(request.protocol(), |handler| handler(data_json, context)) else {
+                return Err(serde_json::Value::String("No handler for protocol".to_string()));
+            };
+            handler_future.await
+        })
+    }
+}
+
+/// Run a request handler (behind its middleware), aborting it if it exceeds `timeout`
+///
+/// The call is counted in `protocol`'s latency histogram, see [`crate::metrics`],
+/// and its outcome goes in the audit log when the server keeps one.
+async fn run_request_handler(
+    protocol: &serde_json::Value,
+    server: &fastn_id52::PublicKey,
+    peer: &fastn_id52::PublicKey,
+    handler_future: impl std::future::Future<Output = HandlerResult>,
+    timeout: Option<std::time::Duration>,
+    audit: Option<crate::server::audit::PendingAudit>,
+) -> HandlerResult {
+    // Dropped unfinished if the peer stops waiting, which counts as failed
+    let timer = crate::metrics::Timer::start(crate::metrics::protocol_name(protocol), None, server.id52(), *peer);
+    let (result, timed_out) = match timeout {
+        None => (handler_future.await, false),
+        // Dropping the future on timeout aborts the handler
+        Some(timeout) => match tokio::time::timeout(timeout, handler_future).await {
+            Ok(result) => (result, false),
+            Err(_elapsed) => {
+                tracing::warn!("Request handler timed out after {:?}", timeout);
+                (Err(serde_json::Value::String(RequestTimeoutError { timeout }.to_string())), true)
+            }
+        },
+    };
+    timer.finish(result.is_ok());
+    if let Some(audit) = audit {
+        let outcome = match &result {
+            Ok(_) => crate::server::audit::AuditOutcome::Ok,
+            Err(_) if timed_out => crate::server::audit::AuditOutcome::Timeout,
+            Err(error) => crate::server::audit::AuditOutcome::Error { message: crate::metrics::protocol_name(error) },
+        };
+        let mut measured = LimitedBuffer::new(Some(0));
+        let bytes_out = match &result {
+            Ok(value) | Err(value) => serde_json::to_writer(&mut measured, value).map_or(0, |()| measured.size),
+        };
+        audit.finish(outcome, bytes_out as u64).await;
+    }
+    result
+}
+
+/// Run `handler_future` unless the client's deadline passes first
+///
+/// Like [`until_peer_gone`], dropping the future aborts the handler.
+async fn until_client_gives_up<T>(
+    client_deadline: Option<tokio::time::Instant>,
+    handler_future: impl std::future::Future<Output = T>,
+) -> Option<T> {
+    let Some(deadline) = client_deadline else {
+        return Some(handler_future.await);
+    };
+    let result = tokio::time::timeout_at(deadline, handler_future).await.ok();
+    if result.is_none() {
+        tracing::debug!("Client deadline passed, dropping the handler and its response");
+    }
+    result
+}
+
+/// Run `handler_future` unless the peer stops waiting for the response first
+///
+/// Dropping the future aborts the handler and cancels any deferred response
+/// it handed out.
+async fn until_peer_gone<T>(
+    send_stream: &mut iroh::endpoint::SendStream,
+    handler_future: impl std::future::Future<Output = T>,
+) -> Option<T> {
+    tokio::select! {
+        result = handler_future => Some(result),
+        _ = send_stream.stopped() => None,
+    }
+}
+
+/// Encode a handler result for the peer in `codec`, signed when the request was
+///
+/// A response whose encoding is over `max_response_size` bytes is swapped for
+/// a [`ResponseTooLargeError`]; it is measured as it is encoded, never held
+/// in memory whole. Tagged answers carry `max_age`, see [`crate::wire::MAX_AGE`].
+fn encode_for_peer(
+    result: HandlerResult,
+    tagged: bool,
+    signed: Option<(&fastn_id52::SecretKey, &crate::signing::PayloadSignature)>,
+    codec: crate::codec::Codec,
+    max_response_size: Option<usize>,
+    max_age: Option<std::time::Duration>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let encode = |result: HandlerResult, limit: Option<usize>| -> Result<LimitedBuffer, Box<dyn std::error::Error>> {
+        let mut response = LimitedBuffer::new(limit);
+        match (signed, result) {
+            (Some((key, request)), result) if tagged => {
+                codec.encode_into(&crate::wire::signed_response(result, key, request, max_age)?, &mut response)?
+            }
+            (_, result) if tagged && max_age.is_some() && result.is_ok() => {
+                let envelope = serde_json::to_value(crate::wire::ResponseEnvelope::from(result))?;
+                codec.encode_into(&crate::wire::with_max_age(envelope, max_age), &mut response)?
+            }
+            (_, result) if tagged => codec.encode_into(&crate::wire::ResponseEnvelope::from(result), &mut response)?,
+            (_, Ok(value) | Err(value)) => codec.encode_into(&value, &mut response)?,
+        }
+        Ok(response)
+    };
+    let response = encode(result, max_response_size)?;
+    match max_response_size {
+        Some(limit) if response.size > limit => {
+            let error = ResponseTooLargeError { size: response.size, limit };
+            tracing::warn!("{}", error);
+            Ok(encode(Err(serde_json::Value::String(error.to_string())), None)?.bytes)
+        }
+        _ => Ok(response.bytes),
+    }
+}
+
+/// Bytes written to it, kept only while they fit in `limit` but all counted
+struct LimitedBuffer {
+    bytes: Vec<u8>,
+    size: usize,
+    limit: Option<usize>,
+}
+
+impl LimitedBuffer {
+    fn new(limit: Option<usize>) -> Self {
+        Self { bytes: Vec::new(), size: 0, limit }
+    }
+}
+
+impl std::io::Write for LimitedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.size += buf.len();
+        if self.limit.is_none_or(|limit| self.size <= limit) {
+            self.bytes.extend_from_slice(buf);
+        } else if !self.bytes.is_empty() {
+            self.bytes = Vec::new();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
-    
-    Ok(())
 }
 
-/// Send response with proper error handling and logging
+/// Send a response from [`encode_for_peer`] with proper error handling and logging
+///
+/// A response too big for one frame goes out in chunks when the client
+/// negotiated tagged responses, see [`crate::wire::CHUNKED_RESPONSE`].
+/// Responses in a `codec` other than JSON go out as [`crate::wire::EncodedResponse`].
 async fn send_response(
     send_stream: &mut iroh::endpoint::SendStream,
-    response_json: &str,
+    response: &[u8],
+    tagged: bool,
+    codec: crate::codec::Codec,
     peer_key: &fastn_id52::PublicKey,
     protocol_json: &serde_json::Value,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Send JSON followed by newline (same format as original)
-    send_stream.write_all(response_json.as_bytes()).await?;
-    send_stream.write_all(b"\n").await?;
+    if codec != crate::codec::Codec::Json {
+        crate::wire::write_encoded_response(send_stream, response, codec).await?;
+    } else if tagged && response.len() > fastn_net::MAX_FRAME_LEN {
+        crate::wire::write_chunked_response(send_stream, response).await?;
+    } else {
+        // Send JSON followed by newline (same format as original)
+        send_stream.write_all(response).await?;
+        send_stream.write_all(b"\n").await?;
+    }
     
     tracing::trace!("Sent response for protocol {:?} to peer {}: {} bytes", 
-                   protocol_json, peer_key.id52(), response_json.len());
+                   protocol_json, peer_key.id52(), response.len());
     
     Ok(())
 }
@@ -536,3 +2879,613 @@ async fn send_response(
 pub fn listen(private_key: fastn_id52::SecretKey) -> ServerBuilder {
     ServerBuilder::new(private_key)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    enum TestProtocol {
+        Echo,
+        Chat,
+    }
+
+    #[derive(Debug, serde::Serialize, thiserror::Error)]
+    #[error("echo failed")]
+    struct EchoError;
+
+    async fn echo(input: String) -> Result<String, EchoError> {
+        Ok(input)
+    }
+
+    async fn echo_stream(_session: crate::server::Session<TestProtocol>, _data: String, _state: ()) -> Result<(), EchoError> {
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_handler_fails_listen() {
+        let builder = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests(TestProtocol::Echo, echo)
+            .handle_streams(TestProtocol::Echo, (), echo_stream);
+
+        let duplicate = RegistrationError::DuplicateHandler { protocol: serde_json::json!("Echo") };
+        assert_eq!(builder.registration_errors(), std::slice::from_ref(&duplicate));
+
+        let error = builder.await.unwrap_err();
+        assert_eq!(error.downcast_ref::<RegistrationError>(), Some(&duplicate));
+    }
+
+    #[test]
+    fn test_handler_deadline_is_the_earliest() {
+        let soon = tokio::time::Instant::now() + std::time::Duration::from_secs(1);
+        let hour = Some(std::time::Duration::from_secs(3600));
+        assert_eq!(handler_deadline(hour, Some(soon)), Some(soon));
+        assert!(handler_deadline(Some(std::time::Duration::ZERO), Some(soon)).unwrap() < soon);
+        assert_eq!(handler_deadline(None, Some(soon)), Some(soon));
+        assert_eq!(handler_deadline(None, None), None);
+    }
+
+    #[tokio::test]
+    async fn test_resumed_sessions_drop_unregistered_protocols() {
+        let server = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests(TestProtocol::Echo, echo)
+            .handle_streams(TestProtocol::Chat, (), echo_stream)
+            .start()
+            .unwrap();
+        let target = server.public_key();
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate());
+        crate::client::wait_until_reachable(&client, target).await;
+        let endpoint = client.endpoint().await.unwrap();
+        let hello = crate::handshake::HelloMetadata::default();
+        let connect = |protocols: Vec<serde_json::Value>, token: Option<String>| {
+            crate::coordination::connect_peer(&endpoint, &target, protocols, token, None, 1024, &hello)
+        };
+
+        let both = vec![serde_json::json!("Echo"), serde_json::json!("Chat")];
+        let (first, _) = connect(both.clone(), None).await.unwrap();
+        assert_eq!(first.accepted_protocols, both);
+
+        // The token restores both, but Chat is no longer served
+        server.unregister_protocol(TestProtocol::Chat).unwrap();
+        let (resumed, _) = connect(vec![serde_json::json!("Echo")], first.resumption_token.clone()).await.unwrap();
+        assert_eq!(resumed.accepted_protocols, vec![serde_json::json!("Echo")]);
+
+        // A token alone restores the session, but only once
+        assert!(connect(vec![], first.resumption_token).await.is_err());
+        let (restored, _) = connect(vec![], resumed.resumption_token).await.unwrap();
+        assert_eq!(restored.accepted_protocols, vec![serde_json::json!("Echo")]);
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_early_requests_are_answered_in_the_handshake() {
+        let server = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests(TestProtocol::Echo, echo)
+            .with_sync_stream_auth(|_, _, data| *data != serde_json::json!("secret"))
+            .start()
+            .unwrap();
+        let target = server.public_key();
+        let secret = fastn_id52::SecretKey::generate();
+        let client = crate::client::Client::new(secret.clone());
+        crate::client::wait_until_reachable(&client, target).await;
+        let endpoint = client.endpoint().await.unwrap();
+        let early = |data: serde_json::Value| {
+            let signature = crate::signing::sign_request(&secret, &target, &serde_json::json!("Echo"), &data);
+            crate::handshake::EarlyRequest {
+                protocol: serde_json::json!("Echo"),
+                data,
+                trace: None,
+                deadline_ms: None,
+                signature: Some(signature),
+            }
+        };
+        let hello = crate::handshake::HelloMetadata::default();
+        let connect = |request| {
+            crate::coordination::connect_peer(&endpoint, &target, vec![serde_json::json!("Echo")], None, Some(request), 1024, &hello)
+        };
+
+        // Signed, so the response comes back signed for this request
+        let request = early(serde_json::json!("hi"));
+        let signature = request.signature.clone();
+        let (peer, response) = connect(request).await.unwrap();
+        assert!(peer.tagged_responses);
+        let result: Result<String, String> =
+            crate::client::decode_response(&response.unwrap(), &target, true, signature.as_ref()).unwrap();
+        assert_eq!(result, Ok("hi".to_string()));
+
+        // Refused by stream auth like a request on its own stream
+        let error = connect(early(serde_json::json!("secret"))).await.unwrap_err();
+        assert!(matches!(error, crate::client::CallError::Denied { .. }), "{error}");
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_served_and_refused_requests_are_audited() {
+        use crate::server::audit::AuditOutcome;
+
+        let temp = tempfile::tempdir().unwrap();
+        let server = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests(TestProtocol::Echo, echo)
+            .with_sync_stream_auth(|_, _, data| *data != serde_json::json!("secret"))
+            .with_audit_log(temp.path(), "alice")
+            .start()
+            .unwrap();
+        let target = server.public_key();
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate());
+        crate::client::wait_until_reachable(&client, target).await;
+
+        // Sent early, then on a stream of its own, then in-process
+        let reply: Result<String, String> = client.call(target, TestProtocol::Echo, "hi").await.unwrap();
+        assert_eq!(reply, Ok("hi".to_string()));
+        let error = client.call::<_, _, String, String>(target, TestProtocol::Echo, "secret").await.unwrap_err();
+        assert!(matches!(error, crate::client::CallError::Denied { .. }), "{error}");
+        let local = crate::client::Client::new(fastn_id52::SecretKey::generate()).with_loopback();
+        let reply: Result<String, String> = local.call(target, TestProtocol::Echo, "hi").await.unwrap();
+        assert_eq!(reply, Ok("hi".to_string()));
+
+        let records = crate::server::audit::query(temp.path(), "alice", &Default::default()).await.unwrap();
+        let outcomes: Vec<_> = records.iter().map(|record| (record.protocol.as_str(), record.outcome.clone())).collect();
+        assert_eq!(outcomes, [("Echo", AuditOutcome::Ok), ("Echo", AuditOutcome::Denied), ("Echo", AuditOutcome::Ok)]);
+        assert_eq!(records[0].peer, client.public_key().id52());
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_answers_carry_the_protocol_max_age() {
+        let max_age = std::time::Duration::from_secs(60);
+        let server = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests(TestProtocol::Echo, echo)
+            .handle_requests(TestProtocol::Chat, echo)
+            .with_protocol_max_age(TestProtocol::Echo, max_age)
+            .start()
+            .unwrap();
+        let target = server.public_key();
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate());
+        crate::client::wait_until_reachable(&client, target).await;
+
+        // Sent early, then on a stream of its own
+        for client in [client.clone(), client.with_metadata("trace", "1")] {
+            let reply = client
+                .call_with_options::<_, _, String, String>(target, TestProtocol::Echo, "hi", Default::default())
+                .await
+                .unwrap();
+            assert_eq!((reply.result, reply.max_age), (Ok("hi".to_string()), Some(max_age)));
+            let reply = client
+                .call_with_options::<_, _, String, String>(target, TestProtocol::Chat, "hi", Default::default())
+                .await
+                .unwrap();
+            assert_eq!(reply.max_age, None);
+        }
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_early_requests_are_resent_to_servers_that_ignore_them() {
+        // A server from before early requests: it answers the hello and serves the call on a stream
+        let endpoint = fastn_net::get_endpoint(fastn_id52::SecretKey::generate()).await.unwrap();
+        let old_server = endpoint.clone();
+        let served = tokio::spawn(async move {
+            let conn = old_server.accept().await.unwrap().await.unwrap();
+            let handshake = fastn_net::Protocol::Generic(serde_json::json!(crate::handshake::HANDSHAKE_PROTOCOL));
+            let (_, mut send, mut recv) = fastn_net::accept_bi(&conn, &[handshake]).await.unwrap();
+            let hello: crate::handshake::ClientHello = recv.next_json().await.unwrap();
+            assert!(hello.early_request.is_some());
+            let mut server_hello = crate::handshake::ServerHello::success();
+            if let crate::handshake::ServerHello::Success { accepted_protocols, tagged_responses, .. } = &mut server_hello {
+                *accepted_protocols = hello.supported_protocols;
+                *tagged_responses = hello.tagged_responses;
+            }
+            send.write_all(format!("{}\n", serde_json::to_string(&server_hello).unwrap()).as_bytes()).await.unwrap();
+            send.finish().unwrap();
+
+            let (_, mut send, mut recv) = fastn_net::accept_any_bi(&conn).await.unwrap();
+            let wrapper: crate::wire::WrapperRequest = recv.next_json().await.unwrap();
+            let response = crate::wire::encode_response(Ok(wrapper.data), hello.tagged_responses).unwrap();
+            send.write_all(format!("{response}\n").as_bytes()).await.unwrap();
+            send.finish().unwrap();
+            conn
+        });
+
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate());
+        let address = iroh::Watcher::initialized(&mut endpoint.node_addr()).await;
+        client.endpoint().await.unwrap().add_node_addr(address).unwrap();
+        let target = fastn_id52::PublicKey::from_bytes(endpoint.node_id().as_bytes()).unwrap();
+        let result: Result<String, String> = client.call(target, TestProtocol::Echo, "hi").await.unwrap();
+        assert_eq!(result, Ok("hi".to_string()));
+        served.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream_bytes_sent_with_the_request_reach_the_handler() {
+        async fn sum(mut session: crate::server::Session<TestProtocol>, first: u64, _state: ()) -> Result<(), EchoError> {
+            let mut total = first;
+            while let Ok(n) = session.recv.next_json::<u64>().await {
+                total += n;
+            }
+            session.send.write_all(format!("{total}\n").as_bytes()).await.unwrap();
+            session.send.finish().unwrap();
+            Ok(())
+        }
+
+        let server = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_streams(TestProtocol::Chat, (), sum)
+            .start()
+            .unwrap();
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate());
+        crate::client::wait_until_reachable(&client, server.public_key()).await;
+        let mut session = client.connect(server.public_key(), TestProtocol::Chat, 1u64).await.unwrap();
+        // Written right behind the request, so they arrive in the same reads as its line
+        session.send.write_all(b"2\n3\n4\n").await.unwrap();
+        session.send.finish().unwrap();
+        assert_eq!(session.recv.next_string().await.unwrap(), "10");
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_handler_is_dropped_at_client_deadline() {
+        let past = Some(tokio::time::Instant::now());
+        let never = std::future::pending::<HandlerResult>();
+        assert!(until_client_gives_up(past, never).await.is_none());
+        assert_eq!(until_client_gives_up(None, async { 7 }).await, Some(7));
+    }
+
+    #[test]
+    fn test_oversized_responses_become_errors() {
+        let big = Ok(serde_json::json!("x".repeat(100)));
+        let json = crate::codec::Codec::Json;
+        let response = encode_for_peer(big.clone(), true, None, json, Some(50), None).unwrap();
+        let result: Result<String, String> = serde_json::from_slice::<crate::wire::ResponseEnvelope<_, _>>(&response).unwrap().into();
+        assert_eq!(result.unwrap_err(), "Response of 125 bytes exceeds the limit of 50 bytes");
+
+        let response = encode_for_peer(big.clone(), true, None, json, None, None).unwrap();
+        assert_eq!(response.len(), 125);
+
+        // Oversized bytes are counted, not kept
+        let mut buffer = LimitedBuffer::new(Some(4));
+        std::io::Write::write_all(&mut buffer, b"abc").unwrap();
+        std::io::Write::write_all(&mut buffer, b"def").unwrap();
+        assert_eq!((buffer.size, buffer.bytes.len()), (6, 0));
+
+        // The limit applies to the bytes that go out, in whatever codec
+        for codec in crate::codec::Codec::supported() {
+            let size = codec.encode(&serde_json::json!({"status": "ok", "data": "x".repeat(100)})).unwrap().len();
+            assert_eq!(encode_for_peer(big.clone(), true, None, codec, Some(size), None).unwrap().len(), size);
+            let refused = encode_for_peer(big.clone(), true, None, codec, Some(size - 1), None).unwrap();
+            let error = format!("Response of {size} bytes exceeds the limit of {} bytes", size - 1);
+            assert_eq!(codec.decode(&refused).unwrap(), serde_json::json!({"status": "err", "data": error}));
+        }
+    }
+
+    #[test]
+    fn test_try_handle_requests_rejects_duplicate() {
+        let builder = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .try_handle_requests(TestProtocol::Echo, echo)
+            .unwrap();
+
+        let error = builder.try_handle_requests(TestProtocol::Echo, echo).err().unwrap();
+        assert!(matches!(error, RegistrationError::DuplicateHandler { .. }));
+    }
+
+    #[test]
+    fn test_server_handle_registers_and_unregisters() {
+        let mut builder = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests(TestProtocol::Echo, echo);
+        let (handle, _server) = builder.server();
+        assert_eq!(handle.protocols(), vec![serde_json::json!("Echo")]);
+
+        let error = handle.register_protocol(TestProtocol::Echo, echo).unwrap_err();
+        assert!(matches!(error, RegistrationError::DuplicateHandler { .. }));
+
+        handle.register_stream_protocol(TestProtocol::Chat, (), echo_stream).unwrap();
+        assert!(handle.stream_handlers.contains(&serde_json::json!("Chat")));
+
+        handle.unregister_protocol(TestProtocol::Echo).unwrap();
+        assert_eq!(handle.protocols(), vec![serde_json::json!("Chat")]);
+        let error = handle.unregister_protocol(TestProtocol::Echo).unwrap_err();
+        assert!(matches!(error, RegistrationError::NotRegistered { .. }));
+
+        assert!(!handle.is_stopped());
+        handle.stop();
+        assert!(handle.is_stopped());
+    }
+
+    #[test]
+    fn test_modules_register_and_conflict() {
+        struct Echo;
+        impl ProtocolModule for Echo {
+            fn register(self, builder: ServerBuilder) -> ServerBuilder {
+                builder.handle_requests(TestProtocol::Echo, echo)
+            }
+        }
+
+        struct Chat;
+        impl ProtocolModule for Chat {
+            fn register(self, builder: ServerBuilder) -> ServerBuilder {
+                builder
+                    .handle_streams(TestProtocol::Chat, (), echo_stream)
+                    .handle_requests(TestProtocol::Echo, echo)
+            }
+            fn name(&self) -> &'static str {
+                "chat"
+            }
+        }
+
+        let builder = ServerBuilder::new(fastn_id52::SecretKey::generate()).module(Echo);
+        assert!(builder.registration_errors().is_empty());
+        assert!(builder.request_handlers.contains_key(&serde_json::json!("Echo")));
+
+        let error = builder.try_module(Chat).err().unwrap();
+        assert_eq!(
+            error,
+            RegistrationError::DuplicateModuleHandler {
+                protocol: serde_json::json!("Echo"),
+                first: std::any::type_name::<Echo>(),
+                second: "chat",
+            }
+        );
+
+        // Outside any module the builder itself is named
+        let builder = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .module(Echo)
+            .handle_requests(TestProtocol::Echo, echo);
+        assert!(matches!(
+            builder.registration_errors(),
+            [RegistrationError::DuplicateModuleHandler { second: "the builder", .. }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handle_requests_with_state() {
+        async fn count(input: String, seen: std::sync::Arc<std::sync::atomic::AtomicUsize>) -> Result<String, EchoError> {
+            let n = seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            Ok(format!("{input} #{n}"))
+        }
+
+        let seen = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut builder = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests_with_state(TestProtocol::Echo, seen.clone(), count);
+        let (handle, _server) = builder.server();
+
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        for expected in ["hi #1", "hi #2"] {
+            let request = crate::server::LayerRequest::new(
+                peer, serde_json::json!("Echo"), serde_json::Value::Null, Default::default(), None, false, None,
+            );
+            let context = crate::server::RequestContext::new(&request, None, tokio_util::sync::CancellationToken::new());
+            let reply = handle
+                .request_handlers
+                .with(&serde_json::json!("Echo"), |handler| handler("\"hi\"".to_string(), context))
+                .unwrap();
+            assert_eq!(reply.await, Ok(serde_json::json!(expected)));
+        }
+        assert_eq!(seen.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_batched_calls_stay_within_the_stream_limit() {
+        type Running = std::sync::Arc<(std::sync::atomic::AtomicUsize, std::sync::atomic::AtomicUsize)>;
+        async fn slow(input: String, running: Running) -> Result<String, EchoError> {
+            let now = running.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            running.1.fetch_max(now, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            running.0.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(input)
+        }
+
+        let running = Running::default();
+        let server = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests_with_state(TestProtocol::Echo, running.clone(), slow)
+            .with_max_concurrent_streams(2)
+            .start()
+            .unwrap();
+        let target = server.public_key();
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate());
+        crate::client::wait_until_reachable(&client, target).await;
+
+        let calls = (0..8).map(|n| (TestProtocol::Echo, n.to_string())).collect();
+        let results: Vec<Result<String, String>> = client.call_batch(target, calls).await.unwrap();
+        let expected: Vec<Result<String, String>> = (0..8).map(|n| Ok(n.to_string())).collect();
+        assert_eq!(results, expected);
+        assert!(running.1.load(std::sync::atomic::Ordering::SeqCst) <= 2);
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_loopback_calls_pass_stream_auth_and_limits() {
+        let server_key = fastn_id52::SecretKey::generate();
+        let mut builder = ServerBuilder::new(server_key.clone())
+            .handle_requests(TestProtocol::Echo, echo)
+            .with_sync_stream_auth(|_peer: &fastn_id52::PublicKey, _protocol: &serde_json::Value, data: &serde_json::Value| {
+                *data != serde_json::json!("secret")
+            });
+        let stream_auth = builder.stream_auth.take().map(std::sync::Arc::new);
+        let (handle, _server) = builder.server();
+        let target = server_key.public_key();
+        let registration = crate::server::loopback::register(target, LocalServer {
+            server_key: target,
+            request_handlers: handle.request_handlers.clone(),
+            connection_auth: None,
+            stream_auth,
+            capability_issuers: Default::default(),
+            reputation: handle.reputation.clone(),
+            request_timeouts: RequestTimeouts { default: None, deferred: handle.deferred_timeouts.clone() },
+            max_response_size: Some(64),
+            audit: None,
+            layers: crate::server::middleware::Layers::new(Vec::new()),
+            max_ages: MaxAges::default(),
+        });
+
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate()).with_loopback();
+        let reply = client
+            .call_with_options::<_, _, String, String>(target, TestProtocol::Echo, "hi", Default::default())
+            .await
+            .unwrap();
+        assert_eq!(reply.result, Ok("hi".to_string()));
+        assert_eq!(reply.path, crate::client::PathType::Local);
+
+        let denied = client.call::<_, _, String, String>(target, TestProtocol::Echo, "secret").await;
+        assert!(matches!(denied, Err(crate::client::CallError::Denied { .. })));
+        let unknown = client.call::<_, _, String, String>(target, TestProtocol::Chat, "hi").await;
+        assert!(matches!(unknown, Err(crate::client::CallError::ProtocolNotAccepted { .. })));
+        let too_large = client.call::<_, _, String, String>(target, TestProtocol::Echo, "x".repeat(100)).await.unwrap();
+        assert!(too_large.unwrap_err().contains("exceeds the limit of 64 bytes"));
+
+        drop(registration);
+        assert!(!crate::server::loopback::serves(&target));
+    }
+
+    #[tokio::test]
+    async fn test_loopback_calls_from_another_process_of_the_host() {
+        let server_key = fastn_id52::SecretKey::generate();
+        let mut builder = ServerBuilder::new(server_key.clone())
+            .handle_requests(TestProtocol::Echo, echo)
+            .with_sync_stream_auth(|_peer: &fastn_id52::PublicKey, _protocol: &serde_json::Value, data: &serde_json::Value| {
+                *data != serde_json::json!("secret")
+            });
+        let stream_auth = builder.stream_auth.take().map(std::sync::Arc::new);
+        let (handle, _server) = builder.server();
+        let target = server_key.public_key();
+        let _registration = crate::server::loopback::register(target, LocalServer {
+            server_key: target,
+            request_handlers: handle.request_handlers.clone(),
+            connection_auth: None,
+            stream_auth,
+            capability_issuers: Default::default(),
+            reputation: handle.reputation.clone(),
+            request_timeouts: RequestTimeouts { default: None, deferred: handle.deferred_timeouts.clone() },
+            max_response_size: None,
+            audit: None,
+            layers: crate::server::middleware::Layers::new(Vec::new()),
+            max_ages: std::sync::Arc::new([(serde_json::json!("Echo"), std::time::Duration::from_secs(60))].into()),
+        });
+        let temp = tempfile::tempdir().unwrap();
+        let socket = crate::server::loopback::serve_host(temp.path(), target).unwrap();
+
+        let from = fastn_id52::SecretKey::generate().public_key();
+        let hello = crate::handshake::HelloMetadata::default();
+        let call = |data: &str| crate::interceptor::OutgoingCall::new(
+            target, serde_json::json!("Echo"), serde_json::json!(data), Default::default(), false,
+        );
+        let reply = crate::server::loopback::call_host(temp.path(), from, &hello, &call("hi")).await;
+        assert_eq!(reply.unwrap().unwrap(), (Ok(serde_json::json!("hi")), Some(std::time::Duration::from_secs(60))));
+        let denied = crate::server::loopback::call_host(temp.path(), from, &hello, &call("secret")).await;
+        assert!(matches!(denied, Some(Err(crate::client::CallError::Denied { .. }))));
+
+        // Without a serving process the call takes the network
+        drop(socket);
+        assert!(!crate::server::loopback::host_serves(temp.path(), &target));
+        assert!(crate::server::loopback::call_host(temp.path(), from, &hello, &call("hi")).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_context_is_cancelled_at_the_deadline() {
+        let (cancelled_tx, cancelled) = tokio::sync::oneshot::channel();
+        let cancelled_tx = std::sync::Mutex::new(Some(cancelled_tx));
+        let server_key = fastn_id52::SecretKey::generate();
+        let mut builder = ServerBuilder::new(server_key.clone())
+            .handle_requests_with_context(TestProtocol::Echo, move |_input: String, context: crate::server::RequestContext| {
+                let cancelled_tx = cancelled_tx.lock().unwrap().take().unwrap();
+                // Work handed to another task only learns of the deadline through the context
+                tokio::spawn(async move {
+                    context.context().cancelled().await;
+                    let _ = cancelled_tx.send(());
+                });
+                std::future::pending::<Result<String, EchoError>>()
+            });
+        let (handle, _server) = builder.server();
+        let target = server_key.public_key();
+        let _registration = crate::server::loopback::register(target, LocalServer {
+            server_key: target,
+            request_handlers: handle.request_handlers.clone(),
+            connection_auth: None,
+            stream_auth: None,
+            capability_issuers: Default::default(),
+            reputation: handle.reputation.clone(),
+            request_timeouts: RequestTimeouts {
+                default: Some(std::time::Duration::from_millis(50)),
+                deferred: handle.deferred_timeouts.clone(),
+            },
+            max_response_size: None,
+            audit: None,
+            layers: crate::server::middleware::Layers::new(Vec::new()),
+            max_ages: MaxAges::default(),
+        });
+
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate()).with_loopback();
+        let reply = client.call::<_, _, String, String>(target, TestProtocol::Echo, "hi").await.unwrap();
+        assert!(reply.unwrap_err().contains("timed out"));
+        tokio::time::timeout(std::time::Duration::from_secs(5), cancelled).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_relayed_origin_refused_by_connection_auth() {
+        let origin = fastn_id52::SecretKey::generate().public_key();
+        let friend = fastn_id52::SecretKey::generate().public_key();
+        let relay = fastn_id52::SecretKey::generate().public_key();
+        let relay_id52 = relay.id52();
+        // Admits anyone with the relay's token, and friend
+        let auth = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .with_connection_auth(move |peer, hello| {
+                let relayed_by = hello.metadata.get(crate::handshake::RELAYED_BY).cloned();
+                let relay_id52 = relay_id52.clone();
+                async move {
+                    assert_eq!(relayed_by, Some(relay_id52));
+                    peer == friend || hello.auth_token.as_deref() == Some("relay-secret")
+                }
+            })
+            .connection_auth
+            .unwrap();
+        let reputation = crate::server::reputation::Reputation::new(
+            fastn_id52::SecretKey::generate().public_key(),
+            Some(crate::server::reputation::ReputationConfig { strike_threshold: 1, ..Default::default() }),
+        );
+        let hello = crate::handshake::ClientHello::new("relay", "0").with_auth("relay-secret".to_string());
+
+        let decision = admit_relayed_origin(Some(&auth), &reputation, origin, &relay, &hello).await;
+        assert!(matches!(decision, crate::server::AuthDecision::Deny { .. }));
+        // The refusal counts against the sender, so its next stream is throttled
+        assert!(reputation.banned_for(&origin).is_some());
+        assert!(admit_relayed_origin(None, &reputation, friend, &relay, &hello).await.is_allowed());
+        assert!(admit_relayed_origin(Some(&auth), &reputation, friend, &relay, &hello).await.is_allowed());
+    }
+
+    #[tokio::test]
+    async fn test_relayed_origin_refused_while_banned() {
+        let origin = fastn_id52::SecretKey::generate().public_key();
+        let reputation = crate::server::reputation::Reputation::new(
+            fastn_id52::SecretKey::generate().public_key(),
+            Some(crate::server::reputation::ReputationConfig { strike_threshold: 1, ..Default::default() }),
+        );
+        reputation.record(&origin, crate::server::reputation::Offense::AuthFailure);
+
+        let relay = fastn_id52::SecretKey::generate().public_key();
+        let hello = crate::handshake::ClientHello::new("relay", "0");
+        let decision = admit_relayed_origin(None, &reputation, origin, &relay, &hello).await;
+        assert!(matches!(decision, crate::server::AuthDecision::Throttle { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_requests_on_channel() {
+        let (requests, mut incoming) = tokio::sync::mpsc::channel(1);
+        let mut builder = ServerBuilder::new(fastn_id52::SecretKey::generate())
+            .handle_requests_on_channel(TestProtocol::Echo, requests);
+        let (handle, _server) = builder.server();
+
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let request = crate::server::LayerRequest::new(
+            peer, serde_json::json!("Echo"), serde_json::Value::Null, Default::default(), None, false, None,
+        );
+        let context = crate::server::RequestContext::new(&request, None, tokio_util::sync::CancellationToken::new());
+        let reply = handle
+            .request_handlers
+            .with(&serde_json::json!("Echo"), |handler| handler("\"hi\"".to_string(), context))
+            .unwrap();
+        let reply = tokio::spawn(reply);
+
+        let request = incoming.recv().await.unwrap();
+        assert_eq!(request.peer(), &peer);
+        let (input, response): (String, _) = request.get_input().await.unwrap();
+        response.send(Ok::<_, String>(format!("{input}!"))).await.unwrap();
+        assert_eq!(reply.await.unwrap(), Ok(serde_json::json!("hi!")));
+    }
+}
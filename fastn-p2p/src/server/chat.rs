@@ -0,0 +1,505 @@
+//! Built-in `chat.fastn.com` protocol
+//!
+//! Messages are stored in a sqlite database under the binding's protocol_dir,
+//! so history survives restarts. Commands:
+//!
+//! - `send` (request): store a message and deliver it to live subscribers
+//! - `history` (request): page through stored messages, newest first
+//! - `subscribe` (stream): receive every new message in a room as a JSON line
+//!
+//! The binding config controls access and message size:
+//!
+//! ```json
+//! { "max_message_bytes": 16384, "allowed_peers": ["<peer id52>", "..."] }
+//! ```
+//!
+//! An empty `allowed_peers` list rejects everyone. Stored messages count against
+//! the identity's storage quota, see [`crate::server::quota`].
+//!
+//! # Example
+//! ```rust,no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! fastn_p2p::serve_all()
+//!     .protocol(fastn_p2p::server::chat::CHAT_PROTOCOL, fastn_p2p::server::chat::register)
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+/// Protocol name for the chat protocol
+pub const CHAT_PROTOCOL: &str = "chat.fastn.com";
+
+/// Database file created inside the protocol_dir
+pub const CHAT_DB_FILE: &str = "chat.sqlite3";
+
+/// Room used when a request does not name one
+pub const DEFAULT_ROOM: &str = "general";
+
+/// Upper bound on messages returned by one `history` call
+pub const MAX_HISTORY_PAGE: u32 = 500;
+
+/// Chat connections idle between messages, so they stay open for longer
+pub const CHAT_MAX_IDLE: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+
+/// Size cap on message text used when the binding config does not set `max_message_bytes`
+pub const DEFAULT_MAX_MESSAGE_BYTES: u64 = 16 * 1024;
+
+/// Messages buffered per room for slow subscribers before they start lagging
+const LIVE_BUFFER: usize = 256;
+
+/// Live subscribers per (protocol_dir, room)
+type Rooms = std::collections::HashMap<(PathBuf, String), tokio::sync::broadcast::Sender<ChatMessage>>;
+
+static CHAT_ROOMS: std::sync::LazyLock<std::sync::Mutex<Rooms>> =
+    std::sync::LazyLock::new(|| std::sync::Mutex::new(std::collections::HashMap::new()));
+
+/// Chat binding configuration
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChatConfig {
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: u64,
+    /// ID52s of peers allowed to use any command
+    #[serde(default)]
+    pub allowed_peers: Vec<String>,
+}
+
+fn default_max_message_bytes() -> u64 {
+    DEFAULT_MAX_MESSAGE_BYTES
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            allowed_peers: Vec::new(),
+        }
+    }
+}
+
+impl ChatConfig {
+    /// Load the binding config from `protocol_dir`, using defaults if it is missing
+    pub async fn load(protocol_dir: &Path) -> Result<Self, ChatError> {
+        match tokio::fs::read_to_string(protocol_dir.join(crate::server::subprocess::CONFIG_FILE)).await {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|source| ChatError::Config { source }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(ChatError::Io { source }),
+        }
+    }
+
+    pub fn allows(&self, peer: &fastn_id52::PublicKey) -> bool {
+        let id52 = peer.id52();
+        self.allowed_peers.iter().any(|allowed| allowed == &id52)
+    }
+
+    /// Load the binding config, refusing peers not in `allowed_peers`
+    async fn load_for(protocol_dir: &Path, peer: &fastn_id52::PublicKey) -> Result<Self, ChatError> {
+        let config = Self::load(protocol_dir).await?;
+        if !config.allows(peer) {
+            return Err(ChatError::NotAllowed { peer: peer.id52() });
+        }
+        Ok(config)
+    }
+}
+
+/// A stored chat message
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub room: String,
+    /// ID52 of the peer that sent the message
+    pub author: String,
+    pub text: String,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Input for the `send` command
+///
+/// The author is the sending peer, not something the request can claim.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SendMessageRequest {
+    #[serde(default = "default_room")]
+    pub room: String,
+    pub text: String,
+}
+
+/// Input for the `history` command
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct HistoryRequest {
+    #[serde(default = "default_room")]
+    pub room: String,
+    /// Only messages with an id lower than this (for paging backwards)
+    #[serde(default)]
+    pub before_id: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+/// Output of the `history` command, newest message first
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct HistoryResponse {
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Initial data for the `subscribe` command
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SubscribeRequest {
+    #[serde(default = "default_room")]
+    pub room: String,
+}
+
+fn default_room() -> String {
+    DEFAULT_ROOM.to_string()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChatError {
+    #[error("Invalid chat request: {source}")]
+    InvalidRequest { source: serde_json::Error },
+
+    #[error("Peer {peer} is not allowed to chat here")]
+    NotAllowed { peer: String },
+
+    #[error("Message text cannot be empty")]
+    EmptyMessage,
+
+    #[error("Message of {size} bytes exceeds the {max} byte limit")]
+    TooLarge { size: u64, max: u64 },
+
+    #[error("Invalid chat config: {source}")]
+    Config { source: serde_json::Error },
+
+    #[error("Chat config error: {source}")]
+    Io { source: std::io::Error },
+
+    #[error("Chat storage error: {source}")]
+    Storage { source: rusqlite::Error },
+
+    #[error("Chat storage task failed: {source}")]
+    StorageTask { source: tokio::task::JoinError },
+
+    #[error("Failed to store message: {source}")]
+    Quota {
+        #[source]
+        source: crate::server::quota::StorageError,
+    },
+}
+
+/// Register all chat commands on a serve_all protocol builder
+pub fn register(
+    protocol: crate::server::serve_all::ProtocolBuilder,
+) -> crate::server::serve_all::ProtocolBuilder {
+    protocol
+        .handle_requests("send", send_handler)
+        .handle_requests("history", history_handler)
+        .handle_streams("subscribe", subscribe_handler)
+        .with_idle_policy(fastn_net::IdlePolicy::default().with_max_idle(CHAT_MAX_IDLE))
+}
+
+/// `send` command: store the message as `peer`'s and publish it to live subscribers
+pub fn send_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let config = ChatConfig::load_for(&protocol_dir, &peer).await?;
+        let request: SendMessageRequest = serde_json::from_value(request)
+            .map_err(|source| ChatError::InvalidRequest { source })?;
+        if request.text.trim().is_empty() {
+            return Err(ChatError::EmptyMessage.into());
+        }
+        let size = request.text.len() as u64;
+        if size > config.max_message_bytes {
+            return Err(ChatError::TooLarge { size, max: config.max_message_bytes }.into());
+        }
+        let author = peer.id52();
+
+        let message = store_message(&protocol_dir, author, request).await?;
+        publish(&protocol_dir, &message);
+
+        Ok(serde_json::to_value(&message)?)
+    })
+}
+
+/// `history` command: return stored messages, newest first
+pub fn history_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        ChatConfig::load_for(&protocol_dir, &peer).await?;
+        let request: HistoryRequest = serde_json::from_value(request)
+            .map_err(|source| ChatError::InvalidRequest { source })?;
+        let messages = load_history(&protocol_dir, request).await?;
+        Ok(serde_json::to_value(HistoryResponse { messages })?)
+    })
+}
+
+/// `subscribe` command: stream new messages in a room, one JSON line each
+pub fn subscribe_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    initial_data: serde_json::Value,
+    mut session: crate::server::Session<String>,
+) -> super::serve_all::Reply<()> {
+    let protocol_dir = protocol_dir.to_path_buf();
+
+    Box::pin(async move {
+        ChatConfig::load_for(&protocol_dir, &session.peer).await?;
+        let request: SubscribeRequest = serde_json::from_value(initial_data)
+            .map_err(|source| ChatError::InvalidRequest { source })?;
+        let mut live = subscribe(&protocol_dir, &request.room);
+
+        tracing::debug!("{} subscribed to chat room {}", session.peer.id52(), request.room);
+
+        loop {
+            let message = match live.recv().await {
+                Ok(message) => message,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Chat subscriber {} lagged, skipped {} messages", session.peer.id52(), skipped);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+
+            let mut line = serde_json::to_string(&message)?;
+            line.push('\n');
+            if session.send.write_all(line.as_bytes()).await.is_err() {
+                // Subscriber went away
+                break;
+            }
+        }
+
+        tracing::debug!("{} unsubscribed from chat room {}", session.peer.id52(), request.room);
+        Ok(())
+    })
+}
+
+fn publish(protocol_dir: &Path, message: &ChatMessage) {
+    let rooms = CHAT_ROOMS.lock().expect("Failed to acquire lock on CHAT_ROOMS");
+    if let Some(sender) = rooms.get(&(protocol_dir.to_path_buf(), message.room.clone())) {
+        // No receivers is fine - nobody is listening right now
+        let _ = sender.send(message.clone());
+    }
+}
+
+fn subscribe(protocol_dir: &Path, room: &str) -> tokio::sync::broadcast::Receiver<ChatMessage> {
+    let mut rooms = CHAT_ROOMS.lock().expect("Failed to acquire lock on CHAT_ROOMS");
+
+    // Drop rooms whose subscribers have all left
+    rooms.retain(|_, sender| sender.receiver_count() > 0);
+
+    rooms
+        .entry((protocol_dir.to_path_buf(), room.to_string()))
+        .or_insert_with(|| tokio::sync::broadcast::channel(LIVE_BUFFER).0)
+        .subscribe()
+}
+
+fn open_db(protocol_dir: &Path) -> Result<rusqlite::Connection, rusqlite::Error> {
+    let conn = rusqlite::Connection::open(protocol_dir.join(CHAT_DB_FILE))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            room TEXT NOT NULL,
+            author TEXT NOT NULL,
+            text TEXT NOT NULL,
+            sent_at TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS messages_room_id ON messages (room, id);",
+    )?;
+    Ok(conn)
+}
+
+async fn store_message(
+    protocol_dir: &Path,
+    author: String,
+    request: SendMessageRequest,
+) -> Result<ChatMessage, ChatError> {
+    let storage = crate::server::quota::Storage::open(protocol_dir).map_err(|source| ChatError::Quota { source })?;
+    let size = (request.room.len() + author.len() + request.text.len()) as u64;
+    let protocol_dir = protocol_dir.to_path_buf();
+
+    let insert = move || {
+        let conn = open_db(&protocol_dir)?;
+        let sent_at = chrono::Utc::now();
+        conn.execute(
+            "INSERT INTO messages (room, author, text, sent_at) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![request.room, author, request.text, sent_at.to_rfc3339()],
+        )?;
+
+        Ok(ChatMessage {
+            id: conn.last_insert_rowid(),
+            room: request.room,
+            author,
+            text: request.text,
+            sent_at,
+        })
+    };
+
+    // Only spawned once the quota check passes
+    storage
+        .charge(size, async move { tokio::task::spawn_blocking(insert).await })
+        .await
+        .map_err(|source| ChatError::Quota { source })?
+        .map_err(|source| ChatError::StorageTask { source })?
+        .map_err(|source| ChatError::Storage { source })
+}
+
+async fn load_history(
+    protocol_dir: &Path,
+    request: HistoryRequest,
+) -> Result<Vec<ChatMessage>, ChatError> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let limit = request.limit.unwrap_or(50).min(MAX_HISTORY_PAGE);
+
+    tokio::task::spawn_blocking(move || {
+        let conn = open_db(&protocol_dir)?;
+        let mut statement = conn.prepare(
+            "SELECT id, room, author, text, sent_at FROM messages
+             WHERE room = ?1 AND id < ?2
+             ORDER BY id DESC LIMIT ?3",
+        )?;
+
+        let rows = statement.query_map(
+            rusqlite::params![request.room, request.before_id.unwrap_or(i64::MAX), limit],
+            |row| {
+                let sent_at: String = row.get(4)?;
+                Ok(ChatMessage {
+                    id: row.get(0)?,
+                    room: row.get(1)?,
+                    author: row.get(2)?,
+                    text: row.get(3)?,
+                    sent_at: chrono::DateTime::parse_from_rfc3339(&sent_at)
+                        .map(|t| t.with_timezone(&chrono::Utc))
+                        .unwrap_or_default(),
+                })
+            },
+        )?;
+
+        rows.collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|source| ChatError::StorageTask { source })?
+    .map_err(|source| ChatError::Storage { source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chat binding of a fresh identity, open to `peer`
+    async fn binding(temp: &tempfile::TempDir, peer: &fastn_id52::PublicKey, max_message_bytes: u64) -> PathBuf {
+        let protocol_dir = temp.path().join("protocols").join(CHAT_PROTOCOL).join("default");
+        tokio::fs::create_dir_all(&protocol_dir).await.unwrap();
+        let config = ChatConfig { max_message_bytes, allowed_peers: vec![peer.id52()] };
+        tokio::fs::write(
+            protocol_dir.join(crate::server::subprocess::CONFIG_FILE),
+            serde_json::to_string(&config).unwrap(),
+        )
+        .await
+        .unwrap();
+        protocol_dir
+    }
+
+    #[tokio::test]
+    async fn test_send_history_and_live_delivery() {
+        let temp = tempfile::tempdir().unwrap();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let protocol_dir = binding(&temp, &peer, 8).await;
+        let mut live = subscribe(&protocol_dir, DEFAULT_ROOM);
+
+        for text in ["one", "two", "three"] {
+            let request = serde_json::json!({"text": text});
+            send_handler("alice", "default", CHAT_PROTOCOL, "send", &protocol_dir, &peer, request)
+                .await
+                .unwrap();
+        }
+        let first = live.recv().await.unwrap();
+        assert_eq!((first.text.as_str(), first.author), ("one", peer.id52()));
+
+        // Claiming another author changes nothing
+        let spoofed = serde_json::json!({"author": "bob", "text": "four"});
+        let sent = send_handler("alice", "default", CHAT_PROTOCOL, "send", &protocol_dir, &peer, spoofed)
+            .await
+            .unwrap();
+        assert_eq!(sent["author"], peer.id52());
+
+        for text in ["  ", "way too long"] {
+            let request = serde_json::json!({"text": text});
+            assert!(
+                send_handler("alice", "default", CHAT_PROTOCOL, "send", &protocol_dir, &peer, request)
+                    .await
+                    .is_err()
+            );
+        }
+        let stranger = fastn_id52::SecretKey::generate().public_key();
+        for handler in [send_handler, history_handler] {
+            let refused = handler("alice", "default", CHAT_PROTOCOL, "send", &protocol_dir, &stranger, serde_json::json!({"text": "hi"}))
+                .await
+                .unwrap_err();
+            assert!(matches!(refused.downcast_ref::<ChatError>(), Some(ChatError::NotAllowed { .. })));
+        }
+
+        let page = serde_json::json!({"limit": 2});
+        let response =
+            history_handler("alice", "default", CHAT_PROTOCOL, "history", &protocol_dir, &peer, page)
+                .await
+                .unwrap();
+        let history: HistoryResponse = serde_json::from_value(response).unwrap();
+        let texts: Vec<_> = history.messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["four", "three"]);
+
+        let older = HistoryRequest {
+            room: DEFAULT_ROOM.to_string(),
+            before_id: Some(history.messages[1].id),
+            limit: None,
+        };
+        let rest = load_history(&protocol_dir, older).await.unwrap();
+        let texts: Vec<_> = rest.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["two", "one"]);
+    }
+
+    #[tokio::test]
+    async fn test_messages_count_against_the_quota() {
+        let temp = tempfile::tempdir().unwrap();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let protocol_dir = binding(&temp, &peer, DEFAULT_MAX_MESSAGE_BYTES).await;
+        let send = |text: &str| {
+            let request = serde_json::json!({ "text": text });
+            send_handler("alice", "default", CHAT_PROTOCOL, "send", &protocol_dir, &peer, request)
+        };
+        send("before any limit").await.unwrap();
+
+        let used = crate::server::quota::disk_usage(&temp.path().join("protocols")).await.unwrap();
+        let quota = crate::server::quota::QuotaConfig { max_bytes: Some(used + 100), ..Default::default() };
+        quota.save(temp.path()).await.unwrap();
+
+        let error = send(&"x".repeat(200)).await.unwrap_err();
+        assert!(crate::server::quota::QuotaExceeded::find(error.as_ref()).is_some(), "{error}");
+        let history = load_history(&protocol_dir, HistoryRequest { room: DEFAULT_ROOM.to_string(), before_id: None, limit: None })
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+    }
+}
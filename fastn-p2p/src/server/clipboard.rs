@@ -0,0 +1,234 @@
+//! Built-in `clipboard.fastn.com` protocol
+//!
+//! Lets authorized peers push clipboard contents (text or any binary MIME type)
+//! to an identity. The latest received clip is kept in the binding's
+//! protocol_dir, where `fastn-p2p clip recv` picks it up.
+//!
+//! The binding config (written by `fastn-p2p add-protocol`) controls access:
+//!
+//! ```json
+//! { "max_bytes": 1048576, "allowed_peers": ["<peer id52>", "..."] }
+//! ```
+//!
+//! An empty `allowed_peers` list rejects everyone. Stored clips count against
+//! the identity's storage quota, see [`crate::server::quota`].
+
+use std::path::Path;
+
+/// Protocol name for the clipboard protocol
+pub const CLIPBOARD_PROTOCOL: &str = "clipboard.fastn.com";
+
+/// Command a clip is pushed with
+pub const PUSH_COMMAND: &str = "push";
+
+/// File holding the latest received clip inside the protocol_dir
+pub const RECEIVED_CLIP_FILE: &str = "clipboard.latest.json";
+
+/// Size cap used when the binding config does not set `max_bytes`
+pub const DEFAULT_MAX_CLIP_BYTES: u64 = 1024 * 1024;
+
+/// Clipboard binding configuration
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ClipboardConfig {
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// ID52s of peers allowed to push clips
+    #[serde(default)]
+    pub allowed_peers: Vec<String>,
+}
+
+fn default_max_bytes() -> u64 {
+    DEFAULT_MAX_CLIP_BYTES
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_CLIP_BYTES,
+            allowed_peers: Vec::new(),
+        }
+    }
+}
+
+impl ClipboardConfig {
+    /// Load the binding config from `protocol_dir`, using defaults if it is missing
+    pub async fn load(protocol_dir: &Path) -> Result<Self, ClipboardError> {
+        match tokio::fs::read_to_string(protocol_dir.join(crate::server::subprocess::CONFIG_FILE)).await {
+            Ok(contents) => {
+                serde_json::from_str(&contents).map_err(|source| ClipboardError::Config { source })
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(ClipboardError::Io { source }),
+        }
+    }
+
+    pub fn allows(&self, peer: &fastn_id52::PublicKey) -> bool {
+        let id52 = peer.id52();
+        self.allowed_peers.iter().any(|allowed| allowed == &id52)
+    }
+}
+
+/// Clipboard contents on the wire
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Clip {
+    /// MIME type, e.g. `text/plain;charset=utf-8` or `image/png`
+    pub mime_type: String,
+    /// Base64-encoded contents
+    pub data: String,
+}
+
+impl Clip {
+    pub fn from_bytes(mime_type: impl Into<String>, bytes: &[u8]) -> Self {
+        Self {
+            mime_type: mime_type.into(),
+            data: base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes),
+        }
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, ClipboardError> {
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &self.data)
+            .map_err(|source| ClipboardError::InvalidData { source })
+    }
+}
+
+/// A clip received from a peer, as stored in the protocol_dir
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ReceivedClip {
+    pub from: String,
+    pub received_at: chrono::DateTime<chrono::Utc>,
+    pub clip: Clip,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClipboardError {
+    #[error("Invalid clipboard request: {source}")]
+    InvalidRequest { source: serde_json::Error },
+
+    #[error("Peer {peer} is not allowed to send clipboard contents")]
+    NotAllowed { peer: String },
+
+    #[error("Clip of {size} bytes exceeds the {max} byte limit")]
+    TooLarge { size: u64, max: u64 },
+
+    #[error("Clip data is not valid base64: {source}")]
+    InvalidData { source: base64::DecodeError },
+
+    #[error("Invalid clipboard config: {source}")]
+    Config { source: serde_json::Error },
+
+    #[error("Clipboard storage error: {source}")]
+    Io { source: std::io::Error },
+
+    #[error("Failed to store clip: {source}")]
+    Storage {
+        #[source]
+        source: crate::server::quota::StorageError,
+    },
+}
+
+/// Register the clipboard commands on a serve_all protocol builder
+pub fn register(
+    protocol: crate::server::serve_all::ProtocolBuilder,
+) -> crate::server::serve_all::ProtocolBuilder {
+    protocol.handle_requests(PUSH_COMMAND, push_handler)
+}
+
+/// `push` command: accept a clip from an allowed peer
+pub fn push_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let config = ClipboardConfig::load(&protocol_dir).await?;
+        if !config.allows(&peer) {
+            return Err(ClipboardError::NotAllowed { peer: peer.id52() }.into());
+        }
+
+        let clip: Clip = serde_json::from_value(request)
+            .map_err(|source| ClipboardError::InvalidRequest { source })?;
+        let size = clip.bytes()?.len() as u64;
+        if size > config.max_bytes {
+            return Err(ClipboardError::TooLarge { size, max: config.max_bytes }.into());
+        }
+
+        let received = ReceivedClip {
+            from: peer.id52(),
+            received_at: chrono::Utc::now(),
+            clip,
+        };
+        store(&protocol_dir, &received).await?;
+
+        tracing::info!(target: crate::console::TARGET, "📋 Received {} byte clip ({}) from {}", size, received.clip.mime_type, received.from);
+        Ok(serde_json::json!({ "bytes": size }))
+    })
+}
+
+/// The latest clip received in `protocol_dir`, if any
+pub async fn latest(protocol_dir: &Path) -> Result<Option<ReceivedClip>, ClipboardError> {
+    match tokio::fs::read_to_string(protocol_dir.join(RECEIVED_CLIP_FILE)).await {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|source| ClipboardError::InvalidRequest { source }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(ClipboardError::Io { source }),
+    }
+}
+
+async fn store(protocol_dir: &Path, received: &ReceivedClip) -> Result<(), ClipboardError> {
+    let json = serde_json::to_vec(received).map_err(|source| ClipboardError::InvalidRequest { source })?;
+
+    // Replaced atomically, so `clip recv` never sees a half-written file
+    crate::server::quota::Storage::open(protocol_dir)
+        .map_err(|source| ClipboardError::Storage { source })?
+        .write(RECEIVED_CLIP_FILE, &json)
+        .await
+        .map_err(|source| ClipboardError::Storage { source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_push_allowlist_and_size_cap() {
+        let temp = tempfile::tempdir().unwrap();
+        let identity_dir = temp.path().to_path_buf();
+        let protocol_dir = identity_dir.join("protocols").join(CLIPBOARD_PROTOCOL).join("default");
+        tokio::fs::create_dir_all(&protocol_dir).await.unwrap();
+
+        let allowed = fastn_id52::SecretKey::generate().public_key();
+        let stranger = fastn_id52::SecretKey::generate().public_key();
+        let config = ClipboardConfig {
+            max_bytes: 8,
+            allowed_peers: vec![allowed.id52()],
+        };
+        tokio::fs::write(
+            protocol_dir.join(crate::server::subprocess::CONFIG_FILE),
+            serde_json::to_string(&config).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let push = |peer, bytes: &[u8]| {
+            let clip = serde_json::to_value(Clip::from_bytes("application/octet-stream", bytes)).unwrap();
+            push_handler("alice", "default", CLIPBOARD_PROTOCOL, "push", &protocol_dir, &peer, clip)
+        };
+
+        assert!(push(stranger, b"hi").await.is_err());
+        assert!(push(allowed, b"way too large").await.is_err());
+        assert_eq!(latest(&protocol_dir).await.unwrap(), None);
+
+        push(allowed, &[0, 159, 146, 150]).await.unwrap();
+        let received = latest(&protocol_dir).await.unwrap().unwrap();
+        assert_eq!(received.from, allowed.id52());
+        assert_eq!(received.clip.bytes().unwrap(), vec![0, 159, 146, 150]);
+    }
+}
@@ -0,0 +1,247 @@
+//! Concurrency budgets: one protocol's heavy commands can't starve the others
+//!
+//! Every serve_all protocol binding shares the tokio runtime with the rest, so
+//! a burst of slow commands (transcoding, say) could take all of it. A
+//! protocol registered with [`crate::server::serve_all::ProtocolBuilder::with_concurrency_limit`]
+//! runs at most `max_concurrent` request commands per binding at once; up to
+//! `max_queued` more wait for a slot, and requests past that fail right away
+//! with [`ProtocolBusy`]:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::server::concurrency::ConcurrencyLimit;
+//! # use fastn_p2p::echo_request_handler as transcode;
+//! fastn_p2p::serve_all()
+//!     .protocol("video.fastn.com", |p| p
+//!         .handle_requests("transcode", transcode)
+//!         .with_concurrency_limit(ConcurrencyLimit::new(2).with_max_queued(8))
+//!     )
+//! # ;
+//! ```
+//!
+//! `ProtocolBusy` goes to peers as [`ProtocolBusy::to_value`] so clients can
+//! back off. Running and waiting requests per binding are in [`snapshot`] and
+//! in the Prometheus output of [`crate::metrics::prometheus`].
+
+/// How many requests of one binding run and wait at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConcurrencyLimit {
+    pub max_concurrent: usize,
+    pub max_queued: usize,
+}
+
+impl ConcurrencyLimit {
+    /// Run up to `max_concurrent` at once, with as many again waiting
+    pub fn new(max_concurrent: usize) -> Self {
+        Self { max_concurrent, max_queued: max_concurrent }
+    }
+
+    pub fn with_max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued;
+        self
+    }
+}
+
+/// A binding had no free slot and its queue was full
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+#[error("{protocol} {bind_alias} is busy: {running} requests running and {queued} waiting, try again later")]
+pub struct ProtocolBusy {
+    pub protocol: String,
+    pub bind_alias: String,
+    pub running: usize,
+    pub queued: usize,
+}
+
+impl ProtocolBusy {
+    /// Error value sent to peers: `{"busy": {...}}`
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::json!({ "busy": self })
+    }
+
+    /// Recognize an error value made by [`Self::to_value`]
+    pub fn from_value(value: &serde_json::Value) -> Option<Self> {
+        serde_json::from_value(value.get("busy")?.clone()).ok()
+    }
+}
+
+/// Load of one binding's budget
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BudgetStats {
+    pub identity: String,
+    pub protocol: String,
+    pub bind_alias: String,
+    pub max_concurrent: usize,
+    pub running: usize,
+    pub queued: usize,
+    /// Requests refused with [`ProtocolBusy`] so far
+    pub rejected: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct BudgetKey {
+    identity: String,
+    protocol: String,
+    bind_alias: String,
+}
+
+/// Slots and queue of one binding
+pub(crate) struct Budget {
+    key: BudgetKey,
+    limit: ConcurrencyLimit,
+    slots: std::sync::Arc<tokio::sync::Semaphore>,
+    queued: std::sync::atomic::AtomicUsize,
+    rejected: std::sync::atomic::AtomicU64,
+}
+
+static BUDGETS: std::sync::LazyLock<std::sync::Mutex<std::collections::BTreeMap<BudgetKey, std::sync::Arc<Budget>>>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// The budget of a binding, made with `limit` on first use
+pub(crate) fn budget(identity: &str, protocol: &str, bind_alias: &str, limit: ConcurrencyLimit) -> std::sync::Arc<Budget> {
+    let key = BudgetKey {
+        identity: identity.to_string(),
+        protocol: protocol.to_string(),
+        bind_alias: bind_alias.to_string(),
+    };
+    BUDGETS
+        .lock()
+        .expect("Failed to acquire lock on concurrency budgets")
+        .entry(key.clone())
+        .or_insert_with(|| std::sync::Arc::new(Budget::new(key, limit)))
+        .clone()
+}
+
+impl Budget {
+    fn new(key: BudgetKey, limit: ConcurrencyLimit) -> Self {
+        Self {
+            key,
+            limit,
+            slots: std::sync::Arc::new(tokio::sync::Semaphore::new(limit.max_concurrent)),
+            queued: Default::default(),
+            rejected: Default::default(),
+        }
+    }
+
+    /// A slot to run one request in, waiting for it if the queue has room
+    pub(crate) async fn acquire(&self) -> Result<tokio::sync::OwnedSemaphorePermit, ProtocolBusy> {
+        use std::sync::atomic::Ordering;
+
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        let reserved = self.queued.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+            (queued < self.limit.max_queued).then_some(queued + 1)
+        });
+        if reserved.is_err() {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(ProtocolBusy {
+                protocol: self.key.protocol.clone(),
+                bind_alias: self.key.bind_alias.clone(),
+                running: self.running(),
+                queued: self.queued.load(Ordering::SeqCst),
+            });
+        }
+
+        // Leaves the queue also when the caller stops waiting
+        let _queued = QueuedGuard(&self.queued);
+        Ok(self.slots.clone().acquire_owned().await.expect("budget semaphores are never closed"))
+    }
+
+    fn running(&self) -> usize {
+        self.limit.max_concurrent.saturating_sub(self.slots.available_permits())
+    }
+
+    fn stats(&self) -> BudgetStats {
+        use std::sync::atomic::Ordering;
+
+        BudgetStats {
+            identity: self.key.identity.clone(),
+            protocol: self.key.protocol.clone(),
+            bind_alias: self.key.bind_alias.clone(),
+            max_concurrent: self.limit.max_concurrent,
+            running: self.running(),
+            queued: self.queued.load(Ordering::SeqCst),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct QueuedGuard<'a>(&'a std::sync::atomic::AtomicUsize);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Every budget used so far, ordered by identity, protocol and binding
+pub fn snapshot() -> Vec<BudgetStats> {
+    let budgets = BUDGETS.lock().expect("Failed to acquire lock on concurrency budgets");
+    budgets.values().map(|budget| budget.stats()).collect()
+}
+
+/// Running, waiting and refused requests of every budget as Prometheus gauges and counters
+pub(crate) fn prometheus(out: &mut String) {
+    use std::fmt::Write as _;
+
+    let budgets = snapshot();
+    if budgets.is_empty() {
+        return;
+    }
+    let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    let labels = |stats: &BudgetStats| {
+        format!(
+            "identity=\"{}\",protocol=\"{}\",bind_alias=\"{}\"",
+            escape(&stats.identity),
+            escape(&stats.protocol),
+            escape(&stats.bind_alias)
+        )
+    };
+    type Metric = (&'static str, &'static str, &'static str, fn(&BudgetStats) -> u64);
+    let metrics: [Metric; 3] = [
+        ("fastn_p2p_binding_running", "gauge", "Requests of a serve_all binding running now", |s| s.running as u64),
+        ("fastn_p2p_binding_queued", "gauge", "Requests of a serve_all binding waiting for a slot", |s| s.queued as u64),
+        ("fastn_p2p_binding_busy_total", "counter", "Requests refused because the binding was busy", |s| s.rejected),
+    ];
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for stats in &budgets {
+            let _ = writeln!(out, "{name}{{{}}} {}", labels(stats), value(stats));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_full_budget_refuses_with_busy() {
+        let identity = format!("concurrency-test-{}", rand::random::<u64>());
+        let budget = budget(&identity, "video.fastn.com", "default", ConcurrencyLimit::new(1).with_max_queued(1));
+
+        let running = budget.acquire().await.unwrap();
+        let waiting = {
+            let budget = budget.clone();
+            tokio::spawn(async move { budget.acquire().await.map(drop) })
+        };
+        while budget.stats().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let busy = budget.acquire().await.unwrap_err();
+        assert_eq!((busy.running, busy.queued), (1, 1));
+        assert_eq!(ProtocolBusy::from_value(&busy.to_value()), Some(busy));
+
+        drop(running);
+        waiting.await.unwrap().unwrap();
+        let stats = budget.stats();
+        assert_eq!((stats.running, stats.queued, stats.rejected), (0, 0, 1));
+
+        let mut out = String::new();
+        prometheus(&mut out);
+        assert!(out.contains(&format!(
+            "fastn_p2p_binding_busy_total{{identity=\"{identity}\",protocol=\"video.fastn.com\",bind_alias=\"default\"}} 1\n"
+        )));
+    }
+}
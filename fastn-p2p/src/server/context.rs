@@ -0,0 +1,195 @@
+//! Per-request context for request handlers
+//!
+//! Handlers registered with
+//! [`crate::server::ServerBuilder::handle_requests_with_context`] get a
+//! [`RequestContext`] next to their input. It says who is asking and carries
+//! the request's deadline, metadata and trace, plus a cancellation signal that
+//! fires once the request is over: the peer went away, the request timed out or
+//! the reply was sent. Long handlers watch it to stop early:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::RequestContext;
+//! # #[derive(serde::Deserialize)]
+//! # struct ReindexRequest { chunks: Vec<String> }
+//! # impl ReindexRequest { fn chunks(&self) -> &[String] { &self.chunks } }
+//! # #[derive(serde::Serialize)]
+//! # struct Done;
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # enum ReindexError {
+//! #     #[error("cancelled")]
+//! #     Cancelled,
+//! # }
+//! # async fn index(chunk: &str) -> Result<(), ReindexError> { Ok(()) }
+//! async fn reindex(req: ReindexRequest, ctx: RequestContext) -> Result<Done, ReindexError> {
+//!     for chunk in req.chunks() {
+//!         if ctx.is_cancelled() {
+//!             return Err(ReindexError::Cancelled);
+//!         }
+//!         index(chunk).await?;
+//!     }
+//!     Ok(Done)
+//! }
+//! ```
+//!
+//! The handler future itself is dropped when the peer disconnects; the signal
+//! matters for work it handed to other tasks via [`RequestContext::cancellation_token`].
+
+/// Metadata header naming the binding a daemon call is addressed to
+///
+/// serve_all commands carry their binding in the protocol value; calls to
+/// plain protocols made through the daemon carry it here instead.
+pub const BIND_ALIAS_HEADER: &str = "bind-alias";
+
+/// What a request handler knows about the request it is serving
+#[derive(Clone)]
+pub struct RequestContext {
+    context: std::sync::Arc<fastn_context::Context>,
+    peer: fastn_id52::PublicKey,
+    protocol: serde_json::Value,
+    metadata: std::collections::BTreeMap<String, String>,
+    trace: fastn_net::TraceContext,
+    pub(crate) verified: Option<crate::signing::VerifiedSender>,
+    deadline: Option<tokio::time::Instant>,
+    cancellation: tokio_util::sync::CancellationToken,
+    session: Option<crate::server::PeerSession>,
+    attachments: Vec<crate::attachment::IncomingAttachment>,
+}
+
+impl RequestContext {
+    pub(crate) fn new(
+        request: &crate::server::LayerRequest,
+        deadline: Option<tokio::time::Instant>,
+        cancellation: tokio_util::sync::CancellationToken,
+    ) -> Self {
+        // A root rather than a child of a server-wide context: fastn-context
+        // keeps every child for as long as its parent lives
+        let context = fastn_context::Context::new("request");
+        tokio::spawn({
+            let context = context.clone();
+            let cancellation = cancellation.clone();
+            async move {
+                cancellation.cancelled().await;
+                context.cancel();
+            }
+        });
+        Self {
+            context,
+            peer: *request.peer(),
+            protocol: request.protocol().clone(),
+            metadata: request.metadata.clone(),
+            trace: request.trace().clone(),
+            verified: request.verified.clone(),
+            deadline,
+            cancellation,
+            session: request.session.clone(),
+            attachments: request.attachments.clone(),
+        }
+    }
+
+    /// The fastn-context tree for this request, cancelled along with [`Self::cancelled`]
+    pub fn context(&self) -> &std::sync::Arc<fastn_context::Context> {
+        &self.context
+    }
+
+    /// The peer that sent the request
+    pub fn peer(&self) -> &fastn_id52::PublicKey {
+        &self.peer
+    }
+
+    /// Protocol the handler was registered for
+    pub fn protocol(&self) -> &serde_json::Value {
+        &self.protocol
+    }
+
+    /// Headers sent by the client's interceptors (after any layer rewrote them)
+    pub fn metadata(&self) -> &std::collections::BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// One metadata header
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
+    /// Binding the caller addressed through its daemon, see [`BIND_ALIAS_HEADER`]
+    pub fn bind_alias(&self) -> Option<&str> {
+        self.value(BIND_ALIAS_HEADER)
+    }
+
+    /// Trace context of the handler span, see [`fastn_net::trace`]
+    pub fn trace(&self) -> &fastn_net::TraceContext {
+        &self.trace
+    }
+
+    /// Signer of a signed request, see [`crate::signing`]
+    pub fn verified_sender(&self) -> Option<&crate::signing::VerifiedSender> {
+        self.verified.as_ref()
+    }
+
+    /// State kept for the peer while it stays connected, see [`crate::server::peer_sessions`]
+    ///
+    /// `None` for relayed requests, whose sender is not connected to this server.
+    pub fn session(&self) -> Option<&crate::server::PeerSession> {
+        self.session.as_ref()
+    }
+
+    /// Blobs the client sent alongside the request, see [`crate::attachment`]
+    pub fn attachments(&self) -> &[crate::attachment::IncomingAttachment] {
+        &self.attachments
+    }
+
+    /// When the request times out or the client gives up, whichever comes first
+    ///
+    /// `None` if neither the server nor the client set a timeout.
+    pub fn deadline(&self) -> Option<tokio::time::Instant> {
+        self.deadline
+    }
+
+    /// Time left before the request times out
+    pub fn remaining(&self) -> Option<std::time::Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(tokio::time::Instant::now()))
+    }
+
+    /// Whether the request is over and further work is wasted
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Resolves once the request is over
+    pub async fn cancelled(&self) {
+        self.cancellation.cancelled().await
+    }
+
+    /// Cancellation signal to hand to tasks spawned for this request
+    pub fn cancellation_token(&self) -> tokio_util::sync::CancellationToken {
+        self.cancellation.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_context_reflects_request_and_cancellation() {
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let metadata = std::collections::BTreeMap::from([("tenant".to_string(), "acme".to_string())]);
+        let request = crate::server::LayerRequest::new(
+            peer, serde_json::json!("Reindex"), serde_json::Value::Null, metadata, None, false, None,
+        );
+        let cancellation = tokio_util::sync::CancellationToken::new();
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(30);
+        let context = RequestContext::new(&request, Some(deadline), cancellation.clone());
+
+        assert_eq!(context.peer(), &peer);
+        assert_eq!(context.value("tenant"), Some("acme"));
+        assert!(context.remaining().unwrap() <= std::time::Duration::from_secs(30));
+
+        assert!(!context.is_cancelled());
+        drop(cancellation.drop_guard());
+        context.cancelled().await;
+        assert!(context.is_cancelled());
+        context.context().cancelled().await;
+    }
+}
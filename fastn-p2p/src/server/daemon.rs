@@ -5,7 +5,7 @@
 //! - Identity loading and management
 //! - Generic multi-identity, multi-protocol server setup
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Protocol binding configuration with file-based config
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -59,7 +59,10 @@ impl IdentityConfig {
     }
     
     /// Save this identity config to the identities directory
-    pub async fn save_to_dir(&self, identities_dir: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    ///
+    /// Online state and bindings are read from [`super::state::StateStore`];
+    /// the copies written here are only for tools that predate it.
+    pub async fn save_to_dir(&self, identities_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
         // Only save secret key if it doesn't exist yet
         let key_path = identities_dir.join(format!("{}.private-key", self.alias));
         if !key_path.exists() {
@@ -80,23 +83,35 @@ impl IdentityConfig {
     }
     
     /// Load identity config from conventional directory structure
-    pub async fn load_from_conventional_dir(identity_dir: &PathBuf, alias: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub async fn load_from_conventional_dir(identity_dir: &Path, alias: &str) -> Result<Self, Box<dyn std::error::Error>> {
         // Load the secret key
         let (_id52, secret_key) = fastn_id52::SecretKey::load_from_dir(identity_dir, "identity")?;
         
-        // Check if identity is online (online file exists)
-        let online_marker = identity_dir.join("online");
-        let online = online_marker.exists();
-        
-        // Discover all protocol configurations by scanning protocols/ directory
-        let mut protocols = Vec::new();
-        let protocols_dir = identity_dir.join("protocols");
-        
-        if protocols_dir.exists() {
-            protocols = discover_protocol_bindings(&protocols_dir).await?;
-        }
+        // Online state and bindings come from the state store of FASTN_HOME
+        // (`<home>/identities/<alias>`), so a CLI update is seen whole or not at all
+        let fastn_home = identity_dir
+            .parent()
+            .and_then(|identities_dir| identities_dir.parent())
+            .ok_or_else(|| format!("{} is not under FASTN_HOME/identities", identity_dir.display()))?;
+        let store = super::state::StateStore::open(fastn_home).await?;
+        let online = store.is_online(alias).await?;
+
+        let protocols: Vec<ProtocolBinding> = store
+            .bindings(alias)
+            .await?
+            .into_iter()
+            .map(|binding| {
+                let config_path = identity_dir.join("protocols").join(&binding.protocol).join(&binding.bind_alias);
+                tracing::info!(target: crate::console::TARGET, "    📡 Found: {} as '{}' ({})", binding.protocol, binding.bind_alias, config_path.display());
+                ProtocolBinding {
+                    protocol: binding.protocol,
+                    bind_alias: binding.bind_alias,
+                    config_path,
+                }
+            })
+            .collect();
         
-        println!("🔍 Discovered identity '{}': {} protocols, {}", 
+        tracing::info!(target: crate::console::TARGET, "🔍 Discovered identity '{}': {} protocols, {}", 
                 alias, 
                 protocols.len(),
                 if online { "ONLINE" } else { "OFFLINE" });
@@ -110,7 +125,7 @@ impl IdentityConfig {
     }
     
     /// Legacy load method for backward compatibility
-    pub async fn load_from_dir(identities_dir: &PathBuf, alias: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub async fn load_from_dir(identities_dir: &Path, alias: &str) -> Result<Self, Box<dyn std::error::Error>> {
         // Try new conventional structure first
         let identity_dir = identities_dir.join(alias);
         if identity_dir.exists() {
@@ -134,6 +149,9 @@ impl IdentityConfig {
         };
         
         config.alias = alias.to_string();
+        if let Some(fastn_home) = identities_dir.parent() {
+            config.online = super::state::StateStore::open(fastn_home).await?.is_online(alias).await?;
+        }
         Ok(config)
     }
 }
@@ -142,15 +160,22 @@ impl IdentityConfig {
 pub type ServerConfig = Vec<IdentityConfig>;
 
 /// Get or create FASTN_HOME directory
-pub async fn ensure_fastn_home(fastn_home: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+///
+/// A new home is marked with the current layout version; existing ones are
+/// upgraded by [`super::home::migrate`].
+pub async fn ensure_fastn_home(fastn_home: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let is_new = !fastn_home.join("identities").exists();
     tokio::fs::create_dir_all(fastn_home).await?;
     tokio::fs::create_dir_all(fastn_home.join("identities")).await?;
+    if is_new && !fastn_home.join(super::home::HOME_VERSION_FILE).exists() {
+        super::home::init(fastn_home).await?;
+    }
     Ok(())
 }
 
 /// Load all identity configurations using conventional directory structure
 pub async fn load_all_identities(
-    fastn_home: &PathBuf,
+    fastn_home: &Path,
 ) -> Result<Vec<IdentityConfig>, Box<dyn std::error::Error>> {
     let identities_dir = fastn_home.join("identities");
     
@@ -164,15 +189,15 @@ pub async fn load_all_identities(
     while let Some(entry) = dir_entries.next_entry().await? {
         let identity_dir = entry.path();
         
-        if identity_dir.is_dir() {
-            if let Some(alias) = identity_dir.file_name().and_then(|n| n.to_str()) {
-                match IdentityConfig::load_from_conventional_dir(&identity_dir, alias).await {
-                    Ok(identity_config) => {
-                        identities.push(identity_config);
-                    }
-                    Err(e) => {
-                        eprintln!("⚠️  Failed to load identity '{}': {}", alias, e);
-                    }
+        if identity_dir.is_dir()
+            && let Some(alias) = identity_dir.file_name().and_then(|n| n.to_str())
+        {
+            match IdentityConfig::load_from_conventional_dir(&identity_dir, alias).await {
+                Ok(identity_config) => {
+                    identities.push(identity_config);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load identity '{}': {}", alias, e);
                 }
             }
         }
@@ -181,6 +206,64 @@ pub async fn load_all_identities(
     Ok(identities)
 }
 
+/// Why the identity for an outgoing call could not be picked
+#[derive(Debug, thiserror::Error)]
+pub enum IdentityError {
+    #[error("Identity '{alias}' not found (known identities: {known})")]
+    NotFound { alias: String, known: String },
+
+    #[error("Identity '{alias}' is offline, bring it online with: fastn-p2p identity-online {alias}")]
+    Offline { alias: String },
+
+    #[error("No identities found. Create one with: fastn-p2p create-identity <alias>")]
+    NoIdentities,
+
+    #[error("Multiple identities found ({known}), choose one with --as-identity <alias> or set a default with: fastn-p2p default-identity <alias>")]
+    Ambiguous { known: String },
+
+    #[error("Failed to load identities: {message}")]
+    Load { message: String },
+}
+
+/// Pick the identity an outgoing call is sent as
+///
+/// An explicitly requested alias wins, then the default identity stored in
+/// FASTN_HOME, then the only configured identity. The chosen identity must
+/// exist and be online.
+pub async fn resolve_identity(
+    fastn_home: &Path,
+    requested: Option<&str>,
+) -> Result<IdentityConfig, IdentityError> {
+    let identities = load_all_identities(fastn_home)
+        .await
+        .map_err(|e| IdentityError::Load { message: e.to_string() })?;
+
+    let default = fastn_p2p_client::identity::default_identity(fastn_home)
+        .await
+        .map_err(|e| IdentityError::Load { message: e.to_string() })?;
+
+    let known = || {
+        identities.iter().map(|i| i.alias.as_str()).collect::<Vec<_>>().join(", ")
+    };
+
+    let alias = match requested.map(str::to_string).or(default) {
+        Some(alias) => alias,
+        None => match identities.as_slice() {
+            [only] => only.alias.clone(),
+            [] => return Err(IdentityError::NoIdentities),
+            _ => return Err(IdentityError::Ambiguous { known: known() }),
+        },
+    };
+
+    let Some(identity) = identities.iter().find(|i| i.alias == alias) else {
+        return Err(IdentityError::NotFound { alias, known: known() });
+    };
+    if !identity.online {
+        return Err(IdentityError::Offline { alias });
+    }
+    Ok(identity.clone())
+}
+
 /// Generic server function that can be used by any fastn-p2p application
 /// 
 /// This function sets up a multi-identity, multi-protocol P2P server.
@@ -196,18 +279,18 @@ pub async fn run_generic_server(
     // Acquire singleton lock
     let _lock_file = acquire_singleton_lock(&fastn_home).await?;
     
-    println!("🚀 Starting generic P2P server");
-    println!("📁 FASTN_HOME: {}", fastn_home.display());
-    println!("🔑 Identities: {}", server_config.len());
+    tracing::info!(target: crate::console::TARGET, "🚀 Starting generic P2P server");
+    tracing::info!(target: crate::console::TARGET, "📁 FASTN_HOME: {}", fastn_home.display());
+    tracing::info!(target: crate::console::TARGET, "🔑 Identities: {}", server_config.len());
     
     for identity in &server_config {
-        println!("   Identity '{}': {} (protocols: {})", 
+        tracing::info!(target: crate::console::TARGET, "   Identity '{}': {} (protocols: {})", 
                 identity.alias,
                 identity.secret_key.public_key().id52(),
                 identity.protocols.len());
         
         for protocol in &identity.protocols {
-            println!("     - {} as '{}'", protocol.protocol, protocol.bind_alias);
+            tracing::info!(target: crate::console::TARGET, "     - {} as '{}'", protocol.protocol, protocol.bind_alias);
         }
     }
     
@@ -216,7 +299,7 @@ pub async fn run_generic_server(
 
 /// Acquire singleton lock for daemon (shared utility)
 pub async fn acquire_singleton_lock(
-    fastn_home: &PathBuf,
+    fastn_home: &Path,
 ) -> Result<std::fs::File, Box<dyn std::error::Error>> {
     use fs2::FileExt;
     use std::fs::OpenOptions;
@@ -237,48 +320,25 @@ pub async fn acquire_singleton_lock(
         ).into());
     }
     
-    println!("🔒 Acquired exclusive daemon lock: {}", lock_path.display());
+    tracing::info!(target: crate::console::TARGET, "🔒 Acquired exclusive daemon lock: {}", lock_path.display());
     Ok(lock_file)
 }
 
-/// Discover protocol bindings from conventional protocols/ directory structure
-async fn discover_protocol_bindings(protocols_dir: &PathBuf) -> Result<Vec<ProtocolBinding>, Box<dyn std::error::Error>> {
-    let mut bindings = Vec::new();
-    let mut protocol_entries = tokio::fs::read_dir(protocols_dir).await?;
-    
-    while let Some(protocol_entry) = protocol_entries.next_entry().await? {
-        let protocol_dir = protocol_entry.path();
-        
-        if protocol_dir.is_dir() {
-            if let Some(protocol_name) = protocol_dir.file_name().and_then(|n| n.to_str()) {
-                // Scan for bind aliases within this protocol directory
-                let mut alias_entries = tokio::fs::read_dir(&protocol_dir).await?;
-                
-                while let Some(alias_entry) = alias_entries.next_entry().await? {
-                    let alias_dir = alias_entry.path();
-                    
-                    if alias_dir.is_dir() {
-                        if let Some(bind_alias) = alias_dir.file_name().and_then(|n| n.to_str()) {
-                            // Check if config.json exists
-                            let config_file = alias_dir.join("config.json");
-                            if config_file.exists() {
-                                bindings.push(ProtocolBinding {
-                                    protocol: protocol_name.to_string(),
-                                    bind_alias: bind_alias.to_string(),
-                                    config_path: alias_dir.clone(),
-                                });
-                                
-                                println!("    📡 Found: {} as '{}' ({})", 
-                                        protocol_name, 
-                                        bind_alias, 
-                                        alias_dir.display());
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    
-    Ok(bindings)
-}
\ No newline at end of file
+/// Hold the FASTN_HOME update lock until the returned file is dropped
+///
+/// Every change to identities and bindings (`fastn-p2p` with no daemon
+/// running, and the daemon's control commands) takes this advisory lock, so
+/// concurrent invocations apply their multi-step updates one at a time.
+/// Waits for the current holder.
+pub async fn acquire_update_lock(fastn_home: &Path) -> std::io::Result<std::fs::File> {
+    use fs2::FileExt;
+
+    let lock_path = fastn_home.join("update.lock");
+    tokio::task::spawn_blocking(move || {
+        let lock_file = std::fs::OpenOptions::new().create(true).write(true).truncate(false).open(&lock_path)?;
+        lock_file.lock_exclusive()?;
+        Ok(lock_file)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
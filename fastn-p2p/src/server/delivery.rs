@@ -0,0 +1,130 @@
+//! Exactly-once delivery of outbox messages
+//!
+//! Messages queued with [`StateStore::enqueue`] are sent by [`deliver_pending`]
+//! as ordinary calls with their message ID in the [`MESSAGE_ID_METADATA`]
+//! header, and retried until the peer answers. A retry can reach a peer that
+//! already applied the message when only the answer got lost, so the peer
+//! wraps its handlers in [`exactly_once`]:
+//!
+//! ```rust,no_run
+//! # #[derive(Debug, serde::Serialize)]
+//! # enum MailProtocol { Deliver }
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # #[error("delivery failed")]
+//! # struct DeliveryError;
+//! # async fn deliver_mail(mail: String) -> Result<(), DeliveryError> { Ok(()) }
+//! # async fn run(key: fastn_p2p::SecretKey, fastn_home: &std::path::Path, alias: &str) -> Result<(), Box<dyn std::error::Error>> {
+//! # let store = fastn_p2p::server::state::StateStore::open(fastn_home).await?;
+//! fastn_p2p::listen(key)
+//!     .layer(fastn_p2p::server::delivery::exactly_once(store.clone(), alias))
+//!     .handle_requests(MailProtocol::Deliver, deliver_mail)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The first delivery of a message ID runs the handler and keeps its answer;
+//! later deliveries get that answer again without running it. An answer the
+//! sender gets back is stored as the delivery receipt, queryable with
+//! [`StateStore::delivery`].
+
+use crate::server::state::{MessageClaim, StateStore};
+
+/// Request metadata carrying an outbox message's ID
+pub const MESSAGE_ID_METADATA: &str = "fastn-message-id";
+
+/// Layer applying every message ID from a peer at most once, see the module docs
+///
+/// Requests without a message ID and streams pass straight through.
+pub fn exactly_once(
+    store: StateStore,
+    identity: impl Into<String>,
+) -> impl Fn(crate::server::LayerRequest, crate::server::Next) -> crate::server::middleware::BoxFuture + Send + Sync + 'static {
+    let identity: String = identity.into();
+    move |request, next| {
+        let (store, identity) = (store.clone(), identity.clone());
+        Box::pin(async move {
+            let message_id = match request.metadata.get(MESSAGE_ID_METADATA) {
+                Some(message_id) if !request.is_stream() => message_id.clone(),
+                _ => return next.run(request).await,
+            };
+            let sender = *request.peer();
+
+            match store.claim_message(&identity, &sender, &message_id).await {
+                Ok(MessageClaim::New) => {}
+                Ok(MessageClaim::Applied(response)) => {
+                    tracing::debug!("Message {} from {} applied before, replaying its answer", message_id, sender.id52());
+                    return Ok(response);
+                }
+                Ok(MessageClaim::InProgress) => {
+                    return Err(serde_json::json!({ "message_in_progress": message_id }));
+                }
+                Err(e) => {
+                    // Applying without a record could apply it twice; the sender retries later
+                    tracing::warn!("Failed to claim message {} from {}: {}", message_id, sender.id52(), e);
+                    return Err(serde_json::json!({ "message_not_claimed": message_id }));
+                }
+            }
+
+            let result = next.run(request).await;
+            let recorded = match &result {
+                Ok(response) => store.message_applied(&identity, &sender, &message_id, response).await,
+                Err(_) => store.release_message(&identity, &sender, &message_id).await,
+            };
+            if let Err(e) = recorded {
+                tracing::warn!("Failed to record message {} from {}: {}", message_id, sender.id52(), e);
+            }
+            result
+        })
+    }
+}
+
+/// Outcome of one [`deliver_pending`] round
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeliveryReport {
+    pub delivered: usize,
+    pub failed: usize,
+}
+
+/// Try to deliver every pending outbox message of `identity` through `client`
+///
+/// Messages go out oldest first. Once a delivery to a peer fails, that
+/// peer's later messages wait for the next round so they don't overtake it.
+pub async fn deliver_pending(
+    store: &StateStore,
+    identity: &str,
+    client: &crate::client::Client,
+) -> Result<DeliveryReport, crate::server::state::StateError> {
+    let mut report = DeliveryReport::default();
+    let mut failed_peers = std::collections::HashSet::new();
+
+    for entry in store.outbox(identity).await? {
+        if failed_peers.contains(&entry.peer) {
+            continue;
+        }
+        let protocol = serde_json::from_str::<serde_json::Value>(&entry.protocol)
+            .unwrap_or_else(|_| serde_json::Value::String(entry.protocol.clone()));
+        let client = client.clone().with_metadata(MESSAGE_ID_METADATA, entry.message_id.clone());
+
+        let result = client
+            .call::<_, _, serde_json::Value, serde_json::Value>(entry.peer, protocol, &entry.payload)
+            .await;
+        match result {
+            Ok(Ok(response)) => {
+                store.delivered(entry.id, &response).await?;
+                report.delivered += 1;
+            }
+            Ok(Err(error)) => {
+                store.delivery_failed(entry.id, &error.to_string()).await?;
+                failed_peers.insert(entry.peer);
+                report.failed += 1;
+            }
+            Err(e) => {
+                store.delivery_failed(entry.id, &e.to_string()).await?;
+                failed_peers.insert(entry.peer);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
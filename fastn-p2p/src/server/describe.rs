@@ -0,0 +1,235 @@
+//! Machine-readable descriptions of a server's protocols
+//!
+//! With the `schema` feature, [`crate::server::ServerBuilder::describe_requests`]
+//! and [`crate::server::ServerBuilder::describe_streams`] record JSON Schemas
+//! (generated by `schemars`) for a protocol's request, response and error
+//! types. A server with descriptions also answers [`DESCRIBE_PROTOCOL`] with
+//! an [OpenRPC](https://spec.open-rpc.org) document, which
+//! `fastn-p2p describe <peer> <protocol>` prints:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "schema")]
+//! # async fn run(key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//! # #[derive(Debug, serde::Serialize)]
+//! # enum Mail { Send }
+//! # #[derive(serde::Deserialize, schemars::JsonSchema)]
+//! # struct SendInput { to: String }
+//! # #[derive(serde::Serialize, schemars::JsonSchema)]
+//! # struct SendOutput { id: String }
+//! # #[derive(Debug, serde::Serialize, thiserror::Error, schemars::JsonSchema)]
+//! # #[error("mail failed")]
+//! # struct MailError;
+//! # async fn send(input: SendInput) -> Result<SendOutput, MailError> { Ok(SendOutput { id: input.to }) }
+//! fastn_p2p::listen(key)
+//!     .handle_requests(Mail::Send, send)
+//!     .describe_requests::<_, SendInput, SendOutput, MailError>(Mail::Send)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each handler is one method, named after its protocol value. Servers
+//! without descriptions don't answer the introspection protocol at all.
+
+/// Protocol answered with the OpenRPC document of the described handlers
+pub const DESCRIBE_PROTOCOL: &str = "fastn-p2p-describe";
+
+/// OpenRPC version of the documents served
+pub const OPENRPC_VERSION: &str = "1.3.2";
+
+/// Request data of [`DESCRIBE_PROTOCOL`]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct DescribeRequest {
+    /// Only describe this protocol; everything when `None`, see [`MethodDescription::belongs_to`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+}
+
+/// Whether a described handler answers requests or streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MethodKind {
+    Request,
+    Stream,
+}
+
+/// Schemas of one registered handler
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MethodDescription {
+    /// The protocol value clients send
+    pub protocol: serde_json::Value,
+    pub kind: MethodKind,
+    /// Schema of the request (or the stream's initial data)
+    pub input: serde_json::Value,
+    /// Schema of the response; `None` for streams
+    pub output: Option<serde_json::Value>,
+    /// Schema of the application error; `None` for streams
+    pub error: Option<serde_json::Value>,
+}
+
+impl MethodDescription {
+    /// Method name in the OpenRPC document: the protocol string, or its JSON
+    pub fn name(&self) -> String {
+        match &self.protocol {
+            serde_json::Value::String(name) => name.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    /// Whether this handler is part of `protocol`
+    ///
+    /// Matches the protocol value itself (as a name or as JSON), a serve_all
+    /// [`crate::server::CommandProtocol`] of that protocol and an enum variant
+    /// of that name, e.g. `{"Mail": "inbox"}` for `Mail`.
+    pub fn belongs_to(&self, protocol: &str) -> bool {
+        let wire = serde_json::from_str(protocol).unwrap_or_else(|_| serde_json::Value::String(protocol.to_string()));
+        if self.protocol == wire {
+            return true;
+        }
+        match &self.protocol {
+            serde_json::Value::Object(fields) => {
+                fields.get("protocol").and_then(|p| p.as_str()) == Some(protocol)
+                    || (fields.len() == 1 && fields.contains_key(protocol))
+            }
+            _ => false,
+        }
+    }
+
+    fn to_openrpc(&self) -> serde_json::Value {
+        let mut method = serde_json::json!({
+            "name": self.name(),
+            "paramStructure": "by-position",
+            "params": [{ "name": "input", "required": true, "schema": self.input }],
+            "x-fastn-protocol": self.protocol,
+            "x-fastn-kind": self.kind,
+        });
+        match &self.output {
+            Some(output) => method["result"] = serde_json::json!({ "name": "output", "schema": output }),
+            // The stream's bytes are not described; OpenRPC still wants a result
+            None => method["result"] = serde_json::json!({ "name": "stream", "schema": {} }),
+        }
+        if let Some(error) = &self.error {
+            method["x-fastn-error"] = error.clone();
+        }
+        method
+    }
+
+    /// Read a method back from an OpenRPC document; `None` if it lacks the `x-fastn-*` fields
+    pub fn from_openrpc(method: &serde_json::Value) -> Option<Self> {
+        let kind: MethodKind = serde_json::from_value(method.get("x-fastn-kind")?.clone()).ok()?;
+        let output = match kind {
+            MethodKind::Request => Some(method["result"]["schema"].clone()),
+            MethodKind::Stream => None,
+        };
+        Some(Self {
+            protocol: method.get("x-fastn-protocol")?.clone(),
+            kind,
+            input: method["params"][0]["schema"].clone(),
+            output,
+            error: method.get("x-fastn-error").cloned(),
+        })
+    }
+
+    /// Schemas of a request handler's types
+    #[cfg(feature = "schema")]
+    pub fn request<INPUT, OUTPUT, ERROR>(protocol: serde_json::Value) -> Self
+    where
+        INPUT: schemars::JsonSchema,
+        OUTPUT: schemars::JsonSchema,
+        ERROR: schemars::JsonSchema,
+    {
+        Self {
+            protocol,
+            kind: MethodKind::Request,
+            input: schema::<INPUT>(),
+            output: Some(schema::<OUTPUT>()),
+            error: Some(schema::<ERROR>()),
+        }
+    }
+
+    /// Schema of a stream handler's initial data
+    #[cfg(feature = "schema")]
+    pub fn stream<DATA: schemars::JsonSchema>(protocol: serde_json::Value) -> Self {
+        Self {
+            protocol,
+            kind: MethodKind::Stream,
+            input: schema::<DATA>(),
+            output: None,
+            error: None,
+        }
+    }
+}
+
+#[cfg(feature = "schema")]
+fn schema<T: schemars::JsonSchema>() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(T)).expect("JSON Schemas serialize")
+}
+
+/// The OpenRPC document for `methods`, limited to `protocol` if given
+pub fn document(methods: &[MethodDescription], protocol: Option<&str>) -> serde_json::Value {
+    let methods: Vec<serde_json::Value> = methods
+        .iter()
+        .filter(|method| protocol.is_none_or(|protocol| method.belongs_to(protocol)))
+        .map(MethodDescription::to_openrpc)
+        .collect();
+    serde_json::json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": {
+            "title": protocol.unwrap_or("fastn-p2p server"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "methods": methods,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn method(protocol: serde_json::Value) -> MethodDescription {
+        MethodDescription {
+            protocol,
+            kind: MethodKind::Request,
+            input: serde_json::json!({"type": "string"}),
+            output: Some(serde_json::json!({"type": "string"})),
+            error: Some(serde_json::json!({"type": "string"})),
+        }
+    }
+
+    #[test]
+    fn test_document_filters_by_protocol() {
+        let methods = [
+            method(serde_json::json!("Echo")),
+            method(serde_json::json!({"Mail": "inbox"})),
+            method(serde_json::to_value(crate::server::CommandProtocol::new("mail.fastn.com", "default", "get-mails")).unwrap()),
+        ];
+
+        assert_eq!(document(&methods, None)["methods"].as_array().unwrap().len(), 3);
+
+        let echo = document(&methods, Some("Echo"));
+        assert_eq!(echo["openrpc"], OPENRPC_VERSION);
+        assert_eq!(echo["methods"][0]["name"], "Echo");
+        assert_eq!(echo["methods"][0]["params"][0]["schema"], serde_json::json!({"type": "string"}));
+        assert_eq!(echo["methods"][0]["x-fastn-kind"], "request");
+
+        assert_eq!(document(&methods, Some("Mail"))["methods"][0]["x-fastn-protocol"], serde_json::json!({"Mail": "inbox"}));
+        assert_eq!(document(&methods, Some("mail.fastn.com"))["methods"].as_array().unwrap().len(), 1);
+        assert!(document(&methods, Some("Chat"))["methods"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_openrpc_round_trip() {
+        let request = method(serde_json::json!({"Mail": "inbox"}));
+        let stream = MethodDescription {
+            kind: MethodKind::Stream,
+            output: None,
+            error: None,
+            ..method(serde_json::json!("Upload"))
+        };
+
+        for method in [request, stream] {
+            assert_eq!(MethodDescription::from_openrpc(&method.to_openrpc()), Some(method));
+        }
+        assert_eq!(MethodDescription::from_openrpc(&serde_json::json!({"name": "rpc.discover"})), None);
+    }
+}
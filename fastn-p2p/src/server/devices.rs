@@ -0,0 +1,581 @@
+//! Secondary devices using an identity hosted on a primary daemon
+//!
+//! The secret key of an identity never leaves the primary. A second machine
+//! pairs with it once and from then on sends its calls through the primary,
+//! which opens the onward stream as the identity:
+//!
+//! ```text
+//! device ──DeviceProxy { target }──▶ primary (as identity) ──"fastn-p2p"──▶ target
+//! ```
+//!
+//! 1. On the primary, [`DeviceRegistry::start_pairing`] issues a short-lived,
+//!    single-use code. It is shown as a [`PairingUri`] (print it or render it
+//!    as a QR code).
+//! 2. The secondary generates its own device key and connects to the identity
+//!    with a [`fastn_net::Protocol::DevicePair`] stream carrying the code; the
+//!    primary records the device key in the identity's registry.
+//! 3. Calls from the secondary use [`fastn_net::Protocol::DeviceProxy`]
+//!    streams. The primary answers with a status line (a tagged `()` / error
+//!    message) and then pipes bytes unchanged in both directions.
+//!
+//! The registry lives in `devices.json` in the identity directory, so codes
+//! issued by `fastn-p2p device pair` are seen by the running server.
+//!
+//! # Example
+//! ```rust,no_run
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum EchoProtocol { Echo }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # struct EchoResponse { echoed: String }
+//! # #[derive(Debug, serde::Serialize, serde::Deserialize, thiserror::Error)]
+//! # #[error("echo failed")]
+//! # struct EchoError;
+//! # async fn run(alice_key: fastn_p2p::SecretKey, device_key: fastn_p2p::SecretKey, bob: fastn_p2p::PublicKey) -> Result<(), Box<dyn std::error::Error>> {
+//! # let (alice, alice_dir, request) = (alice_key.public_key(), "identities/alice", "hi".to_string());
+//! // On the primary
+//! fastn_p2p::listen(alice_key).with_devices(alice_dir).await?;
+//!
+//! // On the secondary, once
+//! let client = fastn_p2p::client::Client::new(device_key);
+//! client.pair(alice, "7KQF-M2XD", "laptop").await??;
+//!
+//! // On the secondary, for every call
+//! let result: Result<EchoResponse, EchoError> =
+//!     client.call_as_device(alice, bob, EchoProtocol::Echo, request).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+/// Protocol offered in the handshake by paired (or pairing) devices
+pub const DEVICE_PROTOCOL: &str = "fastn-p2p-device";
+
+/// Registry file in the identity directory
+pub const DEVICES_FILE: &str = "devices.json";
+
+/// How long a pairing code stays valid
+pub const PAIRING_CODE_TTL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Wrong codes tolerated before all pending codes are revoked
+pub const MAX_PAIRING_ATTEMPTS: u32 = 5;
+
+// Unambiguous characters only, codes are typed in by hand
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CODE_LEN: usize = 8;
+
+/// One lock per registry file, see [`lock_registry`]
+static REGISTRY_LOCKS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, std::sync::Arc<tokio::sync::Mutex<()>>>>,
+> = std::sync::LazyLock::new(Default::default);
+
+/// Hold while loading, changing and saving the registry file at `path`
+///
+/// Concurrent redeems would otherwise each load the same attempt count, so
+/// guesses sent in parallel would get past the attempt limit. Also used for
+/// invites, see [`crate::server::invites`].
+pub(crate) async fn lock_registry(path: std::path::PathBuf) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = REGISTRY_LOCKS
+        .lock()
+        .expect("Failed to acquire lock on registry locks")
+        .entry(path)
+        .or_default()
+        .clone();
+    lock.lock_owned().await
+}
+
+/// Device settings of a server: where the identity's device registry lives
+#[derive(Debug, Clone)]
+pub struct DeviceConfig {
+    pub identity_dir: std::path::PathBuf,
+}
+
+impl DeviceConfig {
+    /// The [`DEVICE_PROTOCOL`] as offered in `ClientHello`
+    pub fn protocol_json() -> serde_json::Value {
+        serde_json::Value::String(DEVICE_PROTOCOL.to_string())
+    }
+
+    /// Alias of the identity, the name of its directory
+    pub fn identity_alias(&self) -> String {
+        self.identity_dir
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeviceError {
+    #[error("Invalid or expired pairing code")]
+    InvalidCode,
+
+    #[error("Device {device} is not paired with this identity")]
+    NotPaired { device: String },
+
+    #[error("Invalid peer ID52 '{id52}'")]
+    InvalidPeer { id52: String },
+
+    #[error("Invalid pairing URI '{uri}'")]
+    InvalidUri { uri: String },
+
+    #[error("Could not reach {target}: {source}")]
+    Unreachable {
+        target: String,
+        source: crate::client::CallError,
+    },
+
+    #[error("Invalid device request: {source}")]
+    InvalidRequest { source: eyre::Error },
+
+    #[error("Device registry error: {source}")]
+    Registry { source: serde_json::Error },
+
+    #[error("Device key error: {source}")]
+    Key { source: fastn_id52::KeyringError },
+
+    #[error("IO error: {source}")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+}
+
+/// A device allowed to use the identity
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PairedDevice {
+    pub name: String,
+    pub id52: String,
+    pub paired_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct PendingPairing {
+    code: fastn_p2p_client::Sensitive<String>,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Paired devices and outstanding pairing codes of one identity
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct DeviceRegistry {
+    #[serde(default)]
+    pub devices: Vec<PairedDevice>,
+    #[serde(default)]
+    pending: Vec<PendingPairing>,
+    #[serde(default)]
+    failed_attempts: u32,
+}
+
+impl DeviceRegistry {
+    /// Load the registry from `identity_dir`; a missing file is an empty registry
+    pub async fn load(identity_dir: &std::path::Path) -> Result<Self, DeviceError> {
+        match tokio::fs::read(identity_dir.join(DEVICES_FILE)).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|source| DeviceError::Registry { source }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self, identity_dir: &std::path::Path) -> Result<(), DeviceError> {
+        let json = serde_json::to_string_pretty(self).map_err(|source| DeviceError::Registry { source })?;
+        tokio::fs::write(identity_dir.join(DEVICES_FILE), json).await?;
+        Ok(())
+    }
+
+    /// Issue a new single-use pairing code, valid for [`PAIRING_CODE_TTL`]
+    pub fn start_pairing(&mut self) -> String {
+        let code = random_code();
+        let now = chrono::Utc::now();
+        self.pending.retain(|p| p.expires_at > now);
+        self.pending.push(PendingPairing {
+            code: fastn_p2p_client::Sensitive(code.clone()),
+            expires_at: now + chrono::Duration::from_std(PAIRING_CODE_TTL).expect("TTL fits in chrono::Duration"),
+        });
+        self.failed_attempts = 0;
+        code
+    }
+
+    /// Redeem `code` for `device`, adding it to the paired devices
+    ///
+    /// Too many wrong codes revoke every pending code, so a code cannot be
+    /// guessed while it is valid.
+    pub fn complete_pairing(
+        &mut self,
+        code: &str,
+        device: &fastn_id52::PublicKey,
+        name: &str,
+    ) -> Result<(), DeviceError> {
+        let now = chrono::Utc::now();
+        self.pending.retain(|p| p.expires_at > now);
+
+        let code = code.trim().to_uppercase();
+        let Some(index) = self.pending.iter().position(|p| *p.code == code) else {
+            self.failed_attempts += 1;
+            if self.failed_attempts >= MAX_PAIRING_ATTEMPTS {
+                self.pending.clear();
+            }
+            return Err(DeviceError::InvalidCode);
+        };
+        self.pending.remove(index);
+
+        let id52 = device.id52();
+        self.devices.retain(|d| d.id52 != id52);
+        self.devices.push(PairedDevice {
+            name: name.to_string(),
+            id52,
+            paired_at: now,
+        });
+        Ok(())
+    }
+
+    /// [`Self::complete_pairing`] against the registry in `identity_dir`
+    ///
+    /// Loads, redeems and saves under [`lock_registry`]; failed attempts are
+    /// saved too, they count towards revoking the codes.
+    pub async fn complete_pairing_in(
+        identity_dir: &std::path::Path,
+        code: &str,
+        device: &fastn_id52::PublicKey,
+        name: &str,
+    ) -> Result<(), DeviceError> {
+        let _guard = lock_registry(identity_dir.join(DEVICES_FILE)).await;
+        let mut registry = Self::load(identity_dir).await?;
+        let result = registry.complete_pairing(code, device, name);
+        registry.save(identity_dir).await?;
+        result
+    }
+
+    pub fn is_paired(&self, device: &fastn_id52::PublicKey) -> bool {
+        let id52 = device.id52();
+        self.devices.iter().any(|d| d.id52 == id52)
+    }
+
+    /// Forget a paired device; returns whether it was paired
+    pub fn remove(&mut self, id52: &str) -> bool {
+        let before = self.devices.len();
+        self.devices.retain(|d| d.id52 != id52);
+        self.devices.len() != before
+    }
+}
+
+/// A fresh `XXXX-XXXX` code, also used for invites, see [`crate::server::invites`]
+pub(crate) fn random_code() -> String {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let code: String = (0..CODE_LEN)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..CODE_LEN / 2], &code[CODE_LEN / 2..])
+}
+
+/// What a secondary needs to pair: the identity to connect to and the code
+#[derive(Clone, PartialEq)]
+pub struct PairingUri {
+    pub identity: fastn_id52::PublicKey,
+    pub code: String,
+}
+
+impl std::fmt::Debug for PairingUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PairingUri")
+            .field("identity", &self.identity)
+            .field("code", &fastn_p2p_client::Sensitive(&self.code))
+            .finish()
+    }
+}
+
+impl std::fmt::Display for PairingUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fastn-p2p-pair:{}?code={}", self.identity.id52(), self.code)
+    }
+}
+
+impl std::str::FromStr for PairingUri {
+    type Err = DeviceError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        let invalid = || DeviceError::InvalidUri { uri: uri.to_string() };
+        let (identity, code) = uri
+            .strip_prefix("fastn-p2p-pair:")
+            .and_then(|rest| rest.split_once("?code="))
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            identity: identity.parse().map_err(|_| invalid())?,
+            code: code.to_string(),
+        })
+    }
+}
+
+/// Directory in FASTN_HOME holding the identities this machine uses as a device
+pub const REMOTE_IDENTITIES_DIR: &str = "devices";
+
+/// An identity hosted on another daemon that this machine is paired with
+///
+/// Stored in `FASTN_HOME/devices/<alias>/`: the device key and the identity's
+/// ID52. Calls as `alias` go through the primary hosting the identity.
+#[derive(Debug, Clone)]
+pub struct RemoteIdentity {
+    pub alias: String,
+    pub identity: fastn_id52::PublicKey,
+    pub device_key: fastn_id52::SecretKey,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RemoteIdentityFile {
+    identity: String,
+}
+
+impl RemoteIdentity {
+    pub fn dir(fastn_home: &std::path::Path, alias: &str) -> std::path::PathBuf {
+        fastn_home.join(REMOTE_IDENTITIES_DIR).join(alias)
+    }
+
+    /// Load the paired identity `alias`, if this machine has one
+    pub async fn load(fastn_home: &std::path::Path, alias: &str) -> Result<Option<Self>, DeviceError> {
+        let dir = Self::dir(fastn_home, alias);
+        let bytes = match tokio::fs::read(dir.join("paired.json")).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let file: RemoteIdentityFile =
+            serde_json::from_slice(&bytes).map_err(|source| DeviceError::Registry { source })?;
+        let (_, device_key) = fastn_id52::SecretKey::load_from_dir(&dir, "device")
+            .map_err(|source| DeviceError::Key { source })?;
+
+        Ok(Some(Self {
+            alias: alias.to_string(),
+            identity: file
+                .identity
+                .parse()
+                .map_err(|_| DeviceError::InvalidPeer { id52: file.identity.clone() })?,
+            device_key,
+        }))
+    }
+
+    pub async fn save(&self, fastn_home: &std::path::Path) -> Result<(), DeviceError> {
+        let dir = Self::dir(fastn_home, &self.alias);
+        tokio::fs::create_dir_all(&dir).await?;
+        self.device_key
+            .save_to_dir(&dir, "device")
+            .map_err(|source| DeviceError::Key { source })?;
+
+        let file = RemoteIdentityFile { identity: self.identity.id52() };
+        let json = serde_json::to_string_pretty(&file).map_err(|source| DeviceError::Registry { source })?;
+        tokio::fs::write(dir.join("paired.json"), json).await?;
+        Ok(())
+    }
+}
+
+/// The paired remote identity an outgoing call is sent as, if any
+///
+/// Uses the explicitly requested alias or else the default identity, like
+/// [`crate::server::resolve_identity`] does for local identities.
+pub async fn resolve_remote_identity(
+    fastn_home: &std::path::Path,
+    requested: Option<&str>,
+) -> Result<Option<RemoteIdentity>, DeviceError> {
+    let alias = match requested {
+        Some(alias) => alias.to_string(),
+        None => match fastn_p2p_client::identity::default_identity(fastn_home).await {
+            Ok(Some(alias)) => alias,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(DeviceError::Io { source: std::io::Error::other(e.to_string()) }),
+        },
+    };
+    RemoteIdentity::load(fastn_home, &alias).await
+}
+
+/// Sent by a device on a [`fastn_net::Protocol::DevicePair`] stream
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PairRequest {
+    pub code: String,
+    /// Human readable device name shown in `fastn-p2p device list`
+    pub name: String,
+}
+
+impl std::fmt::Debug for PairRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PairRequest")
+            .field("code", &fastn_p2p_client::Sensitive(&self.code))
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// Serve a `DevicePair` stream: redeem the code and answer with a status line
+pub(crate) async fn pair(
+    mut send: iroh::endpoint::SendStream,
+    mut recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    device: fastn_id52::PublicKey,
+    config: &DeviceConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let result = redeem(&mut recv, &device, config).await;
+
+    match &result {
+        Ok(name) => tracing::info!("Paired device '{}' ({})", name, device.id52()),
+        Err(e) => tracing::warn!("Pairing attempt from {} failed: {}", device.id52(), e),
+    }
+    crate::events::publish(crate::events::EventKind::Notification {
+        identity: config.identity_alias(),
+        protocol: DEVICE_PROTOCOL.to_string(),
+        bind_alias: "default".to_string(),
+        name: "pairing-request".to_string(),
+        peer: Some(device.id52()),
+        title: "Device pairing".to_string(),
+        body: match &result {
+            Ok(name) => format!("Paired device '{}'", name),
+            Err(e) => format!("Pairing attempt failed: {}", e),
+        },
+    });
+
+    crate::server::relay::write_status(&mut send, result.map(|_| ()).map_err(|e| e.to_string())).await?;
+    send.finish()?;
+    Ok(())
+}
+
+async fn redeem(
+    recv: &mut fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    device: &fastn_id52::PublicKey,
+    config: &DeviceConfig,
+) -> Result<String, DeviceError> {
+    let wrapper: crate::wire::WrapperRequest = recv
+        .next_json()
+        .await
+        .map_err(|source| DeviceError::InvalidRequest { source })?;
+    let request: PairRequest = serde_json::from_value(wrapper.data)
+        .map_err(|e| DeviceError::InvalidRequest { source: e.into() })?;
+
+    DeviceRegistry::complete_pairing_in(&config.identity_dir, &request.code, device, &request.name).await?;
+    Ok(request.name)
+}
+
+/// Forward a `DeviceProxy` stream from a paired device to `target` as this identity
+///
+/// `identity_key` is this server's identity, used for the onward connection.
+pub(crate) async fn forward(
+    mut send: iroh::endpoint::SendStream,
+    mut recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    device: fastn_id52::PublicKey,
+    target: String,
+    config: &DeviceConfig,
+    identity_key: fastn_id52::SecretKey,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let onward = open_onward(&mut recv, &device, &target, config, identity_key).await;
+
+    crate::server::relay::write_status(&mut send, onward.as_ref().map(|_| ()).map_err(|e| e.to_string())).await?;
+
+    let onward = match onward {
+        Ok(onward) => onward,
+        Err(e) => {
+            tracing::warn!("Refused to proxy for device {}: {}", device.id52(), e);
+            send.finish()?;
+            return Ok(());
+        }
+    };
+
+    crate::server::relay::pipe(send, recv, onward).await?;
+    tracing::debug!("Finished proxying from device {} to {}", device.id52(), target);
+    Ok(())
+}
+
+async fn open_onward(
+    recv: &mut fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    device: &fastn_id52::PublicKey,
+    target: &str,
+    config: &DeviceConfig,
+    identity_key: fastn_id52::SecretKey,
+) -> Result<crate::client::Session, DeviceError> {
+    // Reload on every stream so `fastn-p2p device remove` takes effect immediately
+    if !DeviceRegistry::load(&config.identity_dir).await?.is_paired(device) {
+        return Err(DeviceError::NotPaired { device: device.id52() });
+    }
+
+    let target_key: fastn_id52::PublicKey = target
+        .parse()
+        .map_err(|_| DeviceError::InvalidPeer { id52: target.to_string() })?;
+
+    let wrapper: crate::wire::WrapperRequest = recv
+        .next_json()
+        .await
+        .map_err(|source| DeviceError::InvalidRequest { source })?;
+
+    tracing::debug!("Proxying {:?} from device {} to {}", wrapper.protocol, device.id52(), target);
+
+    // The signature (the device's own), metadata and trace are passed on untouched
+    let header = fastn_net::Protocol::Generic(serde_json::Value::String("fastn-p2p".to_string()));
+    crate::client::Client::global(identity_key)
+        .connect_with_header(target_key, header, wrapper)
+        .await
+        .map_err(|source| DeviceError::Unreachable {
+            target: target.to_string(),
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairing_code_is_single_use() {
+        let device = fastn_id52::SecretKey::generate().public_key();
+        let mut registry = DeviceRegistry::default();
+
+        let code = registry.start_pairing();
+        assert!(!registry.is_paired(&device));
+        registry.complete_pairing(&code.to_lowercase(), &device, "laptop").unwrap();
+        assert!(registry.is_paired(&device));
+
+        let other = fastn_id52::SecretKey::generate().public_key();
+        assert!(registry.complete_pairing(&code, &other, "phone").is_err());
+        assert!(registry.remove(&device.id52()));
+        assert!(!registry.is_paired(&device));
+    }
+
+    #[test]
+    fn test_wrong_codes_revoke_pending_pairings() {
+        let device = fastn_id52::SecretKey::generate().public_key();
+        let mut registry = DeviceRegistry::default();
+        let code = registry.start_pairing();
+
+        for _ in 0..MAX_PAIRING_ATTEMPTS {
+            assert!(registry.complete_pairing("AAAA-AAAA", &device, "guess").is_err());
+        }
+        assert!(registry.complete_pairing(&code, &device, "laptop").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_wrong_codes_revoke_pending_pairings() {
+        let temp = tempfile::tempdir().unwrap();
+        let identity_dir = temp.path().to_path_buf();
+        let device = fastn_id52::SecretKey::generate().public_key();
+        let mut registry = DeviceRegistry::default();
+        let code = registry.start_pairing();
+        registry.save(&identity_dir).await.unwrap();
+
+        let guesses: Vec<_> = (0..MAX_PAIRING_ATTEMPTS * 4)
+            .map(|_| {
+                let identity_dir = identity_dir.clone();
+                tokio::spawn(async move {
+                    DeviceRegistry::complete_pairing_in(&identity_dir, "AAAA-AAAA", &device, "guess").await
+                })
+            })
+            .collect();
+        for guess in guesses {
+            assert!(matches!(guess.await.unwrap(), Err(DeviceError::InvalidCode)));
+        }
+
+        let redeemed = DeviceRegistry::complete_pairing_in(&identity_dir, &code, &device, "laptop").await;
+        assert!(matches!(redeemed, Err(DeviceError::InvalidCode)));
+        assert!(!DeviceRegistry::load(&identity_dir).await.unwrap().is_paired(&device));
+    }
+
+    #[test]
+    fn test_pairing_uri_round_trip() {
+        let uri = PairingUri {
+            identity: fastn_id52::SecretKey::generate().public_key(),
+            code: "7KQF-M2XD".to_string(),
+        };
+        assert_eq!(uri.to_string().parse::<PairingUri>().unwrap(), uri);
+        assert!("fastn-p2p-pair:nope".parse::<PairingUri>().is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! Draining a host before a restart
+//!
+//! [`drain`] stops every server of the process from accepting connections
+//! and streams, like the first phase of [`crate::shutdown_with_timeout`], and
+//! waits for the streams already accepted to finish. Nothing is cancelled:
+//! streams still running when the timeout passes are reported and keep
+//! going. The daemon does this for `fastn-p2p drain`, counting the calls and
+//! streams it relays with [`InFlight`] and refusing new ones while draining;
+//! restarting it after a clean drain drops nobody.
+//!
+//! Draining can't be undone, only restarted out of.
+
+pub use fastn_p2p_client::admin::DrainReport;
+
+static IN_FLIGHT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Woken whenever an [`InFlight`] finishes, so [`drain`] doesn't have to poll
+static FINISHED: tokio::sync::Notify = tokio::sync::Notify::const_new();
+
+/// Counts one accepted stream until dropped, see [`in_flight`]
+///
+/// Servers count their streams themselves; hosts doing work of their own
+/// that a drain should wait for, like the daemon's relayed calls, hold one too.
+pub struct InFlight(());
+
+impl InFlight {
+    pub fn begin() -> Self {
+        IN_FLIGHT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        InFlight(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+        FINISHED.notify_waiters();
+    }
+}
+
+/// Streams being served by the servers of this process right now
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(std::sync::atomic::Ordering::SeqCst)
+}
+
+/// Stop accepting and wait up to `timeout` for in-flight streams to finish
+pub async fn drain(timeout: std::time::Duration) -> DrainReport {
+    let started = std::time::Instant::now();
+    crate::stop_accepting().await;
+    let in_flight_at_start = in_flight();
+    tracing::info!(target: crate::console::TARGET, "🚰 Draining: {} streams in flight", in_flight_at_start);
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        // Registered before the count is read, so a stream finishing in between isn't missed
+        let finished = FINISHED.notified();
+        if in_flight() == 0 {
+            break;
+        }
+        tokio::select! {
+            _ = finished => {
+                tracing::info!(target: crate::console::TARGET, "⏳ Draining: {} streams left", in_flight());
+            }
+            _ = tokio::time::sleep_until(deadline) => break,
+        }
+    }
+
+    let report = DrainReport {
+        drained: in_flight() == 0,
+        in_flight_at_start,
+        remaining: in_flight(),
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    };
+    if report.drained {
+        tracing::info!(target: crate::console::TARGET, "✅ Drained in {:?}", started.elapsed());
+    } else {
+        tracing::warn!("Drain timed out after {:?} with {} streams left", timeout, report.remaining);
+    }
+    report
+}
+
@@ -4,7 +4,7 @@
 /// preventing common bugs like sending multiple responses or forgetting to respond.
 /// The handle is consumed when sending a response, making multiple responses impossible.
 pub struct ResponseHandle {
-    send_stream: iroh::endpoint::SendStream,
+    response: DeferredResponse,
 }
 
 /// Error when sending a response through ResponseHandle
@@ -15,12 +15,18 @@ pub enum SendError {
 
     #[error("Failed to send response: {source}")]
     SendError { source: eyre::Error },
+
+    #[error("Peer is no longer waiting for this response")]
+    Cancelled,
+
+    #[error("No pending response with id {id}")]
+    NotPending { id: String },
 }
 
 impl ResponseHandle {
-    /// Create a new response handle from a send stream
-    pub(crate) fn new(send_stream: iroh::endpoint::SendStream) -> Self {
-        Self { send_stream }
+    /// Create a new response handle completing `response`
+    pub(crate) fn new(response: DeferredResponse) -> Self {
+        Self { response }
     }
 
     /// Send a response back to the client
@@ -29,40 +35,167 @@ impl ResponseHandle {
     /// Accepts a Result<OUTPUT, ERROR> and automatically serializes the appropriate variant.
     /// This ensures type safety by binding OUTPUT and ERROR together.
     pub async fn send<OUTPUT, ERROR>(
-        mut self,
+        self,
         result: Result<OUTPUT, ERROR>,
     ) -> Result<(), SendError>
     where
         OUTPUT: serde::Serialize,
         ERROR: serde::Serialize,
     {
-        let response_json = match result {
-            Ok(output) => {
-                // Serialize successful response
-                serde_json::to_string(&output)
-                    .map_err(|source| SendError::SerializationError { source })?
-            }
-            Err(error) => {
-                // Serialize error response
-                serde_json::to_string(&error)
-                    .map_err(|source| SendError::SerializationError { source })?
-            }
+        self.response.send(result)
+    }
+}
+
+/// Reply to a request later, possibly from another task
+///
+/// Handed to [`crate::server::ServerBuilder::handle_deferred_requests`]
+/// handlers. The stream stays open until [`DeferredResponse::send`] is called,
+/// the protocol's deferred timeout expires or the peer disconnects; in the
+/// last two cases sending fails with [`SendError::Cancelled`]. Dropping the
+/// handle without sending answers the peer with an error.
+#[derive(Debug)]
+pub struct DeferredResponse {
+    id: String,
+    sender: tokio::sync::oneshot::Sender<Result<serde_json::Value, serde_json::Value>>,
+}
+
+impl DeferredResponse {
+    pub(crate) fn new() -> (
+        Self,
+        tokio::sync::oneshot::Receiver<Result<serde_json::Value, serde_json::Value>>,
+    ) {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let id = format!("{:016x}", rand::random::<u64>());
+        (Self { id, sender }, receiver)
+    }
+
+    /// Identifier used by [`PendingResponses`]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the peer has stopped waiting (timeout or disconnect)
+    pub fn is_cancelled(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Wait until the peer stops waiting, to abandon work nobody will receive
+    pub async fn cancelled(&mut self) {
+        self.sender.closed().await
+    }
+
+    /// Complete the request, consuming the handle
+    pub fn send<OUTPUT, ERROR>(self, result: Result<OUTPUT, ERROR>) -> Result<(), SendError>
+    where
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize,
+    {
+        let result = match result {
+            Ok(output) => Ok(serde_json::to_value(&output)
+                .map_err(|source| SendError::SerializationError { source })?),
+            Err(error) => Err(serde_json::to_value(&error)
+                .map_err(|source| SendError::SerializationError { source })?),
         };
+        self.sender.send(result).map_err(|_| SendError::Cancelled)
+    }
+}
+
+/// Error when parking a deferred response
+#[derive(Debug, thiserror::Error)]
+pub enum PendingError {
+    #[error("Too many pending responses (max {max})")]
+    Full { max: usize },
+}
+
+/// Registry where handlers park deferred responses until something completes them
+///
+/// Cheap to clone; share one between the handler that parks responses and
+/// whatever completes them later (an approval UI, a webhook, another peer).
+/// Responses whose peer has gone away are pruned automatically.
+#[derive(Clone, Default)]
+pub struct PendingResponses {
+    responses: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, DeferredResponse>>>,
+    max_pending: Option<usize>,
+}
+
+impl PendingResponses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse to park more than `max` responses at once
+    pub fn with_max_pending(mut self, max: usize) -> Self {
+        self.max_pending = Some(max);
+        self
+    }
+
+    /// Park a response and return its id; dropping it on error answers the peer
+    pub fn park(&self, response: DeferredResponse) -> Result<String, PendingError> {
+        let mut responses = self.responses.lock().expect("Failed to acquire lock on pending responses");
+        responses.retain(|_, response| !response.is_cancelled());
+
+        if let Some(max) = self.max_pending
+            && responses.len() >= max
+        {
+            return Err(PendingError::Full { max });
+        }
+
+        let id = response.id.clone();
+        responses.insert(id.clone(), response);
+        Ok(id)
+    }
+
+    /// Remove a parked response, e.g. to hand it to another task
+    pub fn take(&self, id: &str) -> Option<DeferredResponse> {
+        self.responses
+            .lock()
+            .expect("Failed to acquire lock on pending responses")
+            .remove(id)
+    }
+
+    /// Complete the parked response `id`
+    pub fn complete<OUTPUT, ERROR>(&self, id: &str, result: Result<OUTPUT, ERROR>) -> Result<(), SendError>
+    where
+        OUTPUT: serde::Serialize,
+        ERROR: serde::Serialize,
+    {
+        self.take(id)
+            .ok_or_else(|| SendError::NotPending { id: id.to_string() })?
+            .send(result)
+    }
+
+    /// Ids of the responses still waiting, in no particular order
+    pub fn ids(&self) -> Vec<String> {
+        let mut responses = self.responses.lock().expect("Failed to acquire lock on pending responses");
+        responses.retain(|_, response| !response.is_cancelled());
+        responses.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pending_responses_complete_and_prune() {
+        let pending = PendingResponses::new().with_max_pending(1);
+
+        let (response, receiver) = DeferredResponse::new();
+        let id = pending.park(response).unwrap();
+        let (second, _second_receiver) = DeferredResponse::new();
+        assert!(matches!(pending.park(second), Err(PendingError::Full { max: 1 })));
+
+        pending.complete(&id, Ok::<_, String>("approved")).unwrap();
+        assert_eq!(receiver.await.unwrap(), Ok(serde_json::json!("approved")));
+        assert!(matches!(
+            pending.complete(&id, Ok::<_, String>("again")),
+            Err(SendError::NotPending { .. })
+        ));
 
-        // Send JSON followed by newline
-        self.send_stream
-            .write_all(response_json.as_bytes())
-            .await
-            .map_err(|e| SendError::SendError {
-                source: eyre::Error::from(e),
-            })?;
-        self.send_stream
-            .write_all(b"\n")
-            .await
-            .map_err(|e| SendError::SendError {
-                source: eyre::Error::from(e),
-            })?;
-
-        Ok(())
+        // A peer that went away frees its slot
+        let (response, receiver) = DeferredResponse::new();
+        pending.park(response).unwrap();
+        drop(receiver);
+        assert!(pending.ids().is_empty());
     }
 }
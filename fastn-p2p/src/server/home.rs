@@ -0,0 +1,310 @@
+//! Versioned FASTN_HOME layout and the migrations between versions
+//!
+//! The version of the on-disk layout is kept in `FASTN_HOME/home_version`; a
+//! home without the file predates versioning and is version 0. The daemon
+//! calls [`migrate`] on start, and `fastn-p2p migrate --dry-run` shows what
+//! it would do with [`plan`]. Before the first file moves, `identities/` and
+//! the state database are copied to `backups/home-v<from>-<timestamp>/`.
+//!
+//! `MIGRATIONS[n]` takes the layout from version `n` to `n + 1`. A migration
+//! plans [`Step`]s by looking at the disk, so planning never changes
+//! anything and a dry run shows exactly what would move. The version file is
+//! written after each migration, so an interrupted run picks up where it
+//! stopped.
+//!
+//! Versions:
+//! 1. Identities live in `identities/<alias>/identity.private-key` (or
+//!    `.id52`). Keys saved flat as `identities/<alias>.private-key` are moved
+//!    there; before this, identity discovery silently skipped them. The flat
+//!    `<alias>.config.json` stays for tools that still read it.
+//!
+//! The state database has its own schema versions, see [`super::state`].
+
+use std::path::{Path, PathBuf};
+
+/// Layout version file inside FASTN_HOME
+pub const HOME_VERSION_FILE: &str = "home_version";
+
+/// Layout version this build reads and writes
+pub const HOME_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Backups of migrated homes, inside FASTN_HOME
+pub const BACKUP_DIR: &str = "backups";
+
+/// `MIGRATIONS[n]` takes the layout from version `n` to `n + 1`
+const MIGRATIONS: &[Migration] = &[Migration {
+    description: "move flat identity keys into per-identity directories",
+    plan: plan_identity_directories,
+}];
+
+struct Migration {
+    description: &'static str,
+    plan: fn(&Path) -> std::io::Result<Vec<Step>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HomeError {
+    #[error("{} was written by a newer fastn-p2p (layout version {found}, this build knows up to {supported}); upgrade fastn-p2p", path.display())]
+    TooNew { path: PathBuf, found: u32, supported: u32 },
+
+    #[error("Invalid layout version '{content}' in {}", path.display())]
+    InvalidVersion { path: PathBuf, content: String },
+
+    #[error("Migration to layout version {version} failed: {source}")]
+    Migration {
+        version: u32,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("IO error: {source}")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+
+    #[error("Migration task failed: {source}")]
+    Task {
+        #[source]
+        source: tokio::task::JoinError,
+    },
+}
+
+/// One change a migration makes
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Step {
+    /// Rename `from` to `to`, creating `to`'s parent
+    Move { from: PathBuf, to: PathBuf },
+    /// Something left alone that needs a person to look at it
+    Skip { path: PathBuf, reason: String },
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Move { from, to } => write!(f, "move {} to {}", from.display(), to.display()),
+            Step::Skip { path, reason } => write!(f, "leave {}: {}", path.display(), reason),
+        }
+    }
+}
+
+/// The steps of one migration
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PlannedMigration {
+    /// Version the migration brings the layout to
+    pub version: u32,
+    pub description: &'static str,
+    pub steps: Vec<Step>,
+}
+
+/// What [`migrate`] does, or did, to a home
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MigrationPlan {
+    pub from: u32,
+    pub to: u32,
+    pub migrations: Vec<PlannedMigration>,
+    /// Where the home was copied before migrating; `None` for plans and when nothing moved
+    pub backup: Option<PathBuf>,
+}
+
+impl MigrationPlan {
+    pub fn is_up_to_date(&self) -> bool {
+        self.from == self.to
+    }
+}
+
+/// Layout version of `fastn_home`; 0 without a version file
+pub async fn version(fastn_home: &Path) -> Result<u32, HomeError> {
+    let path = fastn_home.join(HOME_VERSION_FILE);
+    let content = match tokio::fs::read_to_string(&path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let found = content
+        .trim()
+        .parse()
+        .map_err(|_| HomeError::InvalidVersion { path: path.clone(), content: content.trim().to_string() })?;
+    if found > HOME_VERSION {
+        return Err(HomeError::TooNew { path, found, supported: HOME_VERSION });
+    }
+    Ok(found)
+}
+
+/// Mark a new, empty `fastn_home` as the current layout
+pub(crate) async fn init(fastn_home: &Path) -> Result<(), HomeError> {
+    tokio::fs::write(fastn_home.join(HOME_VERSION_FILE), format!("{}\n", HOME_VERSION)).await?;
+    Ok(())
+}
+
+/// What [`migrate`] would do, without changing anything
+///
+/// Every pending migration is planned against the layout as it is now.
+pub async fn plan(fastn_home: &Path) -> Result<MigrationPlan, HomeError> {
+    let from = version(fastn_home).await?;
+    let fastn_home = fastn_home.to_path_buf();
+    let migrations = tokio::task::spawn_blocking(move || {
+        (from..HOME_VERSION)
+            .map(|version| planned(&fastn_home, version))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|source| HomeError::Task { source })??;
+    Ok(MigrationPlan { from, to: HOME_VERSION, migrations, backup: None })
+}
+
+/// Bring `fastn_home` up to [`HOME_VERSION`], backing it up first
+///
+/// Callers hold the daemon's singleton lock, so nothing else uses the home.
+pub async fn migrate(fastn_home: &Path) -> Result<MigrationPlan, HomeError> {
+    let from = version(fastn_home).await?;
+    if from == HOME_VERSION {
+        return Ok(MigrationPlan { from, to: HOME_VERSION, migrations: Vec::new(), backup: None });
+    }
+
+    let fastn_home = fastn_home.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut backup_dir = None;
+        let mut migrations = Vec::new();
+        for version in from..HOME_VERSION {
+            let migration = planned(&fastn_home, version)?;
+            for step in &migration.steps {
+                // Homes with nothing to move, e.g. new ones, get no backup
+                if backup_dir.is_none() && matches!(step, Step::Move { .. }) {
+                    backup_dir = Some(backup(&fastn_home, from)?);
+                }
+                apply(step).map_err(|source| HomeError::Migration { version: version + 1, source })?;
+            }
+            std::fs::write(fastn_home.join(HOME_VERSION_FILE), format!("{}\n", version + 1))?;
+            tracing::info!("Migrated {} to layout version {}", fastn_home.display(), version + 1);
+            migrations.push(migration);
+        }
+        Ok(MigrationPlan { from, to: HOME_VERSION, migrations, backup: backup_dir })
+    })
+    .await
+    .map_err(|source| HomeError::Task { source })?
+}
+
+fn planned(fastn_home: &Path, version: u32) -> Result<PlannedMigration, HomeError> {
+    let migration = &MIGRATIONS[version as usize];
+    let steps = (migration.plan)(fastn_home).map_err(|source| HomeError::Migration { version: version + 1, source })?;
+    Ok(PlannedMigration { version: version + 1, description: migration.description, steps })
+}
+
+fn apply(step: &Step) -> std::io::Result<()> {
+    match step {
+        Step::Move { from, to } => {
+            if let Some(parent) = to.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(from, to)
+        }
+        Step::Skip { path, reason } => {
+            tracing::warn!("Migration left {} alone: {}", path.display(), reason);
+            Ok(())
+        }
+    }
+}
+
+/// Copy `identities/` and the state database to a new backup directory
+fn backup(fastn_home: &Path, from: u32) -> std::io::Result<PathBuf> {
+    let backup = fastn_home
+        .join(BACKUP_DIR)
+        .join(format!("home-v{}-{}", from, chrono::Utc::now().format("%Y%m%dT%H%M%SZ")));
+    std::fs::create_dir_all(&backup)?;
+    let identities = fastn_home.join("identities");
+    if identities.exists() {
+        copy_dir(&identities, &backup.join("identities"))?;
+    }
+    let state_db = fastn_home.join(super::state::STATE_DB_FILE);
+    if state_db.exists() {
+        std::fs::copy(&state_db, backup.join(super::state::STATE_DB_FILE))?;
+    }
+    Ok(backup)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        match entry.file_type()?.is_dir() {
+            true => copy_dir(&entry.path(), &target)?,
+            false => {
+                std::fs::copy(entry.path(), target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Version 1: `identities/<alias>.private-key` (or `.id52`) to `identities/<alias>/identity.*`
+fn plan_identity_directories(fastn_home: &Path) -> std::io::Result<Vec<Step>> {
+    let identities = fastn_home.join("identities");
+    let entries = match std::fs::read_dir(&identities) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut paths: Vec<PathBuf> = entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+    paths.sort();
+    let mut steps = Vec::new();
+    for from in paths.into_iter().filter(|path| path.is_file()) {
+        let Some(name) = from.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some((alias, extension)) = ["private-key", "id52"]
+            .into_iter()
+            .find_map(|extension| Some((name.strip_suffix(&format!(".{}", extension))?, extension)))
+        else {
+            continue;
+        };
+        let identity_dir = identities.join(alias);
+        let has_key = ["identity.private-key", "identity.id52"].iter().any(|key| identity_dir.join(key).exists());
+        match has_key {
+            true => steps.push(Step::Skip {
+                path: from.clone(),
+                reason: format!("{} already has a key", identity_dir.display()),
+            }),
+            false => steps.push(Step::Move {
+                to: identity_dir.join(format!("identity.{}", extension)),
+                from,
+            }),
+        }
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_migrate_moves_flat_identities_with_backup() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let identities = fastn_home.join("identities");
+        std::fs::create_dir_all(identities.join("bob")).unwrap();
+        std::fs::write(identities.join("alice.private-key"), "alice-key").unwrap();
+        std::fs::write(identities.join("bob.private-key"), "old-bob-key").unwrap();
+        std::fs::write(identities.join("bob").join("identity.private-key"), "bob-key").unwrap();
+
+        let plan = plan(&fastn_home).await.unwrap();
+        assert_eq!((plan.from, plan.to), (0, HOME_VERSION));
+        assert_eq!(plan.migrations[0].steps.len(), 2);
+        assert!(identities.join("alice.private-key").exists(), "a dry run changes nothing");
+
+        let migrated = migrate(&fastn_home).await.unwrap();
+        let backup = migrated.backup.unwrap();
+        assert!(backup.join("identities").join("alice.private-key").exists());
+        assert_eq!(std::fs::read_to_string(identities.join("alice").join("identity.private-key")).unwrap(), "alice-key");
+        assert!(identities.join("bob.private-key").exists(), "conflicting keys are left alone");
+        assert_eq!(version(&fastn_home).await.unwrap(), HOME_VERSION);
+        assert!(migrate(&fastn_home).await.unwrap().is_up_to_date());
+
+        std::fs::write(fastn_home.join(HOME_VERSION_FILE), format!("{}", HOME_VERSION + 1)).unwrap();
+        assert!(matches!(version(&fastn_home).await, Err(HomeError::TooNew { .. })));
+    }
+}
@@ -0,0 +1,368 @@
+//! Invites: first contact between identities that never exchanged keys
+//!
+//! 1. Alice makes an [`Invite`] with [`InviteRegistry::create`] (or `fastn-p2p
+//!    invite create`): her ID52, a one-time code and a
+//!    [`crate::capability::CapabilityToken`] for [`INVITE_PROTOCOL`], signed by
+//!    her key and valid for [`INVITE_TTL`]. She sends it to Bob any way she likes.
+//! 2. Bob accepts it with [`crate::client::Client::accept_invite`] (or
+//!    `fastn-p2p invite accept`). His client checks the signature and connects
+//!    presenting the token, so Alice's connection auth doesn't need to know him.
+//! 3. Alice's server redeems the code once and adds Bob to her address book
+//!    under the name he asked for; Bob adds Alice to his.
+//!
+//! Pending invites live in `invites.json` in the identity directory, so invites
+//! made by the CLI are seen by the running server:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::client::Client;
+//! # use fastn_p2p::server::invites::Invite;
+//! # async fn run(alice_key: fastn_p2p::SecretKey, bob_key: fastn_p2p::SecretKey, link: String) -> Result<(), Box<dyn std::error::Error>> {
+//! # let fastn_home = std::path::PathBuf::from(".fastn");
+//! // Alice
+//! fastn_p2p::listen(alice_key).with_invites(fastn_home, "alice").await?;
+//!
+//! // Bob
+//! let invite: Invite = link.parse()?;
+//! let accepted = Client::new(bob_key).accept_invite(&invite, "bob").await??;
+//! # Ok(())
+//! # }
+//! ```
+
+/// Protocol invitees call to redeem their code
+pub const INVITE_PROTOCOL: &str = "fastn-p2p-invite";
+
+/// Pending invites in the identity directory
+pub const INVITES_FILE: &str = "invites.json";
+
+/// How long an invite stays valid unless asked otherwise
+pub const INVITE_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Wrong codes tolerated before all pending invites are revoked
+pub const MAX_INVITE_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum InviteError {
+    #[error("Invalid invite '{invite}'")]
+    InvalidInvite { invite: String },
+
+    #[error("Invite is not usable: {source}")]
+    Capability {
+        #[from]
+        source: crate::capability::CapabilityError,
+    },
+
+    #[error("Invalid, expired or already used invite code")]
+    InvalidCode,
+
+    #[error("Invite registry error: {source}")]
+    Registry { source: serde_json::Error },
+
+    #[error("Address book error: {source}")]
+    State {
+        #[from]
+        source: crate::server::state::StateError,
+    },
+
+    #[error("IO error: {source}")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+}
+
+/// What an invitee needs: the signed token naming the identity, and the code
+#[derive(Clone, PartialEq)]
+pub struct Invite {
+    pub token: crate::capability::CapabilityToken,
+    pub code: String,
+}
+
+impl std::fmt::Debug for Invite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Invite")
+            .field("token", &self.token)
+            .field("code", &fastn_p2p_client::Sensitive(&self.code))
+            .finish()
+    }
+}
+
+impl Invite {
+    /// The inviting identity
+    pub fn identity(&self) -> fastn_id52::PublicKey {
+        self.token.issuer
+    }
+
+    /// Check the signature and expiry
+    pub fn verify(&self) -> Result<(), InviteError> {
+        self.token.verify(&self.token.issuer, &[self.token.issuer])?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Invite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fastn-p2p-invite:{}?code={}", self.token, self.code)
+    }
+}
+
+impl std::str::FromStr for Invite {
+    type Err = InviteError;
+
+    fn from_str(invite: &str) -> Result<Self, Self::Err> {
+        let invalid = || InviteError::InvalidInvite { invite: invite.to_string() };
+        let (token, code) = invite
+            .trim()
+            .strip_prefix("fastn-p2p-invite:")
+            .and_then(|rest| rest.split_once("?code="))
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            token: token.parse().map_err(|_| invalid())?,
+            code: code.to_string(),
+        })
+    }
+}
+
+/// Sent by the invitee on [`INVITE_PROTOCOL`]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AcceptInvite {
+    pub code: String,
+    /// Name the invitee wants in the inviter's address book
+    pub name: String,
+}
+
+impl std::fmt::Debug for AcceptInvite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcceptInvite")
+            .field("code", &fastn_p2p_client::Sensitive(&self.code))
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// The inviter's answer to [`AcceptInvite`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InviteAccepted {
+    /// Name the inviter goes by, for the invitee's address book
+    pub name: String,
+    /// Name the invitee got in the inviter's address book
+    pub saved_as: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+#[error("{message}")]
+pub struct InviteRefused {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct PendingInvite {
+    code: fastn_p2p_client::Sensitive<String>,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Outstanding invites of one identity
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct InviteRegistry {
+    #[serde(default)]
+    pending: Vec<PendingInvite>,
+    #[serde(default)]
+    failed_attempts: u32,
+}
+
+impl InviteRegistry {
+    /// Load the registry from `identity_dir`; a missing file is an empty registry
+    pub async fn load(identity_dir: &std::path::Path) -> Result<Self, InviteError> {
+        match tokio::fs::read(identity_dir.join(INVITES_FILE)).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|source| InviteError::Registry { source }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self, identity_dir: &std::path::Path) -> Result<(), InviteError> {
+        let json = serde_json::to_string_pretty(self).map_err(|source| InviteError::Registry { source })?;
+        tokio::fs::write(identity_dir.join(INVITES_FILE), json).await?;
+        Ok(())
+    }
+
+    /// Issue a single-use invite from `identity_key`, valid for `ttl`
+    pub fn create(&mut self, identity_key: &fastn_id52::SecretKey, ttl: std::time::Duration) -> Invite {
+        let grant = crate::capability::Grant::protocol(INVITE_PROTOCOL);
+        let token = crate::capability::CapabilityToken::mint(identity_key, None, vec![grant], ttl);
+        let code = crate::server::devices::random_code();
+
+        let now = chrono::Utc::now();
+        self.pending.retain(|p| p.expires_at > now);
+        self.pending.push(PendingInvite {
+            code: fastn_p2p_client::Sensitive(code.clone()),
+            expires_at: token.expires_at,
+        });
+        self.failed_attempts = 0;
+        Invite { token, code }
+    }
+
+    /// Use up `code`
+    ///
+    /// Too many wrong codes revoke every pending invite, so a code cannot be
+    /// guessed while it is valid.
+    pub fn redeem(&mut self, code: &str) -> Result<(), InviteError> {
+        let now = chrono::Utc::now();
+        self.pending.retain(|p| p.expires_at > now);
+
+        let code = code.trim().to_uppercase();
+        let Some(index) = self.pending.iter().position(|p| *p.code == code) else {
+            self.failed_attempts += 1;
+            if self.failed_attempts >= MAX_INVITE_ATTEMPTS {
+                self.pending.clear();
+            }
+            return Err(InviteError::InvalidCode);
+        };
+        self.pending.remove(index);
+        Ok(())
+    }
+
+    /// [`Self::redeem`] against the registry in `identity_dir`
+    ///
+    /// Loads, redeems and saves under [`crate::server::devices::lock_registry`],
+    /// so racing requests can't redeem one invite twice; failed attempts are
+    /// saved too, they count towards revoking the invites.
+    pub async fn redeem_in(identity_dir: &std::path::Path, code: &str) -> Result<(), InviteError> {
+        let _guard = crate::server::devices::lock_registry(identity_dir.join(INVITES_FILE)).await;
+        let mut registry = Self::load(identity_dir).await?;
+        let result = registry.redeem(code);
+        registry.save(identity_dir).await?;
+        result
+    }
+
+    /// Invites not used or expired yet
+    pub fn pending(&self) -> usize {
+        let now = chrono::Utc::now();
+        self.pending.iter().filter(|p| p.expires_at > now).count()
+    }
+}
+
+/// Add `peer` to the address book of `identity` as `name`
+///
+/// A name another peer already has gets the start of `peer`'s ID52 appended,
+/// so an invitee can't take over an existing contact. Returns the name used.
+pub async fn save_contact(
+    store: &crate::server::state::StateStore,
+    identity: &str,
+    name: &str,
+    peer: &fastn_id52::PublicKey,
+) -> Result<String, InviteError> {
+    let id52 = peer.id52();
+    let name = match name.trim() {
+        "" => format!("peer-{}", &id52[..6]),
+        name => name.to_string(),
+    };
+    let contacts = store.contacts(identity).await?;
+    let name = match contacts.iter().any(|c| c.name == name && c.peer != *peer) {
+        true => format!("{}-{}", name, &id52[..6]),
+        false => name,
+    };
+    store.set_contact(identity, &name, peer).await?;
+    Ok(name)
+}
+
+/// Where the server of `identity` keeps its invites and address book
+#[derive(Debug, Clone)]
+pub struct InviteConfig {
+    pub fastn_home: std::path::PathBuf,
+    pub identity: String,
+}
+
+/// Serve an [`AcceptInvite`] from `peer`
+pub(crate) async fn accept(
+    config: std::sync::Arc<InviteConfig>,
+    request: AcceptInvite,
+    peer: fastn_id52::PublicKey,
+) -> Result<InviteAccepted, InviteRefused> {
+    let result = redeem(&config, &request, &peer).await;
+    match &result {
+        Ok(accepted) => tracing::info!("Invite accepted by {}, saved as '{}'", peer.id52(), accepted.saved_as),
+        Err(e) => tracing::warn!("Invite attempt from {} failed: {}", peer.id52(), e),
+    }
+    result.map_err(|e| InviteRefused { message: e.to_string() })
+}
+
+async fn redeem(
+    config: &InviteConfig,
+    request: &AcceptInvite,
+    peer: &fastn_id52::PublicKey,
+) -> Result<InviteAccepted, InviteError> {
+    let identity_dir = config.fastn_home.join("identities").join(&config.identity);
+    InviteRegistry::redeem_in(&identity_dir, &request.code).await?;
+
+    let store = crate::server::state::StateStore::open(&config.fastn_home).await?;
+    let saved_as = save_contact(&store, &config.identity, &request.name, peer).await?;
+    Ok(InviteAccepted {
+        name: config.identity.clone(),
+        saved_as,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invite_is_single_use() {
+        let alice = fastn_id52::SecretKey::generate();
+        let mut registry = InviteRegistry::default();
+
+        let invite = registry.create(&alice, INVITE_TTL);
+        assert_eq!(invite.identity(), alice.public_key());
+        assert_eq!(registry.pending(), 1);
+        registry.redeem(&invite.code.to_lowercase()).unwrap();
+        assert!(matches!(registry.redeem(&invite.code), Err(InviteError::InvalidCode)));
+        assert_eq!(registry.pending(), 0);
+    }
+
+    #[test]
+    fn test_wrong_codes_revoke_pending_invites() {
+        let mut registry = InviteRegistry::default();
+        let invite = registry.create(&fastn_id52::SecretKey::generate(), INVITE_TTL);
+
+        for _ in 0..MAX_INVITE_ATTEMPTS {
+            assert!(registry.redeem("AAAA-AAAA").is_err());
+        }
+        assert!(registry.redeem(&invite.code).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_racing_redeems_use_an_invite_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let identity_dir = temp.path().to_path_buf();
+        let mut registry = InviteRegistry::default();
+        let invite = registry.create(&fastn_id52::SecretKey::generate(), INVITE_TTL);
+        registry.save(&identity_dir).await.unwrap();
+
+        let redeems: Vec<_> = (0..8)
+            .map(|_| {
+                let identity_dir = identity_dir.clone();
+                let code = invite.code.clone();
+                tokio::spawn(async move { InviteRegistry::redeem_in(&identity_dir, &code).await })
+            })
+            .collect();
+        let mut redeemed = 0;
+        for redeem in redeems {
+            redeemed += redeem.await.unwrap().is_ok() as usize;
+        }
+        assert_eq!(redeemed, 1);
+        assert_eq!(InviteRegistry::load(&identity_dir).await.unwrap().pending(), 0);
+    }
+
+    #[test]
+    fn test_invite_round_trip() {
+        let alice = fastn_id52::SecretKey::generate();
+        let invite = InviteRegistry::default().create(&alice, INVITE_TTL);
+        let parsed: Invite = invite.to_string().parse().unwrap();
+        assert_eq!(parsed, invite);
+        parsed.verify().unwrap();
+
+        let expired = InviteRegistry::default().create(&alice, std::time::Duration::ZERO);
+        assert!(matches!(expired.verify(), Err(InviteError::Capability { .. })));
+        assert!("fastn-p2p-invite:nope".parse::<Invite>().is_err());
+    }
+}
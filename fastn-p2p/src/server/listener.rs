@@ -1,180 +1,29 @@
-async fn handle_connection<P>(
-    conn: iroh::endpoint::Incoming,
-    expected_protocols: Vec<P>,
-    tx: tokio::sync::mpsc::Sender<eyre::Result<fastn_p2p::Request<P>>>,
-) -> eyre::Result<()>
-where
-    P: serde::Serialize
-        + for<'de> serde::Deserialize<'de>
-        + Clone
-        + PartialEq
-        + std::fmt::Debug
-        + Send
-        + Sync
-        + 'static,
-{
-    // Wait for the connection to be established
-    let connection = conn.await?;
-
-    // Get peer's ID52
-    let peer_key = fastn_net::get_remote_id52(&connection).await?;
-
-    tracing::debug!("Connection established with peer: {}", peer_key.id52());
-
-    // Convert user protocols to fastn_net::Protocol::Generic for network transmission
-    let net_protocols: Vec<fastn_net::Protocol> = expected_protocols
-        .iter()
-        .map(|p| {
-            let json_value = serde_json::to_value(p).expect("Protocol should be serializable");
-            fastn_net::Protocol::Generic(json_value)
-        })
-        .collect();
-
-    // Accept bi-directional streams on this connection using fastn_net utilities
-    loop {
-        let (net_protocol, send, recv) =
-            match fastn_net::accept_bi(&connection, &net_protocols).await {
-                Ok(result) => result,
-                Err(e) => {
-                    tracing::warn!("Failed to accept bi-directional stream: {e}");
-                    break;
-                }
-            };
-
-        // Convert back from fastn_net::Protocol::Generic to user protocol
-        let user_protocol = match net_protocol {
-            fastn_net::Protocol::Generic(json_value) => {
-                match serde_json::from_value::<P>(json_value) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        tracing::warn!("Failed to deserialize user protocol: {e}");
-                        continue;
-                    }
-                }
-            }
-            other => {
-                tracing::warn!("Expected Generic protocol, got: {:?}", other);
-                continue;
-            }
-        };
-
-        tracing::debug!("Accepted {user_protocol:?} connection from peer: {peer_key}");
-
-        // Create PeerRequest and send it through the channel
-        let peer_request =
-            fastn_p2p::server::request::Request::new(peer_key, user_protocol, send, recv);
-
-        if (tx.send(Ok(peer_request)).await).is_err() {
-            // Channel receiver has been dropped, stop processing
-            tracing::debug!("Channel receiver dropped, stopping connection handling");
-            break;
-        }
-    }
-
-    Ok(())
+//! Deprecated stream-of-requests listener, a thin shim over [`crate::server::ServerBuilder`]
+//!
+//! Requests for `expected` protocols are served by a builder server and
+//! handed out as [`crate::server::Request`]s, so this API shares the builder's
+//! handshake, request envelope, limits and auth hooks. New code should use
+//! [`crate::listen`] with `handle_requests` instead.
+
+/// Cleans up a listener once its stream is gone
+struct ListenerGuard {
+    public_key: fastn_id52::PublicKey,
+    token: tokio_util::sync::CancellationToken,
 }
 
-fn listen_generic<P>(
-    secret_key: fastn_id52::SecretKey,
-    expected: &[P],
-) -> Result<
-    impl futures_core::stream::Stream<Item = eyre::Result<fastn_p2p::Request<P>>>,
-    fastn_p2p::ListenerAlreadyActiveError,
->
-where
-    P: serde::Serialize
-        + for<'de> serde::Deserialize<'de>
-        + Clone
-        + PartialEq
-        + std::fmt::Debug
-        + Send
-        + Sync
-        + 'static,
-{
-    let public_key = secret_key.public_key();
-
-    // Check if already listening and register this endpoint
-    let endpoint_cancellation_token = fastn_p2p::server::management::register_listener(public_key)?;
-
-    let expected = expected.to_vec(); // Clone for move into async block
-
-    Ok(async_stream::try_stream! {
-        println!("🔧 DEBUG: About to call fastn_net::get_endpoint");
-        let endpoint = fastn_net::get_endpoint(secret_key.clone()).await?;
-        println!("🔧 DEBUG: Successfully created endpoint");
-
-        // Channel to receive PeerRequests from spawned connection handlers
-        // Using 1-capacity channel for minimal buffering with backpressure
-        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
-        println!("🔧 DEBUG: Created channel");
-
-        // Spawn connection acceptor task
-        let acceptor_tx = tx.clone();
-        let acceptor_endpoint_cancellation = endpoint_cancellation_token.clone();
-        let acceptor_expected = expected.clone();
-        let acceptor_public_key = public_key;
-
-        crate::spawn(async move {
-            println!("🔧 DEBUG: Started connection acceptor task");
-            loop {
-                println!("🔧 DEBUG: Waiting for endpoint.accept()");
-                tokio::select! {
-                    conn_result = endpoint.accept() => {
-                        println!("🔧 DEBUG: endpoint.accept() returned");
-                        let conn = match conn_result {
-                            Some(conn) => conn,
-                            None => {
-                                tracing::debug!("Endpoint {public_key} closed");
-                                break;
-                            }
-                        };
-
-                        // Spawn task to handle this specific connection
-                        let handler_tx = acceptor_tx.clone();
-                        let handler_expected = acceptor_expected.clone();
-                        // Use global spawn helper
-                        crate::spawn(async move {
-                            if let Err(e) = handle_connection::<P>(conn, handler_expected, handler_tx).await {
-                                tracing::warn!("Connection handling failed: {e}");
-                            }
-                        });
-                    }
-
-                    // Handle global graceful shutdown
-                    _ = crate::cancelled() => {
-                        tracing::debug!("Global shutdown: stopping listener for endpoint {public_key}");
-                        break;
-                    }
-
-                    // Handle endpoint-specific cancellation
-                    _ = acceptor_endpoint_cancellation.cancelled() => {
-                        tracing::debug!("Endpoint-specific shutdown: stopping listener for endpoint {public_key}");
-                        break;
-                    }
-                }
-            }
-
-            // Clean up: remove from global registry when task ends
-            fastn_p2p::server::management::unregister_listener(&acceptor_public_key);
-        });
-
-        // Stream PeerRequests from the channel
-        println!("🔧 DEBUG: About to start streaming from channel");
-        while let Some(peer_request_result) = rx.recv().await {
-            println!("🔧 DEBUG: Received peer request from channel");
-            yield peer_request_result?;
-        }
-        println!("🔧 DEBUG: Channel stream ended");
-
-        // Clean up: ensure removal from registry when stream ends
-        fastn_p2p::server::management::unregister_listener(&public_key);
-    })
+impl Drop for ListenerGuard {
+    fn drop(&mut self) {
+        // Stops the server, see the task spawned in `listen`
+        self.token.cancel();
+        fastn_p2p::server::management::unregister_listener(&self.public_key);
+    }
 }
 
 /// Start listening for P2P requests using global graceful shutdown
 ///
-/// This is the main function end users should use. It automatically uses
-/// the global graceful shutdown coordinator.
+/// Yields one [`fastn_p2p::Request`] per request for any of the `expected`
+/// protocols. The listener stops when the stream is dropped, on
+/// [`fastn_p2p::stop_listening`] or on graceful shutdown.
 ///
 /// # Example
 ///
@@ -183,13 +32,12 @@ where
 ///
 /// async fn server_example() -> Result<(), Box<dyn std::error::Error>> {
 ///     let mut stream = fastn_p2p::listen!(secret_key, &protocols)?;
-///     let mut stream = std::pin::pin!(stream);
-///     
+///
 ///     while let Some(request) = stream.next().await {
 ///         let request = request?;
 ///         match request.protocol {
-///             fastn_p2p::Protocol::Ping => {
-///                 // Handle ping requests...
+///             MyProtocol::Echo => {
+///                 // Handle echo requests...
 ///             }
 ///             _ => { /* other protocols */ }
 ///         }
@@ -197,6 +45,7 @@ where
 ///     Ok(())
 /// }
 /// ```
+#[deprecated(note = "use fastn_p2p::listen(secret_key).handle_requests(protocol, handler) instead")]
 pub fn listen<P>(
     secret_key: fastn_id52::SecretKey,
     expected: &[P],
@@ -214,5 +63,46 @@ where
         + Sync
         + 'static,
 {
-    listen_generic(secret_key, expected)
+    let public_key = secret_key.public_key();
+
+    // Check if already listening and register this endpoint
+    let token = fastn_p2p::server::management::register_listener(public_key)?;
+    let guard = ListenerGuard { public_key, token: token.clone() };
+
+    // 1-capacity channel for minimal buffering with backpressure
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    let mut builder = fastn_p2p::server::builder_listen(secret_key);
+    let mut registered: Vec<&P> = Vec::new();
+    for protocol in expected {
+        if !registered.contains(&protocol) {
+            builder = builder.handle_requests_on_channel(protocol.clone(), tx.clone());
+            registered.push(protocol);
+        }
+    }
+    let server = builder.start().expect("Protocols are deduplicated above");
+
+    let stop_token = token.clone();
+    crate::spawn(async move {
+        tokio::select! {
+            _ = stop_token.cancelled() => {}
+            _ = crate::draining() => {}
+        }
+        tracing::debug!("Stopping listener for endpoint {public_key}");
+        server.stop();
+    });
+
+    Ok(async_stream::stream! {
+        let _guard = guard;
+        loop {
+            let request = tokio::select! {
+                request = rx.recv() => request,
+                _ = token.cancelled() => None,
+                _ = crate::draining() => None,
+            };
+            match request {
+                Some(request) => yield Ok::<_, eyre::Error>(request),
+                None => break,
+            }
+        }
+    })
 }
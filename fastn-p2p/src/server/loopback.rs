@@ -0,0 +1,274 @@
+//! Calls between servers of the same process, without the network
+//!
+//! A process serving several identities, like a `serve_all` host, would
+//! otherwise send a call from one of them to another out through iroh and
+//! back in. Every running server of the process registers here, and a
+//! [`crate::client::Client`] made
+//! [`with_loopback`](crate::client::Client::with_loopback) hands calls to a
+//! registered target straight to its request handlers. On the way they pass
+//! everything a call over the network does: bans, the connection and stream
+//! auth hooks, capability tokens, middleware, request timeouts and the
+//! response size limit. The reply's path is [`crate::ping::PathType::Local`].
+//!
+//! Calls that need a connection still take the network: signed requests,
+//! attachments, calls with a path preference and `connect()`. Loopback calls
+//! open no peer session and don't fire the peer connection hooks.
+//!
+//! The registry is per process. Identities served by another process of the
+//! host, like the daemon calling an identity a `serve_all` process serves,
+//! are reached over a Unix socket instead: `serve_all` opens one per
+//! identity in `FASTN_HOME/loopback/` ([`socket_path`]), and a client made
+//! [`with_loopback_in`](crate::client::Client::with_loopback_in) that home
+//! hands calls to it. The serving process answers them like in-process ones.
+
+pub(crate) use crate::server::builder::LocalServer;
+
+static SERVERS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<fastn_id52::PublicKey, std::sync::Arc<LocalServer>>>,
+> = std::sync::LazyLock::new(Default::default);
+
+fn servers() -> std::sync::MutexGuard<'static, std::collections::HashMap<fastn_id52::PublicKey, std::sync::Arc<LocalServer>>> {
+    SERVERS.lock().expect("Failed to acquire lock on loopback servers")
+}
+
+/// Keeps a server reachable in-process until dropped
+pub(crate) struct Registration {
+    identity: fastn_id52::PublicKey,
+    server: std::sync::Arc<LocalServer>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut servers = servers();
+        // A newer server for the same identity may have taken the slot
+        if servers.get(&self.identity).is_some_and(|server| std::sync::Arc::ptr_eq(server, &self.server)) {
+            servers.remove(&self.identity);
+        }
+    }
+}
+
+/// Make `server`, listening as `identity`, answer loopback calls
+pub(crate) fn register(identity: fastn_id52::PublicKey, server: LocalServer) -> Registration {
+    let server = std::sync::Arc::new(server);
+    servers().insert(identity, server.clone());
+    tracing::debug!("Serving loopback calls to {}", identity.id52());
+    Registration { identity, server }
+}
+
+/// The server of this process listening as `identity`, if any
+pub(crate) fn server(identity: &fastn_id52::PublicKey) -> Option<std::sync::Arc<LocalServer>> {
+    servers().get(identity).cloned()
+}
+
+/// Whether a server of this process listens as `identity`
+pub fn serves(identity: &fastn_id52::PublicKey) -> bool {
+    servers().contains_key(identity)
+}
+
+/// Under FASTN_HOME, the sockets of identities served by a process of this host
+pub const SOCKETS_DIR: &str = "loopback";
+
+/// Socket on which the process serving `identity` from `fastn_home` takes calls
+pub fn socket_path(fastn_home: &std::path::Path, identity: &fastn_id52::PublicKey) -> std::path::PathBuf {
+    fastn_home.join(SOCKETS_DIR).join(format!("{}.sock", identity.id52()))
+}
+
+/// Longest call or answer line on a loopback socket
+const MAX_LINE: u64 = crate::wire::DEFAULT_MAX_RESPONSE_SIZE as u64;
+
+/// One call handed to another process of the host, as a JSON line
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HostCall {
+    from: fastn_id52::PublicKey,
+    target: fastn_id52::PublicKey,
+    hello: crate::handshake::HelloMetadata,
+    protocol: serde_json::Value,
+    data: serde_json::Value,
+    #[serde(default)]
+    metadata: std::collections::BTreeMap<String, String>,
+    #[serde(default)]
+    deadline_ms: Option<u64>,
+}
+
+/// What a call handed to another process got: the result and, for answers, their max age
+pub(crate) type HostResult =
+    Result<(Result<serde_json::Value, serde_json::Value>, Option<std::time::Duration>), crate::client::CallError>;
+
+/// Its answer, as a JSON line
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum HostReply {
+    Ok {
+        value: serde_json::Value,
+        /// Seconds clients may cache it, see [`crate::wire::MAX_AGE`]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_age: Option<u64>,
+    },
+    Err { value: serde_json::Value },
+    Denied { denial: crate::handshake::AuthDenial },
+    NotAccepted { protocol: serde_json::Value },
+    Failed { message: String },
+}
+
+impl HostReply {
+    fn of(
+        result: Result<Result<serde_json::Value, serde_json::Value>, crate::client::CallError>,
+        max_age: Option<std::time::Duration>,
+    ) -> Self {
+        match result {
+            Ok(Ok(value)) => HostReply::Ok { value, max_age: max_age.map(|max_age| max_age.as_secs()) },
+            Ok(Err(value)) => HostReply::Err { value },
+            Err(crate::client::CallError::Denied { code, message }) => HostReply::Denied {
+                denial: crate::handshake::AuthDenial { code, message, retry_after_ms: None },
+            },
+            Err(crate::client::CallError::Throttled { retry_after, message }) => HostReply::Denied {
+                denial: crate::handshake::AuthDenial {
+                    code: "throttled".to_string(),
+                    message,
+                    retry_after_ms: Some(retry_after.as_millis() as u64),
+                },
+            },
+            Err(crate::client::CallError::ProtocolNotAccepted { protocol }) => HostReply::NotAccepted { protocol },
+            Err(e) => HostReply::Failed { message: e.to_string() },
+        }
+    }
+
+    fn into_result(self) -> HostResult {
+        match self {
+            HostReply::Ok { value, max_age } => Ok((Ok(value), max_age.map(std::time::Duration::from_secs))),
+            HostReply::Err { value } => Ok((Err(value), None)),
+            HostReply::Denied { denial } => Err(crate::client::CallError::from_denial(denial)),
+            HostReply::NotAccepted { protocol } => Err(crate::client::CallError::ProtocolNotAccepted { protocol }),
+            HostReply::Failed { message } => Err(crate::client::CallError::Protocol { message }),
+        }
+    }
+}
+
+/// Keeps an identity's socket open until dropped, then removes it
+pub(crate) struct HostSocket {
+    path: std::path::PathBuf,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for HostSocket {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Take calls to `identity` from other processes of the host, like the daemon
+///
+/// Each call is answered by the server of this process registered for
+/// `identity`, through [`LocalServer::call`], so it passes the same checks
+/// as an in-process one. The socket is only open to the user owning
+/// `fastn_home`, who can send as any of its identities through the daemon
+/// anyway, so the caller's claim about who it sends as is believed.
+pub(crate) fn serve_host(fastn_home: &std::path::Path, identity: fastn_id52::PublicKey) -> std::io::Result<HostSocket> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = socket_path(fastn_home, &identity);
+    std::fs::create_dir_all(fastn_home.join(SOCKETS_DIR))?;
+    // Left behind by a process that didn't exit cleanly
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    let task = crate::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Loopback socket of {} failed: {}", identity.id52(), e);
+                    return;
+                }
+            };
+            crate::spawn(async move {
+                if let Err(e) = answer_host_call(stream, identity).await {
+                    tracing::debug!("Loopback call to {} failed: {}", identity.id52(), e);
+                }
+            });
+        }
+    });
+    tracing::debug!("Taking loopback calls to {} on {}", identity.id52(), path.display());
+    Ok(HostSocket { path, task })
+}
+
+async fn answer_host_call(
+    stream: tokio::net::UnixStream,
+    identity: fastn_id52::PublicKey,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    tokio::io::BufReader::new(reader.take(MAX_LINE)).read_line(&mut line).await?;
+    let host_call: HostCall = serde_json::from_str(&line)?;
+    let reply = match server(&identity).filter(|_| host_call.target == identity) {
+        Some(server) => {
+            let mut call = crate::interceptor::OutgoingCall::new(
+                host_call.target, host_call.protocol, host_call.data, host_call.metadata, false,
+            );
+            call.deadline = crate::wire::deadline_from_ms(host_call.deadline_ms);
+            let max_age = server.max_age(call.protocol());
+            HostReply::of(server.call(host_call.from, &host_call.hello, call).await, max_age)
+        }
+        None => HostReply::Failed { message: format!("{} is not served here", host_call.target.id52()) },
+    };
+    let mut json = serde_json::to_string(&reply)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+    Ok(())
+}
+
+/// Hand `call` to the process serving its target from `fastn_home`, if one runs
+///
+/// `None` when no process of the host serves the target, and the call has to
+/// take the network.
+pub(crate) async fn call_host(
+    fastn_home: &std::path::Path,
+    from: fastn_id52::PublicKey,
+    hello: &crate::handshake::HelloMetadata,
+    call: &crate::interceptor::OutgoingCall,
+) -> Option<HostResult> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    let path = socket_path(fastn_home, call.target());
+    let stream = match tokio::net::UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            if e.kind() == std::io::ErrorKind::ConnectionRefused {
+                // Nobody listens any more, so later calls don't try it again
+                let _ = std::fs::remove_file(&path);
+            }
+            return None;
+        }
+    };
+    let host_call = HostCall {
+        from,
+        target: *call.target(),
+        hello: hello.clone(),
+        protocol: call.protocol().clone(),
+        data: call.data.clone(),
+        metadata: call.metadata.clone(),
+        deadline_ms: crate::wire::deadline_to_ms(call.deadline),
+    };
+    let exchange = async {
+        let (reader, mut writer) = stream.into_split();
+        let mut json = serde_json::to_string(&host_call).map_err(|source| crate::client::CallError::Serialization { source })?;
+        json.push('\n');
+        writer.write_all(json.as_bytes()).await.map_err(|source| crate::client::CallError::Io { source })?;
+        let mut line = String::new();
+        tokio::io::BufReader::new(reader.take(MAX_LINE))
+            .read_line(&mut line)
+            .await
+            .map_err(|source| crate::client::CallError::Io { source })?;
+        let reply: HostReply = serde_json::from_str(&line).map_err(|source| crate::client::CallError::Deserialization { source })?;
+        reply.into_result()
+    };
+    Some(exchange.await)
+}
+
+/// Whether a process of the host serves `identity` from `fastn_home`
+pub(crate) fn host_serves(fastn_home: &std::path::Path, identity: &fastn_id52::PublicKey) -> bool {
+    socket_path(fastn_home, identity).exists()
+}
@@ -99,6 +99,149 @@ pub fn active_listeners() -> Vec<fastn_id52::PublicKey> {
     listeners.keys().copied().collect()
 }
 
+/// Per-binding listeners started by `serve_all`, keyed by identity, protocol and bind alias
+static ACTIVE_BINDINGS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<BindingKey, ListenerHandle>>,
+> = std::sync::LazyLock::new(|| std::sync::Mutex::new(std::collections::HashMap::new()));
+
+/// One protocol binding of one identity
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct BindingKey {
+    pub identity: fastn_id52::PublicKey,
+    pub protocol: String,
+    pub bind_alias: String,
+}
+
+impl std::fmt::Display for BindingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} ({})", self.protocol, self.bind_alias, self.identity)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerState {
+    Running,
+    Stopped,
+}
+
+/// Error when starting a binding listener that is already registered
+#[derive(Debug, thiserror::Error)]
+#[error("Listener already active for binding {binding}")]
+pub struct BindingAlreadyActiveError {
+    pub binding: Box<BindingKey>,
+}
+
+/// Error when looking up a binding listener that is not registered
+#[derive(Debug, thiserror::Error)]
+#[error("No listener found for binding {binding}")]
+pub struct BindingNotFoundError {
+    pub binding: Box<BindingKey>,
+}
+
+/// Starts a binding's task; it must stop once the token is cancelled
+type StartBinding = std::sync::Arc<dyn Fn(tokio_util::sync::CancellationToken) + Send + Sync>;
+
+/// Control over one binding's listener: stop it, start it again, ask how it is
+///
+/// Clones share the same listener. Stopping keeps the binding registered so
+/// it can be restarted; [`remove_binding`] forgets it.
+#[derive(Clone)]
+pub struct ListenerHandle {
+    binding: BindingKey,
+    start: StartBinding,
+    token: std::sync::Arc<std::sync::Mutex<tokio_util::sync::CancellationToken>>,
+}
+
+impl ListenerHandle {
+    pub fn binding(&self) -> &BindingKey {
+        &self.binding
+    }
+
+    pub fn state(&self) -> ListenerState {
+        let token = self.token.lock().expect("Failed to acquire lock on listener token");
+        if token.is_cancelled() {
+            ListenerState::Stopped
+        } else {
+            ListenerState::Running
+        }
+    }
+
+    /// Stop accepting for this binding; other bindings of the identity keep running
+    pub fn stop(&self) {
+        tracing::info!("Stopping listener for binding {}", self.binding);
+        self.token.lock().expect("Failed to acquire lock on listener token").cancel();
+    }
+
+    /// Stop the binding if it is running and start it again
+    pub fn restart(&self) {
+        let mut token = self.token.lock().expect("Failed to acquire lock on listener token");
+        token.cancel();
+        *token = tokio_util::sync::CancellationToken::new();
+        tracing::info!("Starting listener for binding {}", self.binding);
+        (self.start)(token.clone());
+    }
+}
+
+/// Register a binding and start it with `start`
+///
+/// `start` is called with a fresh token on every (re)start and must spawn the
+/// binding's task, which stops once the token is cancelled.
+pub fn start_binding<F>(binding: BindingKey, start: F) -> Result<ListenerHandle, BindingAlreadyActiveError>
+where
+    F: Fn(tokio_util::sync::CancellationToken) + Send + Sync + 'static,
+{
+    let mut bindings = ACTIVE_BINDINGS
+        .lock()
+        .expect("Failed to acquire lock on ACTIVE_BINDINGS");
+
+    if bindings.contains_key(&binding) {
+        return Err(BindingAlreadyActiveError { binding: Box::new(binding) });
+    }
+
+    let token = tokio_util::sync::CancellationToken::new();
+    let handle = ListenerHandle {
+        binding: binding.clone(),
+        start: std::sync::Arc::new(start),
+        token: std::sync::Arc::new(std::sync::Mutex::new(token.clone())),
+    };
+    tracing::info!("Starting listener for binding {}", binding);
+    (handle.start)(token);
+    bindings.insert(binding, handle.clone());
+    Ok(handle)
+}
+
+/// The handle of a registered binding
+pub fn binding_listener(binding: &BindingKey) -> Result<ListenerHandle, BindingNotFoundError> {
+    ACTIVE_BINDINGS
+        .lock()
+        .expect("Failed to acquire lock on ACTIVE_BINDINGS")
+        .get(binding)
+        .cloned()
+        .ok_or_else(|| BindingNotFoundError { binding: Box::new(binding.clone()) })
+}
+
+/// Every registered binding and whether it is running
+pub fn binding_listeners() -> Vec<(BindingKey, ListenerState)> {
+    ACTIVE_BINDINGS
+        .lock()
+        .expect("Failed to acquire lock on ACTIVE_BINDINGS")
+        .values()
+        .map(|handle| (handle.binding.clone(), handle.state()))
+        .collect()
+}
+
+/// Stop a binding and forget it
+pub fn remove_binding(binding: &BindingKey) -> Result<(), BindingNotFoundError> {
+    let handle = ACTIVE_BINDINGS
+        .lock()
+        .expect("Failed to acquire lock on ACTIVE_BINDINGS")
+        .remove(binding)
+        .ok_or_else(|| BindingNotFoundError { binding: Box::new(binding.clone()) })?;
+    handle.stop();
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,4 +296,33 @@ mod tests {
         assert_eq!(active_listener_count(), 0);
         assert!(active_listeners().is_empty());
     }
+
+    #[test]
+    fn test_binding_stop_and_restart() {
+        let binding = BindingKey {
+            identity: fastn_id52::SecretKey::generate().public_key(),
+            protocol: "mail.fastn.com".to_string(),
+            bind_alias: "default".to_string(),
+        };
+        let starts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = starts.clone();
+        let handle = start_binding(binding.clone(), move |_token| {
+            counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        })
+        .unwrap();
+        assert_eq!(handle.state(), ListenerState::Running);
+        assert!(start_binding(binding.clone(), |_token| {}).is_err());
+
+        handle.stop();
+        assert_eq!(binding_listener(&binding).unwrap().state(), ListenerState::Stopped);
+
+        handle.restart();
+        assert_eq!(handle.state(), ListenerState::Running);
+        assert_eq!(starts.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        remove_binding(&binding).unwrap();
+        assert_eq!(handle.state(), ListenerState::Stopped);
+        assert!(binding_listener(&binding).is_err());
+        assert!(!binding_listeners().iter().any(|(key, _)| key == &binding));
+    }
 }
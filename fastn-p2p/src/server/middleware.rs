@@ -0,0 +1,245 @@
+//! Middleware: cross-cutting logic wrapped around every handler
+//!
+//! A layer is an async fn taking the request and the rest of the chain, so
+//! logging, metrics, auth and tracing spans live in one place instead of in
+//! every handler:
+//!
+//! ```rust,no_run
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum Protocol { Echo }
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # #[error("echo failed")]
+//! # struct EchoError;
+//! # async fn echo_handler(input: String) -> Result<String, EchoError> { Ok(input) }
+//! # async fn run(key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//! fastn_p2p::listen(key)
+//!     .layer(|request, next| async move {
+//!         let started = std::time::Instant::now();
+//!         let result = next.run(request).await;
+//!         tracing::info!("{:?} took {:?}", result.is_ok(), started.elapsed());
+//!         result
+//!     })
+//!     .handle_requests(Protocol::Echo, echo_handler)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Layers run in registration order, the first one outermost. They see the
+//! request after signature verification and the stream auth hook, may rewrite
+//! its `data`, and may answer without calling `next` (e.g. to deny it). For
+//! streaming handlers `next` resolves to `Ok(Value::Null)` once the handler
+//! returns, and a layer that answers without calling `next` refuses the stream.
+//!
+//! The whole chain runs inside a `p2p_handler` tracing span and with
+//! [`LayerRequest::trace`] as the current [`fastn_net::TraceContext`], so calls
+//! made by the handler continue the caller's trace.
+
+use std::future::Future;
+use std::pin::Pin;
+
+/// What a layer (and the handler behind it) produces: serialized OUTPUT or ERROR
+pub type LayerResult = Result<serde_json::Value, serde_json::Value>;
+
+pub(crate) type BoxFuture = Pin<Box<dyn Future<Output = LayerResult> + Send>>;
+
+pub(crate) type Layer = std::sync::Arc<dyn Fn(LayerRequest, Next) -> BoxFuture + Send + Sync>;
+
+/// Wrap a typed layer fn for storage in a builder
+pub(crate) fn boxed<F, Fut>(layer: F) -> Layer
+where
+    F: Fn(LayerRequest, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = LayerResult> + Send + 'static,
+{
+    std::sync::Arc::new(move |request, next| Box::pin(layer(request, next)))
+}
+
+/// A request or stream as seen by middleware
+pub struct LayerRequest {
+    peer: fastn_id52::PublicKey,
+    protocol: serde_json::Value,
+    /// Request input (or initial stream data), may be rewritten by a layer
+    pub data: serde_json::Value,
+    /// Headers added by client interceptors, see [`crate::interceptor`]
+    pub metadata: std::collections::BTreeMap<String, String>,
+    pub(crate) verified: Option<crate::signing::VerifiedSender>,
+    is_stream: bool,
+    trace: fastn_net::TraceContext,
+    pub(crate) session: Option<crate::server::PeerSession>,
+    pub(crate) attachments: Vec<crate::attachment::IncomingAttachment>,
+}
+
+impl LayerRequest {
+    pub(crate) fn new(
+        peer: fastn_id52::PublicKey,
+        protocol: serde_json::Value,
+        data: serde_json::Value,
+        metadata: std::collections::BTreeMap<String, String>,
+        verified: Option<crate::signing::VerifiedSender>,
+        is_stream: bool,
+        trace: Option<fastn_net::TraceContext>,
+    ) -> Self {
+        // The handler is a new span in the caller's trace
+        let trace = match trace {
+            Some(incoming) => incoming.child(),
+            None => fastn_net::TraceContext::for_outgoing(),
+        };
+        Self {
+            peer,
+            protocol,
+            data,
+            metadata,
+            verified,
+            is_stream,
+            trace,
+            session: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// The peer that opened the stream
+    pub fn peer(&self) -> &fastn_id52::PublicKey {
+        &self.peer
+    }
+
+    /// Protocol the handler was registered for
+    pub fn protocol(&self) -> &serde_json::Value {
+        &self.protocol
+    }
+
+    /// Signer of a signed request, see [`crate::signing`]
+    pub fn verified_sender(&self) -> Option<&crate::signing::VerifiedSender> {
+        self.verified.as_ref()
+    }
+
+    /// Whether this is a streaming handler rather than request/response
+    pub fn is_stream(&self) -> bool {
+        self.is_stream
+    }
+
+    /// Trace context of the handler span; its parent is the caller's span
+    pub fn trace(&self) -> &fastn_net::TraceContext {
+        &self.trace
+    }
+
+    /// State kept for the connected peer, see [`crate::server::peer_sessions`]
+    pub fn session(&self) -> Option<&crate::server::PeerSession> {
+        self.session.as_ref()
+    }
+}
+
+/// The rest of the chain: the remaining layers, then the handler
+pub struct Next {
+    layers: Layers,
+    index: usize,
+    handler: Box<dyn FnOnce(LayerRequest) -> BoxFuture + Send>,
+}
+
+impl Next {
+    pub async fn run(self, request: LayerRequest) -> LayerResult {
+        let layers = self.layers.clone();
+        match layers.0.get(self.index) {
+            Some(layer) => {
+                let next = Next {
+                    layers: self.layers,
+                    index: self.index + 1,
+                    handler: self.handler,
+                };
+                layer(request, next).await
+            }
+            None => (self.handler)(request).await,
+        }
+    }
+}
+
+/// Layers registered on a builder, cheap to clone into every stream task
+#[derive(Clone, Default)]
+pub(crate) struct Layers(std::sync::Arc<Vec<Layer>>);
+
+impl Layers {
+    pub(crate) fn new(layers: Vec<Layer>) -> Self {
+        Self(std::sync::Arc::new(layers))
+    }
+
+    /// Run `request` through every layer and finally `handler`
+    pub(crate) async fn run<F, Fut>(&self, request: LayerRequest, handler: F) -> LayerResult
+    where
+        F: FnOnce(LayerRequest) -> Fut + Send + 'static,
+        Fut: Future<Output = LayerResult> + Send + 'static,
+    {
+        use tracing::Instrument;
+
+        let trace = request.trace.clone();
+        let span = tracing::info_span!(
+            "p2p_handler",
+            protocol = %request.protocol,
+            peer = %request.peer.id52(),
+            trace_id = %trace.trace_id,
+            span_id = %trace.span_id,
+            parent_span_id = trace.parent_span_id.as_deref(),
+        );
+        #[cfg(feature = "otlp")]
+        crate::otlp::set_parent(&span, &trace);
+
+        let next = Next {
+            layers: self.clone(),
+            index: 0,
+            handler: Box::new(move |request| Box::pin(handler(request))),
+        };
+        trace.scope(next.run(request)).instrument(span).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(data: serde_json::Value) -> LayerRequest {
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        LayerRequest::new(peer, serde_json::json!("Echo"), data, Default::default(), None, false, None)
+    }
+
+    #[tokio::test]
+    async fn test_layers_run_in_order_and_can_short_circuit() {
+        let layers = Layers::new(vec![
+            boxed(|mut request: LayerRequest, next: Next| async move {
+                request.data = serde_json::json!(format!("{}+outer", request.data.as_str().unwrap_or_default()));
+                next.run(request).await
+            }),
+            boxed(|request: LayerRequest, next: Next| async move {
+                if request.data == serde_json::json!("deny+outer") {
+                    return Err(serde_json::json!("Denied"));
+                }
+                next.run(request).await
+            }),
+        ]);
+
+        let result = layers.run(request(serde_json::json!("hi")), |request| async move { Ok(request.data) }).await;
+        assert_eq!(result, Ok(serde_json::json!("hi+outer")));
+
+        let result = layers
+            .run(request(serde_json::json!("deny")), |_| async move { Ok(serde_json::json!("handler ran")) })
+            .await;
+        assert_eq!(result, Err(serde_json::json!("Denied")));
+    }
+
+    #[tokio::test]
+    async fn test_handler_runs_in_callers_trace() {
+        let caller = fastn_net::TraceContext::new_root();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let request = LayerRequest::new(
+            peer, serde_json::json!("Echo"), serde_json::Value::Null, Default::default(), None, false, Some(caller.clone()),
+        );
+        let handler_span = request.trace().span_id.clone();
+
+        let outgoing = Layers::default()
+            .run(request, |_| async move {
+                Ok(serde_json::json!(fastn_net::TraceContext::for_outgoing()))
+            })
+            .await
+            .unwrap();
+        let outgoing: fastn_net::TraceContext = serde_json::from_value(outgoing).unwrap();
+        assert_eq!(outgoing.trace_id, caller.trace_id);
+        assert_eq!(outgoing.parent_span_id, Some(handler_span));
+    }
+}
@@ -2,31 +2,74 @@
 //!
 //! This module provides high-level, type-safe APIs for implementing P2P servers.
 
+pub mod approvals;
+pub mod audit;
+pub mod auth;
 pub mod builder;
+pub mod chat;
+pub mod clipboard;
+pub mod concurrency;
+pub mod context;
+pub mod delivery;
+pub mod describe;
+pub mod devices;
+pub mod drain;
 pub mod handle;
+pub mod home;
+pub mod invites;
 pub mod listener;
+pub mod loopback;
 pub mod management;
+pub mod middleware;
+pub mod peer_events;
+pub mod peer_sessions;
+pub mod proc;
+pub mod quota;
+pub mod relay;
+pub mod reputation;
 pub mod request;
+pub mod request_log;
+pub mod responder;
+pub mod resumable;
+pub mod resumption;
 pub mod session;
+pub mod state;
+pub mod subprocess;
+pub mod sync;
+pub mod usage;
+pub mod watch;
+pub mod web;
 pub mod daemon;
 pub mod serve_all;
 
 // Public API exports - no use statements, direct qualification
-pub use builder::{ServerBuilder, listen as builder_listen};
-pub use handle::{ResponseHandle, SendError};
+pub use builder::{ProtocolModule, RegistrationError, RequestTimeoutError, ResponseTooLargeError, ServerBuilder, ServerHandle, listen as builder_listen};
+pub use approvals::ApprovalError;
+pub use auth::{AuthDecision, StreamAuthRequest};
+pub use context::RequestContext;
+pub use handle::{DeferredResponse, PendingError, PendingResponses, ResponseHandle, SendError};
+#[allow(deprecated)]
 pub use listener::listen;
+pub use middleware::{LayerRequest, LayerResult, Next};
+pub use peer_events::{PeerHooks, PeerInfo, PeerStats};
+pub use peer_sessions::{PeerSession, PeerSessions};
 pub use management::{
-    ListenerAlreadyActiveError, ListenerNotFoundError, active_listener_count, active_listeners,
-    is_listening, stop_listening,
+    BindingAlreadyActiveError, BindingKey, BindingNotFoundError, ListenerAlreadyActiveError,
+    ListenerHandle, ListenerNotFoundError, ListenerState, active_listener_count, active_listeners,
+    binding_listener, binding_listeners, is_listening, remove_binding, start_binding, stop_listening,
 };
-pub use request::{GetInputError, HandleRequestError, Request};
-pub use session::Session;
+pub use request::{GetInputError, HandleRequestError, Request, SignedRequest};
+pub use responder::{Responder, ResponderError};
+pub use session::{CloseReason, Session};
+pub use subprocess::{CommandTimeouts, Subprocess, SubprocessConfig, SubprocessError};
+pub use watch::{ConfigChange, ConfigWatcher, WatchError};
 
 // Generic server utilities for applications
 pub use daemon::{
-    IdentityConfig, ProtocolBinding, ServerConfig, 
-    ensure_fastn_home, load_all_identities, run_generic_server, acquire_singleton_lock
+    IdentityConfig, IdentityError, ProtocolBinding, ServerConfig, 
+    ensure_fastn_home, resolve_identity, load_all_identities, run_generic_server, acquire_singleton_lock,
+    acquire_update_lock,
 };
 
 // Modern multi-identity server with callbacks
-pub use serve_all::{serve_all, echo_request_handler};
+pub use serve_all::{CommandProtocol, serve_all, echo_request_handler};
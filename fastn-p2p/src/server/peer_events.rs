@@ -0,0 +1,179 @@
+//! Hooks called when peers connect to and disconnect from a server
+//!
+//! See [`crate::server::ServerBuilder::on_peer_connected`]. The disconnect
+//! hook runs from a drop guard owned by the connection task, so it fires once
+//! per connection however the connection ended: a clean close, the peer
+//! vanishing, a transport error, the server stopping or a panic.
+
+/// A peer connection as reported to [`PeerHooks`]
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub peer: fastn_id52::PublicKey,
+    /// Protocols accepted in the handshake
+    pub protocols: Vec<serde_json::Value>,
+    pub client_name: String,
+    pub client_version: String,
+    /// What the client said about itself in ClientHello, see [`crate::handshake::HelloMetadata`]
+    pub metadata: std::collections::BTreeMap<String, String>,
+    /// Whether the client resumed an earlier session
+    pub resumed: bool,
+    pub stats: PeerStats,
+}
+
+/// Connection statistics; zero on connect except for `rtt`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// Time since the handshake completed
+    pub connected_for: std::time::Duration,
+    /// Application streams accepted on the connection
+    pub streams: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub rtt: std::time::Duration,
+    /// Why the connection closed, if it did; `None` when the server stopped accepting
+    pub close_reason: Option<String>,
+}
+
+type PeerHook = std::sync::Arc<dyn Fn(&PeerInfo) + Send + Sync>;
+
+/// The connect and disconnect hooks of one server
+///
+/// Hooks are synchronous and run on the connection's task, so they should
+/// return quickly; spawn anything slow.
+#[derive(Clone, Default)]
+pub struct PeerHooks {
+    connected: Option<PeerHook>,
+    disconnected: Option<PeerHook>,
+}
+
+impl PeerHooks {
+    pub fn on_connected<F>(&mut self, hook: F)
+    where
+        F: Fn(&PeerInfo) + Send + Sync + 'static,
+    {
+        self.connected = Some(std::sync::Arc::new(hook));
+    }
+
+    pub fn on_disconnected<F>(&mut self, hook: F)
+    where
+        F: Fn(&PeerInfo) + Send + Sync + 'static,
+    {
+        self.disconnected = Some(std::sync::Arc::new(hook));
+    }
+
+    /// Report a handshaken connection to `server`; the returned guard reports the disconnect when dropped
+    ///
+    /// Both are published on [`crate::events`] as well.
+    pub(crate) fn connected(
+        &self,
+        server: fastn_id52::PublicKey,
+        info: PeerInfo,
+        conn: Option<iroh::endpoint::Connection>,
+    ) -> ConnectionGuard {
+        crate::events::publish(crate::events::EventKind::PeerConnected {
+            identity: server.id52(),
+            peer: info.peer.id52(),
+            protocols: info.protocols.clone(),
+            client_name: info.client_name.clone(),
+        });
+        let mut guard = ConnectionGuard {
+            server,
+            info,
+            conn,
+            started: std::time::Instant::now(),
+            streams: std::sync::atomic::AtomicU64::new(0),
+            disconnected: self.disconnected.clone(),
+        };
+        if let Some(hook) = &self.connected {
+            guard.info.stats = guard.stats();
+            hook(&guard.info);
+        }
+        guard
+    }
+}
+
+/// Lives as long as the connection is served, see [`PeerHooks::connected`]
+pub(crate) struct ConnectionGuard {
+    server: fastn_id52::PublicKey,
+    info: PeerInfo,
+    conn: Option<iroh::endpoint::Connection>,
+    started: std::time::Instant,
+    streams: std::sync::atomic::AtomicU64,
+    disconnected: Option<PeerHook>,
+}
+
+impl ConnectionGuard {
+    /// Count one accepted application stream
+    pub(crate) fn stream_accepted(&self) {
+        self.streams.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> PeerStats {
+        let mut stats = PeerStats {
+            connected_for: self.started.elapsed(),
+            streams: self.streams.load(std::sync::atomic::Ordering::Relaxed),
+            ..Default::default()
+        };
+        if let Some(conn) = &self.conn {
+            let transport = conn.stats();
+            stats.bytes_sent = transport.udp_tx.bytes;
+            stats.bytes_received = transport.udp_rx.bytes;
+            stats.rtt = conn.rtt();
+            stats.close_reason = conn.close_reason().map(|reason| reason.to_string());
+        }
+        stats
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        tracing::debug!("Peer {} disconnected", self.info.peer.id52());
+        self.info.stats = self.stats();
+        crate::events::publish(crate::events::EventKind::PeerDisconnected {
+            identity: self.server.id52(),
+            peer: self.info.peer.id52(),
+            connected_ms: self.info.stats.connected_for.as_millis() as u64,
+            streams: self.info.stats.streams,
+            close_reason: self.info.stats.close_reason.clone(),
+        });
+        if let Some(hook) = self.disconnected.take() {
+            hook(&self.info);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disconnect_hook_fires_on_drop() {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut hooks = PeerHooks::default();
+        let seen = events.clone();
+        hooks.on_connected(move |info| seen.lock().unwrap().push(("connected", info.stats.streams)));
+        let seen = events.clone();
+        hooks.on_disconnected(move |info| seen.lock().unwrap().push(("disconnected", info.stats.streams)));
+
+        let info = PeerInfo {
+            peer: fastn_id52::SecretKey::generate().public_key(),
+            protocols: vec![serde_json::json!("Echo")],
+            client_name: "test".to_string(),
+            client_version: "0.1.0".to_string(),
+            metadata: Default::default(),
+            resumed: false,
+            stats: PeerStats::default(),
+        };
+        let guard = hooks.connected(fastn_id52::SecretKey::generate().public_key(), info, None);
+        guard.stream_accepted();
+        guard.stream_accepted();
+
+        // Unwinding out of the connection task still reports the disconnect
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let _guard = guard;
+            panic!("connection task failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(*events.lock().unwrap(), vec![("connected", 0), ("disconnected", 2)]);
+    }
+}
@@ -0,0 +1,186 @@
+//! Per-peer state kept for as long as the peer is connected
+//!
+//! Every connected peer gets a [`PeerSession`]: a map from type to value that
+//! request handlers reach through [`crate::server::RequestContext::session`]
+//! (and layers through [`crate::server::LayerRequest::session`]). Use it for
+//! things worth remembering between requests on the same connection, like an
+//! auth level a login request established or a request counter:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::RequestContext;
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # #[error("echo failed")]
+//! # struct EchoError;
+//! #[derive(Clone, Default)]
+//! struct Requests(u64);
+//!
+//! async fn echo(input: String, ctx: RequestContext) -> Result<String, EchoError> {
+//!     let seen = ctx.session().map(|session| session.update(|n: &mut Requests| { n.0 += 1; n.0 }));
+//!     Ok(format!("{input} (request {seen:?})"))
+//! }
+//! ```
+//!
+//! A peer with several connections shares one session, which is dropped once
+//! its last connection is gone.
+
+type Values = std::collections::HashMap<std::any::TypeId, Box<dyn std::any::Any + Send + Sync>>;
+
+/// State stored for one connected peer, one value per type
+///
+/// Clones share the same values.
+#[derive(Clone, Default)]
+pub struct PeerSession(std::sync::Arc<std::sync::Mutex<Values>>);
+
+impl PeerSession {
+    fn values(&self) -> std::sync::MutexGuard<'_, Values> {
+        self.0.lock().expect("Failed to acquire lock on peer session")
+    }
+
+    /// Store `value`, returning the previous value of its type
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.values()
+            .insert(std::any::TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// A copy of the stored value of type `T`
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.values()
+            .get(&std::any::TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&self) -> Option<T> {
+        self.values()
+            .remove(&std::any::TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Change the value of type `T` in place, starting from `T::default()`
+    pub fn update<T: Default + Send + Sync + 'static, R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut values = self.values();
+        let value = values
+            .entry(std::any::TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()));
+        f(value.downcast_mut::<T>().expect("Values are stored under their own TypeId"))
+    }
+}
+
+struct Entry {
+    connections: usize,
+    session: PeerSession,
+}
+
+/// Sessions of all peers connected to a server
+///
+/// Each server has its own unless one is shared with
+/// [`crate::server::ServerBuilder::with_peer_sessions`], e.g. to reach
+/// sessions from stream handler state. Clones share the same sessions.
+#[derive(Clone, Default)]
+pub struct PeerSessions(std::sync::Arc<std::sync::Mutex<std::collections::HashMap<fastn_id52::PublicKey, Entry>>>);
+
+impl PeerSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, std::collections::HashMap<fastn_id52::PublicKey, Entry>> {
+        self.0.lock().expect("Failed to acquire lock on peer sessions")
+    }
+
+    /// The session of `peer`, if it is connected
+    pub fn get(&self, peer: &fastn_id52::PublicKey) -> Option<PeerSession> {
+        self.entries().get(peer).map(|entry| entry.session.clone())
+    }
+
+    /// Peers that currently have a session
+    pub fn peers(&self) -> Vec<fastn_id52::PublicKey> {
+        self.entries().keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    /// Join (or start) `peer`'s session for one connection
+    pub(crate) fn open(&self, peer: fastn_id52::PublicKey) -> SessionGuard {
+        let mut entries = self.entries();
+        let entry = entries.entry(peer).or_insert_with(|| Entry {
+            connections: 0,
+            session: PeerSession::default(),
+        });
+        entry.connections += 1;
+        SessionGuard {
+            sessions: self.clone(),
+            peer,
+            session: entry.session.clone(),
+        }
+    }
+}
+
+/// Keeps a peer's session while its connection is served
+pub(crate) struct SessionGuard {
+    sessions: PeerSessions,
+    peer: fastn_id52::PublicKey,
+    session: PeerSession,
+}
+
+impl SessionGuard {
+    pub(crate) fn session(&self) -> &PeerSession {
+        &self.session
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut entries = self.sessions.entries();
+        if let Some(entry) = entries.get_mut(&self.peer) {
+            entry.connections -= 1;
+            if entry.connections == 0 {
+                entries.remove(&self.peer);
+                tracing::debug!("Dropped session of peer {}", self.peer.id52());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct AuthLevel(u8);
+
+    #[test]
+    fn test_session_lives_while_peer_is_connected() {
+        let sessions = PeerSessions::new();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+
+        let first = sessions.open(peer);
+        let second = sessions.open(peer);
+        assert_eq!(sessions.peers(), vec![peer]);
+
+        assert_eq!(first.session().insert(AuthLevel(2)), None);
+        assert_eq!(second.session().get::<AuthLevel>(), Some(AuthLevel(2)));
+        assert_eq!(first.session().update(|count: &mut u64| { *count += 1; *count }), 1);
+        assert_eq!(sessions.get(&peer).unwrap().update(|count: &mut u64| { *count += 1; *count }), 2);
+
+        // Shared until the last connection goes away
+        drop(first);
+        assert_eq!(sessions.get(&peer).unwrap().remove::<AuthLevel>(), Some(AuthLevel(2)));
+        drop(second);
+        assert!(sessions.get(&peer).is_none());
+        assert!(sessions.is_empty());
+
+        // A reconnecting peer starts over
+        let again = sessions.open(peer);
+        assert_eq!(again.session().get::<u64>(), None);
+    }
+}
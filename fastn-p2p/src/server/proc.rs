@@ -0,0 +1,577 @@
+//! Built-in `proc.fastn.com` protocol: a remote process supervisor
+//!
+//! Lets authorized peers start, stop and watch long-running processes on the
+//! host. Peers only name processes; what each name runs is fixed by the
+//! binding config:
+//!
+//! ```json
+//! {
+//!   "allowed_peers": ["<peer id52>"],
+//!   "processes": {
+//!     "web": { "command": "python3", "args": ["-m", "http.server"], "cwd": "/srv/www" }
+//!   }
+//! }
+//! ```
+//!
+//! An empty `allowed_peers` list rejects everyone. Commands:
+//!
+//! - `list` (request): every configured process with its state
+//! - `status` (request): the state of one process
+//! - `start` (request): start a process that isn't running
+//! - `stop` (request): kill a running process and wait for it to exit
+//! - `logs` (stream): recent stdout/stderr lines of a process, then new ones
+//!   as they come when `follow` is set, one JSON [`LogLine`] per line
+//!
+//! Processes run as children of the serving process and are killed with it.
+
+use std::path::{Path, PathBuf};
+
+/// Protocol name for the process supervisor protocol
+pub const PROC_PROTOCOL: &str = "proc.fastn.com";
+
+/// Output lines kept per process for `logs`
+pub const LOG_LINES: usize = 1000;
+
+/// Lines `logs` replays when the request does not say
+const DEFAULT_TAIL: usize = 100;
+
+/// Longest output line kept whole; longer ones are split into lines of this size
+const MAX_LINE: usize = 16 * 1024;
+
+/// Supervised processes per (protocol_dir, process name)
+type Processes = std::collections::HashMap<(PathBuf, String), std::sync::Arc<Supervised>>;
+
+static PROCESSES: std::sync::LazyLock<std::sync::Mutex<Processes>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// Process supervisor binding configuration
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct ProcConfig {
+    /// ID52s of peers allowed to use any command
+    #[serde(default)]
+    pub allowed_peers: Vec<String>,
+    /// The processes peers may manage, by name
+    #[serde(default)]
+    pub processes: std::collections::BTreeMap<String, ProcessSpec>,
+}
+
+/// What a configured process runs
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProcessSpec {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Working directory, the protocol_dir if not set
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>,
+}
+
+impl ProcConfig {
+    /// Load the binding config from `protocol_dir`, using defaults if it is missing
+    pub async fn load(protocol_dir: &Path) -> Result<Self, ProcError> {
+        match tokio::fs::read_to_string(protocol_dir.join(crate::server::subprocess::CONFIG_FILE)).await {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|source| ProcError::Config { source }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(ProcError::Io { source }),
+        }
+    }
+
+    pub fn allows(&self, peer: &fastn_id52::PublicKey) -> bool {
+        let id52 = peer.id52();
+        self.allowed_peers.iter().any(|allowed| allowed == &id52)
+    }
+
+    fn process(&self, name: &str) -> Result<&ProcessSpec, ProcError> {
+        self.processes.get(name).ok_or_else(|| ProcError::UnknownProcess { name: name.to_string() })
+    }
+}
+
+/// Where a process is in its life
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum ProcessState {
+    NotStarted,
+    Running {
+        pid: Option<u32>,
+        started_at: chrono::DateTime<chrono::Utc>,
+    },
+    Exited {
+        /// None if a signal ended it
+        code: Option<i32>,
+        /// Ended by the `stop` command
+        stopped: bool,
+        started_at: chrono::DateTime<chrono::Utc>,
+        exited_at: chrono::DateTime<chrono::Utc>,
+    },
+}
+
+/// Output of `status` and `start`, and each entry of `list`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProcessStatus {
+    pub name: String,
+    #[serde(flatten)]
+    pub state: ProcessState,
+}
+
+/// Output of the `list` command, ordered by name
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ListResponse {
+    pub processes: Vec<ProcessStatus>,
+}
+
+/// Input for `status`, `start` and `stop`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ProcessRequest {
+    pub name: String,
+}
+
+/// Initial data for the `logs` command
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LogsRequest {
+    pub name: String,
+    /// Recent lines to send first
+    #[serde(default = "default_tail")]
+    pub tail: usize,
+    /// Keep sending new lines until the peer goes away
+    #[serde(default)]
+    pub follow: bool,
+}
+
+fn default_tail() -> usize {
+    DEFAULT_TAIL
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// One line a process wrote, as sent by `logs`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LogLine {
+    pub stream: LogStream,
+    pub line: String,
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcError {
+    #[error("Invalid proc request: {source}")]
+    InvalidRequest { source: serde_json::Error },
+
+    #[error("Peer {peer} is not allowed to manage processes")]
+    NotAllowed { peer: String },
+
+    #[error("No process named '{name}' is configured")]
+    UnknownProcess { name: String },
+
+    #[error("Process '{name}' is already running")]
+    AlreadyRunning { name: String },
+
+    #[error("Process '{name}' is not running")]
+    NotRunning { name: String },
+
+    #[error("Failed to start process '{name}': {source}")]
+    Spawn {
+        name: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid proc config: {source}")]
+    Config { source: serde_json::Error },
+
+    #[error("Proc config error: {source}")]
+    Io { source: std::io::Error },
+}
+
+/// Register the process supervisor commands on a serve_all protocol builder
+pub fn register(
+    protocol: crate::server::serve_all::ProtocolBuilder,
+) -> crate::server::serve_all::ProtocolBuilder {
+    protocol
+        .handle_requests("list", list_handler)
+        .handle_requests("status", status_handler)
+        .handle_requests("start", start_handler)
+        .handle_requests("stop", stop_handler)
+        .handle_streams("logs", logs_handler)
+}
+
+/// `list` command: every configured process with its state
+pub fn list_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    _request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let config = authorize(&protocol_dir, &peer).await?;
+        let processes = config.processes.keys().map(|name| status(&protocol_dir, name)).collect();
+        Ok(serde_json::to_value(ListResponse { processes })?)
+    })
+}
+
+/// `status` command: the state of one configured process
+pub fn status_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let config = authorize(&protocol_dir, &peer).await?;
+        let request: ProcessRequest =
+            serde_json::from_value(request).map_err(|source| ProcError::InvalidRequest { source })?;
+        config.process(&request.name)?;
+        Ok(serde_json::to_value(status(&protocol_dir, &request.name))?)
+    })
+}
+
+/// `start` command: start a configured process that isn't running
+pub fn start_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let config = authorize(&protocol_dir, &peer).await?;
+        let request: ProcessRequest =
+            serde_json::from_value(request).map_err(|source| ProcError::InvalidRequest { source })?;
+        let spec = config.process(&request.name)?;
+
+        supervised(&protocol_dir, &request.name).start(&protocol_dir, &request.name, spec)?;
+        tracing::info!(target: crate::console::TARGET, "▶️  Started process {} for {}", request.name, peer.id52());
+        Ok(serde_json::to_value(status(&protocol_dir, &request.name))?)
+    })
+}
+
+/// `stop` command: kill a running process and answer once it exited
+pub fn stop_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let config = authorize(&protocol_dir, &peer).await?;
+        let request: ProcessRequest =
+            serde_json::from_value(request).map_err(|source| ProcError::InvalidRequest { source })?;
+        config.process(&request.name)?;
+
+        supervised(&protocol_dir, &request.name).stop(&request.name).await?;
+        tracing::info!(target: crate::console::TARGET, "⏹️  Stopped process {} for {}", request.name, peer.id52());
+        Ok(serde_json::to_value(status(&protocol_dir, &request.name))?)
+    })
+}
+
+/// `logs` command: recent output lines, then new ones if `follow` is set
+pub fn logs_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    initial_data: serde_json::Value,
+    mut session: crate::server::Session<String>,
+) -> super::serve_all::Reply<()> {
+    let protocol_dir = protocol_dir.to_path_buf();
+
+    Box::pin(async move {
+        let config = authorize(&protocol_dir, &session.peer).await?;
+        let request: LogsRequest =
+            serde_json::from_value(initial_data).map_err(|source| ProcError::InvalidRequest { source })?;
+        config.process(&request.name)?;
+
+        let (recent, mut live) = supervised(&protocol_dir, &request.name).subscribe(request.tail);
+        for line in recent {
+            session.send.write_all(&json_line(&line)?).await?;
+        }
+        if !request.follow {
+            session.send.finish()?;
+            return Ok(());
+        }
+
+        loop {
+            let line = match live.recv().await {
+                Ok(line) => line,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Logs follower {} lagged, skipped {} lines", session.peer.id52(), skipped);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            if session.send.write_all(&json_line(&line)?).await.is_err() {
+                // Follower went away
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+
+async fn authorize(protocol_dir: &Path, peer: &fastn_id52::PublicKey) -> Result<ProcConfig, ProcError> {
+    let config = ProcConfig::load(protocol_dir).await?;
+    if !config.allows(peer) {
+        return Err(ProcError::NotAllowed { peer: peer.id52() });
+    }
+    Ok(config)
+}
+
+fn json_line(line: &LogLine) -> Result<Vec<u8>, serde_json::Error> {
+    let mut json = serde_json::to_vec(line)?;
+    json.push(b'\n');
+    Ok(json)
+}
+
+fn status(protocol_dir: &Path, name: &str) -> ProcessStatus {
+    let state = match PROCESSES
+        .lock()
+        .expect("Failed to acquire lock on PROCESSES")
+        .get(&(protocol_dir.to_path_buf(), name.to_string()))
+    {
+        Some(process) => process.state.borrow().clone(),
+        None => ProcessState::NotStarted,
+    };
+    ProcessStatus { name: name.to_string(), state }
+}
+
+fn supervised(protocol_dir: &Path, name: &str) -> std::sync::Arc<Supervised> {
+    PROCESSES
+        .lock()
+        .expect("Failed to acquire lock on PROCESSES")
+        .entry((protocol_dir.to_path_buf(), name.to_string()))
+        .or_insert_with(|| std::sync::Arc::new(Supervised::new()))
+        .clone()
+}
+
+/// One named process of a binding, across its starts
+struct Supervised {
+    state: tokio::sync::watch::Sender<ProcessState>,
+    /// Set while running; firing it kills the process
+    stop: std::sync::Mutex<Option<tokio::sync::oneshot::Sender<()>>>,
+    log: std::sync::Mutex<std::collections::VecDeque<LogLine>>,
+    live: tokio::sync::broadcast::Sender<LogLine>,
+}
+
+impl Supervised {
+    fn new() -> Self {
+        Self {
+            state: tokio::sync::watch::Sender::new(ProcessState::NotStarted),
+            stop: Default::default(),
+            log: Default::default(),
+            live: tokio::sync::broadcast::channel(LOG_LINES).0,
+        }
+    }
+
+    fn start(self: &std::sync::Arc<Self>, protocol_dir: &Path, name: &str, spec: &ProcessSpec) -> Result<(), ProcError> {
+        let mut stop = self.stop.lock().expect("Failed to acquire lock on process stop");
+        // Still running until `supervise` saw it exit, even once `stop` fired
+        if stop.is_some() || matches!(*self.state.borrow(), ProcessState::Running { .. }) {
+            return Err(ProcError::AlreadyRunning { name: name.to_string() });
+        }
+
+        let mut child = tokio::process::Command::new(&spec.command)
+            .args(&spec.args)
+            .envs(&spec.env)
+            .current_dir(spec.cwd.as_deref().unwrap_or(protocol_dir))
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| ProcError::Spawn { name: name.to_string(), source })?;
+
+        let started_at = chrono::Utc::now();
+        self.state.send_replace(ProcessState::Running { pid: child.id(), started_at });
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(self.clone().pump(stdout, LogStream::Stdout));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(self.clone().pump(stderr, LogStream::Stderr));
+        }
+
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+        *stop = Some(stop_tx);
+        tokio::spawn(self.clone().supervise(name.to_string(), child, stop_rx, started_at));
+        Ok(())
+    }
+
+    async fn supervise(
+        self: std::sync::Arc<Self>,
+        name: String,
+        mut child: tokio::process::Child,
+        stop: tokio::sync::oneshot::Receiver<()>,
+        started_at: chrono::DateTime<chrono::Utc>,
+    ) {
+        let exited = tokio::select! {
+            exited = child.wait() => Some(exited),
+            _ = stop => None,
+        };
+        let stopped = exited.is_none();
+        let exited = match exited {
+            Some(exited) => exited,
+            None => {
+                let _ = child.start_kill();
+                child.wait().await
+            }
+        };
+
+        let code = match exited {
+            Ok(status) => status.code(),
+            Err(e) => {
+                tracing::warn!("Lost track of process {}: {}", name, e);
+                None
+            }
+        };
+        self.stop.lock().expect("Failed to acquire lock on process stop").take();
+        self.state.send_replace(ProcessState::Exited { code, stopped, started_at, exited_at: chrono::Utc::now() });
+    }
+
+    /// Kill the process and wait until it has exited
+    async fn stop(&self, name: &str) -> Result<(), ProcError> {
+        let mut state = self.state.subscribe();
+        let stop = self.stop.lock().expect("Failed to acquire lock on process stop").take();
+        let Some(stop) = stop else {
+            return Err(ProcError::NotRunning { name: name.to_string() });
+        };
+        let _ = stop.send(());
+        // The sender lives in `self`, so this only ends with the state change
+        let _ = state.wait_for(|state| matches!(state, ProcessState::Exited { .. })).await;
+        Ok(())
+    }
+
+    /// Record every line of one output stream, splitting lines past [`MAX_LINE`]
+    async fn pump(self: std::sync::Arc<Self>, output: impl tokio::io::AsyncRead + Unpin, stream: LogStream) {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+        let mut output = tokio::io::BufReader::new(output);
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            match (&mut output).take(MAX_LINE as u64).read_until(b'\n', &mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let line = String::from_utf8_lossy(&buffer).trim_end_matches(['\n', '\r']).to_string();
+            self.record(LogLine { stream, line, at: chrono::Utc::now() });
+        }
+    }
+
+    fn record(&self, line: LogLine) {
+        let mut log = self.log.lock().expect("Failed to acquire lock on process log");
+        if log.len() == LOG_LINES {
+            log.pop_front();
+        }
+        log.push_back(line.clone());
+        // No receivers is fine - nobody is following right now
+        let _ = self.live.send(line);
+    }
+
+    /// The last `tail` lines, and every line after them
+    fn subscribe(&self, tail: usize) -> (Vec<LogLine>, tokio::sync::broadcast::Receiver<LogLine>) {
+        // Holding the log lock keeps lines from falling between the two
+        let log = self.log.lock().expect("Failed to acquire lock on process log");
+        let recent = log.iter().skip(log.len().saturating_sub(tail)).cloned().collect();
+        (recent, self.live.subscribe())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_start_logs_and_stop() {
+        let temp = tempfile::tempdir().unwrap();
+        let protocol_dir = temp.path().to_path_buf();
+        tokio::fs::create_dir_all(&protocol_dir).await.unwrap();
+
+        let allowed = fastn_id52::SecretKey::generate().public_key();
+        let stranger = fastn_id52::SecretKey::generate().public_key();
+        let config = serde_json::json!({
+            "allowed_peers": [allowed.id52()],
+            "processes": {
+                "chatty": { "command": "sh", "args": ["-c", "echo hello; echo oops >&2; exec sleep 30"] }
+            }
+        });
+        tokio::fs::write(protocol_dir.join(crate::server::subprocess::CONFIG_FILE), config.to_string()).await.unwrap();
+
+        let call = |handler: crate::server::serve_all::RequestCallback,
+                    peer: fastn_id52::PublicKey,
+                    request: serde_json::Value| {
+            handler("alice", "default", PROC_PROTOCOL, "", &protocol_dir, &peer, request)
+        };
+        let chatty = serde_json::json!({ "name": "chatty" });
+
+        let refused = call(start_handler, stranger, chatty.clone()).await.unwrap_err();
+        assert!(refused.to_string().contains("not allowed"));
+        let unknown = call(start_handler, allowed, serde_json::json!({ "name": "rm" })).await.unwrap_err();
+        assert!(unknown.to_string().contains("No process named 'rm'"));
+
+        let started: ProcessStatus =
+            serde_json::from_value(call(start_handler, allowed, chatty.clone()).await.unwrap()).unwrap();
+        assert!(matches!(started.state, ProcessState::Running { .. }));
+        assert!(call(start_handler, allowed, chatty.clone()).await.is_err());
+
+        let process = supervised(&protocol_dir, "chatty");
+        let (mut lines, mut live) = process.subscribe(10);
+        while lines.len() < 2 {
+            lines.push(live.recv().await.unwrap());
+        }
+        lines.sort_by_key(|line| line.line.clone());
+        assert_eq!((lines[0].stream, lines[0].line.as_str()), (LogStream::Stdout, "hello"));
+        assert_eq!((lines[1].stream, lines[1].line.as_str()), (LogStream::Stderr, "oops"));
+
+        let stopped: ProcessStatus =
+            serde_json::from_value(call(stop_handler, allowed, chatty.clone()).await.unwrap()).unwrap();
+        assert!(matches!(stopped.state, ProcessState::Exited { stopped: true, .. }));
+        assert!(call(stop_handler, allowed, chatty).await.is_err());
+
+        let list: ListResponse =
+            serde_json::from_value(call(list_handler, allowed, serde_json::json!({})).await.unwrap()).unwrap();
+        assert_eq!(list.processes, vec![stopped]);
+    }
+
+    #[tokio::test]
+    async fn test_pump_splits_long_lines() {
+        let process = std::sync::Arc::new(Supervised::new());
+        let mut output = vec![b'x'; MAX_LINE * 2 + 10];
+        output.extend_from_slice(b"\nshort\n");
+
+        process.clone().pump(output.as_slice(), LogStream::Stdout).await;
+
+        let (lines, _) = process.subscribe(10);
+        let lengths: Vec<usize> = lines.iter().map(|line| line.line.len()).collect();
+        assert_eq!(lengths, vec![MAX_LINE, MAX_LINE, 10, 5]);
+        assert_eq!(lines[3].line, "short");
+    }
+}
@@ -0,0 +1,450 @@
+//! Storage quotas per identity and per protocol binding
+//!
+//! Limits live in `identities/<alias>/quota.json` (edited with
+//! `fastn-p2p quota set`):
+//!
+//! ```json
+//! { "max_bytes": 10737418240, "bindings": { "sync.fastn.com/photos": 5368709120 } }
+//! ```
+//!
+//! `max_bytes` caps everything under the identity's `protocols/` directory and
+//! each `bindings` entry caps one binding's protocol_dir. Either may be left
+//! out for no limit.
+//!
+//! Handlers write through a [`Storage`] opened on their protocol_dir, which
+//! refuses writes that would go over a limit with [`QuotaExceeded`]. The error
+//! is serializable: `serve_all` returns it to the peer as
+//! [`QuotaExceeded::to_value`] so clients can tell it from other failures.
+
+use std::path::{Path, PathBuf};
+
+/// Quota file inside an identity directory
+pub const QUOTA_FILE: &str = "quota.json";
+
+/// Serializes quota checks with the writes they allow
+static STORAGE_WRITE_LOCK: std::sync::LazyLock<tokio::sync::Mutex<()>> =
+    std::sync::LazyLock::new(|| tokio::sync::Mutex::new(()));
+
+/// Limits of one identity, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct QuotaConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// Per-binding limits, keyed by [`binding_key`]
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub bindings: std::collections::BTreeMap<String, u64>,
+}
+
+impl QuotaConfig {
+    /// Load the limits of the identity in `identity_dir`; no file means no limits
+    pub async fn load(identity_dir: &Path) -> Result<Self, StorageError> {
+        let path = identity_dir.join(QUOTA_FILE);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|source| StorageError::Config { path, source }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(StorageError::Io { path, source }),
+        }
+    }
+
+    pub async fn save(&self, identity_dir: &Path) -> Result<(), StorageError> {
+        let path = identity_dir.join(QUOTA_FILE);
+        let json = serde_json::to_string_pretty(self).expect("Quota config always serializes");
+        tokio::fs::write(&path, json)
+            .await
+            .map_err(|source| StorageError::Io { path, source })
+    }
+
+    pub fn binding_limit(&self, protocol: &str, bind_alias: &str) -> Option<u64> {
+        self.bindings.get(&binding_key(protocol, bind_alias)).copied()
+    }
+}
+
+/// Key of a binding in [`QuotaConfig::bindings`]: `<protocol>/<bind_alias>`
+pub fn binding_key(protocol: &str, bind_alias: &str) -> String {
+    format!("{protocol}/{bind_alias}")
+}
+
+/// Which limit a write ran into
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum QuotaScope {
+    Identity,
+    Binding { protocol: String, bind_alias: String },
+}
+
+impl std::fmt::Display for QuotaScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuotaScope::Identity => write!(f, "identity"),
+            QuotaScope::Binding { protocol, bind_alias } => write!(f, "{} {}", protocol, bind_alias),
+        }
+    }
+}
+
+/// A write would take storage past a limit
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+#[error("Storage quota of {scope} exceeded: {used} of {limit} bytes in use, {requested} more requested")]
+pub struct QuotaExceeded {
+    pub scope: QuotaScope,
+    pub limit: u64,
+    pub used: u64,
+    pub requested: u64,
+}
+
+impl QuotaExceeded {
+    /// Error value sent to peers: `{"quota_exceeded": {...}}`
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::json!({ "quota_exceeded": self })
+    }
+
+    /// Recognize an error value made by [`Self::to_value`]
+    pub fn from_value(value: &serde_json::Value) -> Option<Self> {
+        serde_json::from_value(value.get("quota_exceeded")?.clone()).ok()
+    }
+
+    /// The `QuotaExceeded` in `error` or its sources, if any
+    pub fn find<'a>(error: &'a (dyn std::error::Error + 'static)) -> Option<&'a Self> {
+        let mut error = Some(error);
+        while let Some(current) = error {
+            if let Some(exceeded) = current.downcast_ref::<Self>() {
+                return Some(exceeded);
+            }
+            error = current.source();
+        }
+        None
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("{source}")]
+    QuotaExceeded {
+        #[source]
+        source: QuotaExceeded,
+    },
+
+    #[error("'{}' is not a protocol binding directory (identities/<alias>/protocols/<protocol>/<bind_alias>)", path.display())]
+    NotABinding { path: PathBuf },
+
+    #[error("Invalid storage path '{path}'")]
+    InvalidPath { path: String },
+
+    #[error("Invalid quota config {}: {source}", path.display())]
+    Config {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Storage error at {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Storage task failed: {source}")]
+    Task {
+        #[source]
+        source: tokio::task::JoinError,
+    },
+}
+
+/// Bytes in use by a binding and by its whole identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Usage {
+    pub binding: u64,
+    pub identity: u64,
+}
+
+/// Quota-checked access to one binding's protocol_dir
+#[derive(Debug, Clone)]
+pub struct Storage {
+    identity_dir: PathBuf,
+    protocol_dir: PathBuf,
+    protocol: String,
+    bind_alias: String,
+}
+
+impl Storage {
+    /// Storage of the binding whose protocol_dir is `protocol_dir`
+    pub fn open(protocol_dir: &Path) -> Result<Self, StorageError> {
+        let not_a_binding = || StorageError::NotABinding { path: protocol_dir.to_path_buf() };
+        let name = |path: &Path| path.file_name().and_then(|n| n.to_str()).map(str::to_string);
+
+        let bind_alias = name(protocol_dir).ok_or_else(not_a_binding)?;
+        let protocol_root = protocol_dir.parent().ok_or_else(not_a_binding)?;
+        let protocol = name(protocol_root).ok_or_else(not_a_binding)?;
+        let protocols_dir = protocol_root.parent().ok_or_else(not_a_binding)?;
+        if name(protocols_dir).as_deref() != Some("protocols") {
+            return Err(not_a_binding());
+        }
+        let identity_dir = protocols_dir.parent().ok_or_else(not_a_binding)?;
+
+        Ok(Self {
+            identity_dir: identity_dir.to_path_buf(),
+            protocol_dir: protocol_dir.to_path_buf(),
+            protocol,
+            bind_alias,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.protocol_dir
+    }
+
+    pub async fn usage(&self) -> Result<Usage, StorageError> {
+        Ok(Usage {
+            binding: disk_usage(&self.protocol_dir).await?,
+            identity: disk_usage(&self.identity_dir.join("protocols")).await?,
+        })
+    }
+
+    /// Fail if storing `additional` more bytes would go over a limit
+    pub async fn check(&self, additional: u64) -> Result<(), StorageError> {
+        let config = QuotaConfig::load(&self.identity_dir).await?;
+        let binding_limit = config.binding_limit(&self.protocol, &self.bind_alias);
+        if config.max_bytes.is_none() && binding_limit.is_none() {
+            return Ok(());
+        }
+
+        let usage = self.usage().await?;
+        let limits = [
+            (QuotaScope::Identity, config.max_bytes, usage.identity),
+            (
+                QuotaScope::Binding { protocol: self.protocol.clone(), bind_alias: self.bind_alias.clone() },
+                binding_limit,
+                usage.binding,
+            ),
+        ];
+        for (scope, limit, used) in limits {
+            let Some(limit) = limit else { continue };
+            if used.saturating_add(additional) > limit {
+                let exceeded = QuotaExceeded { scope, limit, used, requested: additional };
+                tracing::warn!("{}", exceeded);
+                return Err(StorageError::QuotaExceeded { source: exceeded });
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the file at `relative` (`/`-separated) with `bytes`, within quota
+    ///
+    /// Only the growth over the file's current size counts against the quota.
+    /// The file is replaced atomically.
+    pub async fn write(&self, relative: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        let path = self.resolve(relative)?;
+        let _guard = STORAGE_WRITE_LOCK.lock().await;
+
+        let existing = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(source) => return Err(StorageError::Io { path, source }),
+        };
+        self.check((bytes.len() as u64).saturating_sub(existing)).await?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|source| StorageError::Io { path: parent.to_path_buf(), source })?;
+        }
+        let tmp = path.with_file_name(format!(
+            "{}.tmp",
+            path.file_name().and_then(|n| n.to_str()).unwrap_or_default()
+        ));
+        tokio::fs::write(&tmp, bytes)
+            .await
+            .map_err(|source| StorageError::Io { path: tmp.clone(), source })?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|source| StorageError::Io { path, source })
+    }
+
+    /// Run `write`, which stores about `additional` bytes itself, within quota
+    ///
+    /// For stores that manage their own files, like sqlite databases. The
+    /// check and `write` run under the same lock as [`Self::write`].
+    pub async fn charge<T>(&self, additional: u64, write: impl std::future::Future<Output = T>) -> Result<T, StorageError> {
+        let _guard = STORAGE_WRITE_LOCK.lock().await;
+        self.check(additional).await?;
+        Ok(write.await)
+    }
+
+    /// Remove the file at `relative`; a missing file is not an error
+    pub async fn remove(&self, relative: &str) -> Result<(), StorageError> {
+        let path = self.resolve(relative)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(source) => Err(StorageError::Io { path, source }),
+        }
+    }
+
+    /// Map `relative` into the protocol_dir, rejecting anything that escapes it
+    fn resolve(&self, relative: &str) -> Result<PathBuf, StorageError> {
+        let mut path = self.protocol_dir.clone();
+        for part in relative.split('/') {
+            match Path::new(part).components().collect::<Vec<_>>().as_slice() {
+                [std::path::Component::Normal(name)] => path.push(name),
+                _ => return Err(StorageError::InvalidPath { path: relative.to_string() }),
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// Usage and limits of one binding, for `fastn-p2p quota show`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct BindingUsage {
+    pub protocol: String,
+    pub bind_alias: String,
+    pub used: u64,
+    pub limit: Option<u64>,
+}
+
+/// Usage and limits of an identity and each of its bindings
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct QuotaReport {
+    pub used: u64,
+    pub limit: Option<u64>,
+    pub bindings: Vec<BindingUsage>,
+}
+
+impl QuotaReport {
+    /// Bindings, and the identity itself, using more than their limit
+    ///
+    /// Happens when a limit is lowered below current usage; new writes fail
+    /// until enough is deleted.
+    pub fn over_limit(&self) -> Vec<String> {
+        let mut over = Vec::new();
+        if self.limit.is_some_and(|limit| self.used > limit) {
+            over.push(QuotaScope::Identity.to_string());
+        }
+        for binding in &self.bindings {
+            if binding.limit.is_some_and(|limit| binding.used > limit) {
+                over.push(binding_key(&binding.protocol, &binding.bind_alias));
+            }
+        }
+        over
+    }
+}
+
+/// Usage and limits of the identity in `identity_dir`
+pub async fn report(identity_dir: &Path) -> Result<QuotaReport, StorageError> {
+    let config = QuotaConfig::load(identity_dir).await?;
+    let protocols_dir = identity_dir.join("protocols");
+
+    let mut bindings = Vec::new();
+    for (protocol, protocol_root) in subdirectories(&protocols_dir).await? {
+        for (bind_alias, protocol_dir) in subdirectories(&protocol_root).await? {
+            bindings.push(BindingUsage {
+                limit: config.binding_limit(&protocol, &bind_alias),
+                used: disk_usage(&protocol_dir).await?,
+                protocol: protocol.clone(),
+                bind_alias,
+            });
+        }
+    }
+
+    Ok(QuotaReport {
+        used: bindings.iter().map(|binding| binding.used).sum(),
+        limit: config.max_bytes,
+        bindings,
+    })
+}
+
+/// Total size of the files under `dir`; 0 if it doesn't exist
+pub async fn disk_usage(dir: &Path) -> Result<u64, StorageError> {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || match std::fs::symlink_metadata(&dir) {
+        Ok(_) => collect_usage(&dir),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(source) => Err(StorageError::Io { path: dir, source }),
+    })
+    .await
+    .map_err(|source| StorageError::Task { source })?
+}
+
+fn collect_usage(dir: &Path) -> Result<u64, StorageError> {
+    let io = |source| StorageError::Io { path: dir.to_path_buf(), source };
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir).map_err(io)? {
+        let entry = entry.map_err(io)?;
+        // Symlinks are not followed: what they point at isn't stored here
+        let file_type = entry.file_type().map_err(io)?;
+        if file_type.is_dir() {
+            total += collect_usage(&entry.path())?;
+        } else if file_type.is_file() {
+            total += entry.metadata().map_err(io)?.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Named subdirectories of `dir`, sorted; none if it doesn't exist
+async fn subdirectories(dir: &Path) -> Result<Vec<(String, PathBuf)>, StorageError> {
+    let io = |source| StorageError::Io { path: dir.to_path_buf(), source };
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => return Err(io(source)),
+    };
+
+    let mut subdirectories = Vec::new();
+    while let Some(entry) = entries.next_entry().await.map_err(io)? {
+        if !entry.file_type().await.map_err(io)?.is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            subdirectories.push((name.to_string(), entry.path()));
+        }
+    }
+    subdirectories.sort();
+    Ok(subdirectories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_quota_enforcement() {
+        let temp = tempfile::tempdir().unwrap();
+        let identity_dir = temp.path().to_path_buf();
+        let photos = identity_dir.join("protocols").join("sync.fastn.com").join("photos");
+        let clips = identity_dir.join("protocols").join("clipboard.fastn.com").join("default");
+        tokio::fs::create_dir_all(&photos).await.unwrap();
+        tokio::fs::create_dir_all(&clips).await.unwrap();
+
+        let config = QuotaConfig {
+            max_bytes: Some(100),
+            bindings: [(binding_key("sync.fastn.com", "photos"), 60)].into_iter().collect(),
+        };
+        config.save(&identity_dir).await.unwrap();
+
+        let photos = Storage::open(&photos).unwrap();
+        let clips = Storage::open(&clips).unwrap();
+        photos.write("a/one.jpg", &[0; 50]).await.unwrap();
+        // Rewriting a file only counts its growth
+        photos.write("a/one.jpg", &[0; 55]).await.unwrap();
+
+        let Err(StorageError::QuotaExceeded { source }) = photos.write("two.jpg", &[0; 10]).await else {
+            panic!("binding quota not enforced");
+        };
+        assert_eq!(source.scope, QuotaScope::Binding { protocol: "sync.fastn.com".into(), bind_alias: "photos".into() });
+        assert_eq!((source.limit, source.used, source.requested), (60, 55, 10));
+        assert_eq!(QuotaExceeded::from_value(&source.to_value()), Some(source));
+
+        clips.write("clip.json", &[0; 40]).await.unwrap();
+        let error = clips.write("more.json", &[0; 10]).await.unwrap_err();
+        assert_eq!(QuotaExceeded::find(&error).map(|e| e.scope.clone()), Some(QuotaScope::Identity));
+
+        assert!(matches!(clips.write("../escape", b"x").await, Err(StorageError::InvalidPath { .. })));
+        assert!(Storage::open(&identity_dir).is_err());
+
+        let report = report(&identity_dir).await.unwrap();
+        assert_eq!(report.used, 95);
+        assert_eq!(report.bindings.len(), 2);
+        assert!(report.over_limit().is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Multi-hop relaying through a trusted intermediate peer
+//!
+//! When two peers cannot reach each other directly but both can reach a common
+//! friend, the friend can forward streams between them:
+//!
+//! ```text
+//! client ──Relay { target, from: None }──▶ relay ──Relay { target, from: client }──▶ target
+//! ```
+//!
+//! 1. The client connects to the relay offering [`RELAY_PROTOCOL`], opens a
+//!    stream with a [`fastn_net::Protocol::Relay`] header and sends the usual
+//!    `{"protocol", "data"}` wrapper.
+//! 2. The relay checks that both the client and the target are peers it relays
+//!    for, opens a stream to the target stamped with the client's ID52 and
+//!    answers the client with a status line (a tagged `()` / error message).
+//! 3. From then on bytes are piped unchanged in both directions.
+//!
+//! The target sees the relay on the QUIC connection, so it only believes the
+//! `from` claim if the relay is in its trusted relays; handlers and
+//! authorization hooks then see the original client as the peer. The
+//! connection hook is asked about the client at each relayed stream, with a
+//! hello that names the relay but carries none of its credentials (see
+//! [`crate::handshake::ClientHello::relayed_by`]), and a refused client only
+//! gets the denial message back.
+//!
+//! # Example
+//! ```rust,no_run
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum EchoProtocol { Echo }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # struct EchoResponse { echoed: String }
+//! # #[derive(Debug, serde::Serialize, serde::Deserialize, thiserror::Error)]
+//! # #[error("echo failed")]
+//! # struct EchoError;
+//! # async fn echo_handler(message: String) -> Result<EchoResponse, EchoError> { Ok(EchoResponse { echoed: message }) }
+//! # async fn run(friend_key: fastn_p2p::SecretKey, bob_key: fastn_p2p::SecretKey, alice_key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//! # let (alice, bob, friend) = (alice_key.public_key(), bob_key.public_key(), friend_key.public_key());
+//! # let request = "hi".to_string();
+//! // On the common friend
+//! fastn_p2p::listen(friend_key).with_relay([alice, bob]).await?;
+//!
+//! // On bob, accepting relayed streams from the friend
+//! fastn_p2p::listen(bob_key)
+//!     .with_trusted_relays([friend])
+//!     .handle_requests(EchoProtocol::Echo, echo_handler)
+//!     .await?;
+//!
+//! // On alice
+//! let result: Result<EchoResponse, EchoError> = fastn_p2p::client::call_via(
+//!     alice_key, friend, bob, EchoProtocol::Echo, request,
+//! ).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+/// Protocol offered in the handshake by clients that want to relay through a peer
+pub const RELAY_PROTOCOL: &str = "fastn-p2p-relay";
+
+/// Relay settings of a server
+#[derive(Debug, Clone, Default)]
+pub struct RelayConfig {
+    /// Peers this server relays between; both ends of a relayed stream must be listed
+    pub relay_for: std::collections::HashSet<fastn_id52::PublicKey>,
+    /// Relays whose claim about the original sender is believed for streams to this server
+    pub trusted_relays: std::collections::HashSet<fastn_id52::PublicKey>,
+}
+
+impl RelayConfig {
+    /// Whether this server forwards streams for other peers
+    pub fn is_relay(&self) -> bool {
+        !self.relay_for.is_empty()
+    }
+
+    /// The [`RELAY_PROTOCOL`] as offered in `ClientHello`
+    pub fn protocol_json() -> serde_json::Value {
+        serde_json::Value::String(RELAY_PROTOCOL.to_string())
+    }
+
+    /// The peer a stream really comes from
+    ///
+    /// `from` is the claim carried in the relay header; it is only accepted
+    /// from trusted relays. Without a claim the connected peer is the sender.
+    pub fn origin(
+        &self,
+        peer: &fastn_id52::PublicKey,
+        from: Option<&str>,
+    ) -> Result<fastn_id52::PublicKey, RelayError> {
+        let Some(from) = from else {
+            return Ok(*peer);
+        };
+        if !self.trusted_relays.contains(peer) {
+            return Err(RelayError::UntrustedRelay { relay: peer.id52() });
+        }
+        from.parse().map_err(|_| RelayError::InvalidPeer { id52: from.to_string() })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    #[error("Peer {relay} is not a trusted relay")]
+    UntrustedRelay { relay: String },
+
+    #[error("Invalid peer ID52 '{id52}' in relay header")]
+    InvalidPeer { id52: String },
+
+    #[error("Not relaying from {from} to {target}")]
+    NotAuthorized { from: String, target: String },
+
+    #[error("Could not reach {target}: {source}")]
+    Unreachable {
+        target: String,
+        source: crate::client::CallError,
+    },
+
+    #[error("Invalid relay request: {source}")]
+    InvalidRequest { source: eyre::Error },
+}
+
+/// Forward a stream that arrived with a `Relay` header for another peer
+///
+/// `relay_key` is this server's identity, used for the onward connection.
+pub(crate) async fn forward(
+    mut send: iroh::endpoint::SendStream,
+    mut recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    peer: fastn_id52::PublicKey,
+    target: String,
+    from: Option<String>,
+    config: &RelayConfig,
+    relay_key: fastn_id52::SecretKey,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let onward = open_onward(&mut recv, &peer, &target, from.as_deref(), config, relay_key).await;
+
+    // Tell the client whether the onward stream is up before piping anything
+    write_status(&mut send, onward.as_ref().map(|_| ()).map_err(|e| e.to_string())).await?;
+
+    let onward = match onward {
+        Ok(onward) => onward,
+        Err(e) => {
+            tracing::warn!("Refused to relay for {}: {}", peer.id52(), e);
+            send.finish()?;
+            return Ok(());
+        }
+    };
+
+    pipe(send, recv, onward).await?;
+    tracing::debug!("Finished relaying from {} to {}", peer.id52(), target);
+    Ok(())
+}
+
+/// Write the status line a forwarding peer sends before piping: a tagged `()` or error message
+pub(crate) async fn write_status(
+    send: &mut iroh::endpoint::SendStream,
+    status: Result<(), String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let status = status
+        .map(|()| serde_json::Value::Null)
+        .map_err(serde_json::Value::String);
+    let mut line = crate::wire::encode_response(status, true)?;
+    line.push('\n');
+    send.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+/// Pipe an incoming stream and an onward session into each other
+///
+/// Returns once both sides have finished sending.
+pub(crate) async fn pipe(
+    mut send: iroh::endpoint::SendStream,
+    mut recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    mut onward: crate::client::Session,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let upstream = async {
+        tokio::io::copy(&mut recv, &mut onward.send).await?;
+        onward.send.finish()?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    };
+    let downstream = async {
+        tokio::io::copy(&mut onward.recv, &mut send).await?;
+        send.finish()?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    };
+    let (upstream, downstream) = tokio::join!(upstream, downstream);
+    upstream.and(downstream)
+}
+
+async fn open_onward(
+    recv: &mut fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    peer: &fastn_id52::PublicKey,
+    target: &str,
+    from: Option<&str>,
+    config: &RelayConfig,
+    relay_key: fastn_id52::SecretKey,
+) -> Result<crate::client::Session, RelayError> {
+    let origin = config.origin(peer, from)?;
+    let target_key: fastn_id52::PublicKey = target
+        .parse()
+        .map_err(|_| RelayError::InvalidPeer { id52: target.to_string() })?;
+
+    if !config.relay_for.contains(&origin) || !config.relay_for.contains(&target_key) {
+        return Err(RelayError::NotAuthorized {
+            from: origin.id52(),
+            target: target.to_string(),
+        });
+    }
+
+    let wrapper: crate::wire::WrapperRequest = recv
+        .next_json()
+        .await
+        .map_err(|source| RelayError::InvalidRequest { source })?;
+
+    tracing::debug!("Relaying {:?} from {} to {}", wrapper.protocol, origin.id52(), target);
+
+    let header = fastn_net::Protocol::Relay {
+        target: target.to_string(),
+        from: Some(origin.id52()),
+    };
+    // The signature (for the target), metadata and trace are passed on untouched
+    crate::client::Client::global(relay_key)
+        .connect_with_header(target_key, header, wrapper)
+        .await
+        .map_err(|source| RelayError::Unreachable {
+            target: target.to_string(),
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_only_trusts_configured_relays() {
+        let relay = fastn_id52::SecretKey::generate().public_key();
+        let stranger = fastn_id52::SecretKey::generate().public_key();
+        let alice = fastn_id52::SecretKey::generate().public_key();
+
+        let config = RelayConfig {
+            relay_for: Default::default(),
+            trusted_relays: [relay].into_iter().collect(),
+        };
+
+        assert_eq!(config.origin(&stranger, None).unwrap(), stranger);
+        assert_eq!(config.origin(&relay, Some(&alice.id52())).unwrap(), alice);
+        assert!(config.origin(&stranger, Some(&alice.id52())).is_err());
+        assert!(config.origin(&relay, Some("not-an-id52")).is_err());
+    }
+}
@@ -0,0 +1,607 @@
+//! Strikes against abusive peers and temporary bans
+//!
+//! Servers count what a peer does wrong: failed authorization, frames that
+//! don't parse, limits it runs into. Each [`Offense`] adds strikes to the
+//! peer's record for that identity; one strike is forgiven every
+//! `decay_secs`. A peer reaching `strike_threshold` is refused for
+//! `ban_secs`: its connections get a throttled handshake with the time left,
+//! and its open connections are closed at their next stream. Streams relayed
+//! on its behalf are refused too, see [`crate::server::relay`].
+//!
+//! Each server keeps the records of its own peers in a [`Reputation`].
+//! Nothing is counted until it has a config, given with
+//! [`crate::server::ServerBuilder::with_reputation`]:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::server::reputation::ReputationConfig;
+//! # #[derive(Debug, serde::Serialize)]
+//! # enum Mail { Send }
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # #[error("mail failed")]
+//! # struct MailError;
+//! # async fn send(mail: String) -> Result<(), MailError> { Ok(()) }
+//! # async fn run(key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//! fastn_p2p::listen(key)
+//!     .with_reputation(ReputationConfig { strike_threshold: 5, ..Default::default() })
+//!     .handle_requests(Mail::Send, send)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`crate::serve_all`] configures each identity from the `[reputation]`
+//! section of `FASTN_HOME/config.toml`, see [`ReputationSettings`]:
+//!
+//! ```toml
+//! [reputation]
+//! strike_threshold = 10
+//! ban_secs = 600
+//! decay_secs = 300
+//!
+//! [reputation.identities.alice]
+//! strike_threshold = 3
+//! ```
+//!
+//! Records live in memory for as long as the server runs, and bans are
+//! announced on the [`crate::events`] bus. `serve_all` also shares them in
+//! `FASTN_HOME/reputation/`, where the daemon answers `fastn-p2p reputation`
+//! from ([`peers_in`], [`clear_in`]).
+
+pub use fastn_p2p_client::admin::PeerReputation;
+
+/// How many strikes ban a peer, for how long, and how fast they are forgiven
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReputationConfig {
+    pub strike_threshold: u32,
+    pub ban_secs: u64,
+    /// Seconds per strike forgiven; 0 never forgives
+    pub decay_secs: u64,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self { strike_threshold: 10, ban_secs: 10 * 60, decay_secs: 5 * 60 }
+    }
+}
+
+/// `[reputation]` in `FASTN_HOME/config.toml`
+///
+/// `enabled = false` turns counting off for identities without a section of
+/// their own.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReputationSettings {
+    pub enabled: bool,
+    pub strike_threshold: u32,
+    pub ban_secs: u64,
+    pub decay_secs: u64,
+    /// By identity alias; fields left out come from the section above
+    pub identities: std::collections::HashMap<String, IdentityReputation>,
+}
+
+impl Default for ReputationSettings {
+    fn default() -> Self {
+        let defaults = ReputationConfig::default();
+        Self {
+            enabled: true,
+            strike_threshold: defaults.strike_threshold,
+            ban_secs: defaults.ban_secs,
+            decay_secs: defaults.decay_secs,
+            identities: Default::default(),
+        }
+    }
+}
+
+/// `[reputation.identities.<alias>]` in `FASTN_HOME/config.toml`
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityReputation {
+    pub strike_threshold: Option<u32>,
+    pub ban_secs: Option<u64>,
+    pub decay_secs: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid [reputation] in {}: {source}", path.display())]
+    Parse {
+        path: std::path::PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+}
+
+impl ReputationSettings {
+    /// The `[reputation]` section of `fastn_home`'s `config.toml`; defaults without one
+    pub async fn load(fastn_home: &std::path::Path) -> Result<Self, SettingsError> {
+        #[derive(Default, serde::Deserialize)]
+        #[serde(default)]
+        struct ConfigFile {
+            reputation: ReputationSettings,
+        }
+
+        let path = fastn_home.join("config.toml");
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => return Err(SettingsError::Io { path, source }),
+        };
+        toml::from_str::<ConfigFile>(&contents)
+            .map(|file| file.reputation)
+            .map_err(|source| SettingsError::Parse { path, source })
+    }
+
+    /// Limits of the identity `alias`, or `None` if nothing is counted for it
+    pub fn for_identity(&self, alias: &str) -> Option<ReputationConfig> {
+        let base = ReputationConfig {
+            strike_threshold: self.strike_threshold,
+            ban_secs: self.ban_secs,
+            decay_secs: self.decay_secs,
+        };
+        let Some(own) = self.identities.get(alias) else {
+            return self.enabled.then_some(base);
+        };
+        Some(ReputationConfig {
+            strike_threshold: own.strike_threshold.unwrap_or(base.strike_threshold),
+            ban_secs: own.ban_secs.unwrap_or(base.ban_secs),
+            decay_secs: own.decay_secs.unwrap_or(base.decay_secs),
+        })
+    }
+}
+
+/// Something a peer did that counts against it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Offense {
+    /// Refused by an auth hook, a bad capability token or request signature
+    AuthFailure,
+    /// A handshake, request line or payload that does not parse
+    MalformedFrame,
+    /// Throttled by an auth hook or over a size limit
+    LimitViolation,
+}
+
+impl Offense {
+    /// Strikes it adds; malformed frames are rarely honest mistakes
+    pub fn strikes(self) -> u32 {
+        match self {
+            Offense::AuthFailure | Offense::LimitViolation => 1,
+            Offense::MalformedFrame => 2,
+        }
+    }
+
+    /// The offense of a hook refusing a peer, if it did
+    pub(crate) fn of_decision(decision: &crate::server::AuthDecision) -> Option<Self> {
+        match decision {
+            crate::server::AuthDecision::Allow => None,
+            crate::server::AuthDecision::Deny { .. } => Some(Offense::AuthFailure),
+            crate::server::AuthDecision::Throttle { .. } => Some(Offense::LimitViolation),
+        }
+    }
+}
+
+impl std::fmt::Display for Offense {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Offense::AuthFailure => write!(f, "auth-failure"),
+            Offense::MalformedFrame => write!(f, "malformed-frame"),
+            Offense::LimitViolation => write!(f, "limit-violation"),
+        }
+    }
+}
+
+/// Shared records inside FASTN_HOME, see [`Reputation::share_in`]
+pub const REPUTATION_DIR: &str = "reputation";
+
+#[derive(Debug)]
+struct Record {
+    strikes: u32,
+    /// Decay is counted from here
+    since: std::time::Instant,
+    last_offense: Offense,
+    banned_until: Option<std::time::Instant>,
+    /// Written to the shared directory, so a missing file means it was cleared there
+    shared: bool,
+}
+
+impl Record {
+    /// Forgive the strikes `decay_secs` allow for up to `now`
+    fn decay(&mut self, config: &ReputationConfig, now: std::time::Instant) {
+        if config.decay_secs == 0 || self.strikes == 0 {
+            self.since = now;
+            return;
+        }
+        let decay = std::time::Duration::from_secs(config.decay_secs);
+        let forgiven = (now.saturating_duration_since(self.since).as_secs() / config.decay_secs).min(self.strikes as u64) as u32;
+        self.strikes -= forgiven;
+        self.since = if self.strikes == 0 { now } else { self.since + decay * forgiven };
+    }
+
+    fn ban_left(&self, now: std::time::Instant) -> Option<std::time::Duration> {
+        self.banned_until
+            .map(|until| until.saturating_duration_since(now))
+            .filter(|left| !left.is_zero())
+    }
+
+    /// Still worth keeping: it has strikes or a ban
+    fn active(&self, now: std::time::Instant) -> bool {
+        self.strikes > 0 || self.ban_left(now).is_some()
+    }
+
+    fn reputation(
+        &self,
+        identity: &fastn_id52::PublicKey,
+        peer: &fastn_id52::PublicKey,
+        config: &ReputationConfig,
+        now: std::time::Instant,
+    ) -> PeerReputation {
+        PeerReputation {
+            identity: identity.id52(),
+            peer: peer.id52(),
+            strikes: self.strikes,
+            threshold: config.strike_threshold,
+            last_offense: self.last_offense.to_string(),
+            banned_until_ms: self.ban_left(now).map(|left| now_ms() + left.as_millis() as u64),
+        }
+    }
+}
+
+/// A record as written to `FASTN_HOME/reputation/<identity>/<peer>.json`
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SharedRecord {
+    strikes: u32,
+    since_ms: u64,
+    last_offense: Offense,
+    banned_until_ms: Option<u64>,
+    /// The config it was counted with, for decaying it outside the serving process
+    config: ReputationConfig,
+}
+
+impl SharedRecord {
+    fn new(record: &Record, config: ReputationConfig, now: std::time::Instant) -> Self {
+        let now_ms = now_ms();
+        let to_ms = |at: std::time::Instant| {
+            now_ms.saturating_sub(now.saturating_duration_since(at).as_millis() as u64)
+                + at.saturating_duration_since(now).as_millis() as u64
+        };
+        Self {
+            strikes: record.strikes,
+            since_ms: to_ms(record.since),
+            last_offense: record.last_offense,
+            banned_until_ms: record.banned_until.map(to_ms),
+            config,
+        }
+    }
+
+    fn record(&self, now: std::time::Instant) -> Record {
+        let now_ms = now_ms();
+        let to_instant = |ms: u64| {
+            let at = now.checked_sub(std::time::Duration::from_millis(now_ms.saturating_sub(ms))).unwrap_or(now);
+            at + std::time::Duration::from_millis(ms.saturating_sub(now_ms))
+        };
+        Record {
+            strikes: self.strikes,
+            since: to_instant(self.since_ms),
+            last_offense: self.last_offense,
+            banned_until: self.banned_until_ms.map(to_instant),
+            shared: true,
+        }
+    }
+}
+
+/// Strike records of the peers of one server identity
+///
+/// Every server keeps its own, see [`crate::server::ServerHandle::reputation`];
+/// nothing is counted without a config. Shared ones are also written to
+/// FASTN_HOME, where another process lists ([`peers_in`]) and clears them
+/// ([`clear_in`]).
+#[derive(Debug)]
+pub struct Reputation {
+    identity: fastn_id52::PublicKey,
+    config: Option<ReputationConfig>,
+    records: std::sync::Mutex<std::collections::HashMap<fastn_id52::PublicKey, Record>>,
+    /// `FASTN_HOME/reputation/<identity>` when shared
+    shared: Option<std::path::PathBuf>,
+}
+
+impl Reputation {
+    pub fn new(identity: fastn_id52::PublicKey, config: Option<ReputationConfig>) -> Self {
+        Self { identity, config, records: Default::default(), shared: None }
+    }
+
+    /// Also write the records to `fastn_home`, for the daemon
+    pub fn share_in(mut self, fastn_home: &std::path::Path) -> Self {
+        self.shared = Some(fastn_home.join(REPUTATION_DIR).join(self.identity.id52()));
+        self
+    }
+
+    fn records(&self) -> std::sync::MutexGuard<'_, std::collections::HashMap<fastn_id52::PublicKey, Record>> {
+        self.records.lock().expect("Reputation lock poisoned")
+    }
+
+    /// Drop the record of `peer` if another process cleared its shared copy
+    fn forget_cleared(
+        &self,
+        records: &mut std::collections::HashMap<fastn_id52::PublicKey, Record>,
+        peer: &fastn_id52::PublicKey,
+    ) {
+        if let (Some(dir), Some(record)) = (&self.shared, records.get(peer))
+            && record.shared
+            && !shared_file(dir, peer).exists()
+        {
+            records.remove(peer);
+        }
+    }
+
+    /// Add `offense` to the record of `peer`
+    ///
+    /// Returns how long the peer is banned for when this offense banned it.
+    pub fn record(&self, peer: &fastn_id52::PublicKey, offense: Offense) -> Option<std::time::Duration> {
+        let config = self.config?;
+        let now = std::time::Instant::now();
+        let mut records = self.records();
+        self.forget_cleared(&mut records, peer);
+        let record = records.entry(*peer).or_insert_with(|| Record {
+            strikes: 0,
+            since: now,
+            last_offense: offense,
+            banned_until: None,
+            shared: false,
+        });
+        // A banned peer gets no further; what it does meanwhile doesn't add up
+        if record.ban_left(now).is_some() {
+            return None;
+        }
+        record.decay(&config, now);
+        record.strikes += offense.strikes();
+        record.last_offense = offense;
+        tracing::debug!("{} against {} ({} strikes)", offense, peer.id52(), record.strikes);
+
+        let banned = record.strikes >= config.strike_threshold;
+        let ban = std::time::Duration::from_secs(config.ban_secs);
+        if banned {
+            record.strikes = 0;
+            record.since = now;
+            record.banned_until = Some(now + ban);
+        }
+        if let Some(dir) = &self.shared {
+            match write_shared(dir, peer, &SharedRecord::new(record, config, now)) {
+                Ok(()) => record.shared = true,
+                Err(e) => tracing::warn!("Failed to share reputation of {} in {}: {}", peer.id52(), dir.display(), e),
+            }
+        }
+        drop(records);
+        if !banned {
+            return None;
+        }
+
+        tracing::warn!("Banned peer {} from {} for {:?} after repeated {}", peer.id52(), self.identity.id52(), ban, offense);
+        crate::events::publish(crate::events::EventKind::PeerBanned {
+            identity: self.identity.id52(),
+            peer: peer.id52(),
+            offense: offense.to_string(),
+            ban_secs: config.ban_secs,
+        });
+        Some(ban)
+    }
+
+    /// How much longer `peer` is banned, if it is
+    pub fn banned_for(&self, peer: &fastn_id52::PublicKey) -> Option<std::time::Duration> {
+        let mut records = self.records();
+        self.forget_cleared(&mut records, peer);
+        records.get(peer).and_then(|record| record.ban_left(std::time::Instant::now()))
+    }
+
+    /// Peers with strikes or a ban, banned ones first
+    pub fn peers(&self) -> Vec<PeerReputation> {
+        let Some(config) = self.config else {
+            return Vec::new();
+        };
+        let now = std::time::Instant::now();
+        let mut records = self.records();
+        // Forgiven records are dropped on the way
+        records.retain(|_, record| {
+            record.decay(&config, now);
+            record.active(now)
+        });
+        let mut peers: Vec<_> = records
+            .iter()
+            .map(|(peer, record)| record.reputation(&self.identity, peer, &config, now))
+            .collect();
+        sort(&mut peers);
+        peers
+    }
+
+    /// Forget the strikes and ban of `peer`; returns whether it had a record
+    pub fn clear(&self, peer: &fastn_id52::PublicKey) -> bool {
+        let removed = self.records().remove(peer).is_some();
+        if let Some(dir) = &self.shared {
+            let _ = std::fs::remove_file(shared_file(dir, peer));
+        }
+        removed
+    }
+}
+
+/// Peers with strikes or a ban in records servers shared in `fastn_home`, banned ones first
+pub fn peers_in(fastn_home: &std::path::Path) -> Vec<PeerReputation> {
+    let now = std::time::Instant::now();
+    let mut peers = Vec::new();
+    for (identity, dir) in shared_dirs(fastn_home, None) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(peer) = path
+                .file_stem()
+                .filter(|_| path.extension().is_some_and(|extension| extension == "json"))
+                .and_then(|stem| stem.to_str()?.parse::<fastn_id52::PublicKey>().ok())
+            else {
+                continue;
+            };
+            let Some(shared) = std::fs::read(&path).ok().and_then(|json| serde_json::from_slice::<SharedRecord>(&json).ok())
+            else {
+                continue;
+            };
+            let mut record = shared.record(now);
+            record.decay(&shared.config, now);
+            if record.active(now) {
+                peers.push(record.reputation(&identity, &peer, &shared.config, now));
+            }
+        }
+    }
+    sort(&mut peers);
+    peers
+}
+
+/// Forget the shared strikes and ban of `peer`, with `identity` or with every identity
+///
+/// The serving process drops its record the next time it looks at the peer.
+/// Returns how many records were dropped.
+pub fn clear_in(fastn_home: &std::path::Path, identity: Option<&fastn_id52::PublicKey>, peer: &fastn_id52::PublicKey) -> usize {
+    shared_dirs(fastn_home, identity)
+        .into_iter()
+        .filter(|(_, dir)| std::fs::remove_file(shared_file(dir, peer)).is_ok())
+        .count()
+}
+
+/// The shared record directories in `fastn_home`, of `identity` or of all identities
+fn shared_dirs(
+    fastn_home: &std::path::Path,
+    identity: Option<&fastn_id52::PublicKey>,
+) -> Vec<(fastn_id52::PublicKey, std::path::PathBuf)> {
+    let root = fastn_home.join(REPUTATION_DIR);
+    if let Some(identity) = identity {
+        return vec![(*identity, root.join(identity.id52()))];
+    }
+    let Ok(entries) = std::fs::read_dir(&root) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| Some((entry.file_name().to_str()?.parse().ok()?, entry.path())))
+        .collect()
+}
+
+fn shared_file(dir: &std::path::Path, peer: &fastn_id52::PublicKey) -> std::path::PathBuf {
+    dir.join(format!("{}.json", peer.id52()))
+}
+
+/// Written aside and renamed, so readers never see half a record
+fn write_shared(dir: &std::path::Path, peer: &fastn_id52::PublicKey, record: &SharedRecord) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let partial = dir.join(format!("{}.partial", peer.id52()));
+    std::fs::write(&partial, serde_json::to_vec(record)?)?;
+    std::fs::rename(&partial, shared_file(dir, peer))
+}
+
+fn sort(peers: &mut [PeerReputation]) {
+    peers.sort_by(|a, b| {
+        (b.banned_until_ms.is_some(), b.strikes, &a.identity, &a.peer)
+            .cmp(&(a.banned_until_ms.is_some(), a.strikes, &b.identity, &b.peer))
+    });
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strikes_ban_and_decay() {
+        let identity = fastn_id52::SecretKey::generate().public_key();
+        let unconfigured = fastn_id52::SecretKey::generate().public_key();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+
+        // Nothing counts for servers without a config
+        let unconfigured = Reputation::new(unconfigured, None);
+        assert_eq!(unconfigured.record(&peer, Offense::MalformedFrame), None);
+        assert!(unconfigured.peers().is_empty());
+
+        let reputation = Reputation::new(identity, Some(ReputationConfig { strike_threshold: 3, ban_secs: 60, decay_secs: 0 }));
+        assert_eq!(reputation.record(&peer, Offense::AuthFailure), None);
+        assert_eq!(reputation.banned_for(&peer), None);
+        assert_eq!(reputation.record(&peer, Offense::MalformedFrame), Some(std::time::Duration::from_secs(60)));
+        assert!(reputation.banned_for(&peer).is_some());
+        let listed = reputation.peers().into_iter().find(|reputation| reputation.peer == peer.id52()).unwrap();
+        assert_eq!(listed.last_offense, "malformed-frame");
+        assert!(listed.banned_until_ms.is_some());
+
+        assert!(reputation.clear(&peer));
+        assert!(!reputation.clear(&peer));
+        assert_eq!(reputation.banned_for(&peer), None);
+
+        // One strike forgiven per decay interval
+        let config = ReputationConfig { strike_threshold: 10, ban_secs: 60, decay_secs: 10 };
+        let start = std::time::Instant::now();
+        let mut record = Record { strikes: 3, since: start, last_offense: Offense::AuthFailure, banned_until: None, shared: false };
+        record.decay(&config, start + std::time::Duration::from_secs(25));
+        assert_eq!(record.strikes, 1);
+        assert_eq!(record.since, start + std::time::Duration::from_secs(20));
+        record.decay(&config, start + std::time::Duration::from_secs(100));
+        assert_eq!(record.strikes, 0);
+    }
+
+    #[test]
+    fn test_shared_records_are_listed_and_cleared_by_another_process() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path();
+        let identity = fastn_id52::SecretKey::generate().public_key();
+        let other = fastn_id52::SecretKey::generate().public_key();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let config = ReputationConfig { strike_threshold: 2, ban_secs: 60, decay_secs: 0 };
+        let reputation = Reputation::new(identity, Some(config)).share_in(fastn_home);
+
+        reputation.record(&peer, Offense::AuthFailure);
+        let listed = peers_in(fastn_home);
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].identity.as_str(), listed[0].strikes, listed[0].threshold), (identity.id52().as_str(), 1, 2));
+        assert!(listed[0].banned_until_ms.is_none());
+
+        assert!(reputation.record(&peer, Offense::AuthFailure).is_some());
+        let listed = peers_in(fastn_home);
+        assert!(listed[0].banned_until_ms.is_some_and(|until| until > now_ms() + 50_000));
+
+        // Clearing another identity leaves the ban; clearing this one lifts it in the server
+        assert_eq!(clear_in(fastn_home, Some(&other), &peer), 0);
+        assert!(reputation.banned_for(&peer).is_some());
+        assert_eq!(clear_in(fastn_home, None, &peer), 1);
+        assert!(peers_in(fastn_home).is_empty());
+        assert_eq!(reputation.banned_for(&peer), None);
+        assert!(reputation.peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_settings_from_config_toml() {
+        let settings: ReputationSettings =
+            toml::from_str("ban_secs = 60\n[identities.alice]\nstrike_threshold = 3\n").unwrap();
+        let alice = settings.for_identity("alice").unwrap();
+        assert_eq!((alice.strike_threshold, alice.ban_secs), (3, 60));
+        let bob = settings.for_identity("bob").unwrap();
+        assert_eq!(bob.strike_threshold, ReputationConfig::default().strike_threshold);
+        assert_eq!(bob.ban_secs, 60);
+        assert!(toml::from_str::<ReputationSettings>("[identities.alice]\nstrikes = 3\n").is_err());
+
+        // Off for everyone but identities with their own section
+        let temp = tempfile::tempdir().unwrap();
+        assert!(ReputationSettings::load(temp.path()).await.unwrap().for_identity("bob").is_some());
+        let config = "[scheduler]\nmax_in_flight = 8\n\n[reputation]\nenabled = false\n\n[reputation.identities.alice]\nban_secs = 5\n";
+        tokio::fs::write(temp.path().join("config.toml"), config).await.unwrap();
+        let settings = ReputationSettings::load(temp.path()).await.unwrap();
+        assert_eq!(settings.for_identity("bob"), None);
+        assert_eq!(settings.for_identity("alice").unwrap().ban_secs, 5);
+    }
+}
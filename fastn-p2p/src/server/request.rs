@@ -1,8 +1,12 @@
+/// One request accepted by the deprecated stream-based [`crate::server::listen`]
+///
+/// Handed out by a [`crate::server::ServerBuilder`] server, so the request has
+/// been through the handshake, auth hooks and layers like any other.
 pub struct Request<P> {
     peer: fastn_id52::PublicKey,
     pub protocol: P, // Keep public for protocol-based routing
-    send: iroh::endpoint::SendStream,
-    recv: iroh::endpoint::RecvStream,
+    input: String,
+    response: crate::server::DeferredResponse,
 }
 
 impl<P> Request<P> {
@@ -10,14 +14,14 @@ impl<P> Request<P> {
     pub(crate) fn new(
         peer: fastn_id52::PublicKey,
         protocol: P,
-        send: iroh::endpoint::SendStream,
-        recv: iroh::endpoint::RecvStream,
+        input: String,
+        response: crate::server::DeferredResponse,
     ) -> Self {
         Self {
             peer,
             protocol,
-            send,
-            recv,
+            input,
+            response,
         }
     }
 
@@ -63,22 +67,17 @@ impl<P> Request<P> {
     /// }
     /// ```
     pub async fn get_input<INPUT>(
-        mut self,
+        self,
     ) -> Result<(INPUT, fastn_p2p::ResponseHandle), GetInputError>
     where
         INPUT: for<'de> serde::Deserialize<'de>,
     {
-        // Read JSON request from the stream
-        let request_json = fastn_net::next_string(&mut self.recv)
-            .await
-            .map_err(|source| GetInputError::ReceiveError { source })?;
-
         // Deserialize the request
-        let input: INPUT = serde_json::from_str(&request_json)
+        let input: INPUT = serde_json::from_str(&self.input)
             .map_err(|source| GetInputError::DeserializationError { source })?;
 
         // Create response handle
-        let response_handle = fastn_p2p::server::handle::ResponseHandle::new(self.send);
+        let response_handle = fastn_p2p::server::handle::ResponseHandle::new(self.response);
 
         Ok((input, response_handle))
     }
@@ -142,6 +141,69 @@ impl<P> Request<P> {
 
         Ok(())
     }
+
+    /// Handle a request with a blocking closure, run through [`crate::spawn_blocking`]
+    ///
+    /// For CPU-heavy handlers (hashing, compression, media decoding) that would
+    /// otherwise stall the async runtime; otherwise like [`Self::handle`].
+    ///
+    /// ```rust,no_run
+    /// # #[derive(serde::Deserialize)]
+    /// # struct HashRequest { data: Vec<u8> }
+    /// # #[derive(serde::Serialize)]
+    /// # struct HashResponse { hash: String }
+    /// # async fn run(peer_request: fastn_p2p::Request<String>) -> Result<(), fastn_p2p::HandleRequestError> {
+    /// peer_request.handle_blocking(|request: HashRequest| {
+    ///     Ok::<HashResponse, String>(HashResponse { hash: blake3::hash(&request.data).to_string() })
+    /// }).await
+    /// # }
+    /// ```
+    pub async fn handle_blocking<INPUT, OUTPUT, ERROR, F>(
+        self,
+        handler: F,
+    ) -> Result<(), HandleRequestError>
+    where
+        INPUT: for<'de> serde::Deserialize<'de> + Send + 'static,
+        OUTPUT: serde::Serialize + Send + 'static,
+        ERROR: serde::Serialize + Send + 'static,
+        F: FnOnce(INPUT) -> Result<OUTPUT, ERROR> + Send + 'static,
+    {
+        let (input, response_handle) = match self.get_input().await {
+            Ok(result) => result,
+            Err(e) => return Err(HandleRequestError::GetInputFailed { source: e }),
+        };
+
+        let handler_result = crate::spawn_blocking(move || handler(input))
+            .await
+            .map_err(|source| HandleRequestError::BlockingTaskFailed { source })?;
+        response_handle
+            .send(handler_result)
+            .await
+            .map_err(|source| HandleRequestError::SendResponseFailed { source })?;
+
+        Ok(())
+    }
+}
+
+/// Input of a signed request, handed to [`crate::server::ServerBuilder::handle_signed_requests`] handlers
+pub struct SignedRequest<INPUT> {
+    pub input: INPUT,
+    verified_sender: crate::signing::VerifiedSender,
+}
+
+impl<INPUT> SignedRequest<INPUT> {
+    pub(crate) fn new(input: INPUT, verified_sender: crate::signing::VerifiedSender) -> Self {
+        Self { input, verified_sender }
+    }
+
+    /// Who signed this exact payload, and when; already checked by the framework
+    pub fn verified_sender(&self) -> &crate::signing::VerifiedSender {
+        &self.verified_sender
+    }
+
+    pub fn into_input(self) -> INPUT {
+        self.input
+    }
 }
 
 /// Error when trying to get input from a Request
@@ -162,4 +224,7 @@ pub enum HandleRequestError {
 
     #[error("Failed to send response: {source}")]
     SendResponseFailed { source: fastn_p2p::SendError },
+
+    #[error("Blocking handler panicked or was cancelled: {source}")]
+    BlockingTaskFailed { source: tokio::task::JoinError },
 }
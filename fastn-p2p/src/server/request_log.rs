@@ -0,0 +1,227 @@
+//! Structured request logging, sampled, with payload redaction
+//!
+//! [`RequestLogger`] is a layer that writes one `tracing` event per request
+//! to the `fastn_p2p::requests` target, with the peer, protocol, serve_all
+//! command, request and response sizes, duration and outcome as fields:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::server::request_log::RequestLogger;
+//! # #[derive(Debug, serde::Serialize)]
+//! # enum Mail { Send }
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # #[error("mail failed")]
+//! # struct MailError;
+//! # async fn send_mail(mail: String) -> Result<(), MailError> { Ok(()) }
+//! # async fn run(key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//! let logger = RequestLogger::new()
+//!     .sample_rate(0.1)
+//!     .with_payloads()
+//!     .redact_field("body")
+//!     .redact(|protocol, data| {
+//!         if protocol == &serde_json::json!("Login") {
+//!             data["password"] = serde_json::json!("[redacted]");
+//!         }
+//!     });
+//! fastn_p2p::listen(key).layer(logger.layer()).handle_requests(Mail::Send, send_mail).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Successful requests are logged at the sample rate; failed ones always are.
+//! Payloads are only logged with [`RequestLogger::with_payloads`], and then
+//! only after every redaction ran. Streams are logged when they end, without
+//! payloads.
+
+/// Written in place of redacted values
+pub const REDACTED: &str = "[redacted]";
+
+type Redactor = std::sync::Arc<dyn Fn(&serde_json::Value, &mut serde_json::Value) + Send + Sync>;
+
+/// Request logging layer, see the module docs
+#[derive(Clone)]
+pub struct RequestLogger {
+    sample_rate: f64,
+    payloads: bool,
+    redacted_fields: std::sync::Arc<std::collections::BTreeSet<String>>,
+    redactors: Vec<Redactor>,
+}
+
+impl Default for RequestLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestLogger {
+    /// Log every request, without payloads
+    pub fn new() -> Self {
+        Self {
+            sample_rate: 1.0,
+            payloads: false,
+            redacted_fields: Default::default(),
+            redactors: Vec::new(),
+        }
+    }
+
+    /// Share of successful requests logged, from 0 (none) to 1 (all)
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Also log request data and responses, after redaction
+    pub fn with_payloads(mut self) -> Self {
+        self.payloads = true;
+        self
+    }
+
+    /// Redact object fields named `name` at any depth of the request data and response
+    pub fn redact_field(mut self, name: impl Into<String>) -> Self {
+        std::sync::Arc::make_mut(&mut self.redacted_fields).insert(name.into());
+        self
+    }
+
+    /// Redact payloads with `redactor`, given the protocol and the payload to edit in place
+    ///
+    /// Runs after [`Self::redact_field`], on request data and responses alike.
+    pub fn redact<F>(mut self, redactor: F) -> Self
+    where
+        F: Fn(&serde_json::Value, &mut serde_json::Value) + Send + Sync + 'static,
+    {
+        self.redactors.push(std::sync::Arc::new(redactor));
+        self
+    }
+
+    /// The layer to pass to [`crate::server::ServerBuilder::layer`] or `serve_all`'s `layer`
+    pub fn layer(
+        self,
+    ) -> impl Fn(crate::server::LayerRequest, crate::server::Next) -> crate::server::middleware::BoxFuture
+    + Send
+    + Sync
+    + 'static {
+        move |request, next| {
+            let logger = self.clone();
+            Box::pin(async move {
+                let sampled = logger.sample_rate >= 1.0 || rand::random::<f64>() < logger.sample_rate;
+                let peer = request.peer().id52();
+                let (protocol, command) = protocol_and_command(request.protocol());
+                let protocol_value = request.protocol().clone();
+                let is_stream = request.is_stream();
+                let bytes_in = request.data.to_string().len();
+                let data = (logger.payloads && !is_stream).then(|| logger.redacted(&protocol_value, &request.data).to_string());
+
+                let started = std::time::Instant::now();
+                let result = next.run(request).await;
+                let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+                let command = command.as_deref().unwrap_or("");
+                match &result {
+                    Ok(_) if !sampled => {}
+                    Ok(response) => {
+                        let bytes_out = response.to_string().len();
+                        let response = data.is_some().then(|| logger.redacted(&protocol_value, response).to_string());
+                        tracing::info!(
+                            target: "fastn_p2p::requests",
+                            peer = %peer,
+                            protocol = %protocol,
+                            command,
+                            stream = is_stream,
+                            bytes_in,
+                            bytes_out,
+                            duration_ms,
+                            outcome = "ok",
+                            data = data.as_deref(),
+                            response = response.as_deref(),
+                            "request handled"
+                        );
+                    }
+                    Err(error) => {
+                        let bytes_out = error.to_string().len();
+                        let error = logger.redacted(&protocol_value, error).to_string();
+                        tracing::warn!(
+                            target: "fastn_p2p::requests",
+                            peer = %peer,
+                            protocol = %protocol,
+                            command,
+                            stream = is_stream,
+                            bytes_in,
+                            bytes_out,
+                            duration_ms,
+                            outcome = "error",
+                            data = data.as_deref(),
+                            error = %error,
+                            "request failed"
+                        );
+                    }
+                }
+                result
+            })
+        }
+    }
+
+    /// `payload` with every redaction applied
+    fn redacted(&self, protocol: &serde_json::Value, payload: &serde_json::Value) -> serde_json::Value {
+        let mut payload = payload.clone();
+        if !self.redacted_fields.is_empty() {
+            redact_fields(&mut payload, &self.redacted_fields);
+        }
+        for redactor in &self.redactors {
+            redactor(protocol, &mut payload);
+        }
+        payload
+    }
+}
+
+fn redact_fields(value: &mut serde_json::Value, names: &std::collections::BTreeSet<String>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if names.contains(name) {
+                    *field = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_fields(field, names);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| redact_fields(item, names)),
+        _ => {}
+    }
+}
+
+/// Protocol name and serve_all command of a protocol value
+fn protocol_and_command(protocol: &serde_json::Value) -> (String, Option<String>) {
+    match serde_json::from_value::<crate::server::CommandProtocol>(protocol.clone()) {
+        Ok(command) => (command.protocol, Some(command.command)),
+        Err(_) => (crate::metrics::protocol_name(protocol), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction_reaches_nested_fields() {
+        let logger = RequestLogger::new().redact_field("body").redact(|protocol, data| {
+            if protocol == &serde_json::json!("Login") {
+                data["password"] = serde_json::json!(REDACTED);
+            }
+        });
+        let mail = serde_json::json!({ "to": "bob", "parts": [{ "body": "secret" }], "body": "secret" });
+        assert_eq!(
+            logger.redacted(&serde_json::json!("Mail"), &mail),
+            serde_json::json!({ "to": "bob", "parts": [{ "body": REDACTED }], "body": REDACTED })
+        );
+        let login = serde_json::json!({ "user": "alice", "password": "hunter2" });
+        assert_eq!(logger.redacted(&serde_json::json!("Login"), &login)["password"], REDACTED);
+        assert_eq!(logger.redacted(&serde_json::json!("Other"), &login), login);
+    }
+
+    #[test]
+    fn test_protocol_and_command() {
+        let command = serde_json::to_value(crate::server::CommandProtocol::new("mail.fastn.com", "default", "get-mails")).unwrap();
+        assert_eq!(protocol_and_command(&command), ("mail.fastn.com".to_string(), Some("get-mails".to_string())));
+        assert_eq!(protocol_and_command(&serde_json::json!("Echo")), ("Echo".to_string(), None));
+        assert_eq!(RequestLogger::new().sample_rate(7.0).sample_rate, 1.0);
+    }
+}
@@ -0,0 +1,162 @@
+//! Chunked responses for request handlers with big outputs
+//!
+//! A handler registered with [`crate::server::ServerBuilder::handle_chunked_requests`]
+//! gets a [`Responder`] and sends its output as a list, item by item. Items go
+//! out in chunks of [`crate::wire::RESPONSE_CHUNK_SIZE`] as they are produced,
+//! so the server never holds the whole output; clients `call()` it like any
+//! other request and get a `Vec<ITEM>`:
+//!
+//! ```rust,no_run
+//! # use fastn_p2p::Responder;
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum Db { Export }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # struct ExportRequest { table: String }
+//! # #[derive(serde::Serialize, serde::Deserialize)]
+//! # struct Row(Vec<String>);
+//! # #[derive(Debug, serde::Serialize, serde::Deserialize, thiserror::Error)]
+//! # #[error("export failed")]
+//! # struct ExportError;
+//! # impl From<fastn_p2p::server::ResponderError> for ExportError {
+//! #     fn from(_: fastn_p2p::server::ResponderError) -> Self { ExportError }
+//! # }
+//! # struct Rows;
+//! # impl Rows { fn rows(&self, table: &str) -> Vec<Result<Row, ExportError>> { Vec::new() } }
+//! # static db: Rows = Rows;
+//! # async fn run(key: fastn_p2p::SecretKey, client: fastn_p2p::client::Client) -> Result<(), Box<dyn std::error::Error>> {
+//! # let (server, request) = (key.public_key(), ExportRequest { table: "mails".to_string() });
+//! async fn export(request: ExportRequest, responder: Responder<Row>) -> Result<(), ExportError> {
+//!     for row in db.rows(&request.table) {
+//!         responder.send(&row?).await?;
+//!     }
+//!     Ok(())
+//! }
+//!
+//! fastn_p2p::listen(key).handle_chunked_requests(Db::Export, export).await?;
+//! let rows: Result<Vec<Row>, ExportError> = client.call(server, Db::Export, request).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! An ERROR returned before the first chunk went out reaches the client as
+//! usual. After that the response can't turn into an error any more, so the
+//! stream is reset and the client sees the call fail with an IO error.
+
+/// The tagged envelope the items are wrapped in, up to the list
+const OK_PREFIX: &[u8] = br#"{"status":"ok","data":"#;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResponderError {
+    #[error("Failed to serialize response item")]
+    Serialization {
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Failed to send response chunk")]
+    Io {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Sends the output of a chunked request handler, see the module docs
+pub struct Responder<ITEM> {
+    state: std::sync::Arc<tokio::sync::Mutex<State>>,
+    _item: std::marker::PhantomData<fn(&ITEM)>,
+}
+
+struct State {
+    send: iroh::endpoint::SendStream,
+    /// Serialized items not sent yet
+    buffer: Vec<u8>,
+    items: usize,
+    /// Whether the chunked response started, after which it can't become an error
+    started: bool,
+}
+
+impl<ITEM: serde::Serialize> Responder<ITEM> {
+    /// Append `item` to the output, sending a chunk once enough items piled up
+    pub async fn send(&self, item: &ITEM) -> Result<(), ResponderError> {
+        let mut state = self.state.lock().await;
+        let separator = if state.items == 0 { b'[' } else { b',' };
+        state.buffer.push(separator);
+        serde_json::to_writer(&mut state.buffer, item).map_err(|source| ResponderError::Serialization { source })?;
+        state.items += 1;
+        if state.buffer.len() >= crate::wire::RESPONSE_CHUNK_SIZE {
+            state.flush().await.map_err(|source| ResponderError::Io { source })?;
+        }
+        Ok(())
+    }
+
+    /// Items sent so far
+    pub async fn items(&self) -> usize {
+        self.state.lock().await.items
+    }
+}
+
+impl State {
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if !self.started {
+            self.send.write_all(crate::wire::CHUNKED_RESPONSE.as_bytes()).await?;
+            self.send.write_all(b"\n").await?;
+            crate::wire::write_response_chunks(&mut self.send, OK_PREFIX).await?;
+            self.started = true;
+        }
+        crate::wire::write_response_chunks(&mut self.send, &self.buffer).await?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Run `handler` on `input`, then complete its response on `send`
+///
+/// Fails only when the response could not be written.
+pub(crate) async fn respond<INPUT, ITEM, F, Fut, ERROR>(
+    send: iroh::endpoint::SendStream,
+    input: INPUT,
+    handler: std::sync::Arc<F>,
+) -> Result<(), ResponderError>
+where
+    ITEM: serde::Serialize,
+    F: Fn(INPUT, Responder<ITEM>) -> Fut,
+    Fut: std::future::Future<Output = Result<(), ERROR>>,
+    ERROR: serde::Serialize,
+{
+    let state = std::sync::Arc::new(tokio::sync::Mutex::new(State {
+        send,
+        buffer: Vec::new(),
+        items: 0,
+        started: false,
+    }));
+    let responder = Responder { state: state.clone(), _item: std::marker::PhantomData };
+    let result = handler(input, responder).await;
+
+    let mut state = state.lock().await;
+    let io = |source| ResponderError::Io { source };
+    match result {
+        Ok(()) => {
+            if state.items == 0 {
+                state.buffer.push(b'[');
+            }
+            state.buffer.extend_from_slice(b"]}");
+            state.flush().await.map_err(io)?;
+            crate::wire::write_response_end(&mut state.send).await.map_err(io)?;
+        }
+        Err(error) if !state.started => {
+            let error = serde_json::to_value(error).map_err(|source| ResponderError::Serialization { source })?;
+            let mut response_json = crate::wire::encode_response(Err(error), true)
+                .map_err(|source| ResponderError::Serialization { source })?;
+            response_json.push('\n');
+            state.send.write_all(response_json.as_bytes()).await.map_err(|e| io(e.into()))?;
+        }
+        Err(_) => {
+            tracing::warn!("Chunked request handler failed after {} items, resetting the stream", state.items);
+            // The stream is gone either way
+            let _ = state.send.reset(0u32.into());
+            return Ok(());
+        }
+    }
+    state.send.finish().map_err(|e| io(std::io::Error::other(e)))?;
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Downloads that survive a dropped connection
+//!
+//! A protocol registered with
+//! [`crate::server::ServerBuilder::handle_resumable_streams`] answers every
+//! stream with a [`ResumeHeader`]: a token naming the download and the byte
+//! offset it starts from. If the connection drops, the client reconnects with
+//! the token and the number of bytes it already has ([`ResumableRequest::resume`]).
+//! The handler is then called again with that offset and only sends the rest.
+//! [`crate::client::Client::download`] does this transparently.
+//!
+//! Tokens are bound to the peer, the protocol and the initial data, and expire
+//! [`crate::server::resumption::DEFAULT_RESUMPTION_TTL`] after last use. An
+//! unknown or expired token starts the download over at offset 0 with a new
+//! token.
+
+/// Initial data of a resumable stream: the handler's data and where to resume
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ResumableRequest<DATA> {
+    pub data: DATA,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume: Option<Resume>,
+}
+
+/// A download to pick up again
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Resume {
+    pub token: String,
+    /// Bytes the client already has
+    pub offset: u64,
+}
+
+/// First line the server sends on a resumable stream; the bytes from `offset` follow
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ResumeHeader {
+    pub token: String,
+    pub offset: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResumableError {
+    #[error("Failed to send the resume header: {source}")]
+    Io {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Handler failed: {source}")]
+    Handler {
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+#[derive(Debug)]
+struct Download {
+    peer: fastn_id52::PublicKey,
+    protocol: serde_json::Value,
+    data: serde_json::Value,
+    expires_at: std::time::Instant,
+}
+
+/// Downloads by token
+static DOWNLOADS: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<String, Download>>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// Send the [`ResumeHeader`] for a request of `data` on `session`; returns the offset to start from
+///
+/// Takes the request data already serialized, so a request whose data isn't
+/// `Sync` isn't borrowed while the header is written.
+pub(crate) async fn announce<P>(
+    session: &mut crate::server::Session<P>,
+    data: &serde_json::Value,
+    resume: Option<&Resume>,
+) -> Result<u64, ResumableError>
+where
+    P: serde::Serialize,
+{
+    let protocol = serde_json::to_value(&session.protocol).expect("Protocol must be serializable");
+    let header = start(
+        &session.peer,
+        &protocol,
+        data,
+        resume,
+        crate::server::resumption::DEFAULT_RESUMPTION_TTL,
+    );
+
+    let mut line = serde_json::to_vec(&header).map_err(|e| ResumableError::Io { source: e.into() })?;
+    line.push(b'\n');
+    session.send.write_all(&line).await.map_err(|e| ResumableError::Io { source: e.into() })?;
+    Ok(header.offset)
+}
+
+/// Where a stream of `peer` starts: resumed if `resume` names its download, fresh otherwise
+fn start(
+    peer: &fastn_id52::PublicKey,
+    protocol: &serde_json::Value,
+    data: &serde_json::Value,
+    resume: Option<&Resume>,
+    ttl: std::time::Duration,
+) -> ResumeHeader {
+    let now = std::time::Instant::now();
+    let mut downloads = DOWNLOADS.lock().expect("Failed to acquire lock on DOWNLOADS");
+    downloads.retain(|_, download| download.expires_at > now);
+
+    if let Some(resume) = resume {
+        match downloads.get_mut(&resume.token) {
+            Some(download) if download.peer == *peer && download.protocol == *protocol && download.data == *data => {
+                download.expires_at = now + ttl;
+                return ResumeHeader { token: resume.token.clone(), offset: resume.offset };
+            }
+            Some(_) => tracing::warn!("Download token presented by {} was issued for another download", peer.id52()),
+            None => tracing::debug!("Unknown or expired download token from {}, starting over", peer.id52()),
+        }
+    }
+
+    let token: String = rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    downloads.insert(
+        token.clone(),
+        Download { peer: *peer, protocol: protocol.clone(), data: data.clone(), expires_at: now + ttl },
+    );
+    ResumeHeader { token, offset: 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_download_tokens() {
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let other = fastn_id52::SecretKey::generate().public_key();
+        let protocol = serde_json::json!("Download");
+        let data = serde_json::json!({"file": "video.mp4"});
+        let ttl = crate::server::resumption::DEFAULT_RESUMPTION_TTL;
+
+        let fresh = start(&peer, &protocol, &data, None, ttl);
+        assert_eq!(fresh.offset, 0);
+
+        let resume = Resume { token: fresh.token.clone(), offset: 4096 };
+        assert_eq!(start(&peer, &protocol, &data, Some(&resume), ttl), ResumeHeader { token: fresh.token.clone(), offset: 4096 });
+
+        // Another peer, or another file, starts over
+        let restarted = start(&other, &protocol, &data, Some(&resume), ttl);
+        assert_eq!(restarted.offset, 0);
+        assert_ne!(restarted.token, fresh.token);
+        assert_eq!(start(&peer, &protocol, &serde_json::json!({"file": "other"}), Some(&resume), ttl).offset, 0);
+
+        // Expired tokens start over
+        let expired = start(&peer, &protocol, &data, None, std::time::Duration::ZERO);
+        let resume = Resume { token: expired.token, offset: 10 };
+        assert_eq!(start(&peer, &protocol, &data, Some(&resume), ttl).offset, 0);
+    }
+}
@@ -0,0 +1,128 @@
+//! Session resumption tokens issued in ServerHello
+//!
+//! A token remembers which protocols were negotiated with a peer, so a client
+//! reconnecting after a network blip can present it in ClientHello and have the
+//! server restore the previous protocol set instead of renegotiating it.
+//! Tokens are bound to the server identity that issued them and the peer's
+//! public key, work once, and expire after a validity window; the resumed
+//! handshake hands out a fresh one. Protocols the server no longer supports
+//! are dropped from a resumed set.
+//!
+//! Resuming does not skip the handshake: the client still sends ClientHello
+//! and waits for ServerHello, and the auth hooks still run. The token only
+//! carries the negotiated protocols over.
+
+/// Sessions by issuing server identity and token
+type Tokens = std::collections::HashMap<(fastn_id52::PublicKey, String), ResumableSession>;
+
+static RESUMPTION_TOKENS: std::sync::LazyLock<std::sync::Mutex<Tokens>> =
+    std::sync::LazyLock::new(|| std::sync::Mutex::new(std::collections::HashMap::new()));
+
+/// Default validity window for resumption tokens
+pub const DEFAULT_RESUMPTION_TTL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Most tokens kept at once; the ones closest to expiring are dropped first
+pub const MAX_RESUMPTION_TOKENS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct ResumableSession {
+    peer: fastn_id52::PublicKey,
+    accepted_protocols: Vec<serde_json::Value>,
+    expires_at: std::time::Instant,
+}
+
+/// Issue a new resumption token for a completed handshake with `server`
+pub(crate) fn issue(
+    server: fastn_id52::PublicKey,
+    peer: fastn_id52::PublicKey,
+    accepted_protocols: Vec<serde_json::Value>,
+    ttl: std::time::Duration,
+) -> String {
+    let token: String = rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+
+    let mut tokens = RESUMPTION_TOKENS
+        .lock()
+        .expect("Failed to acquire lock on RESUMPTION_TOKENS");
+
+    // Opportunistically drop expired tokens, then the oldest ones over the cap
+    let now = std::time::Instant::now();
+    tokens.retain(|_, session| session.expires_at > now);
+    while tokens.len() >= MAX_RESUMPTION_TOKENS {
+        let Some(oldest) = tokens
+            .iter()
+            .min_by_key(|(_, session)| session.expires_at)
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        tokens.remove(&oldest);
+    }
+
+    tokens.insert(
+        (server, token.clone()),
+        ResumableSession {
+            peer,
+            accepted_protocols,
+            expires_at: now + ttl,
+        },
+    );
+    token
+}
+
+/// Redeem a token presented to `server` by `peer`, consuming it
+///
+/// Returns the protocols negotiated in the original session, or `None` if the
+/// token is unknown to `server`, expired, or was issued to a different peer.
+/// A token presented by the wrong peer is left in place for its owner.
+pub(crate) fn redeem(
+    server: &fastn_id52::PublicKey,
+    token: &str,
+    peer: &fastn_id52::PublicKey,
+) -> Option<Vec<serde_json::Value>> {
+    let mut tokens = RESUMPTION_TOKENS
+        .lock()
+        .expect("Failed to acquire lock on RESUMPTION_TOKENS");
+
+    let key = (*server, token.to_string());
+    let session = tokens.get(&key)?;
+    if session.expires_at <= std::time::Instant::now() {
+        tokens.remove(&key);
+        return None;
+    }
+    if &session.peer != peer {
+        tracing::warn!("Resumption token presented by {} was issued to another peer", peer.id52());
+        return None;
+    }
+
+    tokens.remove(&key).map(|session| session.accepted_protocols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resumption_tokens() {
+        let server = fastn_id52::SecretKey::generate().public_key();
+        let other_server = fastn_id52::SecretKey::generate().public_key();
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let other = fastn_id52::SecretKey::generate().public_key();
+        let protocols = vec![serde_json::json!("Echo")];
+
+        let token = issue(server, peer, protocols.clone(), DEFAULT_RESUMPTION_TTL);
+
+        // Never for another peer or at another server, and only once for the right one
+        assert_eq!(redeem(&server, &token, &other), None);
+        assert_eq!(redeem(&other_server, &token, &peer), None);
+        assert_eq!(redeem(&server, &token, &peer), Some(protocols.clone()));
+        assert_eq!(redeem(&server, &token, &peer), None);
+        assert_eq!(redeem(&server, "not-a-token", &peer), None);
+
+        // Expired tokens are rejected and removed
+        let expired = issue(server, peer, protocols, std::time::Duration::ZERO);
+        assert_eq!(redeem(&server, &expired, &peer), None);
+    }
+}
@@ -3,20 +3,24 @@
 //! This module provides the `serve_all()` builder that automatically discovers
 //! and serves all configured identities and protocols from FASTN_HOME.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 
+/// What serve_all command and lifecycle callbacks return
+pub type Reply<T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+
 /// Async callback type for request/response protocol commands
 pub type RequestCallback = fn(
     &str,                    // identity
     &str,                    // bind_alias  
     &str,                    // protocol (e.g., "mail.fastn.com")
     &str,                    // command (e.g., "settings.add-forwarding")
-    &PathBuf,               // protocol_dir
+    &Path,                 // protocol_dir
+    &fastn_id52::PublicKey, // peer making the request
     serde_json::Value,      // request
-) -> Pin<Box<dyn Future<Output = Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+) -> Reply<serde_json::Value>;
 
 /// Async callback type for streaming protocol commands
 pub type StreamCallback = fn(
@@ -24,9 +28,10 @@ pub type StreamCallback = fn(
     &str,                    // bind_alias
     &str,                    // protocol (e.g., "filetransfer.fastn.com")
     &str,                    // command (e.g., "transfer.large-file")
-    &PathBuf,               // protocol_dir
+    &Path,                 // protocol_dir
     serde_json::Value,      // initial_data
-) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+    super::Session<String>, // streams to/from the peer
+) -> Reply<()>;
 
 /// Protocol binding context passed to all handlers
 #[derive(Debug, Clone)]
@@ -36,23 +41,193 @@ pub struct BindingContext {
     pub protocol_dir: PathBuf,
 }
 
+/// Protocol value a peer sends to address one serve_all command
+///
+/// Used as the `protocol` of the P2P wrapper request, so the receiving server
+/// can route it to the right binding and command callback.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct CommandProtocol {
+    pub protocol: String,
+    pub bind_alias: String,
+    pub command: String,
+    /// Extra command line arguments, e.g. from `fastn-p2p call ... -- <args>`
+    ///
+    /// Left out of the wire value when empty, so commands without arguments
+    /// are addressed exactly as before.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+impl CommandProtocol {
+    pub fn new(protocol: &str, bind_alias: &str, command: &str) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            bind_alias: bind_alias.to_string(),
+            command: command.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// `protocol` without its arguments, if it is a command that has some
+    ///
+    /// Commands are registered without arguments, so this is the key a call
+    /// with arguments finds its handler under.
+    pub(crate) fn without_args(protocol: &serde_json::Value) -> Option<serde_json::Value> {
+        let command: CommandProtocol = serde_json::from_value(protocol.clone()).ok()?;
+        if command.args.is_empty() {
+            return None;
+        }
+        crate::protocol_key::of(&CommandProtocol { args: Vec::new(), ..command }).ok()
+    }
+}
+
+/// A command's error as the peer gets it
+///
+/// Plain text, except for errors the peer can act on, which stay typed:
+/// [`super::quota::QuotaExceeded`] and [`super::concurrency::ProtocolBusy`].
+#[derive(Debug, serde::Serialize)]
+#[serde(transparent)]
+struct CommandError(serde_json::Value);
+
+impl CommandError {
+    fn new(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        if let Some(exceeded) = super::quota::QuotaExceeded::find(&*error) {
+            return Self(exceeded.to_value());
+        }
+        if let Some(busy) = error.downcast_ref::<super::concurrency::ProtocolBusy>() {
+            return Self(busy.to_value());
+        }
+        Self(serde_json::Value::String(error.to_string()))
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            serde_json::Value::String(message) => f.write_str(message),
+            other => write!(f, "{}", other),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// One command of one binding, as registered on its identity's server
+struct BoundCommand {
+    serve_all: std::sync::Arc<ServeAllBuilder>,
+    identity: String,
+    bind_alias: String,
+    protocol: String,
+    command: String,
+    protocol_dir: PathBuf,
+}
+
+impl BoundCommand {
+    fn key(&self) -> CommandProtocol {
+        CommandProtocol::new(&self.protocol, &self.bind_alias, &self.command)
+    }
+    
+    /// Add this command to `server`; already being there is fine
+    fn register(self: &std::sync::Arc<Self>, server: &super::ServerHandle) {
+        let is_stream = self
+            .serve_all
+            .protocols
+            .get(&self.protocol)
+            .is_some_and(|protocol| protocol.stream_callbacks.contains_key(&self.command));
+        let result = if is_stream {
+            server.register_stream_protocol(self.key(), self.clone(), |session, data, command: std::sync::Arc<BoundCommand>| async move {
+                command.stream(session, data).await
+            })
+        } else {
+            let command = self.clone();
+            server.register_protocol_with_context(self.key(), move |request, context| {
+                let command = command.clone();
+                async move { command.request(request, context).await }
+            })
+        };
+        if let Err(e) = result {
+            tracing::debug!("{}", e);
+        }
+    }
+    
+    fn unregister(&self, server: &super::ServerHandle) {
+        if let Err(e) = server.unregister_protocol(self.key()) {
+            tracing::debug!("{}", e);
+        }
+    }
+    
+    async fn request(&self, request: serde_json::Value, context: super::RequestContext) -> Result<serde_json::Value, CommandError> {
+        // The registered key has no arguments, the value the peer sent may
+        let args = serde_json::from_value::<CommandProtocol>(context.protocol().clone())
+            .map(|command| command.args)
+            .unwrap_or_default();
+        self.serve_all
+            .dispatch_request(
+                context.peer(),
+                &self.identity,
+                &self.key().with_args(args),
+                &self.protocol_dir,
+                request,
+                context.verified.as_ref(),
+            )
+            .await
+            .map_err(CommandError::new)
+    }
+
+    async fn stream(&self, session: super::Session<CommandProtocol>, data: serde_json::Value) -> Result<(), CommandError> {
+        let callback = self
+            .serve_all
+            .protocols
+            .get(&self.protocol)
+            .and_then(|protocol| protocol.stream_callbacks.get(&self.command))
+            .ok_or_else(|| CommandError(serde_json::Value::String(format!(
+                "No stream handler for protocol '{}' command '{}'", self.protocol, self.command,
+            ))))?;
+        let session = super::Session {
+            protocol: self.protocol.clone(),
+            send: session.send,
+            recv: session.recv,
+            peer: session.peer,
+            identity: session.identity,
+            context: session.context,
+            datagrams: session.datagrams,
+            tasks: session.tasks,
+            close_reason: session.close_reason,
+        };
+        callback(&self.identity, &self.bind_alias, &self.protocol, &self.command, &self.protocol_dir, data, session)
+            .await
+            .map_err(CommandError::new)
+    }
+}
+
 /// Lifecycle callback types for protocol management (per binding) - clean async fn signatures  
-pub type CreateCallback = fn(BindingContext) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
-pub type ActivateCallback = fn(BindingContext) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
-pub type DeactivateCallback = fn(BindingContext) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
-pub type CheckCallback = fn(BindingContext) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
-pub type ReloadCallback = fn(BindingContext) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
-pub type DeleteCallback = fn(BindingContext) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+pub type CreateCallback = fn(BindingContext) -> Reply<()>;
+pub type ActivateCallback = fn(BindingContext) -> Reply<()>;
+pub type DeactivateCallback = fn(BindingContext) -> Reply<()>;
+pub type CheckCallback = fn(BindingContext) -> Reply<()>;
+pub type ReloadCallback = fn(BindingContext) -> Reply<()>;
+pub type DeleteCallback = fn(BindingContext) -> Reply<()>;
 
 /// Global lifecycle callback types (across all protocol bindings)
-pub type GlobalLoadCallback = fn(&str) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
-pub type GlobalUnloadCallback = fn(&str) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+pub type GlobalLoadCallback = fn(&str) -> Reply<()>;
+pub type GlobalUnloadCallback = fn(&str) -> Reply<()>;
 
 /// Protocol command handlers for a specific protocol
 pub struct ProtocolBuilder {
     protocol_name: String,
     request_callbacks: HashMap<String, RequestCallback>,  // Key: command name
     stream_callbacks: HashMap<String, StreamCallback>,    // Key: command name
+    command_timeouts: HashMap<String, std::time::Duration>, // Key: command name
+    command_max_ages: HashMap<String, std::time::Duration>, // Key: command name
+    concurrency: Option<super::concurrency::ConcurrencyLimit>, // Per binding, request commands only
+    idle_policy: Option<fastn_net::IdlePolicy>,             // Server-wide default if unset
+    layers: Vec<super::middleware::Layer>,                  // Around every command, outermost first
+    registration_errors: Vec<super::builder::RegistrationError>, // Conflicts, reported by serve()
     
     // Per-binding lifecycle callbacks
     create_callback: Option<CreateCallback>,
@@ -68,101 +243,173 @@ pub struct ProtocolBuilder {
 }
 
 impl ProtocolBuilder {
-    /// Add a request/response command handler (panics on duplicate)
+    /// Add a request/response command handler
+    ///
+    /// A command registered twice keeps its first handler and `serve()` fails
+    /// with a [`super::builder::RegistrationError`].
     pub fn handle_requests(mut self, command: &str, callback: RequestCallback) -> Self {
-        if self.request_callbacks.contains_key(command) {
-            panic!("Duplicate request handler for protocol '{}' command '{}' - each command can only be registered once", 
-                   self.protocol_name, command);
+        if self.register_command(command) {
+            self.request_callbacks.insert(command.to_string(), callback);
         }
-        self.request_callbacks.insert(command.to_string(), callback);
         self
     }
     
-    /// Add a streaming command handler (panics on duplicate)
+    /// Add a streaming command handler (see [`Self::handle_requests`] for duplicates)
     pub fn handle_streams(mut self, command: &str, callback: StreamCallback) -> Self {
-        if self.stream_callbacks.contains_key(command) {
-            panic!("Duplicate stream handler for protocol '{}' command '{}' - each command can only be registered once", 
-                   self.protocol_name, command);
+        if self.register_command(command) {
+            self.stream_callbacks.insert(command.to_string(), callback);
         }
-        self.stream_callbacks.insert(command.to_string(), callback);
         self
     }
     
+    /// Whether `command` is still free; records a conflict if not
+    ///
+    /// Request and stream commands share one namespace, they are addressed
+    /// by the same [`CommandProtocol`].
+    fn register_command(&mut self, command: &str) -> bool {
+        if self.request_callbacks.contains_key(command) || self.stream_callbacks.contains_key(command) {
+            self.registration_errors.push(super::builder::RegistrationError::DuplicateCommand {
+                protocol: self.protocol_name.clone(),
+                command: command.to_string(),
+            });
+            return false;
+        }
+        true
+    }
+    
+    /// Whether a lifecycle callback is still unset; records a conflict if not
+    fn register_callback(&mut self, is_set: bool, callback: &'static str) -> bool {
+        if is_set {
+            self.registration_errors.push(super::builder::RegistrationError::DuplicateCallback {
+                protocol: self.protocol_name.clone(),
+                callback,
+            });
+            return false;
+        }
+        true
+    }
+    
+    /// Abort `command` if its request handler runs longer than `timeout`
+    ///
+    /// Overrides the server-wide default set with `ServeAllBuilder::with_request_timeout`.
+    pub fn with_timeout(mut self, command: &str, timeout: std::time::Duration) -> Self {
+        self.command_timeouts.insert(command.to_string(), timeout);
+        self
+    }
+    
+    /// Let clients cache successful answers of `command` for `max_age`
+    ///
+    /// See [`super::ServerBuilder::with_protocol_max_age`].
+    pub fn with_max_age(mut self, command: &str, max_age: std::time::Duration) -> Self {
+        self.command_max_ages.insert(command.to_string(), max_age);
+        self
+    }
+    
+    /// Run at most `limit.max_concurrent` requests per binding of this protocol at once
+    ///
+    /// Requests past the queue fail with a [`super::concurrency::ProtocolBusy`],
+    /// so heavy commands of one protocol can't starve the others.
+    pub fn with_concurrency_limit(mut self, limit: super::concurrency::ConcurrencyLimit) -> Self {
+        self.concurrency = Some(limit);
+        self
+    }
+    
+    /// Keep connections serving this protocol open per `policy`
+    ///
+    /// For protocols whose sessions sit idle for long, like chat, or whose
+    /// peers shouldn't hold connections for long. Overrides the default set
+    /// with `ServeAllBuilder::with_idle_policy`, see
+    /// [`super::ServerBuilder::with_protocol_idle_policy`].
+    pub fn with_idle_policy(mut self, policy: fastn_net::IdlePolicy) -> Self {
+        self.idle_policy = Some(policy);
+        self
+    }
+    
+    /// Wrap every command of this protocol with `layer`
+    ///
+    /// The layer sees the command as a [`CommandProtocol`] in
+    /// `request.protocol()`; see [`crate::server::middleware`].
+    pub fn layer<F, Fut>(mut self, layer: F) -> Self
+    where
+        F: Fn(super::LayerRequest, super::Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = super::LayerResult> + Send + 'static,
+    {
+        self.layers.push(super::middleware::boxed(layer));
+        self
+    }
+    
+    /// Names of all request and stream commands
+    fn commands(&self) -> impl Iterator<Item = &String> {
+        self.request_callbacks.keys().chain(self.stream_callbacks.keys())
+    }
+    
     /// Protocol creation (called from: fastn-p2p add-protocol)
     /// Creates workspace, default configs, initial setup
     pub fn on_create(mut self, callback: CreateCallback) -> Self {
-        if self.create_callback.is_some() {
-            panic!("Duplicate on_create for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.create_callback.is_some(), "on_create") {
+            self.create_callback = Some(callback);
         }
-        self.create_callback = Some(callback);
         self
     }
     
     /// Protocol activation (called from: fastn-p2p start, daemon startup)
     /// Start services, begin accepting requests
     pub fn on_activate(mut self, callback: ActivateCallback) -> Self {
-        if self.activate_callback.is_some() {
-            panic!("Duplicate on_activate for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.activate_callback.is_some(), "on_activate") {
+            self.activate_callback = Some(callback);
         }
-        self.activate_callback = Some(callback);
         self
     }
     
     /// Protocol deactivation (called from: fastn-p2p stop mail default)
     /// Stop accepting requests, but preserve data
     pub fn on_deactivate(mut self, callback: DeactivateCallback) -> Self {
-        if self.deactivate_callback.is_some() {
-            panic!("Duplicate on_deactivate for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.deactivate_callback.is_some(), "on_deactivate") {
+            self.deactivate_callback = Some(callback);
         }
-        self.deactivate_callback = Some(callback);
         self
     }
     
     /// Protocol configuration check (called from: fastn-p2p check)
     /// Validate configuration without affecting runtime
     pub fn on_check(mut self, callback: CheckCallback) -> Self {
-        if self.check_callback.is_some() {
-            panic!("Duplicate on_check for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.check_callback.is_some(), "on_check") {
+            self.check_callback = Some(callback);
         }
-        self.check_callback = Some(callback);
         self
     }
     
     /// Protocol reload (called from: fastn-p2p reload mail default)
     /// Re-read config, restart services with new settings
     pub fn on_reload(mut self, callback: ReloadCallback) -> Self {
-        if self.reload_callback.is_some() {
-            panic!("Duplicate on_reload for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.reload_callback.is_some(), "on_reload") {
+            self.reload_callback = Some(callback);
         }
-        self.reload_callback = Some(callback);
         self
     }
     
     /// Protocol deletion (called from: fastn-p2p delete mail default)
     /// Complete cleanup, remove all data and configs
     pub fn on_delete(mut self, callback: DeleteCallback) -> Self {
-        if self.delete_callback.is_some() {
-            panic!("Duplicate on_delete for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.delete_callback.is_some(), "on_delete") {
+            self.delete_callback = Some(callback);
         }
-        self.delete_callback = Some(callback);
         self
     }
     
     /// Global protocol load (once per protocol, across all bindings)
     pub fn on_global_load(mut self, callback: GlobalLoadCallback) -> Self {
-        if self.global_load_callback.is_some() {
-            panic!("Duplicate on_global_load for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.global_load_callback.is_some(), "on_global_load") {
+            self.global_load_callback = Some(callback);
         }
-        self.global_load_callback = Some(callback);
         self
     }
     
     /// Global protocol unload (once per protocol, across all bindings)  
     pub fn on_global_unload(mut self, callback: GlobalUnloadCallback) -> Self {
-        if self.global_unload_callback.is_some() {
-            panic!("Duplicate on_global_unload for protocol '{}' - can only register once", self.protocol_name);
+        if self.register_callback(self.global_unload_callback.is_some(), "on_global_unload") {
+            self.global_unload_callback = Some(callback);
         }
-        self.global_unload_callback = Some(callback);
         self
     }
 }
@@ -171,6 +418,10 @@ impl ProtocolBuilder {
 pub struct ServeAllBuilder {
     fastn_home: PathBuf,
     protocols: HashMap<String, ProtocolBuilder>,  // Key: protocol name
+    registration_errors: Vec<super::builder::RegistrationError>, // Conflicts, reported by serve()
+    request_timeout: Option<std::time::Duration>, // Default for commands without their own
+    idle_policy: fastn_net::IdlePolicy, // Default for protocols without their own
+    peer_hooks: super::PeerHooks, // Shared by the listeners of every binding
 }
 
 impl ServeAllBuilder {
@@ -178,6 +429,14 @@ impl ServeAllBuilder {
     ///
     /// # Example
     /// ```rust,no_run
+    /// # use fastn_p2p::echo_request_handler as get_mails_handler;
+    /// # use fastn_p2p::echo_request_handler as send_mail_handler;
+    /// # use fastn_p2p::echo_request_handler as forwarding_handler;
+    /// # fn large_file_handler(
+    /// #     _: &str, _: &str, _: &str, _: &str, _: &std::path::Path, _: serde_json::Value, _: fastn_p2p::Session<String>,
+    /// # ) -> fastn_p2p::server::serve_all::Reply<()> {
+    /// #     Box::pin(async { Ok(()) })
+    /// # }
     /// fastn_p2p::serve_all()
     ///     .protocol("mail.fastn.com", |p| p
     ///         .handle_requests("get-mails", get_mails_handler)
@@ -187,20 +446,34 @@ impl ServeAllBuilder {
     ///     .protocol("filetransfer.fastn.com", |p| p
     ///         .handle_streams("transfer.large-file", large_file_handler)
     ///     )
+    /// # ;
     /// ```
-    /// Register a protocol with its commands and lifecycle (panics on duplicate)
+    ///
+    /// Register a protocol with its commands and lifecycle. Registering a
+    /// protocol twice, or a command or callback twice within one, keeps the
+    /// first registration and makes `serve()` fail with a
+    /// [`super::builder::RegistrationError`].
     pub fn protocol<F>(mut self, protocol_name: &str, builder_fn: F) -> Self 
     where
         F: FnOnce(ProtocolBuilder) -> ProtocolBuilder,
     {
         if self.protocols.contains_key(protocol_name) {
-            panic!("Duplicate protocol registration for '{}' - each protocol can only be registered once", protocol_name);
+            self.registration_errors.push(super::builder::RegistrationError::DuplicateProtocol {
+                protocol: protocol_name.to_string(),
+            });
+            return self;
         }
         
         let protocol_builder = ProtocolBuilder {
             protocol_name: protocol_name.to_string(),
             request_callbacks: HashMap::new(),
             stream_callbacks: HashMap::new(),
+            command_timeouts: HashMap::new(),
+            command_max_ages: HashMap::new(),
+            concurrency: None,
+            idle_policy: None,
+            layers: Vec::new(),
+            registration_errors: Vec::new(),
             create_callback: None,
             activate_callback: None,
             deactivate_callback: None,
@@ -211,15 +484,498 @@ impl ServeAllBuilder {
             global_unload_callback: None,
         };
         
-        let configured_protocol = builder_fn(protocol_builder);
+        let mut configured_protocol = builder_fn(protocol_builder);
+        self.registration_errors.append(&mut configured_protocol.registration_errors);
         self.protocols.insert(protocol_name.to_string(), configured_protocol);
         self
     }
     
+    /// Like [`Self::protocol`], but fails on the first conflict it introduces
+    ///
+    /// Meant for servers assembled at runtime, e.g. from plugins, that want to
+    /// skip a conflicting protocol instead of refusing to start.
+    pub fn try_protocol<F>(self, protocol_name: &str, builder_fn: F) -> Result<Self, super::builder::RegistrationError>
+    where
+        F: FnOnce(ProtocolBuilder) -> ProtocolBuilder,
+    {
+        let errors_before = self.registration_errors.len();
+        let mut builder = self.protocol(protocol_name, builder_fn);
+        if builder.registration_errors.len() > errors_before {
+            return Err(builder.registration_errors.swap_remove(errors_before));
+        }
+        Ok(builder)
+    }
+    
+    /// Conflicts recorded so far; `serve()` fails with the first one
+    pub fn registration_errors(&self) -> &[super::builder::RegistrationError] {
+        &self.registration_errors
+    }
+    
+    /// Serve from `fastn_home` instead of FASTN_HOME or `~/.fastn`
+    ///
+    /// Lets tests dispatch requests without touching the user's home.
+    pub fn with_fastn_home(mut self, fastn_home: PathBuf) -> Self {
+        self.fastn_home = fastn_home;
+        self
+    }
+    
+    /// Default timeout for request commands that don't set one with `with_timeout`
+    pub fn with_request_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+    
+    /// Default idle policy for protocols that don't set one with `with_idle_policy`
+    pub fn with_idle_policy(mut self, policy: fastn_net::IdlePolicy) -> Self {
+        self.idle_policy = policy;
+        self
+    }
+    
+    /// The idle policy binding listeners of `protocol` close connections by
+    pub fn idle_policy(&self, protocol: &str) -> fastn_net::IdlePolicy {
+        self.protocols
+            .get(protocol)
+            .and_then(|p| p.idle_policy)
+            .unwrap_or(self.idle_policy)
+    }
+    
+    /// Call `hook` when a peer connects to any identity's binding listener
+    ///
+    /// Same as [`super::ServerBuilder::on_peer_connected`], for all bindings.
+    pub fn on_peer_connected<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&super::PeerInfo) + Send + Sync + 'static,
+    {
+        self.peer_hooks.on_connected(hook);
+        self
+    }
+    
+    /// Call `hook` when a peer connected to a binding listener is gone
+    ///
+    /// Same as [`super::ServerBuilder::on_peer_disconnected`], for all bindings.
+    pub fn on_peer_disconnected<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&super::PeerInfo) + Send + Sync + 'static,
+    {
+        self.peer_hooks.on_disconnected(hook);
+        self
+    }
+    
+    /// Route a request to the callback registered for `command`'s protocol and command
+    ///
+    /// The binding listeners started by [`Self::serve`] call this for every
+    /// request a peer sends; tests can call it directly.
+    /// `command` reaches the protocol's layers as `request.protocol()`, its
+    /// `args` included. The protocol's layers (see [`ProtocolBuilder::layer`]) run around the
+    /// callback, inside the timeout.
+    /// The callback is aborted with a `RequestTimeoutError` if it exceeds the
+    /// command's timeout: the one the binding's `config.json` sets (see
+    /// [`super::CommandTimeouts`]), else [`ProtocolBuilder::with_timeout`]'s,
+    /// else the server-wide default. Every dispatched request
+    /// is counted in the command's latency histogram (see [`crate::metrics`])
+    /// and in the identity's usage (see [`super::usage`]); the identity's
+    /// server records it in the audit log, see [`super::ServerBuilder::with_audit_log`].
+    /// A [`super::quota::QuotaExceeded`] anywhere in the callback's error is
+    /// returned as is, so it reaches the peer typed.
+    /// Protocols with a concurrency limit wait for a slot first, or fail with
+    /// a [`super::concurrency::ProtocolBusy`] when the binding's queue is full.
+    pub async fn dispatch_request(
+        &self,
+        peer: &fastn_id52::PublicKey,
+        identity: &str,
+        command: &CommandProtocol,
+        protocol_dir: &Path,
+        request: serde_json::Value,
+        verified: Option<&crate::signing::VerifiedSender>,
+    ) -> Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>> {
+        let protocol_value = serde_json::to_value(command)?;
+        let CommandProtocol { protocol, bind_alias, command, .. } = command;
+        let protocol_builder = self.protocols.get(protocol)
+            .ok_or_else(|| format!("No handlers registered for protocol '{}'", protocol))?;
+        let callback = protocol_builder.request_callbacks.get(command)
+            .ok_or_else(|| format!("No request handler for protocol '{}' command '{}'", protocol, command))?;
+        
+        // Held until the callback returns
+        let _slot = match protocol_builder.concurrency {
+            Some(limit) => {
+                let budget = super::concurrency::budget(identity, protocol, bind_alias, limit);
+                match budget.acquire().await {
+                    Ok(slot) => Some(slot),
+                    Err(busy) => {
+                        tracing::warn!("{} {} refused from {}: {}", protocol, command, peer.id52(), busy);
+                        return Err(Box::new(busy));
+                    }
+                }
+            }
+            None => None,
+        };
+        
+        let bytes_in = request.to_string().len() as u64;
+        let layer_request = super::LayerRequest::new(
+            *peer,
+            protocol_value,
+            request,
+            Default::default(),
+            verified.cloned(),
+            false,
+            None,
+        );
+        
+        // The innermost layer calls the command callback, which needs owned arguments
+        let callback = *callback;
+        let args = (identity.to_string(), bind_alias.to_string(), protocol.to_string(), command.to_string(), protocol_dir.to_path_buf());
+        let layers = super::middleware::Layers::new(protocol_builder.layers.clone());
+        let future = async move {
+            let result = layers.run(layer_request, move |layer_request| async move {
+                let (identity, bind_alias, protocol, command, protocol_dir) = args;
+                let peer = *layer_request.peer();
+                callback(&identity, &bind_alias, &protocol, &command, &protocol_dir, &peer, layer_request.data)
+                    .await
+                    .map_err(|e| match super::quota::QuotaExceeded::find(&*e) {
+                        // Stays typed for the peer, see `QuotaExceeded::to_value`
+                        Some(exceeded) => exceeded.to_value(),
+                        None => serde_json::Value::String(e.to_string()),
+                    })
+            }).await;
+            result.map_err(|error| -> Box<dyn std::error::Error + Send + Sync> {
+                match error {
+                    serde_json::Value::String(message) => message.into(),
+                    other => match super::quota::QuotaExceeded::from_value(&other) {
+                        Some(exceeded) => Box::new(exceeded),
+                        None => other.to_string().into(),
+                    },
+                }
+            })
+        };
+        let timeout = super::CommandTimeouts::load(protocol_dir).await?.for_command(command)
+            .or_else(|| protocol_builder.command_timeouts.get(command).copied())
+            .or(self.request_timeout);
+        
+        let timer = crate::metrics::Timer::start(protocol.to_string(), Some(command.to_string()), identity.to_string(), *peer);
+        let result = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, future).await {
+                Ok(result) => result,
+                Err(_elapsed) => {
+                    tracing::warn!("{} {} timed out after {:?}", protocol, command, timeout);
+                    Err(Box::new(super::builder::RequestTimeoutError { timeout }) as Box<dyn std::error::Error + Send + Sync>)
+                }
+            },
+            None => future.await,
+        };
+        timer.finish(result.is_ok());
+        
+        let bytes_out = result.as_ref().map_or(0, |response| response.to_string().len() as u64);
+        super::usage::record(identity, peer, protocol, bytes_in, bytes_out);
+        
+        result
+    }
+    
+    /// Apply one change seen on disk while serving
+    ///
+    /// Listeners are brought in line first (see
+    /// [`super::watch::apply_to_listeners`]), then the protocol hears about it:
+    /// an edited config calls `on_reload`, a removed one `on_deactivate`, and
+    /// the identity going online or offline calls `on_activate` /
+    /// `on_deactivate` for each of its bindings.
+    ///
+    /// Bindings without a listener yet are served on their identity's server
+    /// in `servers`; their handles are returned.
+    async fn apply_config_change(
+        self: &std::sync::Arc<Self>,
+        servers: &HashMap<String, super::ServerHandle>,
+        change: super::watch::ConfigChange,
+    ) -> Vec<super::ListenerHandle> {
+        use super::watch::ConfigChange;
+        
+        let alias = change.identity().to_string();
+        let identity_dir = self.fastn_home.join("identities").join(&alias);
+        let identity_config = match super::daemon::IdentityConfig::load_from_conventional_dir(&identity_dir, &alias).await {
+            Ok(identity_config) => identity_config,
+            Err(e) => {
+                tracing::warn!("Ignoring change for {}: {}", alias, e);
+                return Vec::new();
+            }
+        };
+        if let Err(e) = super::watch::apply_to_listeners(&self.fastn_home, &change).await {
+            tracing::warn!("{}", e);
+        }
+        
+        let identity = identity_config.secret_key.public_key();
+        let context = |bind_alias: &str, protocol_dir: PathBuf| BindingContext {
+            identity,
+            bind_alias: bind_alias.to_string(),
+            protocol_dir,
+        };
+        match change {
+            ConfigChange::BindingChanged { protocol, bind_alias, protocol_dir, .. } => {
+                let Some(binding) = identity_config
+                    .protocols
+                    .iter()
+                    .find(|binding| binding.protocol == protocol && binding.bind_alias == bind_alias)
+                else {
+                    return Vec::new();
+                };
+                if !identity_config.online {
+                    return Vec::new();
+                }
+                tracing::info!(target: crate::console::TARGET, "🔄 Reloading {} {} ({})", protocol, bind_alias, alias);
+                let started = self.serve_new_bindings(servers, &alias, std::slice::from_ref(binding)).await;
+                self.run_lifecycle(&protocol, "on_reload", |p| p.reload_callback, context(&bind_alias, protocol_dir)).await;
+                started
+            }
+            ConfigChange::BindingRemoved { protocol, bind_alias, .. } => {
+                tracing::info!(target: crate::console::TARGET, "🗑️  Binding removed: {} {} ({})", protocol, bind_alias, alias);
+                let protocol_dir = identity_dir.join("protocols").join(&protocol).join(&bind_alias);
+                self.run_lifecycle(&protocol, "on_deactivate", |p| p.deactivate_callback, context(&bind_alias, protocol_dir)).await;
+                Vec::new()
+            }
+            ConfigChange::IdentityOnline { .. } => {
+                tracing::info!(target: crate::console::TARGET, "🟢 Identity online: {}", alias);
+                let started = self.serve_new_bindings(servers, &alias, &identity_config.protocols).await;
+                for binding in &identity_config.protocols {
+                    let context = context(&binding.bind_alias, binding.config_path.clone());
+                    self.run_lifecycle(&binding.protocol, "on_activate", |p| p.activate_callback, context).await;
+                }
+                started
+            }
+            ConfigChange::IdentityOffline { .. } => {
+                tracing::info!(target: crate::console::TARGET, "🔴 Identity offline: {}", alias);
+                for binding in &identity_config.protocols {
+                    let context = context(&binding.bind_alias, binding.config_path.clone());
+                    self.run_lifecycle(&binding.protocol, "on_deactivate", |p| p.deactivate_callback, context).await;
+                }
+                Vec::new()
+            }
+        }
+    }
+    
+    /// Serve those of `bindings` that have no listener in this process yet
+    async fn serve_new_bindings(
+        self: &std::sync::Arc<Self>,
+        servers: &HashMap<String, super::ServerHandle>,
+        identity: &str,
+        bindings: &[super::daemon::ProtocolBinding],
+    ) -> Vec<super::ListenerHandle> {
+        let Some(server) = servers.get(identity) else {
+            tracing::warn!("{} was offline at start, restart to serve its bindings", identity);
+            return Vec::new();
+        };
+        let mut started = Vec::new();
+        for binding in bindings {
+            let key = super::management::BindingKey {
+                identity: server.public_key(),
+                protocol: binding.protocol.clone(),
+                bind_alias: binding.bind_alias.clone(),
+            };
+            if super::management::binding_listener(&key).is_err() {
+                started.extend(self.start_serving(server, identity, binding).await);
+            }
+        }
+        started
+    }
+    
+    /// Call one lifecycle callback of `protocol`, if it registered one
+    async fn run_lifecycle(
+        &self,
+        protocol: &str,
+        name: &str,
+        callback: fn(&ProtocolBuilder) -> Option<ReloadCallback>,
+        context: BindingContext,
+    ) {
+        let Some(callback) = self.protocols.get(protocol).and_then(callback) else {
+            return;
+        };
+        if let Err(e) = callback(context).await {
+            tracing::warn!("{} {} failed: {}", protocol, name, e);
+        }
+    }
+    
+    /// Serve `binding` on `server`, with its helper if it has an `exec` section
+    async fn start_serving(
+        self: &std::sync::Arc<Self>,
+        server: &super::ServerHandle,
+        identity: &str,
+        binding: &super::daemon::ProtocolBinding,
+    ) -> Option<super::ListenerHandle> {
+        tracing::info!(target: crate::console::TARGET, "   📡 {} {} → {}", 
+                binding.protocol, 
+                binding.bind_alias,
+                binding.config_path.display());
+        
+        if !self.protocols.contains_key(&binding.protocol) {
+            tracing::warn!("No handlers registered for {}, not serving {} ({})",
+                binding.protocol, binding.bind_alias, identity);
+            return None;
+        }
+        let result = match super::subprocess::SubprocessConfig::load(&binding.config_path).await {
+            Ok(Some(config)) => {
+                tracing::info!(target: crate::console::TARGET, "     🧩 Served by helper {}", config.command.display());
+                let timeouts = match super::CommandTimeouts::load(&binding.config_path).await {
+                    Ok(timeouts) => timeouts,
+                    Err(e) => {
+                        tracing::warn!("{}", e);
+                        return None;
+                    }
+                };
+                self.serve_helper_binding(server, binding, config, timeouts)
+            }
+            Ok(None) => self.serve_binding(server, identity, binding),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                return None;
+            }
+        };
+        result.inspect_err(|e| tracing::warn!("{}", e)).ok()
+    }
+    
+    /// Serve `binding`'s commands on `server` with its helper process
+    ///
+    /// Like [`Self::serve_binding`], but each start of the binding runs a new
+    /// helper and routes the commands to it, and stopping the binding stops
+    /// the helper. Request commands time out per `timeouts`, else per the
+    /// builder's settings like in-process commands.
+    fn serve_helper_binding(
+        &self,
+        server: &super::ServerHandle,
+        binding: &super::daemon::ProtocolBinding,
+        config: super::SubprocessConfig,
+        timeouts: super::CommandTimeouts,
+    ) -> Result<super::ListenerHandle, super::management::BindingAlreadyActiveError> {
+        let key = super::management::BindingKey {
+            identity: server.public_key(),
+            protocol: binding.protocol.clone(),
+            bind_alias: binding.bind_alias.clone(),
+        };
+        let commands: Vec<_> = self
+            .protocols
+            .get(&binding.protocol)
+            .map(|protocol| {
+                protocol
+                    .commands()
+                    .map(|command| {
+                        let is_stream = protocol.stream_callbacks.contains_key(command);
+                        let timeout = timeouts
+                            .for_command(command)
+                            .or_else(|| protocol.command_timeouts.get(command).copied())
+                            .or(self.request_timeout);
+                        (CommandProtocol::new(&binding.protocol, &binding.bind_alias, command), is_stream, timeout)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let name = key.to_string();
+        let server = server.clone();
+        // Bumped on every start, so a stop noticed late doesn't undo a restart
+        let generation = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        super::management::start_binding(key, move |token| {
+            let started = generation.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            let subprocess = super::Subprocess::spawn(name.clone(), config.clone());
+            for (command, is_stream, timeout) in &commands {
+                // Routes to the helper of a previous start are replaced
+                let _ = server.unregister_protocol(command);
+                let result = match is_stream {
+                    true => server.register_subprocess_streams(command, subprocess.clone()),
+                    false => server.register_subprocess_requests(command, subprocess.clone()),
+                };
+                match (result, timeout) {
+                    (Err(e), _) => tracing::debug!("{}", e),
+                    (Ok(()), Some(timeout)) if !is_stream => server.set_request_timeout(command, *timeout),
+                    (Ok(()), _) => {}
+                }
+            }
+            let commands = commands.clone();
+            let server = server.clone();
+            let generation = generation.clone();
+            crate::spawn(async move {
+                tokio::select! {
+                    _ = token.cancelled() => {}
+                    _ = crate::cancelled() => {}
+                }
+                subprocess.stop();
+                if generation.load(std::sync::atomic::Ordering::SeqCst) == started {
+                    for (command, _, _) in &commands {
+                        if let Err(e) = server.unregister_protocol(command) {
+                            tracing::debug!("{}", e);
+                        }
+                    }
+                }
+            });
+        })
+    }
+    
+    /// Serve `binding`'s commands on `server`, the listener of its identity
+    ///
+    /// The binding's [`super::ListenerHandle`] adds the commands to the
+    /// running server when it starts and removes them when it stops, so a
+    /// stopped binding refuses new requests while the identity's other
+    /// bindings keep serving.
+    fn serve_binding(
+        self: &std::sync::Arc<Self>,
+        server: &super::ServerHandle,
+        identity: &str,
+        binding: &super::daemon::ProtocolBinding,
+    ) -> Result<super::ListenerHandle, super::management::BindingAlreadyActiveError> {
+        let key = super::management::BindingKey {
+            identity: server.public_key(),
+            protocol: binding.protocol.clone(),
+            bind_alias: binding.bind_alias.clone(),
+        };
+        let commands: Vec<_> = self
+            .protocols
+            .get(&binding.protocol)
+            .map(|protocol| protocol.commands().cloned().collect())
+            .unwrap_or_default();
+        let commands: Vec<_> = commands
+            .into_iter()
+            .map(|command| std::sync::Arc::new(BoundCommand {
+                serve_all: self.clone(),
+                identity: identity.to_string(),
+                bind_alias: binding.bind_alias.clone(),
+                protocol: binding.protocol.clone(),
+                command,
+                protocol_dir: binding.config_path.clone(),
+            }))
+            .collect();
+        let server = server.clone();
+        // Bumped on every start, so a stop noticed late doesn't undo a restart
+        let generation = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        super::management::start_binding(key, move |token| {
+            let started = generation.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            for command in &commands {
+                command.register(&server);
+            }
+            let commands = commands.clone();
+            let server = server.clone();
+            let generation = generation.clone();
+            crate::spawn(async move {
+                token.cancelled().await;
+                if generation.load(std::sync::atomic::Ordering::SeqCst) == started {
+                    for command in &commands {
+                        command.unregister(&server);
+                    }
+                }
+            });
+        })
+    }
+    
     /// Start serving all configured identities and protocols
+    ///
+    /// Each online identity gets one [`super::ServerBuilder`] listener, and
+    /// each of its bindings registers its commands there, addressed by
+    /// [`CommandProtocol`]. The commands of bindings with an `exec` helper go
+    /// to that process instead of their callbacks.
     pub async fn serve(self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("🚀 Starting multi-identity P2P server");
-        println!("📁 FASTN_HOME: {}", self.fastn_home.display());
+        if let Some(error) = self.registration_errors.first() {
+            for error in &self.registration_errors {
+                tracing::error!("{}", error);
+            }
+            return Err(Box::new(error.clone()));
+        }
+        
+        tracing::info!(target: crate::console::TARGET, "🚀 Starting multi-identity P2P server");
+        tracing::info!(target: crate::console::TARGET, "📁 FASTN_HOME: {}", self.fastn_home.display());
+        // `fastn-p2p approvals` goes through the daemon, a different process
+        super::approvals::share_in(&self.fastn_home);
         
         // Load all identity configurations using daemon utilities
         let identity_configs = super::daemon::load_all_identities(&self.fastn_home).await?;
@@ -232,46 +988,80 @@ impl ServeAllBuilder {
             return Err("No online identities found. Set identities online with: fastn-p2p identity-online <name>".into());
         }
         
-        println!("🔑 Found {} online identities", online_identities.len());
+        tracing::info!(target: crate::console::TARGET, "🔑 Found {} online identities", online_identities.len());
+        let reputation = super::reputation::ReputationSettings::load(&self.fastn_home).await?;
+        let serve_all = std::sync::Arc::new(self);
+        let mut servers = HashMap::new();
+        // Also registered with `management`; kept here for as long as we serve
+        let mut listeners = Vec::new();
+        // Where the daemon hands over calls to our identities, see `loopback`
+        let mut sockets = Vec::new();
         
-        // Start P2P listeners for each identity/protocol combination
+        // One listener per identity, serving the commands of all its bindings
         for identity_config in online_identities {
-            println!("🎧 Starting services for identity: {}", identity_config.alias);
+            tracing::info!(target: crate::console::TARGET, "🎧 Starting services for identity: {}", identity_config.alias);
             
+            // Served anyway; writes through `quota::Storage` fail until space is freed
+            let identity_dir = serve_all.fastn_home.join("identities").join(&identity_config.alias);
+            match super::quota::report(&identity_dir).await {
+                Ok(report) => {
+                    for scope in report.over_limit() {
+                        tracing::warn!("Over storage quota: {} (see fastn-p2p quota show {})", scope, identity_config.alias);
+                    }
+                }
+                Err(e) => tracing::warn!("{}", e),
+            }
+            
+            let mut builder = super::ServerBuilder::new(identity_config.secret_key.clone())
+                .with_idle_policy(serve_all.idle_policy)
+                .with_peer_hooks(serve_all.peer_hooks.clone())
+                .share_reputation_in(&serve_all.fastn_home)
+                .with_audit_log(&serve_all.fastn_home, &identity_config.alias);
+            if let Some(config) = reputation.for_identity(&identity_config.alias) {
+                builder = builder.with_reputation(config);
+            }
             for protocol_binding in &identity_config.protocols {
-                let protocol_dir = protocol_binding.config_path.clone();
-                
-                println!("   📡 {} {} → {}", 
-                        protocol_binding.protocol, 
-                        protocol_binding.bind_alias,
-                        protocol_dir.display());
-                
-                // Check if we have a handler for this protocol
-                if let Some(callback) = self.request_callbacks.get(&protocol_binding.protocol) {
-                    println!("     🔄 Starting request handler for {}", protocol_binding.protocol);
-                    
-                    // TODO: Start actual P2P listener and route requests to callback
-                    // For now, just log that we would start it
-                    let identity = identity_config.alias.clone();
-                    let bind_alias = protocol_binding.bind_alias.clone();
-                    let protocol = protocol_binding.protocol.clone();
-                    let protocol_dir_clone = protocol_dir.clone();
-                    
-                    tokio::spawn(async move {
-                        println!("🎧 Would start P2P listener for {} {} ({})", protocol, bind_alias, identity);
-                        println!("   Working dir: {}", protocol_dir_clone.display());
-                        // TODO: Start fastn_p2p::listen() and route to callback
-                    });
+                let Some(protocol) = serve_all.protocols.get(&protocol_binding.protocol) else {
+                    continue;
+                };
+                for (command, max_age) in &protocol.command_max_ages {
+                    let key = CommandProtocol::new(&protocol_binding.protocol, &protocol_binding.bind_alias, command);
+                    builder = builder.with_protocol_max_age(key, *max_age);
                 }
-                
-                if let Some(callback) = self.stream_callbacks.get(&protocol_binding.protocol) {
-                    println!("     🌊 Starting stream handler for {}", protocol_binding.protocol);
-                    // TODO: Similar to request handler but for streaming
+                let Some(policy) = protocol.idle_policy else {
+                    continue;
+                };
+                for command in protocol.commands() {
+                    let key = CommandProtocol::new(&protocol_binding.protocol, &protocol_binding.bind_alias, command);
+                    builder = builder.with_protocol_idle_policy(key, policy);
                 }
             }
+            let server = builder.start()?;
+            match super::loopback::serve_host(&serve_all.fastn_home, server.public_key()) {
+                Ok(socket) => sockets.push(socket),
+                Err(e) => tracing::warn!("Calls through the daemon to {} take the network: {}", identity_config.alias, e),
+            }
+            
+            for protocol_binding in &identity_config.protocols {
+                listeners.extend(serve_all.start_serving(&server, &identity_config.alias, protocol_binding).await);
+            }
+            servers.insert(identity_config.alias.clone(), server);
         }
         
-        println!("🎯 Multi-identity server ready (TODO: implement actual P2P listening)");
+        tracing::info!(target: crate::console::TARGET, "🎯 Multi-identity server ready");
+        
+        // Config edits on disk take effect without `fastn-p2p reload`
+        match super::watch::ConfigWatcher::new(&serve_all.fastn_home) {
+            Ok(mut watcher) => {
+                tracing::info!(target: crate::console::TARGET, "👀 Watching identities for config changes");
+                while let Some(changes) = watcher.next().await {
+                    for change in changes {
+                        listeners.extend(serve_all.apply_config_change(&servers, change).await);
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Config changes need a restart: {}", e),
+        }
         
         // Keep server running
         loop {
@@ -292,6 +1082,10 @@ pub fn serve_all() -> ServeAllBuilder {
     ServeAllBuilder {
         fastn_home,
         protocols: HashMap::new(),
+        registration_errors: Vec::new(),
+        request_timeout: None,
+        idle_policy: fastn_net::IdlePolicy::default(),
+        peer_hooks: super::PeerHooks::default(),
     }
 }
 
@@ -301,22 +1095,18 @@ pub fn echo_request_handler(
     bind_alias: &str,
     protocol: &str,
     command: &str,
-    protocol_dir: &PathBuf,
+    protocol_dir: &Path,
+    _peer: &fastn_id52::PublicKey,
     request: serde_json::Value,
-) -> Pin<Box<dyn Future<Output = Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>>> + Send>> {
+) -> Reply<serde_json::Value> {
     let identity = identity.to_string();
     let bind_alias = bind_alias.to_string();
     let protocol = protocol.to_string();
     let command = command.to_string();
-    let protocol_dir = protocol_dir.clone();
+    let protocol_dir = protocol_dir.to_path_buf();
     
     Box::pin(async move {
-        println!("💬 Echo handler called:");
-        println!("   Identity: {}", identity);
-        println!("   Bind alias: {}", bind_alias);
-        println!("   Protocol: {}", protocol);
-        println!("   Command: {}", command);
-        println!("   Protocol dir: {}", protocol_dir.display());
+        tracing::debug!("Echo {} {} {} for {} in {}", protocol, bind_alias, command, identity, protocol_dir.display());
         
         // Parse request
         let message = request.get("message")
@@ -327,14 +1117,232 @@ pub fn echo_request_handler(
             return Err("Message cannot be empty".into());
         }
         
-        println!("   Message: '{}'", message);
+        tracing::trace!("Echo message: '{}'", message);
         
         // Create response
         let response = serde_json::json!({
             "echoed": format!("Echo from {} ({}): {}", identity, command, message)
         });
         
-        println!("📤 Echo response: {}", response);
+        tracing::trace!("Echo response: {}", response);
         Ok(response)
     })
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop_lifecycle(_binding: BindingContext) -> Reply<()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_registrations_fail_serve() {
+        let builder = serve_all()
+            .protocol("mail.fastn.com", |p| p
+                .handle_requests("get-mails", echo_request_handler)
+                .handle_requests("get-mails", echo_request_handler)
+                .on_create(noop_lifecycle)
+                .on_create(noop_lifecycle)
+            )
+            .protocol("mail.fastn.com", |p| p);
+
+        assert_eq!(builder.registration_errors(), [
+            crate::server::RegistrationError::DuplicateCommand {
+                protocol: "mail.fastn.com".to_string(),
+                command: "get-mails".to_string(),
+            },
+            crate::server::RegistrationError::DuplicateCallback {
+                protocol: "mail.fastn.com".to_string(),
+                callback: "on_create",
+            },
+            crate::server::RegistrationError::DuplicateProtocol {
+                protocol: "mail.fastn.com".to_string(),
+            },
+        ]);
+
+        let error = builder.serve().await.unwrap_err();
+        assert!(error.downcast_ref::<crate::server::RegistrationError>().is_some());
+    }
+
+    #[test]
+    fn test_try_protocol_rejects_conflicts() {
+        let builder = serve_all()
+            .try_protocol("mail.fastn.com", |p| p.handle_requests("get-mails", echo_request_handler))
+            .unwrap();
+
+        let error = builder
+            .try_protocol("mail.fastn.com", |p| p)
+            .err()
+            .unwrap();
+        assert_eq!(error, crate::server::RegistrationError::DuplicateProtocol {
+            protocol: "mail.fastn.com".to_string(),
+        });
+
+        let error = serve_all()
+            .try_protocol("echo.fastn.com", |p| p
+                .handle_requests("echo", echo_request_handler)
+                .handle_requests("echo", echo_request_handler)
+            )
+            .err()
+            .unwrap();
+        assert!(matches!(error, crate::server::RegistrationError::DuplicateCommand { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_exec_binding_commands_go_to_the_helper() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let alice = fastn_home.join("identities").join("alice");
+        let key = fastn_id52::SecretKey::generate();
+        key.save_to_dir(&alice, "identity").unwrap();
+        let store = crate::server::state::StateStore::open(&fastn_home).await.unwrap();
+        store.set_online("alice", true).await.unwrap();
+        store.add_binding("alice", "echo.fastn.com", "default", &serde_json::json!({})).await.unwrap();
+
+        // Turns every request line into a response with the same data
+        let binding_dir = alice.join("protocols").join("echo.fastn.com").join("default");
+        std::fs::create_dir_all(&binding_dir).unwrap();
+        let config = serde_json::json!({
+            "exec": {"command": "sed", "args": ["-u", "s/\"type\":\"request\"/\"type\":\"response\"/"]}
+        });
+        std::fs::write(binding_dir.join(crate::server::subprocess::CONFIG_FILE), config.to_string()).unwrap();
+
+        let server = serve_all()
+            .with_fastn_home(fastn_home)
+            .protocol("echo.fastn.com", |p| p.handle_requests("echo", echo_request_handler));
+        let target = key.public_key();
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate()).with_loopback();
+        let request = serde_json::json!({"message": "hi"});
+        let call = async {
+            // Until the server is up and its helper has started
+            loop {
+                if crate::server::loopback::serves(&target) {
+                    let protocol = CommandProtocol::new("echo.fastn.com", "default", "echo");
+                    let reply = client.call::<_, _, serde_json::Value, serde_json::Value>(target, protocol, &request).await;
+                    if let Ok(Ok(reply)) = reply {
+                        return reply;
+                    }
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        };
+
+        let reply = tokio::time::timeout(std::time::Duration::from_secs(30), async {
+            tokio::select! {
+                result = server.serve() => panic!("serve() stopped: {:?}", result.err().map(|e| e.to_string())),
+                reply = call => reply,
+            }
+        })
+        .await
+        .unwrap();
+        // The in-process callback would have answered with `echoed`
+        assert_eq!(reply, request);
+    }
+
+    #[tokio::test]
+    async fn test_binding_config_sets_command_timeouts() {
+        fn stuck(
+            _identity: &str,
+            _bind_alias: &str,
+            _protocol: &str,
+            _command: &str,
+            _protocol_dir: &Path,
+            _peer: &fastn_id52::PublicKey,
+            _request: serde_json::Value,
+        ) -> Reply<serde_json::Value> {
+            Box::pin(std::future::pending())
+        }
+
+        let temp = tempfile::tempdir().unwrap();
+        let protocol_dir = temp.path().to_path_buf();
+        let config = serde_json::json!({ "timeout_secs": 3600, "command_timeout_secs": { "stuck": 1 } });
+        std::fs::write(protocol_dir.join(crate::server::subprocess::CONFIG_FILE), config.to_string()).unwrap();
+        let timeouts = crate::server::CommandTimeouts::load(&protocol_dir).await.unwrap();
+        assert_eq!(timeouts.for_command("other"), Some(std::time::Duration::from_secs(3600)));
+
+        // The binding's timeout wins over the builder's
+        let server = serve_all()
+            .protocol("echo.fastn.com", |p| p
+                .handle_requests("stuck", stuck)
+                .with_timeout("stuck", std::time::Duration::from_secs(3600)));
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let command = CommandProtocol::new("echo.fastn.com", "default", "stuck");
+        let error = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            server.dispatch_request(&peer, "alice", &command, &protocol_dir, serde_json::Value::Null, None),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        let timed_out = error.downcast_ref::<crate::server::builder::RequestTimeoutError>().unwrap();
+        assert_eq!(timed_out.timeout, std::time::Duration::from_secs(1));
+
+        std::fs::write(protocol_dir.join(crate::server::subprocess::CONFIG_FILE), "\"not an object\"").unwrap();
+        assert_eq!(crate::server::CommandTimeouts::load(&protocol_dir).await.unwrap(), Default::default());
+    }
+
+    #[tokio::test]
+    async fn test_exec_binding_commands_time_out_per_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let alice = fastn_home.join("identities").join("alice");
+        let key = fastn_id52::SecretKey::generate();
+        key.save_to_dir(&alice, "identity").unwrap();
+        let store = crate::server::state::StateStore::open(&fastn_home).await.unwrap();
+        store.set_online("alice", true).await.unwrap();
+        store.add_binding("alice", "echo.fastn.com", "default", &serde_json::json!({})).await.unwrap();
+
+        // Reads requests and never answers them
+        let binding_dir = alice.join("protocols").join("echo.fastn.com").join("default");
+        std::fs::create_dir_all(&binding_dir).unwrap();
+        let config = serde_json::json!({
+            "exec": {"command": "sh", "args": ["-c", "cat > /dev/null"]},
+            "timeout_secs": 1
+        });
+        std::fs::write(binding_dir.join(crate::server::subprocess::CONFIG_FILE), config.to_string()).unwrap();
+
+        let server = serve_all()
+            .with_fastn_home(fastn_home)
+            .protocol("echo.fastn.com", |p| p.handle_requests("echo", echo_request_handler));
+        let target = key.public_key();
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate()).with_loopback();
+        let call = async {
+            // Until the server is up and its helper is registered
+            loop {
+                if crate::server::loopback::serves(&target) {
+                    let protocol = CommandProtocol::new("echo.fastn.com", "default", "echo");
+                    let reply = client.call::<_, _, serde_json::Value, String>(target, protocol, "hi").await;
+                    if let Ok(Err(error)) = reply {
+                        return error;
+                    }
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        };
+
+        let error = tokio::time::timeout(std::time::Duration::from_secs(30), async {
+            tokio::select! {
+                result = server.serve() => panic!("serve() stopped: {:?}", result.err().map(|e| e.to_string())),
+                error = call => error,
+            }
+        })
+        .await
+        .unwrap();
+        assert!(error.contains("timed out"), "{error}");
+    }
+
+    #[test]
+    fn test_command_protocol_args_are_optional_on_the_wire() {
+        let plain = CommandProtocol::new("mail.fastn.com", "default", "get-mails");
+        assert_eq!(
+            serde_json::to_value(&plain).unwrap(),
+            serde_json::json!({"protocol": "mail.fastn.com", "bind_alias": "default", "command": "get-mails"})
+        );
+
+        let with_args = plain.clone().with_args(vec!["--unread".to_string()]);
+        let value = serde_json::to_value(&with_args).unwrap();
+        assert_eq!(value["args"], serde_json::json!(["--unread"]));
+        assert_eq!(serde_json::from_value::<CommandProtocol>(value).unwrap(), with_args);
+    }
+}
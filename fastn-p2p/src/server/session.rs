@@ -4,12 +4,31 @@ pub struct Session<PROTOCOL> {
     pub protocol: PROTOCOL,
     /// Stream to client (stdout)
     pub send: iroh::endpoint::SendStream,
-    /// Stream from client (stdin)  
-    pub recv: iroh::endpoint::RecvStream,
+    /// Stream from client (stdin), buffered so handlers can also read JSON lines
+    pub recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
     /// Peer's public key
     pub peer: fastn_id52::PublicKey,
+    /// Identity the peer opened the stream to
+    pub(crate) identity: fastn_id52::PublicKey,
     /// Context for this session (integration with fastn-context)
     pub context: std::sync::Arc<fastn_context::Context>,
+    /// Unreliable datagrams, if enabled for the protocol
+    pub datagrams: Option<crate::datagram::Datagrams>,
+    /// Helper tasks, cancelled and awaited once the handler returns
+    pub(crate) tasks: fastn_net::Scope,
+    /// Set before `tasks` is cancelled because the connection went away
+    pub(crate) close_reason: std::sync::Arc<std::sync::OnceLock<CloseReason>>,
+}
+
+/// Why [`Session::closed`] resolved
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CloseReason {
+    /// The peer closed the connection, or it was lost
+    #[error("Connection to the peer closed: {error}")]
+    ConnectionLost { error: iroh::endpoint::ConnectionError },
+    /// The server is shutting down, or the session's tasks were cancelled
+    #[error("Session cancelled")]
+    Cancelled,
 }
 
 impl<PROTOCOL> Session<PROTOCOL> {
@@ -23,6 +42,82 @@ impl<PROTOCOL> Session<PROTOCOL> {
         &self.context
     }
 
+    /// Resolves once the peer is gone or the session is cancelled
+    ///
+    /// The future doesn't borrow the session, so handlers can race it against
+    /// their own writes and stop producing data as soon as the peer leaves:
+    ///
+    /// ```rust,no_run
+    /// # use futures_util::StreamExt;
+    /// # async fn run(
+    /// #     mut session: fastn_p2p::Session<String>,
+    /// #     mut encoder: impl futures_util::Stream<Item = std::io::Result<Vec<u8>>> + Unpin,
+    /// # ) -> Result<(), Box<dyn std::error::Error>> {
+    /// let closed = session.closed();
+    /// tokio::pin!(closed);
+    /// loop {
+    ///     tokio::select! {
+    ///         reason = &mut closed => break,
+    ///         Some(frame) = encoder.next() => session.send.write_all(&frame?).await?,
+    ///         else => break,
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Relayed peers have no direct connection to watch; for them, and for a
+    /// peer that only stops this one stream, a failing write is still the
+    /// first sign.
+    pub fn closed(&self) -> impl std::future::Future<Output = CloseReason> + Send + 'static {
+        let tasks = self.tasks.clone();
+        let close_reason = self.close_reason.clone();
+        async move {
+            tasks.cancelled().await;
+            close_reason.get().cloned().unwrap_or(CloseReason::Cancelled)
+        }
+    }
+
+    /// Whether [`Self::closed`] has resolved
+    pub fn is_closed(&self) -> bool {
+        self.tasks.is_cancelled()
+    }
+
+    /// Spawn a helper task that ends with the session
+    ///
+    /// When the handler returns or the peer disconnects, tasks still running
+    /// are cancelled (dropped at their next await) and awaited before the
+    /// stream is considered done. Shutdown cancels them as well. The handle
+    /// yields `None` for a cancelled task.
+    #[track_caller]
+    pub fn spawn<F>(&self, task: F) -> tokio::task::JoinHandle<Option<F::Output>>
+    where
+        F: std::future::Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.spawn(task)
+    }
+
+    /// The scope of [`Self::spawn`], for helpers that spawn helpers of their own
+    pub fn tasks(&self) -> &fastn_net::Scope {
+        &self.tasks
+    }
+
+    /// Largest datagram [`Self::send_datagram`] takes right now, see [`crate::datagram`]
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.datagrams.as_ref().and_then(crate::datagram::Datagrams::max_size)
+    }
+
+    /// Send an unreliable datagram to the client
+    pub fn send_datagram(&self, payload: &[u8]) -> Result<(), crate::datagram::DatagramError> {
+        self.datagrams.as_ref().ok_or(crate::datagram::DatagramError::NotEnabled)?.send(payload)
+    }
+
+    /// Wait for the next datagram from the client
+    pub async fn recv_datagram(&mut self) -> Result<Vec<u8>, crate::datagram::DatagramError> {
+        self.datagrams.as_mut().ok_or(crate::datagram::DatagramError::NotEnabled)?.recv().await
+    }
+
     /// Convert to Request for RPC handling (consumes Session)
     pub fn into_request(self) -> super::request::Request<PROTOCOL> {
         // TODO: Convert Session to Request for RPC pattern
@@ -79,22 +174,86 @@ impl<PROTOCOL> Session<PROTOCOL> {
 
         futures_util::try_join!(to_remote, from_remote)
     }
+
+    /// Receive a stream sent with `copy_from_verified` into `writer` and check its checksum
+    ///
+    /// Fails with [`crate::checksum::VerifyError::ChecksumMismatch`] if the data
+    /// doesn't hash to what the client computed, see [`crate::checksum`].
+    pub async fn copy_to_verified<W>(&mut self, mut writer: W) -> Result<u64, crate::checksum::VerifyError>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        crate::checksum::receive(&mut self.recv, &mut writer).await
+    }
+
+    /// Send `reader` followed by its BLAKE3 checksum, for `copy_to_verified` on the client
+    pub async fn copy_from_verified<R>(&mut self, mut reader: R) -> std::io::Result<u64>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        crate::checksum::send(&mut reader, &mut self.send).await
+    }
+
+    /// [`Self::copy_to`], reporting to `observer`, see [`crate::progress`]
+    pub async fn copy_to_with_progress<W>(
+        &mut self,
+        mut writer: W,
+        observer: &crate::progress::Observer,
+    ) -> std::io::Result<u64>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        crate::progress::copy(&mut self.recv, &mut writer, observer).await
+    }
+
+    /// [`Self::copy_from`], reporting to `observer`, see [`crate::progress`]
+    pub async fn copy_from_with_progress<R>(
+        &mut self,
+        mut reader: R,
+        observer: &crate::progress::Observer,
+    ) -> std::io::Result<u64>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        crate::progress::copy(&mut reader, &mut self.send, observer).await
+    }
+
+    /// [`Self::copy_both`], reporting each direction to its own observer
+    pub async fn copy_both_with_progress<R, W>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+        upload: &crate::progress::Observer,
+        download: &crate::progress::Observer,
+    ) -> std::io::Result<(u64, u64)>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let to_remote = crate::progress::copy(&mut reader, &mut self.send, upload);
+        let from_remote = crate::progress::copy(&mut self.recv, &mut writer, download);
+
+        futures_util::try_join!(to_remote, from_remote)
+    }
 }
 
-/// Create a new Session (used internally by listener)
-pub(crate) fn create_session<PROTOCOL>(
-    protocol: PROTOCOL,
-    send: iroh::endpoint::SendStream,
-    recv: iroh::endpoint::RecvStream,
-    peer: fastn_id52::PublicKey,
-    parent_context: &std::sync::Arc<fastn_context::Context>,
-) -> Session<PROTOCOL> {
-    // Use parent context for now (can create child context later)
-    Session {
-        protocol,
-        send,
-        recv,
-        peer,
-        context: parent_context.clone(),
+impl<PROTOCOL: serde::Serialize> Session<PROTOCOL> {
+    /// Wait until the user accepts this stream, see [`super::approvals`]
+    ///
+    /// Call it before reading or writing the payload. Fails when the user
+    /// denies it, nobody decides within [`super::approvals::APPROVAL_TIMEOUT`]
+    /// or the peer goes away meanwhile; the handler should then return.
+    pub async fn request_approval(&self, summary: impl Into<String>) -> Result<(), super::approvals::ApprovalError> {
+        let protocol = serde_json::to_value(&self.protocol).unwrap_or_default();
+        super::approvals::request(
+            self.identity,
+            self.peer,
+            crate::metrics::protocol_name(&protocol),
+            summary.into(),
+            super::approvals::APPROVAL_TIMEOUT,
+            self.closed(),
+            super::approvals::shared_dir(),
+        )
+        .await
     }
 }
@@ -0,0 +1,1159 @@
+//! Daemon state kept in `FASTN_HOME/state.db`
+//!
+//! Which identities are online, which protocol bindings exist, each
+//! identity's address book and its outbox of messages waiting for a peer all
+//! live in one SQLite database. The CLI and the daemon both go through
+//! [`StateStore`], and every update is a transaction. A reader never sees
+//! half of a change, and two writers can't both add the same binding.
+//!
+//! An address book maps names to peers, and a peer group names a list of
+//! them, so `fastn-p2p call team-a ...` reaches every member. Group members
+//! are contact names or ID52s, looked up each time the group is used.
+//!
+//! Outbox messages carry a random message ID that stays the same across
+//! retries, and a delivered message keeps its receipt instead of being
+//! dropped. The receiving side remembers the message IDs it applied, with its
+//! answer, so a retried delivery gets that answer again instead of being
+//! applied twice; see [`crate::server::delivery`].
+//!
+//! Usage counts (see [`crate::server::usage`]) are kept per day, and per
+//! month once the day is older than the daemon keeps days for.
+//!
+//! The schema is versioned with `PRAGMA user_version` and brought up to date
+//! by [`StateStore::open`]. The first migration imports the layout from
+//! before the store: `identities/<alias>/online` markers, the `online` flag of
+//! `identities/<alias>.config.json`, and the bindings that have a
+//! `protocols/<protocol>/<bind_alias>/config.json`. From then on those
+//! markers are ignored. The binding's `config.json` stays next to its data as
+//! the protocol's own config.
+
+use std::path::{Path, PathBuf};
+
+/// State database inside FASTN_HOME
+pub const STATE_DB_FILE: &str = "state.db";
+
+/// How long a write waits for another process holding the database
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Schema migrations; `MIGRATIONS[n]` takes the schema from version `n` to `n + 1`
+const MIGRATIONS: &[fn(&rusqlite::Transaction, &Path) -> rusqlite::Result<()>] =
+    &[create_schema, create_peer_groups, add_delivery_receipts, create_usage];
+
+/// A message claimed for applying but not done by then was dropped by a crashed daemon
+const CLAIM_TIMEOUT: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("Failed to open state store {}: {source}", path.display())]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+
+    #[error("Failed to migrate state store to version {version}: {source}")]
+    Migration {
+        version: usize,
+        #[source]
+        source: rusqlite::Error,
+    },
+
+    #[error("State store error: {source}")]
+    Storage {
+        #[source]
+        source: rusqlite::Error,
+    },
+
+    #[error("State store task failed: {source}")]
+    Task {
+        #[source]
+        source: tokio::task::JoinError,
+    },
+
+    #[error("Protocol binding '{protocol}' as '{bind_alias}' already exists for identity '{identity}'")]
+    BindingExists { identity: String, protocol: String, bind_alias: String },
+
+    #[error("Protocol binding '{protocol}' as '{bind_alias}' not found for identity '{identity}'")]
+    BindingNotFound { identity: String, protocol: String, bind_alias: String },
+
+    #[error("Member '{member}' of peer group '{group}' is neither a contact nor a peer ID52")]
+    UnknownMember { group: String, member: String },
+}
+
+impl From<rusqlite::Error> for StateError {
+    fn from(source: rusqlite::Error) -> Self {
+        StateError::Storage { source }
+    }
+}
+
+/// A protocol binding of an identity
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BindingRecord {
+    pub protocol: String,
+    pub bind_alias: String,
+    /// Config given when the binding was added
+    pub config: serde_json::Value,
+}
+
+/// A named peer in an identity's address book
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub name: String,
+    pub peer: fastn_id52::PublicKey,
+}
+
+/// A named list of peers in an identity's address book
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PeerGroup {
+    pub name: String,
+    /// Contact names or ID52s, in the order they were given
+    pub members: Vec<String>,
+}
+
+/// A message waiting in an identity's outbox
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub id: i64,
+    /// Sent with every delivery attempt, see [`crate::server::delivery::MESSAGE_ID_METADATA`]
+    pub message_id: String,
+    pub peer: fastn_id52::PublicKey,
+    /// Protocol as sent to the peer: its JSON, or a plain protocol name
+    pub protocol: String,
+    pub payload: serde_json::Value,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Delivery attempts that failed so far
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Where an outbox message is, see [`StateStore::delivery`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum DeliveryStatus {
+    /// Not delivered yet; retried until it is
+    Pending { attempts: u32, last_error: Option<String> },
+    /// The peer applied it and answered `response`
+    Delivered { delivered_at: chrono::DateTime<chrono::Utc>, response: serde_json::Value },
+}
+
+/// An outbox message with its delivery status
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Delivery {
+    pub message_id: String,
+    pub peer: String,
+    pub protocol: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(flatten)]
+    pub status: DeliveryStatus,
+}
+
+/// What the receiving side knows about a message ID, see [`StateStore::claim_message`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageClaim {
+    /// First time: apply it, then [`StateStore::message_applied`] or [`StateStore::release_message`]
+    New,
+    /// Applied before; this is the answer it got
+    Applied(serde_json::Value),
+    /// Being applied by another delivery right now
+    InProgress,
+}
+
+/// Typed access to `FASTN_HOME/state.db`
+///
+/// Cheap to clone; every call opens its own connection on a blocking thread,
+/// so the store can be used from any task and any process.
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+    fastn_home: PathBuf,
+}
+
+impl StateStore {
+    /// Open the store of `fastn_home`, creating and migrating it as needed
+    pub async fn open(fastn_home: &Path) -> Result<Self, StateError> {
+        let store = Self {
+            path: fastn_home.join(STATE_DB_FILE),
+            fastn_home: fastn_home.to_path_buf(),
+        };
+        let migrating = store.clone();
+        tokio::task::spawn_blocking(move || migrating.migrate())
+            .await
+            .map_err(|source| StateError::Task { source })??;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Run `update` in one transaction; it commits only if `update` succeeds
+    ///
+    /// The transaction takes the write lock up front, so read-check-write
+    /// sequences inside it can't interleave with another writer.
+    pub async fn transaction<T, F>(&self, update: F) -> Result<T, StateError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&rusqlite::Transaction) -> Result<T, StateError> + Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = store.connect()?;
+            let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+            let result = update(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await
+        .map_err(|source| StateError::Task { source })?
+    }
+
+    /// Set `identity` online or offline; returns whether that changed anything
+    pub async fn set_online(&self, identity: &str, online: bool) -> Result<bool, StateError> {
+        let identity = identity.to_string();
+        self.transaction(move |tx| {
+            if identity_online(tx, &identity)? == online {
+                return Ok(false);
+            }
+            tx.execute(
+                "INSERT INTO identities (alias, online, updated_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (alias) DO UPDATE SET online = ?2, updated_at = ?3",
+                rusqlite::params![identity, online, chrono::Utc::now().to_rfc3339()],
+            )?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Whether `identity` is online; identities never set online are offline
+    pub async fn is_online(&self, identity: &str) -> Result<bool, StateError> {
+        let identity = identity.to_string();
+        self.transaction(move |tx| Ok(identity_online(tx, &identity)?)).await
+    }
+
+    /// Online state of every identity the store knows about
+    pub async fn identity_states(&self) -> Result<std::collections::BTreeMap<String, bool>, StateError> {
+        self.transaction(|tx| {
+            let mut statement = tx.prepare("SELECT alias, online FROM identities")?;
+            let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            Ok(rows.collect::<Result<_, rusqlite::Error>>()?)
+        })
+        .await
+    }
+
+    /// Register a binding; fails with [`StateError::BindingExists`] if it already is
+    pub async fn add_binding(
+        &self,
+        identity: &str,
+        protocol: &str,
+        bind_alias: &str,
+        config: &serde_json::Value,
+    ) -> Result<(), StateError> {
+        let (identity, protocol, bind_alias) = (identity.to_string(), protocol.to_string(), bind_alias.to_string());
+        let config = config.to_string();
+        self.transaction(move |tx| {
+            let inserted = tx.execute(
+                "INSERT INTO bindings (identity, protocol, bind_alias, config, created_at) VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT DO NOTHING",
+                rusqlite::params![identity, protocol, bind_alias, config, chrono::Utc::now().to_rfc3339()],
+            )?;
+            if inserted == 0 {
+                return Err(StateError::BindingExists { identity, protocol, bind_alias });
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Unregister a binding; fails with [`StateError::BindingNotFound`] if it isn't
+    pub async fn remove_binding(&self, identity: &str, protocol: &str, bind_alias: &str) -> Result<(), StateError> {
+        let (identity, protocol, bind_alias) = (identity.to_string(), protocol.to_string(), bind_alias.to_string());
+        self.transaction(move |tx| {
+            let removed = tx.execute(
+                "DELETE FROM bindings WHERE identity = ?1 AND protocol = ?2 AND bind_alias = ?3",
+                rusqlite::params![identity, protocol, bind_alias],
+            )?;
+            if removed == 0 {
+                return Err(StateError::BindingNotFound { identity, protocol, bind_alias });
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Bindings of `identity`, ordered by protocol and bind alias
+    pub async fn bindings(&self, identity: &str) -> Result<Vec<BindingRecord>, StateError> {
+        let identity = identity.to_string();
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "SELECT protocol, bind_alias, config FROM bindings WHERE identity = ?1 ORDER BY protocol, bind_alias",
+            )?;
+            let rows = statement.query_map([&identity], |row| {
+                let config: String = row.get(2)?;
+                Ok(BindingRecord {
+                    protocol: row.get(0)?,
+                    bind_alias: row.get(1)?,
+                    config: serde_json::from_str(&config).unwrap_or(serde_json::Value::Null),
+                })
+            })?;
+            Ok(rows.collect::<Result<_, rusqlite::Error>>()?)
+        })
+        .await
+    }
+
+    /// Add `name` to the address book of `identity`, replacing an entry of that name
+    pub async fn set_contact(&self, identity: &str, name: &str, peer: &fastn_id52::PublicKey) -> Result<(), StateError> {
+        let (identity, name, peer) = (identity.to_string(), name.to_string(), peer.id52());
+        self.transaction(move |tx| {
+            tx.execute(
+                "INSERT INTO contacts (identity, name, peer) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (identity, name) DO UPDATE SET peer = ?3",
+                rusqlite::params![identity, name, peer],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove `name` from the address book; returns whether it was there
+    pub async fn remove_contact(&self, identity: &str, name: &str) -> Result<bool, StateError> {
+        let (identity, name) = (identity.to_string(), name.to_string());
+        self.transaction(move |tx| {
+            let removed = tx.execute(
+                "DELETE FROM contacts WHERE identity = ?1 AND name = ?2",
+                rusqlite::params![identity, name],
+            )?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    /// Address book of `identity`, by name
+    pub async fn contacts(&self, identity: &str) -> Result<Vec<Contact>, StateError> {
+        let identity = identity.to_string();
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare("SELECT name, peer FROM contacts WHERE identity = ?1 ORDER BY name")?;
+            let rows = statement.query_map([&identity], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+            let mut contacts = Vec::new();
+            for row in rows {
+                let (name, peer) = row?;
+                match peer.parse() {
+                    Ok(peer) => contacts.push(Contact { name, peer }),
+                    Err(e) => tracing::warn!("Skipping contact '{}' with invalid peer {}: {}", name, peer, e),
+                }
+            }
+            Ok(contacts)
+        })
+        .await
+    }
+
+    /// Define group `name` of `identity` as `members`, replacing an existing group of that name
+    ///
+    /// Each member must be a contact of `identity` or an ID52, else this
+    /// fails with [`StateError::UnknownMember`] and nothing changes.
+    pub async fn set_group(&self, identity: &str, name: &str, members: &[String]) -> Result<(), StateError> {
+        let (identity, name, members) = (identity.to_string(), name.to_string(), members.to_vec());
+        self.transaction(move |tx| {
+            for member in &members {
+                resolve_member(tx, &identity, &name, member)?;
+            }
+            tx.execute("DELETE FROM peer_groups WHERE identity = ?1 AND name = ?2", rusqlite::params![identity, name])?;
+            for (position, member) in members.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO peer_groups (identity, name, position, member) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT DO NOTHING",
+                    rusqlite::params![identity, name, position, member],
+                )?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove group `name`; returns whether it was there
+    pub async fn remove_group(&self, identity: &str, name: &str) -> Result<bool, StateError> {
+        let (identity, name) = (identity.to_string(), name.to_string());
+        self.transaction(move |tx| {
+            let removed = tx.execute(
+                "DELETE FROM peer_groups WHERE identity = ?1 AND name = ?2",
+                rusqlite::params![identity, name],
+            )?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    /// Peer groups of `identity`, by name
+    pub async fn groups(&self, identity: &str) -> Result<Vec<PeerGroup>, StateError> {
+        let identity = identity.to_string();
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "SELECT name, member FROM peer_groups WHERE identity = ?1 ORDER BY name, position",
+            )?;
+            let rows = statement.query_map([&identity], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+            let mut groups: Vec<PeerGroup> = Vec::new();
+            for row in rows {
+                let (name, member) = row?;
+                match groups.last_mut() {
+                    Some(group) if group.name == name => group.members.push(member),
+                    _ => groups.push(PeerGroup { name, members: vec![member] }),
+                }
+            }
+            Ok(groups)
+        })
+        .await
+    }
+
+    /// Members of group `name` with their peers as of now; `None` if there is no such group
+    ///
+    /// A member that is an ID52 is named by it. Fails with
+    /// [`StateError::UnknownMember`] if a member's contact was removed since.
+    pub async fn group_members(&self, identity: &str, name: &str) -> Result<Option<Vec<Contact>>, StateError> {
+        let (identity, name) = (identity.to_string(), name.to_string());
+        self.transaction(move |tx| {
+            let mut statement =
+                tx.prepare("SELECT member FROM peer_groups WHERE identity = ?1 AND name = ?2 ORDER BY position")?;
+            let members = statement
+                .query_map(rusqlite::params![identity, name], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+            if members.is_empty() {
+                return Ok(None);
+            }
+            let members = members
+                .into_iter()
+                .map(|member| {
+                    let peer = resolve_member(tx, &identity, &name, &member)?;
+                    Ok(Contact { name: member, peer })
+                })
+                .collect::<Result<_, StateError>>()?;
+            Ok(Some(members))
+        })
+        .await
+    }
+
+    /// Queue `payload` for `peer` under a new message ID
+    pub async fn enqueue(
+        &self,
+        identity: &str,
+        peer: &fastn_id52::PublicKey,
+        protocol: &str,
+        payload: &serde_json::Value,
+    ) -> Result<OutboxEntry, StateError> {
+        let message_id: String = rand::random::<[u8; 16]>().iter().map(|b| format!("{b:02x}")).collect();
+        let created_at = chrono::Utc::now();
+        let (identity, peer_id52, protocol_name) = (identity.to_string(), peer.id52(), protocol.to_string());
+        let payload_json = payload.to_string();
+        let stored_id = message_id.clone();
+        let id = self
+            .transaction(move |tx| {
+                tx.execute(
+                    "INSERT INTO outbox (identity, message_id, peer, protocol, payload, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    rusqlite::params![identity, stored_id, peer_id52, protocol_name, payload_json, created_at.to_rfc3339()],
+                )?;
+                Ok(tx.last_insert_rowid())
+            })
+            .await?;
+        Ok(OutboxEntry {
+            id,
+            message_id,
+            peer: *peer,
+            protocol: protocol.to_string(),
+            payload: payload.clone(),
+            created_at,
+            attempts: 0,
+            last_error: None,
+        })
+    }
+
+    /// Messages of `identity` not delivered yet, oldest first
+    pub async fn outbox(&self, identity: &str) -> Result<Vec<OutboxEntry>, StateError> {
+        let identity = identity.to_string();
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "SELECT id, message_id, peer, protocol, payload, created_at, attempts, last_error FROM outbox
+                 WHERE identity = ?1 AND delivered_at IS NULL ORDER BY id",
+            )?;
+            let rows = statement.query_map([&identity], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, u32>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                ))
+            })?;
+
+            let mut entries = Vec::new();
+            for row in rows {
+                let (id, message_id, peer, protocol, payload, created_at, attempts, last_error) = row?;
+                let Ok(peer) = peer.parse() else {
+                    tracing::warn!("Skipping outbox entry {} with invalid peer {}", id, peer);
+                    continue;
+                };
+                entries.push(OutboxEntry {
+                    id,
+                    message_id,
+                    peer,
+                    protocol,
+                    payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::Null),
+                    created_at: parse_time(&created_at),
+                    attempts,
+                    last_error,
+                });
+            }
+            Ok(entries)
+        })
+        .await
+    }
+
+    /// Record the peer's receipt of an outbox entry: it applied the message and answered `response`
+    pub async fn delivered(&self, id: i64, response: &serde_json::Value) -> Result<(), StateError> {
+        let response = response.to_string();
+        self.transaction(move |tx| {
+            tx.execute(
+                "UPDATE outbox SET delivered_at = ?2, receipt = ?3 WHERE id = ?1",
+                rusqlite::params![id, chrono::Utc::now().to_rfc3339(), response],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Record a failed delivery attempt of an outbox entry
+    pub async fn delivery_failed(&self, id: i64, error: &str) -> Result<(), StateError> {
+        let error = error.to_string();
+        self.transaction(move |tx| {
+            tx.execute(
+                "UPDATE outbox SET attempts = attempts + 1, last_error = ?2 WHERE id = ?1",
+                rusqlite::params![id, error],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Delivery status of message `message_id` sent by `identity`
+    pub async fn delivery(&self, identity: &str, message_id: &str) -> Result<Option<Delivery>, StateError> {
+        let (identity, message_id) = (identity.to_string(), message_id.to_string());
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(&format!("{DELIVERY_SELECT} WHERE identity = ?1 AND message_id = ?2"))?;
+            let mut rows = statement.query_map([&identity, &message_id], delivery_row)?;
+            Ok(rows.next().transpose()?)
+        })
+        .await
+    }
+
+    /// Delivery status of every message `identity` sent, oldest first
+    pub async fn deliveries(&self, identity: &str) -> Result<Vec<Delivery>, StateError> {
+        let identity = identity.to_string();
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(&format!("{DELIVERY_SELECT} WHERE identity = ?1 ORDER BY id"))?;
+            let rows = statement.query_map([&identity], delivery_row)?;
+            Ok(rows.collect::<Result<_, rusqlite::Error>>()?)
+        })
+        .await
+    }
+
+    /// Claim message `message_id` from `sender` to `identity` before applying it
+    ///
+    /// A claim left by a delivery that never finished is taken over after
+    /// [`CLAIM_TIMEOUT`].
+    pub async fn claim_message(
+        &self,
+        identity: &str,
+        sender: &fastn_id52::PublicKey,
+        message_id: &str,
+    ) -> Result<MessageClaim, StateError> {
+        let (identity, sender, message_id) = (identity.to_string(), sender.id52(), message_id.to_string());
+        self.transaction(move |tx| {
+            let now = chrono::Utc::now();
+            let seen = tx.query_row(
+                "SELECT received_at, response FROM inbox_receipts WHERE identity = ?1 AND sender = ?2 AND message_id = ?3",
+                [&identity, &sender, &message_id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+            );
+            match seen {
+                Ok((_, Some(response))) => {
+                    return Ok(MessageClaim::Applied(serde_json::from_str(&response).unwrap_or(serde_json::Value::Null)));
+                }
+                Ok((received_at, None)) if now - parse_time(&received_at) < CLAIM_TIMEOUT => {
+                    return Ok(MessageClaim::InProgress);
+                }
+                Ok(_) | Err(rusqlite::Error::QueryReturnedNoRows) => {}
+                Err(e) => return Err(e.into()),
+            }
+            tx.execute(
+                "INSERT INTO inbox_receipts (identity, sender, message_id, received_at) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (identity, sender, message_id) DO UPDATE SET received_at = ?4",
+                rusqlite::params![identity, sender, message_id, now.to_rfc3339()],
+            )?;
+            Ok(MessageClaim::New)
+        })
+        .await
+    }
+
+    /// Keep the answer of a claimed message, given again to every later delivery of it
+    pub async fn message_applied(
+        &self,
+        identity: &str,
+        sender: &fastn_id52::PublicKey,
+        message_id: &str,
+        response: &serde_json::Value,
+    ) -> Result<(), StateError> {
+        let (identity, sender, message_id) = (identity.to_string(), sender.id52(), message_id.to_string());
+        let response = response.to_string();
+        self.transaction(move |tx| {
+            tx.execute(
+                "UPDATE inbox_receipts SET response = ?4 WHERE identity = ?1 AND sender = ?2 AND message_id = ?3",
+                rusqlite::params![identity, sender, message_id, response],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Drop the claim of a message that failed to apply, so a retry can apply it
+    pub async fn release_message(
+        &self,
+        identity: &str,
+        sender: &fastn_id52::PublicKey,
+        message_id: &str,
+    ) -> Result<(), StateError> {
+        let (identity, sender, message_id) = (identity.to_string(), sender.id52(), message_id.to_string());
+        self.transaction(move |tx| {
+            tx.execute(
+                "DELETE FROM inbox_receipts
+                 WHERE identity = ?1 AND sender = ?2 AND message_id = ?3 AND response IS NULL",
+                [&identity, &sender, &message_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn connect(&self) -> Result<rusqlite::Connection, StateError> {
+        let open = || -> rusqlite::Result<rusqlite::Connection> {
+            let conn = rusqlite::Connection::open(&self.path)?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            // Readers don't block the writer, e.g. `fastn-p2p status` during a daemon update
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            Ok(conn)
+        };
+        open().map_err(|source| StateError::Open { path: self.path.clone(), source })
+    }
+
+    /// Add usage counts to the days they belong to; returns the number of rows
+    pub async fn add_usage(
+        &self,
+        rows: Vec<(crate::server::usage::UsageKey, crate::server::usage::Usage)>,
+    ) -> Result<usize, StateError> {
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "INSERT INTO usage_daily (day, identity, peer, protocol, requests, bytes_in, bytes_out)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (day, identity, peer, protocol) DO UPDATE SET
+                    requests = requests + excluded.requests,
+                    bytes_in = bytes_in + excluded.bytes_in,
+                    bytes_out = bytes_out + excluded.bytes_out",
+            )?;
+            for (key, usage) in &rows {
+                statement.execute(rusqlite::params![
+                    key.day, key.identity, key.peer, key.protocol,
+                    usage.requests as i64, usage.bytes_in as i64, usage.bytes_out as i64,
+                ])?;
+            }
+            Ok(rows.len())
+        })
+        .await
+    }
+
+    /// Fold the days before `before` into their months; returns how many days were folded
+    pub async fn roll_up_usage(&self, before: chrono::NaiveDate) -> Result<usize, StateError> {
+        let before = before.format("%Y-%m-%d").to_string();
+        self.transaction(move |tx| {
+            tx.execute(
+                "INSERT INTO usage_monthly (month, identity, peer, protocol, requests, bytes_in, bytes_out)
+                 SELECT substr(day, 1, 7), identity, peer, protocol, SUM(requests), SUM(bytes_in), SUM(bytes_out)
+                    FROM usage_daily WHERE day < ?1
+                    GROUP BY substr(day, 1, 7), identity, peer, protocol
+                 ON CONFLICT (month, identity, peer, protocol) DO UPDATE SET
+                    requests = requests + excluded.requests,
+                    bytes_in = bytes_in + excluded.bytes_in,
+                    bytes_out = bytes_out + excluded.bytes_out",
+                [&before],
+            )?;
+            let days = tx.query_row(
+                "SELECT COUNT(DISTINCT day) FROM usage_daily WHERE day < ?1",
+                [&before],
+                |row| row.get::<_, i64>(0),
+            )?;
+            tx.execute("DELETE FROM usage_daily WHERE day < ?1", [&before])?;
+            Ok(days as usize)
+        })
+        .await
+    }
+
+    /// Usage in `month` (`YYYY-MM`) per identity, peer and protocol, of `identity` or all identities
+    pub async fn usage(
+        &self,
+        identity: Option<&str>,
+        month: &str,
+    ) -> Result<Vec<crate::server::usage::UsageRow>, StateError> {
+        let (identity, month) = (identity.map(str::to_string), month.to_string());
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(&format!(
+                "SELECT identity, peer, protocol, SUM(requests), SUM(bytes_in), SUM(bytes_out) FROM ({USAGE_OF_MONTH})
+                 GROUP BY identity, peer, protocol ORDER BY identity, peer, protocol"
+            ))?;
+            let rows = statement.query_map(rusqlite::params![month, identity], |row| {
+                Ok(crate::server::usage::UsageRow {
+                    identity: row.get(0)?,
+                    peer: row.get(1)?,
+                    protocol: row.get(2)?,
+                    usage: usage_columns(row, 3)?,
+                })
+            })?;
+            Ok(rows.collect::<Result<_, rusqlite::Error>>()?)
+        })
+        .await
+    }
+
+    /// Usage per day of `month` still kept by day, of `identity` or all identities
+    pub async fn daily_usage(
+        &self,
+        identity: Option<&str>,
+        month: &str,
+    ) -> Result<Vec<crate::server::usage::DailyUsage>, StateError> {
+        let (identity, month) = (identity.map(str::to_string), month.to_string());
+        self.transaction(move |tx| {
+            let mut statement = tx.prepare(&format!(
+                "SELECT day, SUM(requests), SUM(bytes_in), SUM(bytes_out) FROM ({USAGE_OF_MONTH})
+                 WHERE day IS NOT NULL GROUP BY day ORDER BY day"
+            ))?;
+            let rows = statement.query_map(rusqlite::params![month, identity], |row| {
+                Ok(crate::server::usage::DailyUsage { day: row.get(0)?, usage: usage_columns(row, 1)? })
+            })?;
+            Ok(rows.collect::<Result<_, rusqlite::Error>>()?)
+        })
+        .await
+    }
+
+    /// Apply the migrations this database hasn't seen yet, each in its own transaction
+    fn migrate(&self) -> Result<(), StateError> {
+        let mut conn = self.connect()?;
+        for (version, migration) in MIGRATIONS.iter().enumerate() {
+            let migrated = |source| StateError::Migration { version: version + 1, source };
+            let tx = conn
+                .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+                .map_err(migrated)?;
+            // Another process may have migrated while this one waited for the lock
+            let current: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(migrated)?;
+            if current > version {
+                continue;
+            }
+            migration(&tx, &self.fastn_home).map_err(migrated)?;
+            tx.pragma_update(None, "user_version", version + 1).map_err(migrated)?;
+            tx.commit().map_err(migrated)?;
+            tracing::info!("Migrated {} to version {}", self.path.display(), version + 1);
+        }
+        Ok(())
+    }
+}
+
+/// Usage columns summed over the days of month `?1` and the month's rollup, for identity `?2` or all
+const USAGE_OF_MONTH: &str = "
+    SELECT day, identity, peer, protocol, requests, bytes_in, bytes_out FROM usage_daily
+        WHERE substr(day, 1, 7) = ?1 AND (?2 IS NULL OR identity = ?2)
+    UNION ALL
+    SELECT NULL, identity, peer, protocol, requests, bytes_in, bytes_out FROM usage_monthly
+        WHERE month = ?1 AND (?2 IS NULL OR identity = ?2)";
+
+const DELIVERY_SELECT: &str =
+    "SELECT message_id, peer, protocol, created_at, attempts, last_error, delivered_at, receipt FROM outbox";
+
+/// One row of [`DELIVERY_SELECT`]
+fn delivery_row(row: &rusqlite::Row) -> rusqlite::Result<Delivery> {
+    let status = match row.get::<_, Option<String>>(6)? {
+        Some(delivered_at) => DeliveryStatus::Delivered {
+            delivered_at: parse_time(&delivered_at),
+            response: row
+                .get::<_, Option<String>>(7)?
+                .and_then(|receipt| serde_json::from_str(&receipt).ok())
+                .unwrap_or(serde_json::Value::Null),
+        },
+        None => DeliveryStatus::Pending { attempts: row.get(4)?, last_error: row.get(5)? },
+    };
+    Ok(Delivery {
+        message_id: row.get(0)?,
+        peer: row.get(1)?,
+        protocol: row.get(2)?,
+        created_at: parse_time(&row.get::<_, String>(3)?),
+        status,
+    })
+}
+
+/// Requests, bytes in and bytes out from column `first` on
+fn usage_columns(row: &rusqlite::Row, first: usize) -> rusqlite::Result<crate::server::usage::Usage> {
+    Ok(crate::server::usage::Usage {
+        requests: row.get::<_, i64>(first)? as u64,
+        bytes_in: row.get::<_, i64>(first + 1)? as u64,
+        bytes_out: row.get::<_, i64>(first + 2)? as u64,
+    })
+}
+
+fn parse_time(rfc3339: &str) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::parse_from_rfc3339(rfc3339)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .unwrap_or_default()
+}
+
+fn identity_online(tx: &rusqlite::Transaction, identity: &str) -> rusqlite::Result<bool> {
+    match tx.query_row("SELECT online FROM identities WHERE alias = ?1", [identity], |row| row.get(0)) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+        result => result,
+    }
+}
+
+/// Peer of a group member: the contact of that name, else the member as an ID52
+fn resolve_member(
+    tx: &rusqlite::Transaction,
+    identity: &str,
+    group: &str,
+    member: &str,
+) -> Result<fastn_id52::PublicKey, StateError> {
+    let contact = match tx.query_row(
+        "SELECT peer FROM contacts WHERE identity = ?1 AND name = ?2",
+        [identity, member],
+        |row| row.get::<_, String>(0),
+    ) {
+        Ok(peer) => Some(peer),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e.into()),
+    };
+    contact
+        .as_deref()
+        .unwrap_or(member)
+        .parse()
+        .map_err(|_| StateError::UnknownMember { group: group.to_string(), member: member.to_string() })
+}
+
+/// Version 1: the tables, filled from the marker files and binding configs on disk
+fn create_schema(tx: &rusqlite::Transaction, fastn_home: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE identities (
+            alias TEXT PRIMARY KEY,
+            online INTEGER NOT NULL DEFAULT 0,
+            updated_at TEXT NOT NULL
+        );
+        CREATE TABLE bindings (
+            identity TEXT NOT NULL,
+            protocol TEXT NOT NULL,
+            bind_alias TEXT NOT NULL,
+            config TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (identity, protocol, bind_alias)
+        );
+        CREATE TABLE contacts (
+            identity TEXT NOT NULL,
+            name TEXT NOT NULL,
+            peer TEXT NOT NULL,
+            PRIMARY KEY (identity, name)
+        );
+        CREATE TABLE outbox (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            identity TEXT NOT NULL,
+            peer TEXT NOT NULL,
+            protocol TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT
+        );
+        CREATE INDEX outbox_identity_id ON outbox (identity, id);",
+    )?;
+
+    let now = chrono::Utc::now().to_rfc3339();
+    for (identity, identity_dir) in subdirectories(&fastn_home.join("identities")) {
+        let online = identity_dir.join("online").exists();
+        tx.execute(
+            "INSERT INTO identities (alias, online, updated_at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![identity, online, now],
+        )?;
+
+        for (protocol, protocol_root) in subdirectories(&identity_dir.join("protocols")) {
+            for (bind_alias, protocol_dir) in subdirectories(&protocol_root) {
+                let Ok(config) = std::fs::read_to_string(protocol_dir.join("config.json")) else {
+                    continue;
+                };
+                tx.execute(
+                    "INSERT INTO bindings (identity, protocol, bind_alias, config, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    rusqlite::params![identity, protocol, bind_alias, config, now],
+                )?;
+            }
+        }
+    }
+
+    // Identities with only `identities/<alias>.private-key` kept their state in `<alias>.config.json`
+    let Ok(entries) = std::fs::read_dir(fastn_home.join("identities")) else {
+        return Ok(());
+    };
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        let Some(identity) = path.file_name().and_then(|name| name.to_str()?.strip_suffix(".config.json")) else {
+            continue;
+        };
+        let Ok(config) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let online = serde_json::from_str::<serde_json::Value>(&config)
+            .ok()
+            .and_then(|config| config.get("online")?.as_bool())
+            .unwrap_or(true);
+        tx.execute(
+            "INSERT INTO identities (alias, online, updated_at) VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING",
+            rusqlite::params![identity, online, now],
+        )?;
+    }
+    Ok(())
+}
+
+/// Version 2: named peer groups of each identity's address book
+fn create_peer_groups(tx: &rusqlite::Transaction, _fastn_home: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE peer_groups (
+            identity TEXT NOT NULL,
+            name TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            member TEXT NOT NULL,
+            PRIMARY KEY (identity, name, member)
+        );",
+    )
+}
+
+/// Version 3: message IDs and receipts for the outbox, applied message IDs for the receiving side
+fn add_delivery_receipts(tx: &rusqlite::Transaction, _fastn_home: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE outbox ADD COLUMN message_id TEXT;
+        UPDATE outbox SET message_id = lower(hex(randomblob(16)));
+        CREATE UNIQUE INDEX outbox_message_id ON outbox (message_id);
+        ALTER TABLE outbox ADD COLUMN delivered_at TEXT;
+        ALTER TABLE outbox ADD COLUMN receipt TEXT;
+        CREATE TABLE inbox_receipts (
+            identity TEXT NOT NULL,
+            sender TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            received_at TEXT NOT NULL,
+            response TEXT,
+            PRIMARY KEY (identity, sender, message_id)
+        );",
+    )
+}
+
+/// Version 4: usage counts per day, and per month for days rolled up
+fn create_usage(tx: &rusqlite::Transaction, _fastn_home: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE usage_daily (
+            day TEXT NOT NULL,
+            identity TEXT NOT NULL,
+            peer TEXT NOT NULL,
+            protocol TEXT NOT NULL,
+            requests INTEGER NOT NULL,
+            bytes_in INTEGER NOT NULL,
+            bytes_out INTEGER NOT NULL,
+            PRIMARY KEY (day, identity, peer, protocol)
+        );
+        CREATE TABLE usage_monthly (
+            month TEXT NOT NULL,
+            identity TEXT NOT NULL,
+            peer TEXT NOT NULL,
+            protocol TEXT NOT NULL,
+            requests INTEGER NOT NULL,
+            bytes_in INTEGER NOT NULL,
+            bytes_out INTEGER NOT NULL,
+            PRIMARY KEY (month, identity, peer, protocol)
+        );",
+    )
+}
+
+/// Named subdirectories of `dir`; none if it can't be read
+fn subdirectories(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .filter_map(|entry| Some((entry.file_name().to_str()?.to_string(), entry.path())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_legacy_import_and_bindings() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        let alice = fastn_home.join("identities").join("alice");
+        let binding_dir = alice.join("protocols").join("mail.fastn.com").join("default");
+        std::fs::create_dir_all(&binding_dir).unwrap();
+        std::fs::create_dir_all(fastn_home.join("identities").join("bob")).unwrap();
+        std::fs::write(alice.join("online"), b"").unwrap();
+        std::fs::write(binding_dir.join("config.json"), r#"{"storage_dir": "/var/mail"}"#).unwrap();
+
+        let store = StateStore::open(&fastn_home).await.unwrap();
+        assert!(store.is_online("alice").await.unwrap());
+        assert!(!store.is_online("bob").await.unwrap());
+        assert!(!store.is_online("carol").await.unwrap());
+        let bindings = store.bindings("alice").await.unwrap();
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].config["storage_dir"], "/var/mail");
+
+        // The import runs once; the marker is not consulted again
+        std::fs::remove_file(alice.join("online")).unwrap();
+        let store = StateStore::open(&fastn_home).await.unwrap();
+        assert!(store.is_online("alice").await.unwrap());
+
+        assert!(store.set_online("alice", false).await.unwrap());
+        assert!(!store.set_online("alice", false).await.unwrap());
+        assert_eq!(
+            store.identity_states().await.unwrap(),
+            std::collections::BTreeMap::from([("alice".to_string(), false), ("bob".to_string(), false)])
+        );
+
+        let config = serde_json::json!({});
+        store.add_binding("bob", "chat.fastn.com", "default", &config).await.unwrap();
+        assert!(matches!(
+            store.add_binding("bob", "chat.fastn.com", "default", &config).await,
+            Err(StateError::BindingExists { .. })
+        ));
+        store.remove_binding("bob", "chat.fastn.com", "default").await.unwrap();
+        assert!(matches!(
+            store.remove_binding("bob", "chat.fastn.com", "default").await,
+            Err(StateError::BindingNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_contacts_and_outbox() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        std::fs::create_dir_all(&fastn_home).unwrap();
+        let store = StateStore::open(&fastn_home).await.unwrap();
+        let bob = fastn_id52::SecretKey::generate().public_key();
+
+        store.set_contact("alice", "bob", &bob).await.unwrap();
+        assert_eq!(store.contacts("alice").await.unwrap(), vec![Contact { name: "bob".to_string(), peer: bob }]);
+        assert!(store.contacts("carol").await.unwrap().is_empty());
+        assert!(store.remove_contact("alice", "bob").await.unwrap());
+
+        store.set_contact("alice", "bob", &bob).await.unwrap();
+        let carol = fastn_id52::SecretKey::generate().public_key();
+        let members = vec!["bob".to_string(), carol.id52()];
+        store.set_group("alice", "team-a", &members).await.unwrap();
+        assert_eq!(store.groups("alice").await.unwrap(), vec![PeerGroup { name: "team-a".to_string(), members }]);
+        let resolved = store.group_members("alice", "team-a").await.unwrap().unwrap();
+        assert_eq!(resolved.iter().map(|member| member.peer).collect::<Vec<_>>(), vec![bob, carol]);
+        assert!(store.group_members("alice", "team-b").await.unwrap().is_none());
+        assert!(matches!(
+            store.set_group("alice", "team-b", &["dave".to_string()]).await,
+            Err(StateError::UnknownMember { .. })
+        ));
+
+        // Members follow the address book: a removed contact can't be reached any more
+        assert!(store.remove_contact("alice", "bob").await.unwrap());
+        assert!(matches!(
+            store.group_members("alice", "team-a").await,
+            Err(StateError::UnknownMember { .. })
+        ));
+        assert!(store.remove_group("alice", "team-a").await.unwrap());
+
+        let first = store.enqueue("alice", &bob, "mail.fastn.com", &serde_json::json!({"n": 1})).await.unwrap();
+        let second = store.enqueue("alice", &bob, "mail.fastn.com", &serde_json::json!({"n": 2})).await.unwrap();
+        assert_ne!(first.message_id, second.message_id);
+        store.delivery_failed(first.id, "peer offline").await.unwrap();
+        let outbox = store.outbox("alice").await.unwrap();
+        assert_eq!(outbox.iter().map(|entry| entry.id).collect::<Vec<_>>(), vec![first.id, second.id]);
+        assert_eq!((outbox[0].attempts, outbox[0].last_error.as_deref()), (1, Some("peer offline")));
+
+        store.delivered(first.id, &serde_json::json!("stored")).await.unwrap();
+        assert_eq!(store.outbox("alice").await.unwrap()[0].payload, serde_json::json!({"n": 2}));
+
+        // The receipt stays after delivery
+        let delivery = store.delivery("alice", &first.message_id).await.unwrap().unwrap();
+        assert!(matches!(
+            delivery.status,
+            DeliveryStatus::Delivered { ref response, .. } if *response == serde_json::json!("stored")
+        ));
+        let pending = store.delivery("alice", &second.message_id).await.unwrap().unwrap();
+        assert_eq!(pending.status, DeliveryStatus::Pending { attempts: 0, last_error: None });
+        assert_eq!(store.deliveries("alice").await.unwrap().len(), 2);
+        assert!(store.delivery("carol", &first.message_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_applied_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let fastn_home = temp.path().to_path_buf();
+        std::fs::create_dir_all(&fastn_home).unwrap();
+        let store = StateStore::open(&fastn_home).await.unwrap();
+        let alice = fastn_id52::SecretKey::generate().public_key();
+
+        assert_eq!(store.claim_message("bob", &alice, "m1").await.unwrap(), MessageClaim::New);
+        assert_eq!(store.claim_message("bob", &alice, "m1").await.unwrap(), MessageClaim::InProgress);
+
+        // A failed apply can be retried
+        store.release_message("bob", &alice, "m1").await.unwrap();
+        assert_eq!(store.claim_message("bob", &alice, "m1").await.unwrap(), MessageClaim::New);
+        store.message_applied("bob", &alice, "m1", &serde_json::json!({"ok": true})).await.unwrap();
+        assert_eq!(
+            store.claim_message("bob", &alice, "m1").await.unwrap(),
+            MessageClaim::Applied(serde_json::json!({"ok": true}))
+        );
+
+        // Message IDs are per sender
+        let carol = fastn_id52::SecretKey::generate().public_key();
+        assert_eq!(store.claim_message("bob", &carol, "m1").await.unwrap(), MessageClaim::New);
+    }
+
+    #[tokio::test]
+    async fn test_usage_adds_up_and_rolls_into_months() {
+        use crate::server::usage::{Usage, UsageKey};
+
+        let temp = tempfile::tempdir().unwrap();
+
+        let fastn_home = temp.path().to_path_buf();
+        let store = StateStore::open(&fastn_home).await.unwrap();
+        let key = |day: &str, identity: &str| UsageKey {
+            day: day.to_string(),
+            identity: identity.to_string(),
+            peer: "peer".to_string(),
+            protocol: "mail.fastn.com".to_string(),
+        };
+        let usage = Usage { requests: 1, bytes_in: 100, bytes_out: 10 };
+        store
+            .add_usage(vec![(key("2024-06-01", "alice"), usage), (key("2024-06-20", "alice"), usage), (key("2024-06-20", "bob"), usage)])
+            .await
+            .unwrap();
+        store.add_usage(vec![(key("2024-06-20", "alice"), usage)]).await.unwrap();
+
+        let june = store.usage(Some("alice"), "2024-06").await.unwrap();
+        assert_eq!(june.len(), 1);
+        assert_eq!(june[0].usage, Usage { requests: 3, bytes_in: 300, bytes_out: 30 });
+        assert_eq!(store.usage(None, "2024-06").await.unwrap().len(), 2);
+
+        // Rolled-up days still count towards their month, but no longer by day
+        let folded = store.roll_up_usage(chrono::NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()).await.unwrap();
+        assert_eq!(folded, 1);
+        assert_eq!(store.usage(Some("alice"), "2024-06").await.unwrap()[0].usage.requests, 3);
+        let days = store.daily_usage(Some("alice"), "2024-06").await.unwrap();
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].day, "2024-06-20");
+        assert_eq!(days[0].usage.requests, 2);
+    }
+}
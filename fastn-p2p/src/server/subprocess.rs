@@ -0,0 +1,549 @@
+//! Protocols served by a helper process, written in any language
+//!
+//! A binding whose `config.json` has an `exec` section is served by running
+//! that executable instead of an in-process handler:
+//!
+//! ```json
+//! { "exec": { "command": "/usr/local/bin/mail-helper", "args": ["--quiet"], "max_in_flight": 16 } }
+//! ```
+//!
+//! [`crate::server::serve_all`] hands the commands registered for the
+//! protocol to the helper instead of their callbacks.
+//!
+//! The daemon talks to the helper with newline-delimited JSON on its
+//! stdin/stdout; stderr is passed through. Every request or stream gets an
+//! `id` the helper echoes back:
+//!
+//! ```text
+//! daemon → helper  {"type":"request","id":1,"peer":"<id52>","protocol":"Echo","data":{..}}
+//! helper → daemon  {"type":"response","id":1,"data":{..}}   or   {"type":"error","id":1,"error":..}
+//! daemon → helper  {"type":"stream-open","id":2,"peer":"<id52>","protocol":"Shell","data":{..}}
+//! both ways        {"type":"stream-data","id":2,"bytes":"<base64>"}   {"type":"stream-end","id":2}
+//! ```
+//!
+//! A crashed helper fails the requests it held and is restarted with
+//! exponential backoff, so it can't take the daemon down with it. At most
+//! `max_in_flight` requests and streams are handed to it at once; the rest
+//! wait, which pushes back on the peers through QUIC flow control.
+
+/// Binding config file in the binding directory, as written by `add-protocol`;
+/// it may carry the `exec` section and [`CommandTimeouts`]
+pub const CONFIG_FILE: &str = "config.json";
+
+/// Default cap on requests and streams handed to one helper at once
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 32;
+
+const MIN_RESTART_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+const MAX_RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A helper that ran this long before exiting is restarted without backoff
+const STABLE_UPTIME: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Chunks buffered per stream on the way back to the peer
+const STREAM_BUFFER: usize = 16;
+const READ_CHUNK: usize = 16 * 1024;
+
+/// How to run a binding's helper process
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SubprocessConfig {
+    pub command: std::path::PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>,
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
+}
+
+fn default_max_in_flight() -> usize {
+    DEFAULT_MAX_IN_FLIGHT
+}
+
+impl SubprocessConfig {
+    /// The `exec` section of the binding's `config.json`; `None` if it has none
+    pub async fn load(protocol_dir: &std::path::Path) -> Result<Option<Self>, SubprocessError> {
+        #[derive(serde::Deserialize)]
+        struct BindingConfig {
+            exec: Option<SubprocessConfig>,
+        }
+
+        let path = protocol_dir.join(CONFIG_FILE);
+        let json = match tokio::fs::read_to_string(&path).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => return Err(SubprocessError::ReadConfig { path, source }),
+        };
+        let config: BindingConfig = serde_json::from_str(&json)
+            .map_err(|source| SubprocessError::ParseConfig { path, source })?;
+        Ok(config.exec)
+    }
+}
+
+/// Request timeouts a binding's `config.json` sets, for helpers and callbacks alike
+///
+/// ```json
+/// { "timeout_secs": 30, "command_timeout_secs": { "reindex": 600 } }
+/// ```
+///
+/// They win over the timeouts the serve_all builder sets in code.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CommandTimeouts {
+    /// Timeout of every request command of the binding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// Timeouts of single commands, over `timeout_secs`
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub command_timeout_secs: std::collections::BTreeMap<String, u64>,
+}
+
+impl CommandTimeouts {
+    /// The timeouts in the binding's `config.json`; none if it has none
+    ///
+    /// Configs that aren't JSON objects belong to the protocol and set none.
+    pub async fn load(protocol_dir: &std::path::Path) -> Result<Self, SubprocessError> {
+        let path = protocol_dir.join(CONFIG_FILE);
+        let json = match tokio::fs::read_to_string(&path).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => return Err(SubprocessError::ReadConfig { path, source }),
+        };
+        match serde_json::from_str(&json) {
+            Ok(serde_json::Value::Object(config)) => serde_json::from_value(serde_json::Value::Object(config))
+                .map_err(|source| SubprocessError::ParseTimeouts { path, source }),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// The timeout `command` gets, if the config sets one
+    pub fn for_command(&self, command: &str) -> Option<std::time::Duration> {
+        self.command_timeout_secs
+            .get(command)
+            .copied()
+            .or(self.timeout_secs)
+            .map(std::time::Duration::from_secs)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SubprocessError {
+    #[error("Failed to read {path}")]
+    ReadConfig {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid exec config in {path}")]
+    ParseConfig {
+        path: std::path::PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Invalid timeouts in {path}")]
+    ParseTimeouts {
+        path: std::path::PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Failed to start {command}")]
+    Spawn {
+        command: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("I/O with protocol process failed")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+
+    #[error("Protocol process is not running")]
+    NotRunning,
+
+    #[error("Protocol process exited before finishing")]
+    Exited,
+}
+
+/// Message from the daemon to a helper
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ToChild {
+    Request {
+        id: u64,
+        peer: fastn_id52::PublicKey,
+        protocol: serde_json::Value,
+        data: serde_json::Value,
+        #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+        metadata: std::collections::BTreeMap<String, String>,
+    },
+    StreamOpen {
+        id: u64,
+        peer: fastn_id52::PublicKey,
+        protocol: serde_json::Value,
+        data: serde_json::Value,
+    },
+    StreamData { id: u64, bytes: String },
+    StreamEnd { id: u64 },
+}
+
+/// Message from a helper to the daemon
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum FromChild {
+    Response { id: u64, data: serde_json::Value },
+    Error { id: u64, error: serde_json::Value },
+    StreamData { id: u64, bytes: String },
+    StreamEnd { id: u64 },
+}
+
+/// Where the helper's answer for one id goes
+enum Pending {
+    Request(tokio::sync::oneshot::Sender<Result<serde_json::Value, serde_json::Value>>),
+    /// `None` marks the end of the stream
+    Stream(tokio::sync::mpsc::Sender<Option<Vec<u8>>>),
+}
+
+/// A supervised helper process serving one binding
+///
+/// Clones share the same process. It runs until [`Self::stop`] or graceful
+/// shutdown.
+#[derive(Clone)]
+pub struct Subprocess {
+    inner: std::sync::Arc<Inner>,
+}
+
+struct Inner {
+    name: String,
+    config: SubprocessConfig,
+    in_flight: std::sync::Arc<tokio::sync::Semaphore>,
+    next_id: std::sync::atomic::AtomicU64,
+    /// Messages for the running helper's stdin; `None` while it is restarting
+    child: std::sync::Mutex<Option<tokio::sync::mpsc::Sender<ToChild>>>,
+    pending: std::sync::Mutex<std::collections::HashMap<u64, Pending>>,
+    stop: tokio_util::sync::CancellationToken,
+}
+
+impl Subprocess {
+    /// Start the helper described by `config` and keep it running
+    pub fn spawn(name: impl Into<String>, config: SubprocessConfig) -> Self {
+        let subprocess = Self {
+            inner: std::sync::Arc::new(Inner {
+                name: name.into(),
+                in_flight: std::sync::Arc::new(tokio::sync::Semaphore::new(config.max_in_flight.max(1))),
+                config,
+                next_id: std::sync::atomic::AtomicU64::new(1),
+                child: std::sync::Mutex::new(None),
+                pending: std::sync::Mutex::new(std::collections::HashMap::new()),
+                stop: tokio_util::sync::CancellationToken::new(),
+            }),
+        };
+        crate::spawn(supervise(subprocess.inner.clone()));
+        subprocess
+    }
+
+    /// Kill the helper and don't restart it
+    pub fn stop(&self) {
+        self.inner.stop.cancel();
+    }
+
+    /// Whether a helper process is up right now (not crashed or restarting)
+    pub fn is_running(&self) -> bool {
+        self.inner.child.lock().expect("Failed to acquire lock on helper").is_some()
+    }
+
+    /// Have the helper answer one request
+    pub async fn request(
+        &self,
+        peer: fastn_id52::PublicKey,
+        protocol: serde_json::Value,
+        data: serde_json::Value,
+        metadata: std::collections::BTreeMap<String, String>,
+    ) -> Result<serde_json::Value, serde_json::Value> {
+        let _permit = self.inner.in_flight.clone().acquire_owned().await
+            .expect("Helper semaphore is never closed");
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let pending = self.inner.register(Pending::Request(sender));
+
+        let message = ToChild::Request { id: pending.id, peer, protocol, data, metadata };
+        if let Err(e) = self.inner.send(message).await {
+            return Err(serde_json::Value::String(e.to_string()));
+        }
+        receiver.await.unwrap_or_else(|_| Err(serde_json::Value::String(SubprocessError::Exited.to_string())))
+    }
+
+    /// Serve one peer stream with the helper until either side ends it
+    pub async fn stream(
+        &self,
+        peer: fastn_id52::PublicKey,
+        protocol: serde_json::Value,
+        data: serde_json::Value,
+        mut send: iroh::endpoint::SendStream,
+        mut recv: fastn_net::FrameReader<iroh::endpoint::RecvStream>,
+    ) -> Result<(), SubprocessError> {
+        let _permit = self.inner.in_flight.clone().acquire_owned().await
+            .expect("Helper semaphore is never closed");
+        let (sender, mut from_child) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        let pending = self.inner.register(Pending::Stream(sender));
+        let id = pending.id;
+        self.inner.send(ToChild::StreamOpen { id, peer, protocol, data }).await?;
+
+        // The helper ending the stream (or dying) ends it for the peer
+        let to_peer = async {
+            loop {
+                match from_child.recv().await {
+                    Some(Some(bytes)) => send.write_all(&bytes).await.map_err(std::io::Error::other)?,
+                    Some(None) => break,
+                    None => return Err(SubprocessError::Exited),
+                }
+            }
+            send.finish().map_err(std::io::Error::other)?;
+            Ok(())
+        };
+
+        // The peer ending its side only tells the helper, which may still answer
+        let from_peer = async {
+            let mut buffer = vec![0u8; READ_CHUNK];
+            loop {
+                let read = tokio::io::AsyncReadExt::read(&mut recv, &mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                let bytes = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &buffer[..read]);
+                self.inner.send(ToChild::StreamData { id, bytes }).await?;
+            }
+            self.inner.send(ToChild::StreamEnd { id }).await?;
+            std::future::pending::<Result<(), SubprocessError>>().await
+        };
+
+        tokio::select! {
+            result = to_peer => result,
+            result = from_peer => result,
+        }
+    }
+}
+
+/// Removes a pending entry once its request or stream is over
+struct PendingGuard<'a> {
+    inner: &'a Inner,
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.inner.pending.lock().expect("Failed to acquire lock on pending").remove(&self.id);
+    }
+}
+
+impl Inner {
+    fn register(&self, pending: Pending) -> PendingGuard<'_> {
+        let id = self.next_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.pending.lock().expect("Failed to acquire lock on pending").insert(id, pending);
+        PendingGuard { inner: self, id }
+    }
+
+    async fn send(&self, message: ToChild) -> Result<(), SubprocessError> {
+        let sender = self.child.lock().expect("Failed to acquire lock on helper").clone();
+        let sender = sender.ok_or(SubprocessError::NotRunning)?;
+        sender.send(message).await.map_err(|_| SubprocessError::Exited)
+    }
+
+    /// Route a message from the helper to whoever waits for its id
+    async fn deliver(&self, message: FromChild) {
+        let (id, outcome) = match message {
+            FromChild::Response { id, data } => (id, Ok(Ok(data))),
+            FromChild::Error { id, error } => (id, Ok(Err(error))),
+            FromChild::StreamData { id, bytes } => {
+                match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &bytes) {
+                    Ok(bytes) => (id, Err(Some(bytes))),
+                    Err(e) => {
+                        tracing::warn!("{}: invalid stream-data for {}: {}", self.name, id, e);
+                        return;
+                    }
+                }
+            }
+            FromChild::StreamEnd { id } => (id, Err(None)),
+        };
+
+        // The lock is released before waiting on a stream below
+        let stream_chunk = {
+            let mut pending = self.pending.lock().expect("Failed to acquire lock on pending");
+            match (pending.remove(&id), outcome) {
+                (Some(Pending::Request(sender)), Ok(result)) => {
+                    let _ = sender.send(result);
+                    None
+                }
+                (Some(Pending::Stream(sender)), Err(chunk)) => {
+                    if chunk.is_some() {
+                        pending.insert(id, Pending::Stream(sender.clone()));
+                    }
+                    Some((sender, chunk))
+                }
+                (Some(other), _) => {
+                    tracing::warn!("{}: message for {} does not match its kind", self.name, id);
+                    pending.insert(id, other);
+                    None
+                }
+                (None, _) => {
+                    tracing::debug!("{}: message for finished id {}", self.name, id);
+                    None
+                }
+            }
+        };
+
+        if let Some((sender, chunk)) = stream_chunk {
+            // Waiting here is the back-pressure: the helper's stdout isn't read meanwhile
+            let _ = sender.send(chunk).await;
+        }
+    }
+}
+
+/// Keep the helper running, restarting it with backoff when it exits
+async fn supervise(inner: std::sync::Arc<Inner>) {
+    let mut delay = MIN_RESTART_DELAY;
+    loop {
+        let started = std::time::Instant::now();
+        match run_child(&inner).await {
+            Ok(status) => tracing::warn!("{} exited with {}", inner.name, status),
+            Err(e) => tracing::error!("{}: {}", inner.name, e),
+        }
+        // Dropping the senders fails whatever the helper still held
+        inner.pending.lock().expect("Failed to acquire lock on pending").clear();
+
+        if inner.stop.is_cancelled() {
+            break;
+        }
+        if started.elapsed() >= STABLE_UPTIME {
+            delay = MIN_RESTART_DELAY;
+        }
+        tracing::info!("Restarting {} in {:?}", inner.name, delay);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = inner.stop.cancelled() => break,
+            _ = crate::cancelled() => break,
+        }
+        delay = (delay * 2).min(MAX_RESTART_DELAY);
+    }
+    tracing::info!("Stopped {}", inner.name);
+}
+
+/// Run the helper once, until it exits or is stopped
+async fn run_child(inner: &Inner) -> Result<std::process::ExitStatus, SubprocessError> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let mut child = tokio::process::Command::new(&inner.config.command)
+        .args(&inner.config.args)
+        .envs(&inner.config.env)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|source| SubprocessError::Spawn { command: inner.config.command.clone(), source })?;
+    let mut stdin = child.stdin.take().expect("Helper stdin is piped");
+    let stdout = child.stdout.take().expect("Helper stdout is piped");
+
+    let (sender, mut outgoing) = tokio::sync::mpsc::channel::<ToChild>(inner.config.max_in_flight.max(1));
+    *inner.child.lock().expect("Failed to acquire lock on helper") = Some(sender);
+    tracing::info!("Started {} ({})", inner.name, inner.config.command.display());
+
+    let write = async {
+        while let Some(message) = outgoing.recv().await {
+            let mut line = serde_json::to_vec(&message).expect("Helper messages must be serializable");
+            line.push(b'\n');
+            stdin.write_all(&line).await?;
+        }
+        Ok::<(), SubprocessError>(())
+    };
+    let read = async {
+        let mut lines = tokio::io::BufReader::new(stdout).lines();
+        while let Some(line) = lines.next_line().await? {
+            match serde_json::from_str::<FromChild>(&line) {
+                Ok(message) => inner.deliver(message).await,
+                Err(e) => tracing::warn!("{}: ignoring invalid message: {}", inner.name, e),
+            }
+        }
+        Ok::<(), SubprocessError>(())
+    };
+
+    let exited = tokio::select! {
+        status = child.wait() => Some(status),
+        result = write => {
+            if let Err(e) = result {
+                tracing::warn!("{}: failed to write to helper: {}", inner.name, e);
+            }
+            None
+        }
+        result = read => {
+            if let Err(e) = result {
+                tracing::warn!("{}: failed to read from helper: {}", inner.name, e);
+            }
+            None
+        }
+        _ = inner.stop.cancelled() => None,
+        _ = crate::cancelled() => None,
+    };
+    inner.child.lock().expect("Failed to acquire lock on helper").take();
+
+    let status = match exited {
+        Some(status) => status?,
+        None => {
+            child.kill().await?;
+            child.wait().await?
+        }
+    };
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wire_format() {
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let request = ToChild::Request {
+            id: 7,
+            peer,
+            protocol: serde_json::json!("Echo"),
+            data: serde_json::json!({"message": "hi"}),
+            metadata: Default::default(),
+        };
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["type"], "request");
+        assert_eq!(json["peer"], peer.id52());
+        assert!(json.get("metadata").is_none());
+
+        let response: FromChild = serde_json::from_str(r#"{"type":"stream-end","id":7}"#).unwrap();
+        assert_eq!(response, FromChild::StreamEnd { id: 7 });
+    }
+
+    #[tokio::test]
+    async fn test_exec_config_and_request() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        assert_eq!(SubprocessConfig::load(&dir).await.unwrap(), None);
+
+        // Turns every request line into a response with the same data
+        let config = serde_json::json!({
+            "exec": {"command": "sed", "args": ["-u", "s/\"type\":\"request\"/\"type\":\"response\"/"]}
+        });
+        tokio::fs::write(dir.join(CONFIG_FILE), config.to_string()).await.unwrap();
+        let config = SubprocessConfig::load(&dir).await.unwrap().unwrap();
+        assert_eq!(config.max_in_flight, DEFAULT_MAX_IN_FLIGHT);
+
+        let subprocess = Subprocess::spawn("echo helper", config);
+        while !subprocess.is_running() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let peer = fastn_id52::SecretKey::generate().public_key();
+        let data = serde_json::json!({"message": "hello"});
+        let result = subprocess.request(peer, serde_json::json!("Echo"), data.clone(), Default::default()).await;
+        assert_eq!(result, Ok(data));
+
+        subprocess.stop();
+    }
+}
@@ -0,0 +1,742 @@
+//! Built-in `sync.fastn.com` protocol: one-way directory synchronization
+//!
+//! The server exposes a configured directory. A client sends the manifest of
+//! its local copy (paths, sizes, BLAKE3 hashes of the whole file and of each
+//! [`CHUNK_SIZE`] chunk), gets back a [`SyncPlan`] listing only the chunks that
+//! differ, and streams just those. An interrupted sync resumes naturally: the
+//! chunks already written match on the next run and are not sent again.
+//!
+//! Commands:
+//!
+//! - `manifest` (request): manifest of the served directory
+//! - `diff` (request): [`SyncPlan`] for the client's [`Manifest`]
+//! - `chunks` (stream): requested chunks of files in the manifest, each a [`ChunkHeader`] JSON line
+//!   followed by `len` raw bytes
+//!
+//! The binding config (`config.json` in the protocol_dir) names the served
+//! directory, with relative paths resolved against the protocol_dir, and the
+//! peers allowed to sync it. An empty `allowed_peers` list rejects everyone.
+//!
+//! ```json
+//! { "root": "/srv/shared", "allowed_peers": ["<peer id52>"] }
+//! ```
+//!
+//! File hashes are cached per served directory and only recomputed for files
+//! whose size or modification time changed since the last manifest.
+
+use std::path::{Path, PathBuf};
+
+/// Protocol name for the sync protocol
+pub const SYNC_PROTOCOL: &str = "sync.fastn.com";
+
+/// Files are compared and transferred in chunks of this size
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Manifest entries by relative path, with the mtime they were hashed at
+type CachedEntries = std::collections::HashMap<String, (std::time::SystemTime, FileEntry)>;
+
+/// Last manifest entries of each directory a manifest was built for
+static MANIFESTS: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<PathBuf, CachedEntries>>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// Sync binding configuration
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SyncConfig {
+    /// Directory served to peers
+    pub root: PathBuf,
+    /// ID52s of peers allowed to use any command
+    #[serde(default)]
+    pub allowed_peers: Vec<String>,
+}
+
+impl SyncConfig {
+    /// Load the binding config and resolve `root` against `protocol_dir`
+    pub async fn load(protocol_dir: &Path) -> Result<Self, SyncError> {
+        let contents = tokio::fs::read_to_string(protocol_dir.join(crate::server::subprocess::CONFIG_FILE))
+            .await
+            .map_err(|source| SyncError::Io { source })?;
+        let mut config: SyncConfig =
+            serde_json::from_str(&contents).map_err(|source| SyncError::Config { source })?;
+        config.root = protocol_dir.join(config.root);
+        Ok(config)
+    }
+
+    pub fn allows(&self, peer: &fastn_id52::PublicKey) -> bool {
+        let id52 = peer.id52();
+        self.allowed_peers.iter().any(|allowed| allowed == &id52)
+    }
+
+    /// Load the binding config, refusing peers not in `allowed_peers`
+    async fn load_for(protocol_dir: &Path, peer: &fastn_id52::PublicKey) -> Result<Self, SyncError> {
+        let config = Self::load(protocol_dir).await?;
+        if !config.allows(peer) {
+            return Err(SyncError::NotAllowed { peer: peer.id52() });
+        }
+        Ok(config)
+    }
+}
+
+/// One file in a [`Manifest`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    /// BLAKE3 hash of the whole file, hex encoded
+    pub hash: String,
+    /// BLAKE3 hash of each chunk, hex encoded
+    pub chunks: Vec<String>,
+}
+
+/// Contents of a directory tree, keyed by `/`-separated relative path
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub files: std::collections::BTreeMap<String, FileEntry>,
+}
+
+/// A file the client has to update
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FilePlan {
+    pub path: String,
+    pub size: u64,
+    pub hash: String,
+    /// Indexes of the chunks that differ from the client's copy
+    pub chunks: Vec<u64>,
+}
+
+/// What a client must fetch and delete to mirror the server
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SyncPlan {
+    pub files: Vec<FilePlan>,
+    /// Client paths that no longer exist on the server
+    pub deleted: Vec<String>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.deleted.is_empty()
+    }
+
+    /// Chunk requests to send as the `chunks` stream's initial data
+    pub fn chunk_requests(&self) -> Vec<ChunkRequest> {
+        self.files
+            .iter()
+            .filter(|file| !file.chunks.is_empty())
+            .map(|file| ChunkRequest {
+                path: file.path.clone(),
+                chunks: file.chunks.clone(),
+            })
+            .collect()
+    }
+
+    /// Total bytes the plan will transfer
+    pub fn transfer_size(&self) -> u64 {
+        self.files
+            .iter()
+            .flat_map(|file| file.chunks.iter().map(|index| chunk_len(file.size, *index)))
+            .sum()
+    }
+
+    /// Length of chunk `index` of `path` if the plan asks for it
+    ///
+    /// Clients use it to refuse chunks they never requested.
+    pub fn planned_chunk_len(&self, path: &str, index: u64) -> Option<u64> {
+        let file = self.files.iter().find(|file| file.path == path)?;
+        (file.chunks.contains(&index) && index < chunk_count(file.size)).then(|| chunk_len(file.size, index))
+    }
+}
+
+/// Chunks of one file requested over the `chunks` stream
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChunkRequest {
+    pub path: String,
+    pub chunks: Vec<u64>,
+}
+
+/// Precedes each chunk's bytes on the `chunks` stream
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChunkHeader {
+    pub path: String,
+    pub index: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("Invalid sync request: {source}")]
+    InvalidRequest { source: serde_json::Error },
+
+    #[error("Peer {peer} is not allowed to sync this directory")]
+    NotAllowed { peer: String },
+
+    #[error("Invalid sync config: {source}")]
+    Config { source: serde_json::Error },
+
+    #[error("Path '{path}' escapes the sync directory")]
+    InvalidPath { path: String },
+
+    #[error("'{path}' is not a file of the sync directory")]
+    NotServed { path: String },
+
+    #[error("'{path}' has no chunk {index}")]
+    InvalidChunk { path: String, index: u64 },
+
+    #[error("Hash mismatch for '{path}' after sync")]
+    HashMismatch { path: String },
+
+    #[error("Sync I/O failed: {source}")]
+    Io { source: std::io::Error },
+
+    #[error("Sync task failed: {source}")]
+    Task { source: tokio::task::JoinError },
+}
+
+/// Register the sync commands on a serve_all protocol builder
+pub fn register(
+    protocol: crate::server::serve_all::ProtocolBuilder,
+) -> crate::server::serve_all::ProtocolBuilder {
+    protocol
+        .handle_requests("manifest", manifest_handler)
+        .handle_requests("diff", diff_handler)
+        .handle_streams("chunks", chunks_handler)
+}
+
+/// `manifest` command: manifest of the served directory
+pub fn manifest_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    _request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let config = SyncConfig::load_for(&protocol_dir, &peer).await?;
+        let manifest = build_manifest(&config.root).await?;
+        Ok(serde_json::to_value(&manifest)?)
+    })
+}
+
+/// `diff` command: the plan for bringing the client's manifest up to date
+pub fn diff_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    peer: &fastn_id52::PublicKey,
+    request: serde_json::Value,
+) -> super::serve_all::Reply<serde_json::Value> {
+    let protocol_dir = protocol_dir.to_path_buf();
+    let peer = *peer;
+
+    Box::pin(async move {
+        let local: Manifest = serde_json::from_value(request)
+            .map_err(|source| SyncError::InvalidRequest { source })?;
+        let config = SyncConfig::load_for(&protocol_dir, &peer).await?;
+        let remote = build_manifest(&config.root).await?;
+        Ok(serde_json::to_value(diff(&remote, &local))?)
+    })
+}
+
+/// `chunks` command: stream the requested chunks
+pub fn chunks_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    initial_data: serde_json::Value,
+    mut session: crate::server::Session<String>,
+) -> super::serve_all::Reply<()> {
+    let protocol_dir = protocol_dir.to_path_buf();
+
+    Box::pin(async move {
+        let requests: Vec<ChunkRequest> = serde_json::from_value(initial_data)
+            .map_err(|source| SyncError::InvalidRequest { source })?;
+        let config = SyncConfig::load_for(&protocol_dir, &session.peer).await?;
+        let manifest = build_manifest(&config.root).await?;
+
+        let mut sent = 0u64;
+        for request in requests {
+            let path = served_file(&config.root, &manifest, &request.path).await?;
+            for index in request.chunks {
+                check_chunk(&manifest, &request.path, index)?;
+                let bytes = read_chunk(&path, index).await?;
+                let header = ChunkHeader {
+                    path: request.path.clone(),
+                    index,
+                    len: bytes.len() as u64,
+                };
+                let mut line = serde_json::to_string(&header)?;
+                line.push('\n');
+                session.send.write_all(line.as_bytes()).await?;
+                session.send.write_all(&bytes).await?;
+                sent += bytes.len() as u64;
+            }
+        }
+        session.send.finish()?;
+
+        tracing::debug!("Sent {} bytes of sync chunks to {}", sent, session.peer.id52());
+        Ok(())
+    })
+}
+
+/// Compute the manifest of `root`, creating it if it does not exist
+///
+/// Files whose size and mtime match the previous manifest of `root` keep their
+/// cached hashes instead of being read again.
+pub async fn build_manifest(root: &Path) -> Result<Manifest, SyncError> {
+    tokio::fs::create_dir_all(root)
+        .await
+        .map_err(|source| SyncError::Io { source })?;
+
+    let root = root.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let previous = MANIFESTS.lock().unwrap().get(&root).cloned().unwrap_or_default();
+        let mut current = std::collections::HashMap::new();
+        let mut manifest = Manifest::default();
+        collect_files(&root, &root, &previous, &mut current, &mut manifest)?;
+        // Only files still present are kept, so deleted ones drop out of the cache
+        MANIFESTS.lock().unwrap().insert(root, current);
+        Ok(manifest)
+    })
+    .await
+    .map_err(|source| SyncError::Task { source })?
+}
+
+/// Plan what `local` needs to become a copy of `remote`
+pub fn diff(remote: &Manifest, local: &Manifest) -> SyncPlan {
+    let mut plan = SyncPlan::default();
+
+    for (path, remote_file) in &remote.files {
+        let local_file = local.files.get(path);
+        if local_file.map(|f| f.hash == remote_file.hash && f.size == remote_file.size) == Some(true) {
+            continue;
+        }
+
+        let chunks = remote_file
+            .chunks
+            .iter()
+            .enumerate()
+            .filter(|(index, hash)| local_file.and_then(|f| f.chunks.get(*index)) != Some(*hash))
+            .map(|(index, _)| index as u64)
+            .collect();
+
+        plan.files.push(FilePlan {
+            path: path.clone(),
+            size: remote_file.size,
+            hash: remote_file.hash.clone(),
+            chunks,
+        });
+    }
+
+    plan.deleted = local
+        .files
+        .keys()
+        .filter(|path| !remote.files.contains_key(*path))
+        .cloned()
+        .collect();
+
+    plan
+}
+
+/// Write one received chunk into the local copy
+pub async fn write_chunk(root: &Path, header: &ChunkHeader, bytes: &[u8]) -> Result<(), SyncError> {
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    let path = resolve(root, &header.path)?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|source| SyncError::Io { source })?;
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .await
+        .map_err(|source| SyncError::Io { source })?;
+    file.seek(std::io::SeekFrom::Start(chunk_offset(&header.path, header.index)?))
+        .await
+        .map_err(|source| SyncError::Io { source })?;
+    file.write_all(bytes)
+        .await
+        .map_err(|source| SyncError::Io { source })
+}
+
+/// After all chunks are written: fix file sizes, verify hashes, delete stale files
+pub async fn finish_plan(root: &Path, plan: &SyncPlan) -> Result<(), SyncError> {
+    for file in &plan.files {
+        let path = resolve(root, &file.path)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|source| SyncError::Io { source })?;
+        }
+
+        // Also creates empty files, which have no chunks to transfer
+        let handle = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .await
+            .map_err(|source| SyncError::Io { source })?;
+        handle
+            .set_len(file.size)
+            .await
+            .map_err(|source| SyncError::Io { source })?;
+
+        let (hash, _) = hash_file(&path).await?;
+        if hash != file.hash {
+            return Err(SyncError::HashMismatch {
+                path: file.path.clone(),
+            });
+        }
+    }
+
+    for deleted in &plan.deleted {
+        let path = resolve(root, deleted)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(source) => return Err(SyncError::Io { source }),
+        }
+    }
+
+    Ok(())
+}
+
+/// Read chunk `index` of the file at `path`
+pub async fn read_chunk(path: &Path, index: u64) -> Result<Vec<u8>, SyncError> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|source| SyncError::Io { source })?;
+    file.seek(std::io::SeekFrom::Start(chunk_offset(&path.display().to_string(), index)?))
+        .await
+        .map_err(|source| SyncError::Io { source })?;
+
+    let mut bytes = Vec::with_capacity(CHUNK_SIZE as usize);
+    file.take(CHUNK_SIZE)
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(|source| SyncError::Io { source })?;
+    Ok(bytes)
+}
+
+/// Map a manifest path to a file under `root`, rejecting anything that escapes it
+pub fn resolve(root: &Path, relative: &str) -> Result<PathBuf, SyncError> {
+    let mut path = root.to_path_buf();
+    for part in relative.split('/') {
+        match Path::new(part).components().collect::<Vec<_>>().as_slice() {
+            [std::path::Component::Normal(name)] => path.push(name),
+            _ => {
+                return Err(SyncError::InvalidPath {
+                    path: relative.to_string(),
+                });
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// The file behind a path a peer asked chunks of
+///
+/// Only files in the served directory's current `manifest` are sent, and only
+/// while they still resolve to a place under `root` once symlinks are followed.
+async fn served_file(root: &Path, manifest: &Manifest, relative: &str) -> Result<PathBuf, SyncError> {
+    let not_served = || SyncError::NotServed { path: relative.to_string() };
+    if !manifest.files.contains_key(relative) {
+        return Err(not_served());
+    }
+
+    let root = tokio::fs::canonicalize(root)
+        .await
+        .map_err(|source| SyncError::Io { source })?;
+    let path = match tokio::fs::canonicalize(resolve(&root, relative)?).await {
+        Ok(path) => path,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_served()),
+        Err(source) => return Err(SyncError::Io { source }),
+    };
+    if !path.starts_with(&root) {
+        return Err(SyncError::InvalidPath { path: relative.to_string() });
+    }
+    Ok(path)
+}
+
+/// Refuse chunk indexes past the end of a served file
+fn check_chunk(manifest: &Manifest, relative: &str, index: u64) -> Result<(), SyncError> {
+    match manifest.files.get(relative) {
+        Some(file) if index < chunk_count(file.size) => Ok(()),
+        Some(_) => Err(SyncError::InvalidChunk { path: relative.to_string(), index }),
+        None => Err(SyncError::NotServed { path: relative.to_string() }),
+    }
+}
+
+fn chunk_len(size: u64, index: u64) -> u64 {
+    size.saturating_sub(index.saturating_mul(CHUNK_SIZE)).min(CHUNK_SIZE)
+}
+
+/// Chunks a file of `size` bytes has
+fn chunk_count(size: u64) -> u64 {
+    size.div_ceil(CHUNK_SIZE)
+}
+
+/// Where chunk `index` of `path` starts; peers send indexes, so this can overflow
+fn chunk_offset(path: &str, index: u64) -> Result<u64, SyncError> {
+    index.checked_mul(CHUNK_SIZE).ok_or_else(|| SyncError::InvalidChunk {
+        path: path.to_string(),
+        index,
+    })
+}
+
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    previous: &CachedEntries,
+    current: &mut CachedEntries,
+    manifest: &mut Manifest,
+) -> Result<(), SyncError> {
+    let entries = std::fs::read_dir(dir).map_err(|source| SyncError::Io { source })?;
+    for entry in entries {
+        let entry = entry.map_err(|source| SyncError::Io { source })?;
+        // Symlinks are skipped so a link cannot expose files outside the root
+        let file_type = entry.file_type().map_err(|source| SyncError::Io { source })?;
+        let path = entry.path();
+
+        if file_type.is_dir() {
+            collect_files(root, &path, previous, current, manifest)?;
+        } else if file_type.is_file() {
+            let Some(relative) = relative_path(root, &path) else {
+                tracing::warn!("Skipping non UTF-8 path in sync directory: {}", path.display());
+                continue;
+            };
+            let metadata = entry.metadata().map_err(|source| SyncError::Io { source })?;
+            let modified = metadata.modified().map_err(|source| SyncError::Io { source })?;
+            let file = match previous.get(&relative) {
+                Some((cached_at, file)) if *cached_at == modified && file.size == metadata.len() => file.clone(),
+                _ => {
+                    let (hash, chunks, size) = hash_file_blocking(&path)?;
+                    FileEntry { size, hash, chunks }
+                }
+            };
+            current.insert(relative.clone(), (modified, file.clone()));
+            manifest.files.insert(relative, file);
+        }
+    }
+    Ok(())
+}
+
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let parts: Option<Vec<&str>> = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect();
+    Some(parts?.join("/"))
+}
+
+async fn hash_file(path: &Path) -> Result<(String, Vec<String>), SyncError> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_file_blocking(&path).map(|(hash, chunks, _)| (hash, chunks)))
+        .await
+        .map_err(|source| SyncError::Task { source })?
+}
+
+/// Whole-file hash, per-chunk hashes and size
+fn hash_file_blocking(path: &Path) -> Result<(String, Vec<String>, u64), SyncError> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path).map_err(|source| SyncError::Io { source })?;
+    let mut whole = blake3::Hasher::new();
+    let mut chunks = Vec::new();
+    let mut size = 0u64;
+    let mut buffer = vec![0u8; CHUNK_SIZE as usize];
+
+    loop {
+        // Fill a whole chunk unless the file ends first
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let n = file
+                .read(&mut buffer[filled..])
+                .map_err(|source| SyncError::Io { source })?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        if filled == 0 {
+            break;
+        }
+
+        whole.update(&buffer[..filled]);
+        chunks.push(blake3::hash(&buffer[..filled]).to_hex().to_string());
+        size += filled as u64;
+    }
+
+    Ok((whole.finalize().to_hex().to_string(), chunks, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_diff_transfers_only_changed_chunks() {
+        let temp = tempfile::tempdir().unwrap();
+        let base = temp.path().to_path_buf();
+        let remote_root = base.join("remote");
+        let local_root = base.join("local");
+        tokio::fs::create_dir_all(remote_root.join("docs")).await.unwrap();
+        tokio::fs::create_dir_all(&local_root).await.unwrap();
+
+        // Two chunks plus a tail; the local copy differs only in the middle chunk
+        let mut big = vec![7u8; (CHUNK_SIZE * 2 + 10) as usize];
+        tokio::fs::write(remote_root.join("big.bin"), &big).await.unwrap();
+        big[CHUNK_SIZE as usize + 1] = 0;
+        tokio::fs::write(local_root.join("big.bin"), &big).await.unwrap();
+
+        tokio::fs::write(remote_root.join("docs/readme.txt"), b"hello").await.unwrap();
+        tokio::fs::write(remote_root.join("empty"), b"").await.unwrap();
+        tokio::fs::write(local_root.join("stale.txt"), b"old").await.unwrap();
+
+        let remote = build_manifest(&remote_root).await.unwrap();
+        let local = build_manifest(&local_root).await.unwrap();
+        let plan = diff(&remote, &local);
+
+        let big_plan = plan.files.iter().find(|f| f.path == "big.bin").unwrap();
+        assert_eq!(big_plan.chunks, vec![1]);
+        assert_eq!(plan.deleted, vec!["stale.txt".to_string()]);
+        assert_eq!(plan.transfer_size(), CHUNK_SIZE + 5);
+
+        // What the `chunks` stream would carry
+        for request in plan.chunk_requests() {
+            for index in request.chunks {
+                let bytes = read_chunk(&resolve(&remote_root, &request.path).unwrap(), index).await.unwrap();
+                let header = ChunkHeader { path: request.path.clone(), index, len: bytes.len() as u64 };
+                write_chunk(&local_root, &header, &bytes).await.unwrap();
+            }
+        }
+        finish_plan(&local_root, &plan).await.unwrap();
+
+        assert_eq!(build_manifest(&local_root).await.unwrap(), remote);
+        assert!(diff(&remote, &build_manifest(&local_root).await.unwrap()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_only_manifest_files_are_served() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("served");
+        tokio::fs::create_dir_all(root.join("docs")).await.unwrap();
+        tokio::fs::write(root.join("docs/readme.txt"), b"hello").await.unwrap();
+        tokio::fs::write(temp.path().join("secret.txt"), b"secret").await.unwrap();
+        tokio::fs::symlink(temp.path().join("secret.txt"), root.join("leak.txt")).await.unwrap();
+        tokio::fs::symlink(temp.path(), root.join("outside")).await.unwrap();
+
+        let manifest = build_manifest(&root).await.unwrap();
+        let served = served_file(&root, &manifest, "docs/readme.txt").await.unwrap();
+        assert_eq!(read_chunk(&served, 0).await.unwrap(), b"hello");
+
+        for path in ["leak.txt", "outside/secret.txt", "missing.txt", "../secret.txt"] {
+            assert!(matches!(
+                served_file(&root, &manifest, path).await,
+                Err(SyncError::NotServed { .. })
+            ), "{path}");
+        }
+
+        // A directory swapped for a symlink after the manifest was built
+        tokio::fs::remove_dir_all(root.join("docs")).await.unwrap();
+        tokio::fs::create_dir_all(temp.path().join("elsewhere")).await.unwrap();
+        tokio::fs::write(temp.path().join("elsewhere/readme.txt"), b"other").await.unwrap();
+        tokio::fs::symlink(temp.path().join("elsewhere"), root.join("docs")).await.unwrap();
+        assert!(matches!(
+            served_file(&root, &manifest, "docs/readme.txt").await,
+            Err(SyncError::InvalidPath { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_manifest_reuses_hashes_until_mtime_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().to_path_buf();
+        let path = root.join("notes.txt");
+        tokio::fs::write(&path, b"first").await.unwrap();
+        let first = build_manifest(&root).await.unwrap();
+
+        // Same size and mtime: the cached hash is served without reading the file
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, b"other").unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        assert_eq!(build_manifest(&root).await.unwrap(), first);
+
+        let later = modified + std::time::Duration::from_secs(1);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        let second = build_manifest(&root).await.unwrap();
+        assert_ne!(second.files["notes.txt"].hash, first.files["notes.txt"].hash);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(build_manifest(&root).await.unwrap().files.is_empty());
+        assert!(MANIFESTS.lock().unwrap()[&root].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_only_allowed_peers_sync() {
+        let temp = tempfile::tempdir().unwrap();
+        let protocol_dir = temp.path().to_path_buf();
+        let allowed = fastn_id52::SecretKey::generate().public_key();
+        let stranger = fastn_id52::SecretKey::generate().public_key();
+        let config = serde_json::json!({ "root": "served", "allowed_peers": [allowed.id52()] });
+        tokio::fs::write(protocol_dir.join(crate::server::subprocess::CONFIG_FILE), config.to_string())
+            .await
+            .unwrap();
+
+        let manifest = |peer| manifest_handler("alice", "default", SYNC_PROTOCOL, "manifest", &protocol_dir, &peer, serde_json::Value::Null);
+        let refused = manifest(stranger).await.unwrap_err();
+        assert!(matches!(refused.downcast_ref::<SyncError>(), Some(SyncError::NotAllowed { .. })));
+        assert_eq!(manifest(allowed).await.unwrap(), serde_json::json!({ "files": {} }));
+    }
+
+    #[tokio::test]
+    async fn test_chunks_past_the_end_are_refused() {
+        let temp = tempfile::tempdir().unwrap();
+        let protocol_dir = temp.path().to_path_buf();
+        tokio::fs::create_dir_all(protocol_dir.join("served")).await.unwrap();
+        tokio::fs::write(protocol_dir.join("served/notes.txt"), b"hello").await.unwrap();
+
+        let manifest = build_manifest(&protocol_dir.join("served")).await.unwrap();
+        check_chunk(&manifest, "notes.txt", 0).unwrap();
+        for index in [1, u64::MAX] {
+            assert!(matches!(check_chunk(&manifest, "notes.txt", index), Err(SyncError::InvalidChunk { .. })));
+        }
+
+        let plan = diff(&manifest, &Manifest::default());
+        assert_eq!(plan.planned_chunk_len("notes.txt", 0), Some(5));
+        assert_eq!(plan.planned_chunk_len("notes.txt", 1), None);
+        assert_eq!(plan.planned_chunk_len("other.txt", 0), None);
+
+        let header = ChunkHeader { path: "huge.bin".to_string(), index: u64::MAX, len: 1 };
+        assert!(matches!(write_chunk(temp.path(), &header, b"x").await, Err(SyncError::InvalidChunk { .. })));
+        assert!(matches!(
+            read_chunk(&protocol_dir.join("served/notes.txt"), u64::MAX).await,
+            Err(SyncError::InvalidChunk { .. })
+        ));
+    }
+
+    #[test]
+    fn test_resolve_rejects_escapes() {
+        let root = Path::new("/srv/sync");
+        assert_eq!(resolve(root, "a/b.txt").unwrap(), root.join("a").join("b.txt"));
+        assert!(resolve(root, "../etc/passwd").is_err());
+        assert!(resolve(root, "/etc/passwd").is_err());
+        assert!(resolve(root, "a//b").is_err());
+    }
+}
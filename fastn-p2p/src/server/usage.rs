@@ -0,0 +1,97 @@
+//! Bandwidth and request accounting per identity, peer and protocol
+//!
+//! Requests served with `serve_all` and the calls and streams the daemon
+//! makes are counted here, in memory, as they finish. The daemon writes the
+//! counts to `state.db` every minute with [`flush`], where they add up per
+//! day. Days older than the kept window are rolled up into their month, see
+//! [`super::state::StateStore::roll_up_usage`]. `fastn-p2p usage` reports
+//! a month from both.
+//!
+//! Bytes are payload bytes as the daemon sees them: the JSON of requests
+//! and responses and the raw bytes of streams. Connection overhead, such as
+//! handshakes, keepalives and retransmits, is not counted.
+
+/// Counts of one identity, peer and protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Usage {
+    pub requests: u64,
+    /// Received from the peer
+    pub bytes_in: u64,
+    /// Sent to the peer
+    pub bytes_out: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.requests += other.requests;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+/// What one [`Usage`] counts: a day of traffic between an identity and a peer over a protocol
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UsageKey {
+    /// `YYYY-MM-DD`, UTC
+    pub day: String,
+    /// Identity alias
+    pub identity: String,
+    /// Peer ID52
+    pub peer: String,
+    pub protocol: String,
+}
+
+/// A month's usage between an identity and a peer over a protocol, see [`super::state::StateStore::usage`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct UsageRow {
+    pub identity: String,
+    pub peer: String,
+    pub protocol: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// One day's usage over all peers and protocols, see [`super::state::StateStore::daily_usage`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DailyUsage {
+    /// `YYYY-MM-DD`, UTC
+    pub day: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+static PENDING: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<UsageKey, Usage>>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// Count one request or stream of `identity` with `peer`
+pub fn record(identity: &str, peer: &fastn_id52::PublicKey, protocol: &str, bytes_in: u64, bytes_out: u64) {
+    let key = UsageKey {
+        day: chrono::Utc::now().format("%Y-%m-%d").to_string(),
+        identity: identity.to_string(),
+        peer: peer.id52(),
+        protocol: protocol.to_string(),
+    };
+    let usage = Usage { requests: 1, bytes_in, bytes_out };
+    PENDING.lock().expect("Usage lock poisoned").entry(key).or_default().add(&usage);
+}
+
+/// Write the counts recorded since the last flush to `store`; returns how many rows changed
+///
+/// Counts that fail to write are put back and go out with the next flush.
+pub async fn flush(store: &super::state::StateStore) -> Result<usize, super::state::StateError> {
+    let pending = std::mem::take(&mut *PENDING.lock().expect("Usage lock poisoned"));
+    if pending.is_empty() {
+        return Ok(0);
+    }
+    let rows: Vec<_> = pending.iter().map(|(key, usage)| (key.clone(), *usage)).collect();
+    match store.add_usage(rows).await {
+        Ok(written) => Ok(written),
+        Err(e) => {
+            let mut current = PENDING.lock().expect("Usage lock poisoned");
+            for (key, usage) in pending {
+                current.entry(key).or_default().add(&usage);
+            }
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Watch FASTN_HOME for binding config and online/offline changes
+//!
+//! Editing `identities/<alias>/protocols/<protocol>/<bind_alias>/config.json`
+//! or an identity going online/offline in the [state store](super::state) is
+//! reported as a [`ConfigChange`], so servers can reload, activate or
+//! deactivate bindings without a manual `fastn-p2p reload`. Editors touch
+//! files several times per save; changes are reported once things settle for
+//! [`DEBOUNCE`].
+
+/// How long the tree must be quiet before a batch of changes is reported
+pub const DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// One settled change on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigChange {
+    /// A binding's `config.json` was created or edited
+    BindingChanged {
+        identity: String,
+        protocol: String,
+        bind_alias: String,
+        protocol_dir: std::path::PathBuf,
+    },
+    /// A binding's `config.json` was removed
+    BindingRemoved {
+        identity: String,
+        protocol: String,
+        bind_alias: String,
+    },
+    /// The identity was set online
+    IdentityOnline { identity: String },
+    /// The identity was set offline
+    IdentityOffline { identity: String },
+}
+
+impl ConfigChange {
+    /// Alias of the identity the change belongs to
+    pub fn identity(&self) -> &str {
+        match self {
+            ConfigChange::BindingChanged { identity, .. }
+            | ConfigChange::BindingRemoved { identity, .. }
+            | ConfigChange::IdentityOnline { identity }
+            | ConfigChange::IdentityOffline { identity } => identity,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to watch {path}")]
+pub struct WatchError {
+    pub path: std::path::PathBuf,
+    #[source]
+    pub source: notify::Error,
+}
+
+/// Watcher over `FASTN_HOME/identities` and the state store; dropping it stops watching
+pub struct ConfigWatcher {
+    _watcher: notify::RecommendedWatcher,
+    fastn_home: std::path::PathBuf,
+    identities_dir: std::path::PathBuf,
+    paths: tokio::sync::mpsc::UnboundedReceiver<std::path::PathBuf>,
+    /// Online state last seen in the store, to tell what a write changed
+    online: Option<std::collections::BTreeMap<String, bool>>,
+}
+
+impl ConfigWatcher {
+    pub fn new(fastn_home: &std::path::Path) -> Result<Self, WatchError> {
+        let identities_dir = fastn_home.join("identities");
+        let (sender, paths) = tokio::sync::mpsc::unbounded_channel();
+
+        // notify calls this on its own thread
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            match event {
+                Ok(event) => {
+                    for path in event.paths {
+                        let _ = sender.send(path);
+                    }
+                }
+                Err(e) => tracing::warn!("Config watcher error: {}", e),
+            }
+        })
+        .map_err(|source| WatchError { path: identities_dir.clone(), source })?;
+        notify::Watcher::watch(&mut watcher, &identities_dir, notify::RecursiveMode::Recursive)
+            .map_err(|source| WatchError { path: identities_dir.clone(), source })?;
+        // The state store lives directly in FASTN_HOME
+        notify::Watcher::watch(&mut watcher, fastn_home, notify::RecursiveMode::NonRecursive)
+            .map_err(|source| WatchError { path: fastn_home.to_path_buf(), source })?;
+        tracing::info!("Watching {} for config changes", fastn_home.display());
+
+        Ok(Self {
+            _watcher: watcher,
+            fastn_home: fastn_home.to_path_buf(),
+            identities_dir,
+            paths,
+            online: None,
+        })
+    }
+
+    /// The next settled batch of changes; `None` once the watcher stopped
+    pub async fn next(&mut self) -> Option<Vec<ConfigChange>> {
+        if self.online.is_none() {
+            self.online = self.identity_states().await;
+        }
+        loop {
+            let mut paths = vec![self.paths.recv().await?];
+            while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, self.paths.recv()).await {
+                paths.push(path);
+            }
+
+            let mut changes = Vec::new();
+            let mut state_written = false;
+            for path in paths {
+                state_written |= is_state_store(&self.fastn_home, &path);
+                let Some(change) = classify(&self.identities_dir, &path, path.exists()) else {
+                    continue;
+                };
+                if !changes.contains(&change) {
+                    changes.push(change);
+                }
+            }
+            if state_written {
+                changes.extend(self.online_changes().await);
+            }
+            if !changes.is_empty() {
+                return Some(changes);
+            }
+        }
+    }
+
+    /// Identities whose online state differs from the last look at the store
+    async fn online_changes(&mut self) -> Vec<ConfigChange> {
+        let Some(online) = self.identity_states().await else {
+            return Vec::new();
+        };
+        let before = self.online.replace(online.clone()).unwrap_or_default();
+        online_changes(&before, &online)
+    }
+
+    async fn identity_states(&self) -> Option<std::collections::BTreeMap<String, bool>> {
+        let states = async { crate::server::state::StateStore::open(&self.fastn_home).await?.identity_states().await };
+        states
+            .await
+            .inspect_err(|e| tracing::warn!("Failed to read identity states: {}", e))
+            .ok()
+    }
+}
+
+/// `state.db` or one of its `-wal`/`-journal` companions
+fn is_state_store(fastn_home: &std::path::Path, path: &std::path::Path) -> bool {
+    path.parent() == Some(fastn_home)
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(crate::server::state::STATE_DB_FILE))
+}
+
+fn online_changes(
+    before: &std::collections::BTreeMap<String, bool>,
+    after: &std::collections::BTreeMap<String, bool>,
+) -> Vec<ConfigChange> {
+    after
+        .iter()
+        .filter(|(identity, online)| before.get(*identity).copied().unwrap_or(false) != **online)
+        .map(|(identity, online)| match *online {
+            true => ConfigChange::IdentityOnline { identity: identity.clone() },
+            false => ConfigChange::IdentityOffline { identity: identity.clone() },
+        })
+        .collect()
+}
+
+/// Bring the binding listeners of this process in line with `change`
+///
+/// An edited binding restarts, a removed one stops for good, and an identity
+/// going offline or online stops or restarts all of its bindings. Bindings
+/// without a listener here are left alone; [`crate::server::serve_all`]
+/// starts those on the server of their identity.
+pub async fn apply_to_listeners(
+    fastn_home: &std::path::Path,
+    change: &ConfigChange,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let alias = change.identity();
+    let identity_dir = fastn_home.join("identities").join(alias);
+    let identity_config = crate::server::IdentityConfig::load_from_conventional_dir(&identity_dir, alias)
+        .await
+        .map_err(|e| e.to_string())?;
+    let identity = identity_config.secret_key.public_key();
+    let binding = |protocol: &str, bind_alias: &str| crate::server::BindingKey {
+        identity,
+        protocol: protocol.to_string(),
+        bind_alias: bind_alias.to_string(),
+    };
+
+    match change {
+        ConfigChange::BindingChanged { protocol, bind_alias, .. } => {
+            // A config.json only counts once the binding is registered in the state store
+            let registered = identity_config
+                .protocols
+                .iter()
+                .any(|binding| &binding.protocol == protocol && &binding.bind_alias == bind_alias);
+            if !identity_config.online || !registered {
+                return Ok(());
+            }
+            if let Ok(handle) = crate::server::binding_listener(&binding(protocol, bind_alias)) {
+                handle.restart();
+            }
+        }
+        ConfigChange::BindingRemoved { protocol, bind_alias, .. } => {
+            // Nothing to stop if it never had a listener here
+            let _ = crate::server::remove_binding(&binding(protocol, bind_alias));
+        }
+        ConfigChange::IdentityOnline { .. } => {
+            // Running bindings are left alone, so seeing this twice is harmless
+            for protocol_binding in &identity_config.protocols {
+                let binding = binding(&protocol_binding.protocol, &protocol_binding.bind_alias);
+                match crate::server::binding_listener(&binding) {
+                    Ok(handle) if handle.state() == crate::server::ListenerState::Stopped => handle.restart(),
+                    _ => {}
+                }
+            }
+        }
+        ConfigChange::IdentityOffline { .. } => {
+            for (binding, _state) in crate::server::binding_listeners() {
+                if binding.identity == identity
+                    && let Ok(handle) = crate::server::binding_listener(&binding)
+                {
+                    handle.stop();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What a changed `path` under `identities_dir` means, if anything
+fn classify(identities_dir: &std::path::Path, path: &std::path::Path, exists: bool) -> Option<ConfigChange> {
+    let relative = path.strip_prefix(identities_dir).ok()?;
+    let parts: Vec<&str> = relative.iter().map(|part| part.to_str()).collect::<Option<_>>()?;
+
+    match parts.as_slice() {
+        [identity, "protocols", protocol, bind_alias, "config.json"] => Some(if exists {
+            ConfigChange::BindingChanged {
+                identity: identity.to_string(),
+                protocol: protocol.to_string(),
+                bind_alias: bind_alias.to_string(),
+                protocol_dir: path.parent()?.to_path_buf(),
+            }
+        } else {
+            ConfigChange::BindingRemoved {
+                identity: identity.to_string(),
+                protocol: protocol.to_string(),
+                bind_alias: bind_alias.to_string(),
+            }
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let identities = std::path::Path::new("/home/alice/.fastn/identities");
+        let config = identities.join("alice/protocols/mail.fastn.com/default/config.json");
+
+        assert_eq!(
+            classify(identities, &config, true),
+            Some(ConfigChange::BindingChanged {
+                identity: "alice".to_string(),
+                protocol: "mail.fastn.com".to_string(),
+                bind_alias: "default".to_string(),
+                protocol_dir: identities.join("alice/protocols/mail.fastn.com/default"),
+            })
+        );
+        assert!(matches!(classify(identities, &config, false), Some(ConfigChange::BindingRemoved { .. })));
+
+        // Keys, other files and editor temp files are not config changes
+        assert_eq!(classify(identities, &identities.join("alice/identity.private-key"), true), None);
+        assert_eq!(classify(identities, &config.with_file_name("config.json.swp"), true), None);
+        assert_eq!(classify(identities, std::path::Path::new("/tmp/online"), true), None);
+        // Online state lives in the state store now, not in marker files
+        assert_eq!(classify(identities, &identities.join("alice/online"), false), None);
+    }
+
+    #[test]
+    fn test_online_changes() {
+        let fastn_home = std::path::Path::new("/home/alice/.fastn");
+        assert!(is_state_store(fastn_home, &fastn_home.join("state.db-wal")));
+        assert!(!is_state_store(fastn_home, &fastn_home.join("identities/state.db")));
+
+        let before = [("alice".to_string(), true), ("bob".to_string(), false)].into_iter().collect();
+        let after = [("alice".to_string(), false), ("bob".to_string(), false), ("carol".to_string(), true)]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            online_changes(&before, &after),
+            vec![
+                ConfigChange::IdentityOffline { identity: "alice".to_string() },
+                ConfigChange::IdentityOnline { identity: "carol".to_string() },
+            ]
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! Built-in `web.fastn.com` protocol: static website hosting
+//!
+//! Serves the files of a configured directory by path, so a simple site can be
+//! shared peer-to-peer without a public server. `fastn-p2p browse <peer>` runs a
+//! local HTTP server that proxies browser requests to this protocol.
+//!
+//! Commands:
+//!
+//! - `get` (stream): initial data is a [`GetRequest`]; the server replies with a
+//!   [`GetResponse`] JSON line followed by `content_length` raw body bytes
+//!
+//! The binding config (`config.json` in the protocol_dir) names the
+//! served directory; relative paths are resolved against the protocol_dir:
+//!
+//! ```json
+//! { "root": "/srv/site", "index": "index.html" }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+/// Protocol name for the web protocol
+pub const WEB_PROTOCOL: &str = "web.fastn.com";
+
+/// File served for directory paths when the config does not set `index`
+pub const DEFAULT_INDEX: &str = "index.html";
+
+/// Web binding configuration
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WebConfig {
+    /// Directory served to peers
+    pub root: PathBuf,
+    /// File served for paths naming a directory
+    #[serde(default = "default_index")]
+    pub index: String,
+}
+
+fn default_index() -> String {
+    DEFAULT_INDEX.to_string()
+}
+
+impl WebConfig {
+    /// Load the binding config and resolve `root` against `protocol_dir`
+    pub async fn load(protocol_dir: &Path) -> Result<Self, WebError> {
+        let contents = tokio::fs::read_to_string(protocol_dir.join(crate::server::subprocess::CONFIG_FILE))
+            .await
+            .map_err(|source| WebError::Io { source })?;
+        let mut config: WebConfig =
+            serde_json::from_str(&contents).map_err(|source| WebError::Config { source })?;
+        config.root = protocol_dir.join(config.root);
+        Ok(config)
+    }
+}
+
+/// Initial data for the `get` command
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GetRequest {
+    /// URL path, e.g. `/docs/index.html`; query strings are ignored
+    pub path: String,
+    /// Raw HTTP `Range` header value, e.g. `bytes=0-1023`; ranges in other
+    /// units are ignored and the whole file is served
+    #[serde(default)]
+    pub range: Option<String>,
+}
+
+/// Response line of the `get` command, sent before the body bytes
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GetResponse {
+    /// HTTP status: 200, 206, 404 or 416
+    pub status: u16,
+    pub content_type: String,
+    /// Number of body bytes following this line
+    pub content_length: u64,
+    /// `Content-Range` header value for 206 and 416 responses
+    #[serde(default)]
+    pub content_range: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebError {
+    #[error("Invalid web request: {source}")]
+    InvalidRequest { source: serde_json::Error },
+
+    #[error("Invalid web config: {source}")]
+    Config { source: serde_json::Error },
+
+    #[error("Web I/O failed: {source}")]
+    Io { source: std::io::Error },
+}
+
+/// Register the web commands on a serve_all protocol builder
+pub fn register(
+    protocol: crate::server::serve_all::ProtocolBuilder,
+) -> crate::server::serve_all::ProtocolBuilder {
+    protocol.handle_streams("get", get_handler)
+}
+
+/// `get` command: stream a file (or part of it) from the served directory
+pub fn get_handler(
+    _identity: &str,
+    _bind_alias: &str,
+    _protocol: &str,
+    _command: &str,
+    protocol_dir: &Path,
+    initial_data: serde_json::Value,
+    mut session: crate::server::Session<String>,
+) -> super::serve_all::Reply<()> {
+    let protocol_dir = protocol_dir.to_path_buf();
+
+    Box::pin(async move {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let request: GetRequest = serde_json::from_value(initial_data)
+            .map_err(|source| WebError::InvalidRequest { source })?;
+        let config = WebConfig::load(&protocol_dir).await?;
+
+        let Some((path, size)) = find_file(&config, &request.path).await? else {
+            let body = format!("Not found: {}", request.path);
+            let response = GetResponse {
+                status: 404,
+                content_type: "text/plain; charset=utf-8".to_string(),
+                content_length: body.len() as u64,
+                content_range: None,
+            };
+            write_response(&mut session.send, &response).await?;
+            session.send.write_all(body.as_bytes()).await?;
+            session.send.finish()?;
+            return Ok(());
+        };
+
+        let content_type = content_type(&path).to_string();
+        let (response, start) = match request.range.as_deref().filter(|r| is_byte_range(r)).map(|r| parse_range(r, size)) {
+            None => (
+                GetResponse { status: 200, content_type, content_length: size, content_range: None },
+                0,
+            ),
+            Some(Some((start, end))) => (
+                GetResponse {
+                    status: 206,
+                    content_type,
+                    content_length: end - start + 1,
+                    content_range: Some(format!("bytes {start}-{end}/{size}")),
+                },
+                start,
+            ),
+            Some(None) => (
+                GetResponse {
+                    status: 416,
+                    content_type,
+                    content_length: 0,
+                    content_range: Some(format!("bytes */{size}")),
+                },
+                0,
+            ),
+        };
+
+        write_response(&mut session.send, &response).await?;
+        if response.content_length > 0 {
+            let mut file = tokio::fs::File::open(&path).await?;
+            file.seek(std::io::SeekFrom::Start(start)).await?;
+            tokio::io::copy(&mut file.take(response.content_length), &mut session.send).await?;
+        }
+        session.send.finish()?;
+
+        tracing::debug!("Served {} ({}) to {}", request.path, response.status, session.peer.id52());
+        Ok(())
+    })
+}
+
+async fn write_response(
+    send: &mut iroh::endpoint::SendStream,
+    response: &GetResponse,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    send.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+/// Locate the file a URL path refers to, with its size
+///
+/// Directories resolve to their index file. Anything that does not resolve to
+/// a regular file inside the root (missing files, `..` segments, symlinks
+/// pointing outside) is reported as not found.
+async fn find_file(config: &WebConfig, url_path: &str) -> Result<Option<(PathBuf, u64)>, WebError> {
+    let Some(mut path) = resolve(&config.root, url_path) else {
+        return Ok(None);
+    };
+
+    let root = match tokio::fs::canonicalize(&config.root).await {
+        Ok(root) => root,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => return Err(WebError::Io { source }),
+    };
+
+    for _ in 0..2 {
+        let canonical = match tokio::fs::canonicalize(&path).await {
+            Ok(canonical) => canonical,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => return Err(WebError::Io { source }),
+        };
+        if !canonical.starts_with(&root) {
+            return Ok(None);
+        }
+
+        let metadata = tokio::fs::metadata(&canonical)
+            .await
+            .map_err(|source| WebError::Io { source })?;
+        if metadata.is_file() {
+            return Ok(Some((canonical, metadata.len())));
+        }
+        path = canonical.join(&config.index);
+    }
+
+    Ok(None)
+}
+
+/// Map a URL path to a path under `root`, or `None` if it tries to escape it
+fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let url_path = url_path.split(['?', '#']).next().unwrap_or_default();
+
+    let mut path = root.to_path_buf();
+    for segment in url_path.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode(segment)?;
+        match Path::new(&segment).components().collect::<Vec<_>>().as_slice() {
+            [std::path::Component::Normal(name)] => path.push(name),
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Whether a `Range` header is in the `bytes` unit, the only one served
+pub fn is_byte_range(header: &str) -> bool {
+    header.split_once('=').is_some_and(|(unit, _)| unit.trim().eq_ignore_ascii_case("bytes"))
+}
+
+/// Parse a single-range `Range` header into an inclusive `(start, end)`
+///
+/// Returns `None` when the range cannot be satisfied for a file of `size`
+/// bytes. Multi-range requests are not supported and are also rejected.
+pub fn parse_range(header: &str, size: u64) -> Option<(u64, u64)> {
+    let (unit, spec) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            // Last `suffix` bytes
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return None;
+            }
+            (size.saturating_sub(suffix), size.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, size.checked_sub(1)?),
+        (start, end) => {
+            let end: u64 = end.parse().ok()?;
+            (start.parse().ok()?, end.min(size.checked_sub(1)?))
+        }
+    };
+
+    (start <= end && start < size).then_some((start, end))
+}
+
+/// Content type for a file, based on its extension
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some((990, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+        assert_eq!(parse_range("Bytes=0-99", 1000), Some((0, 99)));
+
+        // Unknown units are not ours to refuse; the handler serves the whole file
+        assert!(is_byte_range(" bytes=0-99"));
+        assert!(!is_byte_range("items=0-1"));
+        assert!(!is_byte_range("bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_find_file_stays_inside_root() {
+        let temp = tempfile::tempdir().unwrap();
+        let protocol_dir = temp.path().to_path_buf();
+        let root = protocol_dir.join("site");
+        tokio::fs::create_dir_all(root.join("docs")).await.unwrap();
+        tokio::fs::write(root.join("index.html"), "<h1>home</h1>").await.unwrap();
+        tokio::fs::write(root.join("docs/index.html"), "<h1>docs</h1>").await.unwrap();
+        tokio::fs::write(protocol_dir.join("secret.txt"), "secret").await.unwrap();
+
+        let config = WebConfig { root: root.clone(), index: default_index() };
+        let found = |path: &'static str| {
+            let config = config.clone();
+            async move { find_file(&config, path).await.unwrap().map(|(_, size)| size) }
+        };
+
+        assert_eq!(found("/").await, Some(13));
+        assert_eq!(found("/docs/?v=1").await, Some(13));
+        assert_eq!(found("/index%2Ehtml").await, Some(13));
+        assert_eq!(found("/missing.html").await, None);
+        assert_eq!(found("/../secret.txt").await, None);
+        assert_eq!(found("/%2E%2E/secret.txt").await, None);
+    }
+}
@@ -0,0 +1,228 @@
+//! Signed-envelope mode: detached signatures on request and response payloads
+//!
+//! Iroh authenticates the connection, but that proves nothing once a payload
+//! has been stored, forwarded by a relay or handed to another process. In
+//! signed mode ([`crate::client::Client::with_signed_requests`]) the wrapper
+//! request carries a [`PayloadSignature`] over the protocol, the payload, the
+//! intended target and a timestamp:
+//!
+//! ```text
+//! {"protocol":<P>,"data":<INPUT>,"signature":{"signer":"<id52>","timestamp":"...","signature":"<hex>"}}
+//! ```
+//!
+//! The server verifies it before running the handler and signs its tagged
+//! response the same way, bound to the request's signature:
+//!
+//! ```text
+//! {"status":"ok","data":<OUTPUT>,"signature":{...}}
+//! ```
+//!
+//! Unsigned peers are unaffected; servers that predate signing ignore the extra
+//! field, and signed clients reject their unsigned responses.
+
+/// How far a signature timestamp may be from the verifier's clock
+pub const MAX_CLOCK_SKEW: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Detached signature carried next to a payload
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PayloadSignature {
+    pub signer: fastn_id52::PublicKey,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub signature: fastn_id52::Signature,
+}
+
+/// A payload signature that has been checked by the framework
+///
+/// `peer` is who signed the payload. It is usually the connected peer, but
+/// differs when the stream was forwarded, e.g. by a relay or a primary acting
+/// for a paired device.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct VerifiedSender {
+    pub peer: fastn_id52::PublicKey,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub signature: fastn_id52::Signature,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("Signature by {signer} does not match the payload")]
+    Invalid { signer: String },
+
+    #[error("Signature timestamp {timestamp} is outside the allowed clock skew")]
+    Stale {
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+
+    #[error("Response is not signed")]
+    Missing,
+
+    #[error("Response signed by {signer}, expected {expected}")]
+    WrongSigner { signer: String, expected: String },
+}
+
+/// Sign a request payload for `target`
+pub fn sign_request(
+    key: &fastn_id52::SecretKey,
+    target: &fastn_id52::PublicKey,
+    protocol: &serde_json::Value,
+    data: &serde_json::Value,
+) -> PayloadSignature {
+    let signer = key.public_key();
+    let timestamp = chrono::Utc::now();
+    let message = request_message(&signer, target, protocol, data, &timestamp);
+    PayloadSignature {
+        signer,
+        timestamp,
+        signature: key.sign(&message),
+    }
+}
+
+/// Check a request signature on a server whose identity is `target`
+pub fn verify_request(
+    signature: &PayloadSignature,
+    target: &fastn_id52::PublicKey,
+    protocol: &serde_json::Value,
+    data: &serde_json::Value,
+) -> Result<VerifiedSender, SignatureError> {
+    let message = request_message(&signature.signer, target, protocol, data, &signature.timestamp);
+    verify(signature, &message)
+}
+
+/// Sign a tagged response envelope, binding it to the request it answers
+pub fn sign_response(
+    key: &fastn_id52::SecretKey,
+    request: &PayloadSignature,
+    envelope: &serde_json::Value,
+) -> PayloadSignature {
+    let signer = key.public_key();
+    let timestamp = chrono::Utc::now();
+    let message = response_message(&signer, request, envelope, &timestamp);
+    PayloadSignature {
+        signer,
+        timestamp,
+        signature: key.sign(&message),
+    }
+}
+
+/// Check that `expected` signed `envelope` in answer to `request`
+pub fn verify_response(
+    signature: &PayloadSignature,
+    expected: &fastn_id52::PublicKey,
+    request: &PayloadSignature,
+    envelope: &serde_json::Value,
+) -> Result<VerifiedSender, SignatureError> {
+    if signature.signer != *expected {
+        return Err(SignatureError::WrongSigner {
+            signer: signature.signer.id52(),
+            expected: expected.id52(),
+        });
+    }
+    let message = response_message(&signature.signer, request, envelope, &signature.timestamp);
+    verify(signature, &message)
+}
+
+fn verify(signature: &PayloadSignature, message: &[u8]) -> Result<VerifiedSender, SignatureError> {
+    let max_skew = chrono::Duration::from_std(MAX_CLOCK_SKEW).expect("MAX_CLOCK_SKEW fits in chrono::Duration");
+    if (chrono::Utc::now() - signature.timestamp).abs() > max_skew {
+        return Err(SignatureError::Stale {
+            timestamp: signature.timestamp,
+        });
+    }
+
+    signature
+        .signer
+        .verify(message, &signature.signature)
+        .map_err(|_| SignatureError::Invalid {
+            signer: signature.signer.id52(),
+        })?;
+
+    Ok(VerifiedSender {
+        peer: signature.signer,
+        timestamp: signature.timestamp,
+        signature: signature.signature,
+    })
+}
+
+// serde_json::Value serializes deterministically, so both sides build the same bytes
+fn request_message(
+    signer: &fastn_id52::PublicKey,
+    target: &fastn_id52::PublicKey,
+    protocol: &serde_json::Value,
+    data: &serde_json::Value,
+    timestamp: &chrono::DateTime<chrono::Utc>,
+) -> Vec<u8> {
+    format!(
+        "fastn-p2p-request-v1\n{}\n{}\n{}\n{}\n{}",
+        signer.id52(),
+        target.id52(),
+        timestamp.timestamp_millis(),
+        protocol,
+        data
+    )
+    .into_bytes()
+}
+
+fn response_message(
+    signer: &fastn_id52::PublicKey,
+    request: &PayloadSignature,
+    envelope: &serde_json::Value,
+    timestamp: &chrono::DateTime<chrono::Utc>,
+) -> Vec<u8> {
+    format!(
+        "fastn-p2p-response-v1\n{}\n{}\n{}\n{}",
+        signer.id52(),
+        request.signature,
+        timestamp.timestamp_millis(),
+        envelope
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_signature_binds_payload_and_target() {
+        let client = fastn_id52::SecretKey::generate();
+        let server = fastn_id52::SecretKey::generate().public_key();
+        let other = fastn_id52::SecretKey::generate().public_key();
+        let protocol = serde_json::json!("Echo");
+        let data = serde_json::json!({"message": "hi"});
+
+        let signature = sign_request(&client, &server, &protocol, &data);
+        let verified = verify_request(&signature, &server, &protocol, &data).unwrap();
+        assert_eq!(verified.peer, client.public_key());
+
+        assert!(verify_request(&signature, &other, &protocol, &data).is_err());
+        assert!(verify_request(&signature, &server, &protocol, &serde_json::json!({"message": "bye"})).is_err());
+
+        let mut stale = signature.clone();
+        stale.timestamp -= chrono::Duration::hours(1);
+        assert!(matches!(
+            verify_request(&stale, &server, &protocol, &data),
+            Err(SignatureError::Stale { .. })
+        ));
+    }
+
+    #[test]
+    fn test_response_signature_is_bound_to_request() {
+        let client = fastn_id52::SecretKey::generate();
+        let server = fastn_id52::SecretKey::generate();
+        let protocol = serde_json::json!("Echo");
+        let data = serde_json::json!({"message": "hi"});
+        let envelope = serde_json::json!({"status": "ok", "data": "hi"});
+
+        let request = sign_request(&client, &server.public_key(), &protocol, &data);
+        let response = sign_response(&server, &request, &envelope);
+        verify_response(&response, &server.public_key(), &request, &envelope).unwrap();
+
+        let other_request =
+            sign_request(&client, &server.public_key(), &protocol, &serde_json::json!({"message": "bye"}));
+        assert!(verify_response(&response, &server.public_key(), &other_request, &envelope).is_err());
+        assert!(matches!(
+            verify_response(&response, &client.public_key(), &request, &envelope),
+            Err(SignatureError::WrongSigner { .. })
+        ));
+    }
+}
@@ -0,0 +1,391 @@
+//! Fault injection for testing handlers and retry logic under network faults
+//!
+//! A [`Chaos`] describes the faults: added latency, streams dropped after a
+//! number of bytes, corrupted reads, failed calls and forced reconnects. The
+//! same value injects them in three places:
+//!
+//! - [`Chaos::wrap`] and [`Chaos::duplex`]: byte streams, e.g. an in-process
+//!   pair to run a stream handler or frame codec against without a network
+//! - [`Chaos::layer`]: server middleware delaying or failing requests
+//! - [`Chaos::client`]: a client whose calls are delayed, lose their request
+//!   or their reply, or go over a fresh connection
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "testing")]
+//! # mod example {
+//! use fastn_p2p::testing::chaos::Chaos;
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # enum Mail { Send }
+//! # #[derive(Debug, serde::Serialize, thiserror::Error)]
+//! # #[error("not sent")]
+//! # struct NotSent;
+//! # async fn send_mail(_: String) -> Result<(), NotSent> { Ok(()) }
+//! # async fn run(server_key: fastn_p2p::SecretKey, client_key: fastn_p2p::SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+//!
+//! let ms = std::time::Duration::from_millis;
+//! let chaos = Chaos::new().seed(7).latency(ms(5), ms(50)).fail(0.2).reconnect(0.1);
+//! fastn_p2p::listen(server_key).layer(chaos.layer()).handle_requests(Mail::Send, send_mail).await?;
+//! let client = chaos.client(fastn_p2p::client::Client::new(client_key));
+//! // ... then assert every mail still arrives exactly once
+//! # Ok(())
+//! # }
+//! # }
+//! # fn main() {}
+//! ```
+//!
+//! With a [`Chaos::seed`] the faults come from a seeded RNG, so a failing run
+//! replays the same faults as long as the calls happen in the same order.
+
+use rand::{Rng, SeedableRng};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Faults to inject, see the module docs
+///
+/// Clones share the RNG. Rates are probabilities from 0 (never) to 1 (always).
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    latency: Option<(std::time::Duration, std::time::Duration)>,
+    drop_after: Option<usize>,
+    corrupt_rate: f64,
+    fail_rate: f64,
+    reconnect_rate: f64,
+    rng: std::sync::Arc<std::sync::Mutex<rand::rngs::StdRng>>,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chaos {
+    /// No faults at all; add them with the builder methods
+    pub fn new() -> Self {
+        Self {
+            latency: None,
+            drop_after: None,
+            corrupt_rate: 0.0,
+            fail_rate: 0.0,
+            reconnect_rate: 0.0,
+            rng: std::sync::Arc::new(std::sync::Mutex::new(rand::rngs::StdRng::from_entropy())),
+        }
+    }
+
+    /// Draw the faults from an RNG seeded with `seed`
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = std::sync::Arc::new(std::sync::Mutex::new(rand::rngs::StdRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Delay every read and every request by a random time between `min` and `max`
+    pub fn latency(mut self, min: std::time::Duration, max: std::time::Duration) -> Self {
+        self.latency = Some((min, max.max(min)));
+        self
+    }
+
+    /// Reset wrapped streams with `ConnectionReset` once `bytes` went through them
+    pub fn drop_after(mut self, bytes: usize) -> Self {
+        self.drop_after = Some(bytes);
+        self
+    }
+
+    /// Flip one bit in this share of the reads from wrapped streams
+    pub fn corrupt(mut self, rate: f64) -> Self {
+        self.corrupt_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Fail this share of requests and client calls
+    pub fn fail(mut self, rate: f64) -> Self {
+        self.fail_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Drop the cached connection before this share of client calls
+    pub fn reconnect(mut self, rate: f64) -> Self {
+        self.reconnect_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Inject latency, drops and corruption into `stream`
+    pub fn wrap<S>(&self, stream: S) -> ChaosStream<S> {
+        ChaosStream {
+            inner: stream,
+            chaos: self.clone(),
+            delay: None,
+            delayed: false,
+            transferred: 0,
+        }
+    }
+
+    /// In-process connected pair of streams, both with these faults
+    ///
+    /// Like [`tokio::io::duplex`], `max_buf_size` bytes can be written before
+    /// the other side has to read.
+    pub fn duplex(
+        &self,
+        max_buf_size: usize,
+    ) -> (ChaosStream<tokio::io::DuplexStream>, ChaosStream<tokio::io::DuplexStream>) {
+        let (a, b) = tokio::io::duplex(max_buf_size);
+        (self.wrap(a), self.wrap(b))
+    }
+
+    /// Server layer delaying requests and failing [`Chaos::fail`] of them
+    ///
+    /// A failed request is answered with `{"chaos": "injected failure"}` as
+    /// its error without running the handler. Clients see that as an ERROR
+    /// their protocol does not know, like a misbehaving peer.
+    pub fn layer(
+        &self,
+    ) -> impl Fn(crate::server::LayerRequest, crate::server::Next) -> crate::server::middleware::BoxFuture
+    + Send
+    + Sync
+    + 'static
+    + use<> {
+        let chaos = self.clone();
+        move |request, next| {
+            let chaos = chaos.clone();
+            Box::pin(async move {
+                chaos.sleep().await;
+                if chaos.roll(chaos.fail_rate) {
+                    tracing::debug!(peer = %request.peer().id52(), protocol = %request.protocol(), "chaos: failing request");
+                    return Err(serde_json::json!({ "chaos": "injected failure" }));
+                }
+                next.run(request).await
+            })
+        }
+    }
+
+    /// `client` with an interceptor injecting these faults into every call
+    ///
+    /// Calls are delayed, [`Chaos::reconnect`] of them drop the cached
+    /// connection first, and [`Chaos::fail`] of them fail with a
+    /// `ConnectionReset` IO error: half of those before the request is sent,
+    /// half after the peer handled it and only the reply got lost.
+    pub fn client(&self, client: crate::client::Client) -> crate::client::Client {
+        let interceptor = self.interceptor(client.clone());
+        client.with_interceptor(interceptor)
+    }
+
+    fn interceptor(
+        &self,
+        connections: crate::client::Client,
+    ) -> impl Fn(
+        crate::interceptor::OutgoingCall,
+        crate::interceptor::CallNext,
+    ) -> Pin<Box<dyn Future<Output = crate::interceptor::InterceptorResult> + Send>>
+    + Send
+    + Sync
+    + 'static
+    + use<> {
+        let chaos = self.clone();
+        move |call, next| {
+            let (chaos, connections) = (chaos.clone(), connections.clone());
+            Box::pin(async move {
+                chaos.sleep().await;
+                if chaos.roll(chaos.reconnect_rate) {
+                    tracing::debug!(peer = %call.target().id52(), "chaos: forcing a reconnect");
+                    connections.forget(call.target()).await;
+                }
+                if !chaos.roll(chaos.fail_rate) {
+                    return next.run(call).await;
+                }
+                if chaos.roll(0.5) {
+                    tracing::debug!(peer = %call.target().id52(), "chaos: dropping the reply");
+                    next.run(call).await?;
+                } else {
+                    tracing::debug!(peer = %call.target().id52(), "chaos: dropping the request");
+                }
+                Err(crate::client::CallError::Io { source: dropped() })
+            })
+        }
+    }
+
+    fn roll(&self, rate: f64) -> bool {
+        rate > 0.0 && self.rng().gen_bool(rate)
+    }
+
+    fn delay(&self) -> Option<std::time::Duration> {
+        let (min, max) = self.latency?;
+        Some(if min == max { min } else { self.rng().gen_range(min..=max) })
+    }
+
+    async fn sleep(&self) {
+        if let Some(delay) = self.delay() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn corrupt_bytes(&self, bytes: &mut [u8]) {
+        let mut rng = self.rng();
+        let index = rng.gen_range(0..bytes.len());
+        bytes[index] ^= 1 << rng.gen_range(0..8);
+    }
+
+    fn rng(&self) -> std::sync::MutexGuard<'_, rand::rngs::StdRng> {
+        self.rng.lock().expect("Failed to acquire lock on chaos RNG")
+    }
+}
+
+fn dropped() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::ConnectionReset, "chaos: stream dropped")
+}
+
+/// A stream with [`Chaos`] faults, made by [`Chaos::wrap`]
+///
+/// Latency and corruption apply to reads; the [`Chaos::drop_after`] budget
+/// counts bytes read and written alike.
+#[derive(Debug)]
+pub struct ChaosStream<S> {
+    inner: S,
+    chaos: Chaos,
+    delay: Option<Pin<Box<tokio::time::Sleep>>>,
+    /// Whether the pending read already waited out its latency
+    delayed: bool,
+    transferred: usize,
+}
+
+impl<S> ChaosStream<S> {
+    /// Bytes read and written so far
+    pub fn transferred(&self) -> usize {
+        self.transferred
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Bytes left before the stream drops
+    fn budget(&self) -> std::io::Result<usize> {
+        match self.chaos.drop_after {
+            None => Ok(usize::MAX),
+            Some(limit) if self.transferred >= limit => Err(dropped()),
+            Some(limit) => Ok(limit - self.transferred),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ChaosStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if !this.delayed {
+            if this.delay.is_none() {
+                this.delay = this.chaos.delay().map(|delay| Box::pin(tokio::time::sleep(delay)));
+            }
+            if let Some(delay) = &mut this.delay {
+                ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+            this.delayed = true;
+        }
+
+        let budget = this.budget()?;
+        let mut chunk = vec![0; buf.remaining().min(budget)];
+        let mut chunk_buf = ReadBuf::new(&mut chunk);
+        let result = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf));
+        this.delayed = false;
+        result?;
+
+        let read = chunk_buf.filled_mut();
+        if !read.is_empty() && this.chaos.roll(this.chaos.corrupt_rate) {
+            this.chaos.corrupt_bytes(read);
+        }
+        this.transferred += read.len();
+        buf.put_slice(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ChaosStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let budget = this.budget()?;
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..buf.len().min(budget)]))?;
+        this.transferred += written;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_streams_drop_mid_transfer() {
+        let (a, mut b) = tokio::io::duplex(64);
+        let mut a = Chaos::new().drop_after(10).wrap(a);
+        let error = a.write_all(b"hello world").await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+        assert_eq!(a.transferred(), 10);
+        drop(a);
+
+        let mut received = Vec::new();
+        b.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"hello worl");
+    }
+
+    #[tokio::test]
+    async fn test_reads_are_delayed_and_corrupted() {
+        let ms = std::time::Duration::from_millis;
+        let (mut a, mut b) = Chaos::new().seed(1).latency(ms(20), ms(20)).corrupt(1.0).duplex(64);
+        b.write_all(b"frame").await.unwrap();
+
+        let started = std::time::Instant::now();
+        let mut received = [0; 5];
+        a.read_exact(&mut received).await.unwrap();
+        assert!(started.elapsed() >= ms(20));
+        let flipped: u32 = received.iter().zip(b"frame").map(|(got, sent)| (got ^ sent).count_ones()).sum();
+        assert!(flipped >= 1, "{:?}", received);
+    }
+
+    #[tokio::test]
+    async fn test_layer_fails_requests() {
+        use crate::server::middleware::{Layers, boxed};
+
+        let request = || {
+            let peer = fastn_id52::SecretKey::generate().public_key();
+            crate::server::LayerRequest::new(peer, serde_json::json!("Echo"), serde_json::json!("hi"), Default::default(), None, false, None)
+        };
+        let handler = |request: crate::server::LayerRequest| async move { Ok(request.data) };
+
+        let failing = Layers::new(vec![boxed(Chaos::new().fail(1.0).layer())]);
+        assert_eq!(failing.run(request(), handler).await, Err(serde_json::json!({ "chaos": "injected failure" })));
+        let calm = Layers::new(vec![boxed(Chaos::new().layer())]);
+        assert_eq!(calm.run(request(), handler).await, Ok(serde_json::json!("hi")));
+    }
+
+    #[tokio::test]
+    async fn test_client_calls_lose_requests_or_replies() {
+        let sent = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let transport_sent = sent.clone();
+        let transport: crate::interceptor::Transport = std::sync::Arc::new(move |_| {
+            transport_sent.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Box::pin(async { Ok(crate::interceptor::Reply::Response(Ok(serde_json::json!("ok")))) })
+        });
+        let client = crate::client::Client::new(fastn_id52::SecretKey::generate());
+        let chaos = Chaos::new().seed(3).fail(1.0).reconnect(1.0);
+        let interceptors = std::sync::Arc::new(vec![crate::interceptor::boxed(chaos.interceptor(client))]);
+
+        let target = fastn_id52::SecretKey::generate().public_key();
+        for _ in 0..20 {
+            let call = crate::interceptor::OutgoingCall::new(target, serde_json::json!("Echo"), serde_json::Value::Null, Default::default(), false);
+            let reply = crate::interceptor::CallNext::new(interceptors.clone(), transport.clone()).run(call).await;
+            assert!(matches!(reply, Err(crate::client::CallError::Io { .. })));
+        }
+        // Some requests were lost on the way out, the others after being handled
+        let sent = sent.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(sent > 0 && sent < 20, "{}", sent);
+    }
+}
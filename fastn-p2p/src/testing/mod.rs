@@ -0,0 +1,10 @@
+//! Helpers for testing protocols built on fastn-p2p (the `testing` feature)
+//!
+//! Enable it in `[dev-dependencies]` only:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! fastn-p2p = { version = "0.1", features = ["testing"] }
+//! ```
+
+pub mod chaos;
@@ -0,0 +1,166 @@
+//! Clock offset to a peer over [`fastn_net::Protocol::WhatTimeIsIt`]
+//!
+//! Mail, audit and presence compare timestamps made on different machines,
+//! and not every machine runs NTP. Every server built with
+//! [`crate::server::ServerBuilder`] answers a `WhatTimeIsIt` stream itself
+//! with one [`ClockReading`]:
+//!
+//! ```text
+//! → "WhatTimeIsIt"
+//! ← ack
+//! ← {"wall_micros":1767225600000000,"monotonic_micros":81234567}
+//! ```
+//!
+//! [`crate::client::Client::time_offset`] asks several times over the cached
+//! connection. Each sample assumes the peer read its clock halfway through
+//! the round trip; the sample with the shortest round trip is the least
+//! skewed by asymmetric delays, so its offset is the one reported. A peer
+//! whose wall clock was stepped between samples shows it as a change of
+//! `wall_micros - monotonic_micros`; samples from before the step are dropped.
+
+/// Samples [`crate::client::Client::time_offset`] takes
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// Change of a peer's wall clock against its monotonic clock that counts as a step
+const CLOCK_STEP_MICROS: i64 = 100_000;
+
+/// A server's clocks at the time it answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClockReading {
+    /// Microseconds since the Unix epoch
+    pub wall_micros: i64,
+    /// Microseconds since the server process started; never jumps
+    pub monotonic_micros: u64,
+}
+
+impl ClockReading {
+    /// This process's clocks now
+    pub fn now() -> Self {
+        static STARTED: std::sync::LazyLock<std::time::Instant> = std::sync::LazyLock::new(std::time::Instant::now);
+        Self { wall_micros: wall_micros(std::time::SystemTime::now()), monotonic_micros: STARTED.elapsed().as_micros() as u64 }
+    }
+
+    /// Wall clock minus monotonic clock; constant unless the wall clock is stepped
+    fn baseline(&self) -> i64 {
+        self.wall_micros - self.monotonic_micros as i64
+    }
+}
+
+/// How far a peer's clock is from ours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeOffset {
+    /// Microseconds the peer's clock is ahead of ours; negative if it is behind
+    pub offset_micros: i64,
+    /// Round trip of the sample the offset comes from; the offset is off by at most half of it
+    pub rtt: std::time::Duration,
+    /// Samples the offset was chosen from, after dropping those from before a clock step
+    pub samples: usize,
+}
+
+impl TimeOffset {
+    /// Pick the offset from `samples` of `(our send time, round trip, peer's reading)`
+    ///
+    /// `None` if there are no samples.
+    pub(crate) fn from_samples(samples: &[(std::time::SystemTime, std::time::Duration, ClockReading)]) -> Option<Self> {
+        let latest = samples.last()?.2.baseline();
+        let current: Vec<_> = samples
+            .iter()
+            .filter(|(_, _, reading)| (reading.baseline() - latest).abs() <= CLOCK_STEP_MICROS)
+            .collect();
+        let (sent, rtt, reading) = current.iter().min_by_key(|(_, rtt, _)| *rtt)?;
+        let midpoint = wall_micros(*sent) + rtt.as_micros() as i64 / 2;
+        Some(Self { offset_micros: reading.wall_micros - midpoint, rtt: *rtt, samples: current.len() })
+    }
+
+    /// Turn a timestamp made by the peer into our clock's time
+    pub fn to_local(&self, peer_time: std::time::SystemTime) -> std::time::SystemTime {
+        shift(peer_time, -self.offset_micros)
+    }
+
+    /// The peer's clock reading now, going by our clock
+    pub fn peer_now(&self) -> std::time::SystemTime {
+        shift(std::time::SystemTime::now(), self.offset_micros)
+    }
+}
+
+/// Answer a `WhatTimeIsIt` stream whose header was acknowledged already
+pub(crate) async fn answer(send: &mut iroh::endpoint::SendStream) -> eyre::Result<()> {
+    let reading = serde_json::to_string(&ClockReading::now())?;
+    send.write_all(reading.as_bytes()).await?;
+    send.write_all(b"\n").await?;
+    send.finish()?;
+    Ok(())
+}
+
+/// Ask `conn`'s peer for the time once: our send time, the round trip and its reading
+pub(crate) async fn sample(
+    conn: &iroh::endpoint::Connection,
+) -> Result<(std::time::SystemTime, std::time::Duration, ClockReading), crate::client::CallError> {
+    use crate::client::CallError;
+
+    let (mut send, recv) = conn.open_bi().await.map_err(CallError::from_net)?;
+    let mut recv = fastn_net::FrameReader::new(recv);
+    let header = serde_json::to_string(&fastn_net::Protocol::WhatTimeIsIt)
+        .map_err(|source| CallError::Serialization { source })?;
+
+    let sent = std::time::SystemTime::now();
+    let started = std::time::Instant::now();
+    send.write_all(header.as_bytes()).await.map_err(CallError::from_net)?;
+    send.write_all(b"\n").await.map_err(CallError::from_net)?;
+    let ack = recv.next_string().await.map_err(CallError::from_net)?;
+    if ack != fastn_net::ACK {
+        return Err(CallError::Protocol { message: format!("Expected ACK for WhatTimeIsIt, got: {}", ack) });
+    }
+    let reading: ClockReading = recv.next_json().await.map_err(CallError::from_net)?;
+    Ok((sent, started.elapsed(), reading))
+}
+
+fn wall_micros(time: std::time::SystemTime) -> i64 {
+    match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(before) => -(before.duration().as_micros() as i64),
+    }
+}
+
+fn shift(time: std::time::SystemTime, micros: i64) -> std::time::SystemTime {
+    let by = std::time::Duration::from_micros(micros.unsigned_abs());
+    if micros >= 0 { time + by } else { time - by }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(micros: i64) -> std::time::SystemTime {
+        shift(std::time::UNIX_EPOCH, micros)
+    }
+
+    #[test]
+    fn test_offset_from_shortest_round_trip() {
+        let ms = std::time::Duration::from_millis;
+        // Peer is 2s ahead; the slow sample's reply was delayed on the way back
+        let samples = [
+            (at(10_000_000), ms(80), ClockReading { wall_micros: 12_010_000, monotonic_micros: 5_000_000 }),
+            (at(11_000_000), ms(20), ClockReading { wall_micros: 13_010_000, monotonic_micros: 6_000_000 }),
+        ];
+        let offset = TimeOffset::from_samples(&samples).unwrap();
+        assert_eq!(offset.offset_micros, 2_000_000);
+        assert_eq!(offset.rtt, ms(20));
+        assert_eq!(offset.samples, 2);
+        assert_eq!(offset.to_local(at(13_010_000)), at(11_010_000));
+        assert_eq!(TimeOffset::from_samples(&[]), None);
+    }
+
+    #[test]
+    fn test_samples_before_a_clock_step_are_dropped() {
+        let ms = std::time::Duration::from_millis;
+        // The peer's wall clock went back an hour between the two samples
+        let samples = [
+            (at(10_000_000), ms(10), ClockReading { wall_micros: 3_610_005_000, monotonic_micros: 5_000_000 }),
+            (at(11_000_000), ms(30), ClockReading { wall_micros: 11_015_000, monotonic_micros: 6_000_000 }),
+        ];
+        let offset = TimeOffset::from_samples(&samples).unwrap();
+        assert_eq!(offset.offset_micros, 0);
+        assert_eq!(offset.samples, 1);
+    }
+}
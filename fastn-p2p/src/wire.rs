@@ -0,0 +1,596 @@
+//! Wire format for request/response payloads
+//!
+//! Responses are sent as an explicitly tagged envelope so the client never has
+//! to guess whether a line is an OUTPUT or an ERROR:
+//!
+//! ```text
+//! {"status":"ok","data":<OUTPUT>}
+//! {"status":"err","data":<ERROR>}
+//! ```
+//!
+//! Tagging is negotiated during the handshake (`ClientHello::tagged_responses`).
+//! Servers that predate it send the bare OUTPUT/ERROR JSON, which clients decode
+//! with the legacy "try OUTPUT, then ERROR" fallback.
+//!
+//! A tagged `ok` answer may carry the seconds clients may cache it for, set
+//! by the server per protocol:
+//!
+//! ```text
+//! {"status":"ok","data":<OUTPUT>,"max_age":300}
+//! ```
+
+/// Tagged response envelope sent by the server for each request
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "status", content = "data", rename_all = "snake_case")]
+pub enum ResponseEnvelope<OUTPUT, ERROR> {
+    Ok(OUTPUT),
+    Err(ERROR),
+}
+
+impl<OUTPUT, ERROR> From<Result<OUTPUT, ERROR>> for ResponseEnvelope<OUTPUT, ERROR> {
+    fn from(result: Result<OUTPUT, ERROR>) -> Self {
+        match result {
+            Ok(output) => Self::Ok(output),
+            Err(error) => Self::Err(error),
+        }
+    }
+}
+
+impl<OUTPUT, ERROR> From<ResponseEnvelope<OUTPUT, ERROR>> for Result<OUTPUT, ERROR> {
+    fn from(envelope: ResponseEnvelope<OUTPUT, ERROR>) -> Self {
+        match envelope {
+            ResponseEnvelope::Ok(output) => Ok(output),
+            ResponseEnvelope::Err(error) => Err(error),
+        }
+    }
+}
+
+/// Field of a tagged `ok` envelope: seconds the answer may be cached
+pub const MAX_AGE: &str = "max_age";
+
+/// `envelope` carrying `max_age` if it is a tagged `ok` answer
+pub fn with_max_age(mut envelope: serde_json::Value, max_age: Option<std::time::Duration>) -> serde_json::Value {
+    if let (Some(max_age), Some(fields)) = (max_age, envelope.as_object_mut())
+        && fields.get("status").and_then(serde_json::Value::as_str) == Some("ok")
+    {
+        fields.insert(MAX_AGE.to_string(), max_age.as_secs().into());
+    }
+    envelope
+}
+
+/// Take the [`MAX_AGE`] out of a tagged envelope
+pub fn take_max_age(envelope: &mut serde_json::Value) -> Option<std::time::Duration> {
+    let max_age = envelope.as_object_mut()?.remove(MAX_AGE)?;
+    max_age.as_u64().map(std::time::Duration::from_secs)
+}
+
+/// Encode a handler result for the wire
+///
+/// `tagged` is the value negotiated in the handshake; when false the legacy
+/// untagged format is produced for old clients.
+pub fn encode_response(
+    result: Result<serde_json::Value, serde_json::Value>,
+    tagged: bool,
+) -> Result<String, serde_json::Error> {
+    if tagged {
+        return serde_json::to_string(&ResponseEnvelope::from(result));
+    }
+
+    match result {
+        Ok(output) => serde_json::to_string(&output),
+        Err(error) => serde_json::to_string(&error),
+    }
+}
+
+/// Wrapper request sent as the first line of every application stream
+///
+/// `signature` is only present in signed mode, see [`crate::signing`].
+/// `metadata` carries headers added by client interceptors; it is not covered
+/// by the signature. `trace` is the caller's span, see [`fastn_net::trace`].
+/// `deadline_ms` is how long the client still waits for the response, see
+/// [`deadline_from_ms`]. With a `codec` other than JSON, `data` is `null` and
+/// the payload follows the line as chunks in that codec, see [`write_encoded_body`].
+/// `attachments` come on streams of their own, see [`crate::attachment`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WrapperRequest {
+    pub protocol: serde_json::Value,
+    pub data: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<crate::signing::PayloadSignature>,
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub metadata: std::collections::BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<fastn_net::TraceContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<crate::codec::Codec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<crate::attachment::AttachmentInfo>,
+}
+
+impl WrapperRequest {
+    /// A wrapper request continuing the current trace, if any
+    pub fn new(protocol: serde_json::Value, data: serde_json::Value) -> Self {
+        Self {
+            protocol,
+            data,
+            signature: None,
+            metadata: std::collections::BTreeMap::new(),
+            trace: Some(fastn_net::TraceContext::for_outgoing()),
+            deadline_ms: None,
+            codec: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Read the payload following the line if it is encoded, into `data`
+    ///
+    /// Payloads over `limit` bytes are refused like an oversized line would be.
+    pub async fn read_encoded_data(
+        &mut self,
+        recv: &mut (impl tokio::io::AsyncRead + Unpin),
+        limit: usize,
+    ) -> Result<(), crate::client::CallError> {
+        let Some(codec) = self.codec.filter(|codec| *codec != crate::codec::Codec::Json) else {
+            return Ok(());
+        };
+        let bytes = read_chunks(recv, limit).await?;
+        self.data = codec.decode(&bytes).map_err(|source| crate::client::CallError::Codec { source })?;
+        Ok(())
+    }
+}
+
+/// Time left before `deadline`, as sent in `deadline_ms`
+///
+/// Deadlines travel as a duration rather than a point in time so the peers'
+/// clocks don't have to agree; the request's transit time is lost, which
+/// only makes the server give up a little late.
+pub fn deadline_to_ms(deadline: Option<tokio::time::Instant>) -> Option<u64> {
+    deadline.map(|deadline| {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX)
+    })
+}
+
+/// The client's deadline on this side, from a received `deadline_ms`
+pub fn deadline_from_ms(deadline_ms: Option<u64>) -> Option<tokio::time::Instant> {
+    deadline_ms.and_then(|ms| tokio::time::Instant::now().checked_add(std::time::Duration::from_millis(ms)))
+}
+
+/// A handler result as a tagged envelope signed by `key`
+///
+/// Only used for signed requests, which always negotiate tagged responses.
+/// The signature covers `max_age` too.
+pub fn signed_response(
+    result: Result<serde_json::Value, serde_json::Value>,
+    key: &fastn_id52::SecretKey,
+    request: &crate::signing::PayloadSignature,
+    max_age: Option<std::time::Duration>,
+) -> Result<serde_json::Value, serde_json::Error> {
+    let mut envelope = with_max_age(serde_json::to_value(ResponseEnvelope::from(result))?, max_age);
+    let signature = crate::signing::sign_response(key, request, &envelope);
+    if let serde_json::Value::Object(ref mut fields) = envelope {
+        fields.insert("signature".to_string(), serde_json::to_value(signature)?);
+    }
+    Ok(envelope)
+}
+
+/// Split a signed response line into the tagged envelope and its signature
+pub fn split_signed_response(
+    response_json: &str,
+) -> Result<(serde_json::Value, Option<crate::signing::PayloadSignature>), serde_json::Error> {
+    let mut envelope: serde_json::Value = serde_json::from_str(response_json)?;
+    let signature = match envelope.as_object_mut().and_then(|fields| fields.remove("signature")) {
+        Some(signature) => Some(serde_json::from_value(signature)?),
+        None => None,
+    };
+    Ok((envelope, signature))
+}
+
+/// Decode a response line received from the server
+///
+/// `tagged` must be the value the server confirmed in `ServerHello`. Untagged
+/// responses come from old servers and fall back to trying OUTPUT then ERROR,
+/// which is ambiguous when both types accept the same JSON.
+pub fn decode_response<OUTPUT, ERROR>(
+    response_json: &str,
+    tagged: bool,
+) -> Result<Result<OUTPUT, ERROR>, serde_json::Error>
+where
+    OUTPUT: serde::de::DeserializeOwned,
+    ERROR: serde::de::DeserializeOwned,
+{
+    if tagged {
+        let envelope: ResponseEnvelope<OUTPUT, ERROR> = serde_json::from_str(response_json)?;
+        return Ok(envelope.into());
+    }
+
+    // Compatibility shim for servers without tagged responses
+    if let Ok(success_response) = serde_json::from_str::<OUTPUT>(response_json) {
+        return Ok(Ok(success_response));
+    }
+
+    if let Ok(error_response) = serde_json::from_str::<ERROR>(response_json) {
+        return Ok(Err(error_response));
+    }
+
+    Err(serde_json::Error::io(std::io::Error::other(format!(
+        "Response doesn't match expected OUTPUT or ERROR types: {response_json}"
+    ))))
+}
+
+/// First line of a response sent in chunks, in place of the response line
+///
+/// A response that does not fit in one frame ([`fastn_net::MAX_FRAME_LEN`]),
+/// or one written through a [`crate::server::Responder`], follows as chunks:
+/// each a [`ResponseChunk`] line and that many bytes, ended by a chunk of 0
+/// bytes. The chunks put together are the tagged response line. Only sent to
+/// clients that negotiated tagged responses; older ones fail on it as they
+/// would on the oversized line.
+pub const CHUNKED_RESPONSE: &str = r#"{"status":"chunked"}"#;
+
+/// Bytes in one chunk of a chunked response at most
+pub const RESPONSE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunked response a client puts together unless told otherwise
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 256 * 1024 * 1024;
+
+/// First line of a response encoded with a [`crate::codec::Codec`] other than JSON
+///
+/// Sent in place of the response line to requests that came in that codec;
+/// the response line, re-encoded, follows as chunks like a
+/// [`CHUNKED_RESPONSE`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "status", rename = "encoded")]
+pub struct EncodedResponse {
+    pub codec: crate::codec::Codec,
+}
+
+const ENCODED_PREFIX: &str = r#"{"status":"encoded""#;
+
+/// Header line of a chunk of a chunked response
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ResponseChunk {
+    pub len: usize,
+}
+
+/// Write `bytes` as chunks of a chunked response, nothing if empty
+pub async fn write_response_chunks(
+    send: &mut (impl tokio::io::AsyncWrite + Unpin),
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    for chunk in bytes.chunks(RESPONSE_CHUNK_SIZE) {
+        write_chunk_header(send, chunk.len()).await?;
+        send.write_all(chunk).await?;
+    }
+    Ok(())
+}
+
+/// Write the chunk that ends a chunked response
+pub async fn write_response_end(send: &mut (impl tokio::io::AsyncWrite + Unpin)) -> std::io::Result<()> {
+    write_chunk_header(send, 0).await
+}
+
+/// Write `bytes` as chunks followed by the end chunk, the body of encoded requests and responses
+pub async fn write_encoded_body(
+    send: &mut (impl tokio::io::AsyncWrite + Unpin),
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    write_response_chunks(send, bytes).await?;
+    write_response_end(send).await
+}
+
+/// Write a response already encoded with `codec`, see [`EncodedResponse`]
+pub async fn write_encoded_response(
+    send: &mut (impl tokio::io::AsyncWrite + Unpin),
+    bytes: &[u8],
+    codec: crate::codec::Codec,
+) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut header = serde_json::to_string(&EncodedResponse { codec })?;
+    header.push('\n');
+    send.write_all(header.as_bytes()).await?;
+    write_encoded_body(send, bytes).await
+}
+
+/// Write a whole response line as a chunked response
+pub async fn write_chunked_response(
+    send: &mut (impl tokio::io::AsyncWrite + Unpin),
+    response_json: &[u8],
+) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    send.write_all(CHUNKED_RESPONSE.as_bytes()).await?;
+    send.write_all(b"\n").await?;
+    write_response_chunks(send, response_json).await?;
+    write_response_end(send).await
+}
+
+async fn write_chunk_header(send: &mut (impl tokio::io::AsyncWrite + Unpin), len: usize) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut header = serde_json::to_string(&ResponseChunk { len })?;
+    header.push('\n');
+    send.write_all(header.as_bytes()).await
+}
+
+/// Sent in place of the response line when stream auth refuses a request
+///
+/// Only to clients that negotiated tagged responses; older ones get the
+/// plain text `Authorization denied`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "status", rename = "denied")]
+pub struct DeniedResponse {
+    pub denial: crate::handshake::AuthDenial,
+}
+
+const DENIED_PREFIX: &str = r#"{"status":"denied""#;
+
+/// The response line refusing a request as `denial` says
+pub fn encode_denied(denial: crate::handshake::AuthDenial, tagged: bool) -> Result<String, serde_json::Error> {
+    match tagged {
+        true => serde_json::to_string(&DeniedResponse { denial }),
+        false => Ok("Authorization denied".to_string()),
+    }
+}
+
+/// The response `line` read from `recv`, or the chunks following it put together
+///
+/// Fails with [`crate::client::CallError::TooLarge`] once the chunks add up to
+/// more than `limit` bytes, so a peer can't make the client buffer forever,
+/// and with [`crate::client::CallError::Denied`] or `Throttled` when the line
+/// is a [`DeniedResponse`]. An [`EncodedResponse`] is decoded back into a
+/// JSON line.
+pub async fn complete_response(
+    line: String,
+    recv: &mut (impl tokio::io::AsyncRead + Unpin),
+    limit: usize,
+) -> Result<String, crate::client::CallError> {
+    if line.starts_with(DENIED_PREFIX) {
+        let denied: DeniedResponse =
+            serde_json::from_str(&line).map_err(|source| crate::client::CallError::Deserialization { source })?;
+        return Err(crate::client::CallError::from_denial(denied.denial));
+    }
+    if line.starts_with(ENCODED_PREFIX) {
+        let encoded: EncodedResponse =
+            serde_json::from_str(&line).map_err(|source| crate::client::CallError::Deserialization { source })?;
+        let bytes = read_chunks(recv, limit).await?;
+        let response = encoded
+            .codec
+            .decode(&bytes)
+            .map_err(|source| crate::client::CallError::Codec { source })?;
+        return serde_json::to_string(&response).map_err(|source| crate::client::CallError::Serialization { source });
+    }
+    if line != CHUNKED_RESPONSE {
+        return Ok(line);
+    }
+    let response = read_chunks(recv, limit).await?;
+    String::from_utf8(response).map_err(|e| crate::client::CallError::Protocol {
+        message: format!("Chunked response is not UTF-8: {e}"),
+    })
+}
+
+/// Put together the chunks read from `recv`, up to the end chunk and at most `limit` bytes
+async fn read_chunks(
+    recv: &mut (impl tokio::io::AsyncRead + Unpin),
+    limit: usize,
+) -> Result<Vec<u8>, crate::client::CallError> {
+    use tokio::io::AsyncReadExt;
+
+    let mut response = Vec::new();
+    loop {
+        let chunk: ResponseChunk = fastn_net::next_json(recv).await.map_err(crate::client::CallError::from_net)?;
+        if chunk.len == 0 {
+            break;
+        }
+        if chunk.len > RESPONSE_CHUNK_SIZE {
+            return Err(crate::client::CallError::Protocol {
+                message: format!("Response chunk of {} bytes exceeds {}", chunk.len, RESPONSE_CHUNK_SIZE),
+            });
+        }
+        if response.len() + chunk.len > limit {
+            return Err(crate::client::CallError::TooLarge { limit });
+        }
+        let start = response.len();
+        response.resize(start + chunk.len, 0);
+        recv.read_exact(&mut response[start..])
+            .await
+            .map_err(|source| crate::client::CallError::Io { source })?;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<OUTPUT, ERROR>(result: Result<OUTPUT, ERROR>) -> Result<OUTPUT, ERROR>
+    where
+        OUTPUT: serde::Serialize + serde::de::DeserializeOwned,
+        ERROR: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let result = match result {
+            Ok(output) => Ok(serde_json::to_value(output).unwrap()),
+            Err(error) => Err(serde_json::to_value(error).unwrap()),
+        };
+        let line = encode_response(result, true).unwrap();
+        decode_response(&line, true).unwrap()
+    }
+
+    #[test]
+    fn test_signed_response_round_trip() {
+        let client = fastn_id52::SecretKey::generate();
+        let server = fastn_id52::SecretKey::generate();
+        let request = crate::signing::sign_request(
+            &client,
+            &server.public_key(),
+            &serde_json::json!("Echo"),
+            &serde_json::json!("hi"),
+        );
+
+        let max_age = Some(std::time::Duration::from_secs(60));
+        let line = serde_json::to_string(&signed_response(Ok(serde_json::json!("hi")), &server, &request, max_age).unwrap()).unwrap();
+        let (mut envelope, signature) = split_signed_response(&line).unwrap();
+        crate::signing::verify_response(&signature.unwrap(), &server.public_key(), &request, &envelope)
+            .unwrap();
+
+        assert_eq!(take_max_age(&mut envelope), max_age);
+        let envelope: ResponseEnvelope<String, String> = serde_json::from_value(envelope).unwrap();
+        let result: Result<String, String> = envelope.into();
+        assert_eq!(result, Ok("hi".to_string()));
+    }
+
+    #[test]
+    fn test_max_age_only_on_ok_answers() {
+        let max_age = Some(std::time::Duration::from_secs(300));
+        let ok = with_max_age(serde_json::to_value(ResponseEnvelope::<_, ()>::Ok("hi")).unwrap(), max_age);
+        assert_eq!(ok.to_string(), r#"{"data":"hi","max_age":300,"status":"ok"}"#);
+        let err = with_max_age(serde_json::to_value(ResponseEnvelope::<(), _>::Err("no")).unwrap(), max_age);
+        assert_eq!(err.to_string(), r#"{"data":"no","status":"err"}"#);
+
+        // Clients that don't know the field still read the answer
+        let result: Result<String, String> = decode_response(&ok.to_string(), true).unwrap();
+        assert_eq!(result, Ok("hi".to_string()));
+    }
+
+    #[test]
+    fn test_string_error_is_not_mistaken_for_output() {
+        let result: Result<String, String> = round_trip(Err("boom".to_string()));
+        assert_eq!(result, Err("boom".to_string()));
+
+        let result: Result<String, String> = round_trip(Ok("fine".to_string()));
+        assert_eq!(result, Ok("fine".to_string()));
+    }
+
+    #[test]
+    fn test_structurally_identical_types() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Output {
+            message: String,
+        }
+
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Error {
+            message: String,
+        }
+
+        let result: Result<Output, Error> = round_trip(Err(Error {
+            message: "denied".to_string(),
+        }));
+        assert_eq!(
+            result,
+            Err(Error {
+                message: "denied".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_unit_and_option_pairs() {
+        let result: Result<Option<u32>, ()> = round_trip(Err(()));
+        assert_eq!(result, Err(()));
+
+        let result: Result<Option<u32>, ()> = round_trip(Ok(None));
+        assert_eq!(result, Ok(None));
+    }
+
+    #[test]
+    fn test_legacy_untagged_fallback() {
+        let line = encode_response(Err(serde_json::json!({"code": 7})), false).unwrap();
+        assert_eq!(line, r#"{"code":7}"#);
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Output {
+            value: String,
+        }
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Error {
+            code: u32,
+        }
+
+        let result: Result<Output, Error> = decode_response(&line, false).unwrap();
+        assert_eq!(result, Err(Error { code: 7 }));
+    }
+
+    #[test]
+    fn test_tagged_wire_shape() {
+        let line = encode_response(Ok(serde_json::json!("hi")), true).unwrap();
+        assert_eq!(line, r#"{"status":"ok","data":"hi"}"#);
+
+        let line = encode_response(Err(serde_json::json!("no")), true).unwrap();
+        assert_eq!(line, r#"{"status":"err","data":"no"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_response_round_trip() {
+        use tokio::io::AsyncWriteExt;
+
+        let big = "x".repeat(RESPONSE_CHUNK_SIZE * 2 + 10);
+        let line = encode_response(Ok(serde_json::json!(big)), true).unwrap();
+        let (mut send, mut recv) = tokio::io::duplex(1024 * 1024);
+        write_chunked_response(&mut send, line.as_bytes()).await.unwrap();
+        send.write_all(b"rest").await.unwrap();
+        drop(send);
+
+        let first = fastn_net::next_string(&mut recv).await.unwrap();
+        let response = complete_response(first, &mut recv, line.len()).await.unwrap();
+        assert_eq!(decode_response::<String, String>(&response, true).unwrap(), Ok(big));
+        // Nothing past the end chunk was consumed
+        let mut rest = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut recv, &mut rest).await.unwrap();
+        assert_eq!(rest, b"rest");
+
+        // Plain lines pass through, oversized chunked responses are refused
+        assert_eq!(complete_response("{}".to_string(), &mut &b""[..], 0).await.unwrap(), "{}");
+        let (mut send, mut recv) = tokio::io::duplex(1024 * 1024);
+        write_chunked_response(&mut send, line.as_bytes()).await.unwrap();
+        let first = fastn_net::next_string(&mut recv).await.unwrap();
+        let error = complete_response(first, &mut recv, RESPONSE_CHUNK_SIZE).await.unwrap_err();
+        assert!(matches!(error, crate::client::CallError::TooLarge { limit } if limit == RESPONSE_CHUNK_SIZE));
+    }
+
+    #[tokio::test]
+    async fn test_encoded_request_and_response() {
+        let samples = serde_json::json!({"samples": [1, -2, 3.5]});
+        let response = serde_json::to_value(ResponseEnvelope::<_, String>::Ok(samples.clone())).unwrap();
+        for codec in crate::codec::Codec::supported() {
+            let bytes = codec.encode(&response).unwrap();
+            let (mut send, mut recv) = tokio::io::duplex(1024 * 1024);
+            write_encoded_response(&mut send, &bytes, codec).await.unwrap();
+            let first = fastn_net::next_string(&mut recv).await.unwrap();
+            let response = complete_response(first, &mut recv, bytes.len()).await.unwrap();
+            assert_eq!(decode_response::<serde_json::Value, String>(&response, true).unwrap(), Ok(samples.clone()));
+        }
+
+        // JSON requests carry their payload in the line; others after it
+        for codec in crate::codec::Codec::supported().into_iter().filter(|c| *c != crate::codec::Codec::Json) {
+            let (mut send, mut recv) = tokio::io::duplex(1024 * 1024);
+            write_encoded_body(&mut send, &codec.encode(&samples).unwrap()).await.unwrap();
+            let mut request = WrapperRequest {
+                codec: Some(codec),
+                ..WrapperRequest::new(serde_json::json!("Samples"), serde_json::Value::Null)
+            };
+            request.read_encoded_data(&mut recv, 1024).await.unwrap();
+            assert_eq!(request.data, samples);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_denied_response() {
+        let denial = crate::handshake::AuthDenial {
+            code: "throttled".to_string(),
+            message: "Slow down".to_string(),
+            retry_after_ms: Some(1500),
+        };
+        assert_eq!(encode_denied(denial.clone(), false).unwrap(), "Authorization denied");
+
+        let line = encode_denied(denial, true).unwrap();
+        let error = complete_response(line, &mut &b""[..], 0).await.unwrap_err();
+        assert_eq!(error.retry_after(), Some(std::time::Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,231 @@
+//! Golden and cross-version tests for the wire format
+//!
+//! Every line a client or server puts on the wire is compared byte for byte
+//! with a file in `golden/wire/`, so a changed field name, order or default
+//! shows up as a test failure instead of a silent break between releases.
+//! After an intentional change, regenerate the files with
+//! `UPDATE_GOLDEN=1 cargo test -p fastn-p2p wire_golden` and review the diff.
+//!
+//! The `v0` module freezes the messages of the first release; the
+//! cross-version tests check that current code and v0 peers understand each
+//! other in both directions.
+
+/// Compare `line` (sent with a trailing newline) with `golden/wire/<name>`
+fn assert_golden(name: &str, line: &str) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/wire").join(name);
+    let sent = format!("{line}\n");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &sent).unwrap();
+        return;
+    }
+    let golden = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Missing golden file {}: {e}", path.display()));
+    assert_eq!(sent, golden, "{name} no longer matches its golden file");
+}
+
+fn trace() -> fastn_net::TraceContext {
+    fastn_net::TraceContext {
+        trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+        span_id: "00f067aa0ba902b7".to_string(),
+        parent_span_id: None,
+    }
+}
+
+/// Messages as the first release sent them
+mod v0 {
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    pub struct ClientHello {
+        pub client_name: String,
+        pub client_version: String,
+        pub supported_protocols: Vec<serde_json::Value>,
+        pub auth_token: Option<String>,
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    #[serde(tag = "status", rename_all = "snake_case")]
+    pub enum ServerHello {
+        Success {
+            server_name: String,
+            server_version: String,
+            accepted_protocols: Vec<serde_json::Value>,
+        },
+        Failure {
+            code: String,
+        },
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    pub struct WrapperRequest {
+        pub protocol: serde_json::Value,
+        pub data: serde_json::Value,
+    }
+}
+
+#[test]
+fn test_stream_headers_match_golden() {
+    let handshake = fastn_net::Protocol::Generic(serde_json::json!(crate::handshake::HANDSHAKE_PROTOCOL));
+    assert_golden("handshake_header.json", &serde_json::to_string(&handshake).unwrap());
+    let app = fastn_net::Protocol::Generic(serde_json::json!("fastn-p2p"));
+    assert_golden("app_header.json", &serde_json::to_string(&app).unwrap());
+    let relay = fastn_net::Protocol::Relay { target: "target-id52".to_string(), from: None };
+    assert_golden("relay_header.json", &serde_json::to_string(&relay).unwrap());
+    assert_golden("time_header.json", &serde_json::to_string(&fastn_net::Protocol::WhatTimeIsIt).unwrap());
+    assert_golden("ack.txt", fastn_net::ACK);
+}
+
+#[test]
+fn test_handshake_matches_golden() {
+    let hello = crate::handshake::ClientHello::new("fastn-p2p-client", "0.1.0")
+        .with_protocol("Echo")
+        .with_resumption_token(Some("token".to_string()));
+    assert_golden("client_hello.json", &serde_json::to_string(&hello).unwrap());
+
+    let hello = crate::handshake::ClientHello::new("fastn-p2p-client", "0.1.0")
+        .with_protocol("Echo")
+        .with_early_request(Some(crate::handshake::EarlyRequest {
+            protocol: serde_json::json!("Echo"),
+            data: serde_json::json!({"message": "hi"}),
+            trace: Some(trace()),
+            deadline_ms: None,
+            signature: None,
+        }));
+    assert_golden("client_hello_early.json", &serde_json::to_string(&hello).unwrap());
+
+    let success = crate::handshake::ServerHello::Success {
+        server_name: "fastn-p2p-server".to_string(),
+        server_version: "0.1.0".to_string(),
+        accepted_protocols: vec![serde_json::json!("Echo")],
+        tagged_responses: true,
+        resumption_token: Some("token".to_string()),
+        resumed: false,
+        early_response: false,
+        datagram_protocols: Vec::new(),
+        codecs: Vec::new(),
+    };
+    assert_golden("server_hello_success.json", &serde_json::to_string(&success).unwrap());
+    let failure = crate::handshake::ServerHello::failure(crate::handshake::HandshakeError::NoCommonProtocols);
+    assert_golden("server_hello_failure.json", &serde_json::to_string(&failure).unwrap());
+}
+
+#[test]
+fn test_requests_and_responses_match_golden() {
+    let request = crate::wire::WrapperRequest {
+        metadata: std::collections::BTreeMap::from([("tenant".to_string(), "acme".to_string())]),
+        trace: Some(trace()),
+        ..crate::wire::WrapperRequest::new(serde_json::json!("Echo"), serde_json::json!({"message": "hi"}))
+    };
+    assert_golden("wrapper_request.json", &serde_json::to_string(&request).unwrap());
+
+    let ok = || Ok(serde_json::json!({"echo": "hi"}));
+    let err = || Err(serde_json::json!("not found"));
+    assert_golden("response_ok.json", &crate::wire::encode_response(ok(), true).unwrap());
+    assert_golden("response_err.json", &crate::wire::encode_response(err(), true).unwrap());
+    assert_golden("response_ok_untagged.json", &crate::wire::encode_response(ok(), false).unwrap());
+    assert_golden("response_err_untagged.json", &crate::wire::encode_response(err(), false).unwrap());
+}
+
+#[test]
+fn test_batch_matches_golden() {
+    let request = crate::batch::BatchRequest {
+        calls: vec![
+            crate::batch::BatchCall { protocol: serde_json::json!("Echo"), data: serde_json::json!({"message": "hi"}) },
+            crate::batch::BatchCall { protocol: serde_json::json!("Lookup"), data: serde_json::json!("bob") },
+        ],
+    };
+    assert_golden("batch_request.json", &serde_json::to_string(&request).unwrap());
+
+    let response = crate::batch::BatchResponse {
+        results: vec![
+            crate::wire::ResponseEnvelope::Ok(serde_json::json!({"echo": "hi"})),
+            crate::wire::ResponseEnvelope::Err(serde_json::json!("not found")),
+        ],
+    };
+    assert_golden("batch_response.json", &serde_json::to_string(&response).unwrap());
+}
+
+#[test]
+fn test_clock_reading_matches_golden() {
+    let reading = crate::time_sync::ClockReading { wall_micros: 1_767_225_600_000_000, monotonic_micros: 81_234_567 };
+    assert_golden("clock_reading.json", &serde_json::to_string(&reading).unwrap());
+}
+
+#[test]
+fn test_v0_client_with_current_server() {
+    let old_hello = serde_json::to_string(&v0::ClientHello {
+        client_name: "malai".to_string(),
+        client_version: "0.0.1".to_string(),
+        supported_protocols: vec![serde_json::json!("Echo")],
+        auth_token: None,
+    })
+    .unwrap();
+
+    // Newer fields default to what a v0 client means: untagged, nothing to resume
+    let hello: crate::handshake::ClientHello = serde_json::from_str(&old_hello).unwrap();
+    assert!(!hello.tagged_responses);
+    assert!(hello.resumption_token.is_none());
+    assert!(hello.early_request.is_none());
+
+    // The server echoes the client's choice, and v0 clients skip the fields they don't know
+    let mut reply = crate::handshake::ServerHello::success();
+    if let crate::handshake::ServerHello::Success { ref mut accepted_protocols, ref mut tagged_responses, .. } = reply {
+        *accepted_protocols = hello.supported_protocols.clone();
+        *tagged_responses = hello.tagged_responses;
+    }
+    let reply: v0::ServerHello = serde_json::from_str(&serde_json::to_string(&reply).unwrap()).unwrap();
+    assert!(matches!(reply, v0::ServerHello::Success { ref accepted_protocols, .. } if accepted_protocols == &[serde_json::json!("Echo")]));
+
+    let old_request = serde_json::to_string(&v0::WrapperRequest {
+        protocol: serde_json::json!("Echo"),
+        data: serde_json::json!({"message": "hi"}),
+    })
+    .unwrap();
+    let request: crate::wire::WrapperRequest = serde_json::from_str(&old_request).unwrap();
+    assert!(request.signature.is_none() && request.metadata.is_empty() && request.trace.is_none());
+
+    // A v0 client reads the bare OUTPUT or ERROR
+    let line = crate::wire::encode_response(Ok(serde_json::json!({"echo": "hi"})), hello.tagged_responses).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&line).unwrap(), serde_json::json!({"echo": "hi"}));
+}
+
+#[test]
+fn test_current_client_with_v0_server() {
+    let old_reply = serde_json::to_string(&v0::ServerHello::Success {
+        server_name: "fastn-p2p-server".to_string(),
+        server_version: "0.0.1".to_string(),
+        accepted_protocols: vec![serde_json::json!("Echo")],
+    })
+    .unwrap();
+    let reply: crate::handshake::ServerHello = serde_json::from_str(&old_reply).unwrap();
+    let crate::handshake::ServerHello::Success { tagged_responses, resumption_token, early_response, datagram_protocols, .. } = reply else {
+        panic!("v0 success reply parsed as failure");
+    };
+    assert!(!tagged_responses && resumption_token.is_none() && !early_response && datagram_protocols.is_empty());
+
+    // v0 servers read wrapper requests without the newer optional fields
+    let request = crate::wire::WrapperRequest::new(serde_json::json!("Echo"), serde_json::json!({"message": "hi"}));
+    let old_request: v0::WrapperRequest = serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+    assert_eq!(old_request.protocol, serde_json::json!("Echo"));
+
+    // ...and hellos without the metadata
+    let hello = crate::handshake::ClientHello::new("malai", "0.2.0")
+        .with_hello_metadata(&crate::handshake::HelloMetadata::new().with_device_name("laptop"));
+    let old_hello: v0::ClientHello = serde_json::from_str(&serde_json::to_string(&hello).unwrap()).unwrap();
+    assert_eq!(old_hello.client_name, "malai");
+
+    let old_failure = serde_json::to_string(&v0::ServerHello::Failure { code: "unauthorized".to_string() }).unwrap();
+    let failure: crate::handshake::ServerHello = serde_json::from_str(&old_failure).unwrap();
+    assert!(matches!(
+        failure,
+        crate::handshake::ServerHello::Failure { code: crate::handshake::HandshakeError::Unauthorized, denial: None }
+    ));
+
+    // Untagged replies decode with the OUTPUT-then-ERROR fallback
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Echo {
+        echo: String,
+    }
+    let result: Result<Echo, String> = crate::wire::decode_response(r#"{"echo":"hi"}"#, tagged_responses).unwrap();
+    assert_eq!(result, Ok(Echo { echo: "hi".to_string() }));
+    let result: Result<Echo, String> = crate::wire::decode_response(r#""not found""#, tagged_responses).unwrap();
+    assert_eq!(result, Err("not found".to_string()));
+}
@@ -0,0 +1,16 @@
+//! Frames split out of a stream by `fastn_net::FrameReader`
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    // The first byte picks the read size, so frames straddle reads differently
+    let Some((read_size, input)) = data.split_first() else {
+        return;
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let mut reader = fastn_net::FrameReader::with_read_size(input, usize::from(*read_size).max(1));
+        while let Ok(frame) = reader.next_frame().await {
+            let _ = serde_json::from_slice::<serde_json::Value>(frame);
+        }
+    });
+});
@@ -0,0 +1,7 @@
+//! Handshake messages and wrapped requests as a peer would send them
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    fastn_p2p::fuzz::handshake(data);
+    fastn_p2p::fuzz::wrapper_request(data);
+});
@@ -0,0 +1,11 @@
+//! Frames read by `fastn_net::next_json`, as protocol headers and as any JSON
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let _ = fastn_net::next_json::<fastn_net::Protocol>(&mut &data[..]).await;
+        let _ = fastn_net::next_json::<serde_json::Value>(&mut &data[..]).await;
+        let _ = fastn_net::next_string(&mut &data[..]).await;
+    });
+});